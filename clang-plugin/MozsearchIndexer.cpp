@@ -1221,6 +1221,16 @@ public:
     const ASTRecordLayout &Layout = C.getASTRecordLayout(decl);
 
     J.attribute("sizeBytes", Layout.getSize().getQuantity());
+    J.attribute("alignBytes", Layout.getAlignment().getQuantity());
+
+    // Packing caps the alignment of the fields, which is what explains the
+    // offsets of a packed record's fields.  `packed` packs to a byte.
+    if (decl->hasAttr<PackedAttr>()) {
+      J.attribute("maxFieldAlignBytes", 1);
+    } else if (const auto *MaxAlign = decl->getAttr<MaxFieldAlignmentAttr>()) {
+      J.attribute("maxFieldAlignBytes",
+                  C.toCharUnitsFromBits(MaxAlign->getAlignment()).getQuantity());
+    }
 
     emitBindingAttributes(J, *decl);
 
@@ -1268,6 +1278,17 @@ public:
       J.arrayEnd();
       J.attributeEnd();
 
+      // Explicit and partial specializations get their own symbols (implicit
+      // instantiations use the template's), so record the template they
+      // specialize so that crossref can relate the two.
+      if (const auto *Spec =
+              dyn_cast<ClassTemplateSpecializationDecl>(cxxDecl)) {
+        if (Spec->getSpecializationKind() == TSK_ExplicitSpecialization) {
+          emitStructuredSpecializationOf(
+              J, Spec->getSpecializedTemplate()->getTemplatedDecl());
+        }
+      }
+
       J.attributeBegin("methods");
       J.arrayBegin();
       for (const CXXMethodDecl *MethodDecl : cxxDecl->methods()) {
@@ -1339,6 +1360,13 @@ public:
       PrintingPolicy Policy(langOptions);
       Policy.PrintCanonicalTypes = true;
       J.attribute("type", CanonicalFieldType.getAsString(Policy));
+      if (const auto *FieldEnumType = CanonicalFieldType->getAs<EnumType>()) {
+        QualType IntType = FieldEnumType->getDecl()->getIntegerType();
+        if (!IntType.isNull()) {
+          J.attribute("underlyingType",
+                      IntType.getCanonicalType().getAsString(Policy));
+        }
+      }
 
       const TagDecl *tagDecl = CanonicalFieldType->getAsTagDecl();
       if (!tagDecl) {
@@ -1387,17 +1415,64 @@ public:
 
   void emitStructuredEnumInfo(llvm::json::OStream &J, const EnumDecl *ED) {
     J.attribute("kind", "enum");
+
+    // This is the fixed underlying type if there is one, otherwise the type
+    // the compiler picked to fit the enumerators.
+    QualType IntType = ED->getIntegerType();
+    if (!IntType.isNull()) {
+      LangOptions langOptions;
+      PrintingPolicy Policy(langOptions);
+      Policy.PrintCanonicalTypes = true;
+      J.attribute("underlyingType",
+                  IntType.getCanonicalType().getAsString(Policy));
+      J.attribute("sizeBytes",
+                  AstContext->getTypeSizeInChars(IntType).getQuantity());
+    }
+
+    J.attributeBegin("enumerators");
+    J.arrayBegin();
+    for (const EnumConstantDecl *ECD : ED->enumerators()) {
+      J.objectBegin();
+      J.attribute("pretty", getQualifiedName(ECD));
+      J.attribute("sym", getMangledName(CurMangleContext, ECD));
+      J.attribute("value", ECD->getInitVal().getExtValue());
+      J.objectEnd();
+    }
+    J.arrayEnd();
+    J.attributeEnd();
   }
 
   void emitStructuredEnumConstantInfo(llvm::json::OStream &J,
                                       const EnumConstantDecl *ECD) {
     J.attribute("kind", "enumConstant");
+
+    if (const auto *parentDecl =
+            dyn_cast_or_null<EnumDecl>(ECD->getDeclContext())) {
+      J.attribute("parentsym", getMangledName(CurMangleContext, parentDecl));
+    }
+  }
+
+  void emitStructuredSpecializationOf(llvm::json::OStream &J,
+                                      const NamedDecl *templatedDecl) {
+    J.attributeBegin("specializationOf");
+    J.arrayBegin();
+    J.objectBegin();
+    J.attribute("sym", getMangledName(CurMangleContext, templatedDecl));
+    J.objectEnd();
+    J.arrayEnd();
+    J.attributeEnd();
   }
 
   void emitStructuredFunctionInfo(llvm::json::OStream &J,
                                   const FunctionDecl *decl) {
     emitBindingAttributes(J, *decl);
 
+    if (decl->getTemplateSpecializationKind() == TSK_ExplicitSpecialization) {
+      if (const FunctionTemplateDecl *Template = decl->getPrimaryTemplate()) {
+        emitStructuredSpecializationOf(J, Template->getTemplatedDecl());
+      }
+    }
+
     J.attributeBegin("args");
     J.arrayBegin();
 
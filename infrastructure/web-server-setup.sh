@@ -90,6 +90,7 @@ do
     # we cache.  These helpers also take into considerationg the "cache" setting
     # in the tree config.
     cache_when_everything crossref-extra
+    cache_when_everything crossref-index
     cache_when_everything crossref
     cache_when_codesearch livegrep.idx
 done
@@ -18,3 +18,11 @@ $MOZSEARCH_PATH/tools/target/release/crossref $CONFIG_FILE $TREE_NAME $ANALYSIS_
 ID_FILE=$INDEX_ROOT/identifiers
 LC_ALL=C sort -f $ID_FILE > ${TMPDIR:-/tmp}/ids
 mv ${TMPDIR:-/tmp}/ids $ID_FILE
+
+# Precompute the per-symbol aggregates that are too expensive to compute at
+# query time for heavily used symbols.  This has to happen after the crossref
+# database is written since it's computed from it.
+TOOL_CMD="precompute-aggregates --output-dir=$INDEX_ROOT"
+SEARCHFOX_SERVER=${CONFIG_FILE} \
+    SEARCHFOX_TREE=${TREE_NAME} \
+    $MOZSEARCH_PATH/tools/target/release/searchfox-tool "$TOOL_CMD"
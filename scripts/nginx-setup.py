@@ -227,6 +227,14 @@ for repo in config['trees']:
 
     # Handled by Rust `pipeline-server.rs`
     location(f'/{repo}/query', ['proxy_pass http://localhost:8002;'])
+    # Live query sessions are WebSockets and need the upgrade headers passed
+    # through.
+    location(f'/{repo}/query-live', [
+        'proxy_pass http://localhost:8002;',
+        'proxy_http_version 1.1;',
+        'proxy_set_header Upgrade $http_upgrade;',
+        'proxy_set_header Connection "upgrade";',
+    ])
 
 
 location('= /', [
@@ -691,7 +691,9 @@ def handle_dictionary_field(records, dictionary_name, dictionary_cpp_sym,
 
     name = target.identifier.name
     loc = to_loc(target.identifier.location, name)
-    pretty = f'{dictionary_name}.{name}'
+    # Use the same delimiter as interface members so that the identifiers
+    # table has an entry for the bare member name.
+    pretty = f'{dictionary_name}::{name}'
     idl_sym = f'WEBIDL_{dictionary_name}_{name}'
     cpp_sym = f'F_<{dictionary_cpp_sym}>_{cpp_dictionary_field_name(name)}'
     js_sym = f'#{name}'
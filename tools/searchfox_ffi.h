@@ -0,0 +1,22 @@
+/* C interface for running searchfox pipelines in-process.  See tools/src/ffi.rs
+ * for the JSON formats of the plan and result. */
+
+#ifndef SEARCHFOX_FFI_H
+#define SEARCHFOX_FFI_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Run the pipeline described by the UTF-8 JSON `json_plan`, returning a UTF-8
+ * JSON result which must be released with `sf_free_result`. */
+char* sf_run_query(const char* json_plan);
+
+/* Release a result returned by `sf_run_query`.  Passing NULL is a no-op. */
+void sf_free_result(char* result);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* SEARCHFOX_FFI_H */
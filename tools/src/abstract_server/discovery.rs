@@ -0,0 +1,148 @@
+use std::path::Path;
+
+use url::Url;
+
+use super::{
+    make_local_server, make_remote_server,
+    server_interface::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError},
+};
+use crate::file_format::config::ConfigJson;
+
+/// The name of the config file the indexer generates at the root of the index.
+pub const CONFIG_FILENAME: &str = "config.json";
+
+/// Where the setup scripts put the index on web-servers and development VMs,
+/// relative to the home directory.
+const STANDARD_INDEX_DIR: &str = "index";
+
+const DEFAULT_REMOTE_SERVER: &str = "https://searchfox.org/";
+const DEFAULT_REMOTE_TREE: &str = "mozilla-central";
+
+/// Normalize a local server value which may either be the path to a config
+/// file or the path of the index directory containing it.
+fn local_config_path(server: &str) -> String {
+    if Path::new(server).is_dir() {
+        format!("{}/{}", server.trim_end_matches('/'), CONFIG_FILENAME)
+    } else {
+        server.to_string()
+    }
+}
+
+/// Find the config file of the index in the standard location, if there is one.
+pub fn discover_config_path() -> Option<String> {
+    let home = std::env::var("HOME").ok()?;
+    let path = format!("{}/{}/{}", home, STANDARD_INDEX_DIR, CONFIG_FILENAME);
+    if Path::new(&path).is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// Pick the tree to use from the config.  An explicitly requested tree must
+/// exist.  Otherwise we use the config's `default_tree`, or the only tree if
+/// there is just one.
+pub fn select_tree(config: &ConfigJson, tree: Option<&str>) -> Result<String> {
+    let known_trees = || config.trees.keys().cloned().collect::<Vec<_>>().join(", ");
+    let bad_input = |message: String| {
+        ServerError::StickyProblem(ErrorDetails {
+            layer: ErrorLayer::BadInput,
+            message,
+        })
+    };
+
+    if let Some(tree) = tree {
+        if config.trees.contains_key(tree) {
+            return Ok(tree.to_string());
+        }
+        return Err(bad_input(format!(
+            "bad tree name: {} (known trees: {})",
+            tree,
+            known_trees()
+        )));
+    }
+    if let Some(default_tree) = &config.default_tree {
+        if config.trees.contains_key(default_tree) {
+            return Ok(default_tree.clone());
+        }
+    }
+    if config.trees.len() == 1 {
+        return Ok(config.trees.keys().next().unwrap().clone());
+    }
+    Err(bad_input(format!(
+        "no default tree configured, please pick one with --tree (known trees: {})",
+        known_trees()
+    )))
+}
+
+/// Create the server for the given `--server` and `--tree` values, either of
+/// which may be omitted.  Servers that parse as URLs are remote; anything else
+/// is a local config file or index directory.  If no server is given, we use
+/// the local index in the standard location if there is one and otherwise
+/// fall back to the production searchfox server.
+///
+/// Returns the kind of server ("local" or "remote") along with the server.
+pub fn make_server_for_selector(
+    server: Option<&str>,
+    tree: Option<&str>,
+) -> Result<(&'static str, Box<dyn AbstractServer + Send + Sync>)> {
+    let discovered;
+    let server = match server {
+        Some(server) => server,
+        None => {
+            discovered = discover_config_path();
+            discovered.as_deref().unwrap_or(DEFAULT_REMOTE_SERVER)
+        }
+    };
+
+    if let Ok(url) = Url::parse(server) {
+        let tree = tree.unwrap_or(DEFAULT_REMOTE_TREE);
+        return Ok(("remote", make_remote_server(url, tree)?));
+    }
+
+    let config_path = local_config_path(server);
+    let config: ConfigJson = serde_json::from_str(&std::fs::read_to_string(&config_path)?)?;
+    let tree = select_tree(&config, tree)?;
+    Ok(("local", make_local_server(&config_path, &tree)?))
+}
+
+#[test]
+fn test_select_tree() {
+    let make_config = |default_tree: Option<&str>, trees: &[&str]| -> ConfigJson {
+        let tree_config = serde_json::json!({
+            "priority": 1,
+            "on_error": "continue",
+            "cache": "nothing",
+            "index_path": "/index",
+            "files_path": "/index/git",
+            "objdir_path": "/index/objdir",
+            "codesearch_path": "/index/livegrep.idx",
+            "codesearch_port": 8081,
+        });
+        let trees: serde_json::Map<String, serde_json::Value> = trees
+            .iter()
+            .map(|name| (name.to_string(), tree_config.clone()))
+            .collect();
+        serde_json::from_value(serde_json::json!({
+            "mozsearch_path": "/mozsearch",
+            "config_repo": "/config",
+            "default_tree": default_tree,
+            "trees": trees,
+        }))
+        .unwrap()
+    };
+
+    let single = make_config(None, &["tests"]);
+    assert_eq!(select_tree(&single, None).unwrap(), "tests");
+    assert!(select_tree(&single, Some("nss")).is_err());
+
+    let multi = make_config(Some("nss"), &["mozilla-central", "nss"]);
+    assert_eq!(select_tree(&multi, None).unwrap(), "nss");
+    assert_eq!(
+        select_tree(&multi, Some("mozilla-central")).unwrap(),
+        "mozilla-central"
+    );
+
+    let no_default = make_config(None, &["mozilla-central", "nss"]);
+    assert!(select_tree(&no_default, None).is_err());
+}
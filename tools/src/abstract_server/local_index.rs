@@ -1,28 +1,46 @@
 use async_trait::async_trait;
+use chrono::datetime::DateTime;
+use chrono::naive::datetime::NaiveDateTime;
+use chrono::offset::fixed::FixedOffset;
 use flate2::read::GzDecoder;
 use futures_core::stream::BoxStream;
+use git2::{Commit, Oid, Repository};
+use regex::Regex;
 use serde_json::{from_str, Value};
-use std::collections::BTreeMap;
-use std::io::Read;
-use std::time::Instant;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{ErrorKind, Read};
+use std::path::Path;
+use std::time::{Duration, Instant};
 use tokio::fs::File;
 use tokio::io::AsyncReadExt;
-use tracing::trace;
+use tracing::{trace, warn};
 use ustr::{ustr, Ustr};
 
 use super::server_interface::{
-    AbstractServer, ErrorDetails, ErrorLayer, FileMatches, HtmlFileRoot, Result,
-    SearchfoxIndexRoot, ServerError, TextBounds, TextMatchInFile,
+    AbstractServer, BlameLine, ErrorDetails, ErrorLayer, FileMatches, FileRevision, HtmlFileRoot,
+    Result, SearchfoxIndexRoot, ServerError, SubRepoInfo, SymbolAggregates, SymbolRelationCounts,
+    TextBounds, TextMatchInFile, TextSearchExitReason,
 };
 use super::{TextMatches, TextMatchesByFile, TreeInfo};
 
 use crate::abstract_server::lazy_crossref::perform_lazy_crossref;
-use crate::file_format::analysis::{read_analyses, read_source};
-use crate::file_format::config::{load, TreeConfig, TreeConfigPaths};
+use crate::abstract_server::retry::with_retries;
+use crate::blame::LineData;
+use crate::cmd_pipeline::warnings::add_pipeline_warning;
+use crate::file_format::analysis::{
+    analysis_record_overlaps, read_analyses, read_source, SourceRange,
+};
+use crate::file_format::config::{
+    load, ExternalRankerConfig, Mailmap, OutputSizeGuardConfig, PathAliasConfig, PathRewriteRule,
+    RedactionConfig, ResultDemotionConfig, SymbolAnnotationConfig, TreeConfig, TreeConfigPaths,
+    VirtualSymbolConfig,
+};
 use crate::file_format::crossref_lookup::CrossrefLookupMap;
 use crate::file_format::identifiers::IdentMap;
-use crate::file_format::per_file_info::FileLookupMap;
+use crate::file_format::per_file_info::{FileLookupMap, TestAnnotations};
+use crate::file_format::repo_data_ingestion::DetailedPerFileInfo;
 use crate::format::format_code;
+use crate::git_ops::read_blob_entry;
 use crate::languages::select_formatting;
 
 pub mod livegrep {
@@ -30,63 +48,65 @@ pub mod livegrep {
 }
 
 use livegrep::code_search_client::CodeSearchClient;
+use livegrep::search_stats::ExitReason;
 use livegrep::Query;
 
-/// IO errors amount to a 404 for our purposes which means a sticky problem.
+/// IO errors mostly amount to a 404 for our purposes which means a sticky
+/// problem, but errors like timeouts may go away if retried.
 impl From<std::io::Error> for ServerError {
     fn from(err: std::io::Error) -> ServerError {
-        ServerError::StickyProblem(ErrorDetails {
-            layer: ErrorLayer::ServerLayer,
+        let details = ErrorDetails {
+            layer: ErrorLayer::FileIoLayer,
             message: err.to_string(),
-        })
+        };
+        match err.kind() {
+            ErrorKind::Interrupted | ErrorKind::TimedOut | ErrorKind::WouldBlock => {
+                ServerError::TransientProblem(details)
+            }
+            _ => ServerError::StickyProblem(details),
+        }
     }
 }
 
 impl From<tonic::Status> for ServerError {
     fn from(status: tonic::Status) -> ServerError {
-        // There are gRPC codes accessible via code() but for now, especially
-        // since we lack the ability to restart the server, it seems safe to
-        // assume any problem will not magically fix itself.
-        ServerError::StickyProblem(ErrorDetails {
-            layer: ErrorLayer::ServerLayer,
+        let details = ErrorDetails {
+            layer: ErrorLayer::CodesearchLayer,
             message: status.to_string(),
-        })
+        };
+        // These codes are what we see while livegrep is restarting or
+        // overloaded; anything else (ex: a bad regexp) won't fix itself.
+        match status.code() {
+            tonic::Code::Unavailable
+            | tonic::Code::DeadlineExceeded
+            | tonic::Code::ResourceExhausted
+            | tonic::Code::Aborted => ServerError::TransientProblem(details),
+            _ => ServerError::StickyProblem(details),
+        }
     }
 }
 
 impl From<tonic::transport::Error> for ServerError {
     fn from(err: tonic::transport::Error) -> ServerError {
-        // There are gRPC codes accessible via code() but for now, especially
-        // since we lack the ability to restart the server, it seems safe to
-        // assume any problem will not magically fix itself.
-        ServerError::StickyProblem(ErrorDetails {
-            layer: ErrorLayer::ServerLayer,
+        // Transport errors are generally failures to connect, which happen
+        // while livegrep is (re)starting.
+        ServerError::TransientProblem(ErrorDetails {
+            layer: ErrorLayer::CodesearchLayer,
             message: err.to_string(),
         })
     }
 }
 
-/// Read newline-delimited JSON that's been gzip-compressed.
-async fn read_gzipped_ndjson_from_file(path: &str) -> Result<Vec<Value>> {
-    let mut f = File::open(path).await?;
-    // We read the entirety to a buffer because
-    // https://github.com/serde-rs/json/issues/160 suggests that the buffered
-    // reader performance is likely to be much worse.
-    //
+/// Parse newline-delimited JSON that's been gzip-compressed.
+fn parse_gzipped_ndjson(buffer: &[u8]) -> Result<Vec<Value>> {
     // When we want to go async here,
     // https://github.com/rust-lang/flate2-rs/pull/214 suggests that we want to
     // use the `async-compression` crate.
-    let mut buffer = Vec::new();
-    f.read_to_end(&mut buffer).await?;
-
-    let mut gz = GzDecoder::new(&buffer[..]);
+    let mut gz = GzDecoder::new(buffer);
 
     let mut raw_str = String::new();
     gz.read_to_string(&mut raw_str)?;
 
-    // let mut raw_str = String::new();
-    // f.read_to_string(&mut raw_str).await?;
-
     raw_str
         .lines()
         .map(|s| from_str(s).map_err(ServerError::from))
@@ -128,7 +148,13 @@ pub struct LocalIndex {
     // But for crossref, it's on us.
     crossref_lookup_map: Option<CrossrefLookupMap>,
     jumpref_lookup_map: Option<CrossrefLookupMap>,
+    /// The `precompute-aggregates` store, which uses the crossref file format.
+    aggregates_lookup_map: Option<CrossrefLookupMap>,
     file_lookup_map: FileLookupMap,
+    /// The resolved revision of each of the tree's `sub_repos`, keyed by path
+    /// prefix.  We resolve these up front since it may involve opening the
+    /// sub-repo's git repository.
+    sub_repo_revisions: BTreeMap<String, Option<String>>,
 }
 
 impl LocalIndex {
@@ -142,6 +168,150 @@ impl LocalIndex {
 
         Ok(norm_path)
     }
+
+    /// The on-disk path of the raw source file for the given tree-local path,
+    /// which may be a generated file living in the objdir.
+    fn raw_source_disk_path(&self, sf_path: &str) -> Result<String> {
+        let norm_path = self.normalize_and_validate_path(sf_path)?;
+        Ok(match norm_path.strip_prefix("__GENERATED__/") {
+            Some(generated_path) => format!("{}/{}", self.config_paths.objdir_path, generated_path),
+            None => format!("{}/{}", self.config_paths.files_path, norm_path),
+        })
+    }
+
+    /// Read the entirety of a file, retrying transient failures per the tree's
+    /// retry policy.  We read the entirety to a buffer because
+    /// https://github.com/serde-rs/json/issues/160 suggests that the buffered
+    /// reader performance is likely to be much worse.
+    async fn read_file(&self, path: &str) -> Result<Vec<u8>> {
+        with_retries(
+            &self.config_paths.retry_policy,
+            "read_file",
+            move || async move {
+                let mut f = File::open(path).await?;
+                let mut buffer = Vec::new();
+                f.read_to_end(&mut buffer).await?;
+                Ok::<_, ServerError>(buffer)
+            },
+        )
+        .await
+    }
+
+    async fn read_file_to_string(&self, path: &str) -> Result<String> {
+        let buffer = self.read_file(path).await?;
+        String::from_utf8(buffer)
+            .map_err(|err| std::io::Error::new(ErrorKind::InvalidData, err).into())
+    }
+
+    /// A single attempt at `search_text` against a single codesearch endpoint
+    /// without any retries.
+    async fn search_text_once(
+        &self,
+        endpoint: &str,
+        pattern: &str,
+        fold_case: bool,
+        path: &str,
+        limit: usize,
+        context_lines: u32,
+    ) -> Result<TextMatches> {
+        let now = Instant::now();
+
+        trace!("search_text: connecting to {}", endpoint);
+
+        let mut client = CodeSearchClient::connect(endpoint.to_string()).await?;
+
+        // Before multiple paths were allowed, an empty path constraint allowed
+        // us to skip the match; now if we pass an empty path in a vec, that
+        // will fail to match, so we want to pass an empty vec.
+        let use_path = if path.is_empty() {
+            vec![]
+        } else {
+            vec![path.into()]
+        };
+
+        let query = tonic::Request::new(Query {
+            line: pattern.into(),
+            file: use_path,
+            repo: "".into(),
+            tags: "".into(),
+            fold_case,
+            not_file: vec![],
+            not_repo: "".into(),
+            not_tags: "".into(),
+            // 0 falls back to the default, I believe.
+            max_matches: limit as i32,
+            filename_only: false,
+            context_lines: context_lines as i32,
+        });
+
+        trace!("search_text: connected, issuing query: {}", pattern);
+        let response = client.search(query).await?.into_inner();
+
+        let exit_reason = match response.stats.as_ref().map(|stats| stats.exit_reason()) {
+            Some(ExitReason::Timeout) => TextSearchExitReason::Timeout,
+            Some(ExitReason::MatchLimit) => TextSearchExitReason::MatchLimit,
+            _ => TextSearchExitReason::Complete,
+        };
+
+        trace!(
+            duration_us = now.elapsed().as_micros() as u64,
+            result_count = response.results.len(),
+            ?exit_reason,
+            "search_text: query completed: {}",
+            pattern
+        );
+
+        let mut by_file: BTreeMap<String, TextMatchesByFile> = BTreeMap::new();
+        for result in response.results {
+            let left = result.bounds.as_ref().map_or(0, |b| b.left);
+            let right = result.bounds.as_ref().map_or(0, |b| b.right);
+            // The "mozilla-subrepo" repo is the mozilla/ subdirectory of
+            // comm-central, whose paths are relative to that subdirectory.
+            let result_path = if result.tree == "mozilla-subrepo" {
+                format!("mozilla/{}", result.path)
+            } else {
+                result.path
+            };
+            // Codesearch provides the before context in reverse order.
+            let mut context_before = result.context_before;
+            context_before.reverse();
+            by_file
+                .entry(result_path.clone())
+                .or_insert_with(|| {
+                    let path = ustr(&result_path);
+                    let path_kind = self
+                        .file_lookup_map
+                        .lookup_file_from_ustr(&path)
+                        .map_or_else(|| ustr(""), |fi| fi.path_kind);
+                    TextMatchesByFile {
+                        file: path,
+                        path_kind,
+                        matches: vec![],
+                    }
+                })
+                .matches
+                .push(TextMatchInFile {
+                    line_num: result.line_number as u32,
+                    bounds: TextBounds {
+                        start: left,
+                        end_exclusive: right,
+                    },
+                    line_str: result.line,
+                    context_before,
+                    context_after: result.context_after,
+                });
+        }
+
+        // Sharded searches are merged in this same order.
+        let mut by_file: Vec<TextMatchesByFile> = by_file.into_values().collect();
+        for file_matches in &mut by_file {
+            file_matches.matches.sort_by_key(|m| m.line_num);
+        }
+        Ok(TextMatches {
+            by_file,
+            exit_reason,
+        })
+    }
 }
 
 #[async_trait]
@@ -181,26 +351,32 @@ impl AbstractServer for LocalIndex {
     async fn fetch_raw_analysis<'a>(&self, sf_path: &str) -> Result<BoxStream<'a, Value>> {
         let norm_path = self.normalize_and_validate_path(sf_path)?;
         let full_path = self.translate_path(SearchfoxIndexRoot::CompressedAnalysis, norm_path)?;
-        let values = read_gzipped_ndjson_from_file(&full_path).await?;
+        let values = parse_gzipped_ndjson(&self.read_file(&full_path).await?)?;
         Ok(Box::pin(tokio_stream::iter(values)))
     }
 
-    async fn fetch_raw_source(&self, sf_path: &str) -> Result<String> {
+    async fn fetch_analysis_in_range(
+        &self,
+        sf_path: &str,
+        range: SourceRange,
+    ) -> Result<Vec<Value>> {
         let norm_path = self.normalize_and_validate_path(sf_path)?;
-        let full_path = if norm_path.starts_with("__GENERATED__/") {
-            format!(
-                "{}/{}",
-                self.config_paths.objdir_path,
-                norm_path.strip_prefix("__GENERATED__/").unwrap()
-            )
-        } else {
-            format!("{}/{}", self.config_paths.files_path, norm_path)
-        };
+        let full_path = self.translate_path(SearchfoxIndexRoot::CompressedAnalysis, norm_path)?;
+        let values = parse_gzipped_ndjson(&self.read_file(&full_path).await?)?;
+        Ok(values
+            .into_iter()
+            .filter(|record| analysis_record_overlaps(record, &range))
+            .collect())
+    }
 
-        let mut f = File::open(full_path).await?;
-        let mut raw_str = String::new();
-        f.read_to_string(&mut raw_str).await?;
-        Ok(raw_str)
+    async fn fetch_raw_source(&self, sf_path: &str) -> Result<String> {
+        let full_path = self.raw_source_disk_path(sf_path)?;
+        self.read_file_to_string(&full_path).await
+    }
+
+    async fn fetch_raw_source_bytes(&self, sf_path: &str) -> Result<Vec<u8>> {
+        let full_path = self.raw_source_disk_path(sf_path)?;
+        self.read_file(&full_path).await
     }
 
     async fn fetch_formatted_lines(&self, sf_path: &str) -> Result<(Vec<String>, String)> {
@@ -265,15 +441,10 @@ impl AbstractServer for LocalIndex {
         };
 
         if !is_gzipped {
-            let mut f = File::open(full_path).await?;
-            let mut raw_str = String::new();
-            f.read_to_string(&mut raw_str).await?;
-            return Ok(raw_str);
+            return self.read_file_to_string(&full_path).await;
         }
 
-        let mut f = File::open(full_path).await?;
-        let mut buffer = Vec::new();
-        f.read_to_end(&mut buffer).await?;
+        let buffer = self.read_file(&full_path).await?;
 
         // When we want to go async here,
         // https://github.com/rust-lang/flate2-rs/pull/214 suggests that we want
@@ -304,6 +475,31 @@ impl AbstractServer for LocalIndex {
         }
     }
 
+    async fn symbol_relation_counts(&self, symbol: &str) -> Result<Option<SymbolRelationCounts>> {
+        match &self.crossref_lookup_map {
+            Some(crossref) => crossref.lookup_relation_counts(symbol),
+            None => Err(ServerError::Unsupported),
+        }
+    }
+
+    async fn precomputed_aggregates(&self, symbol: &str) -> Result<Option<SymbolAggregates>> {
+        let aggregates = match &self.aggregates_lookup_map {
+            Some(aggregates) => aggregates.lookup(symbol)?,
+            None => return Err(ServerError::Unsupported),
+        };
+        if aggregates.is_null() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_value(aggregates)?))
+    }
+
+    async fn crossref_scan<'a>(&self) -> Result<BoxStream<'a, Result<(String, Value)>>> {
+        match &self.crossref_lookup_map {
+            Some(crossref) => Ok(Box::pin(tokio_stream::iter(crossref.iter()))),
+            None => Err(ServerError::Unsupported),
+        }
+    }
+
     async fn jumpref_lookup(&self, symbol: &str) -> Result<Value> {
         let now = Instant::now();
         let result = match &self.jumpref_lookup_map {
@@ -318,6 +514,195 @@ impl AbstractServer for LocalIndex {
         result
     }
 
+    async fn fetch_blame_lines(
+        &self,
+        sf_path: &str,
+        line_range: Option<(u32, u32)>,
+    ) -> Result<Vec<BlameLine>> {
+        let norm_path = self.normalize_and_validate_path(sf_path)?;
+        let (git_path, blame_path) = match (
+            &self.config_paths.git_path,
+            &self.config_paths.git_blame_path,
+        ) {
+            (Some(git_path), Some(blame_path)) => (git_path, blame_path),
+            _ => return Err(ServerError::Unsupported),
+        };
+        // libgit2 is synchronous and its repositories can't be shared across
+        // threads, so we open them fresh for each request.
+        tokio::task::block_in_place(|| {
+            compute_blame_lines(git_path, blame_path, norm_path, line_range).map_err(|message| {
+                ServerError::StickyProblem(ErrorDetails {
+                    layer: ErrorLayer::DataLayer,
+                    message,
+                })
+            })
+        })
+    }
+
+    async fn fetch_file_history(
+        &self,
+        sf_path: &str,
+        max_revisions: usize,
+    ) -> Result<Vec<FileRevision>> {
+        let norm_path = self.normalize_and_validate_path(sf_path)?;
+        let git_path = match &self.config_paths.git_path {
+            Some(git_path) => git_path,
+            None => return Err(ServerError::Unsupported),
+        };
+        // Same libgit2 constraints as fetch_blame_lines.
+        tokio::task::block_in_place(|| {
+            compute_file_history(git_path, norm_path, max_revisions).map_err(|message| {
+                ServerError::StickyProblem(ErrorDetails {
+                    layer: ErrorLayer::DataLayer,
+                    message,
+                })
+            })
+        })
+    }
+
+    async fn fetch_coverage_lines(&self, sf_path: &str) -> Result<Option<Vec<i64>>> {
+        let norm_path = self.normalize_and_validate_path(sf_path)?;
+        let full_path = format!(
+            "{}/detailed-per-file-info/{}",
+            self.config_paths.index_path, norm_path
+        );
+        // Files that had nothing interesting to say don't get a detailed info
+        // file at all.
+        if !Path::new(&full_path).exists() {
+            return Ok(None);
+        }
+        let detailed: DetailedPerFileInfo =
+            serde_json::from_slice(&self.read_file(&full_path).await?)?;
+        Ok(detailed.coverage_lines)
+    }
+
+    async fn fetch_revision_timestamp(&self, rev: &str) -> Result<i64> {
+        let git_path = match &self.config_paths.git_path {
+            Some(git_path) => git_path,
+            None => return Err(ServerError::Unsupported),
+        };
+        tokio::task::block_in_place(|| -> std::result::Result<i64, String> {
+            let repo = Repository::open(git_path).map_err(|e| e.to_string())?;
+            let commit = repo
+                .revparse_single(rev)
+                .and_then(|obj| obj.peel_to_commit())
+                .map_err(|_| format!("Unknown revision {}", rev))?;
+            Ok(commit.time().seconds())
+        })
+        .map_err(|message| {
+            ServerError::StickyProblem(ErrorDetails {
+                layer: ErrorLayer::BadInput,
+                message,
+            })
+        })
+    }
+
+    fn file_bugzilla_component(&self, sf_path: &str) -> Result<Option<(Ustr, Ustr)>> {
+        let norm_path = self.normalize_and_validate_path(sf_path)?;
+        Ok(self
+            .file_lookup_map
+            .lookup_file_from_str(norm_path)
+            .and_then(|fi| fi.bugzilla_component))
+    }
+
+    fn file_test_annotations(&self, sf_path: &str) -> Result<Option<TestAnnotations>> {
+        let norm_path = self.normalize_and_validate_path(sf_path)?;
+        Ok(self
+            .file_lookup_map
+            .lookup_file_from_str(norm_path)
+            .and_then(|fi| TestAnnotations::from_concise_info(&fi.info)))
+    }
+
+    fn file_path_kind(&self, sf_path: &str) -> Result<Option<Ustr>> {
+        let norm_path = self.normalize_and_validate_path(sf_path)?;
+        Ok(self
+            .file_lookup_map
+            .lookup_file_from_str(norm_path)
+            .map(|fi| fi.path_kind))
+    }
+
+    fn sub_repo_for_path(&self, sf_path: &str) -> Result<Option<SubRepoInfo>> {
+        let norm_path = self.normalize_and_validate_path(sf_path)?;
+        let (prefix, sub_repo) = match self.config_paths.sub_repo_for_path(norm_path) {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+        let repo_path = norm_path[prefix.len()..].trim_start_matches('/');
+        let revision = self.sub_repo_revisions.get(prefix).cloned().flatten();
+        Ok(Some(SubRepoInfo {
+            name: sub_repo.name.clone(),
+            prefix: prefix.to_string(),
+            permalink: revision
+                .as_ref()
+                .and_then(|rev| sub_repo.permalink(rev, repo_path)),
+            revision,
+            repo_path: repo_path.to_string(),
+            line_anchor_pattern: sub_repo.line_anchor_pattern().to_string(),
+        }))
+    }
+
+    fn result_demotion_config(&self) -> Result<ResultDemotionConfig> {
+        Ok(self.config_paths.result_demotion.clone())
+    }
+
+    fn redaction_config(&self) -> Result<RedactionConfig> {
+        let mut config = self.config_paths.redaction.clone();
+        // Rewrite the tree's absolute directories into their searchfox
+        // equivalents before any configured rewrites.  The objdir goes first
+        // since it may live inside the files directory.
+        let dir_rule = |dir: &str, replacement: &str| PathRewriteRule {
+            pattern: format!("{}/", regex::escape(dir.trim_end_matches('/'))),
+            replacement: replacement.to_string(),
+        };
+        let mut rules = vec![
+            dir_rule(&self.config_paths.objdir_path, "__GENERATED__/"),
+            dir_rule(&self.config_paths.files_path, ""),
+        ];
+        if let Some(git_path) = &self.config_paths.git_path {
+            rules.push(dir_rule(git_path, ""));
+        }
+        rules.push(dir_rule(&self.config_paths.index_path, "<index>/"));
+        rules.append(&mut config.path_rewrites);
+        config.path_rewrites = rules;
+        Ok(config)
+    }
+
+    fn external_ranker_config(&self, name: &str) -> Result<Option<ExternalRankerConfig>> {
+        Ok(self.config_paths.external_rankers.get(name).cloned())
+    }
+
+    fn virtual_symbol_config(&self, name: &str) -> Result<Option<VirtualSymbolConfig>> {
+        Ok(self.config_paths.virtual_symbols.get(name).cloned())
+    }
+
+    fn output_size_guard_config(&self) -> Result<OutputSizeGuardConfig> {
+        Ok(self
+            .config_paths
+            .output_size_guard
+            .clone()
+            .unwrap_or_default())
+    }
+
+    fn path_alias_config(&self) -> Result<PathAliasConfig> {
+        let mut config = self.config_paths.path_aliases.clone();
+        if config.resolve_symlinks {
+            // The objdir goes first since it may live inside the files
+            // directory.
+            config.symlink_roots = vec![
+                (
+                    self.config_paths.objdir_path.clone(),
+                    "__GENERATED__/".to_string(),
+                ),
+                (self.config_paths.files_path.clone(), "".to_string()),
+            ];
+        }
+        Ok(config)
+    }
+
+    fn symbol_annotation_config(&self) -> Result<SymbolAnnotationConfig> {
+        Ok(self.config_paths.symbol_annotations.clone())
+    }
+
     async fn search_files(
         &self,
         pathre: &str,
@@ -353,87 +738,139 @@ impl AbstractServer for LocalIndex {
         }
     }
 
+    async fn identifiers_starting_with(
+        &self,
+        needle: &str,
+        min_prefix_chars: usize,
+        limit_per_prefix: usize,
+    ) -> Result<(Vec<(Ustr, Vec<Ustr>)>, bool)> {
+        if let Some(ident_map) = &self.ident_map {
+            let now = Instant::now();
+            let (results, truncated) =
+                ident_map.identifiers_starting_with(needle, min_prefix_chars, limit_per_prefix);
+            trace!(
+                duration_us = now.elapsed().as_micros() as u64,
+                result_count = results.len(),
+                truncated,
+                "identifiers_starting_with: {}",
+                needle
+            );
+            Ok((results, truncated))
+        } else {
+            Ok((vec![], false))
+        }
+    }
+
+    async fn identifier_frequency(&self, id: &str) -> Result<usize> {
+        let ident_map = match &self.ident_map {
+            Some(ident_map) => ident_map,
+            None => return Ok(0),
+        };
+        if let Some(frequency) = ident_map.frequency(id) {
+            return Ok(frequency);
+        }
+        let builder = ident_map.clone();
+        tokio::task::spawn_blocking(move || builder.build_frequency_table()).await?;
+        Ok(ident_map.frequency(id).unwrap_or(0))
+    }
+
+    async fn search_identifiers_regex(
+        &self,
+        pattern: &str,
+        types_only: bool,
+        match_limit: usize,
+        time_limit: Duration,
+    ) -> Result<(Vec<(Ustr, Ustr)>, bool)> {
+        let re = Regex::new(pattern)?;
+        if let Some(ident_map) = &self.ident_map {
+            let now = Instant::now();
+            let (matches, truncated) =
+                ident_map.scan_regex(&re, types_only, match_limit, now + time_limit);
+            let results: Vec<(Ustr, Ustr)> =
+                matches.into_iter().map(|ir| (ir.symbol, ir.id)).collect();
+            trace!(
+                duration_us = now.elapsed().as_micros() as u64,
+                result_count = results.len(),
+                truncated,
+                "search_identifiers_regex: {}",
+                pattern
+            );
+            Ok((results, truncated))
+        } else {
+            Ok((vec![], false))
+        }
+    }
+
     async fn search_text(
         &self,
         pattern: &str,
         fold_case: bool,
         path: &str,
         limit: usize,
+        context_lines: u32,
     ) -> Result<TextMatches> {
-        let now = Instant::now();
-
-        let endpoint = format!("http://localhost:{}", self.config_paths.codesearch_port);
-        trace!("search_text: connecting to {}", endpoint);
-
-        let mut client = CodeSearchClient::connect(endpoint).await?;
-
-        // Before multiple paths were allowed, an empty path constraint allowed
-        // us to skip the match; now if we pass an empty path in a vec, that
-        // will fail to match, so we want to pass an empty vec.
-        let use_path = if path.is_empty() {
-            vec![]
-        } else {
-            vec![path.into()]
-        };
-
-        let query = tonic::Request::new(Query {
-            line: pattern.into(),
-            file: use_path,
-            repo: "".into(),
-            tags: "".into(),
-            fold_case,
-            not_file: vec![],
-            not_repo: "".into(),
-            not_tags: "".into(),
-            // 0 falls back to the default, I believe.
-            max_matches: limit as i32,
-            filename_only: false,
-            // 0 should pick the default of 0.
-            context_lines: 0,
-        });
-
-        trace!("search_text: connected, issuing query: {}", pattern);
-        let response = client.search(query).await?.into_inner();
-
-        trace!(
-            duration_us = now.elapsed().as_micros() as u64,
-            result_count = response.results.len(),
-            "search_text: query completed: {}",
-            pattern
-        );
+        let mut endpoints = self.config_paths.codesearch_endpoints();
+        if endpoints.len() == 1 {
+            let endpoint = endpoints.remove(0);
+            return with_retries(&self.config_paths.retry_policy, "search_text", || {
+                self.search_text_once(&endpoint, pattern, fold_case, path, limit, context_lines)
+            })
+            .await;
+        }
 
-        let mut by_file: BTreeMap<String, TextMatchesByFile> = BTreeMap::new();
-        for result in response.results {
-            let left = result.bounds.as_ref().map_or(0, |b| b.left);
-            let right = result.bounds.as_ref().map_or(0, |b| b.right);
-            by_file
-                .entry(result.path.to_string())
-                .or_insert_with(|| {
-                    let path = ustr(&result.path);
-                    let path_kind = self
-                        .file_lookup_map
-                        .lookup_file_from_ustr(&path)
-                        .map_or_else(|| ustr(""), |fi| fi.path_kind);
-                    TextMatchesByFile {
-                        file: path,
-                        path_kind,
-                        matches: vec![],
-                    }
+        // Fan out to all of the shards in parallel, each of which gets the full
+        // limit since we can't know up front how the matches are distributed.
+        let mut handles = vec![];
+        for endpoint in endpoints {
+            let index = self.clone();
+            let pattern = pattern.to_string();
+            let path = path.to_string();
+            handles.push(tokio::spawn(async move {
+                let result = with_retries(&index.config_paths.retry_policy, "search_text", || {
+                    index.search_text_once(
+                        &endpoint,
+                        &pattern,
+                        fold_case,
+                        &path,
+                        limit,
+                        context_lines,
+                    )
                 })
-                .matches
-                .push(TextMatchInFile {
-                    line_num: result.line_number as u32,
-                    bounds: TextBounds {
-                        start: left,
-                        end_exclusive: right,
-                    },
-                    line_str: result.line,
-                });
+                .await;
+                (endpoint, result)
+            }));
         }
 
-        Ok(TextMatches {
-            by_file: by_file.into_values().collect(),
-        })
+        // A shard being down shouldn't take out text search entirely, so we
+        // only fail if every shard failed, reporting the first error.
+        // Otherwise the results are marked as missing the failed shards'.
+        let mut shard_matches = vec![];
+        let mut first_err = None;
+        for handle in handles {
+            match handle.await? {
+                (_, Ok(matches)) => shard_matches.push(matches),
+                (endpoint, Err(err)) => {
+                    warn!("search_text: shard {} failed: {:?}", endpoint, err);
+                    add_pipeline_warning(
+                        "codesearch",
+                        format!(
+                            "codesearch shard {} failed, so its matches are missing: {:?}",
+                            endpoint, err
+                        ),
+                    );
+                    first_err.get_or_insert(err);
+                }
+            }
+        }
+        match first_err {
+            Some(err) if shard_matches.is_empty() => Err(err),
+            Some(_) => {
+                let mut merged = merge_shard_matches(shard_matches, limit);
+                merged.exit_reason = merged.exit_reason.max(TextSearchExitReason::ShardFailed);
+                Ok(merged)
+            }
+            None => Ok(merge_shard_matches(shard_matches, limit)),
+        }
     }
 
     async fn perform_query(&self, _q: &str) -> Result<Value> {
@@ -445,11 +882,207 @@ impl AbstractServer for LocalIndex {
     }
 }
 
+/// Merge the per-shard results of a sharded text search, keeping the matches
+/// ordered by path and then line like `search_text_once`, and truncating to `limit` total matches (if
+/// non-zero) so that the fan-out doesn't change how many results callers get.
+/// The exit reason is the worst of the shards', and truncation here counts as
+/// hitting the match limit.
+fn merge_shard_matches(shard_matches: Vec<TextMatches>, limit: usize) -> TextMatches {
+    let mut by_file: BTreeMap<String, TextMatchesByFile> = BTreeMap::new();
+    let mut exit_reason = TextSearchExitReason::Complete;
+    for matches in shard_matches {
+        exit_reason = exit_reason.max(matches.exit_reason);
+        for file_matches in matches.by_file {
+            match by_file.get_mut(file_matches.file.as_str()) {
+                // Shards shouldn't overlap, but if a file was (re)indexed into
+                // more than one, don't report its lines twice.
+                Some(existing) => {
+                    for m in file_matches.matches {
+                        if !existing.matches.iter().any(|e| e.line_num == m.line_num) {
+                            existing.matches.push(m);
+                        }
+                    }
+                    existing.matches.sort_by_key(|m| m.line_num);
+                }
+                None => {
+                    by_file.insert(file_matches.file.to_string(), file_matches);
+                }
+            }
+        }
+    }
+
+    let mut remaining = if limit == 0 { usize::MAX } else { limit };
+    let mut merged = vec![];
+    for (_, mut file_matches) in by_file {
+        if remaining == 0 || file_matches.matches.len() > remaining {
+            exit_reason = exit_reason.max(TextSearchExitReason::MatchLimit);
+        }
+        if remaining == 0 {
+            break;
+        }
+        file_matches.matches.truncate(remaining);
+        remaining -= file_matches.matches.len();
+        merged.push(file_matches);
+    }
+    TextMatches {
+        by_file: merged,
+        exit_reason,
+    }
+}
+
+/// The details of a commit we report alongside blame and file history as
+/// (author, date, timestamp, bug, summary).
+fn describe_commit(
+    commit: &Commit,
+    mailmap: &Mailmap,
+) -> (String, String, i64, Option<u32>, String) {
+    lazy_static! {
+        static ref BUG_REGEX: Regex =
+            Regex::new(r"(?i)\bbug\s*(?P<bugno>[1-9][0-9]{2,9})\b").unwrap();
+    }
+
+    let sig = commit.author();
+    let (name, _email) = mailmap.lookup(sig.name().unwrap_or(""), sig.email().unwrap_or(""));
+    let naive_t = NaiveDateTime::from_timestamp(commit.time().seconds(), 0);
+    let tz = FixedOffset::east(commit.time().offset_minutes() * 60);
+    let t: DateTime<FixedOffset> = DateTime::from_utc(naive_t, tz);
+    let summary = commit.summary().unwrap_or("").to_string();
+    let bug = BUG_REGEX
+        .captures(&summary)
+        .and_then(|caps| caps["bugno"].parse().ok());
+    (
+        name.to_string(),
+        t.to_rfc3339(),
+        commit.time().seconds(),
+        bug,
+        summary,
+    )
+}
+
+/// Read the blame file for `path` out of the blame repo's HEAD (which
+/// corresponds to the indexed revision) and resolve each referenced revision
+/// against the source repo.
+fn compute_blame_lines(
+    git_path: &str,
+    blame_path: &str,
+    path: &str,
+    line_range: Option<(u32, u32)>,
+) -> std::result::Result<Vec<BlameLine>, String> {
+    let repo = Repository::open(git_path).map_err(|e| e.to_string())?;
+    let blame_repo = Repository::open(blame_path).map_err(|e| e.to_string())?;
+    let mailmap = Mailmap::load(&repo);
+
+    let blame_commit = blame_repo
+        .head()
+        .and_then(|head| head.peel_to_commit())
+        .map_err(|e| e.to_string())?;
+    let blame_entry = blame_commit
+        .tree()
+        .and_then(|tree| tree.get_path(Path::new(path)))
+        .map_err(|_| format!("No blame data for {}", path))?;
+    let blame_data = read_blob_entry(&blame_repo, &blame_entry);
+
+    let (first, last) = line_range.unwrap_or((1, u32::MAX));
+    // Many lines will share the same revision, so cache the commit details.
+    let mut rev_cache: HashMap<String, (String, String, i64, Option<u32>, String)> = HashMap::new();
+    let mut results = vec![];
+    for (i, line) in blame_data.lines().enumerate() {
+        let lno = i as u32 + 1;
+        if lno < first {
+            continue;
+        }
+        if lno > last {
+            break;
+        }
+
+        let line_data = LineData::deserialize(line);
+        let rev = line_data.rev.to_string();
+        if !rev_cache.contains_key(&rev) {
+            let details = match Oid::from_str(&rev).and_then(|oid| repo.find_commit(oid)) {
+                Ok(commit) => describe_commit(&commit, &mailmap),
+                Err(_) => ("".to_string(), "".to_string(), 0, None, "".to_string()),
+            };
+            rev_cache.insert(rev.clone(), details);
+        }
+        let (author, date, timestamp, bug, summary) = rev_cache[&rev].clone();
+
+        results.push(BlameLine {
+            lno,
+            rev,
+            path: if line_data.is_path_unchanged() {
+                path.to_string()
+            } else {
+                line_data.path.to_string()
+            },
+            rev_lno: line_data.lineno.parse().unwrap_or(0),
+            author,
+            date,
+            timestamp,
+            bug,
+            summary,
+        });
+    }
+
+    Ok(results)
+}
+
+/// Walk the first-parent history of the source repo's HEAD (which corresponds
+/// to the indexed revision), reporting each commit that changed the blob at
+/// `path` until the file didn't exist or we have `max_revisions`.
+fn compute_file_history(
+    git_path: &str,
+    path: &str,
+    max_revisions: usize,
+) -> std::result::Result<Vec<FileRevision>, String> {
+    let repo = Repository::open(git_path).map_err(|e| e.to_string())?;
+    let mailmap = Mailmap::load(&repo);
+    let blob_id = |commit: &Commit| -> Option<Oid> {
+        commit
+            .tree()
+            .and_then(|tree| tree.get_path(Path::new(path)))
+            .ok()
+            .map(|entry| entry.id())
+    };
+
+    let mut commit = repo
+        .head()
+        .and_then(|head| head.peel_to_commit())
+        .map_err(|e| e.to_string())?;
+    let mut cur_blob = blob_id(&commit).ok_or_else(|| format!("No such file {}", path))?;
+    let mut results = vec![];
+    while results.len() < max_revisions {
+        let parent = commit.parent(0).ok();
+        let parent_blob = parent.as_ref().and_then(|p| blob_id(p));
+        if parent_blob != Some(cur_blob) {
+            let blob = repo.find_blob(cur_blob).map_err(|e| e.to_string())?;
+            let (author, date, timestamp, bug, summary) = describe_commit(&commit, &mailmap);
+            results.push(FileRevision {
+                rev: commit.id().to_string(),
+                author,
+                date,
+                timestamp,
+                bug,
+                summary,
+                contents: String::from_utf8_lossy(blob.content()).into_owned(),
+            });
+        }
+        match (parent, parent_blob) {
+            (Some(parent), Some(parent_blob)) => {
+                commit = parent;
+                cur_blob = parent_blob;
+            }
+            _ => break,
+        }
+    }
+
+    Ok(results)
+}
+
 fn fab_server(
     tree_config: TreeConfig,
     tree_name: &str,
     config_repo_path: &str,
-) -> Result<Box<dyn AbstractServer + Send + Sync>> {
+) -> Result<LocalIndex> {
     let ident_path = format!("{}/identifiers", tree_config.paths.index_path);
     let ident_map = IdentMap::new(&ident_path);
 
@@ -463,6 +1096,17 @@ fn fab_server(
 
     let jumpref_lookup_map = CrossrefLookupMap::new(&jumpref_path, &jumpref_extra_path);
 
+    // Unlike crossref and jumpref, the aggregates store is optional, and
+    // `CrossrefLookupMap::new` panics on missing files.
+    let aggregates_path = format!("{}/aggregates", tree_config.paths.index_path);
+    let aggregates_extra_path = format!("{}/aggregates-extra", tree_config.paths.index_path);
+    let aggregates_lookup_map =
+        if Path::new(&aggregates_path).exists() && Path::new(&aggregates_extra_path).exists() {
+            CrossrefLookupMap::new(&aggregates_path, &aggregates_extra_path)
+        } else {
+            None
+        };
+
     let file_lookup_path = format!(
         "{}/concise-per-file-info.json",
         tree_config.paths.index_path
@@ -470,7 +1114,14 @@ fn fab_server(
 
     let file_lookup_map = FileLookupMap::new(&file_lookup_path);
 
-    Ok(Box::new(LocalIndex {
+    let sub_repo_revisions = tree_config
+        .paths
+        .sub_repos
+        .iter()
+        .map(|(prefix, sub_repo)| (prefix.clone(), sub_repo.resolve_revision()))
+        .collect();
+
+    Ok(LocalIndex {
         // We don't need the blame_map and hg_map (yet)
         config_paths: tree_config.paths,
         config_repo_path: config_repo_path.to_string(),
@@ -478,8 +1129,10 @@ fn fab_server(
         ident_map,
         crossref_lookup_map,
         jumpref_lookup_map,
+        aggregates_lookup_map,
         file_lookup_map,
-    }))
+        sub_repo_revisions,
+    })
 }
 
 pub fn make_local_server(
@@ -497,17 +1150,106 @@ pub fn make_local_server(
         }
     };
 
-    fab_server(tree_config, tree_name, &config.config_repo_path)
+    Ok(Box::new(fab_server(
+        tree_config,
+        tree_name,
+        &config.config_repo_path,
+    )?))
 }
 
+/// Create servers for all of the config's trees, using the given output size
+/// guard for the trees whose config doesn't specify one.  This is for
+/// long-running servers, so it also starts building each tree's identifier
+/// frequency table in the background rather than leaving that to the first
+/// query which needs it, and so must be called within a tokio runtime.
 pub fn make_all_local_servers(
     config_path: &str,
+    default_output_size_guard: &OutputSizeGuardConfig,
 ) -> Result<BTreeMap<String, Box<dyn AbstractServer + Send + Sync>>> {
     let config = load(config_path, false, None, None);
-    let mut servers = BTreeMap::new();
-    for (tree_name, tree_config) in config.trees {
+    let mut servers: BTreeMap<String, Box<dyn AbstractServer + Send + Sync>> = BTreeMap::new();
+    for (tree_name, mut tree_config) in config.trees {
+        tree_config
+            .paths
+            .output_size_guard
+            .get_or_insert_with(|| default_output_size_guard.clone());
         let server = fab_server(tree_config, &tree_name, &config.config_repo_path)?;
-        servers.insert(tree_name, server);
+        if let Some(ident_map) = &server.ident_map {
+            let builder = ident_map.clone();
+            tokio::task::spawn_blocking(move || builder.build_frequency_table());
+        }
+        servers.insert(tree_name, Box::new(server));
     }
     Ok(servers)
 }
+
+#[test]
+fn test_merge_shard_matches() {
+    let file_matches = |file: &str, lines: &[u32]| TextMatchesByFile {
+        file: ustr(file),
+        path_kind: ustr("Normal"),
+        matches: lines
+            .iter()
+            .map(|&line_num| TextMatchInFile {
+                line_num,
+                bounds: TextBounds {
+                    start: 0,
+                    end_exclusive: 3,
+                },
+                line_str: "foo".to_string(),
+                context_before: vec![],
+                context_after: vec![],
+            })
+            .collect(),
+    };
+    let shards = || {
+        vec![
+            TextMatches {
+                by_file: vec![file_matches("b.cpp", &[1, 2])],
+                exit_reason: TextSearchExitReason::Complete,
+            },
+            TextMatches {
+                by_file: vec![file_matches("a.cpp", &[7]), file_matches("b.cpp", &[2, 9])],
+                exit_reason: TextSearchExitReason::Complete,
+            },
+        ]
+    };
+
+    let lines = |matches: &TextMatches| -> Vec<(String, Vec<u32>)> {
+        matches
+            .by_file
+            .iter()
+            .map(|f| {
+                (
+                    f.file.to_string(),
+                    f.matches.iter().map(|m| m.line_num).collect(),
+                )
+            })
+            .collect()
+    };
+    let merged = merge_shard_matches(shards(), 0);
+    assert_eq!(
+        lines(&merged),
+        vec![
+            ("a.cpp".to_string(), vec![7]),
+            ("b.cpp".to_string(), vec![1, 2, 9])
+        ]
+    );
+    assert_eq!(merged.exit_reason, TextSearchExitReason::Complete);
+    let merged = merge_shard_matches(shards(), 2);
+    assert_eq!(
+        lines(&merged),
+        vec![
+            ("a.cpp".to_string(), vec![7]),
+            ("b.cpp".to_string(), vec![1])
+        ]
+    );
+    assert_eq!(merged.exit_reason, TextSearchExitReason::MatchLimit);
+
+    let mut timed_out = shards();
+    timed_out[0].exit_reason = TextSearchExitReason::Timeout;
+    assert_eq!(
+        merge_shard_matches(timed_out, 0).exit_reason,
+        TextSearchExitReason::Timeout
+    );
+}
@@ -1,11 +1,16 @@
+mod discovery;
 mod lazy_crossref;
 mod local_index;
 mod remote_server;
+mod retry;
 mod server_interface;
 
+pub use discovery::{discover_config_path, make_server_for_selector, select_tree};
 pub use local_index::{make_all_local_servers, make_local_server};
 pub use remote_server::make_remote_server;
 pub use server_interface::{
-    AbstractServer, ErrorDetails, ErrorLayer, FileMatch, FileMatches, HtmlFileRoot, Result,
-    SearchfoxIndexRoot, ServerError, TextMatches, TextMatchesByFile, TreeInfo,
+    AbstractServer, BlameLine, ErrorDetails, ErrorLayer, FileMatch, FileMatches, FileRevision,
+    HtmlFileRoot, PathUseCount, Result, SearchfoxIndexRoot, ServerError, SubRepoInfo,
+    SymbolAggregates, SymbolRelationCounts, TextBounds, TextMatchInFile, TextMatches,
+    TextMatchesByFile, TextSearchExitReason, TreeInfo,
 };
@@ -1,16 +1,25 @@
 use async_trait::async_trait;
 use futures_core::stream::BoxStream;
 use serde_json::{from_str, Value};
+use std::time::Duration;
+use tokio_stream::StreamExt;
 use url::{ParseError, Url};
 use ustr::Ustr;
 
+use super::retry::with_retries;
 use super::{
     server_interface::{
-        AbstractServer, ErrorDetails, ErrorLayer, FileMatches, Result, SearchfoxIndexRoot,
-        ServerError,
+        AbstractServer, BlameLine, ErrorDetails, ErrorLayer, FileMatches, FileRevision, Result,
+        SearchfoxIndexRoot, ServerError, SubRepoInfo, SymbolAggregates, SymbolRelationCounts,
     },
     HtmlFileRoot, TextMatches, TreeInfo,
 };
+use crate::file_format::analysis::{analysis_record_overlaps, SourceRange};
+use crate::file_format::config::{
+    ExternalRankerConfig, OutputSizeGuardConfig, PathAliasConfig, RedactionConfig,
+    ResultDemotionConfig, RetryPolicyConfig, SymbolAnnotationConfig, VirtualSymbolConfig,
+};
+use crate::file_format::per_file_info::TestAnnotations;
 
 /// reqwest won't return an error for an unhappy status code itself; someone
 /// would need to call `Response::error_from_status`, so for now we'll generally
@@ -44,7 +53,16 @@ struct RemoteServer {
     search_url: Url,
 }
 
+/// The remote server has no tree config to get a retry policy from, so it
+/// uses the default.
 async fn get(url: Url) -> Result<reqwest::Response> {
+    with_retries(&RetryPolicyConfig::default(), "get", || {
+        get_once(url.clone())
+    })
+    .await
+}
+
+async fn get_once(url: Url) -> Result<reqwest::Response> {
     //println!("Using URL {}", url);
     let res = reqwest::get(url).await?;
 
@@ -66,6 +84,13 @@ async fn get(url: Url) -> Result<reqwest::Response> {
 }
 
 async fn get_json(url: Url) -> Result<reqwest::Response> {
+    with_retries(&RetryPolicyConfig::default(), "get_json", || {
+        get_json_once(url.clone())
+    })
+    .await
+}
+
+async fn get_json_once(url: Url) -> Result<reqwest::Response> {
     let client = reqwest::Client::new();
     let res = client
         .get(url)
@@ -117,6 +142,18 @@ impl AbstractServer for RemoteServer {
         Ok(Box::pin(tokio_stream::iter(values?)))
     }
 
+    async fn fetch_analysis_in_range(
+        &self,
+        sf_path: &str,
+        range: SourceRange,
+    ) -> Result<Vec<Value>> {
+        let records = self.fetch_raw_analysis(sf_path).await?;
+        Ok(records
+            .filter(|record| analysis_record_overlaps(record, &range))
+            .collect()
+            .await)
+    }
+
     async fn fetch_formatted_lines(&self, _sf_path: &str) -> Result<(Vec<String>, String)> {
         Err(ServerError::Unsupported)
     }
@@ -126,6 +163,10 @@ impl AbstractServer for RemoteServer {
         Err(ServerError::Unsupported)
     }
 
+    async fn fetch_raw_source_bytes(&self, _sf_path: &str) -> Result<Vec<u8>> {
+        Err(ServerError::Unsupported)
+    }
+
     async fn fetch_html(&self, root: HtmlFileRoot, sf_path: &str) -> Result<String> {
         // We don't have access to raw templates, so just call that unsupported.
         // Note that we could special-case for "help.html" here since it does
@@ -156,11 +197,110 @@ impl AbstractServer for RemoteServer {
         Err(ServerError::Unsupported)
     }
 
+    async fn symbol_relation_counts(&self, _symbol: &str) -> Result<Option<SymbolRelationCounts>> {
+        // Same rationale for `crossref_lookup` above.
+        Err(ServerError::Unsupported)
+    }
+
+    async fn precomputed_aggregates(&self, _symbol: &str) -> Result<Option<SymbolAggregates>> {
+        // Same rationale for `crossref_lookup` above.
+        Err(ServerError::Unsupported)
+    }
+
+    async fn crossref_scan<'a>(&self) -> Result<BoxStream<'a, Result<(String, Value)>>> {
+        // There's no way to enumerate symbols via the web-server.
+        Err(ServerError::Unsupported)
+    }
+
     async fn jumpref_lookup(&self, _symbol: &str) -> Result<Value> {
         // Same rationale for `crossref_lookup` above.
         Err(ServerError::Unsupported)
     }
 
+    async fn fetch_blame_lines(
+        &self,
+        _sf_path: &str,
+        _line_range: Option<(u32, u32)>,
+    ) -> Result<Vec<BlameLine>> {
+        // The web server only exposes blame as part of rendered HTML and via
+        // per-revision commit-info, neither of which is a good fit.
+        Err(ServerError::Unsupported)
+    }
+
+    async fn fetch_revision_timestamp(&self, _rev: &str) -> Result<i64> {
+        // Same rationale as fetch_blame_lines.
+        Err(ServerError::Unsupported)
+    }
+
+    async fn fetch_file_history(
+        &self,
+        _sf_path: &str,
+        _max_revisions: usize,
+    ) -> Result<Vec<FileRevision>> {
+        // Same rationale as fetch_blame_lines.
+        Err(ServerError::Unsupported)
+    }
+
+    async fn fetch_coverage_lines(&self, _sf_path: &str) -> Result<Option<Vec<i64>>> {
+        // Coverage is likewise only exposed as part of rendered HTML.
+        Err(ServerError::Unsupported)
+    }
+
+    fn file_bugzilla_component(&self, _sf_path: &str) -> Result<Option<(Ustr, Ustr)>> {
+        // Same rationale as search_files.
+        Err(ServerError::Unsupported)
+    }
+
+    fn file_test_annotations(&self, _sf_path: &str) -> Result<Option<TestAnnotations>> {
+        // Same rationale as search_files.
+        Err(ServerError::Unsupported)
+    }
+
+    fn file_path_kind(&self, _sf_path: &str) -> Result<Option<Ustr>> {
+        // Same rationale as search_files.
+        Err(ServerError::Unsupported)
+    }
+
+    fn sub_repo_for_path(&self, _sf_path: &str) -> Result<Option<SubRepoInfo>> {
+        // The web-server doesn't expose the tree config.
+        Err(ServerError::Unsupported)
+    }
+
+    fn result_demotion_config(&self) -> Result<ResultDemotionConfig> {
+        // Same rationale for `sub_repo_for_path` above.
+        Err(ServerError::Unsupported)
+    }
+
+    fn redaction_config(&self) -> Result<RedactionConfig> {
+        // Same rationale for `sub_repo_for_path` above.
+        Err(ServerError::Unsupported)
+    }
+
+    fn external_ranker_config(&self, _name: &str) -> Result<Option<ExternalRankerConfig>> {
+        // Same rationale for `sub_repo_for_path` above.
+        Err(ServerError::Unsupported)
+    }
+
+    fn virtual_symbol_config(&self, _name: &str) -> Result<Option<VirtualSymbolConfig>> {
+        // Same rationale for `sub_repo_for_path` above.
+        Err(ServerError::Unsupported)
+    }
+
+    fn output_size_guard_config(&self) -> Result<OutputSizeGuardConfig> {
+        // Same rationale for `sub_repo_for_path` above.
+        Err(ServerError::Unsupported)
+    }
+
+    fn path_alias_config(&self) -> Result<PathAliasConfig> {
+        // Same rationale for `sub_repo_for_path` above.
+        Err(ServerError::Unsupported)
+    }
+
+    fn symbol_annotation_config(&self) -> Result<SymbolAnnotationConfig> {
+        // Same rationale for `sub_repo_for_path` above.
+        Err(ServerError::Unsupported)
+    }
+
     async fn search_files(
         &self,
         _pathre: &str,
@@ -182,12 +322,39 @@ impl AbstractServer for RemoteServer {
         Err(ServerError::Unsupported)
     }
 
+    async fn identifiers_starting_with(
+        &self,
+        _needle: &str,
+        _min_prefix_chars: usize,
+        _limit_per_prefix: usize,
+    ) -> Result<(Vec<(Ustr, Vec<Ustr>)>, bool)> {
+        // Same rationale as crossref_lookup.
+        Err(ServerError::Unsupported)
+    }
+
+    async fn identifier_frequency(&self, _id: &str) -> Result<usize> {
+        // Same rationale as crossref_lookup.
+        Err(ServerError::Unsupported)
+    }
+
+    async fn search_identifiers_regex(
+        &self,
+        _pattern: &str,
+        _types_only: bool,
+        _match_limit: usize,
+        _time_limit: Duration,
+    ) -> Result<(Vec<(Ustr, Ustr)>, bool)> {
+        // Same rationale as crossref_lookup.
+        Err(ServerError::Unsupported)
+    }
+
     async fn search_text(
         &self,
         _pattern: &str,
         _fold_case: bool,
         _path: &str,
         _limit: usize,
+        _context_lines: u32,
     ) -> Result<TextMatches> {
         // It's not clear we ever want to implement this.
         Err(ServerError::Unsupported)
@@ -0,0 +1,94 @@
+use std::{future::Future, time::Duration};
+
+use tracing::trace;
+
+use super::server_interface::{Result, ServerError};
+use crate::{cmd_pipeline::warnings::strict_mode_enabled, file_format::config::RetryPolicyConfig};
+
+/// Run `op` until it succeeds, fails with a sticky problem, or the policy's
+/// attempts are exhausted, sleeping with exponential backoff between attempts
+/// that fail with a `ServerError::TransientProblem`.  The error from the last
+/// attempt is returned.  Strict mode only makes a single attempt so that
+/// flakiness isn't hidden.
+pub async fn with_retries<T, F, Fut>(policy: &RetryPolicyConfig, what: &str, mut op: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut backoff_ms = policy.initial_backoff_ms;
+    let mut attempt = 1;
+    loop {
+        match op().await {
+            Err(ServerError::TransientProblem(details))
+                if attempt < policy.attempts && !strict_mode_enabled() =>
+            {
+                trace!(
+                    attempt,
+                    backoff_ms,
+                    layer = ?details.layer,
+                    "{}: transient failure, retrying: {}",
+                    what,
+                    details.message
+                );
+                tokio::time::sleep(Duration::from_millis(backoff_ms)).await;
+                backoff_ms = backoff_ms.saturating_mul(2).min(policy.max_backoff_ms);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[test]
+fn test_with_retries() {
+    use super::server_interface::{ErrorDetails, ErrorLayer};
+
+    let policy = RetryPolicyConfig {
+        attempts: 3,
+        initial_backoff_ms: 0,
+        max_backoff_ms: 0,
+    };
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+
+    let transient = || {
+        ServerError::TransientProblem(ErrorDetails {
+            layer: ErrorLayer::CodesearchLayer,
+            message: "unavailable".to_string(),
+        })
+    };
+
+    // Transient failures are retried until success.
+    let mut calls = 0;
+    let result = runtime.block_on(with_retries(&policy, "test", || {
+        calls += 1;
+        let result = if calls < 3 {
+            Err(transient())
+        } else {
+            Ok(calls)
+        };
+        async move { result }
+    }));
+    assert_eq!(result.unwrap(), 3);
+
+    // But only up to the number of attempts.
+    let mut calls = 0;
+    let result: Result<()> = runtime.block_on(with_retries(&policy, "test", || {
+        calls += 1;
+        let err = transient();
+        async move { Err(err) }
+    }));
+    assert!(matches!(result, Err(ServerError::TransientProblem(_))));
+    assert_eq!(calls, 3);
+
+    // Sticky problems are never retried.
+    let mut calls = 0;
+    let result: Result<()> = runtime.block_on(with_retries(&policy, "test", || {
+        calls += 1;
+        async move { Err(ServerError::Unsupported) }
+    }));
+    assert!(matches!(result, Err(ServerError::Unsupported)));
+    assert_eq!(calls, 1);
+}
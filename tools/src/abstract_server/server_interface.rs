@@ -1,11 +1,20 @@
 use async_trait::async_trait;
 use axum::http::StatusCode;
-use axum::response::{IntoResponse, Response};
+use axum::response::{IntoResponse, Json, Response};
 use futures_core::stream::BoxStream;
-use serde::Serialize;
-use serde_json::Value;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::time::Duration;
 use ustr::{ustr, Ustr};
 
+use crate::cmd_pipeline::diagnostics::PipelineDiagnostic;
+use crate::cmd_pipeline::warnings::PipelineWarning;
+use crate::file_format::analysis::SourceRange;
+use crate::file_format::config::{
+    ExternalRankerConfig, OutputSizeGuardConfig, PathAliasConfig, RedactionConfig,
+    ResultDemotionConfig, SymbolAnnotationConfig, VirtualSymbolConfig,
+};
+use crate::file_format::per_file_info::TestAnnotations;
 use crate::file_format::repo_data_ingestion::ConcisePerFileInfo;
 
 pub type Result<T> = std::result::Result<T, ServerError>;
@@ -62,8 +71,17 @@ impl From<liquid::Error> for ServerError {
 
 impl IntoResponse for ServerError {
     fn into_response(self) -> Response {
-        let body = format!("Error: {:#?}", self);
-        (StatusCode::INTERNAL_SERVER_ERROR, body).into_response()
+        match self {
+            ServerError::BadPipeline(diagnostic) => (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "error": diagnostic })),
+            )
+                .into_response(),
+            _ => {
+                let body = format!("Error: {:#?}", self);
+                (StatusCode::INTERNAL_SERVER_ERROR, body).into_response()
+            }
+        }
     }
 }
 
@@ -81,7 +99,7 @@ pub enum HtmlFileRoot {
 }
 
 /// Express whether the error seems to be happening in the server or the data.
-#[derive(Debug)]
+#[derive(Debug, Deserialize, Serialize)]
 pub enum ErrorLayer {
     /// The request itself has structural issues like a malformed URL.  This
     /// should not be used for cases where the user input results in a search
@@ -110,6 +128,13 @@ pub enum ErrorLayer {
     /// The error seems to be related to the indexed data in question rather
     /// than the server, like the data was not indexed.
     DataLayer,
+    /// A gRPC request to the codesearch (livegrep) server failed.
+    CodesearchLayer,
+    /// Reading the crossref (or jumpref) database failed or produced data we
+    /// couldn't make sense of.
+    CrossrefIoLayer,
+    /// Reading a file from the index or source tree failed.
+    FileIoLayer,
     /// Our data structure doesn't work like it's supposed to and we don't want
     /// to panic, so we return this instead.
     RuntimeInvariantViolation,
@@ -147,38 +172,83 @@ pub enum ServerError {
     /// timeout".
     TransientProblem(ErrorDetails),
     Unsupported,
+    /// The pipeline couldn't be parsed.  This is always a sticky input problem
+    /// but gets its own variant so the diagnostic can be returned to the user
+    /// as-is.
+    BadPipeline(PipelineDiagnostic),
+    /// Strict mode was requested and a command had to degrade its results in
+    /// the way described by the warning.  This is only produced for
+    /// searchfox-tool's `--strict`.
+    StrictFailure(PipelineWarning),
 }
 
 /// Livegrep/codesearch bounds
-#[derive(Serialize)]
+#[derive(Clone, Serialize)]
 pub struct TextBounds {
     pub start: i32,
     pub end_exclusive: i32,
 }
 
 /// Livegrep/codesearch line hit results
-#[derive(Serialize)]
+#[derive(Clone, Serialize)]
 pub struct TextMatchInFile {
     pub line_num: u32,
     pub bounds: TextBounds,
     // This will vary a lot and so can never be a Ustr.
     pub line_str: String,
+    /// The lines before the match in file order, when context lines were
+    /// requested.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub context_before: Vec<String>,
+    /// The lines after the match, when context lines were requested.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub context_after: Vec<String>,
 }
 
-#[derive(Serialize)]
+#[derive(Clone, Serialize)]
 pub struct TextMatchesByFile {
     pub file: Ustr,
     pub path_kind: Ustr,
     pub matches: Vec<TextMatchInFile>,
 }
 
+/// Why codesearch stopped looking for matches, ordered so that combining the
+/// reasons of multiple searches can just take the max.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TextSearchExitReason {
+    /// The search ran to completion, so the matches are all of the matches.
+    Complete,
+    /// The search stopped at the `max_matches` limit, so there may be more.
+    MatchLimit,
+    /// The search timed out, so there may be more matches, and the matches we
+    /// have aren't necessarily the first ones.
+    Timeout,
+    /// Some of the codesearch shards failed, so their matches are missing.
+    /// This has already been reported as a pipeline warning.
+    ShardFailed,
+}
+
+impl TextSearchExitReason {
+    pub fn is_complete(&self) -> bool {
+        *self == TextSearchExitReason::Complete
+    }
+}
+
 /// Livegrep/codesearch text search results clustered by file.
-#[derive(Serialize)]
+#[derive(Clone, Serialize)]
 pub struct TextMatches {
     pub by_file: Vec<TextMatchesByFile>,
+    /// Labels truncated results so they don't look complete; only serialized
+    /// when the results were truncated.
+    #[serde(
+        rename = "exitReason",
+        skip_serializing_if = "TextSearchExitReason::is_complete"
+    )]
+    pub exit_reason: TextSearchExitReason,
 }
 
-#[derive(Serialize)]
+#[derive(Clone, Serialize)]
 pub struct FileMatch {
     pub path: Ustr,
     pub concise: ConcisePerFileInfo<Ustr>,
@@ -194,11 +264,115 @@ impl FileMatch {
     }
 }
 
-#[derive(Serialize)]
+#[derive(Clone, Serialize)]
 pub struct FileMatches {
     pub file_matches: Vec<FileMatch>,
 }
 
+/// Blame information for a single line of a file as of the indexed revision.
+#[derive(Serialize)]
+pub struct BlameLine {
+    /// The 1-based line number in the indexed revision.
+    pub lno: u32,
+    /// The git revision that last changed this line.
+    pub rev: String,
+    /// The path of the file as of `rev`; this differs from the requested path
+    /// if the file was moved since then.
+    pub path: String,
+    /// The 1-based line number of this line as of `rev`.
+    pub rev_lno: u32,
+    /// The mailmap-normalized name of the author of `rev`.
+    pub author: String,
+    /// The author date of `rev` in RFC 3339 format.
+    pub date: String,
+    /// The commit time of `rev` in seconds since the epoch, or 0 if unknown.
+    /// This is what should be used for date comparisons.
+    pub timestamp: i64,
+    /// The bug number extracted from the commit summary, if any.
+    pub bug: Option<u32>,
+    /// The first line of the commit message.
+    pub summary: String,
+}
+
+/// A revision of a file from `fetch_file_history`, with the commit details in
+/// the same form as `BlameLine`.
+#[derive(Clone, Serialize)]
+pub struct FileRevision {
+    /// The git revision that changed the file.
+    pub rev: String,
+    /// The mailmap-normalized name of the author of `rev`.
+    pub author: String,
+    /// The author date of `rev`, formatted like `BlameLine::date`.
+    pub date: String,
+    /// The commit time of `rev` in seconds since the epoch.
+    pub timestamp: i64,
+    /// The bug number extracted from the commit summary, if any.
+    pub bug: Option<u32>,
+    /// The first line of the commit message.
+    pub summary: String,
+    /// The contents of the file as of `rev`.
+    #[serde(skip)]
+    pub contents: String,
+}
+
+/// Information about the sub-repository (ex: git submodule) a file in a tree
+/// that aggregates multiple repositories belongs to.
+#[derive(Clone, Serialize)]
+pub struct SubRepoInfo {
+    /// The configured name of the sub-repo.
+    pub name: String,
+    /// The tree-relative path the sub-repo is checked out at.
+    pub prefix: String,
+    /// The revision of the sub-repo checked out in the tree, if known.
+    pub revision: Option<String>,
+    /// The path of the file relative to the root of the sub-repo.
+    pub repo_path: String,
+    /// A link to the file at `revision` on the sub-repo's upstream server, if
+    /// both are known.
+    pub permalink: Option<String>,
+    /// The pattern to append to `permalink` to target a line, where `{}` is
+    /// replaced by the line number, ex: "#L{}".
+    pub line_anchor_pattern: String,
+}
+
+/// The sizes of a symbol's structured relationship lists, as returned by
+/// `AbstractServer::symbol_relation_counts`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SymbolRelationCounts {
+    pub overridden_by: usize,
+    pub subclasses: usize,
+    pub overrides: usize,
+    pub supers: usize,
+}
+
+/// How many uses of a symbol are in a file, as part of `SymbolAggregates`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PathUseCount {
+    pub path: Ustr,
+    pub uses: usize,
+}
+
+/// Per-symbol aggregates that are expensive to compute at query time, as
+/// returned by `AbstractServer::precomputed_aggregates`.  Which aggregates are
+/// present depends on how `precompute-aggregates` was run.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SymbolAggregates {
+    /// The number of distinct symbols whose definitions use the symbol.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caller_count: Option<usize>,
+    /// The number of lines that use the symbol.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub use_count: Option<usize>,
+    /// The number of direct and indirect subclasses of the symbol.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subclass_count: Option<usize>,
+    /// The files with the most uses of the symbol, most uses first.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_uses: Option<Vec<PathUseCount>>,
+}
+
 pub enum SearchfoxIndexRoot {
     /// Already gzipped analysis files.  Note that `fetch_raw_analysis` exists
     /// and should be used in preference to this for reading file contents.
@@ -295,6 +469,17 @@ pub trait AbstractServer {
     /// tree-local path, decompressing if it's compressed.
     async fn fetch_raw_analysis<'a>(&self, sf_path: &str) -> Result<BoxStream<'a, Value>>;
 
+    /// Fetch the analysis records for the given searchfox tree-local path
+    /// whose `loc` or `nestingRange` overlaps the given range, in file order;
+    /// see `SourceRange::overlaps`.  Unlike filtering by line, this lets callers ask about a specific
+    /// position or selection, and also returns the records of the definitions
+    /// enclosing it.
+    async fn fetch_analysis_in_range(
+        &self,
+        sf_path: &str,
+        range: SourceRange,
+    ) -> Result<Vec<Value>>;
+
     /// Fetch the contents of a raw (not HTML rendered) source file
     /// corresponding to the indexed revision like you would get out of revision
     /// control.
@@ -303,6 +488,10 @@ pub trait AbstractServer {
     /// we can actually check the source file out if needed.
     async fn fetch_raw_source(&self, sf_path: &str) -> Result<String>;
 
+    /// Like `fetch_raw_source` but without requiring the file to be UTF-8 so
+    /// that callers can deal with binary files.
+    async fn fetch_raw_source_bytes(&self, sf_path: &str) -> Result<Vec<u8>>;
+
     /// Fetch the lines in the rendered HTML file.
     ///
     /// Returns a tuple of a list of lines, 0-th item for line 1,
@@ -326,6 +515,107 @@ pub trait AbstractServer {
     /// symbol.
     async fn jumpref_lookup(&self, symbol: &str) -> Result<Value>;
 
+    /// Retrieve just the number of overrides, subclasses, overridden methods,
+    /// and superclasses of the given symbol without retrieving the lists
+    /// themselves.  Symbols without structured information have all-zero
+    /// counts; unknown symbols return None.  Trees without local crossref
+    /// data will return `ServerError::Unsupported`.
+    async fn symbol_relation_counts(&self, symbol: &str) -> Result<Option<SymbolRelationCounts>>;
+
+    /// Retrieve the aggregates `precompute-aggregates` stored for the symbol at
+    /// index time.  Symbols that weren't worth precomputing return None, in
+    /// which case callers should compute what they need live.  Trees without
+    /// a precomputed aggregates store will return `ServerError::Unsupported`.
+    async fn precomputed_aggregates(&self, symbol: &str) -> Result<Option<SymbolAggregates>>;
+
+    /// Stream every (symbol, crossref value) pair in the tree's crossref
+    /// database in symbol order.  This is intended for tree-wide analyses and
+    /// is correspondingly expensive.  No lazy crossref processing is performed.
+    /// Trees without local crossref data will return `ServerError::Unsupported`.
+    async fn crossref_scan<'a>(&self) -> Result<BoxStream<'a, Result<(String, Value)>>>;
+
+    /// Retrieve the blame data for the given tree-local path as of the indexed
+    /// revision, optionally limited to the inclusive 1-based line range.
+    /// Trees without blame data will return `ServerError::Unsupported`.
+    async fn fetch_blame_lines(
+        &self,
+        sf_path: &str,
+        line_range: Option<(u32, u32)>,
+    ) -> Result<Vec<BlameLine>>;
+
+    /// Resolve a git revision (hash, tag, branch, or other revspec) in the
+    /// indexed tree's repository to its commit time in seconds since the epoch.
+    /// Trees without a git repository will return `ServerError::Unsupported`.
+    async fn fetch_revision_timestamp(&self, rev: &str) -> Result<i64>;
+
+    /// Retrieve the revisions of the given tree-local path that changed its
+    /// contents, newest first, walking first-parent history back from the
+    /// indexed revision until `max_revisions` have been found or the file
+    /// didn't exist.  Renames are not followed.  Trees without a git
+    /// repository will return `ServerError::Unsupported`.
+    async fn fetch_file_history(
+        &self,
+        sf_path: &str,
+        max_revisions: usize,
+    ) -> Result<Vec<FileRevision>>;
+
+    /// Retrieve the per-line code coverage data for the given tree-local path
+    /// from its detailed per-file info, where index 0 is line 1.  Values are
+    /// hit counts as post-processed by `interpolate_coverage`: 0 is a miss,
+    /// positive values are hits, -1 means the line wasn't instrumented, and -2
+    /// and -3 are interpolated hits and misses respectively.  Files without
+    /// coverage data return None.
+    async fn fetch_coverage_lines(&self, sf_path: &str) -> Result<Option<Vec<i64>>>;
+
+    /// Return the Bugzilla (product, component) pair for the given tree-local
+    /// path as derived from `moz.build` `BUG_COMPONENT` metadata, if any.  This
+    /// is synchronous because the per-file info is expected to be pre-loaded
+    /// like for `search_files`; an unknown path is not an error and returns
+    /// None.
+    fn file_bugzilla_component(&self, sf_path: &str) -> Result<Option<(Ustr, Ustr)>>;
+
+    /// Return the test manifest annotations (skip-if, fail-if) for the given
+    /// tree-local path.  Like `file_bugzilla_component` this comes from the
+    /// pre-loaded per-file info; paths the test manifests don't know about
+    /// return None.
+    fn file_test_annotations(&self, sf_path: &str) -> Result<Option<TestAnnotations>>;
+
+    /// Return the path kind (ex: "Generated code") of the given tree-local
+    /// path from the pre-loaded per-file info; unknown paths return None.
+    fn file_path_kind(&self, sf_path: &str) -> Result<Option<Ustr>>;
+
+    /// Return the sub-repository the given tree-local path belongs to for
+    /// trees configured with `sub_repos`.  Paths that are part of the tree's
+    /// primary repository return None.
+    fn sub_repo_for_path(&self, sf_path: &str) -> Result<Option<SubRepoInfo>>;
+
+    /// Return the tree's configuration for demoting search results based on
+    /// where they live.
+    fn result_demotion_config(&self) -> Result<ResultDemotionConfig>;
+
+    /// Return the tree's configuration for anonymizing results with `redact`,
+    /// including rewrites for the tree's absolute directories.
+    fn redaction_config(&self) -> Result<RedactionConfig>;
+
+    /// Return the tree's configuration for the named external ranker used by
+    /// `rerank`, if there is one.
+    fn external_ranker_config(&self, name: &str) -> Result<Option<ExternalRankerConfig>>;
+
+    /// Return the tree's configuration for the named virtual symbol group, if
+    /// there is one.  The name excludes the `VIRTUAL_` symbol prefix.
+    fn virtual_symbol_config(&self, name: &str) -> Result<Option<VirtualSymbolConfig>>;
+
+    /// Return the tree's limit on the size of each pipeline stage's output.
+    fn output_size_guard_config(&self) -> Result<OutputSizeGuardConfig>;
+
+    /// Return the tree's configuration for folding together paths that are
+    /// aliases of each other, including the tree's absolute directories if
+    /// symlinks should be resolved.
+    fn path_alias_config(&self) -> Result<PathAliasConfig>;
+
+    /// Return the tree's annotations for symbols like deprecated APIs.
+    fn symbol_annotation_config(&self) -> Result<SymbolAnnotationConfig>;
+
     /// Search the list of all files using a (potentially empty) regexp string
     /// and optionally enforcing a limit.  The underlying list of files should
     /// be equivalent to the union of the `repo-files` and `objdir-files`
@@ -373,17 +663,57 @@ pub trait AbstractServer {
         match_limit: usize,
     ) -> Result<Vec<(Ustr, Ustr)>>;
 
+    /// Return the identifiers sharing a case-insensitive prefix of at least
+    /// `min_prefix_chars` characters with `needle`, each paired with all of
+    /// its symbols, in a single pass over the identifiers index.  This
+    /// replaces doing a `search_identifiers` prefix search per prefix length
+    /// when looking for identifiers that are similar to a needle.  At most
+    /// `limit_per_prefix` identifiers are returned for each shared prefix
+    /// length, with the returned bool indicating whether any were omitted.
+    async fn identifiers_starting_with(
+        &self,
+        needle: &str,
+        min_prefix_chars: usize,
+        limit_per_prefix: usize,
+    ) -> Result<(Vec<(Ustr, Vec<Ustr>)>, bool)>;
+
+    /// Return how many symbols have the given unqualified identifier,
+    /// case-insensitively, for recognizing stop words like "get" that a
+    /// fulltext search would match all over the tree.  This is answered from a
+    /// frequency table built from the identifiers index (in the background at
+    /// pipeline-server startup, otherwise on first use) which only tracks
+    /// identifiers with at least `IDENTIFIER_FREQUENCY_FLOOR` symbols, so
+    /// rarer identifiers report 0.
+    async fn identifier_frequency(&self, id: &str) -> Result<usize>;
+
+    /// Return (symbol, identifier) pairs for the identifiers matching the given
+    /// regex.  Unlike `search_identifiers` this has to scan the entire
+    /// identifier list, so the scan gives up once `match_limit` matches have
+    /// been found or `time_limit` has elapsed, with the returned bool
+    /// indicating whether the results were truncated.  If `types_only`, only
+    /// type symbols ("T_" prefixed) are returned and count towards the limit.
+    async fn search_identifiers_regex(
+        &self,
+        pattern: &str,
+        types_only: bool,
+        match_limit: usize,
+        time_limit: Duration,
+    ) -> Result<(Vec<(Ustr, Ustr)>, bool)>;
+
     /// Given an re2 search pattern and additional config info, run a
     /// livegrep codesearch against an already-running codesearch server.  In
     /// the future while our rust code may be responsible for starting the
     /// codesearch server and keeping it running, for now that responsibility
     /// continues to fall to the `router.py` webserver using `codesearch.py`.
+    /// Each match includes up to `context_lines` lines of context before and
+    /// after it.  Matches are ordered by path and then line.
     async fn search_text(
         &self,
         pattern: &str,
         fold_case: bool,
         path: &str,
         limit: usize,
+        context_lines: u32,
     ) -> Result<TextMatches>;
 
     async fn perform_query(&self, q: &str) -> Result<Value>;
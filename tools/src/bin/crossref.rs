@@ -6,6 +6,7 @@ use std::fs::create_dir_all;
 use std::fs::File;
 use std::io::BufRead;
 use std::io::BufReader;
+use std::io::BufWriter;
 use std::io::Write;
 
 #[macro_use]
@@ -22,13 +23,15 @@ use tools::file_format::analysis::OntologySlotKind;
 use tools::file_format::analysis::StructuredPointerInfo;
 use tools::file_format::analysis::StructuredTag;
 use tools::file_format::analysis::{
-    read_analysis, read_structured, read_target, AnalysisKind, AnalysisTarget, BindingSlotProps,
-    LineRange, Location, SearchResult, StructuredBindingSlotInfo, TargetTag,
+    read_analysis, read_source, read_structured, read_target, AnalysisKind, AnalysisSource,
+    AnalysisTarget, BindingSlotProps, ExpansionInfo, LineRange, Location, SearchResult,
+    StructuredBindingSlotInfo, TargetTag,
 };
 use tools::file_format::analysis_manglings::make_file_sym_from_path;
 use tools::file_format::analysis_manglings::split_pretty;
 use tools::file_format::config;
 use tools::file_format::crossref_converter::convert_crossref_value_to_sym_info_rep;
+use tools::file_format::identifiers::normalize_identifier;
 use tools::file_format::ontology_mapping::OntologyRunnableMode;
 use tools::file_format::ontology_mapping::{
     OntologyLabelOwningClass, OntologyMappingIngestion, OntologyPointerKind,
@@ -76,9 +79,11 @@ type PrettyTable = HashMap<Ustr, Ustr>;
 type IdTable = UstrMap<UstrSet>;
 type MetaTable = BTreeMap<Ustr, AnalysisStructured>;
 type CalleesTable = BTreeMap<Ustr, BTreeMap<Ustr, (Ustr, BTreeSet<u32>)>>;
+type MacroExpansionTable = BTreeMap<Ustr, BTreeMap<Ustr, (Ustr, BTreeSet<u32>)>>;
 type FieldMemberUseTable = BTreeMap<Ustr, BTreeMap<Ustr, Vec<(Ustr, OntologyPointerKind)>>>;
 type XrefLinkSubclass = Vec<(Ustr, Ustr)>;
 type XrefLinkOverride = Vec<(Ustr, Ustr)>;
+type XrefLinkSpecialization = Vec<(Ustr, Ustr)>;
 type XrefLinkSlots = BTreeMap<(Ustr, Ustr), (BindingSlotProps, Option<Ustr>)>;
 
 fn process_analysis_target(
@@ -137,6 +142,7 @@ fn process_analysis_target(
         context: piece.context,
         contextsym: piece.contextsym,
         peek_range: piece.peek_range,
+        origin: piece.origin,
     });
 
     // Idempotently insert the pretty identifier -> symbol mapping as long as the pretty
@@ -169,12 +175,54 @@ fn process_analysis_target(
     }
 }
 
+/// Macro expansion sites are "source" records with an "expandsTo" attribute
+/// and the symbols produced by the expansion are "source" records with an
+/// "inExpansionAt" attribute, both keyed by `{macro symbol}(,{dependencies})*`.
+/// We only need the latter to relate the macro to the symbols it produced.
+fn process_analysis_source_expansion(
+    piece: AnalysisSource,
+    path: &Ustr,
+    loc: &Location,
+    expands_to_table: &mut MacroExpansionTable,
+    expanded_from_table: &mut MacroExpansionTable,
+) {
+    let offsets = match piece.expansion_info {
+        Some(ExpansionInfo::InExpansionAt(offsets)) => offsets,
+        _ => return,
+    };
+    for key in offsets.keys() {
+        let macro_sym = match key.split(',').next() {
+            Some(s) if !s.is_empty() => ustr(s),
+            _ => continue,
+        };
+        for sym in &piece.sym {
+            if *sym == macro_sym {
+                continue;
+            }
+            for (table, from, to) in [
+                (&mut *expands_to_table, macro_sym, *sym),
+                (&mut *expanded_from_table, *sym, macro_sym),
+            ] {
+                let (from_path, lines) = table
+                    .entry(from)
+                    .or_default()
+                    .entry(to)
+                    .or_insert_with(|| (*path, BTreeSet::new()));
+                if from_path == path {
+                    lines.insert(loc.lineno);
+                }
+            }
+        }
+    }
+}
+
 fn process_analysis_structured(
     mut piece: AnalysisStructured,
     subsystem: Option<Ustr>,
     meta_table: &mut MetaTable,
     xref_link_subclass: &mut XrefLinkSubclass,
     xref_link_override: &mut XrefLinkOverride,
+    xref_link_specialization: &mut XrefLinkSpecialization,
     xref_link_slots: &mut XrefLinkSlots,
 ) {
     meta_table.entry(piece.sym).or_insert_with(|| {
@@ -186,6 +234,10 @@ fn process_analysis_structured(
             xref_link_override.push((override_info.sym, piece.sym));
         }
 
+        for specialization_info in &piece.specialization_of {
+            xref_link_specialization.push((specialization_info.sym, piece.sym));
+        }
+
         // We remove all bindings infos from AnalysisStructured instances here
         // but add them back both ways when we iterate over xref_link_slots.
         for slot_info in piece.binding_slots.drain(..) {
@@ -228,18 +280,24 @@ fn make_subsystem(
             impl_kind: ustr("impl"),
             size_bytes: None,
             own_vf_ptr_bytes: None,
+            align_bytes: None,
+            max_field_align_bytes: None,
+            underlying_type: None,
             binding_slots: vec![],
             ontology_slots: vec![],
             supers: vec![],
             methods: vec![],
             fields: vec![],
+            enumerators: vec![],
             overrides: vec![],
+            specialization_of: vec![],
             props: vec![],
             labels: BTreeSet::default(),
 
             idl_sym: None,
             subclass_syms: vec![],
             overridden_by_syms: vec![],
+            specialization_syms: vec![],
             variants: vec![],
             extra: Map::default(),
         };
@@ -405,6 +463,8 @@ async fn main() {
     let xref_ext_file = format!("{}/crossref-extra", tree_config.paths.index_path);
     let jumpref_file = format!("{}/jumpref", tree_config.paths.index_path);
     let jumpref_ext_file = format!("{}/jumpref-extra", tree_config.paths.index_path);
+    let xref_index_file = format!("{}/crossref-index", tree_config.paths.index_path);
+    let jumpref_index_file = format!("{}/jumpref-index", tree_config.paths.index_path);
     let id_file = format!("{}/identifiers", tree_config.paths.index_path);
 
     // Nested table hierarchy keyed by: [symbol, kind, path] with Vec<SearchResult> as the leaf
@@ -456,6 +516,10 @@ async fn main() {
     // is the class referencing the type and whose values are a vec of tuples of
     // the form (field pretty, pointer kind).
     let mut field_member_use_table = FieldMemberUseTable::new();
+    // Maps a (raw) macro symbol to the symbols produced by its expansions, in
+    // the same form as `callees_table`, and the inverse.
+    let mut expands_to_table = MacroExpansionTable::new();
+    let mut expanded_from_table = MacroExpansionTable::new();
 
     // As we process the source entries and build the SourceMeta, we keep a running list of what
     // cross-SourceMeta links need to be established.  We then process this after all of the files
@@ -465,6 +529,9 @@ async fn main() {
     let mut xref_link_subclass = XrefLinkSubclass::new();
     // Pairs of [parent method sym, overridden by sym] to add the override to the parent.
     let mut xref_link_override = XrefLinkOverride::new();
+    // Pairs of [template sym, specialization sym] to add the specialization to
+    // the template.
+    let mut xref_link_specialization = XrefLinkSpecialization::new();
     // (owner symbol, slotted symbol) -> slot props
     // This is a BTreeMap and not a HashMap to force a stable ordering and avoid flaky tests.
     let mut xref_link_slots = XrefLinkSlots::new();
@@ -514,6 +581,7 @@ async fn main() {
                     &mut meta_table,
                     &mut xref_link_subclass,
                     &mut xref_link_override,
+                    &mut xref_link_specialization,
                     &mut xref_link_slots,
                 );
             }
@@ -571,6 +639,19 @@ async fn main() {
                 );
             }
         }
+
+        let source_analysis = read_analysis(&analysis_fname, &mut read_source);
+        for datum in source_analysis {
+            for piece in datum.data {
+                process_analysis_source_expansion(
+                    piece,
+                    path,
+                    &datum.loc,
+                    &mut expands_to_table,
+                    &mut expanded_from_table,
+                );
+            }
+        }
     }
 
     let other_resources_file = &cli.other_resources_list_path;
@@ -623,6 +704,7 @@ async fn main() {
                 end_lineno: 0,
             },
             arg_ranges: vec![],
+            origin: None,
         };
 
         process_analysis_target(
@@ -661,6 +743,12 @@ async fn main() {
         }
     }
 
+    for (template_sym, specialization_sym) in xref_link_specialization {
+        if let Some(template_meta) = meta_table.get_mut(&template_sym) {
+            template_meta.specialization_syms.push(specialization_sym);
+        }
+    }
+
     for ((owner_sym, slotted_sym), (props, subsystem)) in xref_link_slots {
         if let Some(owner) = meta_table.get_mut(&owner_sym) {
             owner.binding_slots.push(StructuredBindingSlotInfo {
@@ -1027,6 +1115,11 @@ async fn main() {
     let mut jumpref_out = File::create(jumpref_file).unwrap();
     let mut jumpref_ext_out = File::create(jumpref_ext_file).unwrap();
 
+    // The `-index` files are the offsets of each identifier line in the main
+    // file so that lookups can bisect directly to an identifier.
+    let mut xref_index_out = BufWriter::new(File::create(xref_index_file).unwrap());
+    let mut jumpref_index_out = BufWriter::new(File::create(jumpref_index_file).unwrap());
+
     // We need to know offset positions in the `-extra` file.  File::tell is a
     // nightly-only experimental API as documented at
     // https://github.com/rust-lang/rust/issues/71213 which makes it preferable
@@ -1038,6 +1131,8 @@ async fn main() {
     // mess up.
     let mut xref_ext_offset: usize = 0;
     let mut jumpref_ext_offset: usize = 0;
+    let mut xref_offset: usize = 0;
+    let mut jumpref_offset: usize = 0;
 
     // Let's only report missing concise info at most once, as for those cases
     // where we have them (ex: NSS), there's usually a lot of symbols in the
@@ -1094,6 +1189,27 @@ async fn main() {
             }
             kindmap.insert("callees".to_string(), json!(callees));
         }
+        for (kindstr, expansion_table) in [
+            ("expands-to", &expands_to_table),
+            ("expanded-from", &expanded_from_table),
+        ] {
+            if let Some(related_syms) = expansion_table.get(&id) {
+                let mut related = Vec::new();
+                for (related_sym, (rel_path, rel_lines)) in related_syms {
+                    let mut obj = BTreeMap::new();
+                    obj.insert("sym".to_string(), related_sym.to_string());
+                    if let Some(pretty) = pretty_table.get(related_sym) {
+                        obj.insert("pretty".to_string(), pretty.to_string());
+                    }
+                    obj.insert(
+                        "jump".to_string(),
+                        format!("{}#{}", rel_path, rel_lines.iter().join(",")),
+                    );
+                    related.push(json!(obj));
+                }
+                kindmap.insert(kindstr.to_string(), json!(related));
+            }
+        }
         if let Some(fmu_syms) = field_member_use_table.get(&id) {
             let mut fmus = Vec::new();
             for (fmu_sym, fmu_field_infos) in fmu_syms {
@@ -1126,6 +1242,9 @@ async fn main() {
         {
             let id_line = format!("!{}\n", id);
             let inline_line = format!(":{}\n", kindmap);
+            xref_index_out
+                .write_all(&(xref_offset as u64).to_le_bytes())
+                .unwrap();
             if inline_line.len() >= EXTERNAL_STORAGE_THRESHOLD {
                 // ### External storage.
                 xref_out.write_all(id_line.as_bytes()).unwrap();
@@ -1142,6 +1261,7 @@ async fn main() {
                     inline_line.len() - 1
                 );
                 xref_out.write_all(ext_offset_line.as_bytes()).unwrap();
+                xref_offset += id_line.len() + ext_offset_line.len();
 
                 xref_ext_out.write_all(inline_line.as_bytes()).unwrap();
                 xref_ext_offset += inline_line.len();
@@ -1149,6 +1269,7 @@ async fn main() {
                 // ### Inline storage.
                 xref_out.write_all(id_line.as_bytes()).unwrap();
                 xref_out.write_all(inline_line.as_bytes()).unwrap();
+                xref_offset += id_line.len() + inline_line.len();
             }
         }
 
@@ -1157,6 +1278,9 @@ async fn main() {
         {
             let id_line = format!("!{}\n", id);
             let inline_line = format!(":{}\n", jumpref_info);
+            jumpref_index_out
+                .write_all(&(jumpref_offset as u64).to_le_bytes())
+                .unwrap();
             if inline_line.len() >= EXTERNAL_STORAGE_THRESHOLD {
                 // ### External storage.
                 jumpref_out.write_all(id_line.as_bytes()).unwrap();
@@ -1173,6 +1297,7 @@ async fn main() {
                     inline_line.len() - 1
                 );
                 jumpref_out.write_all(ext_offset_line.as_bytes()).unwrap();
+                jumpref_offset += id_line.len() + ext_offset_line.len();
 
                 jumpref_ext_out.write_all(inline_line.as_bytes()).unwrap();
                 jumpref_ext_offset += inline_line.len();
@@ -1180,12 +1305,17 @@ async fn main() {
                 // ### Inline storage.
                 jumpref_out.write_all(id_line.as_bytes()).unwrap();
                 jumpref_out.write_all(inline_line.as_bytes()).unwrap();
+                jumpref_offset += id_line.len() + inline_line.len();
             }
         }
     }
+    xref_index_out.flush().unwrap();
+    jumpref_index_out.flush().unwrap();
 
     let mut idf = File::create(id_file).unwrap();
     for (id, syms) in id_table {
+        // The identifier lookup normalizes its needle the same way.
+        let id = normalize_identifier(&id);
         for sym in syms {
             let line = format!("{} {}\n", id, sym);
             let _ = idf.write_all(line.as_bytes());
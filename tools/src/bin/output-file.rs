@@ -124,6 +124,15 @@ fn main() {
     let head_commit =
         head_oid.and_then(|oid| tree_config.git.as_ref().unwrap().repo.find_commit(oid).ok());
 
+    // Sub-repos get their own upstream permalinks, so resolve their revisions
+    // once up front.
+    let sub_repo_revisions: HashMap<&str, String> = tree_config
+        .paths
+        .sub_repos
+        .iter()
+        .filter_map(|(prefix, sub_repo)| Some((prefix.as_str(), sub_repo.resolve_revision()?)))
+        .collect();
+
     writeln!(
         stdout,
         "Blame prep done, duration: {}us",
@@ -350,6 +359,21 @@ fn main() {
                         copyable: true,
                     });
                 }
+                if let Some((prefix, sub_repo)) = tree_config.paths.sub_repo_for_path(&path) {
+                    let repo_path = path[prefix.len()..].trim_start_matches('/');
+                    let upstream_link = sub_repo_revisions
+                        .get(prefix)
+                        .and_then(|rev| sub_repo.permalink(rev, repo_path));
+                    if let Some(link) = upstream_link {
+                        vcs_panel_items.push(PanelItem {
+                            title: format!("Upstream permalink ({})", sub_repo.name),
+                            link,
+                            update_link_lineno: sub_repo.line_anchor_pattern(),
+                            accel_key: None,
+                            copyable: true,
+                        });
+                    }
+                }
                 if tree_config.paths.git_blame_path.is_some() {
                     vcs_panel_items.push(PanelItem {
                         title: "Blame".to_owned(),
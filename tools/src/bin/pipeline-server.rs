@@ -1,39 +1,326 @@
 use std::{
     collections::{BTreeMap, HashMap},
     env,
+    path::{Path as FsPath, PathBuf},
     sync::Arc,
 };
 
+use async_trait::async_trait;
 use axum::{
-    extract::{Path, Query},
-    http::{HeaderMap, StatusCode},
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, Query, RawQuery,
+    },
+    http::{header, HeaderMap, StatusCode},
     response::{Html, IntoResponse, Response},
     routing::get,
     Extension, Json, Router,
 };
 use axum_macros::debug_handler;
+use clap::Parser;
 use liquid::Template;
-use serde_json::Value;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::{
+    sync::mpsc::{unbounded_channel, UnboundedReceiver},
+    task::JoinHandle,
+    time::{sleep_until, Duration, Instant},
+};
 use tools::{
-    abstract_server::{make_all_local_servers, AbstractServer, ServerError},
-    cmd_pipeline::{builder::build_pipeline_graph, PipelineValues},
+    abstract_server::{
+        make_all_local_servers, AbstractServer, ErrorDetails, ErrorLayer, Result as ServerResult,
+        ServerError,
+    },
+    cmd_pipeline::{
+        admission::{classify_graph, estimate_cost, AdmissionControl, PlanCost},
+        builder::build_pipeline_graph,
+        diagnostics::PipelineDiagnostic,
+        interface::ServerPipelineGraph,
+        warnings::{collect_pipeline_warnings, PipelineWarning},
+        worker_pool::{
+            create_private_socket_dir, serve_worker, spawn_worker_processes, AdmitAll,
+            WorkerAdmission, WorkerJob, WorkerJobHandler, WorkerJobKind, WorkerOutput, WorkerPool,
+            WorkerReply,
+        },
+        PipelineValues,
+    },
+    file_format::config::{load, OutputSizeGuardConfig, SizeGuardAction},
     logging::{init_logging, LoggedSpan},
-    query::chew_query::chew_query,
+    query::{
+        chew_query::QueryConfig,
+        config_watcher::TreeQueryConfigs,
+        legacy_router::{legacy_search_estimate, legacy_search_json, LegacySearchParams},
+        query_plan::plan_query_with_config,
+    },
     templating::builder::build_and_parse_query_results,
 };
-use tracing::Instrument;
+use tracing::{error, warn, Instrument};
+
+#[derive(Parser)]
+struct PipelineServerCli {
+    /// Path to the variable-expanded config file
+    #[clap(value_parser)]
+    config_file: String,
+
+    /// Run queries in this many pre-spawned worker processes instead of
+    /// in-process.  0 runs queries in-process.
+    #[clap(long, value_parser, default_value = "0")]
+    workers: usize,
+
+    /// Directory to create the worker sockets in.  Defaults to a directory
+    /// only the current user can access; see `create_private_socket_dir`.
+    #[clap(long, value_parser)]
+    socket_dir: Option<PathBuf>,
+
+    /// How many queries may wait for a free worker before we start responding
+    /// with 503s.
+    #[clap(long, value_parser, default_value = "32")]
+    max_queued: usize,
+
+    /// How many expensive queries (ex: traversals estimated to hit their node
+    /// limit) may run at once.  0 means no limit.
+    #[clap(long, value_parser, default_value = "4")]
+    max_expensive: usize,
+
+    /// How many expensive queries may wait for their turn before we start
+    /// responding to them with 503s.
+    #[clap(long, value_parser, default_value = "8")]
+    max_expensive_queued: usize,
+
+    /// The maximum serialized size of each pipeline stage's output for trees
+    /// whose config doesn't have an `output_size_guard`.  0 disables the
+    /// guard.
+    #[clap(long, value_parser, default_value = "536870912")]
+    max_stage_output_bytes: u64,
+
+    /// Queries each worker runs against every tree before it accepts jobs so
+    /// that the index data they touch is already paged in.
+    #[clap(long, value_parser)]
+    warm_query: Vec<String>,
+
+    /// Run as a worker listening on this socket.  Used by `--workers`.
+    #[clap(long, value_parser, hide = true)]
+    worker_socket: Option<PathBuf>,
+}
+
+/// How long clients should wait before retrying an expensive query that we
+/// rejected, which is longer than for a busy worker since expensive queries
+/// take a while to finish.
+const EXPENSIVE_RETRY_AFTER: &str = "5";
+
+const EXPENSIVE_REJECTED_MESSAGE: &str =
+    "Too many expensive queries are running; try again shortly.";
+
+fn expensive_rejected_response() -> Response {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [("retry-after", EXPENSIVE_RETRY_AFTER)],
+        EXPENSIVE_REJECTED_MESSAGE,
+    )
+        .into_response()
+}
+
+/// Run the job on a worker, returning the response to send instead of its
+/// output if it couldn't be run.
+async fn submit_job(
+    pool: &WorkerPool,
+    job: &WorkerJob,
+    admission: &AdmissionControl,
+) -> Result<Result<WorkerOutput, Response>, ServerError> {
+    match pool.submit(job, admission).await? {
+        Some(WorkerReply::Done(output)) => Ok(Ok(output)),
+        Some(WorkerReply::NoSuchTree) => Ok(Err((
+            StatusCode::NOT_FOUND,
+            format!("No such tree: {}", job.tree),
+        )
+            .into_response())),
+        Some(WorkerReply::Error { error }) => Err(error.into()),
+        Some(WorkerReply::BadPipeline { diagnostic }) => Err(ServerError::BadPipeline(diagnostic)),
+        Some(WorkerReply::NotAdmitted) => Ok(Err(expensive_rejected_response())),
+        Some(WorkerReply::NeedsAdmission) => Err(ServerError::StickyProblem(ErrorDetails {
+            layer: ErrorLayer::RuntimeInvariantViolation,
+            message: "worker admission request outlived the job".to_string(),
+        })),
+        None => Ok(Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            [("retry-after", "1")],
+            "All pipeline workers are busy; try again shortly.",
+        )
+            .into_response())),
+    }
+}
+
+/// Plan the query and classify its graph for admission control.  Classifying
+/// runs the cheap start of the graph's pipelines, whose outputs the graph
+/// keeps for when it runs.
+async fn plan_and_classify(
+    server: &(dyn AbstractServer + Send + Sync),
+    query: &str,
+    query_config: &QueryConfig,
+) -> ServerResult<(ServerPipelineGraph, PlanCost)> {
+    let pipeline_plan = plan_query_with_config(server, query, query_config).await?;
+    let mut graph = build_pipeline_graph(server.clonify(), pipeline_plan)?;
+    let cost = classify_graph(&mut graph).await?;
+    Ok((graph, cost))
+}
+
+/// Extract the SYM_INFO for the results types that provide one.  For
+/// `BatchGroups`, the SYM_INFO of all of the groups is merged since they will
+/// all be displayed on the same page.
+fn sym_info_for_results(results: &PipelineValues) -> Option<Value> {
+    match results {
+        PipelineValues::GraphResultsBundle(grb) => Some(grb.symbols.clone()),
+        PipelineValues::SymbolTreeTableList(sttl) => Some(sttl.unioned_node_sets_as_jumprefs()),
+        PipelineValues::BatchGroups(bg) => {
+            let mut merged = serde_json::Map::new();
+            for group in &bg.groups {
+                if let Some(Value::Object(obj)) = sym_info_for_results(&group.value) {
+                    merged.extend(obj);
+                }
+            }
+            Some(Value::Object(merged))
+        }
+        _ => None,
+    }
+}
+
+fn worker_output(
+    result: ServerResult<PipelineValues>,
+    warnings: Vec<PipelineWarning>,
+) -> ServerResult<WorkerOutput> {
+    let result = result?;
+    Ok(WorkerOutput {
+        sym_info: sym_info_for_results(&result),
+        results: serde_json::to_value(&result)?,
+        warnings,
+    })
+}
+
+/// Runs the queries sent to a worker process by the pipeline-server's
+/// `WorkerPool`.
+struct QueryJobHandler {
+    local_servers: Arc<BTreeMap<String, Box<dyn AbstractServer + Send + Sync>>>,
+    query_configs: Arc<TreeQueryConfigs>,
+}
+
+impl QueryJobHandler {
+    /// Plan and run a query, returning None if it's expensive and wasn't
+    /// admitted.
+    async fn run_query(
+        server: &(dyn AbstractServer + Send + Sync),
+        query_config: &QueryConfig,
+        query: &str,
+        admission: &mut dyn WorkerAdmission,
+    ) -> ServerResult<Option<WorkerOutput>> {
+        let (result, warnings) = collect_pipeline_warnings(async {
+            let (graph, cost) = plan_and_classify(server, query, query_config).await?;
+            if cost == PlanCost::Expensive && !admission.admit().await? {
+                return Ok(None);
+            }
+            graph.run(true).await.map(Some)
+        })
+        .await;
+        match result? {
+            Some(values) => worker_output(Ok(values), warnings).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Run a legacy search, returning None if it's expensive and wasn't
+    /// admitted.  The results are the response's JSON as a string so that it
+    /// stays byte-compatible with router.py's.
+    async fn run_legacy_search(
+        server: &(dyn AbstractServer + Send + Sync),
+        query: &str,
+        admission: &mut dyn WorkerAdmission,
+    ) -> ServerResult<Option<WorkerOutput>> {
+        let params = LegacySearchParams::from_query_string(query);
+        let cost = estimate_cost(&legacy_search_estimate(&params));
+        if cost == PlanCost::Expensive && !admission.admit().await? {
+            return Ok(None);
+        }
+        Ok(Some(WorkerOutput {
+            results: Value::String(legacy_search_json(server, &params).await?),
+            warnings: vec![],
+            sym_info: None,
+        }))
+    }
+}
+
+#[async_trait]
+impl WorkerJobHandler for QueryJobHandler {
+    async fn run_job(&self, job: WorkerJob, admission: &mut dyn WorkerAdmission) -> WorkerReply {
+        let (server, query_config) = match (
+            self.local_servers.get(&job.tree),
+            self.query_configs.get(&job.tree),
+        ) {
+            (Some(s), Some(c)) => (s, c),
+            _ => return WorkerReply::NoSuchTree,
+        };
+        let result = match job.kind {
+            WorkerJobKind::Query => {
+                Self::run_query(server.as_ref(), &query_config, &job.query, admission).await
+            }
+            WorkerJobKind::LegacySearch => {
+                Self::run_legacy_search(server.as_ref(), &job.query, admission).await
+            }
+        };
+        match result {
+            Ok(Some(output)) => WorkerReply::Done(output),
+            Ok(None) => WorkerReply::NotAdmitted,
+            Err(err) => WorkerReply::from_error(err),
+        }
+    }
+}
+
+/// Warm up and then serve jobs on the socket forever.
+async fn run_worker(handler: QueryJobHandler, socket_path: &FsPath, warm_queries: &[String]) {
+    for query in warm_queries {
+        for tree in handler.local_servers.keys() {
+            let job = WorkerJob {
+                tree: tree.clone(),
+                query: query.clone(),
+                kind: WorkerJobKind::Query,
+            };
+            match handler.run_job(job, &mut AdmitAll).await {
+                WorkerReply::Error { error } => {
+                    warn!("warm-up query {:?} failed on {}: {:?}", query, tree, error);
+                }
+                WorkerReply::BadPipeline { diagnostic } => {
+                    warn!(
+                        "warm-up query {:?} failed on {}: {}",
+                        query, tree, diagnostic.message
+                    );
+                }
+                _ => {}
+            }
+        }
+    }
+    // The pipeline-server respawns workers that exit.
+    if let Err(err) = serve_worker(socket_path, &handler).await {
+        error!(
+            "worker unable to serve on {}: {:?}",
+            socket_path.display(),
+            err
+        );
+        std::process::exit(1);
+    }
+}
 
 #[debug_handler]
 async fn handle_query(
     local_servers: Extension<Arc<BTreeMap<String, Box<dyn AbstractServer + Send + Sync>>>>,
+    query_configs: Extension<Arc<TreeQueryConfigs>>,
     templates: Extension<Arc<SomeTemplates>>,
+    worker_pool: Extension<Option<Arc<WorkerPool>>>,
+    admission: Extension<Arc<AdmissionControl>>,
     headers: HeaderMap,
     Path((tree, preset)): Path<(String, String)>,
     Query(params): Query<HashMap<String, String>>,
 ) -> Result<Response, ServerError> {
-    let server = match local_servers.get(&tree) {
-        Some(s) => s,
-        None => {
+    let (server, query_config) = match (local_servers.get(&tree), query_configs.get(&tree)) {
+        (Some(s), Some(c)) => (s, c),
+        _ => {
             return Ok((StatusCode::NOT_FOUND, format!("No such tree: {}", tree)).into_response());
         }
     };
@@ -56,19 +343,67 @@ async fn handle_query(
         }
     };
 
-    let graph = {
-        let _log_entered = logged_span
-            .as_ref()
-            .map(|lspan| lspan.span.clone().entered());
+    let output = match (worker_pool.0.as_ref(), &logged_span) {
+        // Debug logs can only be captured in-process.  The worker classifies
+        // the query itself and asks the pool for admission if it's expensive.
+        (Some(pool), None) => {
+            let job = WorkerJob {
+                tree: tree.clone(),
+                query: query.clone(),
+                kind: WorkerJobKind::Query,
+            };
+            match submit_job(pool, &job, &admission).await? {
+                Ok(output) => output,
+                Err(response) => return Ok(response),
+            }
+        }
+        _ => {
+            // Planning may consult the server, so it can't happen with the
+            // span entered.
+            let planning = collect_pipeline_warnings(plan_query_with_config(
+                server.as_ref(),
+                query,
+                &query_config,
+            ));
+            let (pipeline_plan, mut plan_warnings) = match &logged_span {
+                Some(lspan) => planning.instrument(lspan.span.clone()).await,
+                _ => planning.await,
+            };
+            let pipeline_plan = pipeline_plan?;
 
-        let pipeline_plan = chew_query(query)?;
+            let mut graph = {
+                let _log_entered = logged_span
+                    .as_ref()
+                    .map(|lspan| lspan.span.clone().entered());
 
-        build_pipeline_graph(server.clonify(), pipeline_plan)?
-    };
+                build_pipeline_graph(server.clonify(), pipeline_plan)?
+            };
 
-    let result = match &logged_span {
-        Some(lspan) => graph.run(true).instrument(lspan.span.clone()).await?,
-        _ => graph.run(true).await?,
+            // Admission is decided on the plan the planner actually produced,
+            // since it may have dropped or bounded the expensive parts of the
+            // query.
+            let classifying = collect_pipeline_warnings(classify_graph(&mut graph));
+            let (cost, classify_warnings) = match &logged_span {
+                Some(lspan) => classifying.instrument(lspan.span.clone()).await,
+                _ => classifying.await,
+            };
+            plan_warnings.extend(classify_warnings);
+            let _admission = match admission.admit(cost?).await {
+                Some(admission) => admission,
+                None => return Ok(expensive_rejected_response()),
+            };
+
+            let (result, warnings) = match &logged_span {
+                Some(lspan) => {
+                    collect_pipeline_warnings(graph.run(true))
+                        .instrument(lspan.span.clone())
+                        .await
+                }
+                _ => collect_pipeline_warnings(graph.run(true)).await,
+            };
+            plan_warnings.extend(warnings);
+            worker_output(result, plan_warnings)?
+        }
     };
 
     let accept = headers
@@ -82,19 +417,14 @@ async fn handle_query(
     };
 
     if make_html {
-        let sym_info_str = match &result {
-            PipelineValues::GraphResultsBundle(grb) => {
-                serde_json::to_string(&grb.symbols).unwrap_or_else(|_| "{}".to_string())
-            }
-            PipelineValues::SymbolTreeTableList(sttl) => {
-                serde_json::to_string(&sttl.unioned_node_sets_as_jumprefs())
-                    .unwrap_or_else(|_| "{}".to_string())
-            }
-            _ => "{}".to_string(),
+        let sym_info_str = match &output.sym_info {
+            Some(sym_info) => serde_json::to_string(&sym_info).unwrap_or_else(|_| "{}".to_string()),
+            None => "{}".to_string(),
         };
 
         let globals = liquid::object!({
-            "results": result,
+            "results": output.results,
+            "warnings": output.warnings,
             "query": query.clone(),
             "preset": preset.clone(),
             "tree": tree.clone(),
@@ -105,10 +435,283 @@ async fn handle_query(
         let output = templates.query_results.render(&globals)?;
         Ok(Html(output).into_response())
     } else {
-        Ok(Json(result).into_response())
+        Ok(Json(json!({
+            "results": output.results,
+            "warnings": output.warnings,
+        }))
+        .into_response())
+    }
+}
+
+/// How long to wait after the most recent query string before we actually
+/// start running a query in a live query session.
+const LIVE_QUERY_DEBOUNCE: Duration = Duration::from_millis(150);
+
+/// Messages sent from the server to the client in a live query session.  Every
+/// message carries the `generation` of the query it corresponds to; the client
+/// sends a new query string whenever the user types and should ignore any
+/// messages for generations older than the most recent one it has seen.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+enum LiveQueryMessage {
+    /// We've started running the query for this generation.
+    Pending { generation: u64, query: String },
+    /// An intermediate named value from the pipeline graph, which the client
+    /// may display until it is replaced by a better result.
+    Partial {
+        generation: u64,
+        name: String,
+        value: Value,
+    },
+    /// The final results for this generation, replacing any partials.
+    Results {
+        generation: u64,
+        results: PipelineValues,
+        warnings: Vec<PipelineWarning>,
+    },
+    Error {
+        generation: u64,
+        message: String,
+        /// Present when the query's pipeline couldn't be parsed.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        diagnostic: Option<PipelineDiagnostic>,
+    },
+}
+
+async fn send_live_message(socket: &mut WebSocket, msg: &LiveQueryMessage) -> bool {
+    match serde_json::to_string(msg) {
+        Ok(text) => socket.send(Message::Text(text)).await.is_ok(),
+        // Failing to serialize is bad but not a reason to drop the session.
+        Err(_) => true,
     }
 }
 
+/// Start planning and running the given query in a new task, returning the
+/// task handle and the receiver for any partial results.
+fn start_live_query(
+    server: &(dyn AbstractServer + Send + Sync),
+    query_config: Arc<QueryConfig>,
+    admission: Arc<AdmissionControl>,
+    query: &str,
+) -> (
+    JoinHandle<(ServerResult<PipelineValues>, Vec<PipelineWarning>)>,
+    UnboundedReceiver<(String, Value)>,
+) {
+    let server = server.clonify();
+    let query = query.to_string();
+    let (tx, rx) = unbounded_channel();
+    let handle = tokio::spawn(collect_pipeline_warnings(async move {
+        let (graph, cost) = plan_and_classify(server.as_ref(), &query, &query_config).await?;
+        // Aborting a superseded query drops its admission along with it.
+        let _admission = admission.admit(cost).await.ok_or_else(|| {
+            ServerError::TransientProblem(ErrorDetails {
+                layer: ErrorLayer::ServerLayer,
+                message: EXPENSIVE_REJECTED_MESSAGE.to_string(),
+            })
+        })?;
+        graph.run_with_partials(false, Some(tx)).await
+    }));
+    (handle, rx)
+}
+
+/// Drive a live query session.  The client sends query strings as text
+/// messages as the user types.  We debounce these, and whenever a new query
+/// arrives we abort any query that is still running for a previous generation
+/// because its results are no longer interesting.
+///
+/// We look up the tree's query config for each query so that long-lived
+/// sessions pick up config reloads.
+async fn live_query_session(
+    mut socket: WebSocket,
+    server: Box<dyn AbstractServer + Send + Sync>,
+    query_configs: Arc<TreeQueryConfigs>,
+    admission: Arc<AdmissionControl>,
+    tree: String,
+) {
+    let mut generation: u64 = 0;
+    let mut pending_query: Option<String> = None;
+    let mut debounce_deadline = Instant::now();
+    let mut running: Option<JoinHandle<(ServerResult<PipelineValues>, Vec<PipelineWarning>)>> =
+        None;
+    let mut partials: Option<UnboundedReceiver<(String, Value)>> = None;
+
+    loop {
+        tokio::select! {
+            msg = socket.recv() => {
+                match msg {
+                    Some(Ok(Message::Text(query))) => {
+                        generation += 1;
+                        if let Some(handle) = running.take() {
+                            handle.abort();
+                        }
+                        partials = None;
+                        pending_query = Some(query);
+                        debounce_deadline = Instant::now() + LIVE_QUERY_DEBOUNCE;
+                    }
+                    // Pings are automatically responded to by axum.
+                    Some(Ok(Message::Binary(_) | Message::Ping(_) | Message::Pong(_))) => {}
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                }
+            }
+            _ = sleep_until(debounce_deadline), if pending_query.is_some() => {
+                let query = pending_query.take().unwrap();
+                let query_config = match query_configs.get(&tree) {
+                    Some(config) => config,
+                    None => break,
+                };
+                let (handle, rx) =
+                    start_live_query(server.as_ref(), query_config, admission.clone(), &query);
+                running = Some(handle);
+                partials = Some(rx);
+                let started = LiveQueryMessage::Pending { generation, query };
+                if !send_live_message(&mut socket, &started).await {
+                    break;
+                }
+            }
+            Some((name, value)) = async { partials.as_mut().unwrap().recv().await }, if partials.is_some() => {
+                let msg = LiveQueryMessage::Partial { generation, name, value };
+                if !send_live_message(&mut socket, &msg).await {
+                    break;
+                }
+            }
+            joined = async { running.as_mut().unwrap().await }, if running.is_some() => {
+                running = None;
+                // `select!` doesn't prefer the partials branch, so flush the
+                // partials the query sent before it finished to keep them from
+                // arriving after its results.
+                let mut flushed = true;
+                if let Some(mut rx) = partials.take() {
+                    while let Ok((name, value)) = rx.try_recv() {
+                        let msg = LiveQueryMessage::Partial { generation, name, value };
+                        if !send_live_message(&mut socket, &msg).await {
+                            flushed = false;
+                            break;
+                        }
+                    }
+                }
+                if !flushed {
+                    break;
+                }
+                let msg = match joined {
+                    Ok((Ok(results), warnings)) => LiveQueryMessage::Results {
+                        generation,
+                        results,
+                        warnings,
+                    },
+                    Ok((Err(ServerError::BadPipeline(diagnostic)), _)) => LiveQueryMessage::Error {
+                        generation,
+                        message: diagnostic.message.clone(),
+                        diagnostic: Some(diagnostic),
+                    },
+                    Ok((Err(err), _)) => LiveQueryMessage::Error {
+                        generation,
+                        message: format!("{:?}", err),
+                        diagnostic: None,
+                    },
+                    Err(err) => LiveQueryMessage::Error {
+                        generation,
+                        message: format!("{:?}", ServerError::from(err)),
+                        diagnostic: None,
+                    },
+                };
+                if !send_live_message(&mut socket, &msg).await {
+                    break;
+                }
+            }
+        }
+    }
+
+    if let Some(handle) = running {
+        handle.abort();
+    }
+}
+
+/// router.py's "search" endpoint, for migrating off of router.py.  This takes
+/// the same query string and produces the same JSON; see
+/// `query::legacy_router`.
+async fn handle_legacy_search(
+    local_servers: Extension<Arc<BTreeMap<String, Box<dyn AbstractServer + Send + Sync>>>>,
+    worker_pool: Extension<Option<Arc<WorkerPool>>>,
+    admission: Extension<Arc<AdmissionControl>>,
+    Path(tree): Path<String>,
+    RawQuery(query): RawQuery,
+) -> Result<Response, ServerError> {
+    let server = match local_servers.get(&tree) {
+        Some(server) => server,
+        None => {
+            return Ok((StatusCode::NOT_FOUND, format!("No such tree: {}", tree)).into_response());
+        }
+    };
+    let query = query.unwrap_or_default();
+
+    let json = match worker_pool.0.as_ref() {
+        Some(pool) => {
+            let job = WorkerJob {
+                tree: tree.clone(),
+                query,
+                kind: WorkerJobKind::LegacySearch,
+            };
+            let output = match submit_job(pool, &job, &admission).await? {
+                Ok(output) => output,
+                Err(response) => return Ok(response),
+            };
+            match output.results {
+                Value::String(json) => json,
+                _ => {
+                    return Err(ServerError::StickyProblem(ErrorDetails {
+                        layer: ErrorLayer::RuntimeInvariantViolation,
+                        message: "legacy search worker didn't produce JSON".to_string(),
+                    }));
+                }
+            }
+        }
+        None => {
+            let params = LegacySearchParams::from_query_string(&query);
+            let cost = estimate_cost(&legacy_search_estimate(&params));
+            let _admission = match admission.admit(cost).await {
+                Some(admission) => admission,
+                None => return Ok(expensive_rejected_response()),
+            };
+            legacy_search_json(server.as_ref(), &params).await?
+        }
+    };
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/json;charset=utf-8"),
+            (header::VARY, "Accept"),
+        ],
+        json,
+    )
+        .into_response())
+}
+
+async fn handle_live_query(
+    ws: WebSocketUpgrade,
+    local_servers: Extension<Arc<BTreeMap<String, Box<dyn AbstractServer + Send + Sync>>>>,
+    query_configs: Extension<Arc<TreeQueryConfigs>>,
+    admission: Extension<Arc<AdmissionControl>>,
+    Path((tree, preset)): Path<(String, String)>,
+) -> Response {
+    let server = match local_servers.get(&tree) {
+        Some(s) => s.clonify(),
+        None => {
+            return (StatusCode::NOT_FOUND, format!("No such tree: {}", tree)).into_response();
+        }
+    };
+
+    if preset.as_str() != "default" {
+        return (StatusCode::NOT_FOUND, format!("No such preset: {}", preset)).into_response();
+    }
+
+    let query_configs = query_configs.0.clone();
+    let admission = admission.0.clone();
+    ws.on_upgrade(move |socket| live_query_session(socket, server, query_configs, admission, tree))
+}
+
+/// How often we check the per-tree query config files for changes.  Only
+/// `query.toml` is watched; other tree config changes require a restart.
+const QUERY_CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
 struct SomeTemplates {
     query_results: Template,
 }
@@ -117,7 +720,74 @@ struct SomeTemplates {
 async fn main() {
     init_logging();
 
-    let local_servers = Arc::new(make_all_local_servers(&env::args().nth(1).unwrap()).unwrap());
+    let cli = PipelineServerCli::parse();
+    let default_output_size_guard = OutputSizeGuardConfig {
+        max_stage_output_bytes: cli.max_stage_output_bytes,
+        on_exceeded: SizeGuardAction::Abort,
+    };
+    let local_servers =
+        Arc::new(make_all_local_servers(&cli.config_file, &default_output_size_guard).unwrap());
+
+    let query_configs = {
+        let config = load(&cli.config_file, false, None, None);
+        Arc::new(TreeQueryConfigs::new(
+            &config.config_repo_path,
+            config.trees.keys(),
+        ))
+    };
+    query_configs
+        .clone()
+        .spawn_watcher(QUERY_CONFIG_POLL_INTERVAL);
+
+    if let Some(socket_path) = &cli.worker_socket {
+        let handler = QueryJobHandler {
+            local_servers,
+            query_configs,
+        };
+        run_worker(handler, socket_path, &cli.warm_query).await;
+        return;
+    }
+
+    // Live queries always run in-process since their sessions are long-lived
+    // and abort superseded queries, which workers can't do.
+    let worker_pool = if cli.workers > 0 {
+        let socket_dir = match &cli.socket_dir {
+            Some(socket_dir) => {
+                std::fs::create_dir_all(socket_dir).unwrap();
+                socket_dir.clone()
+            }
+            None => create_private_socket_dir().unwrap(),
+        };
+        let mut worker_args = vec![cli.config_file.clone()];
+        for query in &cli.warm_query {
+            worker_args.push("--warm-query".to_string());
+            worker_args.push(query.clone());
+        }
+        worker_args.push(format!(
+            "--max-stage-output-bytes={}",
+            cli.max_stage_output_bytes
+        ));
+        worker_args.push("--worker-socket".to_string());
+        spawn_worker_processes(
+            env::current_exe().unwrap(),
+            worker_args,
+            &socket_dir,
+            cli.workers,
+        );
+        let pool = WorkerPool::new(&socket_dir, cli.workers, cli.max_queued);
+        pool.wait_until_ready().await;
+        Some(Arc::new(pool))
+    } else {
+        None
+    };
+
+    // Admission is decided here, with workers asking for it over their
+    // sockets, so that the limits apply across all of them.
+    let admission = Arc::new(AdmissionControl::new(
+        cli.max_expensive,
+        cli.max_expensive_queued,
+    ));
+
     let templates = Arc::new(SomeTemplates {
         query_results: build_and_parse_query_results(),
     });
@@ -125,8 +795,13 @@ async fn main() {
     // build our application with a single route
     let app = Router::new()
         .route("/:tree/query/:preset", get(handle_query))
+        .route("/:tree/query-live/:preset", get(handle_live_query))
+        .route("/:tree/search", get(handle_legacy_search))
         .layer(Extension(local_servers))
-        .layer(Extension(templates));
+        .layer(Extension(query_configs))
+        .layer(Extension(templates))
+        .layer(Extension(worker_pool))
+        .layer(Extension(admission));
 
     axum::Server::bind(&"0.0.0.0:8002".parse().unwrap())
         .serve(app.into_make_service())
@@ -428,6 +428,7 @@ fn visit_common(
                 end_lineno: 0,
             },
             arg_ranges: vec![],
+            origin: None,
         },
         loc,
     };
@@ -463,6 +464,7 @@ fn visit_common(
             type_sym: None,
             arg_ranges: vec![],
             expansion_info: None,
+            origin: None,
         },
         loc,
     };
@@ -997,6 +999,7 @@ fn analyze_using_scip(tree_info: &TreeInfo, scip_prefix: Option<&PathBuf>, scip_
                         type_sym: None,
                         arg_ranges: vec![],
                         expansion_info: None,
+                        origin: None,
                     },
                     loc,
                 };
@@ -1022,6 +1025,7 @@ fn analyze_using_scip(tree_info: &TreeInfo, scip_prefix: Option<&PathBuf>, scip_
                             end_lineno: 0,
                         },
                         arg_ranges: vec![],
+                        origin: None,
                     },
                     loc,
                 }
@@ -929,18 +929,24 @@ fn analyze_using_scip(
                         None
                     },
                     own_vf_ptr_bytes: None,
+                    align_bytes: None,
+                    max_field_align_bytes: None,
+                    underlying_type: None,
                     binding_slots: vec![],
                     ontology_slots: vec![],
                     supers,
                     methods: vec![],
                     fields: vec![],
+                    enumerators: vec![],
                     overrides,
+                    specialization_of: vec![],
                     props: vec![],
                     labels: BTreeSet::default(),
 
                     idl_sym: None,
                     subclass_syms: vec![],
                     overridden_by_syms: vec![],
+                    specialization_syms: vec![],
                     variants: vec![],
                     extra: Map::default(),
                 };
@@ -987,6 +993,7 @@ fn analyze_using_scip(
                                             } else {
                                                 None
                                             },
+                                            underlying_type: None,
                                             labels: BTreeSet::default(),
                                             pointer_info: vec![],
                                         });
@@ -1171,18 +1178,24 @@ fn analyze_using_scip(
                         impl_kind: ustr("external"),
                         size_bytes: None,
                         own_vf_ptr_bytes: None,
+                        align_bytes: None,
+                        max_field_align_bytes: None,
+                        underlying_type: None,
                         binding_slots: vec![],
                         ontology_slots: vec![],
                         supers: vec![],
                         methods: vec![],
                         fields: vec![],
+                        enumerators: vec![],
                         overrides: vec![],
+                        specialization_of: vec![],
                         props: vec![],
                         labels: BTreeSet::default(),
 
                         idl_sym: None,
                         subclass_syms: vec![],
                         overridden_by_syms: vec![],
+                        specialization_syms: vec![],
                         variants: vec![],
                         extra: Map::default(),
                     };
@@ -1310,6 +1323,7 @@ fn analyze_using_scip(
                         type_sym: None,
                         arg_ranges: vec![],
                         expansion_info: None,
+                        origin: None,
                     },
                     loc,
                 };
@@ -1344,6 +1358,7 @@ fn analyze_using_scip(
                             end_lineno: 0,
                         },
                         arg_ranges: vec![],
+                        origin: None,
                     },
                     loc,
                 };
@@ -1373,18 +1388,24 @@ fn analyze_using_scip(
             impl_kind: ustr("external"),
             size_bytes: None,
             own_vf_ptr_bytes: None,
+            align_bytes: None,
+            max_field_align_bytes: None,
+            underlying_type: None,
             binding_slots: vec![],
             ontology_slots: vec![],
             supers: vec![],
             methods: vec![],
             fields: vec![],
+            enumerators: vec![],
             overrides: vec![],
+            specialization_of: vec![],
             props: vec![],
             labels: BTreeSet::default(),
 
             idl_sym: None,
             subclass_syms: vec![],
             overridden_by_syms: vec![],
+            specialization_syms: vec![],
             variants: vec![],
             extra: Map::default(),
         };
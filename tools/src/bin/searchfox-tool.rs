@@ -1,17 +1,61 @@
 use std::env::args_os;
+use std::io::BufWriter;
 
-use serde_json::{to_string_pretty, to_value, Value};
+use clap::Parser;
+use serde_json::{json, to_string_pretty, to_value, Value};
 use tools::{
-    abstract_server::{ErrorDetails, ErrorLayer, ServerError},
-    cmd_pipeline::{builder::build_pipeline, parser::OutputFormat, PipelineValues},
+    abstract_server::{ErrorDetails, ErrorLayer, Result, ServerError},
+    cmd_pipeline::{
+        builder::build_pipeline,
+        describe::describe_commands,
+        history::{count_results, history_path, substitute_params, QueryHistory},
+        ndjson_output::{write_ndjson_chunked, NDJSON_SYNC_INTERVAL},
+        parser::OutputFormat,
+        trace_output::{trace_output_enabled, write_trace_output, ChromeTraceLayer},
+        warnings::collect_pipeline_warnings,
+        PipelineValues,
+    },
 };
-use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+use tracing_subscriber::{filter::filter_fn, fmt, prelude::*, EnvFilter};
+
+/// `rerun` re-executes a pipeline from the history, optionally replacing the
+/// values of some of its named arguments.  For example, `rerun 12 --set
+/// max-depth=4` re-runs history entry 12 with `--max-depth=4`.
+#[derive(Debug, Parser)]
+struct RerunOpts {
+    /// The history entry id, as listed by `history`.
+    #[clap(value_parser)]
+    id: u64,
+
+    /// Replace the value of a named argument, as "name=value".  Can be
+    /// specified multiple times.
+    #[clap(long, value_parser)]
+    set: Vec<String>,
+}
+
+fn pipeline_for_rerun(bin_name: &str, arg_str: &str, history: &QueryHistory) -> Result<String> {
+    let mut fake_args = vec![bin_name.to_string()];
+    fake_args.extend(
+        shell_words::split(arg_str)
+            .unwrap_or_default()
+            .into_iter()
+            .skip(1),
+    );
+    let opts = RerunOpts::try_parse_from(fake_args).map_err(|err| {
+        ServerError::StickyProblem(ErrorDetails {
+            layer: ErrorLayer::BadInput,
+            message: err.to_string(),
+        })
+    })?;
+    let entry = history.get(opts.id)?;
+    substitute_params(&entry.pipeline, &opts.set)
+}
 
 #[tokio::main]
 async fn main() {
     tracing_subscriber::registry()
-        .with(fmt::layer())
-        .with(EnvFilter::from_default_env())
+        .with(fmt::layer().with_filter(EnvFilter::from_default_env()))
+        .with(ChromeTraceLayer.with_filter(filter_fn(|_| trace_output_enabled())))
         .init();
 
     let mut os_args: Vec<String> = args_os()
@@ -42,8 +86,80 @@ async fn main() {
         std::process::exit(2);
     }
 
-    let (pipeline, output_format) = match build_pipeline(&os_args[0], &os_args[1]) {
+    // ## Command Descriptions
+    //
+    // Tooling like the web query builder can use this to learn the arguments
+    // of every command without parsing the help text.
+    if os_args[1].trim() == "--describe-commands" {
+        println!("{}", to_string_pretty(&describe_commands()).unwrap());
+        std::process::exit(0);
+    }
+
+    // ## History
+    //
+    // `history` and `rerun` are handled here rather than as pipeline commands
+    // because they operate on whole pipelines.
+    let first_word = os_args[1].split_whitespace().next().unwrap_or("");
+    let wants_history = first_word == "history" || first_word == "rerun";
+    let mut history_file = history_path();
+    if wants_history && history_file.is_none() {
+        println!(
+            "{}: history is disabled; set SEARCHFOX_TOOL_HISTORY to enable it",
+            first_word
+        );
+        std::process::exit(1);
+    }
+    let mut history = match history_file.as_ref().map(QueryHistory::load) {
+        Some(Ok(history)) => history,
+        Some(Err(ServerError::StickyProblem(ErrorDetails { message, .. }))) if wants_history => {
+            println!("{}", message);
+            std::process::exit(1);
+        }
+        Some(Err(err)) => {
+            // Don't let broken history break the tool, but don't overwrite it
+            // either.
+            let message = match err {
+                ServerError::StickyProblem(ErrorDetails { message, .. }) => message,
+                err => format!("{:?}", err),
+            };
+            eprintln!("Not recording history: {}", message);
+            history_file = None;
+            QueryHistory::default()
+        }
+        None => QueryHistory::default(),
+    };
+
+    if first_word == "history" {
+        for entry in &history.entries {
+            println!("{}", to_value(entry).unwrap());
+        }
+        std::process::exit(0);
+    }
+    let pipeline_str = if first_word == "rerun" {
+        match pipeline_for_rerun(&os_args[0], &os_args[1], &history) {
+            Ok(pipeline_str) => {
+                eprintln!("Rerunning: {}", pipeline_str);
+                pipeline_str
+            }
+            Err(ServerError::StickyProblem(ErrorDetails { message, .. })) => {
+                println!("{}", message);
+                std::process::exit(1);
+            }
+            Err(err) => {
+                println!("{:?}", err);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        os_args[1].clone()
+    };
+
+    let (pipeline, output_format) = match build_pipeline(&os_args[0], &pipeline_str) {
         Ok(pipeline) => pipeline,
+        Err(ServerError::BadPipeline(diagnostic)) => {
+            println!("{}", diagnostic.render(&pipeline_str));
+            std::process::exit(1);
+        }
         Err(ServerError::StickyProblem(ErrorDetails {
             layer: ErrorLayer::BadInput,
             message,
@@ -56,10 +172,29 @@ async fn main() {
         }
     };
 
-    let results = pipeline.run(false).await;
+    let (results, warnings) = collect_pipeline_warnings(pipeline.run(false)).await;
+
+    // Warnings go to stderr so they don't interfere with consumers of the
+    // results on stdout.
+    for warning in &warnings {
+        eprintln!("Warning: {}: {}", warning.command, warning.message);
+    }
+
+    match write_trace_output() {
+        Ok(Some(path)) => eprintln!("Wrote trace to {}", path),
+        Ok(None) => {}
+        Err(err) => eprintln!("Unable to write trace: {}", err),
+    }
+
+    if let Some(path) = &history_file {
+        history.record(&pipeline_str, results.as_ref().ok().map(count_results));
+        if let Err(err) = history.save(path) {
+            eprintln!("Unable to save history to {}: {}", path.display(), err);
+        }
+    }
 
     let emit_json = |val: &Value| {
-        if output_format == OutputFormat::Concise {
+        if output_format == OutputFormat::Concise || output_format == OutputFormat::NdjsonChunked {
             println!("{}", val);
         } else if output_format == OutputFormat::Pretty {
             if let Ok(pretty) = to_string_pretty(val) {
@@ -85,7 +220,7 @@ async fn main() {
         }
         Ok(PipelineValues::SymbolCrossrefInfoList(sl)) => {
             for symbol_info in sl.symbol_crossref_infos {
-                emit_json(&symbol_info.crossref_info);
+                emit_json(&symbol_info.crossref_info.into_value());
             }
             0
         }
@@ -114,6 +249,17 @@ async fn main() {
             println!("{}", fb.contents);
             0
         }
+        Ok(PipelineValues::JsonRecords(jr)) if output_format == OutputFormat::NdjsonChunked => {
+            let stdout = std::io::stdout();
+            let mut writer = BufWriter::new(stdout.lock());
+            match write_ndjson_chunked(&mut writer, &jr, NDJSON_SYNC_INTERVAL) {
+                Ok(()) => 0,
+                Err(err) => {
+                    eprintln!("Unable to write output: {}", err);
+                    1
+                }
+            }
+        }
         Ok(PipelineValues::JsonRecords(jr)) => {
             for file_records in jr.by_file {
                 for value in file_records.records {
@@ -146,6 +292,20 @@ async fn main() {
             emit_json(&to_value(sttl).unwrap());
             0
         }
+        Ok(PipelineValues::InterleavedResults(ir)) => {
+            emit_json(&to_value(ir).unwrap());
+            0
+        }
+        Ok(PipelineValues::MentionMatches(mm)) => {
+            emit_json(&to_value(mm).unwrap());
+            0
+        }
+        // `--strict` is for CI, so its failures get a machine-readable reason
+        // and their own exit code to distinguish them from broken pipelines.
+        Err(ServerError::StrictFailure(warning)) => {
+            println!("{}", json!({ "strictFailure": warning }));
+            3
+        }
         Err(err) => {
             println!("Pipeline Error!");
             println!("{:?}", err);
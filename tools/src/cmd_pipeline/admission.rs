@@ -0,0 +1,289 @@
+//! Admission control for the pipeline-server.  Most queries are cheap, but
+//! traversals can walk an unbounded part of the crossref graph and a handful of
+//! them running at once can starve everything else of the shared crossref and
+//! codesearch resources.  So we classify each query's planned pipeline graph
+//! by the estimates of its stages before running it and only let a limited
+//! number of expensive plans run at once.  Further expensive plans wait their
+//! turn, and once `max_queued` of them are already waiting we reject them,
+//! which the pipeline-server surfaces as a 503 with a Retry-After header.
+//! Cheap plans are never held up.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use async_trait::async_trait;
+use serde::Serialize;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use super::interface::{PipelineCommand, PipelineValues, ServerPipelineGraph, StageEstimate};
+use crate::abstract_server::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError};
+
+/// Stages estimated to produce at least this many results are expensive even
+/// if they don't expect to run into any of their limits.
+const EXPENSIVE_RESULTS: u64 = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PlanCost {
+    Cheap,
+    Expensive,
+}
+
+/// Classify a single stage estimate.
+pub fn estimate_cost(estimate: &StageEstimate) -> PlanCost {
+    if !estimate.limits_hit.is_empty()
+        || estimate.expected_results.unwrap_or(0) >= EXPENSIVE_RESULTS
+    {
+        PlanCost::Expensive
+    } else {
+        PlanCost::Cheap
+    }
+}
+
+/// Stands in for the commands at the start of a pipeline that
+/// `classify_graph` already ran, producing their output when the graph runs.
+struct PrecomputedCommand {
+    output: Mutex<Option<PipelineValues>>,
+}
+
+impl fmt::Debug for PrecomputedCommand {
+    // The output can be huge and isn't interesting in traces.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PrecomputedCommand {{ args: {:p} }}", self)
+    }
+}
+
+#[async_trait]
+impl PipelineCommand for PrecomputedCommand {
+    async fn execute(
+        &self,
+        _server: &(dyn AbstractServer + Send + Sync),
+        _input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        self.output.lock().unwrap().take().ok_or_else(|| {
+            ServerError::StickyProblem(ErrorDetails {
+                layer: ErrorLayer::RuntimeInvariantViolation,
+                message: "precomputed output was already used".to_string(),
+            })
+        })
+    }
+}
+
+/// Classify the graph by its most expensive stage estimate.  Each pipeline is
+/// walked the way `ServerPipeline::run_estimate` walks it: cheap commands run
+/// to produce the input of the commands after them, and the first command that
+/// can estimate its work reports the estimate.  Pipelines whose input isn't
+/// available this way, because it comes from a junction or from past a command
+/// that isn't cheap, can't be estimated and don't count against the plan.
+///
+/// The commands that ran are replaced in the graph by their output, so running
+/// the graph afterwards doesn't do their work again.
+pub async fn classify_graph(graph: &mut ServerPipelineGraph) -> Result<PlanCost> {
+    let server = graph.server.as_ref();
+    let mut outputs: HashMap<String, PipelineValues> = HashMap::new();
+    for phase in &mut graph.pipelines {
+        for pipeline in &mut phase.pipelines {
+            let mut values = match &pipeline.input_name {
+                None => PipelineValues::Void,
+                Some(name) => match outputs.get(name).and_then(|v| v.try_clone()) {
+                    Some(values) => values,
+                    None => continue,
+                },
+            };
+            let mut cost = PlanCost::Cheap;
+            let mut executed = 0;
+            for cmd in &pipeline.commands {
+                if let Some(estimate) = cmd.estimate(server, &values).await? {
+                    cost = estimate_cost(&estimate);
+                    break;
+                }
+                if !cmd.is_cheap() {
+                    break;
+                }
+                values = cmd.execute(server, values).await?;
+                executed += 1;
+            }
+            if executed == pipeline.commands.len() {
+                if let Some(output) = values.try_clone() {
+                    outputs.insert(pipeline.output_name.clone(), output);
+                }
+            }
+            if executed > 0 {
+                let precomputed: Box<dyn PipelineCommand + Send + Sync> =
+                    Box::new(PrecomputedCommand {
+                        output: Mutex::new(Some(values)),
+                    });
+                pipeline
+                    .commands
+                    .splice(0..executed, std::iter::once(precomputed));
+            }
+            if cost == PlanCost::Expensive {
+                return Ok(PlanCost::Expensive);
+            }
+        }
+    }
+    Ok(PlanCost::Cheap)
+}
+
+/// Limits how many expensive plans run at once.
+pub struct AdmissionControl {
+    /// One permit per expensive plan allowed to run at once, or None if
+    /// expensive plans aren't limited.
+    permits: Option<Semaphore>,
+    /// Expensive plans that have been admitted or are waiting to be.
+    outstanding: AtomicUsize,
+    max_running: usize,
+    max_queued: usize,
+}
+
+/// An admitted plan's claim on its `AdmissionControl`, which must be held
+/// while the plan runs.
+pub struct Admission<'a> {
+    control: &'a AdmissionControl,
+    expensive: bool,
+    _permit: Option<SemaphorePermit<'a>>,
+}
+
+impl<'a> Drop for Admission<'a> {
+    fn drop(&mut self) {
+        if self.expensive {
+            self.control.outstanding.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+impl AdmissionControl {
+    /// Allow `max_running` expensive plans to run at once with up to
+    /// `max_queued` more waiting.  A `max_running` of 0 disables admission
+    /// control.
+    pub fn new(max_running: usize, max_queued: usize) -> Self {
+        AdmissionControl {
+            permits: if max_running > 0 {
+                Some(Semaphore::new(max_running))
+            } else {
+                None
+            },
+            outstanding: AtomicUsize::new(0),
+            max_running,
+            max_queued,
+        }
+    }
+
+    /// Wait until a plan of the given cost may run, returning None without
+    /// waiting if too many expensive plans are already waiting.
+    pub async fn admit(&self, cost: PlanCost) -> Option<Admission<'_>> {
+        let permits = match (&self.permits, cost) {
+            (Some(permits), PlanCost::Expensive) => permits,
+            _ => {
+                return Some(Admission {
+                    control: self,
+                    expensive: false,
+                    _permit: None,
+                })
+            }
+        };
+
+        let outstanding = self.outstanding.fetch_add(1, Ordering::SeqCst);
+        let mut admission = Admission {
+            control: self,
+            expensive: true,
+            _permit: None,
+        };
+        if outstanding >= self.max_running + self.max_queued {
+            return None;
+        }
+        // The semaphore is never closed.
+        admission._permit = Some(permits.acquire().await.unwrap());
+        Some(admission)
+    }
+}
+
+#[test]
+fn test_classify_graph() {
+    use super::{
+        builder::build_pipeline,
+        interface::{NamedPipeline, ParallelPipelines},
+    };
+
+    let estimate = |expected_results: u64, limits_hit: &[&str]| StageEstimate {
+        expected_results: Some(expected_results),
+        limits_hit: limits_hit.iter().map(|s| s.to_string()).collect(),
+        ..StageEstimate::default()
+    };
+    assert_eq!(estimate_cost(&estimate(10, &[])), PlanCost::Cheap);
+    assert_eq!(
+        estimate_cost(&estimate(EXPENSIVE_RESULTS, &[])),
+        PlanCost::Expensive
+    );
+    assert_eq!(
+        estimate_cost(&estimate(10, &["node limit"])),
+        PlanCost::Expensive
+    );
+
+    // A full-text search can't estimate its work and isn't cheap, so nothing
+    // after it counts against the plan and the server is never consulted.
+    let (pipeline, _) = build_pipeline(
+        "searchfox-tool",
+        "--server=https://searchfox.org/ search-text foo | crossref-expand",
+    )
+    .unwrap();
+    let mut graph = ServerPipelineGraph {
+        server: pipeline.server,
+        pipelines: vec![ParallelPipelines {
+            pipelines: vec![NamedPipeline {
+                input_name: None,
+                output_name: "text".to_string(),
+                commands: pipeline.commands,
+            }],
+            junctions: vec![],
+        }],
+        plan: None,
+    };
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    assert_eq!(
+        runtime.block_on(classify_graph(&mut graph)).unwrap(),
+        PlanCost::Cheap
+    );
+    // Nothing ran, so there's nothing to replace.
+    assert_eq!(graph.pipelines[0].pipelines[0].commands.len(), 2);
+}
+
+#[test]
+fn test_admission_control() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let control = AdmissionControl::new(1, 1);
+        let running = control.admit(PlanCost::Expensive).await;
+        assert!(running.is_some());
+
+        // With one expensive plan running, the next one waits and the one
+        // after that is rejected, but cheap plans still get in.
+        let (queued, rejected, cheap) = tokio::join!(
+            control.admit(PlanCost::Expensive),
+            async {
+                let rejected = control.admit(PlanCost::Expensive).await.is_none();
+                drop(running);
+                rejected
+            },
+            control.admit(PlanCost::Cheap),
+        );
+        assert!(queued.is_some());
+        assert!(rejected);
+        assert!(cheap.is_some());
+        drop(queued);
+
+        assert!(AdmissionControl::new(0, 0)
+            .admit(PlanCost::Expensive)
+            .await
+            .is_some());
+    });
+}
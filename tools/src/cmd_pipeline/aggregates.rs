@@ -0,0 +1,184 @@
+//! Per-symbol aggregates (caller counts, use counts, subclass counts, and top
+//! uses) that `precompute-aggregates` stores at index time and that query-time
+//! commands consult before falling back to computing them live from crossref
+//! records.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use serde_json::to_string;
+
+use ustr::Ustr;
+
+use crate::{
+    abstract_server::{AbstractServer, PathUseCount, Result, ServerError, SymbolAggregates},
+    file_format::crossref_record::CrossrefRecord,
+};
+
+/// Compute the aggregates that only need the symbol's own crossref record,
+/// which is everything but the subclass count.  `top_uses` is how many files
+/// to list in `top_uses`, where 0 skips it.
+pub fn direct_aggregates(record: &CrossrefRecord, top_uses: usize) -> SymbolAggregates {
+    let callers: HashSet<Ustr> = record
+        .uses
+        .iter()
+        .flat_map(|path_hits| path_hits.lines.iter())
+        .map(|hit| hit.contextsym)
+        .filter(|contextsym| !contextsym.is_empty())
+        .collect();
+
+    let mut by_path: Vec<PathUseCount> = record
+        .uses
+        .iter()
+        .map(|path_hits| PathUseCount {
+            path: path_hits.path,
+            uses: path_hits.lines.len(),
+        })
+        .collect();
+    let use_count = by_path.iter().map(|path_uses| path_uses.uses).sum();
+    by_path.sort_by(|a, b| b.uses.cmp(&a.uses).then_with(|| a.path.cmp(&b.path)));
+    by_path.truncate(top_uses);
+
+    SymbolAggregates {
+        caller_count: Some(callers.len()),
+        use_count: Some(use_count),
+        subclass_count: None,
+        top_uses: if top_uses > 0 { Some(by_path) } else { None },
+    }
+}
+
+/// Count the direct and indirect subclasses of every class in the map of
+/// direct subclasses, counting classes reachable by multiple paths once.
+pub fn transitive_subclass_counts(subclasses: &HashMap<Ustr, Vec<Ustr>>) -> HashMap<Ustr, usize> {
+    let mut counts = HashMap::new();
+    for &root in subclasses.keys() {
+        let mut seen: HashSet<Ustr> = HashSet::new();
+        let mut pending = vec![root];
+        while let Some(sym) = pending.pop() {
+            for &sub in subclasses.get(&sym).into_iter().flatten() {
+                // The root can only show up here if the data has a cycle.
+                if sub != root && seen.insert(sub) {
+                    pending.push(sub);
+                }
+            }
+        }
+        counts.insert(root, seen.len());
+    }
+    counts
+}
+
+/// Write the store as `aggregates` and `aggregates-extra` in `root` using the
+/// crossref file format, with every record stored inline.  The map keeps the
+/// symbols in the order the lookup map bisects over.
+pub fn write_aggregates_store(
+    root: &Path,
+    stored: &BTreeMap<String, SymbolAggregates>,
+) -> Result<()> {
+    fs::create_dir_all(root)?;
+    let mut inline = BufWriter::new(fs::File::create(root.join("aggregates"))?);
+    for (sym, aggregates) in stored {
+        write!(inline, "!{}\n:{}\n", sym, to_string(aggregates)?)?;
+    }
+    inline.flush()?;
+    // Nothing is stored externally, but the file can't be empty because
+    // empty files can't be mapped.
+    fs::write(root.join("aggregates-extra"), "\n")?;
+    Ok(())
+}
+
+/// The aggregates for the symbol, preferring the precomputed store and
+/// computing whatever it lacks from the symbol's crossref record.  The
+/// subclass count is only available precomputed since computing it live means
+/// traversing the class hierarchy.
+pub async fn symbol_aggregates(
+    server: &(dyn AbstractServer + Send + Sync),
+    sym: &str,
+    record: &CrossrefRecord,
+    top_uses: usize,
+) -> Result<SymbolAggregates> {
+    let precomputed = match server.precomputed_aggregates(sym).await {
+        Ok(precomputed) => precomputed.unwrap_or_default(),
+        Err(ServerError::Unsupported) => SymbolAggregates::default(),
+        Err(err) => return Err(err),
+    };
+    let needs_live = precomputed.caller_count.is_none()
+        || precomputed.use_count.is_none()
+        || (top_uses > 0 && precomputed.top_uses.is_none());
+    if !needs_live {
+        return Ok(precomputed);
+    }
+    let live = direct_aggregates(record, top_uses);
+    Ok(SymbolAggregates {
+        caller_count: precomputed.caller_count.or(live.caller_count),
+        use_count: precomputed.use_count.or(live.use_count),
+        subclass_count: precomputed.subclass_count,
+        top_uses: precomputed.top_uses.or(live.top_uses),
+    })
+}
+
+#[test]
+fn test_transitive_subclass_counts() {
+    use ustr::ustr;
+
+    // Base <- A <- C, Base <- B <- C (diamond), C <- D
+    let subclasses: HashMap<Ustr, Vec<Ustr>> = [
+        ("Base", vec!["A", "B"]),
+        ("A", vec!["C"]),
+        ("B", vec!["C"]),
+        ("C", vec!["D"]),
+    ]
+    .iter()
+    .map(|(sym, subs)| (ustr(sym), subs.iter().copied().map(ustr).collect()))
+    .collect();
+    let counts = transitive_subclass_counts(&subclasses);
+    assert_eq!(counts[&ustr("Base")], 4);
+    assert_eq!(counts[&ustr("A")], 2);
+    assert_eq!(counts[&ustr("C")], 1);
+    assert_eq!(counts.get(&ustr("D")), None);
+}
+
+#[test]
+fn test_aggregates_store_round_trip() {
+    use crate::file_format::crossref_lookup::CrossrefLookupMap;
+    use ustr::ustr;
+
+    let dir = std::env::temp_dir().join(format!("aggregates-test-{}", std::process::id()));
+    let mut stored = BTreeMap::new();
+    stored.insert(
+        "_ZN7outerNS5Thing10takeDamageEi".to_string(),
+        SymbolAggregates {
+            caller_count: Some(3),
+            use_count: Some(120),
+            subclass_count: None,
+            top_uses: Some(vec![PathUseCount {
+                path: ustr("big_cpp.cpp"),
+                uses: 120,
+            }]),
+        },
+    );
+    stored.insert(
+        "T_outerNS::Thing".to_string(),
+        SymbolAggregates {
+            subclass_count: Some(12),
+            ..SymbolAggregates::default()
+        },
+    );
+    write_aggregates_store(&dir, &stored).unwrap();
+
+    let map = CrossrefLookupMap::new(
+        dir.join("aggregates").to_str().unwrap(),
+        dir.join("aggregates-extra").to_str().unwrap(),
+    )
+    .unwrap();
+    for (sym, aggregates) in &stored {
+        let read: SymbolAggregates = serde_json::from_value(map.lookup(sym).unwrap()).unwrap();
+        assert_eq!(&read, aggregates);
+    }
+    assert!(map.lookup("T_outerNS::Other").unwrap().is_null());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -3,26 +3,65 @@ use clap::Parser;
 use crate::{
     abstract_server::AbstractServer,
     cmd_pipeline::{
-        cmd_prod_filter::ProductionFilterCommand, cmd_query::QueryCommand,
-        cmd_search_text::SearchTextCommand, interface::JunctionInvocation, PipelineCommand,
+        cmd_prod_filter::ProductionFilterCommand,
+        cmd_query::QueryCommand,
+        cmd_search_text::SearchTextCommand,
+        diagnostics::{
+            diagnostic_from_clap, split_words_with_ranges, PipelineDiagnostic,
+            PipelineDiagnosticKind, PipelineWord,
+        },
+        interface::JunctionInvocation,
+        progress::ProgressFormat,
+        trace_output::set_trace_output,
+        PipelineCommand,
     },
     query::chew_query::QueryPipelineGroupBuilder,
 };
 use tracing::{trace, trace_span};
-use url::Url;
 
 use crate::{
-    abstract_server::{
-        make_local_server, make_remote_server, ErrorDetails, ErrorLayer, Result, ServerError,
-    },
+    abstract_server::{make_server_for_selector, ErrorDetails, ErrorLayer, Result, ServerError},
     cmd_pipeline::parser::{Command, OutputFormat, ToolOpts},
 };
 
 use super::{
-    cmd_augment_results::AugmentResultsCommand, cmd_batch_render::BatchRenderCommand,
-    cmd_format_symbols::FormatSymbolsCommand, cmd_fuse_crossrefs::FuseCrossrefsCommand,
-    cmd_jumpref_lookup::JumprefLookupCommand, cmd_render::RenderCommand,
-    cmd_tokenize_source::TokenizeSourceCommand, cmd_traverse::TraverseCommand,
+    cmd_annotate_coverage::AnnotateCoverageCommand,
+    cmd_annotate_repos::AnnotateReposCommand,
+    cmd_args_of_calls::ArgsOfCallsCommand,
+    cmd_augment_results::AugmentResultsCommand,
+    cmd_batch_render::{BatchRenderCommand, BatchRenderJunctionCommand},
+    cmd_blame_lines::BlameLinesCommand,
+    cmd_caller_rollup::CallerRollupCommand,
+    cmd_changed_symbols::ChangedSymbolsCommand,
+    cmd_dead_code::DeadCodeCommand,
+    cmd_define_best::DefineBestCommand,
+    cmd_defs_in_text_matches::DefsInTextMatchesCommand,
+    cmd_diff_against::DiffAgainstCommand,
+    cmd_diff_symbols::DiffSymbolsCommand,
+    cmd_dir_overview::DirOverviewCommand,
+    cmd_enum_usage::EnumUsageCommand,
+    cmd_excerpt::ExcerptCommand,
+    cmd_expand_node::ExpandNodeCommand,
+    cmd_extract_fixture::ExtractFixtureCommand,
+    cmd_fetch_file::FetchFileCommand,
+    cmd_format_symbols::FormatSymbolsCommand,
+    cmd_fuse_crossrefs::FuseCrossrefsCommand,
+    cmd_group_by_def_file::GroupByDefFileCommand,
+    cmd_index_stats::IndexStatsCommand,
+    cmd_jumpref_lookup::JumprefLookupCommand,
+    cmd_l10n_refs::L10nRefsCommand,
+    cmd_legacy_search::LegacySearchCommand,
+    cmd_redact::RedactCommand,
+    cmd_render::RenderCommand,
+    cmd_render_markdown::RenderMarkdownCommand,
+    cmd_render_quickfix::RenderQuickfixCommand,
+    cmd_rerank::RerankCommand,
+    cmd_text_diff_hits::TextDiffHitsCommand,
+    cmd_tokenize_source::TokenizeSourceCommand,
+    cmd_top_symbols::TopSymbolsCommand,
+    cmd_traverse::TraverseCommand,
+    cmd_unsafe_audit::UnsafeAuditCommand,
+    cmd_validate_analysis::ValidateAnalysisCommand,
     cmd_webtest::WebtestCommand,
 };
 use super::{
@@ -36,71 +75,205 @@ use super::{
 };
 use super::{
     cmd_crossref_lookup::CrossrefLookupCommand, cmd_filter_analysis::FilterAnalysisCommand,
-    cmd_graph::GraphCommand, cmd_merge_analyses::MergeAnalysesCommand,
-    cmd_search_identifiers::SearchIdentifiersCommand,
+    cmd_filter_crossref::FilterCrossrefCommand,
+    cmd_flatten_text_matches::FlattenTextMatchesCommand, cmd_graph::GraphCommand,
+    cmd_graph_metrics::GraphMetricsCommand, cmd_history_grep::HistoryGrepCommand,
+    cmd_interleave::InterleaveCommand, cmd_merge_analyses::MergeAnalysesCommand,
+    cmd_module_api::ModuleApiCommand, cmd_only_new_since::OnlyNewSinceCommand,
+    cmd_outline::OutlineCommand, cmd_precompute_aggregates::PrecomputeAggregatesCommand,
+    cmd_preprocessor_filter::PreprocessorFilterCommand,
+    cmd_search_identifiers::SearchIdentifiersCommand, cmd_search_mentions::SearchMentionsCommand,
+};
+use super::{
+    cmd_show_html::ShowHtmlCommand, cmd_similar_functions::SimilarFunctionsCommand,
+    cmd_slot_jump::SlotJumpCommand, cmd_suggest_identifiers::SuggestIdentifiersCommand,
+    cmd_symbol_payloads::SymbolPayloadsCommand, cmd_symbols_at_point::SymbolsAtPointCommand,
+    interface::ParallelPipelines,
 };
-use super::{cmd_show_html::ShowHtmlCommand, interface::ParallelPipelines};
 
 use super::interface::ServerPipeline;
 
+#[derive(Clone, Copy)]
 pub enum CommandSafetyLevel {
     DangerousToolUseAllowed,
     WebSafety,
 }
 
+/// Build the command for the parsed options.
+///
+/// Commands that read or write arbitrary local files or that scan the entire
+/// crossref database are only built for `DangerousToolUseAllowed`, which is
+/// what searchfox-tool uses; the web server and other services use
+/// `WebSafety` and get a `NotAllowed` error for them.
 pub fn fab_command_from_opts(
     opts: ToolOpts,
     safety: CommandSafetyLevel,
 ) -> Result<Box<dyn PipelineCommand + Send + Sync>> {
     match (opts.cmd, safety) {
+        (Command::AnnotateRepos(ar), _) => Ok(Box::new(AnnotateReposCommand { args: ar })),
+
+        (Command::ArgsOfCalls(ac), _) => Ok(Box::new(ArgsOfCallsCommand { args: ac })),
+
         (Command::AugmentResults(ar), _) => Ok(Box::new(AugmentResultsCommand { args: ar })),
 
         (Command::BatchRender(br), _) => Ok(Box::new(BatchRenderCommand { args: br })),
 
+        (Command::BlameLines(bl), _) => Ok(Box::new(BlameLinesCommand { args: bl })),
+
+        (Command::CallerRollup(cr), _) => Ok(Box::new(CallerRollupCommand { args: cr })),
+
         (Command::CatHtml(ch), _) => Ok(Box::new(CatHtmlCommand { args: ch })),
 
+        (Command::ChangedSymbols(cs), CommandSafetyLevel::DangerousToolUseAllowed) => {
+            Ok(Box::new(ChangedSymbolsCommand { args: cs }))
+        }
+
         (Command::CrossrefExpand(ce), _) => Ok(Box::new(CrossrefExpandCommand { args: ce })),
 
         (Command::CrossrefLookup(cl), _) => Ok(Box::new(CrossrefLookupCommand { args: cl })),
 
+        (Command::DeadCode(dc), CommandSafetyLevel::DangerousToolUseAllowed) => {
+            Ok(Box::new(DeadCodeCommand { args: dc }))
+        }
+
+        (Command::DefineBest(db), _) => Ok(Box::new(DefineBestCommand { args: db })),
+
+        (Command::DiffAgainst(da), CommandSafetyLevel::DangerousToolUseAllowed) => {
+            Ok(Box::new(DiffAgainstCommand { args: da }))
+        }
+
+        (Command::DiffSymbols(ds), CommandSafetyLevel::DangerousToolUseAllowed) => {
+            Ok(Box::new(DiffSymbolsCommand { args: ds }))
+        }
+
+        (Command::DirOverview(dov), CommandSafetyLevel::DangerousToolUseAllowed) => {
+            Ok(Box::new(DirOverviewCommand { args: dov }))
+        }
+
+        (Command::EnumUsage(eu), _) => Ok(Box::new(EnumUsageCommand { args: eu })),
+
+        (Command::Excerpt(e), _) => Ok(Box::new(ExcerptCommand { args: e })),
+
+        (Command::ExpandNode(en), _) => Ok(Box::new(ExpandNodeCommand { args: en })),
+
+        (Command::ExtractFixture(ef), CommandSafetyLevel::DangerousToolUseAllowed) => {
+            Ok(Box::new(ExtractFixtureCommand { args: ef }))
+        }
+
+        (Command::FetchFile(ff), _) => Ok(Box::new(FetchFileCommand { args: ff })),
+
         (Command::FilterAnalysis(fa), _) => Ok(Box::new(FilterAnalysisCommand { args: fa })),
 
+        (Command::FilterCrossref(fc), _) => Ok(Box::new(FilterCrossrefCommand { args: fc })),
+
+        (Command::FlattenTextMatches(ftm), _) => {
+            Ok(Box::new(FlattenTextMatchesCommand { args: ftm }))
+        }
+
         (Command::FormatSymbols(fs), _) => Ok(Box::new(FormatSymbolsCommand { args: fs })),
 
         (Command::Graph(g), _) => Ok(Box::new(GraphCommand { args: g })),
 
+        (Command::GraphMetrics(gm), _) => Ok(Box::new(GraphMetricsCommand { args: gm })),
+
+        (Command::HistoryGrep(hg), _) => Ok(Box::new(HistoryGrepCommand { args: hg })),
+
+        (Command::IndexStats(is), CommandSafetyLevel::DangerousToolUseAllowed) => {
+            Ok(Box::new(IndexStatsCommand { args: is }))
+        }
+
         (Command::JumprefLookup(cl), _) => Ok(Box::new(JumprefLookupCommand { args: cl })),
 
+        (Command::L10nRefs(lr), _) => Ok(Box::new(L10nRefsCommand { args: lr })),
+
+        (Command::LegacySearch(ls), _) => Ok(Box::new(LegacySearchCommand { args: ls })),
+
         (Command::MergeAnalyses(ma), _) => Ok(Box::new(MergeAnalysesCommand { args: ma })),
 
+        (Command::ModuleApi(ma), CommandSafetyLevel::DangerousToolUseAllowed) => {
+            Ok(Box::new(ModuleApiCommand { args: ma }))
+        }
+
+        (Command::OnlyNewSince(ons), _) => Ok(Box::new(OnlyNewSinceCommand { args: ons })),
+
+        (Command::Outline(o), _) => Ok(Box::new(OutlineCommand { args: o })),
+
+        (Command::PrecomputeAggregates(pa), CommandSafetyLevel::DangerousToolUseAllowed) => {
+            Ok(Box::new(PrecomputeAggregatesCommand { args: pa }))
+        }
+
+        (Command::PreprocessorFilter(pf), _) => {
+            Ok(Box::new(PreprocessorFilterCommand { args: pf }))
+        }
+
         (Command::ProductionFilter(pf), _) => Ok(Box::new(ProductionFilterCommand { args: pf })),
 
         (Command::Query(q), _) => Ok(Box::new(QueryCommand { args: q })),
 
+        (Command::Redact(r), _) => Ok(Box::new(RedactCommand { args: r })),
+
         (Command::Render(r), _) => Ok(Box::new(RenderCommand { args: r })),
 
+        (Command::RenderMarkdown(rm), _) => Ok(Box::new(RenderMarkdownCommand { args: rm })),
+
+        (Command::RenderQuickfix(rq), _) => Ok(Box::new(RenderQuickfixCommand { args: rq })),
+
+        (Command::Rerank(r), _) => Ok(Box::new(RerankCommand { args: r })),
+
         (Command::Search(q), _) => Ok(Box::new(SearchCommand { args: q })),
 
         (Command::SearchFiles(sf), _) => Ok(Box::new(SearchFilesCommand { args: sf })),
 
         (Command::SearchIdentifiers(si), _) => Ok(Box::new(SearchIdentifiersCommand { args: si })),
 
+        (Command::SearchMentions(sm), _) => Ok(Box::new(SearchMentionsCommand { args: sm })),
+
         (Command::SearchText(st), _) => Ok(Box::new(SearchTextCommand { args: st })),
 
         (Command::ShowHtml(sh), _) => Ok(Box::new(ShowHtmlCommand { args: sh })),
 
+        (Command::SimilarFunctions(sf), _) => Ok(Box::new(SimilarFunctionsCommand { args: sf })),
+
+        (Command::SlotJump(sj), _) => Ok(Box::new(SlotJumpCommand { args: sj })),
+
+        (Command::SuggestIdentifiers(si), _) => {
+            Ok(Box::new(SuggestIdentifiersCommand { args: si }))
+        }
+
+        (Command::SymbolPayloads(sp), _) => Ok(Box::new(SymbolPayloadsCommand { args: sp })),
+
+        (Command::SymbolsAtPoint(sap), _) => Ok(Box::new(SymbolsAtPointCommand { args: sap })),
+
         (Command::TokenizeSource(ts), _) => Ok(Box::new(TokenizeSourceCommand { args: ts })),
 
+        (Command::TopSymbols(ts), CommandSafetyLevel::DangerousToolUseAllowed) => {
+            Ok(Box::new(TopSymbolsCommand { args: ts }))
+        }
+
         (Command::Traverse(t), _) => Ok(Box::new(TraverseCommand { args: t })),
 
+        (Command::UnsafeAudit(ua), _) => Ok(Box::new(UnsafeAuditCommand { args: ua })),
+
+        (Command::ValidateAnalysis(va), _) => Ok(Box::new(ValidateAnalysisCommand { args: va })),
+
         (Command::Webtest(t), CommandSafetyLevel::DangerousToolUseAllowed) => {
             Ok(Box::new(WebtestCommand { args: t }))
         }
 
-        _ => Err(ServerError::StickyProblem(ErrorDetails {
-            layer: ErrorLayer::BadInput,
-            message: "Command not allowed in this context".to_string(),
-        })),
+        _ => Err(ServerError::BadPipeline(PipelineDiagnostic::new(
+            PipelineDiagnosticKind::NotAllowed,
+            "Command not allowed in this context".to_string(),
+        ))),
+    }
+}
+
+/// Attribute a `BadPipeline` error to the given segment.
+fn in_segment(err: ServerError, segment: usize, words: Option<&[PipelineWord]>) -> ServerError {
+    match err {
+        ServerError::BadPipeline(diagnostic) => {
+            ServerError::BadPipeline(diagnostic.in_segment(segment, words))
+        }
+        err => err,
     }
 }
 
@@ -108,9 +281,23 @@ pub fn fab_junction_from_opts(
     opts: JunctionOpts,
 ) -> Result<Box<dyn PipelineJunctionCommand + Send + Sync>> {
     match opts.cmd {
+        JunctionCommand::AnnotateCoverage(ac) => Ok(Box::new(AnnotateCoverageCommand { args: ac })),
+
+        JunctionCommand::BatchRender(br) => Ok(Box::new(BatchRenderJunctionCommand { args: br })),
+
         JunctionCommand::CompileResults(cr) => Ok(Box::new(CompileResultsCommand { args: cr })),
 
+        JunctionCommand::DefsInTextMatches(dtm) => {
+            Ok(Box::new(DefsInTextMatchesCommand { args: dtm }))
+        }
+
         JunctionCommand::FuseCrossrefs(fc) => Ok(Box::new(FuseCrossrefsCommand { args: fc })),
+
+        JunctionCommand::GroupByDefFile(gbdf) => Ok(Box::new(GroupByDefFileCommand { args: gbdf })),
+
+        JunctionCommand::Interleave(il) => Ok(Box::new(InterleaveCommand { args: il })),
+
+        JunctionCommand::TextDiffHits(tdh) => Ok(Box::new(TextDiffHitsCommand { args: tdh })),
     }
 }
 
@@ -121,60 +308,102 @@ pub fn fab_junction_from_opts(
 /// then break into separate sub-commands whenever we see a `|`.  We then pass
 /// these sub-commands to the structopt parsing `from_iter` method, taking care
 /// to stuff our binary name into the first arg.
+///
+/// Parse failures are reported as `ServerError::BadPipeline` diagnostics that
+/// identify the offending segment and its location in `arg_str`.
+///
+/// This is for searchfox-tool and test_check_insta, which we allow to do raw
+/// pipeline stuff that we do not want to expose to the web, and which own
+/// their process, so the first segment's `--progress` and `--trace-output`
+/// arguments are honored.  The pipeline-server uses
+/// `build_pipeline_graph` below.
 pub fn build_pipeline(bin_name: &str, arg_str: &str) -> Result<(ServerPipeline, OutputFormat)> {
+    build_pipeline_with_safety(
+        bin_name,
+        arg_str,
+        CommandSafetyLevel::DangerousToolUseAllowed,
+    )
+}
+
+/// Like `build_pipeline`, but only allowing the commands permitted at the
+/// given safety level.  At `WebSafety` the pipeline is assumed to be running
+/// inside some other process (ex: via the FFI), so progress reporting stays
+/// disabled and the process-wide trace output is left alone.
+pub fn build_pipeline_with_safety(
+    bin_name: &str,
+    arg_str: &str,
+    safety: CommandSafetyLevel,
+) -> Result<(ServerPipeline, OutputFormat)> {
     let span = trace_span!("build_pipeline", arg_str);
     let _span_guard = span.enter();
 
     let all_args = match shell_words::split(arg_str) {
         Ok(parsed) => parsed,
         Err(err) => {
-            return Err(ServerError::StickyProblem(ErrorDetails {
-                layer: ErrorLayer::BadInput,
-                message: err.to_string(),
-            }));
+            return Err(ServerError::BadPipeline(PipelineDiagnostic::new(
+                PipelineDiagnosticKind::Syntax,
+                err.to_string(),
+            )));
         }
     };
+    // Our own splitting only exists to locate things, so we only trust it if
+    // it agrees with shell-words.
+    let all_words = split_words_with_ranges(arg_str).filter(|words| {
+        words.len() == all_args.len()
+            && words
+                .iter()
+                .zip(&all_args)
+                .all(|(word, arg)| &word.value == arg)
+    });
+    let segment_words = all_words
+        .as_ref()
+        .map(|words| words.split(|word| word.value == "|").collect::<Vec<_>>());
 
     let mut server_kind = "none";
     let mut server = None;
     let mut output_format = None;
+    let mut estimate = false;
+    let mut strict = false;
+    let mut progress = ProgressFormat::None;
     let mut first_time = true;
 
     let mut commands: Vec<Box<dyn PipelineCommand + Send + Sync>> = vec![];
 
-    for arg_slices in all_args.split(|v| v == "|") {
+    for (segment, arg_slices) in all_args.split(|v| v == "|").enumerate() {
+        let words = segment_words
+            .as_ref()
+            .and_then(|segments| segments.get(segment).copied());
         let mut fake_args = vec![bin_name.to_string()];
         fake_args.extend(arg_slices.iter().cloned());
 
         let opts = match ToolOpts::try_parse_from(fake_args) {
             Ok(opts) => opts,
             Err(err) => {
-                return Err(ServerError::StickyProblem(ErrorDetails {
-                    layer: ErrorLayer::BadInput,
-                    message: err.to_string(),
-                }));
+                return Err(ServerError::BadPipeline(diagnostic_from_clap(
+                    err, arg_slices, segment, words,
+                )));
             }
         };
         //println!("Pipeline segment: {:?}", opts);
 
         if first_time {
-            (server_kind, server) = match Url::parse(&opts.server) {
-                Ok(url) => ("remote", Some(make_remote_server(url, &opts.tree)?)),
-                Err(_) => ("local", Some(make_local_server(&opts.server, &opts.tree)?)),
-            };
+            let (kind, selected) =
+                make_server_for_selector(opts.server.as_deref(), opts.tree.as_deref())?;
+            (server_kind, server) = (kind, Some(selected));
             output_format = Some(opts.output_format.clone());
+            if let CommandSafetyLevel::DangerousToolUseAllowed = safety {
+                progress = opts.progress;
+                set_trace_output(opts.trace_output.clone());
+            }
+            estimate = opts.estimate;
+            strict = opts.strict;
             first_time = false;
         }
 
         trace!(cmd = ?opts.cmd);
-        // We only use this method (`build_pipeline`) for searchfox-tool and
-        // test_check_insta, and we allow them to do raw pipeline stuff that we
-        // do not want to expose to the web.  The pipeline-server uses
-        // `build_pipeline_graph` below.  (Also, the "query" command )
-        commands.push(fab_command_from_opts(
-            opts,
-            CommandSafetyLevel::DangerousToolUseAllowed,
-        )?);
+        commands.push(
+            fab_command_from_opts(opts, safety).map_err(|err| in_segment(err, segment, words))?,
+        );
     }
 
     Ok((
@@ -182,6 +411,9 @@ pub fn build_pipeline(bin_name: &str, arg_str: &str) -> Result<(ServerPipeline,
             server_kind: server_kind.to_string(),
             server: server.unwrap(),
             commands,
+            estimate,
+            strict,
+            progress,
         },
         output_format.unwrap(),
     ))
@@ -212,7 +444,7 @@ pub fn build_pipeline_graph(
                     input_name = group_info.input.clone();
                 }
 
-                for segment in &group_info.segments {
+                for (segment_index, segment) in group_info.segments.iter().enumerate() {
                     // The args needs to include a fake binary name, then the
                     // command, then the args.
                     let full_args: Vec<String> =
@@ -222,18 +454,25 @@ pub fn build_pipeline_graph(
                             .cloned()
                             .collect();
                     trace!(full_args = ?full_args);
-                    let opts = match ToolOpts::try_parse_from(full_args) {
+                    let opts = match ToolOpts::try_parse_from(&full_args) {
                         Ok(opts) => opts,
                         Err(err) => {
-                            return Err(ServerError::StickyProblem(ErrorDetails {
-                                layer: ErrorLayer::BadInput,
-                                message: err.to_string(),
-                            }));
+                            // There's no pipeline string for these segments,
+                            // so there's no range.
+                            return Err(ServerError::BadPipeline(diagnostic_from_clap(
+                                err,
+                                &full_args[1..],
+                                segment_index,
+                                None,
+                            )));
                         }
                     };
 
                     trace!(cmd = ?opts.cmd);
-                    commands.push(fab_command_from_opts(opts, CommandSafetyLevel::WebSafety)?);
+                    commands.push(
+                        fab_command_from_opts(opts, CommandSafetyLevel::WebSafety)
+                            .map_err(|err| in_segment(err, segment_index, None))?,
+                    );
                 }
 
                 output_name = group_info
@@ -274,13 +513,16 @@ pub fn build_pipeline_graph(
             .cloned()
             .collect();
             trace!(full_args = ?full_args);
-            let opts = match JunctionOpts::try_parse_from(full_args) {
+            let opts = match JunctionOpts::try_parse_from(&full_args) {
                 Ok(opts) => opts,
                 Err(err) => {
-                    return Err(ServerError::StickyProblem(ErrorDetails {
-                        layer: ErrorLayer::BadInput,
-                        message: err.to_string(),
-                    }));
+                    // A junction is its own single segment.
+                    return Err(ServerError::BadPipeline(diagnostic_from_clap(
+                        err,
+                        &full_args[1..],
+                        0,
+                        None,
+                    )));
                 }
             };
 
@@ -311,5 +553,9 @@ pub fn build_pipeline_graph(
         });
     }
 
-    Ok(ServerPipelineGraph { server, pipelines })
+    Ok(ServerPipelineGraph {
+        server,
+        pipelines,
+        plan: query.plan,
+    })
 }
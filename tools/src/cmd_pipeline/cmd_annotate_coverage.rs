@@ -0,0 +1,245 @@
+use std::collections::{HashMap, HashSet};
+
+use async_trait::async_trait;
+use clap::{Args, ValueEnum};
+use ustr::Ustr;
+
+use super::interface::{
+    CoverageStatus, FlattenedLineSpan, FlattenedResultsBundle, LineCoverage,
+    PipelineJunctionCommand, PipelineValues,
+};
+
+use crate::abstract_server::{
+    AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError, TextMatches,
+};
+
+#[derive(Clone, Debug, PartialEq, ValueEnum)]
+pub enum CoverageFilter {
+    /// Keep all hits.
+    Any,
+    /// Only keep hits on lines that were executed.
+    Covered,
+    /// Only keep hits on instrumented lines that were never executed.
+    Uncovered,
+}
+
+/// Junction that joins the tree's code coverage data with a single input of
+/// results, for "uncovered callers of X" style queries.  The line spans of a
+/// `FlattenedResultsBundle` are annotated with their coverage, which is the
+/// coverage of their key line or, if it wasn't instrumented, the first
+/// instrumented line of their range.  `TextMatches` can only be filtered.
+///
+/// Filtering a `FlattenedResultsBundle` drops the facets and collapse hints of
+/// the kind groups it removes files from since they'd no longer be accurate.
+/// Hits in files without coverage data are dropped by any filter.
+#[derive(Debug, Args)]
+pub struct AnnotateCoverage {
+    #[clap(long, value_parser, value_enum, default_value = "any")]
+    only: CoverageFilter,
+}
+
+#[derive(Debug)]
+pub struct AnnotateCoverageCommand {
+    pub args: AnnotateCoverage,
+}
+
+/// Derive the coverage of a span from its file's coverage data.
+fn span_coverage(coverage_lines: &[i64], key_line: u32, line_range: (u32, u32)) -> LineCoverage {
+    std::iter::once(key_line)
+        .chain(line_range.0..=line_range.1)
+        .find_map(|lno| LineCoverage::for_line(coverage_lines, lno))
+        .unwrap_or(LineCoverage {
+            status: CoverageStatus::Uninstrumented,
+            hits: None,
+            interpolated: false,
+        })
+}
+
+fn passes_filter(coverage: Option<&LineCoverage>, filter: &CoverageFilter) -> bool {
+    match (filter, coverage.map(|c| c.status)) {
+        (CoverageFilter::Any, _) => true,
+        (CoverageFilter::Covered, Some(CoverageStatus::Covered)) => true,
+        (CoverageFilter::Uncovered, Some(CoverageStatus::Uncovered)) => true,
+        _ => false,
+    }
+}
+
+/// Fetches and caches the coverage data of files, since the same file tends to
+/// show up in multiple kind groups.
+struct CoverageCache<'a> {
+    server: &'a (dyn AbstractServer + Send + Sync),
+    by_file: HashMap<Ustr, Option<Vec<i64>>>,
+}
+
+impl<'a> CoverageCache<'a> {
+    async fn lines(&mut self, file: Ustr) -> Result<Option<&Vec<i64>>> {
+        if !self.by_file.contains_key(&file) {
+            let lines = self.server.fetch_coverage_lines(&file).await?;
+            self.by_file.insert(file, lines);
+        }
+        Ok(self.by_file[&file].as_ref())
+    }
+}
+
+fn annotate_spans(
+    spans: &mut Vec<FlattenedLineSpan>,
+    coverage_lines: Option<&Vec<i64>>,
+    filter: &CoverageFilter,
+) {
+    for span in spans.iter_mut() {
+        span.coverage =
+            coverage_lines.map(|lines| span_coverage(lines, span.key_line, span.line_range));
+    }
+    spans.retain(|span| passes_filter(span.coverage.as_ref(), filter));
+}
+
+impl AnnotateCoverageCommand {
+    async fn annotate_bundle(
+        &self,
+        cache: &mut CoverageCache<'_>,
+        bundle: &mut FlattenedResultsBundle,
+    ) -> Result<()> {
+        let filter = &self.args.only;
+        // The (path, key line) pairs that survive filtering, for `flat`.
+        let mut kept_lines = HashSet::new();
+        for path_kind_group in &mut bundle.path_kind_results {
+            let mut kept_files = HashSet::new();
+            for kind_group in &mut path_kind_group.kind_groups {
+                let file_count = kind_group.by_file.len() + kind_group.collapsed_by_file.len();
+                for by_file in kind_group
+                    .by_file
+                    .iter_mut()
+                    .chain(kind_group.collapsed_by_file.iter_mut())
+                {
+                    let coverage_lines = cache.lines(by_file.file).await?;
+                    annotate_spans(&mut by_file.line_spans, coverage_lines, filter);
+                    for span in &by_file.line_spans {
+                        kept_lines.insert((by_file.file, span.key_line));
+                    }
+                }
+                kind_group.by_file.retain(|f| !f.line_spans.is_empty());
+                kind_group
+                    .collapsed_by_file
+                    .retain(|f| !f.line_spans.is_empty());
+                if kind_group.by_file.len() + kind_group.collapsed_by_file.len() != file_count {
+                    kind_group.facets.clear();
+                    kind_group.collapse_hint = None;
+                }
+                kept_files.extend(
+                    kind_group
+                        .by_file
+                        .iter()
+                        .chain(&kind_group.collapsed_by_file)
+                        .map(|f| f.file),
+                );
+            }
+            if *filter != CoverageFilter::Any {
+                path_kind_group.kind_groups.retain(|kind_group| {
+                    !kind_group.by_file.is_empty() || !kind_group.collapsed_by_file.is_empty()
+                });
+                // Files from "Files" hits don't have line spans, so they only
+                // survive if they have hits in a kind group too.
+                path_kind_group
+                    .file_names
+                    .retain(|file| kept_files.contains(file));
+            }
+        }
+        if *filter != CoverageFilter::Any {
+            bundle
+                .path_kind_results
+                .retain(|group| !group.kind_groups.is_empty() || !group.file_names.is_empty());
+            if let Some(flat) = &mut bundle.flat {
+                flat.retain(|hit| kept_lines.contains(&(hit.path, hit.line)));
+            }
+        }
+        Ok(())
+    }
+
+    async fn filter_text_matches(
+        &self,
+        cache: &mut CoverageCache<'_>,
+        tm: &mut TextMatches,
+    ) -> Result<()> {
+        if self.args.only == CoverageFilter::Any {
+            return Ok(());
+        }
+        for file_matches in &mut tm.by_file {
+            let coverage_lines = cache.lines(file_matches.file).await?;
+            file_matches.matches.retain(|m| {
+                let coverage = coverage_lines
+                    .map(|lines| span_coverage(lines, m.line_num, (m.line_num, m.line_num)));
+                passes_filter(coverage.as_ref(), &self.args.only)
+            });
+        }
+        tm.by_file.retain(|f| !f.matches.is_empty());
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl PipelineJunctionCommand for AnnotateCoverageCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        input: Vec<(String, PipelineValues)>,
+    ) -> Result<PipelineValues> {
+        if input.len() != 1 {
+            return Err(ServerError::StickyProblem(ErrorDetails {
+                layer: ErrorLayer::ConfigLayer,
+                message: format!(
+                    "annotate-coverage needs exactly 1 input but got {}",
+                    input.len()
+                ),
+            }));
+        }
+        let mut cache = CoverageCache {
+            server,
+            by_file: HashMap::new(),
+        };
+        match input.into_iter().next().unwrap().1 {
+            PipelineValues::FlattenedResultsBundle(mut bundle) => {
+                self.annotate_bundle(&mut cache, &mut bundle).await?;
+                Ok(PipelineValues::FlattenedResultsBundle(bundle))
+            }
+            PipelineValues::TextMatches(mut tm) => {
+                self.filter_text_matches(&mut cache, &mut tm).await?;
+                Ok(PipelineValues::TextMatches(tm))
+            }
+            _ => Err(ServerError::StickyProblem(ErrorDetails {
+                layer: ErrorLayer::ConfigLayer,
+                message: "annotate-coverage only accepts FlattenedResultsBundle or TextMatches"
+                    .to_string(),
+            })),
+        }
+    }
+}
+
+#[test]
+fn test_span_coverage() {
+    // Lines: 1 uninstrumented, 2 hit 5 times, 3 interpolated hit, 4 missed,
+    // 5 interpolated miss, 6 uninstrumented.
+    let lines = vec![-1, 5, -2, 0, -3, -1];
+
+    let covered = span_coverage(&lines, 2, (2, 2));
+    assert_eq!(covered.status, CoverageStatus::Covered);
+    assert_eq!(covered.hits, Some(5));
+    assert!(!covered.interpolated);
+
+    let interpolated = span_coverage(&lines, 5, (5, 5));
+    assert_eq!(interpolated.status, CoverageStatus::Uncovered);
+    assert_eq!(interpolated.hits, None);
+    assert!(interpolated.interpolated);
+
+    // An uninstrumented key line falls back to the first instrumented line of
+    // the range.
+    let fallback = span_coverage(&lines, 1, (1, 4));
+    assert_eq!(fallback.status, CoverageStatus::Covered);
+    assert_eq!(fallback.hits, Some(5));
+
+    // Lines past the end of the data are uninstrumented.
+    let missing = span_coverage(&lines, 6, (6, 9));
+    assert_eq!(missing.status, CoverageStatus::Uninstrumented);
+    assert!(!passes_filter(Some(&missing), &CoverageFilter::Uncovered));
+    assert!(passes_filter(Some(&missing), &CoverageFilter::Any));
+    assert!(!passes_filter(None, &CoverageFilter::Covered));
+}
@@ -0,0 +1,134 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use clap::Args;
+use serde_json::{json, to_value, Value};
+use ustr::Ustr;
+
+use super::interface::{JsonValue, JsonValueList, PipelineCommand, PipelineValues};
+
+use crate::abstract_server::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError};
+
+/// For trees that aggregate multiple repositories (ex: git submodules), annotate
+/// the files in piped-in results with the sub-repository they belong to, its
+/// revision, and a permalink to the file on the sub-repo's upstream server.
+///
+/// Accepts `TextMatches`, `FileMatches`, `SymbolCrossrefInfoList`, or
+/// `InterleavedResults` and produces a JsonValue per distinct file of the form
+/// `{ path, lines: [lno...], repo }` where `repo` is null for files belonging
+/// to the tree's primary repository and otherwise `{ name, prefix, revision,
+/// repo_path, permalink, line_anchor_pattern }`.  When `--line-links` is
+/// specified, there will also be a `lineLinks` list of per-line permalinks.
+#[derive(Debug, Args)]
+pub struct AnnotateRepos {
+    /// Include permalinks for each line in addition to the file permalink.
+    #[clap(long, action)]
+    line_links: bool,
+
+    /// Only emit files that belong to a sub-repo.
+    #[clap(long, action)]
+    sub_repos_only: bool,
+}
+
+#[derive(Debug)]
+pub struct AnnotateReposCommand {
+    pub args: AnnotateRepos,
+}
+
+#[async_trait]
+impl PipelineCommand for AnnotateReposCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        // We use a Vec of paths alongside the map to preserve the input order.
+        let mut path_order: Vec<Ustr> = vec![];
+        let mut lines_by_path: BTreeMap<Ustr, Vec<u32>> = BTreeMap::new();
+        let mut add = |path: Ustr, lno: Option<u32>| {
+            let lines = lines_by_path.entry(path).or_insert_with(|| {
+                path_order.push(path);
+                vec![]
+            });
+            if let Some(lno) = lno {
+                if !lines.contains(&lno) {
+                    lines.push(lno);
+                }
+            }
+        };
+
+        match input {
+            PipelineValues::TextMatches(tm) => {
+                for file_matches in tm.by_file {
+                    add(file_matches.file, None);
+                    for m in file_matches.matches {
+                        add(file_matches.file, Some(m.line_num));
+                    }
+                }
+            }
+            PipelineValues::FileMatches(fm) => {
+                for file_match in fm.file_matches {
+                    add(file_match.path, None);
+                }
+            }
+            PipelineValues::SymbolCrossrefInfoList(scil) => {
+                for info in scil.symbol_crossref_infos {
+                    for (_, path_containers) in info.crossref_info.hit_kinds() {
+                        for pc in path_containers {
+                            add(pc.path, None);
+                            for line in &pc.lines {
+                                add(pc.path, Some(line.lineno));
+                            }
+                        }
+                    }
+                }
+            }
+            PipelineValues::InterleavedResults(ir) => {
+                for result in ir.results {
+                    add(result.path, Some(result.lno));
+                }
+            }
+            _ => {
+                return Err(ServerError::StickyProblem(ErrorDetails {
+                    layer: ErrorLayer::ConfigLayer,
+                    message: "annotate-repos needs TextMatches, FileMatches, SymbolCrossrefInfoList, or InterleavedResults".to_string(),
+                }));
+            }
+        }
+
+        let mut values = vec![];
+        for path in path_order {
+            let lines = lines_by_path.remove(&path).unwrap_or_default();
+            let sub_repo = server.sub_repo_for_path(&path)?;
+            if self.args.sub_repos_only && sub_repo.is_none() {
+                continue;
+            }
+
+            let mut value = json!({
+                "path": path,
+                "lines": lines,
+                "repo": match &sub_repo {
+                    Some(info) => to_value(info)?,
+                    None => Value::Null,
+                },
+            });
+            if self.args.line_links {
+                let permalink = sub_repo.as_ref().and_then(|info| {
+                    info.permalink
+                        .as_ref()
+                        .map(|link| (link, &info.line_anchor_pattern))
+                });
+                value["lineLinks"] = match permalink {
+                    Some((link, pattern)) => json!(lines
+                        .iter()
+                        .map(|lno| format!("{}{}", link, pattern.replace("{}", &lno.to_string())))
+                        .collect::<Vec<_>>()),
+                    None => json!([]),
+                };
+            }
+            values.push(JsonValue { value });
+        }
+
+        Ok(PipelineValues::JsonValueList(JsonValueList { values }))
+    }
+}
@@ -0,0 +1,340 @@
+use std::collections::{BTreeMap, HashMap};
+
+use async_trait::async_trait;
+use clap::Args;
+use serde::Serialize;
+use serde_json::to_value;
+use ustr::{ustr, Ustr};
+
+use super::interface::{JsonValue, PipelineCommand, PipelineValues};
+
+use crate::{
+    abstract_server::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError},
+    file_format::crossref_record::CrossrefRecord,
+};
+
+/// For each piped-in (or explicitly specified) function symbol, find the
+/// argument expressions used at each of its call sites and tabulate the
+/// distinct values seen for each parameter position.  This is useful for
+/// auditing how a flag parameter is actually used across the tree.
+///
+/// Crossref "uses" hits only tell us where the function's name appears and
+/// their line excerpts are trimmed and truncated, so we re-read the source
+/// lines and start slicing the argument list at the end of the use's bounds.
+/// This is a purely lexical process, so it won't do anything clever for
+/// macros, and uses that aren't immediately followed by a `(` (ex: taking a
+/// function pointer) are counted as `non_call_uses`.
+#[derive(Debug, Args)]
+pub struct ArgsOfCalls {
+    /// Explicit symbols to tabulate call arguments for.
+    #[clap(short, long, value_parser)]
+    symbol: Vec<String>,
+
+    /// Maximum number of distinct values to report per parameter position.
+    #[clap(long, value_parser, default_value = "20")]
+    values_limit: usize,
+
+    /// Maximum number of source lines an argument list may span before we give
+    /// up on it.
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..=64), default_value = "8")]
+    max_lines: u32,
+}
+
+#[derive(Debug)]
+pub struct ArgsOfCallsCommand {
+    pub args: ArgsOfCalls,
+}
+
+#[derive(Serialize)]
+struct ArgValueCount {
+    value: String,
+    count: u32,
+    /// The first "path#lno" we saw this value used at.
+    example: String,
+}
+
+#[derive(Serialize)]
+struct ArgPosition {
+    position: usize,
+    /// The total number of distinct values, even if we truncated `values`.
+    distinct: usize,
+    values: Vec<ArgValueCount>,
+}
+
+#[derive(Serialize)]
+struct SymbolArgsOfCalls {
+    symbol: Ustr,
+    pretty: Ustr,
+    call_sites: u32,
+    /// Uses that weren't followed by an argument list.
+    non_call_uses: u32,
+    /// Call sites where we couldn't find the end of the argument list or
+    /// couldn't read the source.
+    unparsed: u32,
+    positions: Vec<ArgPosition>,
+}
+
+#[derive(Serialize)]
+struct ArgsOfCallsResult {
+    symbols: Vec<SymbolArgsOfCalls>,
+    unknown_symbols: Vec<String>,
+}
+
+/// The result of trying to slice an argument list out of source text.
+#[derive(Debug, PartialEq)]
+enum ArgSlice {
+    /// We found a complete, balanced argument list.
+    Args(Vec<String>),
+    /// The text didn't start with a `(`.
+    NotACall,
+    /// We ran out of text before the argument list was closed.
+    Incomplete,
+}
+
+/// Given text that immediately follows a function name, split out the
+/// top-level comma-delimited argument expressions, normalizing whitespace.
+/// Nested parens/brackets/braces and string/char literals are respected, but
+/// angle brackets are not because we can't tell them apart from comparisons.
+fn slice_call_args(text: &str) -> ArgSlice {
+    let text = text.trim_start();
+    if !text.starts_with('(') {
+        return ArgSlice::NotACall;
+    }
+
+    let mut args = vec![];
+    let mut cur = String::new();
+    let mut depth = 0;
+    let mut in_quote: Option<char> = None;
+    let mut escaped = false;
+
+    for c in text.chars() {
+        if let Some(quote) = in_quote {
+            cur.push(c);
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == quote {
+                in_quote = None;
+            }
+            continue;
+        }
+
+        match c {
+            '(' | '[' | '{' => {
+                depth += 1;
+                if depth == 1 {
+                    continue;
+                }
+            }
+            ')' | ']' | '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    let last = cur.split_whitespace().collect::<Vec<_>>().join(" ");
+                    // A call with no arguments shouldn't report an empty arg.
+                    if !last.is_empty() || !args.is_empty() {
+                        args.push(last);
+                    }
+                    return ArgSlice::Args(args);
+                }
+            }
+            ',' if depth == 1 => {
+                args.push(cur.split_whitespace().collect::<Vec<_>>().join(" "));
+                cur.clear();
+                continue;
+            }
+            '"' | '\'' => {
+                in_quote = Some(c);
+            }
+            _ => {}
+        }
+        cur.push(c);
+    }
+
+    ArgSlice::Incomplete
+}
+
+#[async_trait]
+impl PipelineCommand for ArgsOfCallsCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        let mut unknown_symbols = vec![];
+        let mut to_process: Vec<(Ustr, CrossrefRecord)> = vec![];
+
+        match input {
+            PipelineValues::SymbolCrossrefInfoList(cil) => {
+                unknown_symbols = cil.unknown_symbols;
+                for info in cil.symbol_crossref_infos {
+                    to_process.push((info.symbol, info.crossref_info));
+                }
+            }
+            PipelineValues::Void => {
+                for symbol in &self.args.symbol {
+                    let info = server.crossref_lookup(symbol, false).await?;
+                    if info.is_null() {
+                        unknown_symbols.push(symbol.clone());
+                        continue;
+                    }
+                    to_process.push((ustr(symbol), CrossrefRecord::from_value(info)?));
+                }
+            }
+            _ => {
+                return Err(ServerError::StickyProblem(ErrorDetails {
+                    layer: ErrorLayer::ConfigLayer,
+                    message: "args-of-calls needs a Void or SymbolCrossrefInfoList".to_string(),
+                }));
+            }
+        }
+
+        // Cache source files across symbols since overloads will frequently be
+        // used in the same files.  A None indicates we failed to read the file.
+        let mut source_lines: HashMap<Ustr, Option<Vec<String>>> = HashMap::new();
+        let mut symbols = vec![];
+
+        for (symbol, crossref_info) in to_process {
+            let pretty = crossref_info.pretty().unwrap_or(symbol);
+            let mut result = SymbolArgsOfCalls {
+                symbol,
+                pretty,
+                call_sites: 0,
+                non_call_uses: 0,
+                unparsed: 0,
+                positions: vec![],
+            };
+            // position => value => (count, example)
+            let mut tallies: BTreeMap<usize, HashMap<String, (u32, String)>> = BTreeMap::new();
+
+            for path_hits in &crossref_info.uses {
+                let path = path_hits.path;
+                let hits = &path_hits.lines;
+
+                if !source_lines.contains_key(&path) {
+                    let lines = match server.fetch_raw_source(&path).await {
+                        Ok(source) => Some(source.lines().map(|s| s.to_string()).collect()),
+                        Err(_) => None,
+                    };
+                    source_lines.insert(path, lines);
+                }
+                let lines = match source_lines.get(&path) {
+                    Some(Some(lines)) => lines,
+                    _ => {
+                        result.unparsed += hits.len() as u32;
+                        continue;
+                    }
+                };
+
+                for hit in hits {
+                    let lno = hit.lineno as usize;
+                    let bounds_end = hit.bounds.1 as usize;
+                    let line = match lno.checked_sub(1).and_then(|idx| lines.get(idx)) {
+                        Some(line) => line,
+                        None => {
+                            result.unparsed += 1;
+                            continue;
+                        }
+                    };
+                    // The crossref bounds are relative to the line with its
+                    // leading whitespace trimmed.
+                    let indent = line.len() - line.trim_start().len();
+                    let mut text = match line.get(indent + bounds_end..) {
+                        Some(rest) => rest.to_string(),
+                        None => {
+                            result.unparsed += 1;
+                            continue;
+                        }
+                    };
+
+                    let mut next_idx = lno;
+                    let mut slice = slice_call_args(&text);
+                    while slice == ArgSlice::Incomplete
+                        && next_idx < lno - 1 + self.args.max_lines as usize
+                    {
+                        match lines.get(next_idx) {
+                            Some(next_line) => {
+                                text.push('\n');
+                                text.push_str(next_line);
+                            }
+                            None => break,
+                        }
+                        next_idx += 1;
+                        slice = slice_call_args(&text);
+                    }
+
+                    match slice {
+                        ArgSlice::Args(args) => {
+                            result.call_sites += 1;
+                            for (position, value) in args.into_iter().enumerate() {
+                                let entry = tallies
+                                    .entry(position)
+                                    .or_default()
+                                    .entry(value)
+                                    .or_insert_with(|| (0, format!("{}#{}", path, lno)));
+                                entry.0 += 1;
+                            }
+                        }
+                        ArgSlice::NotACall => {
+                            result.non_call_uses += 1;
+                        }
+                        ArgSlice::Incomplete => {
+                            result.unparsed += 1;
+                        }
+                    }
+                }
+            }
+
+            for (position, values) in tallies {
+                let distinct = values.len();
+                let mut values: Vec<ArgValueCount> = values
+                    .into_iter()
+                    .map(|(value, (count, example))| ArgValueCount {
+                        value,
+                        count,
+                        example,
+                    })
+                    .collect();
+                values.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+                values.truncate(self.args.values_limit);
+                result.positions.push(ArgPosition {
+                    position,
+                    distinct,
+                    values,
+                });
+            }
+
+            symbols.push(result);
+        }
+
+        Ok(PipelineValues::JsonValue(JsonValue {
+            value: to_value(ArgsOfCallsResult {
+                symbols,
+                unknown_symbols,
+            })?,
+        }))
+    }
+}
+
+#[test]
+fn test_slice_call_args() {
+    assert_eq!(
+        slice_call_args("(a, b)"),
+        ArgSlice::Args(vec!["a".to_string(), "b".to_string()])
+    );
+    assert_eq!(slice_call_args("()"), ArgSlice::Args(vec![]));
+    assert_eq!(
+        slice_call_args(" (foo(1, 2), \"x, y\", ',') + 1;"),
+        ArgSlice::Args(vec![
+            "foo(1, 2)".to_string(),
+            "\"x, y\"".to_string(),
+            "','".to_string()
+        ])
+    );
+    assert_eq!(
+        slice_call_args("(aFlags,\n      true)"),
+        ArgSlice::Args(vec!["aFlags".to_string(), "true".to_string()])
+    );
+    assert_eq!(slice_call_args("(a, b"), ArgSlice::Incomplete);
+    assert_eq!(slice_call_args(" = &Foo;"), ArgSlice::NotACall);
+}
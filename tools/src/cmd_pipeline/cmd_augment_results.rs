@@ -5,7 +5,7 @@ use clap::Parser;
 use lol_html::{element, HtmlRewriter, Settings};
 use ustr::UstrMap;
 
-use super::interface::{PipelineCommand, PipelineValues};
+use super::interface::{PipelineCommand, PipelineValues, StageEstimate};
 use crate::abstract_server::{
     AbstractServer, ErrorDetails, ErrorLayer, HtmlFileRoot, Result, ServerError,
 };
@@ -37,20 +37,35 @@ use crate::abstract_server::{
 ///   bones syntax highlighting.
 #[derive(Debug, Parser)]
 pub struct AugmentResults {
-    /// Lines of context before a hit.
+    /// Lines of context before a hit.  This is clamped to
+    /// `MAX_CONTEXT_LINES` so that a query like `context:100000` can't make us
+    /// extract entire files for every hit.
     #[clap(short, long, value_parser, default_value = "0")]
     before: u32,
 
-    /// Lines of context after a hit.
+    /// Lines of context after a hit, clamped like `before`.
     #[clap(short, long, value_parser, default_value = "0")]
     after: u32,
 }
 
+/// The most lines of context we'll show before or after a hit.
+const MAX_CONTEXT_LINES: u32 = 50;
+
 #[derive(Debug)]
 pub struct AugmentResultsCommand {
     pub args: AugmentResults,
 }
 
+impl AugmentResultsCommand {
+    /// The (before, after) lines of context to show, clamped.
+    fn context(&self) -> (u32, u32) {
+        (
+            self.args.before.min(MAX_CONTEXT_LINES),
+            self.args.after.min(MAX_CONTEXT_LINES),
+        )
+    }
+}
+
 #[async_trait]
 impl PipelineCommand for AugmentResultsCommand {
     async fn execute(
@@ -76,11 +91,10 @@ impl PipelineCommand for AugmentResultsCommand {
         // - Don't wanna have this experimental thing steal all the resources
         //   from the non-experimental router.py and web-server.rs yet!
 
+        let (before, after) = self.context();
         let mut path_line_contents: UstrMap<HashMap<u32, String>> = UstrMap::default();
 
-        for (path, lines_to_show) in
-            results.compute_path_line_sets(self.args.before, self.args.after)
-        {
+        for (path, lines_to_show) in results.compute_path_line_sets(before, after) {
             // XXX Doing this as a single string received in a lump is fine for
             // our testing use-case, but this may need to be reconsidered in
             // production.  Or maybe production really wants the performance?
@@ -192,8 +206,25 @@ impl PipelineCommand for AugmentResultsCommand {
         }
 
         // ## Ingest the new lines.
-        results.ingest_html_lines(&path_line_contents, self.args.before, self.args.after);
+        results.ingest_html_lines(&path_line_contents, before, after);
 
         Ok(PipelineValues::FlattenedResultsBundle(results))
     }
+
+    async fn estimate(
+        &self,
+        _server: &(dyn AbstractServer + Send + Sync),
+        input: &PipelineValues,
+    ) -> Result<Option<StageEstimate>> {
+        let results = match input {
+            PipelineValues::FlattenedResultsBundle(frb) => frb,
+            _ => return Ok(None),
+        };
+        let (before, after) = self.context();
+        let path_line_sets = results.compute_path_line_sets(before, after);
+        Ok(Some(StageEstimate {
+            files: Some(path_line_sets.len() as u64),
+            ..StageEstimate::default()
+        }))
+    }
 }
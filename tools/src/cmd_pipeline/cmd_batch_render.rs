@@ -1,7 +1,12 @@
 use async_trait::async_trait;
 use clap::Args;
 
-use super::interface::{PipelineCommand, PipelineValues};
+use super::{
+    interface::{
+        BatchGroupItem, BatchGroups, PipelineCommand, PipelineJunctionCommand, PipelineValues,
+    },
+    progress::ProgressTracker,
+};
 use crate::{
     abstract_server::{
         AbstractServer, ErrorDetails, ErrorLayer, Result, SearchfoxIndexRoot, ServerError,
@@ -49,7 +54,10 @@ impl PipelineCommand for BatchRenderCommand {
             "dir" => {
                 let template = build_and_parse_dir_listing();
                 let tree_info = server.tree_info()?;
+                let mut progress =
+                    ProgressTracker::new("batch-render", Some(batch_groups.groups.len() as u64));
                 for item in batch_groups.groups {
+                    progress.advance(1);
                     if let PipelineValues::FileMatches(fm) = item.value {
                         let liquid_globals = liquid::object!({
                             "tree": tree_info.name,
@@ -74,6 +82,7 @@ impl PipelineCommand for BatchRenderCommand {
                         write_file_ensuring_parent_dir(&output_path, &rendered)?;
                     }
                 }
+                progress.finish();
                 Ok(PipelineValues::Void)
             }
             unknown => Err(ServerError::StickyProblem(ErrorDetails {
@@ -83,3 +92,51 @@ impl PipelineCommand for BatchRenderCommand {
         }
     }
 }
+
+/// Junction that gathers each of its named inputs into its own group of a
+/// `BatchGroups` so that they can be rendered independently as separate
+/// sections of a single results page.  This is how a query that is ambiguous
+/// (ex: an identifier that matches both a class and an unrelated function) can
+/// present each interpretation in its own section rather than intermixing
+/// them.
+///
+/// Groups are emitted in the order of the junction's inputs and are named
+/// after the input.  Inputs that are themselves `BatchGroups` have their groups
+/// spliced in with the input name prefixed, and `Void` inputs are dropped
+/// because an empty section is not interesting.
+#[derive(Debug, Args)]
+pub struct BatchRenderJunction {}
+
+#[derive(Debug)]
+pub struct BatchRenderJunctionCommand {
+    pub args: BatchRenderJunction,
+}
+
+#[async_trait]
+impl PipelineJunctionCommand for BatchRenderJunctionCommand {
+    async fn execute(
+        &self,
+        _server: &(dyn AbstractServer + Send + Sync),
+        input: Vec<(String, PipelineValues)>,
+    ) -> Result<PipelineValues> {
+        let mut groups = vec![];
+        for (name, value) in input {
+            match value {
+                PipelineValues::Void => {}
+                PipelineValues::BatchGroups(bg) => {
+                    for item in bg.groups {
+                        groups.push(BatchGroupItem {
+                            name: format!("{}/{}", name, item.name),
+                            value: item.value,
+                        });
+                    }
+                }
+                value => {
+                    groups.push(BatchGroupItem { name, value });
+                }
+            }
+        }
+
+        Ok(PipelineValues::BatchGroups(BatchGroups { groups }))
+    }
+}
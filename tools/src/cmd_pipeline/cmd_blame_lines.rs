@@ -0,0 +1,130 @@
+use std::collections::HashSet;
+
+use async_trait::async_trait;
+use clap::Args;
+use serde_json::{to_value, Value};
+
+use super::interface::{JsonRecords, JsonRecordsByFile, PipelineCommand, PipelineValues};
+use crate::abstract_server::{
+    AbstractServer, BlameLine, ErrorDetails, ErrorLayer, Result, ServerError,
+};
+
+/// Emit blame records (rev, author, date, bug) for the lines of a file as
+/// `JsonRecords` so that blame can be consumed by other pipeline stages rather
+/// than only being visible in the rendered source UI.  Each record also gets a
+/// "loc" so that commands like `show-html` can be used on the output.
+///
+/// If `TextMatches` are piped in, the blame for each matched line is emitted
+/// instead, which makes it possible to answer questions like "who last touched
+/// the lines matching this regexp?".
+#[derive(Debug, Args)]
+pub struct BlameLines {
+    /// Tree-relative source file path
+    #[clap(value_parser)]
+    file: Option<String>,
+
+    /// Inclusive 1-based line range to blame, either "N" or "N-M".  Defaults to
+    /// the whole file.
+    #[clap(long, value_parser = parse_line_range)]
+    lines: Option<(u32, u32)>,
+}
+
+#[derive(Debug)]
+pub struct BlameLinesCommand {
+    pub args: BlameLines,
+}
+
+fn parse_line_range(s: &str) -> std::result::Result<(u32, u32), String> {
+    let parse = |n: &str| -> std::result::Result<u32, String> {
+        match n.trim().parse::<u32>() {
+            Ok(0) | Err(_) => Err(format!("bad line number: {}", n)),
+            Ok(v) => Ok(v),
+        }
+    };
+    let (first, last) = match s.split_once('-') {
+        Some((first, last)) => (parse(first)?, parse(last)?),
+        None => {
+            let lno = parse(s)?;
+            (lno, lno)
+        }
+    };
+    if last < first {
+        return Err(format!("line range {} is backwards", s));
+    }
+    Ok((first, last))
+}
+
+fn blame_to_records(lines: Vec<BlameLine>) -> Result<Vec<Value>> {
+    let mut records = vec![];
+    for line in lines {
+        let lno = line.lno;
+        let mut record = to_value(line)?;
+        if let Value::Object(obj) = &mut record {
+            obj.insert("loc".to_string(), Value::from(format!("{}:0", lno)));
+        }
+        records.push(record);
+    }
+    Ok(records)
+}
+
+#[async_trait]
+impl PipelineCommand for BlameLinesCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        let mut by_file = vec![];
+
+        match input {
+            PipelineValues::TextMatches(tm) => {
+                for file_matches in tm.by_file {
+                    let wanted: HashSet<u32> =
+                        file_matches.matches.iter().map(|m| m.line_num).collect();
+                    let (first, last) = match (wanted.iter().min(), wanted.iter().max()) {
+                        (Some(first), Some(last)) => (*first, *last),
+                        _ => continue,
+                    };
+                    let mut lines = server
+                        .fetch_blame_lines(&file_matches.file, Some((first, last)))
+                        .await?;
+                    lines.retain(|l| wanted.contains(&l.lno));
+                    by_file.push(JsonRecordsByFile {
+                        file: file_matches.file.to_string(),
+                        records: blame_to_records(lines)?,
+                    });
+                }
+            }
+            PipelineValues::Void => {
+                let file = self.args.file.as_ref().ok_or_else(|| {
+                    ServerError::StickyProblem(ErrorDetails {
+                        layer: ErrorLayer::BadInput,
+                        message: "blame-lines needs a file path".to_string(),
+                    })
+                })?;
+                let lines = server.fetch_blame_lines(file, self.args.lines).await?;
+                by_file.push(JsonRecordsByFile {
+                    file: file.clone(),
+                    records: blame_to_records(lines)?,
+                });
+            }
+            _ => {
+                return Err(ServerError::StickyProblem(ErrorDetails {
+                    layer: ErrorLayer::ConfigLayer,
+                    message: "blame-lines needs a Void or TextMatches".to_string(),
+                }));
+            }
+        }
+
+        Ok(PipelineValues::JsonRecords(JsonRecords { by_file }))
+    }
+}
+
+#[test]
+fn test_parse_line_range() {
+    assert_eq!(parse_line_range("12"), Ok((12, 12)));
+    assert_eq!(parse_line_range("3-9"), Ok((3, 9)));
+    assert!(parse_line_range("0").is_err());
+    assert!(parse_line_range("9-3").is_err());
+    assert!(parse_line_range("x").is_err());
+}
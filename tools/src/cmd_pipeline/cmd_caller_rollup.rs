@@ -0,0 +1,237 @@
+use std::collections::{BTreeMap, HashSet};
+
+use async_trait::async_trait;
+use clap::Args;
+use serde::Serialize;
+use serde_json::to_value;
+use ustr::{ustr, Ustr};
+
+use super::{
+    interface::{JsonValue, OverloadInfo, OverloadKind, PipelineCommand, PipelineValues},
+    progress::ProgressTracker,
+    warnings::record_overload,
+};
+
+use crate::{
+    abstract_server::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError},
+    file_format::crossref_record::CrossrefRecord,
+};
+
+/// Aggregate the transitive callers of one or more symbols level by level,
+/// reporting how many distinct callers and call sites exist at each level and
+/// which modules (directories) the callers live in, rather than building a
+/// full graph like `traverse --edge=uses` would.  This is intended for quick
+/// "blast radius" estimates on hot functions.
+///
+/// Symbols can be provided explicitly via `-s` or piped in as a
+/// `SymbolCrossrefInfoList`.
+#[derive(Debug, Args)]
+pub struct CallerRollup {
+    /// Explicit symbols to roll up the callers of.
+    #[clap(short, long, value_parser)]
+    symbol: Vec<String>,
+
+    /// How many levels of callers to aggregate.  Level 1 is the direct callers
+    /// of the symbols, level 2 is the callers of those callers, etc.
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..=8), default_value = "2")]
+    levels: u32,
+
+    /// How many of the most popular caller modules to report per level.
+    #[clap(long, value_parser, default_value = "10")]
+    top: usize,
+
+    /// Maximum number of new callers per level whose crossref data we will
+    /// look up in order to find the next level of callers.  Counts for the
+    /// level itself are not affected by this limit.
+    #[clap(long, value_parser = clap::value_parser!(u32).range(16..=16384), default_value = "1024")]
+    level_node_limit: u32,
+}
+
+#[derive(Debug)]
+pub struct CallerRollupCommand {
+    pub args: CallerRollup,
+}
+
+#[derive(Serialize)]
+struct ModuleRollup {
+    module: Ustr,
+    /// Number of distinct callers defined in this module at this level.
+    callers: u32,
+    /// Number of call sites in this module at this level.
+    call_sites: u32,
+}
+
+#[derive(Serialize)]
+struct LevelRollup {
+    level: u32,
+    /// Number of distinct callers first seen at this level.
+    callers: u32,
+    /// Number of call sites for the symbols of the previous level.
+    call_sites: u32,
+    /// The `top` modules ordered by descending caller count.
+    top_modules: Vec<ModuleRollup>,
+    /// The total number of modules callers were seen in.
+    module_count: u32,
+    overloads_hit: Vec<OverloadInfo>,
+}
+
+#[derive(Serialize)]
+struct CallerRollupResult {
+    roots: Vec<Ustr>,
+    unknown_symbols: Vec<String>,
+    levels: Vec<LevelRollup>,
+}
+
+/// Map a path to the "module" we report it as belonging to, which is just its
+/// containing directory.
+fn path_to_module(path: &str) -> Ustr {
+    match path.rfind('/') {
+        Some(offset) => ustr(&path[0..offset]),
+        None => ustr(""),
+    }
+}
+
+#[async_trait]
+impl PipelineCommand for CallerRollupCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        let mut roots = vec![];
+        let mut unknown_symbols = vec![];
+        // The set of symbols whose uses we will process for the next level.
+        let mut frontier: Vec<CrossrefRecord> = vec![];
+
+        match input {
+            PipelineValues::SymbolCrossrefInfoList(cil) => {
+                unknown_symbols = cil.unknown_symbols;
+                for info in cil.symbol_crossref_infos {
+                    roots.push(info.symbol);
+                    frontier.push(info.crossref_info);
+                }
+            }
+            PipelineValues::Void => {
+                for symbol in &self.args.symbol {
+                    // As in crossref-lookup, we only mint a Ustr once we know
+                    // the symbol actually exists.
+                    let info = server.crossref_lookup(symbol, false).await?;
+                    if info.is_null() {
+                        unknown_symbols.push(symbol.clone());
+                        continue;
+                    }
+                    let sym = ustr(symbol);
+                    roots.push(sym);
+                    frontier.push(CrossrefRecord::from_value(info)?);
+                }
+            }
+            _ => {
+                return Err(ServerError::StickyProblem(ErrorDetails {
+                    layer: ErrorLayer::ConfigLayer,
+                    message: "caller-rollup needs a Void or SymbolCrossrefInfoList".to_string(),
+                }));
+            }
+        }
+
+        // Callers are only counted at the first level we see them at so that
+        // recursion and mutual recursion don't inflate deeper levels.
+        let mut seen: HashSet<Ustr> = roots.iter().cloned().collect();
+        let mut levels = vec![];
+
+        for level in 1..=self.args.levels {
+            if frontier.is_empty() {
+                break;
+            }
+
+            let mut call_sites = 0;
+            let mut new_callers: Vec<Ustr> = vec![];
+            let mut by_module: BTreeMap<Ustr, ModuleRollup> = BTreeMap::new();
+
+            for crossref_info in frontier.drain(..) {
+                for path_hits in &crossref_info.uses {
+                    let module = path_to_module(&path_hits.path);
+                    for hit in &path_hits.lines {
+                        if hit.contextsym.is_empty() {
+                            continue;
+                        }
+                        call_sites += 1;
+                        let module_rollup =
+                            by_module.entry(module).or_insert_with(|| ModuleRollup {
+                                module,
+                                callers: 0,
+                                call_sites: 0,
+                            });
+                        module_rollup.call_sites += 1;
+
+                        let caller = hit.contextsym;
+                        if seen.insert(caller) {
+                            module_rollup.callers += 1;
+                            new_callers.push(caller);
+                        }
+                    }
+                }
+            }
+
+            let mut overloads_hit = vec![];
+            // Only bother looking up the next level's data if there is a next
+            // level.
+            if level < self.args.levels {
+                let limit = self.args.level_node_limit as usize;
+                if new_callers.len() > limit {
+                    record_overload(
+                        &mut overloads_hit,
+                        "caller-rollup",
+                        OverloadInfo {
+                            kind: OverloadKind::NodeLimit,
+                            sym: None,
+                            exist: new_callers.len() as u32,
+                            included: self.args.level_node_limit,
+                            local_limit: self.args.level_node_limit,
+                            global_limit: 0,
+                        },
+                    );
+                }
+                let mut progress = ProgressTracker::new(
+                    "caller-rollup",
+                    Some(std::cmp::min(new_callers.len(), limit) as u64),
+                );
+                for caller in new_callers.iter().take(limit) {
+                    let info = server.crossref_lookup(caller, false).await?;
+                    if !info.is_null() {
+                        frontier.push(CrossrefRecord::from_value(info)?);
+                    }
+                    progress.advance(1);
+                }
+                progress.finish();
+            }
+
+            let module_count = by_module.len() as u32;
+            let mut top_modules: Vec<ModuleRollup> = by_module.into_values().collect();
+            // Sort by descending caller count, then call sites; the BTreeMap
+            // already gave us a stable path ordering for ties.
+            top_modules.sort_by(|a, b| {
+                b.callers
+                    .cmp(&a.callers)
+                    .then_with(|| b.call_sites.cmp(&a.call_sites))
+            });
+            top_modules.truncate(self.args.top);
+
+            levels.push(LevelRollup {
+                level,
+                callers: new_callers.len() as u32,
+                call_sites,
+                top_modules,
+                module_count,
+                overloads_hit,
+            });
+        }
+
+        Ok(PipelineValues::JsonValue(JsonValue {
+            value: to_value(CallerRollupResult {
+                roots,
+                unknown_symbols,
+                levels,
+            })?,
+        }))
+    }
+}
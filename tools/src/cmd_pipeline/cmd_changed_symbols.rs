@@ -0,0 +1,326 @@
+use std::{
+    cmp::Ordering,
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    iter::Peekable,
+    path::Path,
+};
+
+use async_trait::async_trait;
+use clap::{Args, ValueEnum};
+use serde::Serialize;
+use serde_json::{to_value, Value};
+use ustr::Ustr;
+
+use super::{
+    interface::{JsonValue, JsonValueList, PipelineCommand, PipelineValues},
+    progress::ProgressTracker,
+};
+
+use crate::{
+    abstract_server::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError},
+    file_format::{
+        crossref_lookup::{CrossrefLookupIter, CrossrefLookupMap},
+        crossref_record::CrossrefRecord,
+    },
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum SymbolChange {
+    Added,
+    Removed,
+    Moved,
+    SignatureChanged,
+}
+
+/// List the symbols whose definitions changed between two index runs by
+/// diffing the crossref databases of the two index directories, for "what
+/// changed in today's index" reports.
+///
+/// Only symbols with definitions are considered.  A symbol is `added` or
+/// `removed` if only one of the indexes defines it, `moved` if its definitions
+/// are in different files, and `signature-changed` if its signature hash
+/// differs, which takes precedence over `moved`.  The signature hash covers
+/// the symbol's kind, its type, and the text of its definition lines.  Since
+/// any edit shifts the lines of all the definitions after it, definitions that
+/// only moved within their file aren't reported unless `--line-moves` is
+/// specified.
+///
+/// Produces a `JsonValueList` of `{ sym, pretty, kind, change, old, new }` in
+/// symbol order, where `old` and `new` are the `{ path, lineno }` of the
+/// symbol's first definition in each index, if it has one.
+///
+/// This reads the given index directories and ignores the index it's run
+/// against.
+#[derive(Debug, Args)]
+pub struct ChangedSymbols {
+    /// The older index directory, which must contain `crossref` and
+    /// `crossref-extra`.
+    #[clap(long, value_parser)]
+    old: String,
+
+    /// The newer index directory.
+    #[clap(long, value_parser)]
+    new: String,
+
+    /// Only report these changes.  All of them are reported if omitted.
+    #[clap(long, value_enum)]
+    change: Vec<SymbolChange>,
+
+    /// Also report symbols whose definitions moved to other lines of the same
+    /// file.
+    #[clap(long, action)]
+    line_moves: bool,
+}
+
+#[derive(Debug)]
+pub struct ChangedSymbolsCommand {
+    pub args: ChangedSymbols,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+struct DefLocation {
+    path: Ustr,
+    lineno: u32,
+}
+
+/// What we compare about a symbol's definitions.
+#[derive(Debug)]
+struct DefSummary {
+    pretty: Ustr,
+    kind: Option<Ustr>,
+    /// All the definition locations, sorted.
+    defs: Vec<(Ustr, u32)>,
+    signature_hash: u64,
+}
+
+impl DefSummary {
+    /// Summarize the symbol's crossref value, returning None if the symbol has
+    /// no definitions.
+    fn from_value(sym: &str, value: Value) -> Result<Option<DefSummary>> {
+        if value.is_null() {
+            return Ok(None);
+        }
+        let record = CrossrefRecord::from_value(value)?;
+        let mut defs = vec![];
+        let mut def_lines = vec![];
+        for path_hits in &record.defs {
+            for hit in &path_hits.lines {
+                defs.push((path_hits.path, hit.lineno));
+                def_lines.push(hit.line.trim().to_string());
+            }
+        }
+        if defs.is_empty() {
+            return Ok(None);
+        }
+        defs.sort();
+        def_lines.sort();
+
+        let meta = record.meta.as_ref();
+        let kind = meta.map(|meta| meta.kind);
+        let mut hasher = DefaultHasher::new();
+        kind.hash(&mut hasher);
+        meta.and_then(|meta| meta.type_pretty).hash(&mut hasher);
+        def_lines.hash(&mut hasher);
+
+        Ok(Some(DefSummary {
+            pretty: record.pretty().unwrap_or_else(|| ustr::ustr(sym)),
+            kind,
+            defs,
+            signature_hash: hasher.finish(),
+        }))
+    }
+
+    fn first_def(&self) -> DefLocation {
+        let (path, lineno) = self.defs[0];
+        DefLocation { path, lineno }
+    }
+}
+
+#[derive(Serialize)]
+struct ChangedSymbol {
+    sym: String,
+    pretty: Ustr,
+    kind: Option<Ustr>,
+    change: SymbolChange,
+    old: Option<DefLocation>,
+    new: Option<DefLocation>,
+}
+
+/// Classify how the symbol's definitions changed, if they did.
+fn classify_change(
+    old: Option<&DefSummary>,
+    new: Option<&DefSummary>,
+    line_moves: bool,
+) -> Option<SymbolChange> {
+    match (old, new) {
+        (None, None) => None,
+        (None, Some(_)) => Some(SymbolChange::Added),
+        (Some(_), None) => Some(SymbolChange::Removed),
+        (Some(old), Some(new)) => {
+            if old.signature_hash != new.signature_hash {
+                return Some(SymbolChange::SignatureChanged);
+            }
+            let moved = if line_moves {
+                old.defs != new.defs
+            } else {
+                let paths = |summary: &DefSummary| -> Vec<Ustr> {
+                    let mut paths: Vec<Ustr> = summary.defs.iter().map(|(path, _)| *path).collect();
+                    paths.dedup();
+                    paths
+                };
+                paths(old) != paths(new)
+            };
+            if moved {
+                Some(SymbolChange::Moved)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+fn open_crossref(dir: &str) -> Result<CrossrefLookupMap> {
+    let inline_path = Path::new(dir).join("crossref");
+    let extra_path = Path::new(dir).join("crossref-extra");
+    let map = if inline_path.exists() && extra_path.exists() {
+        CrossrefLookupMap::new(
+            &inline_path.to_string_lossy(),
+            &extra_path.to_string_lossy(),
+        )
+    } else {
+        None
+    };
+    map.ok_or_else(|| {
+        ServerError::StickyProblem(ErrorDetails {
+            layer: ErrorLayer::ConfigLayer,
+            message: format!("{} has no crossref database", dir),
+        })
+    })
+}
+
+fn next_entry(iter: &mut Peekable<CrossrefLookupIter>) -> Result<Option<(String, Value)>> {
+    iter.next().transpose()
+}
+
+#[async_trait]
+impl PipelineCommand for ChangedSymbolsCommand {
+    async fn execute(
+        &self,
+        _server: &(dyn AbstractServer + Send + Sync),
+        input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        if !matches!(input, PipelineValues::Void) {
+            return Err(ServerError::StickyProblem(ErrorDetails {
+                layer: ErrorLayer::ConfigLayer,
+                message: "changed-symbols needs Void".to_string(),
+            }));
+        }
+
+        let old_map = open_crossref(&self.args.old)?;
+        let new_map = open_crossref(&self.args.new)?;
+        let mut old_iter = old_map.iter().peekable();
+        let mut new_iter = new_map.iter().peekable();
+
+        // Both crossref databases are sorted by symbol, so we can walk them in
+        // lockstep.
+        let mut values = vec![];
+        let mut progress = ProgressTracker::new("changed-symbols", None);
+        loop {
+            let order = match (old_iter.peek(), new_iter.peek()) {
+                (None, None) => break,
+                (Some(Ok((old_sym, _))), Some(Ok((new_sym, _)))) => Some(old_sym.cmp(new_sym)),
+                // Either one side is exhausted or has an error, which surfaces
+                // below.
+                _ => None,
+            };
+            let (old, new) = match order {
+                Some(Ordering::Less) => (next_entry(&mut old_iter)?, None),
+                Some(Ordering::Greater) => (None, next_entry(&mut new_iter)?),
+                _ => (next_entry(&mut old_iter)?, next_entry(&mut new_iter)?),
+            };
+            progress.advance(1);
+
+            let sym = match old.as_ref().or(new.as_ref()) {
+                Some((sym, _)) => sym.clone(),
+                None => continue,
+            };
+            let old = match old {
+                Some((_, value)) => DefSummary::from_value(&sym, value)?,
+                None => None,
+            };
+            let new = match new {
+                Some((_, value)) => DefSummary::from_value(&sym, value)?,
+                None => None,
+            };
+
+            let change = match classify_change(old.as_ref(), new.as_ref(), self.args.line_moves) {
+                Some(change) => change,
+                None => continue,
+            };
+            if !self.args.change.is_empty() && !self.args.change.contains(&change) {
+                continue;
+            }
+            let latest = new.as_ref().or(old.as_ref()).unwrap();
+            values.push(JsonValue {
+                value: to_value(ChangedSymbol {
+                    pretty: latest.pretty,
+                    kind: latest.kind,
+                    sym,
+                    change,
+                    old: old.as_ref().map(DefSummary::first_def),
+                    new: new.as_ref().map(DefSummary::first_def),
+                })?,
+            });
+        }
+        progress.finish();
+
+        Ok(PipelineValues::JsonValueList(JsonValueList { values }))
+    }
+}
+
+#[test]
+fn test_classify_change() {
+    use serde_json::json;
+
+    let summary = |path: &str, lno: u32, line: &str| {
+        DefSummary::from_value(
+            "F_Foo",
+            json!({"defs": [{"path": path, "path_kind": "Normal", "lines": [
+                {"lno": lno, "bounds": [0, 3], "line": line, "context": "", "contextsym": ""}
+            ]}]}),
+        )
+        .unwrap()
+        .unwrap()
+    };
+    let old = summary("a.cpp", 10, "void Foo(int aBar) {");
+
+    assert_eq!(
+        classify_change(None, Some(&old), false),
+        Some(SymbolChange::Added)
+    );
+    assert_eq!(
+        classify_change(Some(&old), None, false),
+        Some(SymbolChange::Removed)
+    );
+
+    let shifted = summary("a.cpp", 12, "void Foo(int aBar) {");
+    assert_eq!(classify_change(Some(&old), Some(&shifted), false), None);
+    assert_eq!(
+        classify_change(Some(&old), Some(&shifted), true),
+        Some(SymbolChange::Moved)
+    );
+
+    let moved = summary("b.cpp", 10, "void Foo(int aBar) {");
+    assert_eq!(
+        classify_change(Some(&old), Some(&moved), false),
+        Some(SymbolChange::Moved)
+    );
+
+    let changed = summary("b.cpp", 10, "void Foo(int aBar, bool aBaz) {");
+    assert_eq!(
+        classify_change(Some(&old), Some(&changed), false),
+        Some(SymbolChange::SignatureChanged)
+    );
+}
@@ -1,14 +1,15 @@
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 
 use async_trait::async_trait;
 use clap::Args;
-use serde_json::{from_value, Value};
+use serde_json::{to_value, Value};
 use ustr::{ustr, Ustr, UstrMap};
 
 use super::interface::{
-    FlattenedKindGroupResults, FlattenedLineSpan, FlattenedPathKindGroupResults,
-    FlattenedResultsBundle, FlattenedResultsByFile, PipelineJunctionCommand, PipelineValues,
-    PresentationKind, ResultFacetGroup, ResultFacetKind, ResultFacetRoot, SymbolCrossrefInfo,
+    AnnotatedSymbol, CollapseHint, CollapsedDirGroup, FlatResultHit, FlattenedKindGroupResults,
+    FlattenedLineSpan, FlattenedPathKindGroupResults, FlattenedResultsBundle,
+    FlattenedResultsByFile, PipelineJunctionCommand, PipelineValues, PresentationKind,
+    PrettyMentions, ResultFacetGroup, ResultFacetKind, ResultFacetRoot, SymbolCrossrefInfo,
     SymbolQuality, SymbolRelation,
 };
 
@@ -16,7 +17,10 @@ use crate::{
     abstract_server::{
         AbstractServer, ErrorDetails, ErrorLayer, FileMatch, Result, ServerError, TextMatchesByFile,
     },
-    file_format::analysis::PathSearchResult,
+    cmd_pipeline::transforms::{BugzillaComponentFilter, PathAliases, PathDemotion},
+    file_format::{
+        analysis::PathSearchResult, config::VIRTUAL_SYMBOL_PREFIX, per_file_info::TestAnnotations,
+    },
 };
 
 /// Process file, crossref, and fulltext search results into a classic
@@ -25,6 +29,14 @@ use crate::{
 /// by key/kind precedence (files, IDL, defs, override stuff, super/subclass
 /// stuff, assignments, uses, declarations, text matches), noting that
 /// precedences will likely change.
+///
+/// Path kind groups and the files within each kind group are ordered by the
+/// tree's `result_demotion` weights, with sufficiently demoted files moved to
+/// `collapsed_by_file` so the UI can put them in an expandable group.
+///
+/// Files reachable via multiple paths per the tree's `path_aliases` are
+/// reported once under their canonical path, with the other paths listed in
+/// `path_alternates`.
 #[derive(Debug, Args)]
 pub struct CompileResults {
     /// Maximum number of file results to list, truncating at the limit.
@@ -35,6 +47,37 @@ pub struct CompileResults {
     /// Context lines don't impact this limit.
     #[clap(short, long, value_parser, default_value = "2000")]
     line_limit: usize,
+
+    /// Only include results in files whose Bugzilla component matches, ex:
+    /// "Core::DOM: Core & HTML".  A bare product like "Core" is also accepted.
+    #[clap(long, value_parser)]
+    component: Option<String>,
+
+    /// Only include results in test files whose test manifest skips them on
+    /// this OS in at least some configurations, ex: "win".
+    #[clap(long, value_parser)]
+    test_disabled_on: Option<String>,
+
+    /// Kind groups with more files than this get a `collapse_hint` telling the
+    /// UI to only expand the first `--collapse-hint-expanded` files and group
+    /// the rest by top-level directory.  0 disables the hints.
+    #[clap(long, value_parser, default_value = "100")]
+    collapse_hint_threshold: usize,
+
+    /// How many files a kind group with a `collapse_hint` shows expanded.
+    #[clap(long, value_parser, default_value = "20")]
+    collapse_hint_expanded: usize,
+
+    /// Also include every line hit as a flat `flat` list of (path, line, kind,
+    /// symbol, pretty) alongside the grouped results.
+    #[clap(long, value_parser)]
+    also_flat: bool,
+
+    /// Definition hits whose peek range spans more lines than this have the
+    /// interior of the range folded, keeping the lines through the one after
+    /// the definition and the last line visible.  0 disables folding.
+    #[clap(long, value_parser, default_value = "0")]
+    fold_spans_over: u32,
 }
 
 /// Core result processing logic / helper data-structures most analogous to the
@@ -95,6 +138,79 @@ pub struct SearchResults {
     /// Every key_line gets added to this set like `{path}:{key_line}` to
     /// suppress redundant hits on the line (from fulltext matches).
     pub path_line_suppressions: HashSet<String>,
+    /// Bugzilla (product, component) for every path we might see, populated by
+    /// `populate_path_components` prior to ingestion so that the ingestion
+    /// logic doesn't need access to the server.  Paths without a component
+    /// will not be present.
+    pub path_components: UstrMap<(Ustr, Ustr)>,
+    pub component_filter: Option<BugzillaComponentFilter>,
+    /// Test manifest annotations for every test file we might see, populated
+    /// alongside `path_components`.
+    pub path_test_annotations: UstrMap<TestAnnotations>,
+    pub test_disabled_on: Option<String>,
+    /// Used by `compile` to order and collapse results; the default doesn't
+    /// demote anything.
+    pub demotion: PathDemotion,
+    /// Kind groups with more files than this get a `CollapseHint`; 0 (the
+    /// default) disables the hints.
+    pub collapse_hint_threshold: usize,
+    pub collapse_hint_expanded: usize,
+    /// Every line hit we keep, if the flat representation was requested.
+    pub flat_hits: Option<Vec<FlatResultHit>>,
+    /// Spans covering more lines than this get their interior folded; 0 (the
+    /// default) disables folding.
+    pub fold_spans_over: u32,
+    /// Used to fold aliased paths into their canonical paths; the default
+    /// doesn't alias anything.
+    pub path_aliases: PathAliases,
+    /// The canonical path and path kind for every aliased path we might see,
+    /// populated alongside `path_components`.  Canonical paths are not
+    /// present.
+    pub path_alias_targets: UstrMap<(Ustr, Ustr)>,
+    /// Paths that have already been checked against `path_aliases`.
+    pub path_alias_checked: HashSet<Ustr>,
+    /// The aliased paths whose results were folded into each canonical path.
+    pub path_alternates: BTreeMap<Ustr, BTreeSet<Ustr>>,
+    /// The first annotated symbol seen for each annotated pretty identifier.
+    pub annotations: BTreeMap<Ustr, AnnotatedSymbol>,
+}
+
+/// Compute the `CollapseHint` for a kind group's `by_file` if it has more than
+/// `threshold` files.
+fn compute_collapse_hint(
+    by_file: &[FlattenedResultsByFile],
+    threshold: usize,
+    expanded: usize,
+) -> Option<CollapseHint> {
+    if threshold == 0 || by_file.len() <= threshold {
+        return None;
+    }
+
+    let mut groups: BTreeMap<&str, CollapsedDirGroup> = BTreeMap::new();
+    for (i, results) in by_file.iter().enumerate().skip(expanded) {
+        let dir = match results.file.split_once('/') {
+            Some((dir, _)) => dir,
+            None => "",
+        };
+        let group = groups.entry(dir).or_insert_with(|| CollapsedDirGroup {
+            dir: dir.to_string(),
+            file_count: 0,
+            line_count: 0,
+            file_indices: vec![],
+        });
+        group.file_count += 1;
+        group.line_count += results.line_spans.len();
+        group.file_indices.push(i);
+    }
+
+    // The BTreeMap ordered the groups by directory, which the stable sort
+    // preserves for groups of the same size.
+    let mut dir_groups: Vec<CollapsedDirGroup> = groups.into_values().collect();
+    dir_groups.sort_by(|a, b| b.file_count.cmp(&a.file_count));
+    Some(CollapseHint {
+        expanded_count: expanded.min(by_file.len()),
+        dir_groups,
+    })
 }
 
 #[derive(Default)]
@@ -115,6 +231,8 @@ pub struct QualKindDescriptor {
 pub struct QualKindGroup {
     pub path_facet: MaybeFacetRoot,
     pub relation_facet: MaybeFacetRoot,
+    pub component_facet: MaybeFacetRoot,
+    pub test_status_facet: MaybeFacetRoot,
     pub path_hits: BTreeMap<Ustr, FlattenedResultsByFile>,
 }
 
@@ -123,12 +241,192 @@ impl QualKindGroup {
         QualKindGroup {
             path_facet: MaybeFacetRoot::new(ResultFacetKind::PathByPath),
             relation_facet: MaybeFacetRoot::new(ResultFacetKind::SymbolByRelation),
+            component_facet: MaybeFacetRoot::new(ResultFacetKind::PathByComponent),
+            test_status_facet: MaybeFacetRoot::new(ResultFacetKind::PathByTestStatus),
             path_hits: BTreeMap::new(),
         }
     }
 }
 
 impl SearchResults {
+    /// Load the tree's result demotion config.  Servers that don't expose it
+    /// get the default config.
+    pub fn load_demotion(&mut self, server: &(dyn AbstractServer + Send + Sync)) -> Result<()> {
+        let config = server.result_demotion_config().unwrap_or_default();
+        self.demotion = PathDemotion::new(&config)?;
+        Ok(())
+    }
+
+    /// Load the tree's path alias config.  Servers that don't expose it don't
+    /// alias anything.
+    pub fn load_path_aliases(&mut self, server: &(dyn AbstractServer + Send + Sync)) -> Result<()> {
+        let config = server.path_alias_config().unwrap_or_default();
+        self.path_aliases = PathAliases::new(&config)?;
+        Ok(())
+    }
+
+    /// Check and cache whether the given path is an alias and, if so, the
+    /// canonical path and its path kind.
+    fn populate_path_alias(
+        &mut self,
+        server: &(dyn AbstractServer + Send + Sync),
+        path: Ustr,
+        path_kind: Ustr,
+    ) {
+        if !self.path_alias_checked.insert(path) {
+            return;
+        }
+        if let Some(canonical) = self.path_aliases.canonical_path(&path) {
+            // Remote servers can't tell us the path kind of the canonical
+            // path, in which case we assume it's the same as the alias's.
+            let canonical_kind = match server.file_path_kind(&canonical) {
+                Ok(Some(kind)) => kind,
+                _ => path_kind,
+            };
+            self.path_alias_targets
+                .insert(path, (canonical, canonical_kind));
+            // The results get faceted under the canonical path.
+            self.populate_path_component(server, canonical);
+        }
+    }
+
+    /// Map an aliased path to its canonical path and path kind, recording the
+    /// alias as an alternate of the canonical path.  Other paths are returned
+    /// as-is.
+    fn resolve_path_alias(&mut self, path: Ustr, path_kind: Ustr) -> (Ustr, Ustr) {
+        match self.path_alias_targets.get(&path) {
+            Some(&(canonical, canonical_kind)) => {
+                self.path_alternates
+                    .entry(canonical)
+                    .or_default()
+                    .insert(path);
+                (canonical, canonical_kind)
+            }
+            None => (path, path_kind),
+        }
+    }
+
+    /// Look up and cache the Bugzilla component and test annotations for the
+    /// given path.
+    fn populate_path_component(&mut self, server: &(dyn AbstractServer + Send + Sync), path: Ustr) {
+        if self.path_components.contains_key(&path) {
+            return;
+        }
+        // Remote servers don't support these lookups, in which case we just
+        // don't know the component or annotations.
+        if let Ok(Some(component)) = server.file_bugzilla_component(&path) {
+            self.path_components.insert(path, component);
+        }
+        if let Ok(Some(annotations)) = server.file_test_annotations(&path) {
+            self.path_test_annotations.insert(path, annotations);
+        }
+    }
+
+    /// Walk all of the paths in the inputs so that `path_components` will be
+    /// populated for the ingestion methods to consult.
+    pub fn populate_path_components(
+        &mut self,
+        server: &(dyn AbstractServer + Send + Sync),
+        input: &[(String, PipelineValues)],
+    ) {
+        for (_, pipe_value) in input {
+            match pipe_value {
+                PipelineValues::FileMatches(fm) => {
+                    for file_match in &fm.file_matches {
+                        if let Some(component) = file_match.concise.bugzilla_component {
+                            self.path_components.insert(file_match.path, component);
+                        }
+                        if let Some(annotations) =
+                            TestAnnotations::from_concise_info(&file_match.concise.info)
+                        {
+                            self.path_test_annotations
+                                .insert(file_match.path, annotations);
+                        }
+                        self.populate_path_alias(
+                            server,
+                            file_match.path,
+                            file_match.concise.path_kind,
+                        );
+                    }
+                }
+                PipelineValues::SymbolCrossrefInfoList(scil) => {
+                    for info in &scil.symbol_crossref_infos {
+                        for (_kind, path_containers) in info.crossref_info.hit_kinds() {
+                            for path_container in path_containers {
+                                self.populate_path_component(server, path_container.path);
+                                self.populate_path_alias(
+                                    server,
+                                    path_container.path,
+                                    path_container.path_kind,
+                                );
+                            }
+                        }
+                    }
+                }
+                PipelineValues::TextMatches(tm) => {
+                    for file_match in &tm.by_file {
+                        self.populate_path_component(server, file_match.file);
+                        self.populate_path_alias(server, file_match.file, file_match.path_kind);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Should results from this path be included given the component and test
+    /// annotation filters?
+    fn path_passes_filter(&self, path: &Ustr) -> bool {
+        if let Some(filter) = &self.component_filter {
+            if !filter.matches(&self.path_components.get(path).cloned()) {
+                return false;
+            }
+        }
+        match &self.test_disabled_on {
+            Some(os) => self
+                .path_test_annotations
+                .get(path)
+                .map_or(false, |annotations| annotations.disabled_on(os)),
+            None => true,
+        }
+    }
+
+    /// Place the path in the component facet if we know its component.
+    fn place_component_facet_item(
+        path_components: &UstrMap<(Ustr, Ustr)>,
+        qual_kind_group: &mut QualKindGroup,
+        path: Ustr,
+    ) {
+        if let Some((product, component)) = path_components.get(&path) {
+            qual_kind_group
+                .component_facet
+                .place_item(vec![ustr(&format!("{}::", product)), *component], path);
+        }
+    }
+
+    /// Place the path in the test status facet if it's a known test file.
+    fn place_test_status_facet_item(
+        path_test_annotations: &UstrMap<TestAnnotations>,
+        qual_kind_group: &mut QualKindGroup,
+        path: Ustr,
+    ) {
+        if let Some(annotations) = path_test_annotations.get(&path) {
+            let skipped_oses = annotations.skipped_oses();
+            let label = if !skipped_oses.is_empty() {
+                format!("Skipped on {}", skipped_oses.join(", "))
+            } else if !annotations.skip_if.is_empty() {
+                "Conditionally skipped".to_string()
+            } else if !annotations.fail_if.is_empty() {
+                "Expected to fail".to_string()
+            } else {
+                "Enabled".to_string()
+            };
+            qual_kind_group
+                .test_status_facet
+                .place_item(vec![ustr(&label)], path);
+        }
+    }
+
     /// For each symbol we:
     /// - Figure out what identifier this symbol should be filed under based on
     ///   the `SymbolRelation`, and what "kinds" are applicable for line
@@ -151,6 +449,11 @@ impl SearchResults {
             static ref OVERRIDDEN_BY: Ustr = ustr("Overriden By");
             static ref OVERRIDES: Ustr = ustr("Overrides");
             static ref COUSIN_OVERRIDES: Ustr = ustr("Cousin Overrides");
+            static ref MACRO_EXPANSIONS: Ustr = ustr("Macro Expansions");
+            static ref SPECIALIZATIONS: Ustr = ustr("Specializations");
+            static ref VIRTUAL_MEMBERS: Ustr = ustr("Virtual Symbol Members");
+            static ref CLASS_MEMBERS: Ustr = ustr("Class Members");
+            static ref IDL_COUNTERPARTS: Ustr = ustr("IDL Bindings");
         }
 
         // There are other ways we could get this mapping like always baking the
@@ -169,7 +472,38 @@ impl SearchResults {
             SymbolRelation::OverrideOf(sym, _) => (*sym, &OVERRIDDEN_BY),
             SymbolRelation::OverriddenBy(sym, _) => (*sym, &OVERRIDES),
             SymbolRelation::CousinOverrideOf(sym, _) => (*sym, &COUSIN_OVERRIDES),
+            SymbolRelation::MacroExpansionOf(sym) => (*sym, &MACRO_EXPANSIONS),
+            SymbolRelation::SpecializationOf(sym) => (*sym, &SPECIALIZATIONS),
+            SymbolRelation::MemberOf(sym, _) => (*sym, &CLASS_MEMBERS),
+            SymbolRelation::IdlCounterpartOf(sym) => (*sym, &IDL_COUNTERPARTS),
+            SymbolRelation::VirtualMemberOf(sym) => {
+                // Virtual symbols have no crossref entry of their own, so the
+                // members get grouped under the group's name.
+                self.sym_to_pretty
+                    .entry(*sym)
+                    .or_insert_with(|| ustr(sym.trim_start_matches(VIRTUAL_SYMBOL_PREFIX)));
+                (*sym, &VIRTUAL_MEMBERS)
+            }
         };
+        if let Some(annotation) = &info.annotation {
+            let pretty = info.get_pretty();
+            self.annotations
+                .entry(pretty)
+                .or_insert_with(|| AnnotatedSymbol {
+                    pretty,
+                    symbol: info.symbol,
+                    annotation: annotation.clone(),
+                });
+        }
+
+        // For symbols produced by a macro expansion, what's interesting is
+        // where the expansion defined them; their uses are not uses of the
+        // macro.  Likewise, class members are only here to show the shape of
+        // the class.
+        let defs_only = matches!(
+            info.relation,
+            SymbolRelation::MacroExpansionOf(_) | SymbolRelation::MemberOf(_, _)
+        );
 
         let root_pretty = *self.sym_to_pretty.get(&root_sym).ok_or_else(|| {
             ServerError::StickyProblem(ErrorDetails {
@@ -178,49 +512,55 @@ impl SearchResults {
             })
         })?;
 
-        if let Value::Object(obj) = info.crossref_info {
-            // This generic traversal is currently somewhat required because we
-            // have different shapes for "meta", "callees", and everything else
-            // (the kinds).  It could make sense to normalize the schema by not
-            // having everything at the top-level.  (If doing that, it might
-            // also be worth re-thinking other aspects of storage if it lets us
-            // be lazier about parsing, etc.)
-            for (kind, val) in obj.into_iter() {
-                let pkind = match kind.as_str() {
-                    "idl" => PresentationKind::IDL,
-                    "defs" => PresentationKind::Definitions,
-                    "decls" => PresentationKind::Declarations,
-                    "assignments" => PresentationKind::Assignments,
-                    "uses" => PresentationKind::Uses,
-                    "meta" => {
-                        // We save off the meta for this symbol for the UI.
-                        self.sym_to_meta.insert(info.symbol, val);
-                        continue;
-                    }
-                    // We expect this to match:
-                    // - "callees": This is used only for call-graph stuff and is
-                    //   something a human can learn from just looking at the
-                    //   contents of a given method/symbol, etc.
-                    _ => {
-                        continue;
-                    }
-                };
+        let mut crossref_info = info.crossref_info;
+        // We save off the meta for this symbol for the UI.
+        if let Some(meta) = crossref_info.meta.take() {
+            self.sym_to_meta.insert(info.symbol, to_value(meta)?);
+        } else if let Some(meta) = crossref_info.extra.remove("meta") {
+            self.sym_to_meta.insert(info.symbol, meta);
+        }
+        // And mark symbols that are only declared in this tree so the UI can
+        // distinguish them from the symbols it can jump to definitions of.
+        if info.declaration_only {
+            if let Some(Value::Object(meta)) = self.sym_to_meta.get_mut(&info.symbol) {
+                meta.insert("declarationOnly".to_string(), Value::Bool(true));
+            }
+        }
+        // Class members are listed with their offset within the queried class,
+        // which may differ from their offset within the class declaring them.
+        if let SymbolRelation::MemberOf(_, offset) = info.relation {
+            if let Some(Value::Object(meta)) = self.sym_to_meta.get_mut(&info.symbol) {
+                meta.insert("memberOffsetBytes".to_string(), Value::from(offset));
+            }
+        }
 
-                let descriptor = QualKindDescriptor {
-                    kind: pkind,
-                    quality: info.quality.clone(),
-                    pretty: root_pretty,
-                };
+        // Other kinds like "callees" are used only for call-graph stuff and are
+        // something a human can learn from just looking at the contents of a
+        // given method/symbol, etc.
+        for (pkind, path_containers) in [
+            (PresentationKind::Uses, crossref_info.uses),
+            (PresentationKind::Definitions, crossref_info.defs),
+            (PresentationKind::Assignments, crossref_info.assignments),
+            (PresentationKind::Declarations, crossref_info.decls),
+            (PresentationKind::IDL, crossref_info.idl),
+        ] {
+            if defs_only && pkind != PresentationKind::Definitions {
+                continue;
+            }
 
-                let path_containers: Vec<PathSearchResult> = from_value(val)?;
-                for path_container in path_containers {
-                    self.ingest_path_hits(
-                        &info.symbol,
-                        descriptor.clone(),
-                        relation_facet,
-                        path_container,
-                    );
-                }
+            let descriptor = QualKindDescriptor {
+                kind: pkind,
+                quality: info.quality.clone(),
+                pretty: root_pretty,
+            };
+
+            for path_container in path_containers {
+                self.ingest_path_hits(
+                    &info.symbol,
+                    descriptor.clone(),
+                    relation_facet,
+                    path_container,
+                );
             }
         }
 
@@ -234,18 +574,22 @@ impl SearchResults {
         relation_facet: &Ustr,
         path_container: PathSearchResult,
     ) {
-        let path_kind_group = self
-            .path_kind_groups
-            .entry(path_container.path_kind)
-            .or_default();
+        if !self.path_passes_filter(&path_container.path) {
+            return;
+        }
+        let (kind, pretty) = (descriptor.kind.clone(), descriptor.pretty);
+        let (path, path_kind) =
+            self.resolve_path_alias(path_container.path, path_container.path_kind);
+
+        let path_kind_group = self.path_kind_groups.entry(path_kind).or_default();
         let qual_kind_group = path_kind_group
             .qual_kind_groups
             .entry(descriptor)
             .or_insert_with(QualKindGroup::new);
 
         // ### path faceting
-        let path_sans_filename = match path_container.path.rfind('/') {
-            Some(offset) => ustr(&path_container.path[0..offset + 1]),
+        let path_sans_filename = match path.rfind('/') {
+            Some(offset) => ustr(&path[0..offset + 1]),
             None => ustr(""),
         };
         let mut path_pieces: Vec<Ustr> =
@@ -261,14 +605,19 @@ impl SearchResults {
             .relation_facet
             .place_item(vec![*relation_facet], *sym);
 
+        // ### component faceting
+        Self::place_component_facet_item(&self.path_components, qual_kind_group, path);
+        Self::place_test_status_facet_item(&self.path_test_annotations, qual_kind_group, path);
+
         // ### line results
-        let file_results = qual_kind_group
-            .path_hits
-            .entry(path_container.path)
-            .or_insert_with(|| FlattenedResultsByFile {
-                file: path_container.path,
-                line_spans: vec![],
-            });
+        let file_results =
+            qual_kind_group
+                .path_hits
+                .entry(path)
+                .or_insert_with(|| FlattenedResultsByFile {
+                    file: path,
+                    line_spans: vec![],
+                });
         for search_result in path_container.lines {
             // Path-line suppressions exist to avoid redundant fulltext matches
             // showing up, so we don't actually care if there already was
@@ -285,8 +634,17 @@ impl SearchResults {
             // gets mapped down to a single token, but in that case we already
             // should have merged all of those redundant same-symbols.
             self.path_line_suppressions
-                .insert(format!("{}:{}", path_container.path, search_result.lineno));
-            file_results.line_spans.push(FlattenedLineSpan {
+                .insert(format!("{}:{}", path, search_result.lineno));
+            if let Some(flat_hits) = &mut self.flat_hits {
+                flat_hits.push(FlatResultHit {
+                    path,
+                    line: search_result.lineno,
+                    kind: kind.clone(),
+                    symbol: Some(*sym),
+                    pretty: Some(pretty),
+                });
+            }
+            let mut span = FlattenedLineSpan {
                 key_line: search_result.lineno,
                 line_range: if search_result.peek_range.is_empty() {
                     (search_result.lineno, search_result.lineno)
@@ -299,34 +657,66 @@ impl SearchResults {
                 contents: search_result.line,
                 context: search_result.context,
                 contextsym: search_result.contextsym,
-            });
+                coverage: None,
+                folds: vec![],
+            };
+            if self.fold_spans_over > 0
+                && span.line_range.1 - span.line_range.0 + 1 > self.fold_spans_over
+            {
+                span.fold_interior(1, 1);
+            }
+            file_results.line_spans.push(span);
         }
     }
 
     pub fn ingest_file_match_hits(&mut self, file_matches: Vec<FileMatch>) {
         for file_match in file_matches {
-            let path_kind_group = self
-                .path_kind_groups
-                .entry(file_match.concise.path_kind)
-                .or_default();
-            path_kind_group.file_names.push(file_match.path);
+            if !self.path_passes_filter(&file_match.path) {
+                continue;
+            }
+            let (path, path_kind) =
+                self.resolve_path_alias(file_match.path, file_match.concise.path_kind);
+            let path_kind_group = self.path_kind_groups.entry(path_kind).or_default();
+            path_kind_group.file_names.push(path);
         }
     }
 
     pub fn ingest_fulltext_hits(&mut self, matches_by_file: Vec<TextMatchesByFile>) {
+        self.ingest_text_hits(matches_by_file, PresentationKind::TextualOccurrences, None);
+    }
+
+    /// Mentions get a kind group per pretty name, like symbols do.  They are
+    /// ingested after the symbols so that a line that's already a semantic
+    /// hit isn't also listed as a mention.
+    pub fn ingest_mentions(&mut self, by_pretty: Vec<PrettyMentions>) {
+        for mentions in by_pretty {
+            self.ingest_text_hits(
+                mentions.by_file,
+                PresentationKind::Mentions,
+                Some(mentions.pretty),
+            );
+        }
+    }
+
+    fn ingest_text_hits(
+        &mut self,
+        matches_by_file: Vec<TextMatchesByFile>,
+        kind: PresentationKind,
+        pretty: Option<Ustr>,
+    ) {
         let descriptor = QualKindDescriptor {
-            kind: PresentationKind::TextualOccurrences,
+            kind: kind.clone(),
             // The quality doesn't matter; there's only one class of text matches.
             quality: SymbolQuality::ExplicitSymbol,
-            pretty: ustr(""),
+            pretty: pretty.unwrap_or_else(|| ustr("")),
         };
 
         for file_match in matches_by_file {
-            let path = file_match.file;
-            let path_kind_group = self
-                .path_kind_groups
-                .entry(file_match.path_kind)
-                .or_default();
+            if !self.path_passes_filter(&file_match.file) {
+                continue;
+            }
+            let (path, path_kind) = self.resolve_path_alias(file_match.file, file_match.path_kind);
+            let path_kind_group = self.path_kind_groups.entry(path_kind).or_default();
             let qual_kind_group = path_kind_group
                 .qual_kind_groups
                 .entry(descriptor.clone())
@@ -346,12 +736,23 @@ impl SearchResults {
                     .path_line_suppressions
                     .insert(format!("{}:{}", path, text_match.line_num))
                 {
+                    if let Some(flat_hits) = &mut self.flat_hits {
+                        flat_hits.push(FlatResultHit {
+                            path,
+                            line: text_match.line_num,
+                            kind: kind.clone(),
+                            symbol: None,
+                            pretty,
+                        });
+                    }
                     file_results.line_spans.push(FlattenedLineSpan {
                         key_line: text_match.line_num,
                         line_range: (text_match.line_num, text_match.line_num),
                         contents: text_match.line_str,
                         context: ustr(""),
                         contextsym: ustr(""),
+                        coverage: None,
+                        folds: vec![],
                     });
                 }
             }
@@ -376,13 +777,21 @@ impl SearchResults {
                 qual_kind_group
                     .path_facet
                     .place_item(path_pieces, path_sans_filename);
+                Self::place_component_facet_item(&self.path_components, qual_kind_group, path);
+                Self::place_test_status_facet_item(
+                    &self.path_test_annotations,
+                    qual_kind_group,
+                    path,
+                );
             }
         }
     }
 
     pub fn compile(self, _file_limit: usize, _line_limit: usize) -> FlattenedResultsBundle {
-        let mut path_kind_results = vec![];
-        for (path_kind, pk_group) in self.path_kind_groups {
+        let demotion = self.demotion;
+        let mut weighted_path_kind_results = vec![];
+        for (path_kind, mut pk_group) in self.path_kind_groups {
+            let kind_weight = demotion.path_kind_weight(&path_kind);
             let mut kind_groups = vec![];
             for (descriptor, qk_group) in pk_group.qual_kind_groups {
                 let mut facets = vec![];
@@ -393,6 +802,12 @@ impl SearchResults {
                 if let Some(facet) = qk_group.path_facet.compile() {
                     facets.push(facet);
                 }
+                if let Some(facet) = qk_group.component_facet.compile() {
+                    facets.push(facet);
+                }
+                if let Some(facet) = qk_group.test_status_facet.compile() {
+                    facets.push(facet);
+                }
 
                 let mut by_file: Vec<FlattenedResultsByFile> =
                     qk_group.path_hits.into_values().collect();
@@ -400,26 +815,99 @@ impl SearchResults {
                 // so we sort them now.
                 for results in by_file.iter_mut() {
                     results.line_spans.sort_by_key(|x| x.line_range);
+                    // Files with alternates can have the same hit once per
+                    // path.
+                    if self.path_alternates.contains_key(&results.file) {
+                        results.line_spans.dedup_by(|a, b| {
+                            a.key_line == b.key_line
+                                && a.line_range == b.line_range
+                                && a.contents == b.contents
+                        });
+                    }
                 }
+                // Files are in path order; a stable sort keeps that order for
+                // files with the same weight.
+                by_file.sort_by(|a, b| {
+                    demotion
+                        .path_weight(&b.file)
+                        .total_cmp(&demotion.path_weight(&a.file))
+                });
+                let (collapsed_by_file, by_file) = by_file.into_iter().partition(|results| {
+                    demotion.should_collapse(kind_weight * demotion.path_weight(&results.file))
+                });
+
+                let collapse_hint = compute_collapse_hint(
+                    &by_file,
+                    self.collapse_hint_threshold,
+                    self.collapse_hint_expanded,
+                );
 
                 kind_groups.push(FlattenedKindGroupResults {
                     kind: descriptor.kind,
                     pretty: descriptor.pretty,
                     facets,
                     by_file,
+                    collapsed_by_file,
+                    collapse_hint,
                 });
             }
 
-            path_kind_results.push(FlattenedPathKindGroupResults {
-                path_kind,
-                file_names: pk_group.file_names,
-                kind_groups,
-            });
+            // Aliases of the same file will have been mapped to the same
+            // canonical path.
+            let mut seen_file_names = HashSet::new();
+            pk_group
+                .file_names
+                .retain(|path| seen_file_names.insert(*path));
+            pk_group
+                .file_names
+                .sort_by(|a, b| demotion.path_weight(b).total_cmp(&demotion.path_weight(a)));
+
+            weighted_path_kind_results.push((
+                kind_weight,
+                FlattenedPathKindGroupResults {
+                    path_kind,
+                    collapsed: demotion.should_collapse(kind_weight),
+                    file_names: pk_group.file_names,
+                    kind_groups,
+                },
+            ));
         }
 
+        // Path kind groups are heaviest first, falling back to the path kind
+        // name so that the order is deterministic.
+        weighted_path_kind_results.sort_by(|(a_weight, a), (b_weight, b)| {
+            b_weight
+                .total_cmp(a_weight)
+                .then_with(|| a.path_kind.cmp(&b.path_kind))
+        });
+        let path_kind_results = weighted_path_kind_results
+            .into_iter()
+            .map(|(_, results)| results)
+            .collect();
+
+        let flat = self.flat_hits.map(|mut flat_hits| {
+            flat_hits.sort_by(|a, b| {
+                a.path
+                    .cmp(&b.path)
+                    .then_with(|| a.line.cmp(&b.line))
+                    .then_with(|| a.kind.cmp(&b.kind))
+            });
+            flat_hits
+        });
+
         FlattenedResultsBundle {
             path_kind_results,
             content_type: "text/plain".to_string(),
+            context_lines: None,
+            suggestions: vec![],
+            query_plan: None,
+            flat,
+            path_alternates: self
+                .path_alternates
+                .into_iter()
+                .map(|(path, alternates)| (path, alternates.into_iter().collect()))
+                .collect(),
+            annotations: self.annotations.into_values().collect(),
         }
     }
 }
@@ -468,6 +956,8 @@ impl MaybeFacetRoot {
         let (label, clump_thresh, other) = match self.kind {
             ResultFacetKind::SymbolByRelation => ("Relation".to_string(), 0, None),
             ResultFacetKind::PathByPath => ("Path".to_string(), 3, Some("*".to_string())),
+            ResultFacetKind::PathByComponent => ("Component".to_string(), 0, None),
+            ResultFacetKind::PathByTestStatus => ("Test status".to_string(), 0, None),
         };
         let (compiled, breadth) = self.root.compile("".to_string(), clump_thresh, other);
         if breadth > 1 {
@@ -674,14 +1164,34 @@ pub struct CompileResultsCommand {
 impl PipelineJunctionCommand for CompileResultsCommand {
     async fn execute(
         &self,
-        _server: &(dyn AbstractServer + Send + Sync),
+        server: &(dyn AbstractServer + Send + Sync),
         input: Vec<(String, PipelineValues)>,
     ) -> Result<PipelineValues> {
-        let mut results = SearchResults::default();
+        let mut results = SearchResults {
+            component_filter: self
+                .args
+                .component
+                .as_ref()
+                .map(|c| BugzillaComponentFilter::parse(c)),
+            test_disabled_on: self.args.test_disabled_on.clone(),
+            collapse_hint_threshold: self.args.collapse_hint_threshold,
+            collapse_hint_expanded: self.args.collapse_hint_expanded,
+            flat_hits: if self.args.also_flat {
+                Some(vec![])
+            } else {
+                None
+            },
+            fold_spans_over: self.args.fold_spans_over,
+            ..Default::default()
+        };
+        results.load_demotion(server)?;
+        results.load_path_aliases(server)?;
+        results.populate_path_components(server, &input);
 
         // We currently don't care about the name of the input because we only
         // match by type, but one could imagine a scenario in which they serve
         // as labels we want to propagate.
+        let mut mentions = vec![];
         for (_, pipe_value) in input {
             match pipe_value {
                 PipelineValues::FileMatches(fm) => {
@@ -695,6 +1205,9 @@ impl PipelineJunctionCommand for CompileResultsCommand {
                 PipelineValues::TextMatches(tm) => {
                     results.ingest_fulltext_hits(tm.by_file);
                 }
+                PipelineValues::MentionMatches(mm) => {
+                    mentions.extend(mm.by_pretty);
+                }
                 _ => {
                     return Err(ServerError::StickyProblem(ErrorDetails {
                         layer: ErrorLayer::ConfigLayer,
@@ -703,9 +1216,107 @@ impl PipelineJunctionCommand for CompileResultsCommand {
                 }
             }
         }
+        results.ingest_mentions(mentions);
 
         let results_bundle = results.compile(self.args.file_limit, self.args.line_limit);
 
         Ok(PipelineValues::FlattenedResultsBundle(results_bundle))
     }
 }
+
+#[test]
+fn test_compute_collapse_hint() {
+    let file = |path: &str, lines: u32| FlattenedResultsByFile {
+        file: ustr(path),
+        line_spans: (1..=lines)
+            .map(|lno| FlattenedLineSpan {
+                key_line: lno,
+                line_range: (lno, lno),
+                contents: String::new(),
+                context: ustr(""),
+                contextsym: ustr(""),
+                coverage: None,
+                folds: vec![],
+            })
+            .collect(),
+    };
+    let by_file = vec![
+        file("dom/a.cpp", 1),
+        file("dom/b.cpp", 2),
+        file("layout/c.cpp", 1),
+        file("dom/d.cpp", 3),
+        file("moz.build", 1),
+    ];
+
+    assert_eq!(compute_collapse_hint(&by_file, 5, 1), None);
+    assert_eq!(compute_collapse_hint(&by_file, 0, 1), None);
+
+    let hint = compute_collapse_hint(&by_file, 4, 1).unwrap();
+    assert_eq!(hint.expanded_count, 1);
+    let groups: Vec<(&str, usize, usize, Vec<usize>)> = hint
+        .dir_groups
+        .iter()
+        .map(|g| {
+            (
+                g.dir.as_str(),
+                g.file_count,
+                g.line_count,
+                g.file_indices.clone(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        groups,
+        vec![
+            ("dom", 2, 5, vec![1, 3]),
+            ("", 1, 1, vec![4]),
+            ("layout", 1, 1, vec![2]),
+        ]
+    );
+}
+
+#[test]
+fn test_ingest_member_offset() {
+    use super::interface::SymbolMetaFlags;
+    use crate::file_format::crossref_record::CrossrefRecord;
+    use serde_json::json;
+
+    let info = |symbol: &str, relation: SymbolRelation, meta: Value| SymbolCrossrefInfo {
+        symbol: ustr(symbol),
+        crossref_info: CrossrefRecord::from_value(json!({ "meta": meta })).unwrap(),
+        relation,
+        quality: SymbolQuality::ExplicitSymbol,
+        overloads_hit: vec![],
+        flags: SymbolMetaFlags::default(),
+        declaration_only: false,
+        annotation: None,
+    };
+
+    let mut results = SearchResults::default();
+    results
+        .ingest_symbol(info(
+            "T_Derived",
+            SymbolRelation::Queried,
+            json!({ "structured": 1, "pretty": "Derived", "sym": "T_Derived", "kind": "class" }),
+        ))
+        .unwrap();
+    // The field is at offset 4 of its own class, which sits at offset 8 of
+    // the queried class.
+    results
+        .ingest_symbol(info(
+            "F_<T_Mixin>_mM",
+            SymbolRelation::MemberOf(ustr("T_Derived"), 12),
+            json!({
+                "structured": 1, "pretty": "Mixin::mM", "sym": "F_<T_Mixin>_mM", "kind": "field",
+            }),
+        ))
+        .unwrap();
+
+    assert_eq!(
+        results.sym_to_meta[&ustr("F_<T_Mixin>_mM")]["memberOffsetBytes"],
+        json!(12)
+    );
+    assert!(results.sym_to_meta[&ustr("T_Derived")]
+        .get("memberOffsetBytes")
+        .is_none());
+}
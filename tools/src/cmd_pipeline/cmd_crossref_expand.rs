@@ -1,17 +1,22 @@
-use std::collections::{HashSet, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 
 use async_trait::async_trait;
 use clap::Args;
-use serde_json::Value;
 use tracing::trace;
-use ustr::ustr;
+use ustr::Ustr;
 
-use super::interface::{
-    OverloadInfo, OverloadKind, PipelineCommand, PipelineValues, SymbolCrossrefInfo,
-    SymbolCrossrefInfoList, SymbolMetaFlags, SymbolRelation,
+use super::{
+    interface::{
+        OverloadInfo, OverloadKind, PipelineCommand, PipelineValues, StageEstimate,
+        SymbolCrossrefInfo, SymbolCrossrefInfoList, SymbolMetaFlags, SymbolRelation,
+    },
+    warnings::record_overload,
 };
 
-use crate::abstract_server::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError};
+use crate::{
+    abstract_server::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError},
+    file_format::{analysis::AnalysisStructured, crossref_record::CrossrefRecord},
+};
 
 /// Given a set of symbol crossref data, expand the set via relevant semantic
 /// relationships like override set membership.  This is fundamentally entwined
@@ -28,6 +33,32 @@ pub struct CrossrefExpand {
     pub override_local_limit: u32,
     #[clap(long, value_parser, default_value = "400")]
     pub override_global_limit: u32,
+
+    #[clap(long, value_parser, default_value = "100")]
+    pub macro_expansion_local_limit: u32,
+    #[clap(long, value_parser, default_value = "400")]
+    pub macro_expansion_global_limit: u32,
+
+    /// Also include the known explicit and partial specializations of queried
+    /// C++ templates (and the templates of queried specializations) so that
+    /// uses scattered across specializations aren't invisible.
+    #[clap(long, action)]
+    pub specializations: bool,
+    #[clap(long, value_parser, default_value = "100")]
+    pub specialization_local_limit: u32,
+    #[clap(long, value_parser, default_value = "400")]
+    pub specialization_global_limit: u32,
+
+    /// Also include the fields of queried classes and of all their
+    /// superclasses, labeled with their byte offset within the queried class,
+    /// so that the full inherited shape of the class is available rather than
+    /// just its direct fields.
+    #[clap(long, action)]
+    pub list_supers_with_members: bool,
+    #[clap(long, value_parser, default_value = "200")]
+    pub member_local_limit: u32,
+    #[clap(long, value_parser, default_value = "800")]
+    pub member_global_limit: u32,
 }
 
 /// Crosseref expansion exists to help us:
@@ -62,6 +93,29 @@ pub struct CrossrefExpand {
 /// lookup mechanism unifying things.  Our initial focus in this implementation
 /// will be on the override set because this is where we've regressed some use
 /// cases and we have tentative plans for faceting and diagramming.
+/// Return the fields of a class which sits at `base_offset` within the queried
+/// class `root_sym`, paired with their offset within the queried class.  The
+/// offsets of the class's superclasses within the queried class are recorded in
+/// `super_offsets`, keyed by `(root_sym, super_sym)`, for when we get to them;
+/// the first offset recorded for a superclass wins.
+fn class_members(
+    root_sym: Ustr,
+    base_offset: u32,
+    meta: &AnalysisStructured,
+    super_offsets: &mut HashMap<(Ustr, Ustr), u32>,
+) -> Vec<(Ustr, u32)> {
+    for super_info in &meta.supers {
+        super_offsets
+            .entry((root_sym, super_info.sym))
+            .or_insert(base_offset + super_info.offset_bytes);
+    }
+    meta.fields
+        .iter()
+        .filter(|field| !field.sym.is_empty())
+        .map(|field| (field.sym, base_offset + field.offset_bytes))
+        .collect()
+}
+
 #[derive(Debug)]
 pub struct CrossrefExpandCommand {
     pub args: CrossrefExpand,
@@ -76,6 +130,49 @@ struct LimitGroup {
     global_limit: u32,
 }
 
+impl LimitGroup {
+    /// Check whether `count` more symbols fit within our limits, tallying them
+    /// if so and otherwise recording the overload in `overloads_hit`.
+    fn admit(&mut self, count: u32, overloads_hit: &mut Vec<OverloadInfo>) -> bool {
+        if self.local_limit > 0 && count > self.local_limit {
+            record_overload(
+                overloads_hit,
+                "crossref-expand",
+                OverloadInfo {
+                    kind: self.kind.clone(),
+                    // We're explicitly hanging off a symbol, so we don't need to
+                    // encode any other symbol here.
+                    sym: None,
+                    exist: count,
+                    included: 0,
+                    local_limit: self.local_limit,
+                    global_limit: 0,
+                },
+            );
+            return false;
+        }
+        if self.global_limit > 0 && self.global_count + count > self.global_limit {
+            record_overload(
+                overloads_hit,
+                "crossref-expand",
+                OverloadInfo {
+                    kind: self.kind.clone(),
+                    // We're explicitly hanging off a symbol, so we don't need to
+                    // encode any other symbol here.
+                    sym: None,
+                    exist: count,
+                    included: 0,
+                    local_limit: 0,
+                    global_limit: self.global_limit,
+                },
+            );
+            return false;
+        }
+        self.global_count += count;
+        true
+    }
+}
+
 #[async_trait]
 impl PipelineCommand for CrossrefExpandCommand {
     async fn execute(
@@ -106,6 +203,9 @@ impl PipelineCommand for CrossrefExpandCommand {
         // to label each symbol.  We do take care to list distances in the enum,
         // but even a min() heuristic is potentially going to look weird.
 
+        // Servers that don't expose the tree config don't annotate anything.
+        let annotations = server.symbol_annotation_config().unwrap_or_default();
+
         let mut to_traverse = VecDeque::new();
         let mut considered = HashSet::new();
 
@@ -134,78 +234,96 @@ impl PipelineCommand for CrossrefExpandCommand {
             global_count: 0,
             global_limit: self.args.subclass_global_limit,
         };
+        let mut macro_expansion_limits = LimitGroup {
+            kind: OverloadKind::MacroExpansions,
+            local_limit: self.args.macro_expansion_local_limit,
+            global_count: 0,
+            global_limit: self.args.macro_expansion_global_limit,
+        };
+        let mut specialization_limits = LimitGroup {
+            kind: OverloadKind::Specializations,
+            local_limit: self.args.specialization_local_limit,
+            global_count: 0,
+            global_limit: self.args.specialization_global_limit,
+        };
+        let mut member_limits = LimitGroup {
+            kind: OverloadKind::Members,
+            local_limit: self.args.member_local_limit,
+            global_count: 0,
+            global_limit: self.args.member_global_limit,
+        };
+        // For `--list-supers-with-members`, the byte offset of each superclass
+        // we've seen within the queried class it was reached from, keyed by
+        // (queried class, superclass).
+        let mut super_offsets: HashMap<(Ustr, Ustr), u32> = HashMap::new();
 
         while let Some((symbol, relation, quality, maybe_info)) = to_traverse.pop_front() {
             let mut info = match maybe_info {
                 Some(existing) => existing,
                 None => {
                     let fresh_info = server.crossref_lookup(&symbol, false).await?;
-                    SymbolCrossrefInfo {
+                    let mut fresh_info = SymbolCrossrefInfo {
                         symbol,
-                        crossref_info: fresh_info,
+                        crossref_info: CrossrefRecord::from_value(fresh_info)?,
                         relation: relation.clone(),
                         quality,
                         overloads_hit: vec![],
                         flags: SymbolMetaFlags::default(),
-                    }
+                        declaration_only: false,
+                        annotation: None,
+                    };
+                    fresh_info.declaration_only = fresh_info.is_declaration_only();
+                    fresh_info.annotate(&annotations);
+                    fresh_info
                 }
             };
 
-            // Given a JSON pointer to a an array, if present, process it, transforming
-            // each array value using `xfunc` to extract the symbol.  `use_relation`
-            // specifies the resulting relationship that should be associated
-            // with the extracted symbol.  `use_limits` is the `LimitGroup` to
-            // adjust and apply.
-            let mut proc_ptr =
-                |ptr: &str,
-                 xfunc: &dyn Fn(&Value) -> &Value,
+            let overridden_by = info.crossref_info.overridden_by_syms();
+            let overrides = info.crossref_info.override_syms();
+            let subclasses = info.crossref_info.subclass_syms();
+            let supers = info.crossref_info.super_syms();
+            let idl_counterparts = info.crossref_info.idl_field_counterpart_syms();
+            let expands_to: Vec<Ustr> = info
+                .crossref_info
+                .expands_to
+                .iter()
+                .map(|x| x.sym)
+                .collect();
+            let (specializations, specialization_of) = if self.args.specializations {
+                (
+                    info.crossref_info.specialization_syms(),
+                    info.crossref_info.specialization_of_syms(),
+                )
+            } else {
+                (vec![], vec![])
+            };
+
+            // Given a list of related symbols for the edge named `edge`,
+            // process them.  `use_relation` specifies the resulting
+            // relationship that should be associated with the symbols.
+            // `use_limits` is the `LimitGroup` to adjust and apply.
+            let mut proc_syms =
+                |edge: &str,
+                 arr: &[Ustr],
                  use_relation: SymbolRelation,
                  use_limits: Option<&mut LimitGroup>| {
-                    if let Some(Value::Array(arr)) = info.crossref_info.pointer(ptr) {
-                        if let Some(limits) = use_limits {
-                            if limits.local_limit > 0 && arr.len() as u32 > limits.local_limit {
-                                info.overloads_hit.push(OverloadInfo {
-                                    kind: limits.kind.clone(),
-                                    // We're explicitly hanging off a symbol, so we don't need to
-                                    // encode any other symbol here.
-                                    sym: None,
-                                    exist: arr.len() as u32,
-                                    included: 0,
-                                    local_limit: limits.local_limit,
-                                    global_limit: 0,
-                                });
-                                return;
-                            }
-                            if limits.global_limit > 0
-                                && limits.global_count + arr.len() as u32 > limits.global_limit
-                            {
-                                info.overloads_hit.push(OverloadInfo {
-                                    kind: limits.kind.clone(),
-                                    // We're explicitly hanging off a symbol, so we don't need to
-                                    // encode any other symbol here.
-                                    sym: None,
-                                    exist: arr.len() as u32,
-                                    included: 0,
-                                    local_limit: 0,
-                                    global_limit: limits.global_limit,
-                                });
-                                return;
-                            }
-                            limits.global_count += arr.len() as u32;
+                    if arr.is_empty() {
+                        return;
+                    }
+                    if let Some(limits) = use_limits {
+                        if !limits.admit(arr.len() as u32, &mut info.overloads_hit) {
+                            return;
                         }
-                        trace!(edge = ptr, count = arr.len(), "considering");
-                        for wrapped in arr.iter() {
-                            if let Value::String(sym) = xfunc(wrapped) {
-                                let usym = ustr(sym);
-                                if considered.insert(usym) {
-                                    to_traverse.push_back((
-                                        usym,
-                                        use_relation.clone(),
-                                        info.quality.clone(),
-                                        None,
-                                    ));
-                                }
-                            }
+                    }
+                    trace!(edge, count = arr.len(), "considering");
+                    for usym in arr.iter() {
+                        if considered.insert(*usym) {
+                            to_traverse.push_back((
+                                *usym,
+                                use_relation.clone(),
+                                info.quality.clone(),
+                                None,
+                            ));
                         }
                     }
                 };
@@ -228,91 +346,165 @@ impl PipelineCommand for CrossrefExpandCommand {
             //   what they want.
             match &relation {
                 SymbolRelation::Queried => {
-                    proc_ptr(
-                        "/meta/overridenBy",
-                        &|x| x,
+                    proc_syms(
+                        "overriddenBy",
+                        &overridden_by,
                         SymbolRelation::OverrideOf(symbol, 1),
                         Some(&mut override_limits),
                     );
-                    proc_ptr(
-                        "/meta/overrides",
-                        &|x| &x["sym"],
+                    proc_syms(
+                        "overrides",
+                        &overrides,
                         SymbolRelation::OverriddenBy(symbol, 1),
                         None,
                     );
-                    proc_ptr(
-                        "/meta/subclasses",
-                        &|x| x,
+                    proc_syms(
+                        "subclasses",
+                        &subclasses,
                         SymbolRelation::SubclassOf(symbol, 1),
                         Some(&mut subclass_limits),
                     );
-                    proc_ptr(
-                        "/meta/supers",
-                        &|x| &x["sym"],
+                    proc_syms(
+                        "supers",
+                        &supers,
                         SymbolRelation::SuperclassOf(symbol, 1),
                         None,
                     );
+                    // If this is a macro, include the symbols its expansions
+                    // produced.  This is a leaf relationship; we don't expand
+                    // the produced symbols' overrides or classes.
+                    proc_syms(
+                        "expands-to",
+                        &expands_to,
+                        SymbolRelation::MacroExpansionOf(symbol),
+                        Some(&mut macro_expansion_limits),
+                    );
+                    // Specializations are also a leaf relationship in both
+                    // directions; we don't go from a specialization to its
+                    // template's other specializations.
+                    proc_syms(
+                        "specializations",
+                        &specializations,
+                        SymbolRelation::SpecializationOf(symbol),
+                        Some(&mut specialization_limits),
+                    );
+                    proc_syms(
+                        "specializationOf",
+                        &specialization_of,
+                        SymbolRelation::SpecializationOf(symbol),
+                        None,
+                    );
+                    // An IDL field and the C++ field generated for it (ex: for
+                    // a WebIDL dictionary member) are the same field, so the
+                    // uses of either are uses of both.  This is also a leaf
+                    // relationship.
+                    proc_syms(
+                        "idlCounterparts",
+                        &idl_counterparts,
+                        SymbolRelation::IdlCounterpartOf(symbol),
+                        None,
+                    );
                 }
                 SymbolRelation::OverriddenBy(root_sym, dist) => {
-                    proc_ptr(
-                        "/meta/overrides",
-                        &|x| &x["sym"],
+                    proc_syms(
+                        "overrides",
+                        &overrides,
                         SymbolRelation::OverriddenBy(*root_sym, dist + 1),
                         None,
                     );
-                    proc_ptr(
-                        "/meta/overridenBy",
-                        &|x| x,
+                    proc_syms(
+                        "overriddenBy",
+                        &overridden_by,
                         SymbolRelation::CousinOverrideOf(*root_sym, dist + 1),
                         Some(&mut override_limits),
                     );
                 }
                 SymbolRelation::OverrideOf(root_sym, dist) => {
-                    proc_ptr(
-                        "/meta/overridenBy",
-                        &|x| x,
+                    proc_syms(
+                        "overriddenBy",
+                        &overridden_by,
                         SymbolRelation::OverrideOf(*root_sym, dist + 1),
                         Some(&mut override_limits),
                     );
                 }
                 SymbolRelation::CousinOverrideOf(root_sym, dist) => {
-                    proc_ptr(
-                        "/meta/overridenBy",
-                        &|x| x,
+                    proc_syms(
+                        "overriddenBy",
+                        &overridden_by,
                         SymbolRelation::CousinOverrideOf(*root_sym, dist + 1),
                         Some(&mut override_limits),
                     );
                 }
                 SymbolRelation::SubclassOf(root_sym, dist) => {
-                    proc_ptr(
-                        "/meta/subclasses",
-                        &|x| x,
+                    proc_syms(
+                        "subclasses",
+                        &subclasses,
                         SymbolRelation::SubclassOf(*root_sym, dist + 1),
                         Some(&mut subclass_limits),
                     );
                 }
                 SymbolRelation::SuperclassOf(root_sym, dist) => {
-                    proc_ptr(
-                        "/meta/supers",
-                        &|x| &x["sym"],
+                    proc_syms(
+                        "supers",
+                        &supers,
                         SymbolRelation::SuperclassOf(*root_sym, dist + 1),
                         None,
                     );
-                    proc_ptr(
-                        "/meta/subclasses",
-                        &|x| x,
+                    proc_syms(
+                        "subclasses",
+                        &subclasses,
                         SymbolRelation::CousinClassOf(*root_sym, dist + 1),
                         Some(&mut subclass_limits),
                     );
                 }
                 SymbolRelation::CousinClassOf(root_sym, dist) => {
-                    proc_ptr(
-                        "/meta/subclasses",
-                        &|x| x,
+                    proc_syms(
+                        "subclasses",
+                        &subclasses,
                         SymbolRelation::CousinClassOf(*root_sym, dist + 1),
                         Some(&mut subclass_limits),
                     );
                 }
+                SymbolRelation::MacroExpansionOf(_)
+                | SymbolRelation::SpecializationOf(_)
+                | SymbolRelation::VirtualMemberOf(_)
+                | SymbolRelation::MemberOf(_, _) => {}
+            }
+
+            // Members get labeled with their offset within the queried class,
+            // so rather than going through `proc_syms` we add them here, where
+            // we know where this class sits within the queried class.
+            let class_base = match &relation {
+                _ if !self.args.list_supers_with_members => None,
+                SymbolRelation::Queried => Some((symbol, 0)),
+                SymbolRelation::SuperclassOf(root_sym, _) => Some((
+                    *root_sym,
+                    super_offsets
+                        .get(&(*root_sym, symbol))
+                        .cloned()
+                        .unwrap_or(0),
+                )),
+                _ => None,
+            };
+            if let (Some((root_sym, base_offset)), Some(meta)) =
+                (class_base, &info.crossref_info.meta)
+            {
+                let members = class_members(root_sym, base_offset, meta, &mut super_offsets);
+                if !members.is_empty()
+                    && member_limits.admit(members.len() as u32, &mut info.overloads_hit)
+                {
+                    trace!(edge = "fields", count = members.len(), "considering");
+                    for (member_sym, offset) in members {
+                        if considered.insert(member_sym) {
+                            to_traverse.push_back((
+                                member_sym,
+                                SymbolRelation::MemberOf(root_sym, offset),
+                                info.quality.clone(),
+                                None,
+                            ));
+                        }
+                    }
+                }
             }
 
             expanded.push(info);
@@ -325,4 +517,123 @@ impl PipelineCommand for CrossrefExpandCommand {
             },
         ))
     }
+
+    /// The estimate only accounts for the direct subclasses and overrides of
+    /// the input symbols, so the expected results are a lower bound when the
+    /// hierarchies are deeper than that.
+    async fn estimate(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        input: &PipelineValues,
+    ) -> Result<Option<StageEstimate>> {
+        let source_crossrefs = match input {
+            PipelineValues::SymbolCrossrefInfoList(scil) => scil,
+            _ => return Ok(None),
+        };
+
+        let mut estimate = StageEstimate {
+            symbols: Some(source_crossrefs.symbol_crossref_infos.len() as u64),
+            ..StageEstimate::default()
+        };
+        let mut subclasses = 0;
+        let mut overrides = 0;
+        for info in &source_crossrefs.symbol_crossref_infos {
+            let counts = match server.symbol_relation_counts(&info.symbol).await? {
+                Some(counts) => counts,
+                None => continue,
+            };
+            if counts.subclasses > self.args.subclass_local_limit as usize {
+                estimate.limits_hit.push(format!(
+                    "{} has {} subclasses, over the local limit of {}",
+                    info.symbol, counts.subclasses, self.args.subclass_local_limit
+                ));
+            }
+            if counts.overridden_by > self.args.override_local_limit as usize {
+                estimate.limits_hit.push(format!(
+                    "{} has {} overrides, over the local limit of {}",
+                    info.symbol, counts.overridden_by, self.args.override_local_limit
+                ));
+            }
+            subclasses += counts
+                .subclasses
+                .min(self.args.subclass_local_limit as usize);
+            overrides += counts
+                .overridden_by
+                .min(self.args.override_local_limit as usize);
+        }
+        if subclasses > self.args.subclass_global_limit as usize {
+            estimate.limits_hit.push(format!(
+                "{} subclasses in total, over the global limit of {}",
+                subclasses, self.args.subclass_global_limit
+            ));
+        }
+        if overrides > self.args.override_global_limit as usize {
+            estimate.limits_hit.push(format!(
+                "{} overrides in total, over the global limit of {}",
+                overrides, self.args.override_global_limit
+            ));
+        }
+        estimate.expected_results = Some(
+            (source_crossrefs.symbol_crossref_infos.len()
+                + subclasses.min(self.args.subclass_global_limit as usize)
+                + overrides.min(self.args.override_global_limit as usize)) as u64,
+        );
+        Ok(Some(estimate))
+    }
+}
+
+#[test]
+fn test_class_members() {
+    use serde_json::{from_value, json};
+    use ustr::ustr;
+
+    let meta = |value: serde_json::Value| -> AnalysisStructured { from_value(value).unwrap() };
+    // Derived : Base, Mixin where Mixin sits after Base's 8 bytes.
+    let derived = meta(json!({
+        "structured": 1,
+        "supers": [
+            {"sym": "T_Base", "offsetBytes": 0},
+            {"sym": "T_Mixin", "offsetBytes": 8},
+        ],
+        "fields": [
+            {"sym": "F_<T_Derived>_mD", "offsetBytes": 16},
+            {"sym": "", "offsetBytes": 20},
+        ],
+    }));
+    let base = meta(json!({
+        "structured": 1,
+        "supers": [{"sym": "T_Root", "offsetBytes": 0}],
+        "fields": [{"sym": "F_<T_Base>_mB", "offsetBytes": 0}],
+    }));
+    let mixin = meta(json!({
+        "structured": 1,
+        "fields": [{"sym": "F_<T_Mixin>_mM", "offsetBytes": 4}],
+    }));
+
+    let root = ustr("T_Derived");
+    let mut super_offsets = HashMap::new();
+    assert_eq!(
+        class_members(root, 0, &derived, &mut super_offsets),
+        vec![(ustr("F_<T_Derived>_mD"), 16)]
+    );
+    assert_eq!(super_offsets.get(&(root, ustr("T_Base"))), Some(&0));
+    assert_eq!(super_offsets.get(&(root, ustr("T_Mixin"))), Some(&8));
+
+    // Superclass fields are offset by where the superclass sits.
+    let mixin_offset = super_offsets[&(root, ustr("T_Mixin"))];
+    assert_eq!(
+        class_members(root, mixin_offset, &mixin, &mut super_offsets),
+        vec![(ustr("F_<T_Mixin>_mM"), 12)]
+    );
+
+    // Superclasses of superclasses are recorded relative to the root too.
+    assert_eq!(
+        class_members(root, 0, &base, &mut super_offsets),
+        vec![(ustr("F_<T_Base>_mB"), 0)]
+    );
+    assert_eq!(super_offsets.get(&(root, ustr("T_Root"))), Some(&0));
+
+    // A superclass reached a second way keeps its first offset.
+    class_members(root, 8, &base, &mut super_offsets);
+    assert_eq!(super_offsets.get(&(root, ustr("T_Root"))), Some(&0));
 }
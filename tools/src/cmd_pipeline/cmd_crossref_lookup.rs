@@ -1,13 +1,26 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
 use async_trait::async_trait;
 use clap::Args;
 use ustr::{ustr, Ustr};
 
-use super::interface::{
-    PipelineCommand, PipelineValues, SymbolCrossrefInfo, SymbolCrossrefInfoList, SymbolMetaFlags,
-    SymbolQuality, SymbolRelation,
+use super::{
+    interface::{
+        PipelineCommand, PipelineValues, SymbolCrossrefInfo, SymbolCrossrefInfoList,
+        SymbolMetaFlags, SymbolQuality, SymbolRelation,
+    },
+    warnings::add_pipeline_warning,
+};
+
+use crate::{
+    abstract_server::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError},
+    file_format::{config::VIRTUAL_SYMBOL_PREFIX, crossref_record::CrossrefRecord},
 };
 
-use crate::abstract_server::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError};
+/// Limits for each of a virtual symbol's `identifier_patterns` scans.
+const VIRTUAL_PATTERN_MATCH_LIMIT: usize = 1000;
+const VIRTUAL_PATTERN_TIME_LIMIT_MS: u64 = 2000;
 
 /// Return the crossref data for one or more symbols received via pipeline or as
 /// explicit arguments.
@@ -28,6 +41,15 @@ pub struct CrossrefLookup {
     /// search-identifiers which were not an absolute identifier match.
     #[clap(short, long, value_parser)]
     exact_match: bool,
+
+    /// Identifier lookups are biased toward symbols defined in this tree, so a
+    /// symbol that's only declared (ex: a system library function declared in
+    /// a header) is moved after the other results if its identifier also
+    /// matched a defined symbol.  This drops such symbols instead, which is
+    /// useful when the API surface of system libraries is just noise.  Either
+    /// way, declaration-only symbols are marked `declarationOnly`.
+    #[clap(long, action)]
+    exclude_declaration_only: bool,
 }
 
 #[derive(Debug)]
@@ -35,6 +57,80 @@ pub struct CrossrefLookupCommand {
     pub args: CrossrefLookup,
 }
 
+/// Move the declaration-only symbols that were looked up for an identifier
+/// which also matched a symbol with a definition after all the other symbols,
+/// or drop them if `exclude_declaration_only` is set.  Symbols that weren't
+/// looked up for an identifier are never moved.
+fn bias_toward_defined(
+    infos: Vec<(Option<Ustr>, SymbolCrossrefInfo)>,
+    exclude_declaration_only: bool,
+) -> Vec<SymbolCrossrefInfo> {
+    let defined_idents: HashSet<Ustr> = infos
+        .iter()
+        .filter(|(_, info)| !info.declaration_only)
+        .filter_map(|(from_ident, _)| *from_ident)
+        .collect();
+    let (shadowed, mut kept): (Vec<_>, Vec<_>) =
+        infos.into_iter().partition(|(from_ident, info)| {
+            info.declaration_only
+                && from_ident.map_or(false, |ident| defined_idents.contains(&ident))
+        });
+    if !exclude_declaration_only {
+        kept.extend(shadowed);
+    }
+    kept.into_iter().map(|(_, info)| info).collect()
+}
+
+/// Combine a virtual symbol's explicit member symbols with the symbols found
+/// by its identifier patterns, dropping duplicates but otherwise keeping the
+/// configured order.
+fn merge_virtual_members(explicit: &[String], scanned: Vec<Ustr>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    explicit
+        .iter()
+        .cloned()
+        .chain(scanned.into_iter().map(|sym| sym.to_string()))
+        .filter(|sym| seen.insert(sym.clone()))
+        .collect()
+}
+
+/// Expand the named virtual symbol into its member symbols, returning None if
+/// there's no such virtual symbol.
+async fn expand_virtual_symbol(
+    server: &(dyn AbstractServer + Send + Sync),
+    name: &str,
+) -> Result<Option<Vec<String>>> {
+    // Servers without local config (ex: remote servers) have no virtual
+    // symbols.
+    let config = match server.virtual_symbol_config(name) {
+        Ok(Some(config)) => config,
+        Ok(None) | Err(ServerError::Unsupported) => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    let mut scanned = vec![];
+    for pattern in &config.identifier_patterns {
+        let (matches, truncated) = server
+            .search_identifiers_regex(
+                pattern,
+                false,
+                VIRTUAL_PATTERN_MATCH_LIMIT,
+                Duration::from_millis(VIRTUAL_PATTERN_TIME_LIMIT_MS),
+            )
+            .await?;
+        if truncated {
+            add_pipeline_warning(
+                "crossref-lookup",
+                format!(
+                    "virtual symbol {} pattern {} matched too many identifiers; its members are incomplete",
+                    name, pattern
+                ),
+            );
+        }
+        scanned.extend(matches.into_iter().map(|(sym, _)| sym));
+    }
+    Ok(Some(merge_virtual_members(&config.symbols, scanned)))
+}
+
 #[async_trait]
 impl PipelineCommand for CrossrefLookupCommand {
     async fn execute(
@@ -69,9 +165,46 @@ impl PipelineCommand for CrossrefLookupCommand {
             }
         };
 
+        // Servers that don't expose the tree config don't annotate anything.
+        let annotations = server.symbol_annotation_config().unwrap_or_default();
+
         let mut symbol_crossref_infos = vec![];
         let mut unknown_symbols = vec![];
         for (symbol, quality, from_ident) in symbol_list {
+            if let Some(name) = symbol.strip_prefix(VIRTUAL_SYMBOL_PREFIX) {
+                let members = match expand_virtual_symbol(server, name).await? {
+                    Some(members) => members,
+                    None => {
+                        unknown_symbols.push(symbol);
+                        continue;
+                    }
+                };
+                // The config vouches for the virtual symbol, so it's safe to
+                // intern.
+                let virtual_sym = ustr(&symbol);
+                for member in members {
+                    let info = server.crossref_lookup(&member, false).await?;
+                    if info.is_null() {
+                        unknown_symbols.push(member);
+                        continue;
+                    }
+                    let mut member_info = SymbolCrossrefInfo {
+                        symbol: ustr(&member),
+                        crossref_info: CrossrefRecord::from_value(info)?,
+                        relation: SymbolRelation::VirtualMemberOf(virtual_sym),
+                        quality: quality.clone(),
+                        overloads_hit: vec![],
+                        flags: SymbolMetaFlags::default(),
+                        declaration_only: false,
+                        annotation: None,
+                    };
+                    member_info.declaration_only = member_info.is_declaration_only();
+                    member_info.annotate(&annotations);
+                    symbol_crossref_infos.push((None, member_info));
+                }
+                continue;
+            }
+
             let info = server.crossref_lookup(&symbol, false).await?;
 
             if info.is_null() {
@@ -79,18 +212,22 @@ impl PipelineCommand for CrossrefLookupCommand {
                 continue;
             }
 
-            let crossref_info = SymbolCrossrefInfo {
+            let mut crossref_info = SymbolCrossrefInfo {
                 // Now that we've validted that the symbol exists via crossref
                 // lookup, we know it's safe to mint a Ustr for it if it doesn't
                 // exist.  (Otherwise hostile/broken callers could explode our
                 // interning table.)
                 symbol: ustr(&symbol),
-                crossref_info: info,
+                crossref_info: CrossrefRecord::from_value(info)?,
                 relation: SymbolRelation::Queried,
                 quality,
                 overloads_hit: vec![],
                 flags: SymbolMetaFlags::default(),
+                declaration_only: false,
+                annotation: None,
             };
+            crossref_info.declaration_only = crossref_info.is_declaration_only();
+            crossref_info.annotate(&annotations);
             if let (true, Some(pretty)) = (self.args.exact_match, from_ident) {
                 if pretty.to_lowercase() != crossref_info.get_pretty().to_lowercase() {
                     continue;
@@ -100,27 +237,107 @@ impl PipelineCommand for CrossrefLookupCommand {
                 if let Some(method_syms) = crossref_info.get_method_symbols() {
                     for method_sym in method_syms {
                         let method_info = server.crossref_lookup(&method_sym, false).await?;
-                        symbol_crossref_infos.push(SymbolCrossrefInfo {
+                        let mut method_info = SymbolCrossrefInfo {
                             symbol: method_sym,
-                            crossref_info: method_info,
+                            crossref_info: CrossrefRecord::from_value(method_info)?,
                             relation: SymbolRelation::Queried,
                             quality: crossref_info.quality.clone(),
                             overloads_hit: vec![],
                             flags: SymbolMetaFlags::default(),
-                        });
+                            declaration_only: false,
+                            annotation: None,
+                        };
+                        method_info.declaration_only = method_info.is_declaration_only();
+                        method_info.annotate(&annotations);
+                        symbol_crossref_infos.push((None, method_info));
                     }
                     continue;
                 }
             }
 
-            symbol_crossref_infos.push(crossref_info);
+            symbol_crossref_infos.push((from_ident, crossref_info));
         }
 
         Ok(PipelineValues::SymbolCrossrefInfoList(
             SymbolCrossrefInfoList {
-                symbol_crossref_infos,
+                symbol_crossref_infos: bias_toward_defined(
+                    symbol_crossref_infos,
+                    self.args.exclude_declaration_only,
+                ),
                 unknown_symbols,
             },
         ))
     }
+
+    /// Looking up the given symbols is a handful of crossref reads.
+    fn is_cheap(&self) -> bool {
+        true
+    }
+}
+
+#[test]
+fn test_merge_virtual_members() {
+    let explicit = vec![
+        "_ZN7mozilla11CycleCollectE".to_string(),
+        "M_NS_IMPL_CYCLE_COLLECTION".to_string(),
+    ];
+    let scanned = vec![
+        ustr("M_NS_IMPL_CYCLE_COLLECTION"),
+        ustr("M_NS_DECL_CYCLE_COLLECTION_CLASS"),
+    ];
+    assert_eq!(
+        merge_virtual_members(&explicit, scanned),
+        vec![
+            "_ZN7mozilla11CycleCollectE",
+            "M_NS_IMPL_CYCLE_COLLECTION",
+            "M_NS_DECL_CYCLE_COLLECTION_CLASS"
+        ]
+    );
+}
+
+#[test]
+fn test_bias_toward_defined() {
+    let info = |symbol: &str, record: serde_json::Value| {
+        let mut info = SymbolCrossrefInfo {
+            symbol: ustr(symbol),
+            crossref_info: CrossrefRecord::from_value(record).unwrap(),
+            relation: SymbolRelation::Queried,
+            quality: SymbolQuality::ExactIdentifier,
+            overloads_hit: vec![],
+            flags: SymbolMetaFlags::default(),
+            declaration_only: false,
+            annotation: None,
+        };
+        info.declaration_only = info.is_declaration_only();
+        info
+    };
+    let hits = serde_json::json!([{"path": "a.h", "path_kind": "Normal", "lines": [
+        {"lno": 1, "bounds": [0, 3], "line": "foo", "context": "", "contextsym": ""}
+    ]}]);
+    let defined = serde_json::json!({ "defs": hits.clone(), "decls": hits.clone() });
+    let declared = serde_json::json!({ "decls": hits });
+
+    let infos = || {
+        vec![
+            (Some(ustr("foo")), info("_Z3foov", defined.clone())),
+            (Some(ustr("foo")), info("_Z3fooi", declared.clone())),
+            (Some(ustr("bar")), info("_Z3barv", declared.clone())),
+            (Some(ustr("foo")), info("_Z3foof", defined.clone())),
+        ]
+    };
+    let syms = |infos: Vec<SymbolCrossrefInfo>| -> Vec<String> {
+        infos
+            .into_iter()
+            .map(|info| info.symbol.to_string())
+            .collect()
+    };
+    // "bar" only has a declaration-only symbol, so it stays in place.
+    assert_eq!(
+        syms(bias_toward_defined(infos(), false)),
+        vec!["_Z3foov", "_Z3barv", "_Z3foof", "_Z3fooi"]
+    );
+    assert_eq!(
+        syms(bias_toward_defined(infos(), true)),
+        vec!["_Z3foov", "_Z3barv", "_Z3foof"]
+    );
 }
@@ -0,0 +1,257 @@
+use std::cmp::Ordering;
+
+use async_trait::async_trait;
+use clap::Args;
+use regex::RegexSet;
+use serde::Serialize;
+use serde_json::to_value;
+use tokio_stream::StreamExt;
+use ustr::Ustr;
+
+use super::{
+    interface::{JsonValue, JsonValueList, PipelineCommand, PipelineValues},
+    progress::ProgressTracker,
+    top_k::{RankOrd, TopK},
+};
+
+use crate::{
+    abstract_server::{AbstractServer, Result},
+    file_format::crossref_record::CrossrefRecord,
+};
+
+/// Scan the entire crossref database for symbols with structured information
+/// that have definitions but no uses outside of their own definitions, producing
+/// a ranked list of dead code candidates.
+///
+/// Symbols can be "used" in ways we can't see, so by default we exclude
+/// likely roots:
+/// - IDL-ish symbols: those with IDL hits or binding slot relationships since
+///   they are likely invoked from another language.
+/// - Override relationships: methods that override or are overridden since
+///   they are likely invoked via virtual dispatch.
+/// - Special members: constructors, destructors, and operators, which are
+///   frequently invoked implicitly.
+/// - Generated code.
+/// - Anything defined in a path matching `--root-path`, which can be used to
+///   mark exported APIs.
+///
+/// Candidates are scored from 0 to 1 with higher scores being more likely to
+/// actually be dead.  Candidates which are also declared in another file (ex:
+/// a header) or which live in non-"normal" paths like tests are demoted, as
+/// are candidates which are only used recursively.
+///
+/// This is expensive and requires a local index.
+#[derive(Debug, Args)]
+pub struct DeadCode {
+    /// Only consider symbols with a definition under this path prefix.
+    #[clap(long, value_parser)]
+    path: Option<String>,
+
+    /// Only consider symbols of these structured kinds (ex: "function",
+    /// "method", "field").  All kinds are considered if omitted.
+    #[clap(long, value_parser)]
+    kind: Vec<String>,
+
+    /// Regular expressions for paths whose definitions should be considered
+    /// exported roots and therefore not candidates.
+    #[clap(long, value_parser)]
+    root_path: Vec<String>,
+
+    /// Don't exclude symbols with IDL or binding slot relationships.
+    #[clap(long, action)]
+    include_idl: bool,
+
+    /// Don't exclude symbols with override relationships.
+    #[clap(long, action)]
+    include_overrides: bool,
+
+    /// Don't exclude constructors, destructors, and operators.
+    #[clap(long, action)]
+    include_special_members: bool,
+
+    /// Don't exclude symbols defined in generated code.
+    #[clap(long, action)]
+    include_generated: bool,
+
+    /// Maximum number of candidates to produce.  0 means no limit.
+    #[clap(short, long, value_parser, default_value = "1000")]
+    limit: usize,
+}
+
+#[derive(Debug)]
+pub struct DeadCodeCommand {
+    pub args: DeadCode,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DeadCodeCandidate {
+    sym: String,
+    pretty: Ustr,
+    kind: Ustr,
+    score: f64,
+    path: Ustr,
+    path_kind: Ustr,
+    lno: u32,
+    /// Number of uses from within the symbol's own definition.
+    self_uses: usize,
+    /// Is the symbol also declared in a file other than where it's defined?
+    declared_elsewhere: bool,
+}
+
+impl RankOrd for DeadCodeCandidate {
+    /// Most likely dead first, then by location.
+    fn rank_cmp(&self, other: &Self) -> Ordering {
+        other
+            .score
+            .total_cmp(&self.score)
+            .then_with(|| self.path.cmp(&other.path))
+            .then_with(|| self.lno.cmp(&other.lno))
+            .then_with(|| self.sym.cmp(&other.sym))
+    }
+}
+
+/// Does the pretty identifier look like a constructor, destructor, or operator?
+fn is_special_member(pretty: &str) -> bool {
+    let mut pieces = pretty.rsplit("::");
+    let last = pieces.next().unwrap_or("");
+    if last.starts_with('~') || last.starts_with("operator") {
+        return true;
+    }
+    match pieces.next() {
+        Some(parent) => parent == last,
+        None => false,
+    }
+}
+
+impl DeadCodeCommand {
+    /// Evaluate a crossref record, returning a candidate if it looks dead.
+    fn evaluate(
+        &self,
+        sym: String,
+        record: &CrossrefRecord,
+        root_paths: &RegexSet,
+    ) -> Option<DeadCodeCandidate> {
+        let meta = record.meta.as_ref()?;
+        let def_path = record.defs.first()?;
+        let def_line = def_path.lines.first()?;
+
+        if let Some(prefix) = &self.args.path {
+            if !def_path.path.starts_with(prefix.as_str()) {
+                return None;
+            }
+        }
+        if !self.args.kind.is_empty() && !self.args.kind.iter().any(|k| k == meta.kind.as_str()) {
+            return None;
+        }
+        if root_paths.is_match(&def_path.path) {
+            return None;
+        }
+        if !self.args.include_generated && def_path.path.starts_with("__GENERATED__") {
+            return None;
+        }
+        if !self.args.include_idl
+            && (!record.idl.is_empty()
+                || meta.idl_sym.is_some()
+                || meta.slot_owner.is_some()
+                || !meta.binding_slots.is_empty())
+        {
+            return None;
+        }
+        if !self.args.include_overrides
+            && (!meta.overrides.is_empty() || !meta.overridden_by_syms.is_empty())
+        {
+            return None;
+        }
+        if !self.args.include_special_members && is_special_member(&meta.pretty) {
+            return None;
+        }
+
+        // Uses whose context is the symbol itself are self-uses (ex: recursion)
+        // and don't keep the symbol alive.
+        let mut self_uses = 0;
+        for path_container in &record.uses {
+            for hit in &path_container.lines {
+                if hit.contextsym.as_str() != sym {
+                    return None;
+                }
+                self_uses += 1;
+            }
+        }
+
+        let declared_elsewhere = record
+            .decls
+            .iter()
+            .any(|pc| record.defs.iter().all(|def| def.path != pc.path));
+
+        let mut score = 1.0;
+        if declared_elsewhere {
+            score *= 0.5;
+        }
+        if !def_path.path_kind.is_empty() && !def_path.path_kind.eq_ignore_ascii_case("normal") {
+            score *= 0.75;
+        }
+        if self_uses > 0 {
+            score *= 0.9;
+        }
+
+        Some(DeadCodeCandidate {
+            sym,
+            pretty: meta.pretty,
+            kind: meta.kind,
+            score,
+            path: def_path.path,
+            path_kind: def_path.path_kind,
+            lno: def_line.lineno,
+            self_uses,
+            declared_elsewhere,
+        })
+    }
+}
+
+#[async_trait]
+impl PipelineCommand for DeadCodeCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        _input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        let root_paths = RegexSet::new(&self.args.root_path)?;
+
+        let mut candidates = TopK::new(self.args.limit);
+        let mut records = server.crossref_scan().await?;
+        let mut progress = ProgressTracker::new("dead-code", None);
+        while let Some(entry) = records.next().await {
+            let (sym, value) = entry?;
+            progress.advance(1);
+            if value.is_null() {
+                continue;
+            }
+            let record = CrossrefRecord::from_value(value)?;
+            if let Some(candidate) = self.evaluate(sym, &record, &root_paths) {
+                candidates.push(candidate);
+            }
+        }
+        progress.finish();
+
+        let values = candidates
+            .into_sorted_vec()
+            .into_iter()
+            .map(|candidate| {
+                Ok(JsonValue {
+                    value: to_value(candidate)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(PipelineValues::JsonValueList(JsonValueList { values }))
+    }
+}
+
+#[test]
+fn test_is_special_member() {
+    assert!(is_special_member("mozilla::dom::Element::Element"));
+    assert!(is_special_member("mozilla::dom::Element::~Element"));
+    assert!(is_special_member("nsCOMPtr::operator="));
+    assert!(!is_special_member("mozilla::dom::Element::GetAttr"));
+    assert!(!is_special_member("main"));
+}
@@ -0,0 +1,235 @@
+use std::collections::HashSet;
+
+use async_trait::async_trait;
+use clap::Args;
+use serde::Serialize;
+use serde_json::to_value;
+use ustr::Ustr;
+
+use super::{
+    cmd_search_identifiers::identifier_quality,
+    interface::{JsonValue, PipelineCommand, PipelineValues, SymbolQuality},
+};
+
+use crate::{
+    abstract_server::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError},
+    file_format::{analysis::PathSearchResult, crossref_record::CrossrefRecord},
+};
+
+/// Resolve an identifier to the single definition a user most likely wants to
+/// jump to, for "I'm feeling lucky" style navigation.  This runs a prefix,
+/// case-insensitive identifier search like `search-identifiers`, looks up the
+/// crossref data of the best candidates, and picks the best jump target by
+/// identifier quality, then by whether we have a definition (rather than just
+/// IDL or a declaration), then by popularity.
+///
+/// Produces a `JsonValue` of `{ identifier, best, alternates }` where `best`
+/// is null if nothing could be resolved and each target is `{ sym, pretty,
+/// jumpKind, jump, quality, defCount, useCount }`, `jump` being "path#lno".
+#[derive(Debug, Args)]
+pub struct DefineBest {
+    /// The identifier to resolve.
+    #[clap(short, long, value_parser)]
+    identifier: String,
+
+    /// Maximum number of candidate symbols to look up crossref data for, taken
+    /// in identifier quality order.
+    #[clap(long, value_parser, default_value = "32")]
+    candidate_limit: usize,
+
+    /// Maximum number of runner-up targets to list.
+    #[clap(long, value_parser, default_value = "5")]
+    alternates: usize,
+}
+
+#[derive(Debug)]
+pub struct DefineBestCommand {
+    pub args: DefineBest,
+}
+
+/// The kinds of jump targets in order of preference.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum JumpKind {
+    Def,
+    Idl,
+    Decl,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DefineTarget {
+    sym: Ustr,
+    pretty: Ustr,
+    jump_kind: JumpKind,
+    jump: String,
+    quality: SymbolQuality,
+    def_count: u32,
+    use_count: u32,
+}
+
+#[derive(Serialize)]
+struct DefineBestResult {
+    identifier: String,
+    best: Option<DefineTarget>,
+    alternates: Vec<DefineTarget>,
+}
+
+fn count_hits(path_hits: &[PathSearchResult]) -> u32 {
+    path_hits.iter().map(|ph| ph.lines.len() as u32).sum()
+}
+
+/// Pick the jump target for a symbol, favoring definitions over IDL over
+/// declarations and taking the first hit of the first kind that has any.
+fn resolve_jump(crossref_info: &CrossrefRecord) -> Option<(JumpKind, String)> {
+    let kinds = [
+        (JumpKind::Def, &crossref_info.defs),
+        (JumpKind::Idl, &crossref_info.idl),
+        (JumpKind::Decl, &crossref_info.decls),
+    ];
+    for (kind, path_hits) in kinds {
+        let first = path_hits
+            .iter()
+            .find_map(|ph| ph.lines.first().map(|line| (ph.path, line.lineno)));
+        if let Some((path, lno)) = first {
+            return Some((kind, format!("{}#{}", path, lno)));
+        }
+    }
+    None
+}
+
+/// Sort targets best first.  Ties are broken by preferring symbols with fewer
+/// definitions (an unambiguous target) and then more uses (popularity).
+fn rank_targets(targets: &mut [DefineTarget]) {
+    targets.sort_by(|a, b| {
+        a.quality
+            .cmp(&b.quality)
+            .then_with(|| a.jump_kind.cmp(&b.jump_kind))
+            .then_with(|| a.def_count.cmp(&b.def_count))
+            .then_with(|| b.use_count.cmp(&a.use_count))
+    });
+}
+
+#[async_trait]
+impl PipelineCommand for DefineBestCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        if !matches!(input, PipelineValues::Void) {
+            return Err(ServerError::StickyProblem(ErrorDetails {
+                layer: ErrorLayer::ConfigLayer,
+                message: "define-best needs Void".to_string(),
+            }));
+        }
+
+        let identifier = &self.args.identifier;
+        let mut candidates: Vec<(Ustr, SymbolQuality)> = vec![];
+        let mut seen = HashSet::new();
+        for (sym, from_ident) in server
+            .search_identifiers(identifier, false, true, self.args.candidate_limit * 4)
+            .await?
+        {
+            // The same symbol can be found via multiple identifiers (ex: both
+            // `Foo::Bar` and `Bar`), but the first is the best match.
+            if seen.insert(sym) {
+                candidates.push((sym, identifier_quality(false, identifier, &from_ident)));
+            }
+        }
+        candidates.sort_by(|a, b| a.1.cmp(&b.1));
+        candidates.truncate(self.args.candidate_limit);
+
+        let mut targets = vec![];
+        for (sym, quality) in candidates {
+            let info = server.crossref_lookup(&sym, false).await?;
+            if info.is_null() {
+                continue;
+            }
+            let crossref_info = CrossrefRecord::from_value(info)?;
+            let (jump_kind, jump) = match resolve_jump(&crossref_info) {
+                Some(resolved) => resolved,
+                None => continue,
+            };
+            targets.push(DefineTarget {
+                sym,
+                pretty: crossref_info.pretty().unwrap_or(sym),
+                jump_kind,
+                jump,
+                quality,
+                def_count: count_hits(&crossref_info.defs),
+                use_count: count_hits(&crossref_info.uses),
+            });
+        }
+        rank_targets(&mut targets);
+
+        let mut targets = targets.into_iter();
+        let best = targets.next();
+        let alternates = targets.take(self.args.alternates).collect();
+
+        Ok(PipelineValues::JsonValue(JsonValue {
+            value: to_value(DefineBestResult {
+                identifier: identifier.clone(),
+                best,
+                alternates,
+            })?,
+        }))
+    }
+}
+
+#[test]
+fn test_rank_targets() {
+    let target = |sym: &str, quality, jump_kind, def_count, use_count| DefineTarget {
+        sym: ustr::ustr(sym),
+        pretty: ustr::ustr(sym),
+        jump_kind,
+        jump: String::new(),
+        quality,
+        def_count,
+        use_count,
+    };
+    let mut targets = vec![
+        target(
+            "prefix",
+            SymbolQuality::IdentifierPrefix(3, 2),
+            JumpKind::Def,
+            1,
+            100,
+        ),
+        target(
+            "decl",
+            SymbolQuality::ExactIdentifier,
+            JumpKind::Decl,
+            0,
+            500,
+        ),
+        target("rare", SymbolQuality::ExactIdentifier, JumpKind::Def, 1, 2),
+        target(
+            "popular",
+            SymbolQuality::ExactIdentifier,
+            JumpKind::Def,
+            1,
+            50,
+        ),
+        target(
+            "ambiguous",
+            SymbolQuality::ExactIdentifier,
+            JumpKind::Def,
+            3,
+            900,
+        ),
+        target(
+            "case",
+            SymbolQuality::CaseMismatchedIdentifier,
+            JumpKind::Def,
+            1,
+            900,
+        ),
+    ];
+    rank_targets(&mut targets);
+    let syms: Vec<&str> = targets.iter().map(|t| t.sym.as_str()).collect();
+    assert_eq!(
+        syms,
+        vec!["popular", "rare", "ambiguous", "decl", "case", "prefix"]
+    );
+}
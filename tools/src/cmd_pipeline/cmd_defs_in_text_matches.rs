@@ -0,0 +1,226 @@
+use std::collections::{BTreeMap, HashSet};
+
+use async_trait::async_trait;
+use clap::Args;
+use serde_json::{from_value, Value};
+use tokio_stream::StreamExt;
+use ustr::{ustr, Ustr};
+
+use super::interface::{
+    PipelineJunctionCommand, PipelineValues, SymbolList, SymbolQuality, SymbolWithContext,
+};
+
+use crate::{
+    abstract_server::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError},
+    file_format::analysis::{AnalysisSource, WithLocation},
+};
+
+/// Junction that takes `TextMatches` and produces a `SymbolList` of the symbols
+/// whose definitions overlap the matched lines, enabling queries like "find
+/// the classes whose definitions mention XP_WIN" when combined with
+/// `--syntax type`.
+///
+/// A definition covers the lines from its identifier through the end of its
+/// nesting range (ex: the body of a class or function), or just the line of
+/// the identifier if it has no nesting range.
+///
+/// Analysis data for the matched files can be provided via `JsonRecords`
+/// inputs (ex: from `filter-analysis`); files without provided records will
+/// have their analysis data fetched, and are skipped if they have none.  All
+/// other inputs are rejected.
+#[derive(Debug, Args)]
+pub struct DefsInTextMatches {
+    /// Only include definitions whose source record syntax includes this (ex:
+    /// "type", "function", "field").
+    #[clap(long, value_parser)]
+    syntax: Option<String>,
+
+    /// Only include the innermost (smallest) definition overlapping each
+    /// matched line rather than all of the enclosing definitions.
+    #[clap(long, action)]
+    innermost: bool,
+}
+
+#[derive(Debug)]
+pub struct DefsInTextMatchesCommand {
+    pub args: DefsInTextMatches,
+}
+
+/// A definition from a source record along with the inclusive line range it
+/// covers.
+pub struct DefExtent {
+    pub syms: Vec<Ustr>,
+    pub pretty: Ustr,
+    pub start_lineno: u32,
+    pub end_lineno: u32,
+}
+
+/// Extract the definitions from raw analysis records, optionally only keeping
+/// those whose source record syntax includes `syntax`.
+pub fn extract_def_extents(records: Vec<Value>, syntax: Option<&str>) -> Vec<DefExtent> {
+    let mut defs = vec![];
+    for record in records {
+        if record.get("source").is_none() {
+            continue;
+        }
+        let source: WithLocation<AnalysisSource<Ustr>> = match from_value(record) {
+            Ok(source) => source,
+            Err(_) => continue,
+        };
+        let record_syntax = &source.data.syntax;
+        if !record_syntax.iter().any(|s| s == "def") {
+            continue;
+        }
+        if let Some(wanted) = syntax {
+            if !record_syntax.iter().any(|s| s == wanted) {
+                continue;
+            }
+        }
+
+        let start_lineno = source.loc.lineno;
+        let end_lineno = if source.data.nesting_range.is_empty() {
+            start_lineno
+        } else {
+            source.data.nesting_range.end_lineno.max(start_lineno)
+        };
+        defs.push(DefExtent {
+            syms: source.data.sym,
+            pretty: source.data.pretty,
+            start_lineno,
+            end_lineno,
+        });
+    }
+    defs
+}
+
+impl DefsInTextMatchesCommand {
+    fn extract_defs(&self, records: Vec<Value>) -> Vec<DefExtent> {
+        extract_def_extents(records, self.args.syntax.as_deref())
+    }
+
+    /// Return the symbols of the definitions overlapping the given lines.
+    fn overlapping_syms(&self, defs: &[DefExtent], lines: &[u32]) -> Vec<Ustr> {
+        let mut syms = vec![];
+        for &lineno in lines {
+            let mut overlapping = defs
+                .iter()
+                .filter(|def| def.start_lineno <= lineno && lineno <= def.end_lineno);
+            if self.args.innermost {
+                if let Some(def) = overlapping.min_by_key(|def| def.end_lineno - def.start_lineno) {
+                    syms.extend(def.syms.iter().cloned());
+                }
+            } else {
+                for def in overlapping {
+                    syms.extend(def.syms.iter().cloned());
+                }
+            }
+        }
+        syms
+    }
+}
+
+#[async_trait]
+impl PipelineJunctionCommand for DefsInTextMatchesCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        input: Vec<(String, PipelineValues)>,
+    ) -> Result<PipelineValues> {
+        // Matched lines by file, preserving the order files are first seen.
+        let mut file_order: Vec<Ustr> = vec![];
+        let mut lines_by_file: BTreeMap<Ustr, Vec<u32>> = BTreeMap::new();
+        let mut records_by_file: BTreeMap<Ustr, Vec<Value>> = BTreeMap::new();
+
+        for (_name, pipe_value) in input {
+            match pipe_value {
+                PipelineValues::TextMatches(tm) => {
+                    for file_matches in tm.by_file {
+                        let lines = lines_by_file.entry(file_matches.file).or_insert_with(|| {
+                            file_order.push(file_matches.file);
+                            vec![]
+                        });
+                        lines.extend(file_matches.matches.iter().map(|m| m.line_num));
+                    }
+                }
+                PipelineValues::JsonRecords(jr) => {
+                    for file_records in jr.by_file {
+                        records_by_file
+                            .entry(ustr(&file_records.file))
+                            .or_default()
+                            .extend(file_records.records);
+                    }
+                }
+                _ => {
+                    return Err(ServerError::StickyProblem(ErrorDetails {
+                        layer: ErrorLayer::ConfigLayer,
+                        message:
+                            "defs-in-text-matches needs TextMatches and optionally JsonRecords"
+                                .to_string(),
+                    }));
+                }
+            }
+        }
+
+        let mut seen = HashSet::new();
+        let mut symbols = vec![];
+        for file in file_order {
+            let records = match records_by_file.remove(&file) {
+                Some(records) => records,
+                None => match server.fetch_raw_analysis(&file).await {
+                    Ok(stream) => stream.collect().await,
+                    Err(_) => continue,
+                },
+            };
+            let defs = self.extract_defs(records);
+            let lines = lines_by_file.remove(&file).unwrap_or_default();
+            for symbol in self.overlapping_syms(&defs, &lines) {
+                if seen.insert(symbol) {
+                    symbols.push(SymbolWithContext {
+                        symbol,
+                        quality: SymbolQuality::ExplicitSymbol,
+                        from_identifier: None,
+                    });
+                }
+            }
+        }
+
+        Ok(PipelineValues::SymbolList(SymbolList {
+            symbols,
+            overloads: vec![],
+        }))
+    }
+}
+
+#[test]
+fn test_overlapping_syms() {
+    let cmd = DefsInTextMatchesCommand {
+        args: DefsInTextMatches {
+            syntax: None,
+            innermost: false,
+        },
+    };
+    let records = vec![
+        serde_json::json!({"loc": "10:6-11", "source": 1, "syntax": "def,type", "pretty": "type Outer", "sym": "T_Outer", "nestingRange": "10:18-40:0"}),
+        serde_json::json!({"loc": "12:7-12", "source": 1, "syntax": "def,function", "pretty": "function Outer::Inner", "sym": "F_Inner", "nestingRange": "12:20-20:2"}),
+        serde_json::json!({"loc": "15:2-5", "source": 1, "syntax": "use,function", "pretty": "function Foo", "sym": "F_Foo"}),
+        serde_json::json!({"loc": "50:4-8", "source": 1, "syntax": "def,field", "pretty": "field Bar", "sym": "F_Bar"}),
+    ];
+    let defs = cmd.extract_defs(records);
+    assert_eq!(defs.len(), 3);
+
+    assert_eq!(
+        cmd.overlapping_syms(&defs, &[15, 50, 60]),
+        vec![ustr("T_Outer"), ustr("F_Inner"), ustr("F_Bar")]
+    );
+
+    let innermost = DefsInTextMatchesCommand {
+        args: DefsInTextMatches {
+            syntax: None,
+            innermost: true,
+        },
+    };
+    assert_eq!(
+        innermost.overlapping_syms(&defs, &[15]),
+        vec![ustr("F_Inner")]
+    );
+}
@@ -0,0 +1,250 @@
+use async_trait::async_trait;
+use clap::Args;
+use serde_json::{from_str, to_string, to_string_pretty, to_value, Value};
+
+use super::interface::{PipelineCommand, PipelineValues, TextFile};
+use crate::abstract_server::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError};
+
+/// Deep-compare the JSON serialization of the piped-in value against a stored
+/// expectation file, producing a readable list of the differences as a
+/// `TextFile` (which is empty if there are none).  This is intended for golden
+/// test workflows where byte-for-byte comparisons of output are too brittle.
+///
+/// Volatile fields can be excluded from the comparison with `--ignore`.  A
+/// value without a leading "/" ignores all object fields with that name
+/// wherever they appear (ex: "timestamp").  A value with a leading "/" is a
+/// JSON pointer path where a "*" segment matches any single key or index (ex:
+/// "/JsonValueList/values/*/count").
+#[derive(Debug, Args)]
+pub struct DiffAgainst {
+    /// Path to the JSON expectation file.
+    #[clap(long, value_parser)]
+    expected: String,
+
+    /// Fields to exclude from the comparison; see above.
+    #[clap(long, value_parser)]
+    ignore: Vec<String>,
+
+    /// Maximum number of differences to list before summarizing.
+    #[clap(long, value_parser, default_value = "50")]
+    max_diffs: usize,
+
+    /// Fail the pipeline if there are any differences.
+    #[clap(long, action)]
+    fail_on_diff: bool,
+
+    /// Write the current value to the expectation file (with ignored fields
+    /// removed) instead of comparing against it.
+    #[clap(long, action)]
+    bless: bool,
+}
+
+#[derive(Debug)]
+pub struct DiffAgainstCommand {
+    pub args: DiffAgainst,
+}
+
+/// Does the JSON pointer-ish `path` (as segments) match the ignore pattern?
+fn ignore_matches(pattern: &str, path: &[String]) -> bool {
+    match pattern.strip_prefix('/') {
+        Some(pointer) => {
+            let pattern_segments: Vec<&str> = pointer.split('/').collect();
+            pattern_segments.len() == path.len()
+                && pattern_segments
+                    .iter()
+                    .zip(path)
+                    .all(|(pat, seg)| *pat == "*" || pat == seg)
+        }
+        None => path.last().map_or(false, |last| last == pattern),
+    }
+}
+
+/// Remove the ignored fields from the value.  Only object fields are removed;
+/// patterns matching array items are not supported since removing them would
+/// shift the indices of their siblings.
+fn strip_ignored(value: &mut Value, ignore: &[String], path: &mut Vec<String>) {
+    match value {
+        Value::Object(obj) => {
+            obj.retain(|key, _| {
+                path.push(key.clone());
+                let ignored = ignore.iter().any(|pat| ignore_matches(pat, path));
+                path.pop();
+                !ignored
+            });
+            for (key, child) in obj.iter_mut() {
+                path.push(key.clone());
+                strip_ignored(child, ignore, path);
+                path.pop();
+            }
+        }
+        Value::Array(arr) => {
+            for (i, child) in arr.iter_mut().enumerate() {
+                path.push(i.to_string());
+                strip_ignored(child, ignore, path);
+                path.pop();
+            }
+        }
+        _ => {}
+    }
+}
+
+fn format_path(path: &[String]) -> String {
+    if path.is_empty() {
+        "/".to_string()
+    } else {
+        path.iter().map(|seg| format!("/{}", seg)).collect()
+    }
+}
+
+/// A single difference between the expected and actual values, where a None
+/// means the value is absent on that side.
+struct Difference {
+    path: String,
+    expected: Option<Value>,
+    actual: Option<Value>,
+}
+
+fn diff_values(
+    expected: &Value,
+    actual: &Value,
+    path: &mut Vec<String>,
+    diffs: &mut Vec<Difference>,
+) {
+    match (expected, actual) {
+        (Value::Object(exp_obj), Value::Object(act_obj)) => {
+            for (key, exp_child) in exp_obj {
+                path.push(key.clone());
+                match act_obj.get(key) {
+                    Some(act_child) => diff_values(exp_child, act_child, path, diffs),
+                    None => diffs.push(Difference {
+                        path: format_path(path),
+                        expected: Some(exp_child.clone()),
+                        actual: None,
+                    }),
+                }
+                path.pop();
+            }
+            for (key, act_child) in act_obj {
+                if !exp_obj.contains_key(key) {
+                    path.push(key.clone());
+                    diffs.push(Difference {
+                        path: format_path(path),
+                        expected: None,
+                        actual: Some(act_child.clone()),
+                    });
+                    path.pop();
+                }
+            }
+        }
+        (Value::Array(exp_arr), Value::Array(act_arr)) => {
+            for i in 0..exp_arr.len().max(act_arr.len()) {
+                path.push(i.to_string());
+                match (exp_arr.get(i), act_arr.get(i)) {
+                    (Some(exp_child), Some(act_child)) => {
+                        diff_values(exp_child, act_child, path, diffs)
+                    }
+                    (exp_child, act_child) => diffs.push(Difference {
+                        path: format_path(path),
+                        expected: exp_child.cloned(),
+                        actual: act_child.cloned(),
+                    }),
+                }
+                path.pop();
+            }
+        }
+        _ => {
+            if expected != actual {
+                diffs.push(Difference {
+                    path: format_path(path),
+                    expected: Some(expected.clone()),
+                    actual: Some(actual.clone()),
+                });
+            }
+        }
+    }
+}
+
+fn render_diffs(expected_path: &str, diffs: &[Difference], max_diffs: usize) -> String {
+    if diffs.is_empty() {
+        return String::new();
+    }
+    let mut lines = vec![format!("--- {}", expected_path), "+++ actual".to_string()];
+    for diff in diffs.iter().take(max_diffs) {
+        lines.push(format!("@ {}", diff.path));
+        if let Some(expected) = &diff.expected {
+            lines.push(format!("- {}", to_string(expected).unwrap_or_default()));
+        }
+        if let Some(actual) = &diff.actual {
+            lines.push(format!("+ {}", to_string(actual).unwrap_or_default()));
+        }
+    }
+    if diffs.len() > max_diffs {
+        lines.push(format!(
+            "... and {} more differences",
+            diffs.len() - max_diffs
+        ));
+    }
+    lines.join("\n") + "\n"
+}
+
+#[async_trait]
+impl PipelineCommand for DiffAgainstCommand {
+    async fn execute(
+        &self,
+        _server: &(dyn AbstractServer + Send + Sync),
+        input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        let mut actual = to_value(&input)?;
+        strip_ignored(&mut actual, &self.args.ignore, &mut vec![]);
+
+        if self.args.bless {
+            std::fs::write(&self.args.expected, to_string_pretty(&actual)? + "\n")?;
+            return Ok(PipelineValues::TextFile(TextFile {
+                mime_type: "text/plain".to_string(),
+                contents: String::new(),
+            }));
+        }
+
+        let mut expected: Value = from_str(&std::fs::read_to_string(&self.args.expected)?)?;
+        strip_ignored(&mut expected, &self.args.ignore, &mut vec![]);
+
+        let mut diffs = vec![];
+        diff_values(&expected, &actual, &mut vec![], &mut diffs);
+        let contents = render_diffs(&self.args.expected, &diffs, self.args.max_diffs);
+
+        if self.args.fail_on_diff && !diffs.is_empty() {
+            return Err(ServerError::StickyProblem(ErrorDetails {
+                layer: ErrorLayer::DataLayer,
+                message: contents,
+            }));
+        }
+
+        Ok(PipelineValues::TextFile(TextFile {
+            mime_type: "text/plain".to_string(),
+            contents,
+        }))
+    }
+}
+
+#[test]
+fn test_diff_values() {
+    let ignore = vec!["timestamp".to_string(), "/results/*/count".to_string()];
+    let mut expected = serde_json::json!({
+        "timestamp": 1,
+        "results": [{ "path": "a.cpp", "count": 3 }, { "path": "b.cpp", "count": 1 }],
+    });
+    let mut actual = serde_json::json!({
+        "timestamp": 2,
+        "results": [{ "path": "a.cpp", "count": 4 }, { "path": "c.cpp", "count": 1 }],
+        "extra": true,
+    });
+    strip_ignored(&mut expected, &ignore, &mut vec![]);
+    strip_ignored(&mut actual, &ignore, &mut vec![]);
+
+    let mut diffs = vec![];
+    diff_values(&expected, &actual, &mut vec![], &mut diffs);
+    assert_eq!(
+        render_diffs("expected.json", &diffs, 50),
+        "--- expected.json\n+++ actual\n@ /results/1/path\n- \"b.cpp\"\n+ \"c.cpp\"\n@ /extra\n+ true\n"
+    );
+}
@@ -0,0 +1,344 @@
+use std::collections::{BTreeMap, HashMap};
+
+use async_trait::async_trait;
+use clap::Args;
+use serde::Serialize;
+use serde_json::to_value;
+use tokio_stream::StreamExt;
+use ustr::Ustr;
+
+use super::{
+    aggregates::symbol_aggregates,
+    cmd_defs_in_text_matches::{extract_def_extents, DefExtent},
+    interface::{JsonValue, JsonValueList, PipelineCommand, PipelineValues},
+};
+
+use crate::{
+    abstract_server::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError},
+    file_format::crossref_record::CrossrefRecord,
+};
+
+/// Summarize the "blast radius" of a unified diff against the indexed
+/// revision: map the lines the diff adds and removes to the definitions
+/// containing them via the analysis records and report the touched symbols
+/// along with how many callers and uses they have, most-called first.
+///
+/// The diff must be against the indexed revision since removed lines are
+/// located by their line numbers in the old file.  Added lines only touch a
+/// definition if they are inserted inside it (and not just between two
+/// definitions).  Newly added files and files without analysis (ex: text
+/// files) have no symbols and are skipped.
+///
+/// By default only the innermost definitions with structured records (ex:
+/// classes, methods, fields) are reported, excluding local variables and the
+/// like.
+///
+/// Produces a `JsonValueList` of `{ sym, pretty, kind, path, addedLines,
+/// removedLines, callerCount, useCount }`.  The counts come from the
+/// precomputed aggregates store when the symbol is in it.
+#[derive(Debug, Args)]
+pub struct DiffSymbols {
+    /// Path to the unified diff (ex: from `git diff` or `hg diff`).
+    #[clap(long, value_parser)]
+    patch: String,
+
+    /// Number of leading path components to strip from the paths in the diff,
+    /// like `patch -p`.  The default handles the "a/" and "b/" prefixes of
+    /// git-style diffs.
+    #[clap(short = 'p', long, value_parser, default_value = "1")]
+    strip: usize,
+
+    /// Also report the definitions enclosing the innermost touched definitions
+    /// (ex: the class of a touched method).
+    #[clap(long, action)]
+    enclosing: bool,
+
+    /// Also report symbols without structured records, like local variables.
+    #[clap(long, action)]
+    all_symbols: bool,
+}
+
+#[derive(Debug)]
+pub struct DiffSymbolsCommand {
+    pub args: DiffSymbols,
+}
+
+/// The changes a diff makes to a single file, in terms of the old file's line
+/// numbers.
+#[derive(Debug, Default, PartialEq)]
+struct FileChanges {
+    path: String,
+    /// The removed lines.
+    removed: Vec<u32>,
+    /// The (previous, next) old lines that added lines were inserted between.
+    added: Vec<(u32, u32)>,
+}
+
+fn strip_path(path: &str, strip: usize) -> String {
+    // Diffs may have a tab-separated timestamp after the path.
+    let path = path.split('\t').next().unwrap_or_default().trim();
+    path.splitn(strip + 1, '/')
+        .last()
+        .unwrap_or(path)
+        .to_string()
+}
+
+/// Parse "@@ -1,2 +3,4 @@" into the old start line and the old and new line
+/// counts.
+fn parse_hunk_header(line: &str) -> Option<(u32, u32, u32)> {
+    let mut pieces = line.strip_prefix("@@ ")?.split(' ');
+    let parse_range = |range: &str| -> Option<(u32, u32)> {
+        match range.split_once(',') {
+            Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+            None => Some((range.parse().ok()?, 1)),
+        }
+    };
+    let (old_start, old_count) = parse_range(pieces.next()?.strip_prefix('-')?)?;
+    let (_, new_count) = parse_range(pieces.next()?.strip_prefix('+')?)?;
+    Some((old_start, old_count, new_count))
+}
+
+fn parse_unified_diff(diff: &str, strip: usize) -> Vec<FileChanges> {
+    let mut files: Vec<FileChanges> = vec![];
+    // The next old line number and how many old/new lines remain in the hunk.
+    let mut old_lno = 0;
+    let mut old_remaining = 0;
+    let mut new_remaining = 0;
+    // Are we in a file whose changes we care about?
+    let mut in_file = false;
+
+    for line in diff.lines() {
+        if old_remaining > 0 || new_remaining > 0 {
+            if let Some(file) = files.last_mut().filter(|_| in_file) {
+                match line.chars().next() {
+                    Some('-') => file.removed.push(old_lno),
+                    Some('+') => file.added.push((old_lno.saturating_sub(1), old_lno)),
+                    _ => {}
+                }
+            }
+            // Be robust against hunks whose counts don't match their lines.
+            match line.chars().next() {
+                Some('-') => {
+                    old_lno += 1;
+                    old_remaining = old_remaining.saturating_sub(1);
+                }
+                Some('+') => new_remaining = new_remaining.saturating_sub(1),
+                Some('\\') => {}
+                _ => {
+                    old_lno += 1;
+                    old_remaining = old_remaining.saturating_sub(1);
+                    new_remaining = new_remaining.saturating_sub(1);
+                }
+            }
+            continue;
+        }
+
+        if let Some(old_path) = line.strip_prefix("--- ") {
+            // New files have an old path of /dev/null and no analysis.
+            in_file = old_path.trim() != "/dev/null";
+            if in_file {
+                files.push(FileChanges {
+                    path: strip_path(old_path, strip),
+                    ..FileChanges::default()
+                });
+            }
+        } else if let Some((old_start, old_count, new_count)) = parse_hunk_header(line) {
+            // A count of 0 means the start is the line before the change.
+            old_lno = if old_count == 0 {
+                old_start + 1
+            } else {
+                old_start
+            };
+            old_remaining = old_count;
+            new_remaining = new_count;
+        }
+    }
+
+    files
+}
+
+/// Does the definition contain the touch?  For removed lines, the previous and
+/// next lines are both the removed line.
+fn def_contains(def: &DefExtent, (prev, next): (u32, u32)) -> bool {
+    def.start_lineno <= prev && next <= def.end_lineno
+}
+
+#[derive(Default)]
+struct TouchedSymbol {
+    pretty: Ustr,
+    path: String,
+    added_lines: usize,
+    removed_lines: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DiffSymbol {
+    sym: Ustr,
+    pretty: Ustr,
+    kind: Option<Ustr>,
+    path: String,
+    added_lines: usize,
+    removed_lines: usize,
+    caller_count: usize,
+    use_count: usize,
+}
+
+/// The symbols touched so far, along with the crossref records of all the
+/// symbols we've looked up, or None if the symbol isn't one we report.
+#[derive(Default)]
+struct TouchState {
+    crossrefs: HashMap<Ustr, Option<CrossrefRecord>>,
+    touched: BTreeMap<Ustr, TouchedSymbol>,
+}
+
+impl DiffSymbolsCommand {
+    /// Record the symbols of the definitions containing the touch, which is
+    /// either an added or removed line, innermost first.  Symbols we don't
+    /// report (ex: local variables) are skipped so that the innermost reported
+    /// definition is the one that counts.
+    async fn touch(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        state: &mut TouchState,
+        defs: &[DefExtent],
+        path: &str,
+        lines: (u32, u32),
+        added: bool,
+    ) -> Result<()> {
+        let mut containing: Vec<&DefExtent> =
+            defs.iter().filter(|def| def_contains(def, lines)).collect();
+        containing.sort_by_key(|def| def.end_lineno - def.start_lineno);
+
+        for def in containing {
+            let mut reported = false;
+            for sym in &def.syms {
+                if !state.crossrefs.contains_key(sym) {
+                    let crossref =
+                        CrossrefRecord::from_value(server.crossref_lookup(sym, false).await?)?;
+                    let reportable = crossref.meta.is_some() || self.args.all_symbols;
+                    state
+                        .crossrefs
+                        .insert(*sym, Some(crossref).filter(|_| reportable));
+                }
+                if state.crossrefs[sym].is_none() {
+                    continue;
+                }
+                reported = true;
+                let entry = state.touched.entry(*sym).or_insert_with(|| TouchedSymbol {
+                    pretty: def.pretty,
+                    path: path.to_string(),
+                    ..TouchedSymbol::default()
+                });
+                if added {
+                    entry.added_lines += 1;
+                } else {
+                    entry.removed_lines += 1;
+                }
+            }
+            if reported && !self.args.enclosing {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl PipelineCommand for DiffSymbolsCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        if !matches!(input, PipelineValues::Void) {
+            return Err(ServerError::StickyProblem(ErrorDetails {
+                layer: ErrorLayer::ConfigLayer,
+                message: "diff-symbols needs Void".to_string(),
+            }));
+        }
+
+        let diff = std::fs::read_to_string(&self.args.patch)?;
+        let mut state = TouchState::default();
+        for file in parse_unified_diff(&diff, self.args.strip) {
+            let records = match server.fetch_raw_analysis(&file.path).await {
+                Ok(stream) => stream.collect().await,
+                Err(_) => continue,
+            };
+            let defs = extract_def_extents(records, None);
+            let touches = file
+                .removed
+                .iter()
+                .map(|&lno| ((lno, lno), false))
+                .chain(file.added.iter().map(|&lines| (lines, true)));
+            for (lines, added) in touches {
+                self.touch(server, &mut state, &defs, &file.path, lines, added)
+                    .await?;
+            }
+        }
+
+        let mut symbols = vec![];
+        for (sym, info) in state.touched {
+            let crossref = match state.crossrefs.remove(&sym).flatten() {
+                Some(crossref) => crossref,
+                None => continue,
+            };
+            let aggregates = symbol_aggregates(server, &sym, &crossref, 0).await?;
+            symbols.push(DiffSymbol {
+                sym,
+                pretty: info.pretty,
+                kind: crossref.meta.as_ref().map(|meta| meta.kind),
+                path: info.path,
+                added_lines: info.added_lines,
+                removed_lines: info.removed_lines,
+                caller_count: aggregates.caller_count.unwrap_or_default(),
+                use_count: aggregates.use_count.unwrap_or_default(),
+            });
+        }
+        symbols.sort_by(|a, b| {
+            b.caller_count
+                .cmp(&a.caller_count)
+                .then_with(|| a.path.cmp(&b.path))
+                .then_with(|| a.sym.cmp(&b.sym))
+        });
+
+        let mut values = vec![];
+        for symbol in symbols {
+            values.push(JsonValue {
+                value: to_value(symbol)?,
+            });
+        }
+        Ok(PipelineValues::JsonValueList(JsonValueList { values }))
+    }
+}
+
+#[test]
+fn test_parse_unified_diff() {
+    let diff = "\
+diff --git a/dom/Foo.cpp b/dom/Foo.cpp
+--- a/dom/Foo.cpp
++++ b/dom/Foo.cpp
+@@ -10,3 +10,4 @@ void Foo::Bar() {
+ context
+--- removed line that looks like a header
++added
++added
+ context
+@@ -20,0 +22,1 @@
++inserted
+diff --git a/dom/New.cpp b/dom/New.cpp
+--- /dev/null
++++ b/dom/New.cpp
+@@ -0,0 +1,1 @@
++new file
+";
+    assert_eq!(
+        parse_unified_diff(diff, 1),
+        vec![FileChanges {
+            path: "dom/Foo.cpp".to_string(),
+            removed: vec![11],
+            added: vec![(11, 12), (11, 12), (20, 21)],
+        }]
+    );
+    assert_eq!(parse_hunk_header("@@ -5 +5,2 @@"), Some((5, 1, 2)));
+}
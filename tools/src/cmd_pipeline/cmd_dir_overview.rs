@@ -0,0 +1,265 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use async_trait::async_trait;
+use clap::Args;
+use serde::Serialize;
+use serde_json::{json, to_value, Value};
+use tokio_stream::StreamExt;
+use ustr::Ustr;
+
+use super::{
+    cmd_index_stats::language_for_path,
+    cmd_module_api::is_in_module,
+    interface::{JsonValue, PipelineCommand, PipelineValues},
+    progress::ProgressTracker,
+};
+
+use crate::{
+    abstract_server::{AbstractServer, Result, ServerError},
+    file_format::crossref_record::CrossrefRecord,
+};
+
+/// Summarize a directory for the benefit of directory listing pages: file
+/// counts by language and path kind, the top-level classes and functions
+/// defined in it, its symbols that are most referenced from outside of it, and
+/// its recent blame activity.
+///
+/// Produces a JsonValue of the form `{ path, files, definitions,
+/// externallyReferenced, recentChanges }` where `recentChanges` is null if the
+/// tree has no blame data.
+///
+/// Like `module-api`, this scans the entire crossref database and so requires
+/// a local index.
+#[derive(Debug, Args)]
+pub struct DirOverview {
+    /// The directory to summarize (ex: "dom/media/").  Paths are matched on
+    /// path segment boundaries, so "dom/media" doesn't include
+    /// "dom/mediasession/".
+    #[clap(value_parser)]
+    path: String,
+
+    /// Maximum number of top-level definitions to list.  0 means no limit.
+    #[clap(long, value_parser, default_value = "50")]
+    definition_limit: usize,
+
+    /// Maximum number of externally referenced symbols to list.  0 means no
+    /// limit.
+    #[clap(long, value_parser, default_value = "20")]
+    referenced_limit: usize,
+
+    /// Maximum number of recent changes to list.
+    #[clap(long, value_parser, default_value = "10")]
+    change_limit: usize,
+
+    /// Maximum number of files to consult blame for, largest first, since blame
+    /// has to be read per file.  0 disables the blame summary.
+    #[clap(long, value_parser, default_value = "100")]
+    blame_file_limit: usize,
+}
+
+#[derive(Debug)]
+pub struct DirOverviewCommand {
+    pub args: DirOverview,
+}
+
+/// The structured kinds we consider to be interesting top-level definitions.
+const DEFINITION_KINDS: &[&str] = &["class", "struct", "union", "enum", "function"];
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DirDefinition {
+    sym: String,
+    pretty: Ustr,
+    kind: Ustr,
+    path: Ustr,
+    lno: u32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DirReferencedSymbol {
+    sym: String,
+    pretty: Ustr,
+    kind: Ustr,
+    path: Ustr,
+    lno: u32,
+    /// Number of uses from files outside of the directory.
+    external_uses: usize,
+    /// Number of distinct files outside of the directory using the symbol.
+    external_files: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DirChange {
+    rev: String,
+    author: String,
+    date: String,
+    #[serde(skip)]
+    timestamp: i64,
+    bug: Option<u32>,
+    summary: String,
+    /// Number of lines in the directory still attributed to the revision.
+    lines: usize,
+    /// Paths of the files in the directory with lines attributed to the
+    /// revision.
+    files: BTreeSet<String>,
+}
+
+#[async_trait]
+impl PipelineCommand for DirOverviewCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        _input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        let dir = self.args.path.trim_end_matches('/');
+        let pathre = if dir.is_empty() {
+            "".to_string()
+        } else {
+            format!("^{}/", regex::escape(dir))
+        };
+        let mut files = server.search_files(&pathre, false, usize::MAX).await?;
+
+        let mut by_language: BTreeMap<&str, u64> = BTreeMap::new();
+        let mut by_path_kind: BTreeMap<Ustr, u64> = BTreeMap::new();
+        for file in &files.file_matches {
+            *by_language
+                .entry(language_for_path(&file.path))
+                .or_default() += 1;
+            *by_path_kind.entry(file.concise.path_kind).or_default() += 1;
+        }
+
+        // ## Definitions and external references
+        let mut definitions = vec![];
+        let mut referenced = vec![];
+        let mut records = server.crossref_scan().await?;
+        let mut progress = ProgressTracker::new("dir-overview", None);
+        while let Some(entry) = records.next().await {
+            let (sym, value) = entry?;
+            progress.advance(1);
+            if value.is_null() {
+                continue;
+            }
+            let record = CrossrefRecord::from_value(value)?;
+            let (meta, def_path, def_line) = match (
+                record.meta.as_ref(),
+                record.defs.first(),
+                record.defs.first().and_then(|p| p.lines.first()),
+            ) {
+                (Some(meta), Some(def_path), Some(def_line)) => (meta, def_path, def_line),
+                _ => continue,
+            };
+            if !is_in_module(&def_path.path, dir) {
+                continue;
+            }
+
+            if meta.parent_sym.is_none() && DEFINITION_KINDS.contains(&meta.kind.as_str()) {
+                definitions.push(DirDefinition {
+                    sym: sym.clone(),
+                    pretty: meta.pretty,
+                    kind: meta.kind,
+                    path: def_path.path,
+                    lno: def_line.lineno,
+                });
+            }
+
+            let mut external_uses = 0;
+            let mut external_files = BTreeSet::new();
+            for path_container in &record.uses {
+                if !is_in_module(&path_container.path, dir) {
+                    external_uses += path_container.lines.len();
+                    external_files.insert(path_container.path);
+                }
+            }
+            if external_uses > 0 {
+                referenced.push(DirReferencedSymbol {
+                    sym,
+                    pretty: meta.pretty,
+                    kind: meta.kind,
+                    path: def_path.path,
+                    lno: def_line.lineno,
+                    external_uses,
+                    external_files: external_files.len(),
+                });
+            }
+        }
+        progress.finish();
+
+        definitions.sort_by(|a, b| a.path.cmp(&b.path).then_with(|| a.lno.cmp(&b.lno)));
+        if self.args.definition_limit > 0 {
+            definitions.truncate(self.args.definition_limit);
+        }
+        referenced.sort_by(|a, b| {
+            b.external_uses
+                .cmp(&a.external_uses)
+                .then_with(|| a.path.cmp(&b.path))
+                .then_with(|| a.lno.cmp(&b.lno))
+        });
+        if self.args.referenced_limit > 0 {
+            referenced.truncate(self.args.referenced_limit);
+        }
+
+        // ## Blame activity
+        //
+        // We attribute each line of the largest files to the revision that
+        // last changed it and list the most recent of those revisions.
+        files
+            .file_matches
+            .sort_by(|a, b| b.concise.file_size.cmp(&a.concise.file_size));
+        let mut changes: HashMap<String, DirChange> = HashMap::new();
+        let mut have_blame = self.args.blame_file_limit > 0;
+        for file in files.file_matches.iter().take(self.args.blame_file_limit) {
+            let lines = match server.fetch_blame_lines(&file.path, None).await {
+                Ok(lines) => lines,
+                Err(ServerError::Unsupported) => {
+                    have_blame = false;
+                    break;
+                }
+                Err(err) => return Err(err),
+            };
+            for line in lines {
+                let change = changes
+                    .entry(line.rev.clone())
+                    .or_insert_with(|| DirChange {
+                        rev: line.rev,
+                        author: line.author,
+                        date: line.date,
+                        timestamp: line.timestamp,
+                        bug: line.bug,
+                        summary: line.summary,
+                        lines: 0,
+                        files: BTreeSet::new(),
+                    });
+                change.lines += 1;
+                change.files.insert(file.path.to_string());
+            }
+        }
+        let recent_changes = if have_blame {
+            let mut changes: Vec<DirChange> = changes.into_values().collect();
+            changes.sort_by(|a, b| {
+                b.timestamp
+                    .cmp(&a.timestamp)
+                    .then_with(|| a.rev.cmp(&b.rev))
+            });
+            changes.truncate(self.args.change_limit);
+            to_value(changes)?
+        } else {
+            Value::Null
+        };
+
+        Ok(PipelineValues::JsonValue(JsonValue {
+            value: json!({
+                "path": dir,
+                "files": {
+                    "total": files.file_matches.len(),
+                    "byLanguage": by_language,
+                    "byPathKind": by_path_kind,
+                },
+                "definitions": to_value(definitions)?,
+                "externallyReferenced": to_value(referenced)?,
+                "recentChanges": recent_changes,
+            }),
+        }))
+    }
+}
@@ -0,0 +1,215 @@
+use async_trait::async_trait;
+use clap::Args;
+use serde::Serialize;
+use serde_json::to_value;
+use ustr::{ustr, Ustr};
+
+use super::interface::{JsonValue, JsonValueList, PipelineCommand, PipelineValues};
+
+use crate::{
+    abstract_server::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError},
+    file_format::crossref_record::CrossrefRecord,
+};
+
+/// Given one or more enums, list their variants from the structured
+/// `enumerators` data along with the number and locations of the uses of each
+/// variant, flagging the variants that have no uses.  This is useful for
+/// pruning dead enum values and for understanding how many of the values a
+/// `switch` needs to handle actually show up.
+///
+/// Enums can be specified via `--symbol` or piped in as a
+/// `SymbolCrossrefInfoList` (ex: from `crossref-lookup`), in which case any
+/// symbols which aren't enums are ignored.
+///
+/// Produces a `JsonValueList` with one `{ sym, pretty, variantCount,
+/// unusedCount, variants }` value per enum, where each variant is `{ sym,
+/// pretty, value, useCount, unused, uses }` and `uses` is a list of `{ path,
+/// lines }`.
+#[derive(Debug, Args)]
+pub struct EnumUsage {
+    /// Explicit enum symbols to report on.
+    #[clap(short, long, value_parser)]
+    symbol: Vec<String>,
+
+    /// Only report variants with no uses.
+    #[clap(long, action)]
+    unused_only: bool,
+
+    /// Maximum number of use locations to list per variant; the `useCount` is
+    /// always the total.
+    #[clap(long, value_parser, default_value = "50")]
+    location_limit: usize,
+}
+
+#[derive(Debug)]
+pub struct EnumUsageCommand {
+    pub args: EnumUsage,
+}
+
+#[derive(Serialize)]
+struct VariantUses {
+    path: Ustr,
+    lines: Vec<u32>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct VariantUsage {
+    sym: Ustr,
+    pretty: Ustr,
+    value: Option<i64>,
+    use_count: usize,
+    unused: bool,
+    uses: Vec<VariantUses>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EnumUsageInfo {
+    sym: Ustr,
+    pretty: Ustr,
+    variant_count: usize,
+    unused_count: usize,
+    variants: Vec<VariantUsage>,
+}
+
+/// Collect the use locations of a variant, listing at most `limit` of them,
+/// and return them along with the total number of uses.
+fn collect_uses(crossref: &CrossrefRecord, limit: usize) -> (Vec<VariantUses>, usize) {
+    let mut uses = vec![];
+    let mut listed = 0;
+    let mut use_count = 0;
+    for path_hits in &crossref.uses {
+        use_count += path_hits.lines.len();
+        if listed >= limit {
+            continue;
+        }
+        let lines: Vec<u32> = path_hits
+            .lines
+            .iter()
+            .take(limit - listed)
+            .map(|hit| hit.lineno)
+            .collect();
+        listed += lines.len();
+        uses.push(VariantUses {
+            path: path_hits.path,
+            lines,
+        });
+    }
+    (uses, use_count)
+}
+
+impl EnumUsageCommand {
+    async fn enum_usage(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        sym: Ustr,
+        crossref: &CrossrefRecord,
+    ) -> Result<Option<EnumUsageInfo>> {
+        let meta = match &crossref.meta {
+            Some(meta) if meta.kind == "enum" => meta,
+            _ => return Ok(None),
+        };
+
+        let mut variants = vec![];
+        let mut unused_count = 0;
+        for enumerator in &meta.enumerators {
+            let info = server.crossref_lookup(&enumerator.sym, false).await?;
+            let (uses, use_count) = if info.is_null() {
+                (vec![], 0)
+            } else {
+                collect_uses(&CrossrefRecord::from_value(info)?, self.args.location_limit)
+            };
+            let unused = use_count == 0;
+            if unused {
+                unused_count += 1;
+            } else if self.args.unused_only {
+                continue;
+            }
+            variants.push(VariantUsage {
+                sym: enumerator.sym,
+                pretty: enumerator.pretty,
+                value: enumerator.value,
+                use_count,
+                unused,
+                uses,
+            });
+        }
+
+        Ok(Some(EnumUsageInfo {
+            sym,
+            pretty: meta.pretty,
+            variant_count: meta.enumerators.len(),
+            unused_count,
+            variants,
+        }))
+    }
+}
+
+#[async_trait]
+impl PipelineCommand for EnumUsageCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        let mut enums = vec![];
+        match input {
+            PipelineValues::SymbolCrossrefInfoList(list) => {
+                for info in list.symbol_crossref_infos {
+                    enums.push((info.symbol, info.crossref_info));
+                }
+            }
+            PipelineValues::Void => {
+                for symbol in &self.args.symbol {
+                    let info = server.crossref_lookup(symbol, false).await?;
+                    if info.is_null() {
+                        return Err(ServerError::StickyProblem(ErrorDetails {
+                            layer: ErrorLayer::BadInput,
+                            message: format!("enum-usage: unknown symbol {}", symbol),
+                        }));
+                    }
+                    // It's safe to mint the Ustr now that we know it exists.
+                    enums.push((ustr(symbol), CrossrefRecord::from_value(info)?));
+                }
+            }
+            _ => {
+                return Err(ServerError::StickyProblem(ErrorDetails {
+                    layer: ErrorLayer::ConfigLayer,
+                    message: "enum-usage needs a Void or SymbolCrossrefInfoList".to_string(),
+                }));
+            }
+        }
+
+        let mut values = vec![];
+        for (sym, crossref) in enums {
+            if let Some(usage) = self.enum_usage(server, sym, &crossref).await? {
+                values.push(JsonValue {
+                    value: to_value(usage)?,
+                });
+            }
+        }
+        Ok(PipelineValues::JsonValueList(JsonValueList { values }))
+    }
+}
+
+#[test]
+fn test_collect_uses() {
+    let hit = |lno: u32| serde_json::json!({"lno": lno, "bounds": [0, 3], "line": "Foo", "context": "", "contextsym": ""});
+    let crossref = CrossrefRecord::from_value(serde_json::json!({
+        "uses": [
+            {"path": "a.cpp", "path_kind": "Normal", "lines": [hit(1), hit(5)]},
+            {"path": "b.cpp", "path_kind": "Normal", "lines": [hit(2)]},
+        ],
+    }))
+    .unwrap();
+
+    let (uses, use_count) = collect_uses(&crossref, 2);
+    assert_eq!(use_count, 3);
+    assert_eq!(uses.len(), 1);
+    assert_eq!(uses[0].lines, vec![1, 5]);
+
+    let (uses, use_count) = collect_uses(&CrossrefRecord::default(), 2);
+    assert_eq!(use_count, 0);
+    assert!(uses.is_empty());
+}
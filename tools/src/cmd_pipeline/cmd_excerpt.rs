@@ -0,0 +1,190 @@
+use std::collections::{BTreeMap, HashSet};
+
+use async_trait::async_trait;
+use clap::Args;
+
+use super::{
+    cmd_show_html::extract_html_lines,
+    interface::{HtmlExcerpts, HtmlExcerptsByFile, PipelineCommand, PipelineValues},
+};
+use crate::abstract_server::{
+    AbstractServer, ErrorDetails, ErrorLayer, HtmlFileRoot, Result, ServerError,
+};
+
+/// Produce `HtmlExcerpts` of the rendered source for arbitrary sets of lines,
+/// independent of a search result bundle, so that tools can fetch pretty
+/// excerpts on demand (ex: for hover cards).  Each excerpt is the HTML of a
+/// contiguous run of lines, so lines whose context overlaps are merged into a
+/// single excerpt.
+///
+/// Lines can be specified explicitly via `--path` and `--lines`, or files can
+/// be piped in as `FileMatches` in which case `--lines` is used for every
+/// file, or as `TextMatches` in which case the matched lines are used.
+#[derive(Debug, Args)]
+pub struct Excerpt {
+    /// Tree-relative source file path when nothing is piped in.
+    #[clap(long, value_parser)]
+    path: Option<String>,
+
+    /// Comma-separated 1-based lines and inclusive line ranges to excerpt, like
+    /// "10,20-25".
+    #[clap(long, value_parser)]
+    lines: Option<String>,
+
+    /// Number of lines of context to include before and after each line.
+    #[clap(short = 'C', long, value_parser, default_value = "0")]
+    context: u32,
+}
+
+#[derive(Debug)]
+pub struct ExcerptCommand {
+    pub args: Excerpt,
+}
+
+fn parse_line_set(spec: &str) -> Option<Vec<u32>> {
+    let mut lines = vec![];
+    for piece in spec.split(',') {
+        let (first, last) = match piece.split_once('-') {
+            Some((first, last)) => (first.trim().parse().ok()?, last.trim().parse().ok()?),
+            None => {
+                let lno = piece.trim().parse().ok()?;
+                (lno, lno)
+            }
+        };
+        if first == 0 || last < first {
+            return None;
+        }
+        lines.extend(first..=last);
+    }
+    Some(lines)
+}
+
+fn with_context(lines: &[u32], context: u32) -> HashSet<u32> {
+    let mut wanted = HashSet::new();
+    for &lno in lines {
+        wanted.extend(lno.saturating_sub(context).max(1)..=lno.saturating_add(context));
+    }
+    wanted
+}
+
+/// Concatenate the HTML of consecutive lines into a single excerpt per run.
+fn group_runs(html_lines: Vec<(u32, String)>) -> Vec<String> {
+    let mut excerpts: Vec<String> = vec![];
+    let mut last_lno = 0;
+    for (lno, html) in html_lines {
+        match excerpts.last_mut() {
+            Some(excerpt) if lno == last_lno + 1 => excerpt.push_str(&html),
+            _ => excerpts.push(html),
+        }
+        last_lno = lno;
+    }
+    excerpts
+}
+
+impl ExcerptCommand {
+    fn explicit_lines(&self) -> Result<Vec<u32>> {
+        let spec = match &self.args.lines {
+            Some(spec) => spec,
+            None => {
+                return Err(ServerError::StickyProblem(ErrorDetails {
+                    layer: ErrorLayer::BadInput,
+                    message: "excerpt needs --lines unless TextMatches are piped in".to_string(),
+                }))
+            }
+        };
+        parse_line_set(spec).ok_or_else(|| {
+            ServerError::StickyProblem(ErrorDetails {
+                layer: ErrorLayer::BadInput,
+                message: format!("bad --lines value: {}", spec),
+            })
+        })
+    }
+}
+
+#[async_trait]
+impl PipelineCommand for ExcerptCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        // We use a BTreeMap for the lines so that multiple matches in the same
+        // file get merged, but track the order files were first seen in.
+        let mut file_order: Vec<String> = vec![];
+        let mut lines_by_file: BTreeMap<String, Vec<u32>> = BTreeMap::new();
+        let mut add_lines = |file: &str, lines: &[u32]| {
+            lines_by_file
+                .entry(file.to_string())
+                .or_insert_with(|| {
+                    file_order.push(file.to_string());
+                    vec![]
+                })
+                .extend_from_slice(lines);
+        };
+
+        match input {
+            PipelineValues::Void => match &self.args.path {
+                Some(path) => add_lines(path, &self.explicit_lines()?),
+                None => {
+                    return Err(ServerError::StickyProblem(ErrorDetails {
+                        layer: ErrorLayer::BadInput,
+                        message: "excerpt needs --path if nothing is piped in".to_string(),
+                    }));
+                }
+            },
+            PipelineValues::FileMatches(fm) => {
+                let lines = self.explicit_lines()?;
+                for file_match in fm.file_matches {
+                    add_lines(&file_match.path, &lines);
+                }
+            }
+            PipelineValues::TextMatches(tm) => {
+                for file_matches in tm.by_file {
+                    let lines: Vec<u32> = file_matches.matches.iter().map(|m| m.line_num).collect();
+                    add_lines(&file_matches.file, &lines);
+                }
+            }
+            _ => {
+                return Err(ServerError::StickyProblem(ErrorDetails {
+                    layer: ErrorLayer::ConfigLayer,
+                    message: "excerpt needs Void, FileMatches, or TextMatches".to_string(),
+                }));
+            }
+        }
+
+        let mut by_file = vec![];
+        for file in file_order {
+            let lines = lines_by_file.remove(&file).unwrap_or_default();
+            let wanted = with_context(&lines, self.args.context);
+            let html_str = server
+                .fetch_html(HtmlFileRoot::FormattedFile, &file)
+                .await?;
+            by_file.push(HtmlExcerptsByFile {
+                excerpts: group_runs(extract_html_lines(&html_str, &wanted)),
+                file,
+            });
+        }
+
+        Ok(PipelineValues::HtmlExcerpts(HtmlExcerpts { by_file }))
+    }
+}
+
+#[test]
+fn test_excerpt_lines() {
+    assert_eq!(parse_line_set("10,20-22"), Some(vec![10, 20, 21, 22]));
+    assert_eq!(parse_line_set("0"), None);
+    assert_eq!(parse_line_set("5-3"), None);
+
+    let mut wanted: Vec<u32> = with_context(&[2, 10], 2).into_iter().collect();
+    wanted.sort();
+    assert_eq!(wanted, vec![1, 2, 3, 4, 8, 9, 10, 11, 12]);
+
+    assert_eq!(
+        group_runs(vec![
+            (1, "a".to_string()),
+            (2, "b".to_string()),
+            (5, "c".to_string()),
+        ]),
+        vec!["ab".to_string(), "c".to_string()]
+    );
+}
@@ -0,0 +1,308 @@
+use std::collections::HashSet;
+
+use async_trait::async_trait;
+use clap::{Args, ValueEnum};
+use serde_json::{from_str, json, Map, Value};
+use ustr::{ustr, Ustr};
+
+use super::{
+    interface::{JsonValue, PipelineCommand, PipelineValues},
+    warnings::add_pipeline_warning,
+};
+
+use crate::{
+    abstract_server::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError},
+    file_format::crossref_record::CrossrefRecord,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ExpandEdge {
+    /// From the node to the symbols it calls.
+    Callees,
+    /// From the symbols that use the node to the node.
+    Uses,
+    /// From subclasses and overrides to the node, and from the node to its
+    /// superclasses and the methods it overrides.
+    Inheritance,
+    /// From the node (a class) to its methods.
+    Class,
+    /// From the node (a macro) to the symbols its expansions produced.
+    ExpandsTo,
+    /// From the node to the macros that produced it.
+    ExpandedFrom,
+}
+
+impl ExpandEdge {
+    fn name(&self) -> &'static str {
+        match self {
+            ExpandEdge::Callees => "callees",
+            ExpandEdge::Uses => "uses",
+            ExpandEdge::Inheritance => "inheritance",
+            ExpandEdge::Class => "class",
+            ExpandEdge::ExpandsTo => "expands-to",
+            ExpandEdge::ExpandedFrom => "expanded-from",
+        }
+    }
+}
+
+/// Expand a single node of an existing graph by one hop, returning only the
+/// nodes and edges that aren't already in the graph so that graph UIs can
+/// grow a graph as the user clicks on nodes without re-running the whole
+/// traversal.
+///
+/// The existing graph is either piped in as a `SymbolGraphCollection` (ex:
+/// from `traverse`) or passed via `--graph` as the JSON serialization of one
+/// (as produced by the pipeline), in which case `--graph-index` picks which of
+/// its graphs to expand.
+///
+/// Produces a JsonValue of the form `{ node, nodes, edges, jumprefs }` where
+/// `nodes` are the new symbols, `edges` are the new `{ from, to, edge }`
+/// edges, and `jumprefs` has the jumpref info of the new symbols when the
+/// server has it.
+#[derive(Debug, Args)]
+pub struct ExpandNode {
+    /// The symbol of the node to expand.
+    #[clap(long, value_parser)]
+    node: String,
+
+    /// The kinds of edges to follow from the node.
+    #[clap(long, value_enum, required = true)]
+    edge: Vec<ExpandEdge>,
+
+    /// The JSON serialization of the existing graph collection, if it's not
+    /// being piped in.
+    #[clap(long, value_parser)]
+    graph: Option<String>,
+
+    /// Which of the collection's graphs is being expanded.
+    #[clap(long, value_parser, default_value = "0")]
+    graph_index: usize,
+
+    /// Maximum number of new nodes to add.  Edges to nodes beyond the limit
+    /// are omitted.
+    #[clap(long, value_parser, default_value = "64")]
+    node_limit: usize,
+}
+
+#[derive(Debug)]
+pub struct ExpandNodeCommand {
+    pub args: ExpandNode,
+}
+
+/// The nodes and edges of a graph in the `graph_to_json` `{ nodes, edges }`
+/// representation.
+fn known_graph(graph: &Value) -> (HashSet<Ustr>, HashSet<(Ustr, Ustr)>) {
+    let sym = |v: &Value| v.as_str().map(ustr);
+    let nodes = graph["nodes"]
+        .as_array()
+        .map(|nodes| nodes.iter().filter_map(sym).collect())
+        .unwrap_or_default();
+    let edges = graph["edges"]
+        .as_array()
+        .map(|edges| {
+            edges
+                .iter()
+                .filter_map(|edge| Some((sym(&edge["from"])?, sym(&edge["to"])?)))
+                .collect()
+        })
+        .unwrap_or_default();
+    (nodes, edges)
+}
+
+/// The (from, to, kind) edges of the requested kinds touching the node.
+fn neighbor_edges(
+    node: Ustr,
+    record: &CrossrefRecord,
+    kinds: &[ExpandEdge],
+) -> Vec<(Ustr, Ustr, ExpandEdge)> {
+    let mut edges = vec![];
+    for &kind in kinds {
+        let outgoing = |edges: &mut Vec<(Ustr, Ustr, ExpandEdge)>, syms: Vec<Ustr>| {
+            edges.extend(syms.into_iter().map(|sym| (node, sym, kind)));
+        };
+        let incoming = |edges: &mut Vec<(Ustr, Ustr, ExpandEdge)>, syms: Vec<Ustr>| {
+            edges.extend(syms.into_iter().map(|sym| (sym, node, kind)));
+        };
+        match kind {
+            ExpandEdge::Callees => {
+                outgoing(&mut edges, record.callees.iter().map(|r| r.sym).collect())
+            }
+            ExpandEdge::Uses => incoming(
+                &mut edges,
+                record
+                    .uses
+                    .iter()
+                    .flat_map(|path_hits| path_hits.lines.iter().map(|line| line.contextsym))
+                    .filter(|sym| !sym.is_empty())
+                    .collect(),
+            ),
+            ExpandEdge::Inheritance => {
+                outgoing(&mut edges, record.super_syms());
+                outgoing(&mut edges, record.override_syms());
+                incoming(&mut edges, record.subclass_syms());
+                incoming(&mut edges, record.overridden_by_syms());
+            }
+            ExpandEdge::Class => outgoing(&mut edges, record.method_syms()),
+            ExpandEdge::ExpandsTo => outgoing(
+                &mut edges,
+                record.expands_to.iter().map(|r| r.sym).collect(),
+            ),
+            ExpandEdge::ExpandedFrom => outgoing(
+                &mut edges,
+                record.expanded_from.iter().map(|r| r.sym).collect(),
+            ),
+        }
+    }
+    edges
+}
+
+/// Filter the candidate edges down to those not already in the graph, adding
+/// at most `node_limit` new nodes, and returning the new nodes in the order
+/// they were first seen along with the new edges and whether we hit the limit.
+fn graph_delta(
+    candidates: Vec<(Ustr, Ustr, ExpandEdge)>,
+    known_nodes: &HashSet<Ustr>,
+    known_edges: &HashSet<(Ustr, Ustr)>,
+    node_limit: usize,
+) -> (Vec<Ustr>, Vec<(Ustr, Ustr, ExpandEdge)>, bool) {
+    let mut new_nodes: Vec<Ustr> = vec![];
+    let mut seen_edges: HashSet<(Ustr, Ustr)> = HashSet::new();
+    let mut new_edges = vec![];
+    let mut truncated = false;
+    for (from, to, kind) in candidates {
+        if from == to || known_edges.contains(&(from, to)) || !seen_edges.insert((from, to)) {
+            continue;
+        }
+        let mut fits = true;
+        for sym in [from, to] {
+            if known_nodes.contains(&sym) || new_nodes.contains(&sym) {
+                continue;
+            }
+            if new_nodes.len() >= node_limit {
+                truncated = true;
+                fits = false;
+            } else {
+                new_nodes.push(sym);
+            }
+        }
+        if fits {
+            new_edges.push((from, to, kind));
+        }
+    }
+    (new_nodes, new_edges, truncated)
+}
+
+#[async_trait]
+impl PipelineCommand for ExpandNodeCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        let collection: Value = match (input, &self.args.graph) {
+            (PipelineValues::SymbolGraphCollection(sgc), None) => sgc.to_json(),
+            (PipelineValues::Void, Some(graph)) => from_str(graph)?,
+            _ => {
+                return Err(ServerError::StickyProblem(ErrorDetails {
+                    layer: ErrorLayer::ConfigLayer,
+                    message: "expand-node needs a SymbolGraphCollection or --graph".to_string(),
+                }));
+            }
+        };
+        let graph = match collection["graphs"].get(self.args.graph_index) {
+            Some(graph) => graph,
+            None => {
+                return Err(ServerError::StickyProblem(ErrorDetails {
+                    layer: ErrorLayer::BadInput,
+                    message: format!("there's no graph {}", self.args.graph_index),
+                }));
+            }
+        };
+        let (mut known_nodes, known_edges) = known_graph(graph);
+
+        let info = server.crossref_lookup(&self.args.node, false).await?;
+        if info.is_null() {
+            return Err(ServerError::StickyProblem(ErrorDetails {
+                layer: ErrorLayer::BadInput,
+                message: format!("no crossref data for {}", self.args.node),
+            }));
+        }
+        // The lookup vouches for the symbol, so it's safe to intern.
+        let node = ustr(&self.args.node);
+        let record = CrossrefRecord::from_value(info)?;
+        // Expanding a node that isn't in the graph yet adds the node too.
+        let added_node = known_nodes.insert(node);
+
+        let (mut new_nodes, new_edges, truncated) = graph_delta(
+            neighbor_edges(node, &record, &self.args.edge),
+            &known_nodes,
+            &known_edges,
+            self.args.node_limit,
+        );
+        if truncated {
+            add_pipeline_warning(
+                "expand-node",
+                format!(
+                    "stopped at {} new nodes; raise --node-limit to see the rest",
+                    self.args.node_limit
+                ),
+            );
+        }
+        if added_node {
+            new_nodes.insert(0, node);
+        }
+
+        let mut jumprefs = Map::new();
+        for sym in &new_nodes {
+            // Remote servers don't have jumprefs, in which case the UI just
+            // doesn't get to show anything fancy for the new nodes.
+            if let Ok(jumpref) = server.jumpref_lookup(sym).await {
+                if !jumpref.is_null() {
+                    jumprefs.insert(sym.to_string(), jumpref);
+                }
+            }
+        }
+
+        Ok(PipelineValues::JsonValue(JsonValue {
+            value: json!({
+                "node": node,
+                "nodes": new_nodes,
+                "edges": new_edges
+                    .into_iter()
+                    .map(|(from, to, kind)| json!({ "from": from, "to": to, "edge": kind.name() }))
+                    .collect::<Vec<Value>>(),
+                "jumprefs": jumprefs,
+            }),
+        }))
+    }
+}
+
+#[test]
+fn test_graph_delta() {
+    let record = CrossrefRecord::from_value(json!({
+        "callees": [{ "sym": "B" }, { "sym": "C" }, { "sym": "D" }],
+        "uses": [{ "path": "a.cpp", "path_kind": "Normal", "lines": [
+            { "lno": 1, "bounds": [0, 1], "line": "a()", "context": "e", "contextsym": "E" },
+            { "lno": 2, "bounds": [0, 1], "line": "a()", "context": "", "contextsym": "" },
+        ]}],
+    }))
+    .unwrap();
+    let a = ustr("A");
+    let candidates = neighbor_edges(a, &record, &[ExpandEdge::Callees, ExpandEdge::Uses]);
+    assert_eq!(candidates.len(), 4);
+
+    let (known_nodes, known_edges) = known_graph(&json!({
+        "nodes": ["A", "B", "C"],
+        "edges": [{ "from": "A", "to": "B" }],
+    }));
+    let (nodes, edges, truncated) = graph_delta(candidates, &known_nodes, &known_edges, 1);
+    assert_eq!(nodes, vec![ustr("D")]);
+    assert_eq!(
+        edges
+            .iter()
+            .map(|(from, to, _)| format!("{}->{}", from, to))
+            .collect::<Vec<_>>(),
+        vec!["A->C", "A->D"]
+    );
+    assert!(truncated);
+}
@@ -0,0 +1,383 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+};
+
+use async_trait::async_trait;
+use clap::Args;
+use serde_json::{json, to_string_pretty, Map, Value};
+use tokio_stream::StreamExt;
+use ustr::{ustr, Ustr};
+
+use super::{
+    cmd_redact::{tree_redaction_config, Redactor},
+    interface::{JsonValue, PipelineCommand, PipelineValues},
+    progress::ProgressTracker,
+    warnings::add_pipeline_warning,
+};
+
+use crate::{
+    abstract_server::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError},
+    file_format::{analysis::parse_location, crossref_record::CrossrefRecord},
+};
+
+/// Extract the minimal slice of an index needed to reproduce a query about the
+/// given symbols into a self-contained fixture directory that can be attached
+/// to a bug report.  Starting from the seed symbols, relationships (callees,
+/// callers, overrides, the class hierarchy, and members) are followed for
+/// `--depth` hops, and the fixture gets:
+///
+/// - `manifest.json`: what was extracted and how.
+/// - `crossref.json` and `jumpref.json`: the records of every symbol, keyed by
+///   symbol.
+/// - `identifiers`: the pretty identifiers of the symbols in the same format as
+///   the index's `identifiers` file.
+/// - `analysis/PATH`: the analysis records (as ndjson) on the excerpted lines
+///   of each file the symbols have hits in.
+/// - `files/PATH`: the excerpted lines of each file with every other line left
+///   blank so line numbers are preserved.
+///
+/// `--anonymize` applies the tree's redaction config (see `redact`) to
+/// everything written, including the paths the files are written under and
+/// listed as in the manifest, so the fixture can be shared outside the
+/// organization.
+///
+/// Produces a JsonValue summary of what was written.
+#[derive(Debug, Args)]
+pub struct ExtractFixture {
+    /// The symbols to seed the fixture with.
+    #[clap(short, long, value_parser, required = true)]
+    symbol: Vec<String>,
+
+    /// How many hops of relationships to follow from the seed symbols.
+    #[clap(long, value_parser, default_value = "1")]
+    depth: u32,
+
+    /// Stop adding symbols once the fixture has this many.
+    #[clap(long, value_parser, default_value = "200")]
+    max_symbols: usize,
+
+    /// Lines of source context to keep around each hit.
+    #[clap(long, value_parser, default_value = "3")]
+    context: u32,
+
+    /// Apply the tree's redaction config to everything written.
+    #[clap(long, action)]
+    anonymize: bool,
+
+    /// The directory to write the fixture to, which is created if needed.
+    #[clap(short, long, value_parser)]
+    output: String,
+}
+
+#[derive(Debug)]
+pub struct ExtractFixtureCommand {
+    pub args: ExtractFixture,
+}
+
+/// The symbols related to a crossref record that `--depth` follows.
+fn related_syms(record: &CrossrefRecord) -> Vec<Ustr> {
+    let mut syms: Vec<Ustr> = record
+        .callees
+        .iter()
+        .chain(&record.expands_to)
+        .chain(&record.expanded_from)
+        .map(|r| r.sym)
+        .collect();
+    syms.extend(record.method_syms());
+    syms.extend(record.override_syms());
+    syms.extend(record.overridden_by_syms());
+    syms.extend(record.super_syms());
+    syms.extend(record.subclass_syms());
+    // The callers.
+    for path_hits in &record.uses {
+        for line in &path_hits.lines {
+            if !line.contextsym.is_empty() {
+                syms.push(line.contextsym);
+            }
+        }
+    }
+    syms
+}
+
+/// The lines within `context` lines of any of the hit lines.
+fn excerpt_lines(hit_lines: &BTreeSet<u32>, context: u32) -> BTreeSet<u32> {
+    hit_lines
+        .iter()
+        .flat_map(|&lno| lno.saturating_sub(context).max(1)..=lno + context)
+        .collect()
+}
+
+/// Blank out every line of the source that isn't in `keep`, returning the
+/// sparse source along with the number of lines kept.
+fn sparse_source(source: &str, keep: &BTreeSet<u32>) -> (String, usize) {
+    let mut kept = 0;
+    let mut sparse = String::new();
+    for (i, line) in source.lines().enumerate() {
+        if keep.contains(&(i as u32 + 1)) {
+            sparse.push_str(line);
+            kept += 1;
+        }
+        sparse.push('\n');
+    }
+    (sparse, kept)
+}
+
+/// The identifiers lines for a pretty name, which like the `identifiers` file
+/// include each of its qualified suffixes.
+fn identifier_lines(pretty: &str, sym: &str) -> Vec<String> {
+    let mut lines = vec![format!("{} {}", pretty, sym)];
+    let mut rest = pretty;
+    while let Some((_, suffix)) = rest.split_once("::") {
+        lines.push(format!("{} {}", suffix, sym));
+        rest = suffix;
+    }
+    lines
+}
+
+/// The path a file's data is written under and listed as in the fixture, which
+/// is redacted like the paths in the records when anonymizing.
+fn fixture_path(redactor: Option<&Redactor>, path: &str) -> String {
+    match redactor {
+        Some(redactor) => redactor.rewrite_paths(path),
+        None => path.to_string(),
+    }
+}
+
+fn write_fixture_file(root: &Path, rel_path: &str, contents: &str) -> Result<()> {
+    // Paths come from the index, but make sure they can't escape the fixture.
+    if rel_path.split('/').any(|seg| seg == ".." || seg.is_empty()) {
+        return Err(ServerError::StickyProblem(ErrorDetails {
+            layer: ErrorLayer::DataLayer,
+            message: format!("refusing to write fixture file for path {:?}", rel_path),
+        }));
+    }
+    let path = root.join(rel_path);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+#[async_trait]
+impl PipelineCommand for ExtractFixtureCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        _input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        let redactor = if self.args.anonymize {
+            Some(Redactor::new(tree_redaction_config(server)?)?)
+        } else {
+            None
+        };
+        let redact = |value: &mut Value| {
+            if let Some(redactor) = &redactor {
+                redactor.redact(value);
+            }
+        };
+
+        // ## Gather the symbols, breadth-first.
+        let mut records: BTreeMap<Ustr, CrossrefRecord> = BTreeMap::new();
+        let mut frontier: Vec<Ustr> = self.args.symbol.iter().map(|s| ustr(s)).collect();
+        let mut truncated = false;
+        for hop in 0..=self.args.depth {
+            let mut next = vec![];
+            for sym in frontier {
+                if records.contains_key(&sym) {
+                    continue;
+                }
+                if records.len() >= self.args.max_symbols {
+                    truncated = true;
+                    break;
+                }
+                let value = server.crossref_lookup(&sym, false).await?;
+                if value.is_null() {
+                    if hop == 0 {
+                        add_pipeline_warning(
+                            "extract-fixture",
+                            format!("no crossref data for seed symbol {}", sym),
+                        );
+                    }
+                    continue;
+                }
+                let record = CrossrefRecord::from_value(value)?;
+                if hop < self.args.depth {
+                    next.extend(related_syms(&record));
+                }
+                records.insert(sym, record);
+            }
+            frontier = next;
+        }
+        if truncated {
+            add_pipeline_warning(
+                "extract-fixture",
+                format!(
+                    "stopped at {} symbols; raise --max-symbols or lower --depth",
+                    self.args.max_symbols
+                ),
+            );
+        }
+
+        // ## Write the symbol data.
+        let root = Path::new(&self.args.output);
+        fs::create_dir_all(root)?;
+
+        let mut path_hit_lines: BTreeMap<Ustr, BTreeSet<u32>> = BTreeMap::new();
+        let mut identifiers = vec![];
+        let mut crossref = Map::new();
+        let mut jumpref = Map::new();
+        for (sym, record) in records {
+            for (_kind, path_hits) in record.hit_kinds() {
+                for path_hit in path_hits {
+                    path_hit_lines
+                        .entry(path_hit.path)
+                        .or_default()
+                        .extend(path_hit.lines.iter().map(|line| line.lineno));
+                }
+            }
+            if let Some(pretty) = record.pretty() {
+                identifiers.extend(identifier_lines(&pretty, &sym));
+            }
+            // Remote servers don't have jumprefs, which is fine.
+            if let Ok(mut value) = server.jumpref_lookup(&sym).await {
+                if !value.is_null() {
+                    redact(&mut value);
+                    jumpref.insert(sym.to_string(), value);
+                }
+            }
+            let mut value = record.into_value();
+            redact(&mut value);
+            crossref.insert(sym.to_string(), value);
+        }
+        let symbol_count = crossref.len();
+        write_fixture_file(root, "crossref.json", &to_string_pretty(&crossref)?)?;
+        write_fixture_file(root, "jumpref.json", &to_string_pretty(&jumpref)?)?;
+
+        identifiers.sort_by_key(|line| line.to_uppercase());
+        identifiers.dedup();
+        let mut identifiers_contents = identifiers.join("\n");
+        identifiers_contents.push('\n');
+        write_fixture_file(root, "identifiers", &identifiers_contents)?;
+
+        // ## Write the per-file data.
+        let mut files = vec![];
+        let mut analysis_records = 0;
+        let mut source_lines = 0;
+        let mut progress =
+            ProgressTracker::new("extract-fixture", Some(path_hit_lines.len() as u64));
+        for (path, hit_lines) in path_hit_lines {
+            progress.advance(1);
+            let keep = excerpt_lines(&hit_lines, self.args.context);
+            let out_path = fixture_path(redactor.as_ref(), &path);
+
+            match server.fetch_raw_source(&path).await {
+                Ok(source) => {
+                    let (mut sparse, kept) = sparse_source(&source, &keep);
+                    if let Some(redactor) = &redactor {
+                        sparse = redactor.rewrite_paths(&sparse);
+                    }
+                    write_fixture_file(root, &format!("files/{}", out_path), &sparse)?;
+                    source_lines += kept;
+                }
+                Err(err) => {
+                    add_pipeline_warning(
+                        "extract-fixture",
+                        format!("unable to fetch the source of {}: {:?}", path, err),
+                    );
+                }
+            }
+
+            // Not every file with hits has analysis data (ex: generated files
+            // that weren't kept).
+            if let Ok(stream) = server.fetch_raw_analysis(&path).await {
+                let mut lines = vec![];
+                for mut record in stream.collect::<Vec<Value>>().await {
+                    let on_kept_line = record
+                        .get("loc")
+                        .and_then(Value::as_str)
+                        .map_or(false, |loc| keep.contains(&parse_location(loc).lineno));
+                    if on_kept_line {
+                        redact(&mut record);
+                        lines.push(serde_json::to_string(&record)?);
+                    }
+                }
+                if !lines.is_empty() {
+                    analysis_records += lines.len();
+                    write_fixture_file(
+                        root,
+                        &format!("analysis/{}", out_path),
+                        &(lines.join("\n") + "\n"),
+                    )?;
+                }
+            }
+            files.push(out_path);
+        }
+        progress.finish();
+
+        let summary = json!({
+            "output": self.args.output,
+            "tree": server.tree_info()?.name,
+            "seeds": self.args.symbol,
+            "depth": self.args.depth,
+            "symbols": symbol_count,
+            "files": files,
+            "analysisRecords": analysis_records,
+            "sourceLines": source_lines,
+            "truncated": truncated,
+            "anonymized": self.args.anonymize,
+        });
+        write_fixture_file(root, "manifest.json", &(to_string_pretty(&summary)? + "\n"))?;
+
+        Ok(PipelineValues::JsonValue(JsonValue { value: summary }))
+    }
+}
+
+#[test]
+fn test_fixture_excerpts() {
+    let hits: BTreeSet<u32> = [2, 9].into_iter().collect();
+    let keep = excerpt_lines(&hits, 1);
+    assert_eq!(
+        keep.into_iter().collect::<Vec<_>>(),
+        vec![1, 2, 3, 8, 9, 10]
+    );
+
+    let keep: BTreeSet<u32> = [2, 3].into_iter().collect();
+    let (sparse, kept) = sparse_source("a\nb\nc\nd\n", &keep);
+    assert_eq!(sparse, "\nb\nc\n\n");
+    assert_eq!(kept, 2);
+
+    assert_eq!(
+        identifier_lines("ns::Foo::Bar", "_ZN2ns3Foo3BarEv"),
+        vec![
+            "ns::Foo::Bar _ZN2ns3Foo3BarEv",
+            "Foo::Bar _ZN2ns3Foo3BarEv",
+            "Bar _ZN2ns3Foo3BarEv",
+        ]
+    );
+}
+
+#[test]
+fn test_fixture_path() {
+    use crate::file_format::config::{PathRewriteRule, RedactionConfig};
+
+    let redactor = Redactor::new(RedactionConfig {
+        path_rewrites: vec![PathRewriteRule {
+            pattern: "^secret-project/".to_string(),
+            replacement: "project/".to_string(),
+        }],
+        ..RedactionConfig::default()
+    })
+    .unwrap();
+    assert_eq!(
+        fixture_path(Some(&redactor), "secret-project/Foo.cpp"),
+        "project/Foo.cpp"
+    );
+    assert_eq!(fixture_path(Some(&redactor), "dom/Foo.cpp"), "dom/Foo.cpp");
+    assert_eq!(
+        fixture_path(None, "secret-project/Foo.cpp"),
+        "secret-project/Foo.cpp"
+    );
+}
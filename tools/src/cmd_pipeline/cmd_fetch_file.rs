@@ -0,0 +1,212 @@
+use async_trait::async_trait;
+use clap::{Args, ValueEnum};
+
+use super::interface::{PipelineCommand, PipelineValues, TextFile};
+use crate::abstract_server::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError};
+
+#[derive(Clone, Debug, PartialEq, ValueEnum)]
+pub enum BinaryHandling {
+    /// Fail the pipeline with an error.
+    Refuse,
+    /// Emit the (sliced) bytes as lowercase hex digits with a mime type of
+    /// "application/octet-stream; encoding=hex".
+    Hex,
+}
+
+/// Fetch the raw contents of a source file, optionally limited to a range of
+/// lines or bytes, producing a `TextFile` with a mime type derived from the
+/// file's extension.  This lets pipelines operate on arbitrary file slices.
+///
+/// Files are considered binary if they contain a NUL byte or are not valid
+/// UTF-8, in which case `--binary` determines what happens.
+#[derive(Debug, Args)]
+pub struct FetchFile {
+    /// Tree-relative source file path.
+    #[clap(value_parser)]
+    file: String,
+
+    /// Inclusive 1-based line range to emit, like "10-20".  "10-" means line
+    /// 10 through the end of the file and "10" means just line 10.
+    #[clap(long, value_parser, conflicts_with = "bytes")]
+    lines: Option<String>,
+
+    /// 0-based byte offset range to emit where the end is exclusive, like
+    /// "0-512".  "512-" means from offset 512 through the end of the file and
+    /// "512" means just the byte at offset 512.
+    /// Slices of text files that split a multi-byte character will have the
+    /// partial character replaced with U+FFFD.
+    #[clap(long, value_parser)]
+    bytes: Option<String>,
+
+    #[clap(long, value_parser, value_enum, default_value = "refuse")]
+    binary: BinaryHandling,
+}
+
+#[derive(Debug)]
+pub struct FetchFileCommand {
+    pub args: FetchFile,
+}
+
+/// Parse an "A-B", "A-", or "A" range into a start and optional end.
+fn parse_range(range: &str) -> Option<(usize, Option<usize>)> {
+    match range.split_once('-') {
+        Some((start, "")) => Some((start.parse().ok()?, None)),
+        Some((start, end)) => {
+            let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+            if end < start {
+                return None;
+            }
+            Some((start, Some(end)))
+        }
+        None => {
+            let only = range.parse().ok()?;
+            Some((only, Some(only)))
+        }
+    }
+}
+
+/// Parse a `--bytes` range.  Unlike `parse_range`, the end is exclusive, so a
+/// lone "A" becomes "A-(A+1)".
+fn parse_byte_range(range: &str) -> Option<(usize, Option<usize>)> {
+    if range.contains('-') {
+        return parse_range(range);
+    }
+    let only: usize = range.parse().ok()?;
+    Some((only, Some(only.checked_add(1)?)))
+}
+
+/// A file is binary if it has a NUL in its first 8000 bytes (the same
+/// heuristic as git) or if it isn't valid UTF-8.
+fn looks_binary(bytes: &[u8]) -> bool {
+    bytes.iter().take(8000).any(|b| *b == 0) || std::str::from_utf8(bytes).is_err()
+}
+
+fn mime_type_for_path(path: &str) -> &'static str {
+    let filename = path.rsplit('/').next().unwrap_or(path);
+    let ext = match filename.rsplit_once('.') {
+        Some((_, ext)) => ext.to_ascii_lowercase(),
+        None => return "text/plain",
+    };
+    match ext.as_str() {
+        "c" | "h" => "text/x-c",
+        "cc" | "cpp" | "cxx" | "hh" | "hpp" | "hxx" | "mm" => "text/x-c++",
+        "css" => "text/css",
+        "htm" | "html" | "xhtml" => "text/html",
+        "java" => "text/x-java",
+        "js" | "jsm" | "mjs" => "text/javascript",
+        "json" => "application/json",
+        "md" => "text/markdown",
+        "py" => "text/x-python",
+        "rs" => "text/x-rust",
+        "svg" => "image/svg+xml",
+        "toml" => "application/toml",
+        "xml" | "xul" => "application/xml",
+        "yaml" | "yml" => "application/yaml",
+        _ => "text/plain",
+    }
+}
+
+fn bad_range(what: &str, range: &str) -> ServerError {
+    ServerError::StickyProblem(ErrorDetails {
+        layer: ErrorLayer::BadInput,
+        message: format!(
+            "fetch-file needs a --{} range like A-B, not {:?}",
+            what, range
+        ),
+    })
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[async_trait]
+impl PipelineCommand for FetchFileCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        _input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        let raw = server.fetch_raw_source_bytes(&self.args.file).await?;
+
+        let byte_range = match &self.args.bytes {
+            Some(range) => Some(parse_byte_range(range).ok_or_else(|| bad_range("bytes", range))?),
+            None => None,
+        };
+        let line_range = match &self.args.lines {
+            Some(range) => match parse_range(range) {
+                Some((start, end)) if start > 0 => Some((start, end)),
+                _ => return Err(bad_range("lines", range)),
+            },
+            None => None,
+        };
+
+        let sliced = match byte_range {
+            Some((start, end)) => {
+                let end = end.unwrap_or(raw.len()).min(raw.len());
+                &raw[start.min(end)..end]
+            }
+            None => &raw[..],
+        };
+
+        // We check the whole file rather than the slice so that a byte range
+        // which splits a multi-byte character doesn't make a text file binary.
+        if looks_binary(&raw) {
+            return match self.args.binary {
+                BinaryHandling::Refuse => Err(ServerError::StickyProblem(ErrorDetails {
+                    layer: ErrorLayer::BadInput,
+                    message: format!(
+                        "fetch-file refuses to emit binary file {}; try --binary=hex",
+                        self.args.file
+                    ),
+                })),
+                BinaryHandling::Hex => {
+                    if line_range.is_some() {
+                        return Err(ServerError::StickyProblem(ErrorDetails {
+                            layer: ErrorLayer::BadInput,
+                            message: "fetch-file can't apply --lines to a binary file".to_string(),
+                        }));
+                    }
+                    Ok(PipelineValues::TextFile(TextFile {
+                        mime_type: "application/octet-stream; encoding=hex".to_string(),
+                        contents: hex_encode(sliced),
+                    }))
+                }
+            };
+        }
+
+        let text = String::from_utf8_lossy(sliced);
+        let contents = match line_range {
+            Some((start, end)) => {
+                let count = end.map_or(usize::MAX, |end| end - start + 1);
+                text.split_inclusive('\n')
+                    .skip(start - 1)
+                    .take(count)
+                    .collect()
+            }
+            None => text.into_owned(),
+        };
+
+        Ok(PipelineValues::TextFile(TextFile {
+            mime_type: mime_type_for_path(&self.args.file).to_string(),
+            contents,
+        }))
+    }
+}
+
+#[test]
+fn test_parse_range() {
+    assert_eq!(parse_range("10-20"), Some((10, Some(20))));
+    assert_eq!(parse_range("10-"), Some((10, None)));
+    assert_eq!(parse_range("7"), Some((7, Some(7))));
+    assert_eq!(parse_range("20-10"), None);
+    assert_eq!(parse_range("a-b"), None);
+    assert_eq!(parse_byte_range("0-512"), Some((0, Some(512))));
+    assert_eq!(parse_byte_range("512-"), Some((512, None)));
+    assert_eq!(parse_byte_range("512"), Some((512, Some(513))));
+    assert!(looks_binary(b"GIF89a\0\0"));
+    assert!(looks_binary(&[0xff, 0xfe, 0x41]));
+    assert!(!looks_binary("caf\u{e9}\n".as_bytes()));
+    assert_eq!(mime_type_for_path("dom/base/nsINode.CPP"), "text/x-c++");
+    assert_eq!(mime_type_for_path("Makefile"), "text/plain");
+}
@@ -23,6 +23,12 @@ pub struct FilterAnalysis {
     #[clap(long, short, value_parser)]
     kind: Option<String>,
 
+    /// Only include records with the given `origin` (ex: "clang").  This is
+    /// useful for debugging conflicts when multiple indexers emit records for
+    /// the same file; see "Record Origin" in `docs/analysis.md`.
+    #[clap(long, value_parser)]
+    origin: Option<String>,
+
     #[clap(flatten)]
     query_opts: SymbolicQueryOpts,
 }
@@ -82,6 +88,13 @@ impl PipelineCommand for FilterAnalysisCommand {
             }));
         }
 
+        // ## Filter by origin
+        if let Some(origin) = &self.args.origin {
+            filtered = Box::pin(
+                filtered.filter(move |val| val["origin"].as_str() == Some(origin.as_str())),
+            );
+        }
+
         // ## Filter by symbol
         if let Some(symbol) = &self.args.query_opts.symbol {
             // "sym" is optionally
@@ -0,0 +1,181 @@
+use async_trait::async_trait;
+use clap::Args;
+use regex::Regex;
+
+use super::{
+    interface::{PipelineCommand, PipelineValues},
+    transforms::path_glob_transform,
+};
+use crate::{
+    abstract_server::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError},
+    file_format::crossref_record::CrossrefRecord,
+};
+
+/// Filter the hits in a `SymbolCrossrefInfoList` so that only hits in paths
+/// matching the given path constraint and/or produced by the given indexer
+/// remain.  Symbols that had hits but have none left after filtering are
+/// removed entirely; symbols that never had any hits (ex: they are only
+/// present for their "meta" info) are retained.
+///
+/// This is what lets a query like "symbol:Shutdown path:dom/" pre-filter the
+/// crossref hits by path instead of having to compose this manually.
+#[derive(Debug, Args)]
+pub struct FilterCrossref {
+    /// Constrain hits with a non-regexp path constraint that will be
+    /// glob-transformed into a regexp.
+    #[clap(long, value_parser)]
+    path: Option<String>,
+
+    /// Constrain hits with a path regexp.
+    #[clap(long, value_parser)]
+    pathre: Option<String>,
+
+    /// Only keep hits derived from records with the given `origin` (ex:
+    /// "clang"); see "Record Origin" in `docs/analysis.md`.  Hits without an
+    /// origin are all filtered out.
+    #[clap(long, value_parser)]
+    origin: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct FilterCrossrefCommand {
+    pub args: FilterCrossref,
+}
+
+/// Filter the path containers of every hit kind in the crossref info in place,
+/// returning (hits before, hits after).  When an origin is provided, the lines
+/// within each path container are filtered too and path containers left
+/// without lines are removed.
+///
+/// Only the hit kinds are filtered; "callees", "expands-to", etc. are symbol
+/// relationships rather than hits.
+fn filter_crossref_hits(
+    crossref_info: &mut CrossrefRecord,
+    re: Option<&Regex>,
+    origin: Option<&str>,
+) -> (usize, usize) {
+    let mut before = 0;
+    let mut after = 0;
+    for path_containers in crossref_info.hit_kinds_mut() {
+        before += path_containers.len();
+        path_containers.retain_mut(|pc| {
+            if let Some(re) = re {
+                if !re.is_match(&pc.path) {
+                    return false;
+                }
+            }
+            if let Some(origin) = origin {
+                pc.lines
+                    .retain(|line| line.origin.as_deref() == Some(origin));
+                return !pc.lines.is_empty();
+            }
+            true
+        });
+        after += path_containers.len();
+    }
+    (before, after)
+}
+
+#[async_trait]
+impl PipelineCommand for FilterCrossrefCommand {
+    async fn execute(
+        &self,
+        _server: &(dyn AbstractServer + Send + Sync),
+        input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        let mut scil = match input {
+            PipelineValues::SymbolCrossrefInfoList(scil) => scil,
+            _ => {
+                return Err(ServerError::StickyProblem(ErrorDetails {
+                    layer: ErrorLayer::ConfigLayer,
+                    message: "filter-crossref needs a SymbolCrossrefInfoList".to_string(),
+                }));
+            }
+        };
+
+        let pattern = if let Some(pathre) = &self.args.pathre {
+            Some(pathre.clone())
+        } else {
+            self.args
+                .path
+                .as_ref()
+                .map(|path| path_glob_transform(path))
+        };
+        let origin = self.args.origin.as_deref();
+        if pattern.is_none() && origin.is_none() {
+            return Ok(PipelineValues::SymbolCrossrefInfoList(scil));
+        }
+        let re = match pattern {
+            Some(pattern) => Some(Regex::new(&pattern).map_err(|err| {
+                ServerError::StickyProblem(ErrorDetails {
+                    layer: ErrorLayer::BadInput,
+                    message: format!("bad path constraint: {}", err),
+                })
+            })?),
+            None => None,
+        };
+
+        scil.symbol_crossref_infos.retain_mut(|info| {
+            let (before, after) =
+                filter_crossref_hits(&mut info.crossref_info, re.as_ref(), origin);
+            before == 0 || after > 0
+        });
+
+        Ok(PipelineValues::SymbolCrossrefInfoList(scil))
+    }
+
+    /// Filtering only looks at the input.
+    fn is_cheap(&self) -> bool {
+        true
+    }
+}
+
+#[test]
+fn test_filter_crossref_hits() {
+    use serde_json::{json, Value};
+
+    let hits = |path: &str, origins: &[&str]| -> Value {
+        json!({
+            "path": path,
+            "path_kind": "Normal",
+            "lines": origins.iter().enumerate().map(|(i, origin)| json!({
+                "lno": i + 1,
+                "bounds": [0, 3],
+                "line": "Foo();",
+                "context": "",
+                "contextsym": "",
+                "origin": origin,
+            })).collect::<Vec<_>>(),
+        })
+    };
+
+    let re = Regex::new(&path_glob_transform("dom/")).unwrap();
+    let mut info = CrossrefRecord::from_value(json!({
+        "meta": { "structured": 1, "pretty": "Shutdown", "sym": "Shutdown" },
+        "defs": [hits("dom/base/Foo.cpp", &["clang"])],
+        "uses": [hits("xpcom/Bar.cpp", &["clang"]), hits("dom/media/Baz.cpp", &["clang"])],
+        "decls": [hits("xpcom/Bar.h", &["clang"])],
+        "callees": [{ "sym": "_ZN3Foo3BarEv", "jump": "xpcom/Bar.cpp#3" }],
+    }))
+    .unwrap();
+    assert_eq!(filter_crossref_hits(&mut info, Some(&re), None), (4, 2));
+    assert_eq!(info.uses.len(), 1);
+    assert_eq!(info.uses[0].path.as_str(), "dom/media/Baz.cpp");
+    assert!(info.decls.is_empty());
+    assert!(info.meta.is_some());
+    assert_eq!(info.callees.len(), 1);
+
+    let mut info = CrossrefRecord::from_value(json!({
+        "uses": [
+            hits("dom/Foo.cpp", &["clang"]),
+            hits("dom/Foo.rs", &["rust-analyzer", "clang"]),
+            hits("dom/Bar.rs", &["rust-analyzer"]),
+        ],
+    }))
+    .unwrap();
+    assert_eq!(filter_crossref_hits(&mut info, None, Some("clang")), (3, 2));
+    assert_eq!(info.uses[0].path.as_str(), "dom/Foo.cpp");
+    assert_eq!(info.uses[1].path.as_str(), "dom/Foo.rs");
+    assert_eq!(info.uses[1].lines.len(), 1);
+    assert_eq!(info.uses[1].lines[0].lineno, 2);
+}
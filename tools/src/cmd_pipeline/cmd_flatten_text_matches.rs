@@ -0,0 +1,59 @@
+use async_trait::async_trait;
+use clap::Args;
+
+use super::{
+    cmd_compile_results::SearchResults,
+    interface::{PipelineCommand, PipelineValues},
+};
+use crate::abstract_server::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError};
+
+/// Convert raw fulltext `TextMatches` (ex: from `search-text`) into a
+/// `FlattenedResultsBundle` so that plain grep-style searches can be rendered
+/// with the same templates and `augment-results` context logic as our mixed
+/// semantic results without having to go through the `compile-results`
+/// junction.  All matches end up grouped by path kind under a single
+/// "TextualOccurrences" kind group, with a line span per matching line.
+#[derive(Debug, Args)]
+pub struct FlattenTextMatches {
+    /// Maximum number of files to include, truncating at the limit.  0 means
+    /// no limit.
+    #[clap(short, long, value_parser, default_value = "0")]
+    file_limit: usize,
+}
+
+#[derive(Debug)]
+pub struct FlattenTextMatchesCommand {
+    pub args: FlattenTextMatches,
+}
+
+#[async_trait]
+impl PipelineCommand for FlattenTextMatchesCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        let mut matches = match input {
+            PipelineValues::TextMatches(tm) => tm,
+            _ => {
+                return Err(ServerError::StickyProblem(ErrorDetails {
+                    layer: ErrorLayer::ConfigLayer,
+                    message: "flatten-text-matches needs TextMatches".to_string(),
+                }));
+            }
+        };
+
+        if self.args.file_limit > 0 {
+            matches.by_file.truncate(self.args.file_limit);
+        }
+
+        let mut results = SearchResults::default();
+        results.load_demotion(server)?;
+        results.ingest_fulltext_hits(matches.by_file);
+        // The limits are only advisory for `compile` right now and we've
+        // already applied our file limit above.
+        Ok(PipelineValues::FlattenedResultsBundle(
+            results.compile(0, 0),
+        ))
+    }
+}
@@ -90,6 +90,8 @@ struct Field {
     field_id: Option<FieldId>,
     field_type_syms: Option<String>,
     type_pretty: String,
+    // For fields of enum type, the enum's underlying integer type.
+    underlying_type: Option<String>,
     pretty: String,
     def_path: String,
     start_lineno: u64,
@@ -127,6 +129,7 @@ impl Field {
             field_id: Some(field_id),
             field_type_syms: Some(field_type_syms),
             type_pretty: info.type_pretty.to_string(),
+            underlying_type: info.underlying_type.map(|t| t.to_string()),
             pretty: info.pretty.to_string(),
             def_path,
             start_lineno,
@@ -140,6 +143,13 @@ impl Field {
         }
     }
 
+    fn type_label(&self) -> String {
+        match &self.underlying_type {
+            Some(underlying_type) => format!("{} : {}", self.type_pretty, underlying_type),
+            None => self.type_pretty.clone(),
+        }
+    }
+
     fn parse_path_and_line_range(
         s: String,
         struct_def_path: &Option<String>,
@@ -190,6 +200,7 @@ impl Field {
             field_id: None,
             field_type_syms: None,
             type_pretty: "".to_string(),
+            underlying_type: None,
             pretty: "(vtable)".to_string(),
             def_path: "".to_string(),
             start_lineno: 0,
@@ -204,6 +215,66 @@ impl Field {
     }
 }
 
+fn plural_bytes(bytes: u32) -> String {
+    format!("{} byte{}", bytes, if bytes > 1 { "s" } else { "" })
+}
+
+// Describe the packing and alignment of the class, or the underlying type of
+// the enum, which can't be seen from the field offsets alone.  Values that
+// differ between platforms are listed together.
+fn layout_notes(structured: &AnalysisStructured) -> Vec<String> {
+    let variants: Vec<&AnalysisStructured> = structured
+        .per_platform()
+        .into_iter()
+        .map(|(_, s)| s)
+        .collect();
+
+    let mut notes = vec![];
+    if structured.kind == "enum" {
+        let underlying = variants
+            .iter()
+            .filter_map(|s| {
+                let underlying_type = s.underlying_type?;
+                Some(match s.size_bytes {
+                    Some(size_bytes) => {
+                        format!("{} ({})", underlying_type, plural_bytes(size_bytes))
+                    }
+                    None => underlying_type.to_string(),
+                })
+            })
+            .unique()
+            .join(" / ");
+        if !underlying.is_empty() {
+            notes.push(format!("Underlying type: {}", underlying));
+        }
+        return notes;
+    }
+
+    let packing = variants
+        .iter()
+        .filter_map(|s| s.max_field_align_bytes)
+        .unique()
+        .map(|bytes| match bytes {
+            1 => "packed".to_string(),
+            bytes => format!("pack({})", bytes),
+        })
+        .join(" / ");
+    if !packing.is_empty() {
+        notes.push(format!("Packing: {}", packing));
+    }
+
+    let alignment = variants
+        .iter()
+        .filter_map(|s| s.align_bytes)
+        .unique()
+        .map(plural_bytes)
+        .join(" / ");
+    if !alignment.is_empty() {
+        notes.push(format!("Alignment: {}", alignment));
+    }
+    notes
+}
+
 // A container for fields, with pre-calculated hash of fields.
 struct FieldsWithHash {
     fields: Vec<Field>,
@@ -305,15 +376,18 @@ struct FieldListItem {
 struct Class {
     id: ClassId,
     name: String,
+    // Packing, alignment, and enum underlying type notes.
+    notes: Vec<String>,
     fields: HashMap<Option<FieldId>, HashMap<PlatformGroupId, Field>>,
     merged_fields: Vec<Vec<Option<Field>>>,
 }
 
 impl Class {
-    fn new(id: ClassId, name: String) -> Self {
+    fn new(id: ClassId, name: String, notes: Vec<String>) -> Self {
         Self {
             id,
             name,
+            notes,
             fields: HashMap::new(),
             merged_fields: vec![],
         }
@@ -743,7 +817,11 @@ impl ClassMap {
             };
             let struct_def_path = sym_info.get_def_path().cloned();
 
-            let cls = Class::new(class_id.clone(), structured.pretty.to_string());
+            let cls = Class::new(
+                class_id.clone(),
+                structured.pretty.to_string(),
+                layout_notes(&structured),
+            );
 
             let traversal_id = TraversalId(traversal_index);
 
@@ -990,7 +1068,7 @@ impl ClassMap {
     ) -> Result<SymbolGraphNodeId> {
         let (root_sym_id, _) = self.stt.node_set.add_symbol(DerivedSymbolInfo::new(
             nom_sym_info.symbol,
-            nom_sym_info.crossref_info,
+            nom_sym_info.crossref_info.into_value(),
             0,
         ));
 
@@ -1058,6 +1136,12 @@ impl ClassMap {
                 );
             }
 
+            for note in &cls.notes {
+                class_node
+                    .items
+                    .push(SymbolTreeTableItem::Note(note.clone()));
+            }
+
             let field_prefix = format!("{}::", cls.name);
 
             for field_variants in &cls.merged_fields {
@@ -1125,11 +1209,12 @@ impl ClassMap {
                 for maybe_field in field_variants {
                     match maybe_field {
                         Some(field) => {
-                            if !type_label_set.contains(&field.type_pretty) {
-                                type_label_set.insert(field.type_pretty.clone());
+                            let type_label = field.type_label();
+                            if !type_label_set.contains(&type_label) {
+                                type_label_set.insert(type_label.clone());
 
                                 field_item.types.push(SymbolTreeTableFieldType::new(
-                                    field.type_pretty.clone(),
+                                    type_label,
                                     match &field.field_type_syms {
                                         Some(type_syms) => type_syms.clone(),
                                         None => "".to_string(),
@@ -1296,3 +1381,23 @@ impl PipelineCommand for FormatSymbolsCommand {
         }
     }
 }
+
+#[test]
+fn test_layout_notes() {
+    let notes = |json: &str| layout_notes(&from_str(json).unwrap());
+    assert_eq!(
+        notes(
+            r#"{"structured": 1, "kind": "struct", "alignBytes": 1, "maxFieldAlignBytes": 1,
+                "platforms": ["linux64"],
+                "variants": [{"structured": 1, "kind": "struct", "alignBytes": 2,
+                              "maxFieldAlignBytes": 2, "platforms": ["win64"]}]}"#
+        ),
+        vec!["Packing: packed / pack(2)", "Alignment: 1 byte / 2 bytes"]
+    );
+    assert_eq!(
+        notes(
+            r#"{"structured": 1, "kind": "enum", "underlyingType": "unsigned char", "sizeBytes": 1}"#
+        ),
+        vec!["Underlying type: unsigned char (1 byte)"]
+    );
+}
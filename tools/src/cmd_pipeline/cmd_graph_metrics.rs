@@ -0,0 +1,63 @@
+use async_trait::async_trait;
+use clap::Args;
+use serde_json::to_value;
+
+use super::interface::{JsonValue, PipelineCommand, PipelineValues};
+
+use crate::abstract_server::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError};
+
+/// Compute basic metrics for the most recent graph in a
+/// `SymbolGraphCollection` (ex: from `traverse`) so that the coupling of a
+/// subsystem can be quantified without exporting the graph to external tools.
+///
+/// Produces a `JsonValue` of `{ nodeCount, edgeCount, inDegree, outDegree,
+/// cycles }` where the degrees are `{ max, mean, histogram }` and `cycles` lists
+/// the strongly connected components that contain cycles, largest first.
+#[derive(Debug, Args)]
+pub struct GraphMetrics {
+    /// Also include the graph as an `adjacency` object mapping each symbol to
+    /// the symbols it has edges to.
+    #[clap(long, action)]
+    adjacency: bool,
+}
+
+#[derive(Debug)]
+pub struct GraphMetricsCommand {
+    pub args: GraphMetrics,
+}
+
+#[async_trait]
+impl PipelineCommand for GraphMetricsCommand {
+    async fn execute(
+        &self,
+        _server: &(dyn AbstractServer + Send + Sync),
+        input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        let graphs = match input {
+            PipelineValues::SymbolGraphCollection(sgc) => sgc,
+            _ => {
+                return Err(ServerError::StickyProblem(ErrorDetails {
+                    layer: ErrorLayer::ConfigLayer,
+                    message: "graph-metrics needs a SymbolGraphCollection".to_string(),
+                }));
+            }
+        };
+
+        let graph_idx = graphs.graphs.len().saturating_sub(1);
+        let metrics = match graphs.graph_metrics(graph_idx) {
+            Some(metrics) => metrics,
+            None => {
+                return Err(ServerError::StickyProblem(ErrorDetails {
+                    layer: ErrorLayer::BadInput,
+                    message: "graph-metrics: the collection has no graphs".to_string(),
+                }));
+            }
+        };
+
+        let mut value = to_value(metrics)?;
+        if self.args.adjacency {
+            value["adjacency"] = graphs.graph_to_adjacency(graph_idx);
+        }
+        Ok(PipelineValues::JsonValue(JsonValue { value }))
+    }
+}
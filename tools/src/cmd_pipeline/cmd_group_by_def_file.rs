@@ -0,0 +1,149 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use clap::Args;
+
+use super::interface::{
+    BatchGroupItem, BatchGroups, PipelineJunctionCommand, PipelineValues, SymbolCrossrefInfo,
+    SymbolCrossrefInfoList,
+};
+
+use crate::{
+    abstract_server::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError},
+    file_format::crossref_record::CrossrefRecord,
+};
+
+/// Junction that takes one or more `SymbolCrossrefInfoList`s and groups their
+/// symbols by the file containing each symbol's definition, producing a
+/// `BatchGroups` with one `SymbolCrossrefInfoList` per file named after the
+/// file.  This is intended for header-centric review workflows where, for
+/// example, all of the methods of the classes declared in a header should be
+/// looked at together.
+///
+/// Groups are ordered by path and symbols within a group are ordered by the
+/// line of their definition.  Symbols without a definition are grouped by
+/// their first declaration instead.  Symbols that have neither, plus any
+/// unknown symbols from the inputs, end up in a final group with an empty name.
+#[derive(Debug, Args)]
+pub struct GroupByDefFile {}
+
+#[derive(Debug)]
+pub struct GroupByDefFileCommand {
+    pub args: GroupByDefFile,
+}
+
+/// The path and line of the first definition of the symbol, falling back to
+/// its first declaration.
+fn def_location(crossref_info: &CrossrefRecord) -> Option<(String, u32)> {
+    [&crossref_info.defs, &crossref_info.decls]
+        .iter()
+        .find_map(|path_hits| {
+            path_hits.iter().find_map(|ph| {
+                ph.lines
+                    .first()
+                    .map(|line| (ph.path.to_string(), line.lineno))
+            })
+        })
+}
+
+/// Group the items by path, ordering the groups by path and the items within
+/// each group by line number, keeping the input order for ties.  Items without
+/// a location are returned separately in their input order.
+fn group_by_location<T>(
+    items: impl IntoIterator<Item = (Option<(String, u32)>, T)>,
+) -> (Vec<(String, Vec<T>)>, Vec<T>) {
+    let mut by_path: BTreeMap<String, Vec<(u32, T)>> = BTreeMap::new();
+    let mut unplaced = vec![];
+    for (location, item) in items {
+        match location {
+            Some((path, lno)) => by_path.entry(path).or_default().push((lno, item)),
+            None => unplaced.push(item),
+        }
+    }
+
+    let groups = by_path
+        .into_iter()
+        .map(|(path, mut items)| {
+            items.sort_by_key(|(lno, _)| *lno);
+            (path, items.into_iter().map(|(_, item)| item).collect())
+        })
+        .collect();
+    (groups, unplaced)
+}
+
+#[async_trait]
+impl PipelineJunctionCommand for GroupByDefFileCommand {
+    async fn execute(
+        &self,
+        _server: &(dyn AbstractServer + Send + Sync),
+        input: Vec<(String, PipelineValues)>,
+    ) -> Result<PipelineValues> {
+        let mut infos: Vec<SymbolCrossrefInfo> = vec![];
+        let mut unknown_symbols = vec![];
+        for (_name, pipe_value) in input {
+            match pipe_value {
+                PipelineValues::SymbolCrossrefInfoList(mut scil) => {
+                    infos.append(&mut scil.symbol_crossref_infos);
+                    unknown_symbols.append(&mut scil.unknown_symbols);
+                }
+                _ => {
+                    return Err(ServerError::StickyProblem(ErrorDetails {
+                        layer: ErrorLayer::ConfigLayer,
+                        message: "group-by-def-file needs SymbolCrossrefInfoList".to_string(),
+                    }));
+                }
+            }
+        }
+
+        let (grouped, unplaced) = group_by_location(
+            infos
+                .into_iter()
+                .map(|info| (def_location(&info.crossref_info), info)),
+        );
+
+        let mut groups: Vec<BatchGroupItem> = grouped
+            .into_iter()
+            .map(|(path, symbol_crossref_infos)| BatchGroupItem {
+                name: path,
+                value: PipelineValues::SymbolCrossrefInfoList(SymbolCrossrefInfoList {
+                    symbol_crossref_infos,
+                    unknown_symbols: vec![],
+                }),
+            })
+            .collect();
+        if !unplaced.is_empty() || !unknown_symbols.is_empty() {
+            groups.push(BatchGroupItem {
+                name: String::new(),
+                value: PipelineValues::SymbolCrossrefInfoList(SymbolCrossrefInfoList {
+                    symbol_crossref_infos: unplaced,
+                    unknown_symbols,
+                }),
+            });
+        }
+
+        Ok(PipelineValues::BatchGroups(BatchGroups { groups }))
+    }
+}
+
+#[test]
+fn test_group_by_location() {
+    let loc = |path: &str, lno| Some((path.to_string(), lno));
+    let (groups, unplaced) = group_by_location(vec![
+        (loc("dom/base/Element.h", 30), "Element::GetId"),
+        (loc("dom/base/Attr.h", 12), "Attr::Attr"),
+        (None, "memcpy"),
+        (loc("dom/base/Element.h", 10), "Element"),
+        (loc("dom/base/Element.h", 30), "Element::SetId"),
+    ]);
+    assert_eq!(
+        groups,
+        vec![
+            ("dom/base/Attr.h".to_string(), vec!["Attr::Attr"]),
+            (
+                "dom/base/Element.h".to_string(),
+                vec!["Element", "Element::GetId", "Element::SetId"]
+            ),
+        ]
+    );
+    assert_eq!(unplaced, vec!["memcpy"]);
+}
@@ -0,0 +1,161 @@
+use async_trait::async_trait;
+use clap::Args;
+use regex::Regex;
+use serde::Serialize;
+use serde_json::to_value;
+
+use super::interface::{JsonValue, PipelineCommand, PipelineValues};
+
+use crate::abstract_server::{AbstractServer, FileRevision, Result};
+
+/// Search the git history of a file for a regexp and report the revisions in
+/// which matches appeared and disappeared, for lightweight code archaeology
+/// like "when did this call get added, and was it ever removed before?".
+///
+/// Produces a `JsonValue` of `{ path, pattern, present, revisionsScanned,
+/// timeline }` where `timeline` is oldest first and each entry is the commit
+/// details of the revision plus `event` ("appeared", "disappeared", or, with
+/// `--all-changes`, "changed"), `matchCount`, and `firstMatchLine`.  The
+/// history is first-parent only and renames are not followed, so the oldest
+/// revision scanned may be where the file was moved rather than created.
+#[derive(Debug, Args)]
+pub struct HistoryGrep {
+    /// Tree-relative source file path
+    #[clap(long, value_parser)]
+    path: String,
+
+    /// The regexp to search each revision of the file for.
+    #[clap(long, value_parser)]
+    re: String,
+
+    /// Maximum number of revisions of the file to scan, newest first.
+    #[clap(long, value_parser, default_value = "200")]
+    max_revisions: usize,
+
+    /// Also report revisions where the number of matching lines changed while
+    /// the pattern was present.
+    #[clap(long, value_parser)]
+    all_changes: bool,
+}
+
+#[derive(Debug)]
+pub struct HistoryGrepCommand {
+    pub args: HistoryGrep,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum HistoryGrepEvent {
+    Appeared,
+    Disappeared,
+    Changed,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HistoryGrepEntry<'a> {
+    #[serde(flatten)]
+    revision: &'a FileRevision,
+    event: HistoryGrepEvent,
+    match_count: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    first_match_line: Option<u32>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HistoryGrepResult<'a> {
+    path: &'a str,
+    pattern: &'a str,
+    present: bool,
+    revisions_scanned: usize,
+    timeline: Vec<HistoryGrepEntry<'a>>,
+}
+
+/// Given the match counts of consecutive revisions, oldest first, return the
+/// indices of the revisions where something happened and what.  The oldest
+/// revision counts as an appearance if it has matches.
+fn compute_events(match_counts: &[usize], all_changes: bool) -> Vec<(usize, HistoryGrepEvent)> {
+    let mut events = vec![];
+    let mut prev = 0;
+    for (i, &count) in match_counts.iter().enumerate() {
+        if prev == 0 && count > 0 {
+            events.push((i, HistoryGrepEvent::Appeared));
+        } else if prev > 0 && count == 0 {
+            events.push((i, HistoryGrepEvent::Disappeared));
+        } else if all_changes && prev != count {
+            events.push((i, HistoryGrepEvent::Changed));
+        }
+        prev = count;
+    }
+    events
+}
+
+#[async_trait]
+impl PipelineCommand for HistoryGrepCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        _input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        let re = Regex::new(&self.args.re)?;
+        let mut revisions = server
+            .fetch_file_history(&self.args.path, self.args.max_revisions)
+            .await?;
+        revisions.reverse();
+
+        // (match count, first matching line) for each revision, oldest first.
+        let matches: Vec<(usize, Option<u32>)> = revisions
+            .iter()
+            .map(|revision| {
+                let mut count = 0;
+                let mut first = None;
+                for (i, line) in revision.contents.lines().enumerate() {
+                    if re.is_match(line) {
+                        count += 1;
+                        first.get_or_insert(i as u32 + 1);
+                    }
+                }
+                (count, first)
+            })
+            .collect();
+        let counts: Vec<usize> = matches.iter().map(|(count, _)| *count).collect();
+
+        let timeline = compute_events(&counts, self.args.all_changes)
+            .into_iter()
+            .map(|(i, event)| HistoryGrepEntry {
+                revision: &revisions[i],
+                event,
+                match_count: matches[i].0,
+                first_match_line: matches[i].1,
+            })
+            .collect();
+
+        Ok(PipelineValues::JsonValue(JsonValue {
+            value: to_value(HistoryGrepResult {
+                path: &self.args.path,
+                pattern: &self.args.re,
+                present: counts.last().map_or(false, |count| *count > 0),
+                revisions_scanned: revisions.len(),
+                timeline,
+            })?,
+        }))
+    }
+}
+
+#[test]
+fn test_compute_events() {
+    use HistoryGrepEvent::*;
+
+    let counts = [0, 2, 3, 3, 0, 0, 1];
+    assert_eq!(
+        compute_events(&counts, false),
+        vec![(1, Appeared), (4, Disappeared), (6, Appeared)]
+    );
+    assert_eq!(
+        compute_events(&counts, true),
+        vec![(1, Appeared), (2, Changed), (4, Disappeared), (6, Appeared)]
+    );
+    assert_eq!(compute_events(&[1, 1], false), vec![(0, Appeared)]);
+    assert_eq!(compute_events(&[], false), vec![]);
+}
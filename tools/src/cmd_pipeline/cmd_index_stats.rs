@@ -0,0 +1,273 @@
+use std::{collections::BTreeMap, path::Path};
+
+use async_trait::async_trait;
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use serde_json::{from_str, json, to_string_pretty, to_value, Value};
+use tokio_stream::StreamExt;
+
+use super::{
+    interface::{JsonValue, PipelineCommand, PipelineValues},
+    progress::ProgressTracker,
+};
+
+use crate::abstract_server::{
+    AbstractServer, ErrorDetails, ErrorLayer, Result, SearchfoxIndexRoot, ServerError,
+};
+
+/// Aggregate the analysis record counts of the current index by language,
+/// record kind, and directory, optionally comparing them against the stats
+/// saved from a previous indexing run and flagging large drops.  This is
+/// intended as a smoke test of indexer output that exercises the same reading
+/// path as the rest of the Rust code.
+///
+/// Languages are derived from file extensions.  Record kinds are "source",
+/// "structured", or "target:" followed by the target kind (ex: "target:use").
+///
+/// Produces a JsonValue of the form `{ stats, regressions }` where
+/// `regressions` is null if there were no previous stats to compare against.
+#[derive(Debug, Args)]
+pub struct IndexStats {
+    /// Only consider files under this path prefix.
+    #[clap(long, value_parser)]
+    path: Option<String>,
+
+    /// Number of leading path components to use when grouping by directory.
+    #[clap(long, value_parser, default_value = "1")]
+    dir_depth: usize,
+
+    /// Path to the stats saved by a previous run via `--save`.  It's not an
+    /// error for the file to not exist, as will be the case the first time.
+    #[clap(long, value_parser)]
+    previous: Option<String>,
+
+    /// Path to write the current stats to for use by a future run.
+    #[clap(long, value_parser)]
+    save: Option<String>,
+
+    /// Fraction of a previous count that needs to be lost to be flagged.
+    #[clap(long, value_parser, default_value = "0.25")]
+    threshold: f64,
+
+    /// Previous counts below this are too noisy to be flagged.
+    #[clap(long, value_parser, default_value = "100")]
+    min_count: u64,
+
+    /// Fail the pipeline if any regressions are flagged.
+    #[clap(long, action)]
+    fail_on_regression: bool,
+}
+
+#[derive(Debug)]
+pub struct IndexStatsCommand {
+    pub args: IndexStats,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RecordStats {
+    /// Number of files that had analysis data.
+    files: u64,
+    records: u64,
+    by_language: BTreeMap<String, u64>,
+    by_kind: BTreeMap<String, u64>,
+    by_dir: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Regression {
+    /// What the count is grouped by: "total", "language", "kind", or "dir".
+    group: &'static str,
+    key: String,
+    previous: u64,
+    current: u64,
+}
+
+pub fn language_for_path(path: &str) -> &'static str {
+    let ext = match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => ext,
+        None => return "other",
+    };
+    match ext {
+        "c" | "cc" | "cpp" | "cxx" | "h" | "hh" | "hxx" | "hpp" | "inc" | "mm" | "m" => "cpp",
+        "js" | "jsm" | "mjs" | "sjs" | "jsx" | "ts" | "tsx" => "js",
+        "html" | "htm" | "xhtml" | "xht" | "xml" | "xul" => "html",
+        "idl" | "webidl" | "ipdl" | "ipdlh" | "aidl" => "idl",
+        "py" => "python",
+        "rs" => "rust",
+        "java" => "java",
+        "kt" => "kotlin",
+        _ => "other",
+    }
+}
+
+fn record_kind(record: &Value) -> String {
+    if record.get("source").is_some() {
+        "source".to_string()
+    } else if record.get("structured").is_some() {
+        "structured".to_string()
+    } else if record.get("target").is_some() {
+        match record.get("kind").and_then(|kind| kind.as_str()) {
+            Some(kind) => format!("target:{}", kind),
+            None => "target".to_string(),
+        }
+    } else {
+        "other".to_string()
+    }
+}
+
+fn dir_for_path(path: &str, depth: usize) -> String {
+    let mut pieces: Vec<&str> = path.split('/').collect();
+    // Drop the filename.
+    pieces.pop();
+    pieces.truncate(depth);
+    if pieces.is_empty() {
+        "/".to_string()
+    } else {
+        pieces.join("/")
+    }
+}
+
+/// List the groups whose counts dropped by at least `threshold` of their
+/// previous value, including groups that disappeared entirely.
+fn find_regressions(
+    previous: &RecordStats,
+    current: &RecordStats,
+    threshold: f64,
+    min_count: u64,
+) -> Vec<Regression> {
+    let mut regressions = vec![];
+    let mut check = |group: &'static str, key: &str, previous: u64, current: u64| {
+        if previous >= min_count && (current as f64) <= (previous as f64) * (1.0 - threshold) {
+            regressions.push(Regression {
+                group,
+                key: key.to_string(),
+                previous,
+                current,
+            });
+        }
+    };
+
+    check("total", "files", previous.files, current.files);
+    check("total", "records", previous.records, current.records);
+    for (group, prev_map, cur_map) in [
+        ("language", &previous.by_language, &current.by_language),
+        ("kind", &previous.by_kind, &current.by_kind),
+        ("dir", &previous.by_dir, &current.by_dir),
+    ] {
+        for (key, prev_count) in prev_map {
+            check(
+                group,
+                key,
+                *prev_count,
+                cur_map.get(key).copied().unwrap_or(0),
+            );
+        }
+    }
+    regressions
+}
+
+#[async_trait]
+impl PipelineCommand for IndexStatsCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        _input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        let pathre = match &self.args.path {
+            Some(prefix) => format!("^{}", regex::escape(prefix)),
+            None => "".to_string(),
+        };
+        let files = server.search_files(&pathre, false, usize::MAX).await?;
+
+        let mut stats = RecordStats::default();
+        let mut progress =
+            ProgressTracker::new("index-stats", Some(files.file_matches.len() as u64));
+        for file in &files.file_matches {
+            progress.advance(1);
+            // Not every file gets analyzed, so check for the analysis file
+            // rather than treating all failures to read it as expected.
+            let analysis_path =
+                server.translate_path(SearchfoxIndexRoot::CompressedAnalysis, &file.path)?;
+            if !Path::new(&analysis_path).is_file() {
+                continue;
+            }
+
+            let language = language_for_path(&file.path);
+            let dir = dir_for_path(&file.path, self.args.dir_depth);
+            let mut file_records = 0;
+            let mut records = server.fetch_raw_analysis(&file.path).await?;
+            while let Some(record) = records.next().await {
+                file_records += 1;
+                *stats.by_kind.entry(record_kind(&record)).or_default() += 1;
+            }
+
+            stats.files += 1;
+            stats.records += file_records;
+            *stats.by_language.entry(language.to_string()).or_default() += file_records;
+            *stats.by_dir.entry(dir).or_default() += file_records;
+        }
+        progress.finish();
+
+        let previous: Option<RecordStats> = match &self.args.previous {
+            Some(previous_path) if Path::new(previous_path).is_file() => {
+                Some(from_str(&std::fs::read_to_string(previous_path)?)?)
+            }
+            _ => None,
+        };
+        let regressions = previous.as_ref().map(|previous| {
+            find_regressions(previous, &stats, self.args.threshold, self.args.min_count)
+        });
+
+        if let Some(save_path) = &self.args.save {
+            std::fs::write(save_path, to_string_pretty(&stats)? + "\n")?;
+        }
+
+        if self.args.fail_on_regression {
+            if let Some(regressions) = regressions.as_ref().filter(|r| !r.is_empty()) {
+                return Err(ServerError::StickyProblem(ErrorDetails {
+                    layer: ErrorLayer::DataLayer,
+                    message: format!(
+                        "index-stats found {} regressions: {}",
+                        regressions.len(),
+                        to_value(regressions)?
+                    ),
+                }));
+            }
+        }
+
+        Ok(PipelineValues::JsonValue(JsonValue {
+            value: json!({
+                "stats": to_value(&stats)?,
+                "regressions": to_value(&regressions)?,
+            }),
+        }))
+    }
+}
+
+#[test]
+fn test_find_regressions() {
+    let previous = RecordStats {
+        files: 1000,
+        records: 50000,
+        by_language: BTreeMap::from([("cpp".to_string(), 40000), ("rust".to_string(), 10000)]),
+        by_kind: BTreeMap::from([("source".to_string(), 50000)]),
+        by_dir: BTreeMap::from([("dom".to_string(), 50), ("js".to_string(), 49950)]),
+    };
+    let current = RecordStats {
+        files: 990,
+        records: 41000,
+        by_language: BTreeMap::from([("cpp".to_string(), 41000)]),
+        by_kind: BTreeMap::from([("source".to_string(), 41000)]),
+        by_dir: BTreeMap::from([("js".to_string(), 41000)]),
+    };
+
+    let found: Vec<(&str, String)> = find_regressions(&previous, &current, 0.25, 100)
+        .into_iter()
+        .map(|r| (r.group, r.key))
+        .collect();
+    // The "dom" dir is below the minimum count and the other drops are less
+    // than 25%.
+    assert_eq!(found, vec![("language", "rust".to_string())]);
+}
@@ -0,0 +1,389 @@
+use std::{cmp::Ordering, collections::HashSet};
+
+use async_trait::async_trait;
+use clap::{Args, ValueEnum};
+use ustr::{ustr, Ustr, UstrMap};
+
+use super::{
+    interface::{
+        InterleavedResult, InterleavedResults, InterleavedSource, PipelineJunctionCommand,
+        PipelineValues, PresentationKind, SymbolCrossrefInfo, SymbolQuality, SymbolRelation,
+    },
+    top_k::{RankOrd, TopK},
+};
+
+use crate::abstract_server::{
+    AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError, TextMatchesByFile,
+};
+
+#[derive(Clone, Debug, PartialEq, ValueEnum)]
+pub enum InterleavePolicy {
+    /// All semantic results in the order they were provided, followed by all
+    /// fulltext results.  This is the classic mozsearch results ordering.
+    SemanticFirst,
+    /// Order all results by their score, keeping the input order for ties.
+    ScoreWeighted,
+    /// Order files by their best score and then take one result from each
+    /// file at a time so that no single file dominates the top of the list.
+    RoundRobin,
+}
+
+/// Merge semantic (`SymbolCrossrefInfoList`) and fulltext (`TextMatches`)
+/// results into a single ordered `InterleavedResults` list according to a
+/// policy.  This lets the ordering logic that would otherwise live in the UI
+/// happen server-side where it can be tested.
+///
+/// As with `compile-results`, fulltext matches on lines that already have a
+/// semantic result are suppressed, and symbols only present for their class
+/// relationships don't contribute line results.
+///
+/// Scores are the product of a per-kind weight (definitions rank above uses,
+/// fulltext ranks below everything semantic), a symbol quality factor that
+/// favors exact matches over prefix matches, and a path kind factor that
+/// favors "normal" paths over tests, generated, and third-party code.
+#[derive(Debug, Args)]
+pub struct Interleave {
+    #[clap(long, value_parser, value_enum, default_value = "semantic-first")]
+    policy: InterleavePolicy,
+
+    /// Multiplier applied to the score of fulltext results so they can be
+    /// promoted (> 1) or demoted (< 1) relative to semantic results.
+    #[clap(long, value_parser, default_value = "1.0")]
+    fulltext_weight: f64,
+
+    /// Maximum number of results to produce.  Results that can't make it into
+    /// the output under the policy are discarded as they're ingested rather
+    /// than accumulated.  0 means no limit.
+    #[clap(short, long, value_parser, default_value = "0")]
+    limit: usize,
+}
+
+#[derive(Debug)]
+pub struct InterleaveCommand {
+    pub args: Interleave,
+}
+
+fn kind_weight(kind: &PresentationKind) -> f64 {
+    match kind {
+        PresentationKind::Definitions => 1.0,
+        PresentationKind::IDL => 0.9,
+        PresentationKind::Declarations => 0.8,
+        PresentationKind::Assignments => 0.7,
+        PresentationKind::Uses => 0.6,
+        PresentationKind::TextualOccurrences => 0.3,
+        PresentationKind::Mentions => 0.2,
+    }
+}
+
+fn quality_factor(quality: &SymbolQuality) -> f64 {
+    let factor = 1.0 / (1.0 + quality.numeric_rank() as f64 / 4.0);
+    if quality.is_case_mismatched() {
+        factor * 0.9
+    } else {
+        factor
+    }
+}
+
+fn path_kind_factor(path_kind: &Ustr) -> f64 {
+    if path_kind.is_empty() || path_kind.eq_ignore_ascii_case("normal") {
+        1.0
+    } else {
+        0.5
+    }
+}
+
+/// An `InterleavedResult` tagged with its position in the input so that ties
+/// keep the input order.
+struct SequencedResult {
+    seq: usize,
+    result: InterleavedResult,
+}
+
+impl RankOrd for SequencedResult {
+    /// Higher scores first, keeping the input order for ties.
+    fn rank_cmp(&self, other: &Self) -> Ordering {
+        other
+            .result
+            .score
+            .total_cmp(&self.result.score)
+            .then_with(|| self.seq.cmp(&other.seq))
+    }
+}
+
+/// Accumulates results, suppressing fulltext hits on lines we already have
+/// semantic results for.  When there's a limit, only the results that can
+/// still end up in the output under the policy are retained.
+struct Interleaver {
+    policy: InterleavePolicy,
+    limit: usize,
+    next_seq: usize,
+    /// For "semantic-first", the results in input order.
+    in_order: Vec<InterleavedResult>,
+    /// For "score-weighted", the best results.
+    best: TopK<SequencedResult>,
+    /// For "round-robin", the best results of each file, since no more than
+    /// `limit` results from a single file can be output.
+    best_by_file: UstrMap<TopK<SequencedResult>>,
+    path_line_suppressions: HashSet<(Ustr, u32)>,
+}
+
+impl Interleaver {
+    fn new(policy: InterleavePolicy, limit: usize) -> Self {
+        Interleaver {
+            policy,
+            limit,
+            next_seq: 0,
+            in_order: vec![],
+            best: TopK::new(limit),
+            best_by_file: UstrMap::default(),
+            path_line_suppressions: HashSet::new(),
+        }
+    }
+
+    fn push(&mut self, result: InterleavedResult) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        match self.policy {
+            InterleavePolicy::SemanticFirst => {
+                if self.limit == 0 || self.in_order.len() < self.limit {
+                    self.in_order.push(result);
+                }
+            }
+            InterleavePolicy::ScoreWeighted => self.best.push(SequencedResult { seq, result }),
+            InterleavePolicy::RoundRobin => {
+                let limit = self.limit;
+                self.best_by_file
+                    .entry(result.path)
+                    .or_insert_with(|| TopK::new(limit))
+                    .push(SequencedResult { seq, result });
+            }
+        }
+    }
+
+    fn ingest_symbol(&mut self, info: SymbolCrossrefInfo) {
+        let defs_only = match &info.relation {
+            SymbolRelation::SubclassOf(_, _)
+            | SymbolRelation::SuperclassOf(_, _)
+            | SymbolRelation::CousinClassOf(_, _) => {
+                return;
+            }
+            SymbolRelation::MacroExpansionOf(_) | SymbolRelation::MemberOf(_, _) => true,
+            _ => false,
+        };
+        let pretty = info.get_pretty();
+        let quality = quality_factor(&info.quality);
+
+        let crossref_info = info.crossref_info;
+        for (pkind, path_containers) in [
+            (PresentationKind::Definitions, crossref_info.defs),
+            (PresentationKind::IDL, crossref_info.idl),
+            (PresentationKind::Declarations, crossref_info.decls),
+            (PresentationKind::Assignments, crossref_info.assignments),
+            (PresentationKind::Uses, crossref_info.uses),
+        ] {
+            if defs_only && pkind != PresentationKind::Definitions {
+                continue;
+            }
+            let kind_score = kind_weight(&pkind) * quality;
+            for path_container in path_containers {
+                let score = kind_score * path_kind_factor(&path_container.path_kind);
+                for hit in path_container.lines {
+                    self.path_line_suppressions
+                        .insert((path_container.path, hit.lineno));
+                    self.push(InterleavedResult {
+                        source: InterleavedSource::Semantic,
+                        kind: pkind.clone(),
+                        path: path_container.path,
+                        path_kind: path_container.path_kind,
+                        lno: hit.lineno,
+                        line: hit.line,
+                        symbol: Some(info.symbol),
+                        pretty: Some(pretty),
+                        context: hit.context,
+                        contextsym: hit.contextsym,
+                        score,
+                    });
+                }
+            }
+        }
+    }
+
+    fn ingest_fulltext(&mut self, by_file: Vec<TextMatchesByFile>, weight: f64) {
+        for file_matches in by_file {
+            let score = kind_weight(&PresentationKind::TextualOccurrences)
+                * path_kind_factor(&file_matches.path_kind)
+                * weight;
+            for text_match in file_matches.matches {
+                if !self
+                    .path_line_suppressions
+                    .insert((file_matches.file, text_match.line_num))
+                {
+                    continue;
+                }
+                self.push(InterleavedResult {
+                    source: InterleavedSource::Fulltext,
+                    kind: PresentationKind::TextualOccurrences,
+                    path: file_matches.file,
+                    path_kind: file_matches.path_kind,
+                    lno: text_match.line_num,
+                    line: text_match.line_str,
+                    symbol: None,
+                    pretty: None,
+                    context: ustr(""),
+                    contextsym: ustr(""),
+                    score,
+                });
+            }
+        }
+    }
+
+    /// Order the retained results according to the policy.  Fulltext results
+    /// are ingested after all semantic results regardless of the input order,
+    /// so the "semantic-first" policy is just the ingestion order.
+    fn order(self) -> Vec<InterleavedResult> {
+        let mut ordered = match self.policy {
+            InterleavePolicy::SemanticFirst => self.in_order,
+            InterleavePolicy::ScoreWeighted => self
+                .best
+                .into_sorted_vec()
+                .into_iter()
+                .map(|sequenced| sequenced.result)
+                .collect(),
+            InterleavePolicy::RoundRobin => {
+                let mut files: Vec<Vec<SequencedResult>> = self
+                    .best_by_file
+                    .into_values()
+                    .map(|best| best.into_sorted_vec())
+                    .collect();
+                // Files are ordered by their best (first) result, and every
+                // file has at least one result.
+                files.sort_by(|a, b| a[0].rank_cmp(&b[0]));
+                let mut queues: Vec<std::vec::IntoIter<SequencedResult>> = files
+                    .into_iter()
+                    .map(|results| results.into_iter())
+                    .collect();
+
+                let mut ordered = vec![];
+                while !queues.is_empty() {
+                    queues.retain_mut(|queue| match queue.next() {
+                        Some(sequenced) => {
+                            ordered.push(sequenced.result);
+                            true
+                        }
+                        None => false,
+                    });
+                }
+                ordered
+            }
+        };
+        if self.limit > 0 {
+            ordered.truncate(self.limit);
+        }
+        ordered
+    }
+}
+
+#[async_trait]
+impl PipelineJunctionCommand for InterleaveCommand {
+    async fn execute(
+        &self,
+        _server: &(dyn AbstractServer + Send + Sync),
+        input: Vec<(String, PipelineValues)>,
+    ) -> Result<PipelineValues> {
+        let mut interleaver = Interleaver::new(self.args.policy.clone(), self.args.limit);
+
+        // Semantic results need to be ingested first so that they can suppress
+        // redundant fulltext matches.
+        let mut fulltext = vec![];
+        for (_, pipe_value) in input {
+            match pipe_value {
+                PipelineValues::SymbolCrossrefInfoList(scil) => {
+                    for info in scil.symbol_crossref_infos {
+                        interleaver.ingest_symbol(info);
+                    }
+                }
+                PipelineValues::TextMatches(tm) => {
+                    fulltext.extend(tm.by_file);
+                }
+                _ => {
+                    return Err(ServerError::StickyProblem(ErrorDetails {
+                        layer: ErrorLayer::ConfigLayer,
+                        message:
+                            "interleave needs SymbolCrossrefInfoList and/or TextMatches inputs"
+                                .to_string(),
+                    }));
+                }
+            }
+        }
+        interleaver.ingest_fulltext(fulltext, self.args.fulltext_weight);
+
+        let results = interleaver.order();
+
+        Ok(PipelineValues::InterleavedResults(InterleavedResults {
+            results,
+        }))
+    }
+}
+
+#[test]
+fn test_interleave_order() {
+    let result = |path: &str, lno: u32, source: InterleavedSource, score: f64| InterleavedResult {
+        source,
+        kind: PresentationKind::Uses,
+        path: ustr(path),
+        path_kind: ustr("normal"),
+        lno,
+        line: String::new(),
+        symbol: None,
+        pretty: None,
+        context: ustr(""),
+        contextsym: ustr(""),
+        score,
+    };
+    let make = |policy: InterleavePolicy, limit: usize| {
+        let mut interleaver = Interleaver::new(policy, limit);
+        for r in [
+            result("a.cpp", 1, InterleavedSource::Semantic, 0.95),
+            result("a.cpp", 2, InterleavedSource::Semantic, 1.0),
+            result("a.cpp", 3, InterleavedSource::Semantic, 0.6),
+            result("b.cpp", 1, InterleavedSource::Semantic, 0.8),
+            result("c.cpp", 1, InterleavedSource::Fulltext, 0.9),
+        ] {
+            interleaver.push(r);
+        }
+        interleaver
+    };
+    let keys = |results: Vec<InterleavedResult>| -> Vec<String> {
+        results
+            .iter()
+            .map(|r| format!("{}:{}", r.path, r.lno))
+            .collect()
+    };
+
+    assert_eq!(
+        keys(make(InterleavePolicy::SemanticFirst, 0).order()),
+        vec!["a.cpp:1", "a.cpp:2", "a.cpp:3", "b.cpp:1", "c.cpp:1"]
+    );
+    assert_eq!(
+        keys(make(InterleavePolicy::ScoreWeighted, 0).order()),
+        vec!["a.cpp:2", "a.cpp:1", "c.cpp:1", "b.cpp:1", "a.cpp:3"]
+    );
+    assert_eq!(
+        keys(make(InterleavePolicy::RoundRobin, 0).order()),
+        vec!["a.cpp:2", "c.cpp:1", "b.cpp:1", "a.cpp:1", "a.cpp:3"]
+    );
+
+    // Limits are applied as results are ingested.
+    assert_eq!(
+        keys(make(InterleavePolicy::SemanticFirst, 2).order()),
+        vec!["a.cpp:1", "a.cpp:2"]
+    );
+    assert_eq!(
+        keys(make(InterleavePolicy::ScoreWeighted, 2).order()),
+        vec!["a.cpp:2", "a.cpp:1"]
+    );
+    assert_eq!(
+        keys(make(InterleavePolicy::RoundRobin, 2).order()),
+        vec!["a.cpp:2", "c.cpp:1"]
+    );
+}
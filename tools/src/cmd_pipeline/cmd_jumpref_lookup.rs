@@ -61,4 +61,9 @@ impl PipelineCommand for JumprefLookupCommand {
             values: jumpref_values,
         }))
     }
+
+    /// Looking up the given symbols is a handful of jumpref reads.
+    fn is_cheap(&self) -> bool {
+        true
+    }
 }
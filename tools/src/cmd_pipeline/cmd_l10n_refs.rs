@@ -0,0 +1,203 @@
+use async_trait::async_trait;
+use clap::Args;
+use serde::Serialize;
+use serde_json::{json, to_value, Value};
+use tokio_stream::StreamExt;
+use ustr::Ustr;
+
+use super::{
+    cmd_defs_in_text_matches::extract_def_extents,
+    interface::{JsonValue, PipelineCommand, PipelineValues},
+    warnings::add_pipeline_warning,
+};
+
+use crate::abstract_server::{
+    AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError, TextMatches,
+    TextSearchExitReason,
+};
+
+/// Path regexp for the localization files message IDs are defined in.
+const L10N_FILE_PATHRE: &str = r"\.(ftl|properties)$";
+
+/// Find where a localized string is used.  The tree's localization files
+/// (Fluent `.ftl` and `.properties`) are searched for the definitions of the
+/// message ID, and every other file is searched for code referencing the ID as
+/// a quoted string (ex: `data-l10n-id="foo"` or `GetStringFromName("foo")`).
+/// Where analysis data is available, each reference is attributed to the
+/// innermost definition enclosing it.
+///
+/// Produces a JsonValue of the form `{ id, definitions, references }` where
+/// the definitions are `{ path, lno, line }` and the references additionally
+/// have the `context` and `contextsym` of their enclosing definition if known.
+///
+/// This is local-only at this time because it performs fulltext searches.
+#[derive(Debug, Args)]
+pub struct L10nRefs {
+    /// The message ID (ex: "appmenu-quit-button" or "openFile.label").  Fluent
+    /// term IDs should be given without their leading "-".
+    #[clap(value_parser)]
+    id: String,
+
+    /// Maximum number of fulltext matches for each of the definition and
+    /// reference searches.
+    #[clap(short, long, value_parser, default_value = "1000")]
+    limit: usize,
+}
+
+#[derive(Debug)]
+pub struct L10nRefsCommand {
+    pub args: L10nRefs,
+}
+
+#[derive(Serialize)]
+struct L10nHit {
+    path: Ustr,
+    lno: u32,
+    line: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    context: Option<Ustr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    contextsym: Option<Ustr>,
+}
+
+/// Matches a line defining the message in a `.ftl` (`id = ...`, or
+/// `-id = ...` for terms) or `.properties` (`id=...` or `id: ...`) file.
+fn definition_pattern(id: &str) -> String {
+    format!(r"^\s*-?{}\s*[=:]", regex::escape(id))
+}
+
+/// Matches the message ID as a quoted string.
+fn reference_pattern(id: &str) -> String {
+    format!(r#"["'`]-?{}["'`]"#, regex::escape(id))
+}
+
+fn is_l10n_file(path: &str) -> bool {
+    path.ends_with(".ftl") || path.ends_with(".properties")
+}
+
+fn warn_if_incomplete(matches: &TextMatches, what: &str, limit: usize) {
+    match matches.exit_reason {
+        TextSearchExitReason::Complete => {}
+        TextSearchExitReason::MatchLimit => add_pipeline_warning(
+            "l10n-refs",
+            format!(
+                "the {} search stopped at the limit of {} matches, so there may be more",
+                what, limit
+            ),
+        ),
+        TextSearchExitReason::Timeout => add_pipeline_warning(
+            "l10n-refs",
+            format!("the {} search timed out, so there may be more", what),
+        ),
+        // The server has already warned about the shard.
+        TextSearchExitReason::ShardFailed => {}
+    }
+}
+
+#[async_trait]
+impl PipelineCommand for L10nRefsCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        _input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        let id = self.args.id.trim_start_matches('-');
+        if id.is_empty() {
+            return Err(ServerError::StickyProblem(ErrorDetails {
+                layer: ErrorLayer::BadInput,
+                message: "l10n-refs needs a message ID".to_string(),
+            }));
+        }
+
+        let defs = server
+            .search_text(
+                &definition_pattern(id),
+                false,
+                L10N_FILE_PATHRE,
+                self.args.limit,
+                0,
+            )
+            .await?;
+        warn_if_incomplete(&defs, "definition", self.args.limit);
+        let mut definitions = vec![];
+        for by_file in defs.by_file {
+            for m in by_file.matches {
+                definitions.push(L10nHit {
+                    path: by_file.file,
+                    lno: m.line_num,
+                    line: m.line_str,
+                    context: None,
+                    contextsym: None,
+                });
+            }
+        }
+
+        // Codesearch can't exclude paths, so we drop the localization files on
+        // our side.
+        let refs = server
+            .search_text(&reference_pattern(id), false, "", self.args.limit, 0)
+            .await?;
+        warn_if_incomplete(&refs, "reference", self.args.limit);
+        let mut references = vec![];
+        for by_file in refs.by_file {
+            if is_l10n_file(&by_file.file) {
+                continue;
+            }
+            // Not every file has analysis data (ex: markup and build files),
+            // in which case the references just don't get a context.
+            let def_extents = match server.fetch_raw_analysis(&by_file.file).await {
+                Ok(stream) => extract_def_extents(stream.collect::<Vec<Value>>().await, None),
+                Err(_) => vec![],
+            };
+            for m in by_file.matches {
+                let enclosing = def_extents
+                    .iter()
+                    .filter(|def| def.start_lineno <= m.line_num && m.line_num <= def.end_lineno)
+                    .min_by_key(|def| def.end_lineno - def.start_lineno);
+                references.push(L10nHit {
+                    path: by_file.file,
+                    lno: m.line_num,
+                    line: m.line_str,
+                    context: enclosing.map(|def| def.pretty),
+                    contextsym: enclosing.and_then(|def| def.syms.first().cloned()),
+                });
+            }
+        }
+
+        if definitions.is_empty() {
+            add_pipeline_warning("l10n-refs", format!("no localization file defines {}", id));
+        }
+
+        Ok(PipelineValues::JsonValue(JsonValue {
+            value: json!({
+                "id": id,
+                "definitions": to_value(definitions)?,
+                "references": to_value(references)?,
+            }),
+        }))
+    }
+}
+
+#[test]
+fn test_l10n_patterns() {
+    let defines = |line: &str| {
+        regex::Regex::new(&definition_pattern("openFile.label"))
+            .unwrap()
+            .is_match(line)
+    };
+    assert!(defines("openFile.label=Open File…"));
+    assert!(defines("openFile.label = Open File…"));
+    assert!(defines("openFile.label: Open File…"));
+    assert!(!defines("openFileXlabel=Open File…"));
+    assert!(!defines("reopenFile.label=Reopen"));
+
+    let refers = |line: &str| {
+        regex::Regex::new(&reference_pattern("brand-short-name"))
+            .unwrap()
+            .is_match(line)
+    };
+    assert!(refers(r#"<label data-l10n-id="brand-short-name"/>"#));
+    assert!(refers("document.l10n.formatValue('brand-short-name')"));
+    assert!(refers(r#"{ "-brand-short-name" }"#));
+    assert!(!refers(r#"data-l10n-id="brand-short-name-2""#));
+}
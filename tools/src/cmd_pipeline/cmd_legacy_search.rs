@@ -0,0 +1,52 @@
+use async_trait::async_trait;
+use clap::Args;
+
+use super::interface::{PipelineCommand, PipelineValues, StageEstimate, TextFile};
+
+use crate::{
+    abstract_server::{AbstractServer, Result},
+    query::legacy_router::{legacy_search_estimate, legacy_search_json, LegacySearchParams},
+};
+
+/// Run a search the way the legacy router.py "search" endpoint does and
+/// produce its exact JSON response as a TextFile, so that router.py and the
+/// pipeline-server's `/:tree/search` route can be differentially tested by
+/// diffing their output for the same query string.  See
+/// `query::legacy_router` for the quirks this preserves and the known
+/// divergences.
+#[derive(Debug, Args)]
+pub struct LegacySearch {
+    /// The query string of the router.py URL, the part after the "?" (ex:
+    /// "q=nsINode&path=dom%2F&case=true").
+    #[clap(value_parser)]
+    query_string: String,
+}
+
+#[derive(Debug)]
+pub struct LegacySearchCommand {
+    pub args: LegacySearch,
+}
+
+#[async_trait]
+impl PipelineCommand for LegacySearchCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        _input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        let params = LegacySearchParams::from_query_string(&self.args.query_string);
+        Ok(PipelineValues::TextFile(TextFile {
+            mime_type: "application/json".to_string(),
+            contents: legacy_search_json(server, &params).await?,
+        }))
+    }
+
+    async fn estimate(
+        &self,
+        _server: &(dyn AbstractServer + Send + Sync),
+        _input: &PipelineValues,
+    ) -> Result<Option<StageEstimate>> {
+        let params = LegacySearchParams::from_query_string(&self.args.query_string);
+        Ok(Some(legacy_search_estimate(&params)))
+    }
+}
@@ -0,0 +1,182 @@
+use std::{cmp::Ordering, collections::BTreeSet};
+
+use async_trait::async_trait;
+use clap::Args;
+use serde::Serialize;
+use serde_json::to_value;
+use tokio_stream::StreamExt;
+use ustr::Ustr;
+
+use super::{
+    interface::{JsonValue, JsonValueList, PipelineCommand, PipelineValues},
+    progress::ProgressTracker,
+    top_k::{RankOrd, TopK},
+};
+
+use crate::{
+    abstract_server::{AbstractServer, Result},
+    file_format::crossref_record::CrossrefRecord,
+};
+
+/// Scan the entire crossref database for symbols defined under a directory (or
+/// file) which are used from outside of it, producing the module's de facto
+/// public API ordered by descending external use count.  This is intended to
+/// help modularization efforts figure out what a module would need to export.
+///
+/// This is expensive and requires a local index.
+#[derive(Debug, Args)]
+pub struct ModuleApi {
+    /// The directory (ex: "dom/media/") or file whose API we want.  Paths are
+    /// matched on path segment boundaries, so "dom/media" doesn't include
+    /// "dom/mediasession/".
+    #[clap(value_parser)]
+    path: String,
+
+    /// Only consider symbols of these structured kinds (ex: "function",
+    /// "method", "field").  All kinds are considered if omitted.
+    #[clap(long, value_parser)]
+    kind: Vec<String>,
+
+    /// Minimum number of uses from outside the module for a symbol to be
+    /// reported.
+    #[clap(long, value_parser, default_value = "1")]
+    min_external_uses: usize,
+
+    /// Maximum number of symbols to produce.  0 means no limit.
+    #[clap(short, long, value_parser, default_value = "1000")]
+    limit: usize,
+}
+
+#[derive(Debug)]
+pub struct ModuleApiCommand {
+    pub args: ModuleApi,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ModuleApiSymbol {
+    sym: String,
+    pretty: Ustr,
+    kind: Ustr,
+    path: Ustr,
+    lno: u32,
+    /// Number of uses from files outside of the module.
+    external_uses: usize,
+    /// Number of distinct files outside of the module using the symbol.
+    external_files: usize,
+    /// Number of uses from files inside the module.
+    internal_uses: usize,
+}
+
+/// Is the path the module itself or inside it?
+pub fn is_in_module(path: &str, module: &str) -> bool {
+    let module = module.trim_end_matches('/');
+    match path.strip_prefix(module) {
+        Some(rest) => module.is_empty() || rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+impl RankOrd for ModuleApiSymbol {
+    /// Most used from outside first, then by location.
+    fn rank_cmp(&self, other: &Self) -> Ordering {
+        other
+            .external_uses
+            .cmp(&self.external_uses)
+            .then_with(|| self.path.cmp(&other.path))
+            .then_with(|| self.lno.cmp(&other.lno))
+            .then_with(|| self.sym.cmp(&other.sym))
+    }
+}
+
+impl ModuleApiCommand {
+    /// Evaluate a crossref record, returning an API symbol if it's defined in
+    /// the module and used outside of it.
+    fn evaluate(&self, sym: String, record: &CrossrefRecord) -> Option<ModuleApiSymbol> {
+        let meta = record.meta.as_ref()?;
+        let def_path = record.defs.first()?;
+        let def_line = def_path.lines.first()?;
+
+        if !is_in_module(&def_path.path, &self.args.path) {
+            return None;
+        }
+        if !self.args.kind.is_empty() && !self.args.kind.iter().any(|k| k == meta.kind.as_str()) {
+            return None;
+        }
+
+        let mut external_uses = 0;
+        let mut internal_uses = 0;
+        let mut external_files = BTreeSet::new();
+        for path_container in &record.uses {
+            if is_in_module(&path_container.path, &self.args.path) {
+                internal_uses += path_container.lines.len();
+            } else {
+                external_uses += path_container.lines.len();
+                external_files.insert(path_container.path);
+            }
+        }
+        if external_uses == 0 || external_uses < self.args.min_external_uses {
+            return None;
+        }
+
+        Some(ModuleApiSymbol {
+            sym,
+            pretty: meta.pretty,
+            kind: meta.kind,
+            path: def_path.path,
+            lno: def_line.lineno,
+            external_uses,
+            external_files: external_files.len(),
+            internal_uses,
+        })
+    }
+}
+
+#[async_trait]
+impl PipelineCommand for ModuleApiCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        _input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        let mut api = TopK::new(self.args.limit);
+        let mut records = server.crossref_scan().await?;
+        let mut progress = ProgressTracker::new("module-api", None);
+        while let Some(entry) = records.next().await {
+            let (sym, value) = entry?;
+            progress.advance(1);
+            if value.is_null() {
+                continue;
+            }
+            let record = CrossrefRecord::from_value(value)?;
+            if let Some(api_symbol) = self.evaluate(sym, &record) {
+                api.push(api_symbol);
+            }
+        }
+        progress.finish();
+
+        let values = api
+            .into_sorted_vec()
+            .into_iter()
+            .map(|api_symbol| {
+                Ok(JsonValue {
+                    value: to_value(api_symbol)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(PipelineValues::JsonValueList(JsonValueList { values }))
+    }
+}
+
+#[test]
+fn test_is_in_module() {
+    assert!(is_in_module("dom/media/MediaDecoder.cpp", "dom/media/"));
+    assert!(is_in_module("dom/media/MediaDecoder.cpp", "dom/media"));
+    assert!(is_in_module("dom/media", "dom/media/"));
+    assert!(!is_in_module(
+        "dom/mediasession/MediaSession.cpp",
+        "dom/media"
+    ));
+    assert!(!is_in_module("dom/base/Element.cpp", "dom/media/"));
+    assert!(is_in_module("dom/base/Element.cpp", ""));
+}
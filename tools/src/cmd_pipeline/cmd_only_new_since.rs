@@ -0,0 +1,184 @@
+use std::{
+    collections::HashSet,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use clap::Args;
+
+use super::interface::{PipelineCommand, PipelineValues};
+use crate::{
+    abstract_server::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError},
+    file_format::analysis::PathSearchResult,
+};
+
+/// Filter piped-in `TextMatches` or `SymbolCrossrefInfoList` hits to only the
+/// lines introduced (last changed) after the given point in time according to
+/// blame data.  This enables deprecation tracking queries like "new callers of
+/// DeprecatedFoo added in the last 3 months":
+///
+/// `search-identifiers DeprecatedFoo | crossref-lookup | only-new-since 3m`
+///
+/// Files that have no remaining hits are dropped, as are symbols which had hits
+/// but no longer do.  This requires a local index with blame data.
+#[derive(Debug, Args)]
+pub struct OnlyNewSince {
+    /// The cutoff: a "YYYY-MM-DD" date, a relative duration like "90d", "12w",
+    /// "3m", or "1y" (months are 30 days and years 365 days), or a git
+    /// revision in which case the commit time of the revision is used.
+    #[clap(value_parser)]
+    since: String,
+}
+
+#[derive(Debug)]
+pub struct OnlyNewSinceCommand {
+    pub args: OnlyNewSince,
+}
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Convert a proleptic Gregorian calendar date into days since the epoch.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Parse a date or relative duration cutoff into seconds since the epoch,
+/// returning None if `since` is neither (and so is presumably a revision).
+fn parse_since(since: &str, now: i64) -> Option<i64> {
+    let parts: Vec<&str> = since.split('-').collect();
+    if let [year, month, day] = parts.as_slice() {
+        let (year, month, day) = (
+            year.parse::<i64>().ok()?,
+            month.parse::<i64>().ok()?,
+            day.parse::<i64>().ok()?,
+        );
+        // Restricting the year also keeps `days_from_civil` from overflowing.
+        if !(0..=9999).contains(&year) || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+            return None;
+        }
+        return Some(days_from_civil(year, month, day) * SECONDS_PER_DAY);
+    }
+
+    let unit_days = match since.chars().last()? {
+        'd' => 1,
+        'w' => 7,
+        'm' => 30,
+        'y' => 365,
+        _ => return None,
+    };
+    let count: i64 = since[..since.len() - 1].parse().ok()?;
+    count
+        .checked_mul(unit_days * SECONDS_PER_DAY)
+        .and_then(|seconds| now.checked_sub(seconds))
+}
+
+/// Return the set of the given lines of the file that were changed after the
+/// cutoff.
+async fn new_lines_in_file(
+    server: &(dyn AbstractServer + Send + Sync),
+    path: &str,
+    lines: &HashSet<u32>,
+    cutoff: i64,
+) -> Result<HashSet<u32>> {
+    let (first, last) = match (lines.iter().min(), lines.iter().max()) {
+        (Some(first), Some(last)) => (*first, *last),
+        _ => return Ok(HashSet::new()),
+    };
+    let blame = server.fetch_blame_lines(path, Some((first, last))).await?;
+    Ok(blame
+        .into_iter()
+        .filter(|b| lines.contains(&b.lno) && b.timestamp > cutoff)
+        .map(|b| b.lno)
+        .collect())
+}
+
+async fn filter_path_containers(
+    server: &(dyn AbstractServer + Send + Sync),
+    path_containers: &mut Vec<PathSearchResult>,
+    cutoff: i64,
+) -> Result<()> {
+    for pc in path_containers.iter_mut() {
+        let lines: HashSet<u32> = pc.lines.iter().map(|l| l.lineno).collect();
+        let new_lines = new_lines_in_file(server, &pc.path, &lines, cutoff).await?;
+        pc.lines.retain(|l| new_lines.contains(&l.lineno));
+    }
+    path_containers.retain(|pc| !pc.lines.is_empty());
+    Ok(())
+}
+
+#[async_trait]
+impl PipelineCommand for OnlyNewSinceCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+        let cutoff = match parse_since(&self.args.since, now) {
+            Some(cutoff) => cutoff,
+            None => server.fetch_revision_timestamp(&self.args.since).await?,
+        };
+
+        match input {
+            PipelineValues::TextMatches(mut tm) => {
+                for file_matches in tm.by_file.iter_mut() {
+                    let lines: HashSet<u32> =
+                        file_matches.matches.iter().map(|m| m.line_num).collect();
+                    let new_lines =
+                        new_lines_in_file(server, &file_matches.file, &lines, cutoff).await?;
+                    file_matches
+                        .matches
+                        .retain(|m| new_lines.contains(&m.line_num));
+                }
+                tm.by_file.retain(|f| !f.matches.is_empty());
+                Ok(PipelineValues::TextMatches(tm))
+            }
+            PipelineValues::SymbolCrossrefInfoList(mut scil) => {
+                let mut kept = vec![];
+                for mut info in scil.symbol_crossref_infos.drain(..) {
+                    let mut before = 0;
+                    let mut after = 0;
+                    for path_containers in info.crossref_info.hit_kinds_mut() {
+                        before += path_containers.len();
+                        filter_path_containers(server, path_containers, cutoff).await?;
+                        after += path_containers.len();
+                    }
+                    if before == 0 || after > 0 {
+                        kept.push(info);
+                    }
+                }
+                scil.symbol_crossref_infos = kept;
+                Ok(PipelineValues::SymbolCrossrefInfoList(scil))
+            }
+            _ => Err(ServerError::StickyProblem(ErrorDetails {
+                layer: ErrorLayer::ConfigLayer,
+                message: "only-new-since needs TextMatches or a SymbolCrossrefInfoList".to_string(),
+            })),
+        }
+    }
+}
+
+#[test]
+fn test_parse_since() {
+    assert_eq!(parse_since("1970-01-01", 0), Some(0));
+    assert_eq!(parse_since("2000-03-01", 0), Some(951868800));
+    assert_eq!(parse_since("2024-02-29", 0), Some(1709164800));
+    let now = 1_700_000_000;
+    assert_eq!(parse_since("90d", now), Some(now - 90 * SECONDS_PER_DAY));
+    assert_eq!(parse_since("3m", now), Some(now - 90 * SECONDS_PER_DAY));
+    assert_eq!(parse_since("2w", now), Some(now - 14 * SECONDS_PER_DAY));
+    assert_eq!(parse_since("a1b2c3d", now), None);
+    assert_eq!(parse_since("v1.0", now), None);
+    assert_eq!(parse_since("2024-13-01", now), None);
+    assert_eq!(parse_since("99999999999999999d", now), None);
+    assert_eq!(parse_since(&format!("{}y", i64::MAX / 365), now), None);
+    assert_eq!(parse_since("9223372036854775807-01-01", now), None);
+}
@@ -0,0 +1,215 @@
+use std::collections::{HashMap, HashSet};
+
+use async_trait::async_trait;
+use clap::Args;
+use serde::Serialize;
+use serde_json::{from_value, json, to_value, Value};
+use tokio_stream::StreamExt;
+use ustr::Ustr;
+
+use super::{
+    cmd_defs_in_text_matches::extract_def_extents,
+    interface::{JsonValue, PipelineCommand, PipelineValues},
+    warnings::add_pipeline_warning,
+};
+
+use crate::{
+    abstract_server::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError},
+    file_format::analysis::{AnalysisStructured, WithLocation},
+};
+
+/// Produce the outline of a file: the symbols it defines that have structured
+/// records (ex: classes, functions, methods, fields), in file order, with the
+/// line range each definition spans and how they nest.  This is the data the
+/// file view's navigation panel displays.
+///
+/// Produces a JsonValue of the form `{ path, symbols }` where `symbols` is a
+/// pre-order list of `{ sym, pretty, kind, startLine, endLine, depth, parent }`.
+/// Nesting is determined by line range containment, so `depth` is 0 for the
+/// top-level definitions and `parent` is the symbol of the innermost
+/// containing definition, if any.
+#[derive(Debug, Args)]
+pub struct Outline {
+    /// The path of the file to outline.
+    #[clap(long, value_parser)]
+    path: String,
+
+    /// Only include symbols of these structured kinds (ex: "class", "method").
+    /// Can be specified multiple times.
+    #[clap(long, value_parser)]
+    kind: Vec<String>,
+
+    /// Only include symbols nested at most this deep, where 0 means only the
+    /// top-level definitions.
+    #[clap(long, value_parser)]
+    max_depth: Option<usize>,
+}
+
+#[derive(Debug)]
+pub struct OutlineCommand {
+    pub args: Outline,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct OutlineEntry {
+    sym: Ustr,
+    pretty: Ustr,
+    kind: Ustr,
+    start_line: u32,
+    end_line: u32,
+    depth: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parent: Option<Ustr>,
+}
+
+/// Build the unnested outline entries from the structured records, using the
+/// "def" source records for the line ranges since structured records only know
+/// where the definition starts.
+fn outline_entries(records: Vec<Value>, kinds: &[String]) -> Vec<OutlineEntry> {
+    let mut structured = vec![];
+    let mut others = vec![];
+    for record in records {
+        if record.get("structured").is_some() {
+            structured.push(record);
+        } else {
+            others.push(record);
+        }
+    }
+
+    // (sym, start line) => end line
+    let mut ends: HashMap<(Ustr, u32), u32> = HashMap::new();
+    for def in extract_def_extents(others, None) {
+        for sym in def.syms {
+            ends.insert((sym, def.start_lineno), def.end_lineno);
+        }
+    }
+
+    let mut seen: HashSet<(Ustr, u32)> = HashSet::new();
+    let mut entries = vec![];
+    for record in structured {
+        let record: WithLocation<AnalysisStructured<Ustr>> = match from_value(record) {
+            Ok(record) => record,
+            Err(_) => continue,
+        };
+        let start_line = record.loc.lineno;
+        let sym = record.data.sym;
+        if !kinds.is_empty()
+            && !kinds
+                .iter()
+                .any(|k| k.as_str() == record.data.kind.as_str())
+        {
+            continue;
+        }
+        if !seen.insert((sym, start_line)) {
+            continue;
+        }
+        entries.push(OutlineEntry {
+            sym,
+            pretty: record.data.pretty,
+            kind: record.data.kind,
+            start_line,
+            end_line: ends.get(&(sym, start_line)).copied().unwrap_or(start_line),
+            depth: 0,
+            parent: None,
+        });
+    }
+    entries
+}
+
+/// Order the entries in file order with containing definitions before the
+/// definitions they contain, filling in their depth and parent.
+fn nest_entries(mut entries: Vec<OutlineEntry>) -> Vec<OutlineEntry> {
+    entries.sort_by(|a, b| {
+        a.start_line
+            .cmp(&b.start_line)
+            .then_with(|| b.end_line.cmp(&a.end_line))
+            .then_with(|| a.pretty.cmp(&b.pretty))
+    });
+    // The (sym, end line) of the definitions containing the current entry.
+    let mut containing: Vec<(Ustr, u32)> = vec![];
+    for entry in &mut entries {
+        while let Some(&(_, end_line)) = containing.last() {
+            if entry.end_line <= end_line {
+                break;
+            }
+            containing.pop();
+        }
+        entry.depth = containing.len();
+        entry.parent = containing.last().map(|&(sym, _)| sym);
+        containing.push((entry.sym, entry.end_line));
+    }
+    entries
+}
+
+#[async_trait]
+impl PipelineCommand for OutlineCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        if !matches!(input, PipelineValues::Void) {
+            return Err(ServerError::StickyProblem(ErrorDetails {
+                layer: ErrorLayer::ConfigLayer,
+                message: "outline needs Void".to_string(),
+            }));
+        }
+
+        let records = server
+            .fetch_raw_analysis(&self.args.path)
+            .await?
+            .collect()
+            .await;
+        let mut symbols = nest_entries(outline_entries(records, &self.args.kind));
+        if let Some(max_depth) = self.args.max_depth {
+            symbols.retain(|entry| entry.depth <= max_depth);
+        }
+        if symbols.is_empty() {
+            add_pipeline_warning(
+                "outline",
+                format!("{} has no structured records to outline", self.args.path),
+            );
+        }
+
+        Ok(PipelineValues::JsonValue(JsonValue {
+            value: json!({
+                "path": self.args.path,
+                "symbols": to_value(symbols)?,
+            }),
+        }))
+    }
+}
+
+#[test]
+fn test_nest_entries() {
+    let entry = |sym: &str, start_line: u32, end_line: u32| OutlineEntry {
+        sym: ustr::ustr(sym),
+        pretty: ustr::ustr(sym),
+        kind: ustr::ustr(""),
+        start_line,
+        end_line,
+        depth: 0,
+        parent: None,
+    };
+    let nested = nest_entries(vec![
+        entry("Bar", 30, 40),
+        entry("Foo::Method", 12, 15),
+        entry("Foo", 10, 20),
+        entry("Foo::mField", 18, 18),
+        entry("Baz", 41, 41),
+    ]);
+    assert_eq!(
+        nested
+            .iter()
+            .map(|e| (e.sym.as_str(), e.depth, e.parent.map(|p| p.to_string())))
+            .collect::<Vec<_>>(),
+        vec![
+            ("Foo", 0, None),
+            ("Foo::Method", 1, Some("Foo".to_string())),
+            ("Foo::mField", 1, Some("Foo".to_string())),
+            ("Bar", 0, None),
+            ("Baz", 0, None),
+        ]
+    );
+}
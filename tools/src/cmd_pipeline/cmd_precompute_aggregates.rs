@@ -0,0 +1,154 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
+use async_trait::async_trait;
+use clap::{Args, ValueEnum};
+use serde_json::json;
+use tokio_stream::StreamExt;
+use ustr::Ustr;
+
+use super::{
+    aggregates::{direct_aggregates, transitive_subclass_counts, write_aggregates_store},
+    interface::{JsonValue, PipelineCommand, PipelineValues},
+    progress::ProgressTracker,
+};
+
+use crate::{
+    abstract_server::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError},
+    file_format::crossref_record::CrossrefRecord,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Aggregate {
+    CallerCount,
+    UseCount,
+    SubclassCount,
+    TopUses,
+}
+
+/// Compute expensive per-symbol aggregates over the entire crossref database
+/// at index time and store them in the index directory, where the local server
+/// picks them up as its precomputed aggregates store.  Query-time commands
+/// that need these aggregates (ex: `diff-symbols`, `traverse --counts-only`)
+/// consult the store before computing them live.
+///
+/// Only symbols that are expensive to compute live are stored, which is those
+/// with at least `--min-uses` uses or at least `--min-subclasses` direct and
+/// indirect subclasses.
+///
+/// The store is written as `aggregates` and `aggregates-extra` in
+/// `--output-dir` using the crossref file format, with every record stored
+/// inline.
+///
+/// Produces a JsonValue summary of what was written.  This is expensive and
+/// requires a local index.
+#[derive(Debug, Args)]
+pub struct PrecomputeAggregates {
+    /// The aggregates to compute.  All of them are computed if omitted.
+    #[clap(long, value_enum)]
+    aggregate: Vec<Aggregate>,
+
+    /// How many of the files with the most uses to store for `top-uses`.
+    #[clap(long, value_parser, default_value = "10")]
+    top_uses: usize,
+
+    /// Store the aggregates of symbols with at least this many uses.
+    #[clap(long, value_parser, default_value = "100")]
+    min_uses: usize,
+
+    /// Store the aggregates of classes with at least this many direct and
+    /// indirect subclasses.
+    #[clap(long, value_parser, default_value = "10")]
+    min_subclasses: usize,
+
+    /// The directory to write the store to, normally the tree's index
+    /// directory.
+    #[clap(long, value_parser)]
+    output_dir: String,
+}
+
+#[derive(Debug)]
+pub struct PrecomputeAggregatesCommand {
+    pub args: PrecomputeAggregates,
+}
+
+impl PrecomputeAggregatesCommand {
+    fn wants(&self, aggregate: Aggregate) -> bool {
+        self.args.aggregate.is_empty() || self.args.aggregate.contains(&aggregate)
+    }
+}
+
+#[async_trait]
+impl PipelineCommand for PrecomputeAggregatesCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        if !matches!(input, PipelineValues::Void) {
+            return Err(ServerError::StickyProblem(ErrorDetails {
+                layer: ErrorLayer::ConfigLayer,
+                message: "precompute-aggregates needs Void".to_string(),
+            }));
+        }
+
+        let top_uses = if self.wants(Aggregate::TopUses) {
+            self.args.top_uses
+        } else {
+            0
+        };
+
+        // The crossref database is in symbol order, but the subclass counts
+        // can't be known until the whole class hierarchy has been seen, so we
+        // accumulate everything before writing.
+        let mut stored = BTreeMap::new();
+        let mut subclasses: HashMap<Ustr, Vec<Ustr>> = HashMap::new();
+        let mut records = server.crossref_scan().await?;
+        let mut progress = ProgressTracker::new("precompute-aggregates", None);
+        while let Some(entry) = records.next().await {
+            let (sym, value) = entry?;
+            progress.advance(1);
+            if value.is_null() {
+                continue;
+            }
+            let record = CrossrefRecord::from_value(value)?;
+            if self.wants(Aggregate::SubclassCount) {
+                let subs = record.subclass_syms();
+                if !subs.is_empty() {
+                    subclasses.insert(ustr::ustr(&sym), subs);
+                }
+            }
+
+            let mut aggregates = direct_aggregates(&record, top_uses);
+            if aggregates.use_count.unwrap_or_default() < self.args.min_uses {
+                continue;
+            }
+            if !self.wants(Aggregate::CallerCount) {
+                aggregates.caller_count = None;
+            }
+            if !self.wants(Aggregate::UseCount) {
+                aggregates.use_count = None;
+            }
+            stored.insert(sym, aggregates);
+        }
+        progress.finish();
+
+        for (sym, count) in transitive_subclass_counts(&subclasses) {
+            if count < self.args.min_subclasses {
+                continue;
+            }
+            stored.entry(sym.to_string()).or_default().subclass_count = Some(count);
+        }
+
+        write_aggregates_store(Path::new(&self.args.output_dir), &stored)?;
+
+        Ok(PipelineValues::JsonValue(JsonValue {
+            value: json!({
+                "outputDir": self.args.output_dir,
+                "symbols": stored.len(),
+            }),
+        }))
+    }
+}
@@ -0,0 +1,412 @@
+use async_trait::async_trait;
+use clap::Args;
+use serde::Serialize;
+use serde_json::to_value;
+use ustr::Ustr;
+
+use super::interface::{JsonValue, JsonValueList, PipelineCommand, PipelineValues};
+
+use crate::abstract_server::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError};
+
+/// Filter `TextMatches` (ex: from `search-text`) to the hits inside C
+/// preprocessor conditional regions, attaching the conditions each hit's line
+/// is compiled under.  This allows asking for "uses of Foo in code only
+/// compiled when MOZ_WIDGET_GTK is defined" via `--defined MOZ_WIDGET_GTK`.
+///
+/// The analysis records don't describe preprocessor regions, so we derive them
+/// from the raw source of each file, which means this is local-only.  The
+/// conditions of `#elif` and `#else` branches include the negations of the
+/// earlier branches' conditions.  Macros are only recognized in simple
+/// conjunctions like `defined(A) && !defined(B)`; we don't attempt to evaluate
+/// anything more complicated.
+///
+/// Produces a `JsonValueList` of `{ path, lno, line, conditions }` where
+/// `conditions` are the conditions of the enclosing regions, outermost first.
+#[derive(Debug, Args)]
+pub struct PreprocessorFilter {
+    /// Only keep hits in code that is only compiled when this macro is defined.
+    #[clap(long, value_parser)]
+    defined: Option<String>,
+
+    /// Only keep hits in code that is only compiled when this macro is not
+    /// defined.
+    #[clap(long, value_parser)]
+    undefined: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct PreprocessorFilterCommand {
+    pub args: PreprocessorFilter,
+}
+
+/// A condition that must hold for a line to be compiled.
+#[derive(Clone, Debug, PartialEq)]
+struct Conjunct {
+    expr: String,
+    negated: bool,
+}
+
+impl Conjunct {
+    fn negate(&self) -> Conjunct {
+        Conjunct {
+            expr: self.expr.clone(),
+            negated: !self.negated,
+        }
+    }
+
+    fn render(&self) -> String {
+        if self.negated {
+            format!("!({})", self.expr)
+        } else {
+            self.expr.clone()
+        }
+    }
+}
+
+/// An open `#if` block.
+struct IfFrame {
+    /// The conditions of the block's branches so far, all of which must be
+    /// false for later branches to be compiled.
+    branches: Vec<Conjunct>,
+    /// The conditions of the current branch.
+    current: Vec<Conjunct>,
+}
+
+/// Strip comments from a directive's expression.
+fn strip_comments(expr: &str) -> String {
+    let mut expr = expr.to_string();
+    while let Some(start) = expr.find("/*") {
+        match expr[start..].find("*/") {
+            Some(end) => expr.replace_range(start..start + end + 2, " "),
+            None => expr.truncate(start),
+        }
+    }
+    if let Some(start) = expr.find("//") {
+        expr.truncate(start);
+    }
+    expr.trim().to_string()
+}
+
+/// Compute the conditions under which each line of the source is compiled,
+/// indexed by 0-based line number.  Directive lines get the conditions of the
+/// block they appear in.
+fn line_conditions(source: &str) -> Vec<Vec<Conjunct>> {
+    let flatten = |stack: &[IfFrame]| -> Vec<Conjunct> {
+        stack
+            .iter()
+            .flat_map(|frame| frame.current.iter().cloned())
+            .collect()
+    };
+
+    let mut conditions = vec![];
+    let mut stack: Vec<IfFrame> = vec![];
+    let mut lines = source.lines();
+    while let Some(line) = lines.next() {
+        let directive = match line.trim_start().strip_prefix('#') {
+            Some(directive) => directive.trim_start(),
+            None => {
+                conditions.push(flatten(&stack));
+                continue;
+            }
+        };
+        // Directives can be continued across lines with a trailing backslash.
+        let mut directive = directive.to_string();
+        let mut line_count = 1;
+        while directive.ends_with('\\') {
+            directive.pop();
+            match lines.next() {
+                Some(next) => {
+                    directive.push_str(next);
+                    line_count += 1;
+                }
+                None => break,
+            }
+        }
+        let (word, expr) = directive
+            .split_once(|c: char| c.is_whitespace() || c == '(' || c == '!')
+            .map(|(word, _)| (word, strip_comments(&directive[word.len()..])))
+            .unwrap_or((directive.as_str(), String::new()));
+
+        let outer = match word {
+            "if" | "ifdef" | "ifndef" => {
+                let outer = flatten(&stack);
+                let conjunct = match word {
+                    "ifdef" => Conjunct {
+                        expr: format!("defined({})", expr),
+                        negated: false,
+                    },
+                    "ifndef" => Conjunct {
+                        expr: format!("defined({})", expr),
+                        negated: true,
+                    },
+                    _ => Conjunct {
+                        expr,
+                        negated: false,
+                    },
+                };
+                stack.push(IfFrame {
+                    branches: vec![conjunct.clone()],
+                    current: vec![conjunct],
+                });
+                outer
+            }
+            "elif" | "else" => {
+                let frame = stack.pop();
+                let outer = flatten(&stack);
+                if let Some(mut frame) = frame {
+                    frame.current = frame.branches.iter().map(Conjunct::negate).collect();
+                    if word == "elif" {
+                        let conjunct = Conjunct {
+                            expr,
+                            negated: false,
+                        };
+                        frame.branches.push(conjunct.clone());
+                        frame.current.push(conjunct);
+                    }
+                    stack.push(frame);
+                }
+                outer
+            }
+            "endif" => {
+                stack.pop();
+                flatten(&stack)
+            }
+            _ => flatten(&stack),
+        };
+        for _ in 0..line_count {
+            conditions.push(outer.clone());
+        }
+    }
+    conditions
+}
+
+/// Does the opening parenthesis at the start of the expression match the
+/// closing parenthesis at its end, as in "(A)" but not "(A)&&(B)"?
+fn is_wrapped_in_parens(expr: &str) -> bool {
+    if !expr.starts_with('(') || !expr.ends_with(')') {
+        return false;
+    }
+    let mut depth = 0;
+    for (i, c) in expr.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return i == expr.len() - 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Remove whitespace and any parentheses wrapping the whole expression.
+fn normalize_expr(expr: &str) -> String {
+    let mut expr: String = expr.chars().filter(|c| !c.is_whitespace()).collect();
+    while is_wrapped_in_parens(&expr) {
+        expr = expr[1..expr.len() - 1].to_string();
+    }
+    expr
+}
+
+/// Split the expression on the operator where it's not inside parentheses,
+/// returning None if the operator doesn't appear at the top level.
+fn split_top_level<'a>(expr: &'a str, op: &str) -> Option<Vec<&'a str>> {
+    let mut parts = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in expr.char_indices() {
+        // Skip over the rest of an operator we just split on.
+        if i < start {
+            continue;
+        }
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ if depth == 0 && expr[i..].starts_with(op) => {
+                parts.push(&expr[start..i]);
+                start = i + op.len();
+            }
+            _ => {}
+        }
+    }
+    if parts.is_empty() {
+        return None;
+    }
+    parts.push(&expr[start..]);
+    Some(parts)
+}
+
+/// Break the expression down into the literals that must all hold for it to
+/// be true (or false if `positive` is false), where a literal is an expression
+/// we can't break down any further and whether it must be true.
+fn required_literals(expr: &str, positive: bool, literals: &mut Vec<(String, bool)>) {
+    let expr = normalize_expr(expr);
+    // `A && B` requires both if it must be true and `A || B` requires both to
+    // be false if it must be false.
+    let op = if positive { "&&" } else { "||" };
+    let other_op = if positive { "||" } else { "&&" };
+    if split_top_level(&expr, other_op).is_none() {
+        if let Some(parts) = split_top_level(&expr, op) {
+            for part in parts {
+                required_literals(part, positive, literals);
+            }
+            return;
+        }
+        if let Some(rest) = expr.strip_prefix('!') {
+            required_literals(rest, !positive, literals);
+            return;
+        }
+    }
+    literals.push((expr, positive));
+}
+
+/// Do the conditions require the macro to be defined (or not defined if
+/// `defined` is false)?
+fn requires_macro(conditions: &[Conjunct], name: &str, defined: bool) -> bool {
+    let forms = [format!("defined({})", name), format!("defined{}", name)];
+    let mut literals = vec![];
+    for conjunct in conditions {
+        required_literals(&conjunct.expr, !conjunct.negated, &mut literals);
+    }
+    literals.iter().any(|(literal, positive)| {
+        if forms.contains(literal) {
+            *positive == defined
+        } else {
+            // A bare `#if FOO` only requires FOO to be defined (and non-zero).
+            literal == name && *positive && defined
+        }
+    })
+}
+
+#[derive(Serialize)]
+struct ConditionalHit {
+    path: Ustr,
+    lno: u32,
+    line: String,
+    conditions: Vec<String>,
+}
+
+impl PreprocessorFilterCommand {
+    fn keep(&self, conditions: &[Conjunct]) -> bool {
+        if conditions.is_empty() {
+            return false;
+        }
+        if let Some(name) = &self.args.defined {
+            if !requires_macro(conditions, name, true) {
+                return false;
+            }
+        }
+        if let Some(name) = &self.args.undefined {
+            if !requires_macro(conditions, name, false) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[async_trait]
+impl PipelineCommand for PreprocessorFilterCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        let matches = match input {
+            PipelineValues::TextMatches(tm) => tm,
+            _ => {
+                return Err(ServerError::StickyProblem(ErrorDetails {
+                    layer: ErrorLayer::ConfigLayer,
+                    message: "preprocessor-filter needs TextMatches".to_string(),
+                }));
+            }
+        };
+
+        let mut values = vec![];
+        for file_matches in matches.by_file {
+            let source = server.fetch_raw_source(&file_matches.file).await?;
+            let conditions = line_conditions(&source);
+            for m in file_matches.matches {
+                let line_idx = (m.line_num as usize).checked_sub(1);
+                let line_conditions = match line_idx.and_then(|idx| conditions.get(idx)) {
+                    Some(line_conditions) => line_conditions,
+                    None => continue,
+                };
+                if !self.keep(line_conditions) {
+                    continue;
+                }
+                values.push(JsonValue {
+                    value: to_value(ConditionalHit {
+                        path: file_matches.file,
+                        lno: m.line_num,
+                        line: m.line_str,
+                        conditions: line_conditions.iter().map(Conjunct::render).collect(),
+                    })?,
+                });
+            }
+        }
+        Ok(PipelineValues::JsonValueList(JsonValueList { values }))
+    }
+}
+
+#[test]
+fn test_line_conditions() {
+    let source = "\
+int a;
+#ifdef MOZ_WIDGET_GTK
+int b;
+#  if defined(XP_LINUX) && !defined(ANDROID) // comment
+int c;
+#  endif
+#elif defined(XP_WIN)
+int d;
+#else
+int e;
+#endif
+#if !(defined(A) || \\
+      defined(B))
+int f;
+#endif
+";
+    let conditions = line_conditions(source);
+    let rendered =
+        |lno: usize| -> Vec<String> { conditions[lno - 1].iter().map(Conjunct::render).collect() };
+    assert!(rendered(1).is_empty());
+    assert!(rendered(2).is_empty());
+    assert_eq!(rendered(3), vec!["defined(MOZ_WIDGET_GTK)"]);
+    assert_eq!(
+        rendered(5),
+        vec![
+            "defined(MOZ_WIDGET_GTK)",
+            "defined(XP_LINUX) && !defined(ANDROID)"
+        ]
+    );
+    assert_eq!(
+        rendered(8),
+        vec!["!(defined(MOZ_WIDGET_GTK))", "defined(XP_WIN)"]
+    );
+    assert_eq!(
+        rendered(10),
+        vec!["!(defined(MOZ_WIDGET_GTK))", "!(defined(XP_WIN))"]
+    );
+    assert_eq!(rendered(14).len(), 1);
+
+    assert!(requires_macro(&conditions[4], "MOZ_WIDGET_GTK", true));
+    assert!(requires_macro(&conditions[4], "XP_LINUX", true));
+    assert!(requires_macro(&conditions[4], "ANDROID", false));
+    assert!(!requires_macro(&conditions[4], "ANDROID", true));
+    assert!(requires_macro(&conditions[7], "MOZ_WIDGET_GTK", false));
+    assert!(!requires_macro(&conditions[7], "MOZ_WIDGET_GTK", true));
+    assert!(requires_macro(&conditions[13], "A", false));
+    assert!(requires_macro(&conditions[13], "B", false));
+
+    // Non-ASCII characters (ex: in a stray comment) don't break splitting.
+    assert_eq!(
+        split_top_level("defined(\u{e9})&&(A||B)", "&&"),
+        Some(vec!["defined(\u{e9})", "(A||B)"])
+    );
+    assert_eq!(split_top_level("\u{e9}||(A&&B)", "&&"), None);
+}
@@ -8,7 +8,7 @@ use super::{
 };
 use crate::{
     abstract_server::{AbstractServer, Result},
-    query::chew_query::chew_query,
+    query::{chew_query::chew_query, query_plan::plan_query},
 };
 
 /// Run a new-style `query-parser` `term:value` query parse against the local
@@ -23,6 +23,13 @@ pub struct Query {
     /// Output the constructed pipeline instead of running the pipeline.
     #[clap(short, long, value_parser)]
     dump_pipeline: bool,
+
+    /// Consult the query planner's cost model like the pipeline-server does,
+    /// which may skip the fulltext search if the tree's query config enables
+    /// the planner.  The plan is included in the dumped pipeline and in
+    /// `FlattenedResultsBundle` results.
+    #[clap(long, action)]
+    plan: bool,
 }
 
 #[derive(Debug)]
@@ -37,7 +44,11 @@ impl PipelineCommand for QueryCommand {
         server: &(dyn AbstractServer + Send + Sync),
         _input: PipelineValues,
     ) -> Result<PipelineValues> {
-        let pipeline_plan = chew_query(&self.args.query)?;
+        let pipeline_plan = if self.args.plan {
+            plan_query(server, &self.args.query).await?
+        } else {
+            chew_query(&self.args.query)?
+        };
 
         if self.args.dump_pipeline {
             return Ok(PipelineValues::JsonValue(JsonValue {
@@ -0,0 +1,167 @@
+use async_trait::async_trait;
+use clap::Args;
+use regex::Regex;
+use serde_json::{to_value, Value};
+use uuid::Uuid;
+
+use super::interface::{JsonValue, PipelineCommand, PipelineValues};
+
+use crate::{
+    abstract_server::{AbstractServer, Result, ServerError},
+    file_format::config::{AuthorRedaction, RedactionConfig},
+};
+
+/// Anonymize any pipeline value so that results can be shared outside of the
+/// organization.  This should be the last command in a pipeline since it
+/// produces a `JsonValue` of the serialized input, which means later commands
+/// won't recognize what it contains.
+///
+/// What gets redacted is controlled by the tree's "redaction" config:
+/// - Author names (ex: from `blame-lines`) in the configured `author_fields`
+///   are hashed with a salt (the default) or stripped.  Without a configured
+///   `hash_salt`, a random salt is used for each run.
+/// - The tree's absolute directories in any string are rewritten to their
+///   tree-relative equivalents, followed by the configured `path_rewrites`.
+#[derive(Debug, Args)]
+pub struct Redact {}
+
+#[derive(Debug)]
+pub struct RedactCommand {
+    pub args: Redact,
+}
+
+pub(super) struct Redactor {
+    config: RedactionConfig,
+    rewrites: Vec<(Regex, String)>,
+}
+
+/// 64-bit FNV-1a, which unlike `DefaultHasher` is guaranteed to be stable so
+/// that the same author always hashes the same way across releases.
+fn fnv1a(bytes: impl Iterator<Item = u8>) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in bytes {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+impl Redactor {
+    pub(super) fn new(mut config: RedactionConfig) -> Result<Self> {
+        // An empty salt would let anyone reverse the hashes by hashing a list
+        // of known names.
+        if config.hash_salt.is_empty() {
+            config.hash_salt = Uuid::new_v4().to_string();
+        }
+        let mut rewrites = vec![];
+        for rule in &config.path_rewrites {
+            rewrites.push((Regex::new(&rule.pattern)?, rule.replacement.clone()));
+        }
+        Ok(Redactor { config, rewrites })
+    }
+
+    fn redact_author(&self, author: &str) -> String {
+        match self.config.authors {
+            AuthorRedaction::Keep => author.to_string(),
+            AuthorRedaction::Strip => String::new(),
+            AuthorRedaction::Hash => {
+                let salted = self.config.hash_salt.bytes().chain(author.bytes());
+                format!("anon-{:016x}", fnv1a(salted))
+            }
+        }
+    }
+
+    pub(super) fn rewrite_paths(&self, s: &str) -> String {
+        let mut s = s.to_string();
+        for (re, replacement) in &self.rewrites {
+            s = re.replace_all(&s, replacement.as_str()).into_owned();
+        }
+        s
+    }
+
+    pub(super) fn redact(&self, value: &mut Value) {
+        match value {
+            Value::Object(obj) => {
+                for (key, child) in obj.iter_mut() {
+                    match child {
+                        Value::String(s) if self.config.author_fields.contains(key) => {
+                            *s = self.redact_author(s);
+                        }
+                        _ => self.redact(child),
+                    }
+                }
+            }
+            Value::Array(arr) => {
+                for child in arr {
+                    self.redact(child);
+                }
+            }
+            Value::String(s) => *s = self.rewrite_paths(s),
+            _ => {}
+        }
+    }
+}
+
+/// Get the tree's redaction config, falling back to the defaults for servers
+/// that don't have one.
+pub(super) fn tree_redaction_config(
+    server: &(dyn AbstractServer + Send + Sync),
+) -> Result<RedactionConfig> {
+    match server.redaction_config() {
+        Err(ServerError::Unsupported) => Ok(RedactionConfig::default()),
+        result => result,
+    }
+}
+
+#[async_trait]
+impl PipelineCommand for RedactCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        let redactor = Redactor::new(tree_redaction_config(server)?)?;
+        let mut value = to_value(input)?;
+        redactor.redact(&mut value);
+        Ok(PipelineValues::JsonValue(JsonValue { value }))
+    }
+}
+
+#[test]
+fn test_redact() {
+    use crate::file_format::config::PathRewriteRule;
+
+    let config = RedactionConfig {
+        path_rewrites: vec![PathRewriteRule {
+            pattern: "^/builds/worker/checkouts/gecko/".to_string(),
+            replacement: "".to_string(),
+        }],
+        ..RedactionConfig::default()
+    };
+    let redactor = Redactor::new(config).unwrap();
+    let mut value = serde_json::json!({
+        "records": [
+            {"author": "Jane Doe", "path": "/builds/worker/checkouts/gecko/dom/Foo.cpp"},
+            {"author": "Jane Doe", "lno": 3},
+        ],
+    });
+    redactor.redact(&mut value);
+
+    let hashed = value["records"][0]["author"].as_str().unwrap();
+    assert!(hashed.starts_with("anon-"));
+    assert_eq!(value["records"][1]["author"], hashed);
+    assert_eq!(value["records"][0]["path"], "dom/Foo.cpp");
+    assert_eq!(value["records"][1]["lno"], 3);
+
+    // Without a configured salt, each run gets its own.
+    let rehash = |config: RedactionConfig| Redactor::new(config).unwrap().redact_author("Jane Doe");
+    assert_ne!(
+        rehash(RedactionConfig::default()),
+        rehash(RedactionConfig::default())
+    );
+    let salted = || RedactionConfig {
+        hash_salt: "pepper".to_string(),
+        ..RedactionConfig::default()
+    };
+    assert_eq!(rehash(salted()), rehash(salted()));
+}
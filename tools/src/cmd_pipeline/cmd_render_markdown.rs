@@ -0,0 +1,396 @@
+use async_trait::async_trait;
+use clap::Args;
+use serde_json::Value;
+
+use super::{
+    cmd_render_quickfix::html_to_text,
+    interface::{
+        FlattenedResultsBundle, JsonValue, PipelineCommand, PipelineValues, PresentationKind,
+        TextFile,
+    },
+    symbol_graph::SymbolGraphCollection,
+};
+
+use crate::abstract_server::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError};
+
+/// Render results as a Markdown report suitable for pasting into bugs and
+/// design docs.  This should be the last command in a pipeline.
+///
+/// Accepts a `FlattenedResultsBundle` (ex: from `compile-results`), which is
+/// rendered as a section per path kind and hit kind with a code block per file,
+/// a `SymbolGraphCollection` (ex: from `traverse`), which is rendered as an
+/// edge table per graph, or summaries in the form of a `JsonValue` or
+/// `JsonValueList` (ex: from `index-stats`), where objects and lists of
+/// objects become tables.  Files and symbols link back to searchfox.
+#[derive(Debug, Args)]
+pub struct RenderMarkdown {
+    /// Title for the report, rendered as a top-level heading.
+    #[clap(long, value_parser)]
+    title: Option<String>,
+
+    /// Base URL of the searchfox server to link to.
+    #[clap(long, value_parser, default_value = "https://searchfox.org/")]
+    base_url: String,
+}
+
+#[derive(Debug)]
+pub struct RenderMarkdownCommand {
+    pub args: RenderMarkdown,
+}
+
+fn kind_heading(kind: &PresentationKind) -> &'static str {
+    match kind {
+        PresentationKind::IDL => "IDL",
+        PresentationKind::Definitions => "Definitions",
+        PresentationKind::Declarations => "Declarations",
+        PresentationKind::Assignments => "Assignments",
+        PresentationKind::Uses => "Uses",
+        PresentationKind::TextualOccurrences => "Textual occurrences",
+        PresentationKind::Mentions => "Mentions",
+    }
+}
+
+/// The language hint for a code block of lines from the given path, if we know
+/// one that common Markdown renderers will highlight.
+fn syntax_hint(path: &str) -> &'static str {
+    let ext = match path.rsplit_once('.') {
+        Some((_, ext)) => ext,
+        None => return "",
+    };
+    match ext {
+        "c" | "cc" | "cpp" | "cxx" | "h" | "hh" | "hpp" | "hxx" | "mm" | "m" => "cpp",
+        "js" | "jsm" | "mjs" | "sjs" | "jsx" => "js",
+        "ts" | "tsx" => "ts",
+        "rs" => "rust",
+        "py" => "python",
+        "java" => "java",
+        "kt" => "kotlin",
+        "idl" | "webidl" => "webidl",
+        "html" | "xhtml" => "html",
+        "css" => "css",
+        "json" => "json",
+        "toml" => "toml",
+        "yaml" | "yml" => "yaml",
+        "sh" => "sh",
+        _ => "",
+    }
+}
+
+/// A code fence long enough that it can't be closed by any run of backticks in
+/// the contents.
+fn code_fence(contents: &str) -> String {
+    let mut longest = 0;
+    let mut run = 0;
+    for c in contents.chars() {
+        if c == '`' {
+            run += 1;
+            longest = longest.max(run);
+        } else {
+            run = 0;
+        }
+    }
+    "`".repeat(longest.max(2) + 1)
+}
+
+/// Escape a value for use in a table cell, which can't contain newlines or
+/// unescaped pipes.
+fn table_cell(s: &str) -> String {
+    s.replace('|', "\\|").replace('\n', " ")
+}
+
+fn json_cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => table_cell(s),
+        _ => table_cell(&value.to_string()),
+    }
+}
+
+fn is_scalar(value: &Value) -> bool {
+    !value.is_object() && !value.is_array()
+}
+
+/// Render a list of JSON objects as a table whose columns are the union of the
+/// objects' keys in order of first appearance.
+fn json_table(rows: &[Value], out: &mut Vec<String>) {
+    let mut columns: Vec<&str> = vec![];
+    for row in rows {
+        if let Value::Object(obj) = row {
+            for key in obj.keys() {
+                if !columns.contains(&key.as_str()) {
+                    columns.push(key);
+                }
+            }
+        }
+    }
+    out.push(format!("| {} |", columns.join(" | ")));
+    out.push(format!("|{}", " --- |".repeat(columns.len())));
+    for row in rows {
+        let cells: Vec<String> = columns.iter().map(|col| json_cell(&row[*col])).collect();
+        out.push(format!("| {} |", cells.join(" | ")));
+    }
+    out.push(String::new());
+}
+
+/// Render a JSON summary: the scalar fields of an object become a key/value
+/// table and its other fields become subsections, lists of objects become
+/// tables, and anything else is included as a JSON code block.
+fn render_json(value: &Value, depth: usize, out: &mut Vec<String>) {
+    match value {
+        Value::Object(obj) => {
+            let scalars: Vec<_> = obj.iter().filter(|(_, v)| is_scalar(v)).collect();
+            if !scalars.is_empty() {
+                out.push("| Key | Value |".to_string());
+                out.push("| --- | --- |".to_string());
+                for (key, v) in scalars {
+                    out.push(format!("| {} | {} |", table_cell(key), json_cell(v)));
+                }
+                out.push(String::new());
+            }
+            for (key, v) in obj.iter().filter(|(_, v)| !is_scalar(v)) {
+                out.push(format!("{} {}", "#".repeat(depth.min(6)), key));
+                out.push(String::new());
+                render_json(v, depth + 1, out);
+            }
+        }
+        Value::Array(items) if !items.is_empty() && items.iter().all(Value::is_object) => {
+            json_table(items, out)
+        }
+        Value::Array(items) if items.iter().all(is_scalar) => {
+            for item in items {
+                out.push(format!("- {}", json_cell(item)));
+            }
+            out.push(String::new());
+        }
+        _ => {
+            let pretty = serde_json::to_string_pretty(value).unwrap_or_default();
+            let fence = code_fence(&pretty);
+            out.push(format!("{}json", fence));
+            out.push(pretty);
+            out.push(fence);
+            out.push(String::new());
+        }
+    }
+}
+
+impl RenderMarkdownCommand {
+    fn tree_url(&self, tree: &str) -> String {
+        format!("{}/{}", self.args.base_url.trim_end_matches('/'), tree)
+    }
+
+    fn file_link(&self, tree: &str, path: &str, line: Option<u32>) -> String {
+        let anchor = line.map(|l| format!("#{}", l)).unwrap_or_default();
+        format!(
+            "[{}]({}/source/{}{})",
+            path,
+            self.tree_url(tree),
+            path,
+            anchor
+        )
+    }
+
+    fn symbol_link(&self, tree: &str, pretty: &str, symbol: &str) -> String {
+        format!(
+            "[`{}`]({}/search?q=symbol:{})",
+            pretty,
+            self.tree_url(tree),
+            symbol
+        )
+    }
+
+    fn render_bundle(&self, tree: &str, bundle: &FlattenedResultsBundle, out: &mut Vec<String>) {
+        for annotated in &bundle.annotations {
+            let annotation = &annotated.annotation;
+            let mut banner = format!(
+                "> **{}** is {}.",
+                self.symbol_link(tree, &annotated.pretty, &annotated.symbol),
+                annotation.status.name()
+            );
+            if let Some(replacement) = &annotation.replacement {
+                banner.push_str(&format!(" Use `{}` instead.", replacement));
+            }
+            if let Some(message) = &annotation.message {
+                banner.push_str(&format!(" {}", message));
+            }
+            if let Some(bug) = &annotation.removal_bug {
+                banner.push_str(&format!(" Removal is tracked in {}.", bug));
+            }
+            out.push(banner);
+            out.push(String::new());
+        }
+
+        if bundle.path_kind_results.is_empty() {
+            out.push("No results.".to_string());
+            if !bundle.suggestions.is_empty() {
+                let suggestions: Vec<String> = bundle
+                    .suggestions
+                    .iter()
+                    .map(|s| format!("`{}`", s.identifier))
+                    .collect();
+                out.push(String::new());
+                out.push(format!("Did you mean: {}?", suggestions.join(", ")));
+            }
+            out.push(String::new());
+            return;
+        }
+
+        let is_html = bundle.content_type == "text/html";
+        for path_kind_group in &bundle.path_kind_results {
+            out.push(format!(
+                "## {} ({} files)",
+                path_kind_group.path_kind,
+                path_kind_group.file_names.len()
+            ));
+            out.push(String::new());
+            for kind_group in &path_kind_group.kind_groups {
+                if kind_group.pretty.is_empty() {
+                    out.push(format!("### {}", kind_heading(&kind_group.kind)));
+                } else {
+                    out.push(format!(
+                        "### {} of `{}`",
+                        kind_heading(&kind_group.kind),
+                        kind_group.pretty
+                    ));
+                }
+                out.push(String::new());
+                let by_file = kind_group
+                    .by_file
+                    .iter()
+                    .chain(kind_group.collapsed_by_file.iter());
+                for file_results in by_file {
+                    let first_line = file_results.line_spans.first().map(|s| s.key_line);
+                    out.push(self.file_link(tree, &file_results.file, first_line));
+                    out.push(String::new());
+
+                    let mut lines = vec![];
+                    for span in &file_results.line_spans {
+                        let contents = if is_html {
+                            html_to_text(&span.contents)
+                        } else {
+                            span.contents.clone()
+                        };
+                        for (i, line) in contents.lines().enumerate() {
+                            lines.push(format!("{:>5}: {}", span.line_range.0 as usize + i, line));
+                        }
+                    }
+                    let block = lines.join("\n");
+                    let fence = code_fence(&block);
+                    out.push(format!("{}{}", fence, syntax_hint(&file_results.file)));
+                    out.push(block);
+                    out.push(fence);
+                    out.push(String::new());
+                }
+            }
+        }
+    }
+
+    fn render_graphs(&self, tree: &str, graphs: &SymbolGraphCollection, out: &mut Vec<String>) {
+        for graph in &graphs.graphs {
+            let name = if graph.name.is_empty() {
+                "Graph"
+            } else {
+                graph.name.as_str()
+            };
+            out.push(format!("## {}", name));
+            out.push(String::new());
+
+            let edges = graph.list_edges();
+            if edges.is_empty() {
+                out.push("No edges.".to_string());
+                out.push(String::new());
+                continue;
+            }
+            out.push("| From | To |".to_string());
+            out.push("| --- | --- |".to_string());
+            for (source, target, _edge) in edges {
+                let source = graphs.node_set.get(&source);
+                let target = graphs.node_set.get(&target);
+                out.push(format!(
+                    "| {} | {} |",
+                    table_cell(&self.symbol_link(tree, &source.get_pretty(), &source.symbol)),
+                    table_cell(&self.symbol_link(tree, &target.get_pretty(), &target.symbol)),
+                ));
+            }
+            out.push(String::new());
+        }
+
+        if !graphs.overloads_hit.is_empty() {
+            out.push("## Limits hit".to_string());
+            out.push(String::new());
+            for overload in &graphs.overloads_hit {
+                out.push(format!(
+                    "- {:?}{}: included {} of {}",
+                    overload.kind,
+                    overload
+                        .sym
+                        .as_ref()
+                        .map(|sym| format!(" for `{}`", sym))
+                        .unwrap_or_default(),
+                    overload.included,
+                    overload.exist
+                ));
+            }
+            out.push(String::new());
+        }
+    }
+}
+
+#[async_trait]
+impl PipelineCommand for RenderMarkdownCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        let tree = server.tree_info()?.name;
+
+        let mut out = vec![];
+        if let Some(title) = &self.args.title {
+            out.push(format!("# {}", title));
+            out.push(String::new());
+        }
+        match input {
+            PipelineValues::FlattenedResultsBundle(bundle) => {
+                self.render_bundle(&tree, &bundle, &mut out)
+            }
+            PipelineValues::SymbolGraphCollection(graphs) => {
+                self.render_graphs(&tree, &graphs, &mut out)
+            }
+            PipelineValues::JsonValue(JsonValue { value }) => render_json(&value, 2, &mut out),
+            PipelineValues::JsonValueList(jvl) => {
+                for jv in &jvl.values {
+                    render_json(&jv.value, 2, &mut out);
+                }
+            }
+            _ => {
+                return Err(ServerError::StickyProblem(ErrorDetails {
+                    layer: ErrorLayer::ConfigLayer,
+                    message: "render-markdown needs a FlattenedResultsBundle, SymbolGraphCollection, JsonValue, or JsonValueList".to_string(),
+                }));
+            }
+        }
+
+        Ok(PipelineValues::TextFile(TextFile {
+            mime_type: "text/markdown".to_string(),
+            contents: out.join("\n"),
+        }))
+    }
+}
+
+#[test]
+fn test_render_json() {
+    let value = serde_json::json!({
+        "files": 3,
+        "name": "a|b",
+        "biggest": [{ "path": "a.cpp", "size": 10 }, { "path": "b.h", "kind": "header" }],
+    });
+    let mut out = vec![];
+    render_json(&value, 2, &mut out);
+    assert_eq!(
+        out.join("\n"),
+        "| Key | Value |\n| --- | --- |\n| files | 3 |\n| name | a\\|b |\n\n## biggest\n\n\
+         | path | size | kind |\n| --- | --- | --- |\n| a.cpp | 10 |  |\n| b.h |  | header |\n"
+    );
+    assert_eq!(code_fence("a ``` b"), "````");
+    assert_eq!(code_fence("plain"), "```");
+}
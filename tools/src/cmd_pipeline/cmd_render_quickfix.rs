@@ -0,0 +1,156 @@
+use async_trait::async_trait;
+use clap::Args;
+
+use super::interface::{
+    FlattenedResultsBundle, PipelineCommand, PipelineValues, PresentationKind, TextFile,
+};
+
+use crate::abstract_server::{
+    AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError, TextMatches,
+};
+
+/// Render results as `file:line:col: message` lines, which is the format of
+/// vim's quickfix list (`:cfile`) and of VS Code tasks using a
+/// `problemMatcher`, so that editor integrations can jump through searchfox
+/// hits natively.  This should be the last command in a pipeline.
+///
+/// Accepts a `FlattenedResultsBundle` (ex: from `compile-results`), where the
+/// message is the kind of hit, the symbol, and the line's contents, or
+/// `TextMatches` (ex: from `search-text`), where the message is the line and
+/// the column is that of the match.
+#[derive(Debug, Args)]
+pub struct RenderQuickfix {
+    /// Directory to prefix onto the tree-relative paths so they resolve against
+    /// a local checkout (ex: the editor's workspace root).
+    #[clap(long, value_parser)]
+    root: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct RenderQuickfixCommand {
+    pub args: RenderQuickfix,
+}
+
+fn kind_label(kind: &PresentationKind) -> &'static str {
+    match kind {
+        PresentationKind::IDL => "idl",
+        PresentationKind::Definitions => "definition",
+        PresentationKind::Declarations => "declaration",
+        PresentationKind::Assignments => "assignment",
+        PresentationKind::Uses => "use",
+        PresentationKind::TextualOccurrences => "text",
+        PresentationKind::Mentions => "mention",
+    }
+}
+
+/// Reduce a line of formatted HTML to its text, since quickfix messages are
+/// plain text.
+pub fn html_to_text(html: &str) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+impl RenderQuickfixCommand {
+    fn path(&self, path: &str) -> String {
+        match &self.args.root {
+            Some(root) => format!("{}/{}", root.trim_end_matches('/'), path),
+            None => path.to_string(),
+        }
+    }
+
+    fn render_bundle(&self, bundle: &FlattenedResultsBundle, lines: &mut Vec<String>) {
+        let is_html = bundle.content_type == "text/html";
+        for path_kind_group in &bundle.path_kind_results {
+            for kind_group in &path_kind_group.kind_groups {
+                let by_file = kind_group
+                    .by_file
+                    .iter()
+                    .chain(kind_group.collapsed_by_file.iter());
+                for file_results in by_file {
+                    for span in &file_results.line_spans {
+                        let contents = if is_html {
+                            html_to_text(&span.contents)
+                        } else {
+                            span.contents.clone()
+                        };
+                        // Multi-line spans are reported by their key line, so
+                        // only the first line of the contents is useful.
+                        let contents = contents.lines().next().unwrap_or_default().trim();
+                        lines.push(format!(
+                            "{}:{}:1: {} {}: {}",
+                            self.path(&file_results.file),
+                            span.key_line,
+                            kind_label(&kind_group.kind),
+                            kind_group.pretty,
+                            contents
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    fn render_text_matches(&self, matches: &TextMatches, lines: &mut Vec<String>) {
+        for file_matches in &matches.by_file {
+            for m in &file_matches.matches {
+                lines.push(format!(
+                    "{}:{}:{}: {}",
+                    self.path(&file_matches.file),
+                    m.line_num,
+                    m.bounds.start + 1,
+                    m.line_str.trim_end()
+                ));
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl PipelineCommand for RenderQuickfixCommand {
+    async fn execute(
+        &self,
+        _server: &(dyn AbstractServer + Send + Sync),
+        input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        let mut lines = vec![];
+        match input {
+            PipelineValues::FlattenedResultsBundle(bundle) => {
+                self.render_bundle(&bundle, &mut lines)
+            }
+            PipelineValues::TextMatches(matches) => self.render_text_matches(&matches, &mut lines),
+            _ => {
+                return Err(ServerError::StickyProblem(ErrorDetails {
+                    layer: ErrorLayer::ConfigLayer,
+                    message: "render-quickfix needs a FlattenedResultsBundle or TextMatches"
+                        .to_string(),
+                }));
+            }
+        }
+
+        Ok(PipelineValues::TextFile(TextFile {
+            mime_type: "text/plain".to_string(),
+            contents: lines.join("\n"),
+        }))
+    }
+}
+
+#[test]
+fn test_html_to_text() {
+    assert_eq!(
+        html_to_text(r#"<span class="syn_def">Foo</span>(a &lt; b &amp;&amp; c)"#),
+        "Foo(a < b && c)"
+    );
+}
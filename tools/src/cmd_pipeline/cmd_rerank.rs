@@ -0,0 +1,191 @@
+use std::{process::Stdio, time::Duration};
+
+use async_trait::async_trait;
+use clap::Args;
+use serde::Deserialize;
+use serde_json::{from_slice, json, to_value, to_vec, Value};
+use tokio::{io::AsyncWriteExt, process::Command};
+
+use super::interface::{PipelineCommand, PipelineValues};
+
+use crate::{
+    abstract_server::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError},
+    file_format::config::ExternalRankerConfig,
+};
+
+/// Re-order results using one of the tree's configured `external_rankers`, so
+/// that ranking experiments (ex: ML-based) can happen without changing
+/// searchfox.  The ranker is sent `{ kind, items }` and replies with
+/// `{ scores }`, one number per item; items are stably sorted by descending
+/// score.
+///
+/// Accepts:
+/// - `SymbolCrossrefInfoList` (ex: from `crossref-lookup`), where `kind` is
+///   "symbols" and the items are the serialized `SymbolCrossrefInfo`s.
+/// - `FlattenedResultsBundle` (ex: from `compile-results`), where `kind` is
+///   "files" and the items are `{ group, pathKind, kind, pretty, file }` for
+///   the files of each kind group, with `file` being the serialized file
+///   results.  Files are only re-ordered within their kind group.
+#[derive(Debug, Args)]
+pub struct Rerank {
+    /// The name of the ranker in the tree's `external_rankers` config.
+    #[clap(long, value_parser)]
+    ranker: String,
+}
+
+#[derive(Debug)]
+pub struct RerankCommand {
+    pub args: Rerank,
+}
+
+#[derive(Deserialize)]
+struct RankerResponse {
+    scores: Vec<f64>,
+}
+
+fn ranker_problem(message: String) -> ServerError {
+    ServerError::StickyProblem(ErrorDetails {
+        layer: ErrorLayer::ConfigLayer,
+        message,
+    })
+}
+
+/// Run the ranker on the items and return one score per item.
+async fn score_items(
+    name: &str,
+    config: &ExternalRankerConfig,
+    kind: &str,
+    items: Vec<Value>,
+) -> Result<Vec<f64>> {
+    let item_count = items.len();
+    let (program, args) = match config.command.split_first() {
+        Some(split) => split,
+        None => return Err(ranker_problem(format!("ranker {} has no command", name))),
+    };
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    // Write the request from its own task so that a ranker that starts
+    // replying before it has read everything can't deadlock us.
+    let request = to_vec(&json!({ "kind": kind, "items": items }))?;
+    let mut stdin = child.stdin.take().unwrap();
+    let writer = tokio::spawn(async move {
+        let _ = stdin.write_all(&request).await;
+    });
+
+    let output = tokio::time::timeout(
+        Duration::from_millis(config.timeout_ms),
+        child.wait_with_output(),
+    )
+    .await
+    .map_err(|_| {
+        ServerError::TransientProblem(ErrorDetails {
+            layer: ErrorLayer::ServerLayer,
+            message: format!("ranker {} timed out", name),
+        })
+    })??;
+    writer.await?;
+
+    if !output.status.success() {
+        return Err(ranker_problem(format!(
+            "ranker {} failed: {}",
+            name, output.status
+        )));
+    }
+    let response: RankerResponse = from_slice(&output.stdout)?;
+    if response.scores.len() != item_count {
+        return Err(ranker_problem(format!(
+            "ranker {} returned {} scores for {} items",
+            name,
+            response.scores.len(),
+            item_count
+        )));
+    }
+    Ok(response.scores)
+}
+
+/// Stably reorder the values by descending score.
+fn sort_by_scores<T>(values: Vec<T>, scores: &[f64]) -> Vec<T> {
+    let mut scored: Vec<(f64, T)> = scores.iter().copied().zip(values).collect();
+    scored.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+    scored.into_iter().map(|(_, value)| value).collect()
+}
+
+#[async_trait]
+impl PipelineCommand for RerankCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        let name = &self.args.ranker;
+        let config = match server.external_ranker_config(name)? {
+            Some(config) => config,
+            None => {
+                return Err(ServerError::StickyProblem(ErrorDetails {
+                    layer: ErrorLayer::BadInput,
+                    message: format!("rerank: unknown ranker {}", name),
+                }));
+            }
+        };
+
+        match input {
+            PipelineValues::SymbolCrossrefInfoList(mut list) => {
+                let mut items = vec![];
+                for info in &list.symbol_crossref_infos {
+                    items.push(to_value(info)?);
+                }
+                let scores = score_items(name, &config, "symbols", items).await?;
+                list.symbol_crossref_infos = sort_by_scores(list.symbol_crossref_infos, &scores);
+                Ok(PipelineValues::SymbolCrossrefInfoList(list))
+            }
+            PipelineValues::FlattenedResultsBundle(mut bundle) => {
+                let mut items = vec![];
+                let mut group = 0;
+                for path_kind_group in &bundle.path_kind_results {
+                    for kind_group in &path_kind_group.kind_groups {
+                        for file in &kind_group.by_file {
+                            items.push(json!({
+                                "group": group,
+                                "pathKind": path_kind_group.path_kind,
+                                "kind": kind_group.kind,
+                                "pretty": kind_group.pretty,
+                                "file": to_value(file)?,
+                            }));
+                        }
+                        group += 1;
+                    }
+                }
+                let scores = score_items(name, &config, "files", items).await?;
+
+                let mut remaining = &scores[..];
+                for path_kind_group in &mut bundle.path_kind_results {
+                    for kind_group in &mut path_kind_group.kind_groups {
+                        let (group_scores, rest) = remaining.split_at(kind_group.by_file.len());
+                        remaining = rest;
+                        let by_file = std::mem::take(&mut kind_group.by_file);
+                        kind_group.by_file = sort_by_scores(by_file, group_scores);
+                    }
+                }
+                Ok(PipelineValues::FlattenedResultsBundle(bundle))
+            }
+            _ => Err(ServerError::StickyProblem(ErrorDetails {
+                layer: ErrorLayer::ConfigLayer,
+                message: "rerank needs a SymbolCrossrefInfoList or FlattenedResultsBundle"
+                    .to_string(),
+            })),
+        }
+    }
+}
+
+#[test]
+fn test_sort_by_scores() {
+    assert_eq!(
+        sort_by_scores(vec!["a", "b", "c", "d"], &[0.5, 2.0, 0.5, f64::NAN]),
+        vec!["b", "a", "c", "d"]
+    );
+}
@@ -4,7 +4,7 @@ use itertools::Itertools;
 
 use super::{
     interface::{BatchGroupItem, BatchGroups, PipelineCommand, PipelineValues},
-    transforms::path_glob_transform,
+    transforms::{path_glob_transform, BugzillaComponentFilter},
 };
 
 use crate::abstract_server::{AbstractServer, FileMatches, Result};
@@ -34,6 +34,17 @@ pub struct SearchFiles {
 
     #[clap(long, short, value_parser, value_enum)]
     group_by: Option<GroupFilesBy>,
+
+    /// Only include files whose Bugzilla component (from moz.build metadata)
+    /// matches, ex: "Core::DOM: Core & HTML".  A bare product like "Core" is
+    /// also accepted.
+    #[clap(long, value_parser)]
+    component: Option<String>,
+
+    /// Only include test files whose test manifest skips them on this OS in at
+    /// least some configurations, ex: "win".
+    #[clap(long, value_parser)]
+    test_disabled_on: Option<String>,
 }
 
 #[derive(Debug)]
@@ -67,9 +78,35 @@ impl PipelineCommand for SearchFilesCommand {
             self.args.limit
         };
 
-        let matches = server
-            .search_files(&pathre_pattern, self.args.include_dirs, use_limit)
-            .await?;
+        let matches = if self.args.component.is_some() || self.args.test_disabled_on.is_some() {
+            // The limit needs to apply after we've filtered.
+            let mut matches = server
+                .search_files(&pathre_pattern, self.args.include_dirs, FILE_MATCH_LIMIT)
+                .await?;
+            if let Some(component) = &self.args.component {
+                let filter = BugzillaComponentFilter::parse(component);
+                matches
+                    .file_matches
+                    .retain(|fm| filter.matches(&fm.concise.bugzilla_component));
+            }
+            if let Some(os) = &self.args.test_disabled_on {
+                let mut kept = vec![];
+                for fm in matches.file_matches {
+                    if let Some(annotations) = server.file_test_annotations(&fm.path)? {
+                        if annotations.disabled_on(os) {
+                            kept.push(fm);
+                        }
+                    }
+                }
+                matches.file_matches = kept;
+            }
+            matches.file_matches.truncate(use_limit);
+            matches
+        } else {
+            server
+                .search_files(&pathre_pattern, self.args.include_dirs, use_limit)
+                .await?
+        };
 
         match self.args.group_by {
             Some(GroupFilesBy::Directory) => {
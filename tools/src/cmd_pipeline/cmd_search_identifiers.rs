@@ -1,11 +1,21 @@
+use std::time::Duration;
+
 use async_trait::async_trait;
 use clap::Args;
+use ustr::ustr;
 
-use super::interface::{
-    PipelineCommand, PipelineValues, SymbolList, SymbolQuality, SymbolWithContext,
+use super::{
+    interface::{
+        OverloadInfo, OverloadKind, PipelineCommand, PipelineValues, SymbolList, SymbolQuality,
+        SymbolWithContext,
+    },
+    warnings::record_overload,
 };
 
-use crate::abstract_server::{AbstractServer, Result};
+use crate::{
+    abstract_server::{AbstractServer, Result, ServerError},
+    file_format::{config::VIRTUAL_SYMBOL_PREFIX, identifiers::normalize_identifier},
+};
 
 /// Return the crossref data for one or more symbols received via pipeline or as
 /// explicit arguments.
@@ -37,6 +47,17 @@ pub struct SearchIdentifiers {
 
     #[clap(short, long, value_parser, default_value = "1000")]
     limit: usize,
+
+    /// Instead of searching for the identifiers, scan all identifiers for ones
+    /// matching this regex, like `^ns.*Service$`.  The scan is bounded by
+    /// `--limit` and `--symre-time-limit-ms` and any truncation is reported
+    /// as an overload.
+    #[clap(long, value_parser)]
+    symre: Option<String>,
+
+    /// How long the `--symre` scan may run before giving up.
+    #[clap(long, value_parser, default_value = "500")]
+    symre_time_limit_ms: u64,
 }
 
 #[derive(Debug)]
@@ -44,6 +65,73 @@ pub struct SearchIdentifiersCommand {
     pub args: SearchIdentifiers,
 }
 
+/// Determine the quality of an identifier search result given what was
+/// searched for and the identifier that matched.
+pub fn identifier_quality(exact_match: bool, searched: &str, result: &str) -> SymbolQuality {
+    let searched = normalize_identifier(searched);
+    let same_case = result.starts_with(searched.as_ref());
+    // Lengths are in characters rather than bytes so that non-ASCII
+    // identifiers aren't ranked as worse matches than ASCII ones.
+    let searched_len = searched.chars().count() as u32;
+    let extra = (result.chars().count() as u32).saturating_sub(searched_len);
+    match (exact_match, same_case, extra) {
+        (true, true, _) => SymbolQuality::ExplicitIdentifier,
+        (false, true, 0) => SymbolQuality::ExactIdentifier,
+        (_, false, 0) => SymbolQuality::CaseMismatchedIdentifier,
+        (_, true, extra) => SymbolQuality::IdentifierPrefix(searched_len, extra),
+        (_, false, extra) => SymbolQuality::CaseMismatchedIdentifierPrefix(searched_len, extra),
+    }
+}
+
+impl SearchIdentifiersCommand {
+    async fn scan_regex(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        pattern: &str,
+    ) -> Result<PipelineValues> {
+        let (matches, truncated) = server
+            .search_identifiers_regex(
+                pattern,
+                self.args.types_only,
+                self.args.limit,
+                Duration::from_millis(self.args.symre_time_limit_ms),
+            )
+            .await?;
+
+        let symbols: Vec<SymbolWithContext> = matches
+            .into_iter()
+            .map(|(sym, from_ident)| SymbolWithContext {
+                symbol: sym,
+                quality: SymbolQuality::IdentifierRegex,
+                from_identifier: Some(from_ident),
+            })
+            .collect();
+
+        let mut overloads = vec![];
+        if truncated {
+            record_overload(
+                &mut overloads,
+                "search-identifiers",
+                OverloadInfo {
+                    kind: OverloadKind::IdentifierScan,
+                    sym: None,
+                    // We don't know how many more matches there would have been,
+                    // so like other soft limits we say 0.
+                    exist: 0,
+                    included: symbols.len() as u32,
+                    local_limit: 0,
+                    global_limit: self.args.limit as u32,
+                },
+            );
+        }
+
+        Ok(PipelineValues::SymbolList(SymbolList {
+            symbols,
+            overloads,
+        }))
+    }
+}
+
 #[async_trait]
 impl PipelineCommand for SearchIdentifiersCommand {
     async fn execute(
@@ -51,6 +139,10 @@ impl PipelineCommand for SearchIdentifiersCommand {
         server: &(dyn AbstractServer + Send + Sync),
         input: PipelineValues,
     ) -> Result<PipelineValues> {
+        if let Some(pattern) = &self.args.symre {
+            return self.scan_regex(server, pattern).await;
+        }
+
         let identifier_list: Vec<String> = match input {
             PipelineValues::IdentifierList(il) => il
                 .identifiers
@@ -74,6 +166,22 @@ impl PipelineCommand for SearchIdentifiersCommand {
             if id.len() < self.args.min_length {
                 continue;
             }
+            let first_for_id = symbols.len();
+
+            // An identifier naming a virtual symbol group finds the virtual
+            // symbol, which `crossref-lookup` expands into its members.  Trees
+            // without local config just don't have any.
+            let virtual_config = match server.virtual_symbol_config(&id) {
+                Err(ServerError::Unsupported) => None,
+                result => result?,
+            };
+            if virtual_config.is_some() && !self.args.types_only {
+                symbols.push(SymbolWithContext {
+                    symbol: ustr(&format!("{}{}", VIRTUAL_SYMBOL_PREFIX, id)),
+                    quality: identifier_quality(self.args.exact_match, &id, &id),
+                    from_identifier: Some(ustr(&id)),
+                });
+            }
 
             for (sym, from_ident) in server
                 .search_identifiers(
@@ -88,27 +196,59 @@ impl PipelineCommand for SearchIdentifiersCommand {
                     continue;
                 }
 
-                let quality = match (
-                    &self.args.exact_match,
-                    id.as_str() == from_ident.as_str(),
-                    &id,
-                    &from_ident,
-                ) {
-                    (true, _, _, _) => SymbolQuality::ExplicitIdentifier,
-                    (false, true, _, _) => SymbolQuality::ExactIdentifier,
-                    (_, _, searched, result) => SymbolQuality::IdentifierPrefix(
-                        searched.len() as u32,
-                        (result.len() - searched.len()) as u32,
-                    ),
-                };
+                let quality = identifier_quality(self.args.exact_match, &id, &from_ident);
                 symbols.push(SymbolWithContext {
                     symbol: sym,
                     quality,
                     from_identifier: Some(from_ident),
                 });
             }
+
+            // For case-insensitive searches, the server returns results in
+            // case-folded order, so `document` and `Document` are intermixed.
+            // Stably move the case-mismatched results after the exact-case
+            // results so that what the user actually typed wins.
+            symbols[first_for_id..].sort_by_key(|s| s.quality.is_case_mismatched());
         }
 
-        Ok(PipelineValues::SymbolList(SymbolList { symbols }))
+        Ok(PipelineValues::SymbolList(SymbolList {
+            symbols,
+            overloads: vec![],
+        }))
     }
+
+    /// Identifier lookups are bounded by `--limit`.
+    fn is_cheap(&self) -> bool {
+        true
+    }
+}
+
+#[test]
+fn test_identifier_quality() {
+    assert!(identifier_quality(false, "document", "document") == SymbolQuality::ExactIdentifier);
+    assert!(
+        identifier_quality(false, "document", "Document")
+            == SymbolQuality::CaseMismatchedIdentifier
+    );
+    assert!(
+        identifier_quality(true, "document", "Document") == SymbolQuality::CaseMismatchedIdentifier
+    );
+    assert!(
+        identifier_quality(false, "document", "documentURI")
+            == SymbolQuality::IdentifierPrefix(8, 3)
+    );
+    assert!(
+        identifier_quality(false, "document", "DocumentType")
+            == SymbolQuality::CaseMismatchedIdentifierPrefix(8, 4)
+    );
+    assert!(
+        identifier_quality(false, "größe", "größeMax") == SymbolQuality::IdentifierPrefix(5, 3)
+    );
+    assert!(
+        identifier_quality(false, "cafe\u{301}", "caf\u{e9}") == SymbolQuality::ExactIdentifier
+    );
+    assert!(
+        identifier_quality(false, "document", "document")
+            < identifier_quality(false, "document", "Document")
+    );
 }
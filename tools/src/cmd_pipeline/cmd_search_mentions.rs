@@ -0,0 +1,158 @@
+use async_trait::async_trait;
+use clap::Args;
+use ustr::Ustr;
+
+use super::{
+    cmd_search_text::whole_word_pattern,
+    interface::{MentionMatches, PipelineCommand, PipelineValues, PrettyMentions, SymbolRelation},
+    warnings::add_pipeline_warning,
+};
+
+use crate::abstract_server::{
+    AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError, TextMatchInFile,
+    TextSearchExitReason,
+};
+
+/// Find mentions of the queried symbols' pretty names in comments and in-tree
+/// documentation so that documentation references can be shown alongside the
+/// semantic results by `compile-results`.
+///
+/// Each distinct pretty name of the directly queried symbols is searched for
+/// as a case-sensitive whole word.  Every match in a documentation file
+/// (`.md`, `.rst`) is kept, but matches in other files are only kept if they
+/// look like they're in a comment.  We have no tokenizer here, so "in a
+/// comment" means the line has a `//`, `/*`, `#`, or `<!--` before the match
+/// or the line is a block comment continuation starting with `*`.  This will
+/// miss the middle lines of block comments that don't use leading `*`s and
+/// will mistake `#include` and friends for comments, but mentions are
+/// presented as their own kind group so a little noise is tolerable.
+///
+/// This is local-only at this time because it performs fulltext searches.
+#[derive(Debug, Args)]
+pub struct SearchMentions {
+    /// Maximum number of fulltext matches per pretty name, before filtering
+    /// down to comments and documentation.
+    #[clap(short, long, value_parser, default_value = "1000")]
+    limit: usize,
+}
+
+#[derive(Debug)]
+pub struct SearchMentionsCommand {
+    pub args: SearchMentions,
+}
+
+fn is_doc_file(path: &str) -> bool {
+    path.ends_with(".md") || path.ends_with(".rst")
+}
+
+const COMMENT_STARTS: &[&str] = &["//", "/*", "#", "<!--"];
+
+/// Does the match look like it's in a comment?  See the command's docs for the
+/// heuristic and its limitations.
+fn is_comment_mention(text_match: &TextMatchInFile) -> bool {
+    let line = &text_match.line_str;
+    if line.trim_start().starts_with('*') {
+        return true;
+    }
+    // Be conservative if the bounds don't land on a char boundary.
+    let before = match line.get(..text_match.bounds.start.max(0) as usize) {
+        Some(before) => before,
+        None => return false,
+    };
+    COMMENT_STARTS.iter().any(|start| before.contains(start))
+}
+
+#[async_trait]
+impl PipelineCommand for SearchMentionsCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        let infos = match input {
+            PipelineValues::SymbolCrossrefInfoList(scil) => scil.symbol_crossref_infos,
+            _ => {
+                return Err(ServerError::StickyProblem(ErrorDetails {
+                    layer: ErrorLayer::ConfigLayer,
+                    message: "search-mentions needs a SymbolCrossrefInfoList".to_string(),
+                }));
+            }
+        };
+
+        // Overloads and the like share a pretty name, so only search once.
+        let mut prettys: Vec<Ustr> = vec![];
+        for info in &infos {
+            let pretty = info.get_pretty();
+            if matches!(info.relation, SymbolRelation::Queried) && !prettys.contains(&pretty) {
+                prettys.push(pretty);
+            }
+        }
+
+        let mut by_pretty = vec![];
+        for pretty in prettys {
+            let matches = server
+                .search_text(&whole_word_pattern(&pretty), false, "", self.args.limit, 0)
+                .await?;
+            match matches.exit_reason {
+                TextSearchExitReason::Complete => {}
+                TextSearchExitReason::MatchLimit => add_pipeline_warning(
+                    "search-mentions",
+                    format!(
+                        "the search for {} stopped at the limit of {} matches, so there may be \
+                         more mentions",
+                        pretty, self.args.limit
+                    ),
+                ),
+                TextSearchExitReason::Timeout => add_pipeline_warning(
+                    "search-mentions",
+                    format!(
+                        "the search for {} timed out, so there may be more mentions",
+                        pretty
+                    ),
+                ),
+                // The server has already warned about the shard.
+                TextSearchExitReason::ShardFailed => {}
+            }
+
+            let mut by_file = vec![];
+            for mut file_matches in matches.by_file {
+                if !is_doc_file(&file_matches.file) {
+                    file_matches.matches.retain(is_comment_mention);
+                }
+                if !file_matches.matches.is_empty() {
+                    by_file.push(file_matches);
+                }
+            }
+            if !by_file.is_empty() {
+                by_pretty.push(PrettyMentions { pretty, by_file });
+            }
+        }
+
+        Ok(PipelineValues::MentionMatches(MentionMatches { by_pretty }))
+    }
+}
+
+#[test]
+fn test_is_comment_mention() {
+    use crate::abstract_server::TextBounds;
+
+    let mention = |line: &str| {
+        let start = line.find("Frob").unwrap();
+        is_comment_mention(&TextMatchInFile {
+            line_num: 1,
+            bounds: TextBounds {
+                start: start as i32,
+                end_exclusive: (start + 4) as i32,
+            },
+            line_str: line.to_string(),
+            context_before: vec![],
+            context_after: vec![],
+        })
+    };
+    assert!(mention("  // Calls Frob when ready."));
+    assert!(mention("x = 1; /* see Frob */"));
+    assert!(mention("   * Frob must be called on the main thread."));
+    assert!(mention("# Frob is configured here"));
+    assert!(!mention("  Frob(aThing);"));
+    assert!(!mention("  Frob(aThing); // no comment before the call"));
+}
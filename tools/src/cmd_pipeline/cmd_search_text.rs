@@ -1,15 +1,38 @@
+use std::time::{Duration, Instant};
+
 use async_trait::async_trait;
 use clap::Args;
+use regex::RegexBuilder;
+use ustr::UstrSet;
 
 use super::{
     interface::{PipelineCommand, PipelineValues},
     transforms::path_glob_transform,
+    warnings::add_pipeline_warning,
 };
 
-use crate::abstract_server::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError};
+use crate::abstract_server::{
+    AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError, TextBounds, TextMatchInFile,
+    TextMatches, TextMatchesByFile, TextSearchExitReason,
+};
 
 /// Perform a fulltext search against our livegrep/codesearch server over gRPC.
 /// This is local-only at this time.
+///
+/// If the input is a `FileMatches` (ex: from `search-files`), results will be
+/// constrained to only those files.  When no explicit path constraint was
+/// provided, we express the file set as path regexps of up to
+/// `MAX_FILES_FOR_PATHRE` files each so codesearch can do the filtering for
+/// us, running one bounded search per batch; otherwise we filter the results
+/// on our side and apply the `limit` after filtering.
+///
+/// Codesearch only matches within single lines, so `--multiline` patterns
+/// (ex: a function signature split across lines) are instead matched by us.
+/// We derive the longest literal that every match must have on a single line,
+/// ask codesearch for the files containing it, and then fetch those files and
+/// apply the pattern to their full contents.  This is subject to strict budgets
+/// on the number and size of the files we scan and the time we spend, and
+/// results that exceed them are marked as incomplete.
 #[derive(Debug, Args)]
 pub struct SearchText {
     /// Text to search for; this will be regexp escaped.
@@ -33,6 +56,24 @@ pub struct SearchText {
     #[clap(short, long, value_parser)]
     case_sensitive: bool,
 
+    /// Only match `text` as a whole word.  Word boundaries are only required
+    /// at ends of the text that are word characters, so "->Init" will still
+    /// match "foo->Init()" but not "foo->Initialize()".
+    #[clap(short, long, value_parser)]
+    word: bool,
+
+    /// Only match lines consisting entirely of the text or regexp, ignoring
+    /// leading and trailing whitespace.
+    #[clap(long, value_parser)]
+    line_anchored: bool,
+
+    /// Allow matches to span multiple lines, in which case `^` and `$` match
+    /// at line boundaries and `\s` matches newlines.  Matches are reported on
+    /// the line they start on.  The pattern must contain a literal of at least
+    /// 3 characters outside of any group or repetition.
+    #[clap(long, value_parser)]
+    multiline: bool,
+
     #[clap(short, long, value_parser, default_value = "0")]
     limit: usize,
 }
@@ -42,17 +83,397 @@ pub struct SearchTextCommand {
     pub args: SearchText,
 }
 
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Escape the text into a regexp, adding `\b` word boundaries at each end of
+/// the text that is a word character.  A boundary next to a non-word character
+/// would require the adjacent character in the line to be a word character,
+/// which is not what anyone means by a whole-word search.
+pub fn whole_word_pattern(text: &str) -> String {
+    let mut pattern = regex::escape(text);
+    if text.chars().next().map_or(false, is_word_char) {
+        pattern.insert_str(0, "\\b");
+    }
+    if text.chars().last().map_or(false, is_word_char) {
+        pattern.push_str("\\b");
+    }
+    pattern
+}
+
+/// The maximum number of input files we will turn into a single path regexp
+/// alternation for codesearch.  Larger file sets are searched in batches.
+const MAX_FILES_FOR_PATHRE: usize = 64;
+
+/// The shortest prefilter literal we'll accept for a multiline search, since
+/// shorter literals match too many files to scan.
+const MIN_MULTILINE_PREFILTER_LEN: usize = 3;
+/// How many prefilter matches we ask codesearch for when finding candidate
+/// files for a multiline search.
+const MAX_MULTILINE_PREFILTER_MATCHES: usize = 10000;
+/// The most candidate files a multiline search will scan.
+const MAX_MULTILINE_FILES: usize = 500;
+/// Candidate files larger than this aren't scanned by multiline searches.
+const MAX_MULTILINE_FILE_BYTES: usize = 4 * 1024 * 1024;
+/// The most time a multiline search will spend scanning candidate files.
+const MULTILINE_TIME_BUDGET: Duration = Duration::from_secs(10);
+
+/// Limit the matches to the first `limit` matching lines, where 0 means no
+/// limit, returning whether any were dropped.
+fn truncate_matches(matches: &mut TextMatches, limit: usize) -> bool {
+    if limit == 0 {
+        return false;
+    }
+    let mut remaining = limit;
+    let mut truncated = false;
+    for by_file in &mut matches.by_file {
+        if by_file.matches.len() > remaining {
+            by_file.matches.truncate(remaining);
+            truncated = true;
+        }
+        remaining -= by_file.matches.len();
+    }
+    matches
+        .by_file
+        .retain(|by_file| !by_file.matches.is_empty());
+    if truncated {
+        matches.exit_reason = matches.exit_reason.max(TextSearchExitReason::MatchLimit);
+    }
+    truncated
+}
+
+/// Find the longest literal in the regexp that must appear on a single line of
+/// every match, returning None if there is none.  This is conservative, only
+/// considering literals outside of groups and character classes and ending
+/// literals at anything that could match a newline.  Alternations outside of
+/// groups mean there is no required literal.
+fn multiline_prefilter(pattern: &str) -> Option<String> {
+    let mut literals: Vec<String> = vec![];
+    let mut current = String::new();
+    // Was the last thing we saw a literal character in `current`?
+    let mut after_literal = false;
+    let mut depth = 0;
+    let mut in_class = false;
+
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            let escaped = chars.next()?;
+            // Letters and digits are classes (ex: `\s`), assertions (ex: `\b`),
+            // or special characters (ex: `\n`); anything else is itself.
+            if depth == 0 && !in_class && !escaped.is_ascii_alphanumeric() {
+                current.push(escaped);
+                after_literal = true;
+            } else {
+                literals.push(std::mem::take(&mut current));
+                after_literal = false;
+            }
+            continue;
+        }
+        if in_class {
+            in_class = c != ']';
+            continue;
+        }
+        match c {
+            '[' => in_class = true,
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            '|' if depth == 0 => return None,
+            // Repetitions that can skip the character they apply to.
+            '*' | '?' | '{' if depth == 0 => {
+                if after_literal {
+                    current.pop();
+                }
+                if c == '{' {
+                    while chars.next_if(|c| *c != '}').is_some() {}
+                    chars.next();
+                }
+            }
+            // Anything else that isn't in a group and can't match a newline
+            // is literal.
+            _ if depth == 0 && !matches!(c, '.' | '^' | '$' | '+' | '\n') => {
+                current.push(c);
+                after_literal = true;
+                continue;
+            }
+            _ => {}
+        }
+        literals.push(std::mem::take(&mut current));
+        after_literal = false;
+    }
+    literals.push(current);
+
+    literals
+        .into_iter()
+        .max_by_key(|literal| literal.chars().count())
+        .filter(|literal| literal.trim().chars().count() >= MIN_MULTILINE_PREFILTER_LEN)
+}
+
+/// Find the multiline regexp matches in the file's contents, reporting each
+/// match on the line it starts on, with bounds up to the end of that line.
+fn multiline_matches_in_file(re: &regex::Regex, contents: &str) -> Vec<TextMatchInFile> {
+    let mut matches = vec![];
+    // The line number and byte offset of the start of the line we're scanning.
+    let mut line_num = 1;
+    let mut line_start = 0;
+    for m in re.find_iter(contents) {
+        // Empty matches are never what anyone wants from a multiline search.
+        if m.as_str().is_empty() {
+            continue;
+        }
+        line_num += contents[line_start..m.start()].matches('\n').count() as u32;
+        line_start = contents[..m.start()].rfind('\n').map_or(0, |pos| pos + 1);
+        let line_end = contents[line_start..]
+            .find('\n')
+            .map_or(contents.len(), |pos| line_start + pos);
+        matches.push(TextMatchInFile {
+            line_num,
+            bounds: TextBounds {
+                start: (m.start() - line_start) as i32,
+                end_exclusive: (m.end().min(line_end) - line_start) as i32,
+            },
+            line_str: contents[line_start..line_end].to_string(),
+            context_before: vec![],
+            context_after: vec![],
+        });
+    }
+    matches
+}
+
+impl SearchTextCommand {
+    /// Run a search per path regexp, concatenating their results.  If
+    /// codesearch only searches the input files, each search is bounded by
+    /// what's left of the limit, and we stop once it's used up.  Otherwise the
+    /// limit has to be applied after we filter the results, or it could be
+    /// used up by matches in other files.
+    async fn search_batches(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        re_pattern: &str,
+        pathre_patterns: &[String],
+        codesearch_filters_files: bool,
+    ) -> Result<TextMatches> {
+        let mut matches = TextMatches {
+            by_file: vec![],
+            exit_reason: TextSearchExitReason::Complete,
+        };
+        let mut match_count = 0;
+        for pathre_pattern in pathre_patterns {
+            let limit = if !codesearch_filters_files || self.args.limit == 0 {
+                0
+            } else if match_count < self.args.limit {
+                self.args.limit - match_count
+            } else {
+                // The remaining batches may have had matches too.
+                matches.exit_reason = matches.exit_reason.max(TextSearchExitReason::MatchLimit);
+                break;
+            };
+            let batch = server
+                .search_text(
+                    re_pattern,
+                    !self.args.case_sensitive,
+                    pathre_pattern,
+                    limit,
+                    0,
+                )
+                .await?;
+            match_count += batch
+                .by_file
+                .iter()
+                .map(|by_file| by_file.matches.len())
+                .sum::<usize>();
+            matches.exit_reason = matches.exit_reason.max(batch.exit_reason);
+            matches.by_file.extend(batch.by_file);
+        }
+        Ok(matches)
+    }
+
+    /// Run a multiline search by scanning the files codesearch finds for the
+    /// prefilter ourselves.  Budget overruns are reported as warnings and in
+    /// the exit reason.
+    async fn multiline_search(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        re_pattern: &str,
+        prefilter: &str,
+        pathre_pattern: &str,
+        file_set: &Option<UstrSet>,
+    ) -> Result<TextMatches> {
+        let re = RegexBuilder::new(re_pattern)
+            .case_insensitive(!self.args.case_sensitive)
+            .multi_line(true)
+            .size_limit(1 << 20)
+            .build()
+            .map_err(|err| {
+                ServerError::StickyProblem(ErrorDetails {
+                    layer: ErrorLayer::BadInput,
+                    message: format!("Bad `multiline` pattern: {}", err),
+                })
+            })?;
+
+        // The prefilter loses any inline case-insensitivity flag.
+        let candidates = server
+            .search_text(
+                prefilter,
+                !self.args.case_sensitive || re_pattern.contains("(?i"),
+                pathre_pattern,
+                MAX_MULTILINE_PREFILTER_MATCHES,
+                0,
+            )
+            .await?;
+        let mut exit_reason = candidates.exit_reason;
+        if !exit_reason.is_complete() {
+            add_pipeline_warning(
+                "search-text",
+                format!(
+                    "codesearch didn't find all the files containing \"{}\", so there may be \
+                     more multiline matches",
+                    prefilter
+                ),
+            );
+        }
+
+        let mut candidate_files: Vec<TextMatchesByFile> = candidates
+            .by_file
+            .into_iter()
+            .filter(|by_file| {
+                file_set
+                    .as_ref()
+                    .map_or(true, |files| files.contains(&by_file.file))
+            })
+            .collect();
+        if candidate_files.len() > MAX_MULTILINE_FILES {
+            add_pipeline_warning(
+                "search-text",
+                format!(
+                    "only scanning the first {} of {} files for multiline matches",
+                    MAX_MULTILINE_FILES,
+                    candidate_files.len()
+                ),
+            );
+            candidate_files.truncate(MAX_MULTILINE_FILES);
+            exit_reason = exit_reason.max(TextSearchExitReason::MatchLimit);
+        }
+
+        let started = Instant::now();
+        let mut match_count = 0;
+        let mut by_file = vec![];
+        for candidate in candidate_files {
+            if started.elapsed() > MULTILINE_TIME_BUDGET {
+                add_pipeline_warning(
+                    "search-text",
+                    "ran out of time scanning files for multiline matches".to_string(),
+                );
+                exit_reason = TextSearchExitReason::Timeout;
+                break;
+            }
+            if self.args.limit > 0 && match_count >= self.args.limit {
+                add_pipeline_warning(
+                    "search-text",
+                    format!(
+                        "stopped at the limit of {} multiline matches, so there may be more",
+                        self.args.limit
+                    ),
+                );
+                exit_reason = exit_reason.max(TextSearchExitReason::MatchLimit);
+                break;
+            }
+
+            // A candidate we can't read shouldn't cost us the matches in the
+            // rest of them.
+            let contents = match server.fetch_raw_source(&candidate.file).await {
+                Ok(contents) => contents,
+                Err(err) => {
+                    add_pipeline_warning(
+                        "search-text",
+                        format!(
+                            "unable to scan {} for multiline matches: {:?}",
+                            candidate.file, err
+                        ),
+                    );
+                    continue;
+                }
+            };
+            if contents.len() > MAX_MULTILINE_FILE_BYTES {
+                add_pipeline_warning(
+                    "search-text",
+                    format!(
+                        "{} is too big to scan for multiline matches",
+                        candidate.file
+                    ),
+                );
+                exit_reason = exit_reason.max(TextSearchExitReason::MatchLimit);
+                continue;
+            }
+
+            let mut matches = multiline_matches_in_file(&re, &contents);
+            if self.args.limit > 0 {
+                matches.truncate(self.args.limit - match_count);
+            }
+            if matches.is_empty() {
+                continue;
+            }
+            match_count += matches.len();
+            by_file.push(TextMatchesByFile {
+                file: candidate.file,
+                path_kind: candidate.path_kind,
+                matches,
+            });
+        }
+
+        Ok(TextMatches {
+            by_file,
+            exit_reason,
+        })
+    }
+}
+
 #[async_trait]
 impl PipelineCommand for SearchTextCommand {
     async fn execute(
         &self,
         server: &(dyn AbstractServer + Send + Sync),
-        _input: PipelineValues,
+        input: PipelineValues,
     ) -> Result<PipelineValues> {
-        let re_pattern = if let Some(re) = &self.args.re {
+        let file_set: Option<UstrSet> = match input {
+            PipelineValues::FileMatches(fm) => {
+                Some(fm.file_matches.into_iter().map(|fm| fm.path).collect())
+            }
+            PipelineValues::Void => None,
+            _ => {
+                return Err(ServerError::StickyProblem(ErrorDetails {
+                    layer: ErrorLayer::ConfigLayer,
+                    message: "search-text needs FileMatches or Void".to_string(),
+                }));
+            }
+        };
+
+        // An empty file set can't match anything, so don't bother asking.
+        if let Some(files) = &file_set {
+            if files.is_empty() {
+                return Ok(PipelineValues::TextMatches(TextMatches {
+                    by_file: vec![],
+                    exit_reason: TextSearchExitReason::Complete,
+                }));
+            }
+        }
+
+        let mut re_pattern = if let Some(re) = &self.args.re {
+            if self.args.word {
+                return Err(ServerError::StickyProblem(ErrorDetails {
+                    layer: ErrorLayer::BadInput,
+                    message: "`word` can only be used with search text; use `\\b` in `re`"
+                        .to_string(),
+                }));
+            }
             re.clone()
         } else if let Some(text) = &self.args.text {
-            regex::escape(text)
+            if self.args.word {
+                whole_word_pattern(text)
+            } else {
+                regex::escape(text)
+            }
         } else {
             return Err(ServerError::StickyProblem(ErrorDetails {
                 layer: ErrorLayer::BadInput,
@@ -60,23 +481,205 @@ impl PipelineCommand for SearchTextCommand {
             }));
         };
 
-        let pathre_pattern = if let Some(pathre) = &self.args.pathre {
-            pathre.clone()
+        let prefilter = if self.args.multiline {
+            match multiline_prefilter(&re_pattern) {
+                Some(literal) => Some(regex::escape(&literal)),
+                None => {
+                    return Err(ServerError::StickyProblem(ErrorDetails {
+                        layer: ErrorLayer::BadInput,
+                        message: format!(
+                            "`multiline` patterns need a literal of at least {} characters \
+                             outside of any group or repetition",
+                            MIN_MULTILINE_PREFILTER_LEN
+                        ),
+                    }));
+                }
+            }
+        } else {
+            None
+        };
+
+        if self.args.line_anchored {
+            re_pattern = format!("^\\s*(?:{})\\s*$", re_pattern);
+        }
+
+        // Whether codesearch only searches the input files, which is the case
+        // unless we have to filter its results on our side.
+        let mut codesearch_filters_files = file_set.is_none();
+        let pathre_patterns = if let Some(pathre) = &self.args.pathre {
+            vec![pathre.clone()]
         } else if let Some(path) = &self.args.path {
-            path_glob_transform(path)
+            vec![path_glob_transform(path)]
         } else {
-            "".to_string()
+            match &file_set {
+                Some(files) => {
+                    let mut paths: Vec<String> =
+                        files.iter().map(|path| regex::escape(path)).collect();
+                    // Keep the patterns stable for tracing/snapshot purposes,
+                    // and the batches' results in path order.
+                    paths.sort();
+                    codesearch_filters_files = true;
+                    paths
+                        .chunks(MAX_FILES_FOR_PATHRE)
+                        .map(|batch| format!("^({})$", batch.join("|")))
+                        .collect()
+                }
+                None => vec!["".to_string()],
+            }
         };
 
-        let matches = server
-            .search_text(
-                &re_pattern,
-                !self.args.case_sensitive,
-                &pathre_pattern,
-                self.args.limit,
-            )
-            .await?;
+        let mut matches = match &prefilter {
+            Some(prefilter) => {
+                // The candidates are filtered to the file set anyway, so a
+                // file set that needs batching isn't worth the extra searches.
+                let pathre_pattern = match pathre_patterns.as_slice() {
+                    [pathre_pattern] => pathre_pattern.as_str(),
+                    _ => "",
+                };
+                self.multiline_search(server, &re_pattern, prefilter, pathre_pattern, &file_set)
+                    .await?
+            }
+            None => {
+                self.search_batches(
+                    server,
+                    &re_pattern,
+                    &pathre_patterns,
+                    codesearch_filters_files,
+                )
+                .await?
+            }
+        };
+
+        if let Some(files) = &file_set {
+            matches
+                .by_file
+                .retain(|by_file| files.contains(&by_file.file));
+        }
+        if !codesearch_filters_files
+            && prefilter.is_none()
+            && truncate_matches(&mut matches, self.args.limit)
+        {
+            add_pipeline_warning(
+                "search-text",
+                format!(
+                    "stopped at the limit of {} matches, so there may be more",
+                    self.args.limit
+                ),
+            );
+        }
+
+        match matches.exit_reason {
+            TextSearchExitReason::Complete => {}
+            // These limits were applied by us and have already been warned
+            // about.
+            TextSearchExitReason::MatchLimit
+                if prefilter.is_some() || !codesearch_filters_files => {}
+            TextSearchExitReason::MatchLimit => add_pipeline_warning(
+                "search-text",
+                format!(
+                    "codesearch stopped at the limit of {} matches, so there may be more",
+                    self.args.limit
+                ),
+            ),
+            // Multiline searches have already warned about running out of
+            // time, whether it was codesearch or their own scan.
+            TextSearchExitReason::Timeout if prefilter.is_some() => {}
+            TextSearchExitReason::Timeout => add_pipeline_warning(
+                "search-text",
+                "codesearch timed out, so there may be more matches".to_string(),
+            ),
+            // The server has already warned about the shard.
+            TextSearchExitReason::ShardFailed => {}
+        }
 
         Ok(PipelineValues::TextMatches(matches))
     }
 }
+
+#[test]
+fn test_whole_word_pattern() {
+    assert_eq!(whole_word_pattern("Init"), "\\bInit\\b");
+    assert_eq!(
+        whole_word_pattern("->Init"),
+        format!("{}\\b", regex::escape("->Init"))
+    );
+    assert_eq!(whole_word_pattern("operator()"), "\\boperator\\(\\)");
+    assert_eq!(whole_word_pattern("::"), "::");
+}
+
+#[test]
+fn test_truncate_matches() {
+    let by_file = |file: &str, lines: u32| TextMatchesByFile {
+        file: ustr::ustr(file),
+        path_kind: ustr::ustr("Normal"),
+        matches: (1..=lines)
+            .map(|line_num| TextMatchInFile {
+                line_num,
+                bounds: TextBounds {
+                    start: 0,
+                    end_exclusive: 1,
+                },
+                line_str: "x".to_string(),
+                context_before: vec![],
+                context_after: vec![],
+            })
+            .collect(),
+    };
+    let mut matches = TextMatches {
+        by_file: vec![
+            by_file("a.cpp", 2),
+            by_file("b.cpp", 2),
+            by_file("c.cpp", 1),
+        ],
+        exit_reason: TextSearchExitReason::Complete,
+    };
+    assert!(!truncate_matches(&mut matches, 5));
+    assert!(truncate_matches(&mut matches, 3));
+    assert_eq!(
+        matches
+            .by_file
+            .iter()
+            .map(|by_file| (by_file.file.as_str(), by_file.matches.len()))
+            .collect::<Vec<_>>(),
+        vec![("a.cpp", 2), ("b.cpp", 1)]
+    );
+    assert_eq!(matches.exit_reason, TextSearchExitReason::MatchLimit);
+}
+
+#[test]
+fn test_multiline_prefilter() {
+    let prefilter = |pattern: &str| multiline_prefilter(pattern);
+    assert_eq!(
+        prefilter(r"void\s+Foo::Bar\(\s*int aBaz"),
+        Some("Foo::Bar(".to_string())
+    );
+    assert_eq!(
+        prefilter(r"(?i)NS_IMETHODIMP\nFoo"),
+        Some("NS_IMETHODIMP".to_string())
+    );
+    assert_eq!(prefilter("Initial?ize[dr]*"), Some("Initia".to_string()));
+    assert_eq!(prefilter("(foo|bar)\\s*baz"), Some("baz".to_string()));
+    assert_eq!(prefilter("(foo|bar)\\s*b"), None);
+    assert_eq!(prefilter("Foo|Bar"), None);
+}
+
+#[test]
+fn test_multiline_matches_in_file() {
+    let re = RegexBuilder::new(r"Foo\(\s*int")
+        .multi_line(true)
+        .build()
+        .unwrap();
+    let matches = multiline_matches_in_file(&re, "// Foo(int)\nvoid\nFoo(\n  int aBar)\n");
+    assert_eq!(
+        matches
+            .iter()
+            .map(|m| (
+                m.line_num,
+                m.bounds.start,
+                m.bounds.end_exclusive,
+                m.line_str.as_str()
+            ))
+            .collect::<Vec<_>>(),
+        vec![(1, 3, 10, "// Foo(int)"), (3, 0, 4, "Foo(")]
+    );
+}
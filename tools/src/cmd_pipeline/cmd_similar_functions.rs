@@ -0,0 +1,246 @@
+use std::collections::HashSet;
+
+use async_trait::async_trait;
+use clap::Args;
+use serde::Serialize;
+use serde_json::to_value;
+use ustr::{ustr, Ustr};
+
+use super::{
+    interface::{JsonValue, JsonValueList, PipelineCommand, PipelineValues},
+    progress::ProgressTracker,
+};
+
+use crate::{
+    abstract_server::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError},
+    file_format::crossref_record::CrossrefRecord,
+};
+
+/// Experimental "search by example": given a function, rank the other
+/// functions in the same module scope by the Jaccard similarity of their callee
+/// sets in order to help find duplicate or near-duplicate implementations.
+///
+/// Candidates are found by looking at the callers of the function's callees,
+/// so functions that share no callees with it are never considered.  Callees
+/// with more than `--max-callee-uses` uses (ex: refcounting helpers) are still
+/// part of the similarity computation but aren't used to find candidates.
+///
+/// Produces a `JsonValueList` of `{ sym, pretty, path, lno, similarity,
+/// sharedCallees, calleeCount }` ordered by descending similarity.
+#[derive(Debug, Args)]
+pub struct SimilarFunctions {
+    /// The function to find similar functions to.
+    #[clap(short, long, value_parser)]
+    symbol: String,
+
+    /// Path prefix candidates must be defined under.  Defaults to the directory
+    /// containing the definition of the function.  Pass an empty string to
+    /// consider the whole tree.
+    #[clap(long, value_parser)]
+    scope: Option<String>,
+
+    /// Minimum similarity (0 to 1) for a candidate to be reported.
+    #[clap(long, value_parser, default_value = "0.3")]
+    min_similarity: f64,
+
+    /// Callees used more than this many times are not used to find candidates.
+    #[clap(long, value_parser, default_value = "500")]
+    max_callee_uses: usize,
+
+    /// Maximum number of candidates whose callees we will look up.
+    #[clap(long, value_parser = clap::value_parser!(u32).range(16..=16384), default_value = "1024")]
+    candidate_limit: u32,
+
+    /// Maximum number of similar functions to report.
+    #[clap(short, long, value_parser, default_value = "20")]
+    limit: usize,
+}
+
+#[derive(Debug)]
+pub struct SimilarFunctionsCommand {
+    pub args: SimilarFunctions,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SimilarFunction {
+    sym: Ustr,
+    pretty: Ustr,
+    path: Ustr,
+    lno: u32,
+    similarity: f64,
+    shared_callees: usize,
+    callee_count: usize,
+}
+
+fn callee_set(crossref: &CrossrefRecord) -> HashSet<Ustr> {
+    crossref.callees.iter().map(|callee| callee.sym).collect()
+}
+
+/// Returns the Jaccard similarity of the two sets and the size of their
+/// intersection.  Two empty sets are considered to have nothing in common.
+fn jaccard(a: &HashSet<Ustr>, b: &HashSet<Ustr>) -> (f64, usize) {
+    let shared = a.intersection(b).count();
+    let union = a.len() + b.len() - shared;
+    if union == 0 {
+        return (0.0, 0);
+    }
+    (shared as f64 / union as f64, shared)
+}
+
+fn in_scope(path: &str, scope: &str) -> bool {
+    scope.is_empty()
+        || path
+            .strip_prefix(scope)
+            .map_or(false, |rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// The first definition of the symbol, if it has one.
+fn def_location(crossref: &CrossrefRecord) -> Option<(Ustr, u32)> {
+    crossref.defs.iter().find_map(|path_hits| {
+        path_hits
+            .lines
+            .first()
+            .map(|hit| (path_hits.path, hit.lineno))
+    })
+}
+
+#[async_trait]
+impl PipelineCommand for SimilarFunctionsCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        _input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        let info = server.crossref_lookup(&self.args.symbol, false).await?;
+        if info.is_null() {
+            return Err(ServerError::StickyProblem(ErrorDetails {
+                layer: ErrorLayer::BadInput,
+                message: format!("similar-functions: unknown symbol {}", self.args.symbol),
+            }));
+        }
+        let target_sym = ustr(&self.args.symbol);
+        let target = CrossrefRecord::from_value(info)?;
+        let target_callees = callee_set(&target);
+
+        let scope = match (&self.args.scope, def_location(&target)) {
+            (Some(scope), _) => scope.trim_end_matches('/').to_string(),
+            (None, Some((path, _))) => match path.rfind('/') {
+                Some(offset) => path[0..offset].to_string(),
+                None => "".to_string(),
+            },
+            (None, None) => {
+                return Err(ServerError::StickyProblem(ErrorDetails {
+                    layer: ErrorLayer::BadInput,
+                    message: format!(
+                        "similar-functions: {} has no definition, so please specify --scope",
+                        self.args.symbol
+                    ),
+                }));
+            }
+        };
+
+        // Find the in-scope callers of our callees, keeping them in the order
+        // we first see them so that the candidate limit is deterministic.
+        let mut seen = HashSet::new();
+        seen.insert(target_sym);
+        let mut candidates = vec![];
+        let mut progress =
+            ProgressTracker::new("similar-functions", Some(target_callees.len() as u64));
+        let mut sorted_callees: Vec<&Ustr> = target_callees.iter().collect();
+        sorted_callees.sort();
+        for callee in sorted_callees {
+            progress.advance(1);
+            let info = server.crossref_lookup(callee, false).await?;
+            if info.is_null() {
+                continue;
+            }
+            let callee_crossref = CrossrefRecord::from_value(info)?;
+            let use_count: usize = callee_crossref
+                .uses
+                .iter()
+                .map(|path_hits| path_hits.lines.len())
+                .sum();
+            if use_count > self.args.max_callee_uses {
+                continue;
+            }
+            for path_hits in &callee_crossref.uses {
+                if !in_scope(&path_hits.path, &scope) {
+                    continue;
+                }
+                for hit in &path_hits.lines {
+                    if !hit.contextsym.is_empty() && seen.insert(hit.contextsym) {
+                        candidates.push(hit.contextsym);
+                    }
+                }
+            }
+        }
+        progress.finish();
+
+        let limit = self.args.candidate_limit as usize;
+        let mut progress = ProgressTracker::new(
+            "similar-functions",
+            Some(std::cmp::min(candidates.len(), limit) as u64),
+        );
+        let mut similar = vec![];
+        for candidate in candidates.into_iter().take(limit) {
+            progress.advance(1);
+            let info = server.crossref_lookup(&candidate, false).await?;
+            if info.is_null() {
+                continue;
+            }
+            let crossref = CrossrefRecord::from_value(info)?;
+            // The caller's use may be in scope even though it's defined
+            // elsewhere (ex: inline functions in headers).
+            let (path, lno) = match def_location(&crossref) {
+                Some((path, lno)) if in_scope(&path, &scope) => (path, lno),
+                _ => continue,
+            };
+            let callees = callee_set(&crossref);
+            let (similarity, shared_callees) = jaccard(&target_callees, &callees);
+            if similarity < self.args.min_similarity {
+                continue;
+            }
+            let pretty = crossref.meta.as_ref().map_or(candidate, |meta| meta.pretty);
+            similar.push(SimilarFunction {
+                sym: candidate,
+                pretty,
+                path,
+                lno,
+                similarity,
+                shared_callees,
+                callee_count: callees.len(),
+            });
+        }
+        progress.finish();
+
+        similar.sort_by(|a, b| {
+            b.similarity
+                .partial_cmp(&a.similarity)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.sym.cmp(&b.sym))
+        });
+        similar.truncate(self.args.limit);
+
+        let mut values = vec![];
+        for result in similar {
+            values.push(JsonValue {
+                value: to_value(result)?,
+            });
+        }
+        Ok(PipelineValues::JsonValueList(JsonValueList { values }))
+    }
+}
+
+#[test]
+fn test_jaccard() {
+    let set = |syms: &[&str]| -> HashSet<Ustr> { syms.iter().map(|s| ustr(s)).collect() };
+    let (similarity, shared) = jaccard(&set(&["a", "b", "c"]), &set(&["b", "c", "d"]));
+    assert_eq!(shared, 2);
+    assert!((similarity - 0.5).abs() < f64::EPSILON);
+    assert_eq!(jaccard(&set(&[]), &set(&[])), (0.0, 0));
+
+    assert!(in_scope("dom/base/Foo.cpp", "dom/base"));
+    assert!(in_scope("dom/base/Foo.cpp", ""));
+    assert!(!in_scope("dom/baseline/Foo.cpp", "dom/base"));
+}
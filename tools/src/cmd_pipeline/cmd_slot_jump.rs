@@ -0,0 +1,194 @@
+use async_trait::async_trait;
+use clap::Args;
+use serde_json::{json, to_value, Value};
+
+use super::interface::{JsonValue, JsonValueList, PipelineCommand, PipelineValues};
+
+use crate::{
+    abstract_server::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError},
+    file_format::{
+        analysis::{BindingSlotKind, BindingSlotLang},
+        crossref_record::CrossrefRecord,
+    },
+};
+
+/// For symbols participating in binding slot relationships (ex: an XPIDL or
+/// WebIDL method, its C++ implementation, and its JS reflection), list all of
+/// the slot-linked symbols with their languages and definition locations.  This
+/// is the backbone of cross-language go-to.
+///
+/// Each input symbol produces a JsonValue of the form:
+/// `{ sym, pretty, owner: { sym, pretty, ownerLang, def }, slots: [...] }`
+/// where each slot is `{ sym, pretty, slotKind, slotLang, implKind, ownerLang,
+/// def }` and `def` is a "path#lno" string or null if there's no definition.
+/// Like the jumpref logic, C++ method slots (ex: an XPIDL pure virtual) also
+/// list their `overriddenBy` implementations so we can get to the actual impl.
+///
+/// Symbols without any binding slot relationships produce a null `owner` and
+/// an empty `slots`.
+#[derive(Debug, Args)]
+pub struct SlotJump {
+    /// Explicit symbols to lookup.
+    #[clap(value_parser)]
+    symbols: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct SlotJumpCommand {
+    pub args: SlotJump,
+}
+
+async fn lookup_record(
+    server: &(dyn AbstractServer + Send + Sync),
+    symbol: &str,
+) -> Result<Option<CrossrefRecord>> {
+    let info = server.crossref_lookup(symbol, false).await?;
+    if info.is_null() {
+        return Ok(None);
+    }
+    Ok(Some(CrossrefRecord::from_value(info)?))
+}
+
+/// IDL symbols like XPIDL methods don't have `defs`, just their `idl` hits,
+/// which are effectively their definitions.
+fn def_location(record: &CrossrefRecord) -> Value {
+    match record.defs.first().or_else(|| record.idl.first()) {
+        Some(path_hits) => match path_hits.lines.first() {
+            Some(line) => json!(format!("{}#{}", path_hits.path, line.lineno)),
+            None => json!(path_hits.path),
+        },
+        None => Value::Null,
+    }
+}
+
+fn pretty_value(record: Option<&CrossrefRecord>) -> Value {
+    match record.and_then(|r| r.pretty()) {
+        Some(pretty) => json!(pretty),
+        None => Value::Null,
+    }
+}
+
+#[async_trait]
+impl PipelineCommand for SlotJumpCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        let to_process: Vec<(String, Option<CrossrefRecord>)> = match input {
+            PipelineValues::SymbolList(sl) => sl
+                .symbols
+                .into_iter()
+                .map(|info| (info.symbol.to_string(), None))
+                .collect(),
+            PipelineValues::SymbolCrossrefInfoList(scil) => scil
+                .symbol_crossref_infos
+                .into_iter()
+                .map(|info| (info.symbol.to_string(), Some(info.crossref_info)))
+                .collect(),
+            PipelineValues::Void => self
+                .args
+                .symbols
+                .iter()
+                .map(|sym| (sym.clone(), None))
+                .collect(),
+            _ => {
+                return Err(ServerError::StickyProblem(ErrorDetails {
+                    layer: ErrorLayer::ConfigLayer,
+                    message: "slot-jump needs a Void, SymbolList, or SymbolCrossrefInfoList"
+                        .to_string(),
+                }));
+            }
+        };
+
+        let mut values = vec![];
+        for (symbol, record) in to_process {
+            let record = match record {
+                Some(record) => Some(record),
+                None => lookup_record(server, &symbol).await?,
+            };
+            let meta = record.as_ref().and_then(|r| r.meta.as_ref());
+
+            // Figure out who owns the binding slots.  If we're a slot, that's
+            // our slotOwner, otherwise it's us if we have any binding slots.
+            let owner_info = match meta {
+                Some(meta) => match &meta.slot_owner {
+                    Some(slot_owner) => Some((
+                        slot_owner.sym.to_string(),
+                        Some(slot_owner.props.owner_lang),
+                        None,
+                    )),
+                    None if !meta.binding_slots.is_empty() => Some((
+                        symbol.clone(),
+                        meta.binding_slots.first().map(|s| s.props.owner_lang),
+                        record.as_ref(),
+                    )),
+                    None => None,
+                },
+                None => None,
+            };
+
+            let (owner_json, slots_json) = match owner_info {
+                Some((owner_sym, owner_lang, owner_record)) => {
+                    let looked_up_owner;
+                    let owner_record = match owner_record {
+                        Some(r) => Some(r),
+                        None => {
+                            looked_up_owner = lookup_record(server, &owner_sym).await?;
+                            looked_up_owner.as_ref()
+                        }
+                    };
+                    let owner_json = json!({
+                        "sym": owner_sym,
+                        "pretty": pretty_value(owner_record),
+                        "ownerLang": owner_lang,
+                        "def": owner_record.map_or(Value::Null, def_location),
+                    });
+
+                    let mut slots_json = vec![];
+                    let slots = owner_record
+                        .and_then(|r| r.meta.as_ref())
+                        .map(|meta| &meta.binding_slots[..])
+                        .unwrap_or(&[]);
+                    for slot in slots {
+                        let slot_record = lookup_record(server, &slot.sym).await?;
+                        let mut slot_json = to_value(slot)?;
+                        slot_json["pretty"] = pretty_value(slot_record.as_ref());
+                        slot_json["def"] = slot_record.as_ref().map_or(Value::Null, def_location);
+
+                        if let (BindingSlotKind::Method, BindingSlotLang::Cpp, Some(slot_record)) =
+                            (slot.props.slot_kind, slot.props.slot_lang, &slot_record)
+                        {
+                            let mut impls = vec![];
+                            for impl_sym in slot_record.overridden_by_syms() {
+                                let impl_record = lookup_record(server, &impl_sym).await?;
+                                impls.push(json!({
+                                    "sym": impl_sym,
+                                    "pretty": pretty_value(impl_record.as_ref()),
+                                    "def": impl_record.as_ref().map_or(Value::Null, def_location),
+                                }));
+                            }
+                            if !impls.is_empty() {
+                                slot_json["overriddenBy"] = json!(impls);
+                            }
+                        }
+                        slots_json.push(slot_json);
+                    }
+                    (owner_json, slots_json)
+                }
+                None => (Value::Null, vec![]),
+            };
+
+            values.push(JsonValue {
+                value: json!({
+                    "sym": symbol,
+                    "pretty": pretty_value(record.as_ref()),
+                    "owner": owner_json,
+                    "slots": slots_json,
+                }),
+            });
+        }
+
+        Ok(PipelineValues::JsonValueList(JsonValueList { values }))
+    }
+}
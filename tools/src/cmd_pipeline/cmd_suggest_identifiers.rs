@@ -0,0 +1,164 @@
+use std::collections::{HashMap, HashSet};
+
+use async_trait::async_trait;
+use clap::Args;
+use ustr::Ustr;
+
+use super::{
+    interface::{IdentifierSuggestion, PipelineCommand, PipelineValues},
+    warnings::add_pipeline_warning,
+};
+use crate::abstract_server::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError};
+
+/// If the piped-in `FlattenedResultsBundle` is empty, populate its
+/// `suggestions` with "did you mean" identifiers that are close to the given
+/// identifiers by edit distance, preferring more popular identifiers (those
+/// which correspond to more symbols) when distances are tied.  Non-empty
+/// bundles are passed through untouched, so this is cheap to leave in the
+/// default query pipeline.
+///
+/// Candidates are found with a single pass over the identifiers index for
+/// identifiers sharing a case-insensitive prefix of the identifier, down to a
+/// minimum prefix length, so typos in the first couple of characters won't
+/// produce suggestions.  A pipeline warning is reported if the candidates were
+/// limited by `--prefix-match-limit`.
+#[derive(Debug, Args)]
+pub struct SuggestIdentifiers {
+    /// Identifiers the user searched for.
+    #[clap(value_parser)]
+    identifiers: Vec<String>,
+
+    /// Maximum edit distance for a suggestion.
+    #[clap(long, value_parser, default_value = "2")]
+    max_distance: usize,
+
+    /// Maximum number of suggestions to return.
+    #[clap(long, value_parser, default_value = "5")]
+    limit: usize,
+
+    /// Maximum number of identifiers to consider for each length of prefix
+    /// shared with the identifier.
+    #[clap(long, value_parser, default_value = "2000")]
+    prefix_match_limit: usize,
+}
+
+#[derive(Debug)]
+pub struct SuggestIdentifiersCommand {
+    pub args: SuggestIdentifiers,
+}
+
+/// The shortest prefix we'll use to look for candidates; anything shorter than
+/// this is too expensive and too likely to hit the `prefix_match_limit`.
+const MIN_PREFIX_LEN: usize = 2;
+
+/// Case-insensitive Levenshtein distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().flat_map(|c| c.to_lowercase()).collect();
+    let b: Vec<char> = b.chars().flat_map(|c| c.to_lowercase()).collect();
+
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut cur = vec![0; b.len() + 1];
+    for (i, ca) in a.iter().enumerate() {
+        cur[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let subst = prev[j] + if ca == cb { 0 } else { 1 };
+            cur[j + 1] = subst.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    prev[b.len()]
+}
+
+#[async_trait]
+impl PipelineCommand for SuggestIdentifiersCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        let mut bundle = match input {
+            PipelineValues::FlattenedResultsBundle(frb) => frb,
+            _ => {
+                return Err(ServerError::StickyProblem(ErrorDetails {
+                    layer: ErrorLayer::ConfigLayer,
+                    message: "suggest-identifiers needs a FlattenedResultsBundle".to_string(),
+                }));
+            }
+        };
+
+        if !bundle.path_kind_results.is_empty() {
+            return Ok(PipelineValues::FlattenedResultsBundle(bundle));
+        }
+
+        // identifier => (distance, symbols)
+        let mut candidates: HashMap<Ustr, (usize, HashSet<Ustr>)> = HashMap::new();
+        for id in &self.args.identifiers {
+            let char_len = id.chars().count();
+            if char_len < MIN_PREFIX_LEN {
+                continue;
+            }
+            // A typo at character N can only be found by a prefix shorter than
+            // N, so consider identifiers sharing any prefix down to `min_len`,
+            // but bound how far we go since shorter prefixes match
+            // increasingly many identifiers that can't be close enough.
+            let min_len = MIN_PREFIX_LEN.max(char_len.saturating_sub(self.args.max_distance * 4));
+            let (matches, truncated) = server
+                .identifiers_starting_with(id, min_len, self.args.prefix_match_limit)
+                .await?;
+            if truncated {
+                add_pipeline_warning(
+                    "suggest-identifiers",
+                    format!(
+                        "too many identifiers share a prefix with {}; suggestions may be incomplete",
+                        id
+                    ),
+                );
+            }
+            for (from_ident, syms) in matches {
+                let ident_len = from_ident.chars().count();
+                // Cheap length check before the real distance computation.
+                if ident_len.abs_diff(char_len) > self.args.max_distance {
+                    continue;
+                }
+                if !candidates.contains_key(&from_ident) {
+                    let distance = edit_distance(id, &from_ident);
+                    if distance == 0 || distance > self.args.max_distance {
+                        continue;
+                    }
+                    candidates.insert(from_ident, (distance, HashSet::new()));
+                }
+                if let Some((_distance, candidate_syms)) = candidates.get_mut(&from_ident) {
+                    candidate_syms.extend(syms);
+                }
+            }
+        }
+
+        let mut suggestions: Vec<IdentifierSuggestion> = candidates
+            .into_iter()
+            .map(|(identifier, (distance, syms))| IdentifierSuggestion {
+                identifier,
+                distance: distance as u32,
+                symbol_count: syms.len() as u32,
+            })
+            .collect();
+        suggestions.sort_by(|a, b| {
+            a.distance
+                .cmp(&b.distance)
+                .then_with(|| b.symbol_count.cmp(&a.symbol_count))
+                .then_with(|| a.identifier.as_str().cmp(b.identifier.as_str()))
+        });
+        suggestions.truncate(self.args.limit);
+        bundle.suggestions = suggestions;
+
+        Ok(PipelineValues::FlattenedResultsBundle(bundle))
+    }
+}
+
+#[test]
+fn test_edit_distance() {
+    assert_eq!(edit_distance("document", "document"), 0);
+    assert_eq!(edit_distance("document", "Document"), 0);
+    assert_eq!(edit_distance("documnet", "document"), 2);
+    assert_eq!(edit_distance("docment", "document"), 1);
+    assert_eq!(edit_distance("", "abc"), 3);
+}
@@ -0,0 +1,300 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use clap::Args;
+use serde::Serialize;
+use serde_json::{to_value, Map, Value};
+use ustr::Ustr;
+
+use super::interface::{JsonValue, PipelineCommand, PipelineValues};
+
+use crate::{
+    abstract_server::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError},
+    file_format::{
+        crossref_converter::convert_crossref_value_to_sym_info_rep,
+        crossref_record::{CrossrefRecord, CrossrefSymbolRef},
+    },
+};
+
+/// Precompute the symbol popup payloads for a `SymbolCrossrefInfoList` (ex:
+/// from `crossref-lookup`) so they can be generated at index time rather than
+/// per request.  This should be the last command in a pipeline.
+///
+/// Produces a `JsonValue` object keyed by symbol, like the `SYM_INFO` the web
+/// UI's context menu consumes, where each value is the usual `SYM_INFO` rep
+/// (`sym`, `pretty`, `jumps`, `meta`) plus:
+/// - `graph`: a one-hop `{ nodes, edges }` snippet of the symbol's top callers
+///   and its callees, where edges are `{ from, to }` symbol pairs.
+/// - `topCallers`: `{ sym, pretty, callSites, jump }` for the callers with the
+///   most call sites, where `jump` is the first call site.
+/// - `doc`: the comment block immediately preceding the symbol's definition,
+///   if it has a single definition and there is such a comment.
+#[derive(Debug, Args)]
+pub struct SymbolPayloads {
+    /// How many callers to include in `topCallers` (and the graph).
+    #[clap(long, value_parser, default_value = "5")]
+    top_callers: usize,
+
+    /// How many callees to include in the graph.
+    #[clap(long, value_parser, default_value = "8")]
+    callees: usize,
+
+    /// Maximum number of lines of the doc comment to include.
+    #[clap(long, value_parser, default_value = "8")]
+    doc_lines: usize,
+}
+
+#[derive(Debug)]
+pub struct SymbolPayloadsCommand {
+    pub args: SymbolPayloads,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TopCaller {
+    sym: Ustr,
+    pretty: Ustr,
+    call_sites: u32,
+    jump: String,
+}
+
+#[derive(Serialize)]
+struct GraphNode {
+    sym: Ustr,
+    pretty: Ustr,
+}
+
+#[derive(Serialize)]
+struct GraphEdge {
+    from: Ustr,
+    to: Ustr,
+}
+
+#[derive(Serialize)]
+struct GraphSnippet {
+    nodes: Vec<GraphNode>,
+    edges: Vec<GraphEdge>,
+}
+
+/// Find the callers of the symbol with the most call sites, ties broken by
+/// symbol so the payloads are stable across index runs.
+fn top_callers(crossref_info: &CrossrefRecord, limit: usize) -> Vec<TopCaller> {
+    let mut by_caller: BTreeMap<Ustr, TopCaller> = BTreeMap::new();
+    for path_hits in &crossref_info.uses {
+        for hit in &path_hits.lines {
+            if hit.contextsym.is_empty() {
+                continue;
+            }
+            by_caller
+                .entry(hit.contextsym)
+                .or_insert_with(|| TopCaller {
+                    sym: hit.contextsym,
+                    pretty: hit.context,
+                    call_sites: 0,
+                    jump: format!("{}#{}", path_hits.path, hit.lineno),
+                })
+                .call_sites += 1;
+        }
+    }
+    let mut callers: Vec<TopCaller> = by_caller.into_values().collect();
+    callers.sort_by(|a, b| b.call_sites.cmp(&a.call_sites));
+    callers.truncate(limit);
+    callers
+}
+
+fn graph_snippet(
+    sym: Ustr,
+    pretty: Ustr,
+    callers: &[TopCaller],
+    callees: &[CrossrefSymbolRef],
+) -> GraphSnippet {
+    let mut nodes = vec![GraphNode { sym, pretty }];
+    let mut edges = vec![];
+    for caller in callers {
+        nodes.push(GraphNode {
+            sym: caller.sym,
+            pretty: caller.pretty,
+        });
+        edges.push(GraphEdge {
+            from: caller.sym,
+            to: sym,
+        });
+    }
+    for callee in callees {
+        // Recursive calls are already covered by the symbol's own node.
+        if callee.sym != sym {
+            nodes.push(GraphNode {
+                sym: callee.sym,
+                pretty: callee.pretty.unwrap_or(callee.sym),
+            });
+        }
+        edges.push(GraphEdge {
+            from: sym,
+            to: callee.sym,
+        });
+    }
+    GraphSnippet { nodes, edges }
+}
+
+/// Extract the comment block ending immediately above the 1-based `lno`,
+/// skipping over any attribute/annotation/decorator lines between the two, and
+/// with comment markers stripped.
+fn doc_excerpt(source: &str, lno: u32, max_lines: usize) -> Option<String> {
+    let lines: Vec<&str> = source.lines().collect();
+    let def_idx = (lno as usize).checked_sub(1)?;
+    if def_idx > lines.len() {
+        return None;
+    }
+
+    let mut doc = vec![];
+    let mut in_block = false;
+    for line in lines[..def_idx].iter().rev() {
+        let line = line.trim();
+        if in_block {
+            let start = line.find("/*");
+            let text = match start {
+                Some(start) => &line[start + 2..],
+                None => line,
+            };
+            doc.push(text.trim_start_matches('*').trim());
+            if start.is_some() {
+                in_block = false;
+            }
+            continue;
+        }
+        if line.ends_with("*/") {
+            let text = line.trim_end_matches("*/");
+            match text.find("/*") {
+                Some(start) => doc.push(text[start + 2..].trim_start_matches('*').trim()),
+                None => {
+                    doc.push(text.trim_start_matches('*').trim());
+                    in_block = true;
+                }
+            }
+        } else if line.starts_with("//") || line == "#" || line.starts_with("# ") {
+            doc.push(line.trim_start_matches(&['/', '!', '#'][..]).trim());
+        } else if doc.is_empty()
+            && (line.starts_with("#[") || line.starts_with("[[") || line.starts_with('@'))
+        {
+            continue;
+        } else {
+            break;
+        }
+    }
+    doc.reverse();
+
+    // Drop blank lines left behind by block comment delimiters.
+    while doc.first() == Some(&"") {
+        doc.remove(0);
+    }
+    while doc.last() == Some(&"") {
+        doc.pop();
+    }
+    if doc.is_empty() {
+        return None;
+    }
+    doc.truncate(max_lines);
+    Some(doc.join("\n"))
+}
+
+#[async_trait]
+impl PipelineCommand for SymbolPayloadsCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        let cil = match input {
+            PipelineValues::SymbolCrossrefInfoList(cil) => cil,
+            _ => {
+                return Err(ServerError::StickyProblem(ErrorDetails {
+                    layer: ErrorLayer::ConfigLayer,
+                    message: "symbol-payloads needs a SymbolCrossrefInfoList".to_string(),
+                }));
+            }
+        };
+
+        let mut payloads = Map::new();
+        for info in cil.symbol_crossref_infos {
+            let sym = info.symbol;
+            let pretty = info.get_pretty();
+            let callers = top_callers(&info.crossref_info, self.args.top_callers);
+            let callees: Vec<CrossrefSymbolRef> = info
+                .crossref_info
+                .callees
+                .iter()
+                .take(self.args.callees)
+                .cloned()
+                .collect();
+
+            // Only a lone definition has an unambiguous doc comment, which is
+            // the same rule the SYM_INFO "def" jump uses.
+            let doc = match info.crossref_info.defs.as_slice() {
+                [path_hits] if path_hits.lines.len() == 1 => {
+                    match server.fetch_raw_source(&path_hits.path).await {
+                        Ok(source) => {
+                            doc_excerpt(&source, path_hits.lines[0].lineno, self.args.doc_lines)
+                        }
+                        // Generated files may not be available, which shouldn't
+                        // prevent producing the rest of the payload.
+                        Err(_) => None,
+                    }
+                }
+                _ => None,
+            };
+
+            let graph = to_value(graph_snippet(sym, pretty, &callers, &callees))?;
+            let mut rep = convert_crossref_value_to_sym_info_rep(
+                info.crossref_info.into_value(),
+                &sym,
+                Some(&pretty),
+            );
+            if let Value::Object(rep) = &mut rep {
+                rep.insert("graph".to_string(), graph);
+                rep.insert("topCallers".to_string(), to_value(callers)?);
+                if let Some(doc) = doc {
+                    rep.insert("doc".to_string(), Value::String(doc));
+                }
+            }
+            payloads.insert(sym.to_string(), rep);
+        }
+
+        Ok(PipelineValues::JsonValue(JsonValue {
+            value: Value::Object(payloads),
+        }))
+    }
+}
+
+#[test]
+fn test_doc_excerpt() {
+    let source = "\
+int x;
+
+/**
+ * Frobs the widget.
+ *
+ * Returns true on success.
+ */
+[[nodiscard]]
+bool Frob();
+// Line comment one.
+// Line comment two.
+void Twiddle();
+int y;
+void Undocumented();
+";
+    assert_eq!(
+        doc_excerpt(source, 9, 8).as_deref(),
+        Some("Frobs the widget.\n\nReturns true on success.")
+    );
+    assert_eq!(
+        doc_excerpt(source, 9, 1).as_deref(),
+        Some("Frobs the widget.")
+    );
+    assert_eq!(
+        doc_excerpt(source, 12, 8).as_deref(),
+        Some("Line comment one.\nLine comment two.")
+    );
+    assert_eq!(doc_excerpt(source, 14, 8), None);
+    assert_eq!(doc_excerpt(source, 1, 8), None);
+}
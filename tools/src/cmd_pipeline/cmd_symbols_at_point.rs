@@ -0,0 +1,177 @@
+use std::cmp::Reverse;
+
+use async_trait::async_trait;
+use clap::Args;
+use serde::Serialize;
+use serde_json::{from_value, to_value, Value};
+use ustr::Ustr;
+
+use super::interface::{JsonValue, JsonValueList, PipelineCommand, PipelineValues};
+
+use crate::{
+    abstract_server::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError},
+    file_format::analysis::{AnalysisSource, Location, SourceRange, WithLocation},
+};
+
+/// Report the stack of symbols at a position in a file: the symbols of the
+/// token under the position followed by the definitions whose
+/// `nestingRange` encloses it, innermost first.  This is the building block
+/// for hover and context menus in editor integrations.
+///
+/// Produces a `JsonValueList` of `{ sym, pretty, syntax, loc, nestingRange,
+/// enclosing }` where `enclosing` is false for the token under the position.
+#[derive(Debug, Args)]
+pub struct SymbolsAtPoint {
+    /// Tree-relative path of the file.
+    #[clap(long, value_parser)]
+    path: String,
+
+    /// 1-based line number.
+    #[clap(long, value_parser)]
+    line: u32,
+
+    /// 1-based column number, like editors and `render-quickfix` use.
+    #[clap(long, value_parser, default_value = "1")]
+    col: u32,
+}
+
+#[derive(Debug)]
+pub struct SymbolsAtPointCommand {
+    pub args: SymbolsAtPoint,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PointSymbol {
+    sym: Vec<Ustr>,
+    pretty: Ustr,
+    syntax: Vec<Ustr>,
+    loc: Location,
+    #[serde(skip_serializing_if = "SourceRange::is_empty")]
+    nesting_range: SourceRange,
+    enclosing: bool,
+}
+
+/// Build the symbol stack from the source records overlapping the point,
+/// where `col` is 0-based like the analysis columns.
+fn symbol_stack(records: Vec<Value>, lineno: u32, col: u32) -> Vec<PointSymbol> {
+    let point = SourceRange {
+        start_lineno: lineno,
+        start_col: col,
+        end_lineno: lineno,
+        end_col: col + 1,
+    };
+
+    let mut tokens = vec![];
+    let mut enclosing = vec![];
+    for record in records {
+        if record.get("source").is_none() {
+            continue;
+        }
+        let source: WithLocation<AnalysisSource<Ustr>> = match from_value(record) {
+            Ok(source) => source,
+            Err(_) => continue,
+        };
+        let is_token = SourceRange::from_location(&source.loc).overlaps(&point);
+        if !is_token && !source.data.nesting_range.overlaps(&point) {
+            continue;
+        }
+        let symbol = PointSymbol {
+            sym: source.data.sym,
+            pretty: source.data.pretty,
+            syntax: source.data.syntax,
+            loc: source.loc,
+            nesting_range: source.data.nesting_range,
+            enclosing: !is_token,
+        };
+        if is_token {
+            tokens.push(symbol);
+        } else {
+            enclosing.push(symbol);
+        }
+    }
+
+    // Nesting ranges are properly nested, so the latest starting range is the
+    // innermost, with the earliest ending range breaking ties.
+    enclosing.sort_by_key(|symbol| {
+        let range = &symbol.nesting_range;
+        (
+            Reverse((range.start_lineno, range.start_col)),
+            (range.end_lineno, range.end_col),
+        )
+    });
+    tokens.extend(enclosing);
+    tokens
+}
+
+#[async_trait]
+impl PipelineCommand for SymbolsAtPointCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        if !matches!(input, PipelineValues::Void) {
+            return Err(ServerError::StickyProblem(ErrorDetails {
+                layer: ErrorLayer::ConfigLayer,
+                message: "symbols-at-point needs Void".to_string(),
+            }));
+        }
+        if self.args.line == 0 || self.args.col == 0 {
+            return Err(ServerError::StickyProblem(ErrorDetails {
+                layer: ErrorLayer::BadInput,
+                message: "symbols-at-point lines and columns are 1-based".to_string(),
+            }));
+        }
+
+        let lineno = self.args.line;
+        let col = self.args.col - 1;
+        let range = SourceRange {
+            start_lineno: lineno,
+            start_col: col,
+            end_lineno: lineno,
+            end_col: col + 1,
+        };
+        let records = server
+            .fetch_analysis_in_range(&self.args.path, range)
+            .await?;
+
+        let mut values = vec![];
+        for symbol in symbol_stack(records, lineno, col) {
+            values.push(JsonValue {
+                value: to_value(symbol)?,
+            });
+        }
+        Ok(PipelineValues::JsonValueList(JsonValueList { values }))
+    }
+}
+
+#[test]
+fn test_symbol_stack() {
+    let records = vec![
+        serde_json::json!({"loc": "00001:6-8", "source": 1, "syntax": "def,class", "pretty": "class Foo", "sym": "T_Foo", "nestingRange": "1:10-20:0"}),
+        serde_json::json!({"loc": "00003:7-9", "source": 1, "syntax": "def,function", "pretty": "function Foo::Bar", "sym": "_ZN3Foo3BarEv", "nestingRange": "3:14-8:2"}),
+        serde_json::json!({"loc": "00005:4-6", "source": 1, "syntax": "use,function", "pretty": "function Baz", "sym": "_Z3Bazv"}),
+        serde_json::json!({"loc": "00005:4-6", "target": 1, "kind": "use", "pretty": "Baz", "sym": "_Z3Bazv"}),
+        serde_json::json!({"loc": "00012:4-6", "source": 1, "syntax": "def,function", "pretty": "function Foo::Qux", "sym": "_ZN3Foo3QuxEv", "nestingRange": "12:14-14:2"}),
+    ];
+
+    let stack = symbol_stack(records.clone(), 5, 5);
+    let pretties: Vec<(&str, bool)> = stack
+        .iter()
+        .map(|symbol| (symbol.pretty.as_str(), symbol.enclosing))
+        .collect();
+    assert_eq!(
+        pretties,
+        vec![
+            ("function Baz", false),
+            ("function Foo::Bar", true),
+            ("class Foo", true),
+        ]
+    );
+
+    // Between definitions, only the class encloses the point.
+    let stack = symbol_stack(records, 10, 0);
+    assert_eq!(stack.len(), 1);
+    assert_eq!(stack[0].pretty, "class Foo");
+}
@@ -0,0 +1,249 @@
+use std::collections::{HashMap, HashSet};
+
+use async_trait::async_trait;
+use clap::{Args, ValueEnum};
+use ustr::Ustr;
+
+use super::interface::{PipelineJunctionCommand, PipelineValues};
+
+use crate::abstract_server::{
+    AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError, TextMatches, TextMatchesByFile,
+};
+
+#[derive(Clone, Debug, PartialEq, ValueEnum)]
+pub enum TextDiffMode {
+    /// Hits of the first input that the second input doesn't have.
+    OnlyFirst,
+    /// Hits of the second input that the first input doesn't have.
+    OnlySecond,
+    /// Hits that only one of the inputs has.
+    Symmetric,
+}
+
+#[derive(Clone, Debug, PartialEq, ValueEnum)]
+pub enum TextDiffGranularity {
+    /// Hits are the same if they're on the same line of the same file.
+    Line,
+    /// Hits are the same if they're in the same file, so a file is only
+    /// reported if the other input has no hits in it at all.
+    File,
+}
+
+/// Junction that takes exactly two `TextMatches` inputs (ex: searches for two
+/// regex variants, or the same search on two trees) and produces the
+/// `TextMatches` hits present in one but not the other.  This supports
+/// migration audits like "files using the old API but not yet the new one"
+/// via `--mode only-first --granularity file`.
+///
+/// The inputs are considered in the order they're given to the junction.  If
+/// either input was truncated, the output is labeled as truncated too since
+/// hits that look exclusive may just be missing from the other side.
+#[derive(Debug, Args)]
+pub struct TextDiffHits {
+    #[clap(long, value_parser, value_enum, default_value = "only-first")]
+    mode: TextDiffMode,
+
+    #[clap(long, value_parser, value_enum, default_value = "line")]
+    granularity: TextDiffGranularity,
+}
+
+#[derive(Debug)]
+pub struct TextDiffHitsCommand {
+    pub args: TextDiffHits,
+}
+
+/// The identity of a hit for comparison purposes; the line is 0 for file
+/// granularity.
+fn hit_keys(tm: &TextMatches, granularity: &TextDiffGranularity) -> HashSet<(Ustr, u32)> {
+    let mut keys = HashSet::new();
+    for file_matches in &tm.by_file {
+        match granularity {
+            TextDiffGranularity::Line => keys.extend(
+                file_matches
+                    .matches
+                    .iter()
+                    .map(|m| (file_matches.file, m.line_num)),
+            ),
+            TextDiffGranularity::File => {
+                if !file_matches.matches.is_empty() {
+                    keys.insert((file_matches.file, 0));
+                }
+            }
+        }
+    }
+    keys
+}
+
+/// Compute the hits that are exclusive to one side per `mode`.  Files are
+/// ordered as they first appear in the first and then second input, with the
+/// lines of files that have exclusive hits on both sides re-sorted.
+fn diff_text_matches(
+    first: TextMatches,
+    second: TextMatches,
+    mode: &TextDiffMode,
+    granularity: &TextDiffGranularity,
+) -> Vec<TextMatchesByFile> {
+    let first_keys = hit_keys(&first, granularity);
+    let second_keys = hit_keys(&second, granularity);
+
+    let sides = match mode {
+        TextDiffMode::OnlyFirst => vec![(first, &second_keys)],
+        TextDiffMode::OnlySecond => vec![(second, &first_keys)],
+        TextDiffMode::Symmetric => vec![(first, &second_keys), (second, &first_keys)],
+    };
+
+    let mut by_file: Vec<TextMatchesByFile> = vec![];
+    let mut file_indices: HashMap<Ustr, usize> = HashMap::new();
+    let mut merged_files = HashSet::new();
+    for (tm, other_keys) in sides {
+        for mut file_matches in tm.by_file {
+            let file = file_matches.file;
+            file_matches.matches.retain(|m| {
+                let line = match granularity {
+                    TextDiffGranularity::Line => m.line_num,
+                    TextDiffGranularity::File => 0,
+                };
+                !other_keys.contains(&(file, line))
+            });
+            if file_matches.matches.is_empty() {
+                continue;
+            }
+            match file_indices.get(&file) {
+                Some(&index) => {
+                    by_file[index].matches.append(&mut file_matches.matches);
+                    merged_files.insert(index);
+                }
+                None => {
+                    file_indices.insert(file, by_file.len());
+                    by_file.push(file_matches);
+                }
+            }
+        }
+    }
+    for index in merged_files {
+        by_file[index].matches.sort_by_key(|m| m.line_num);
+    }
+    by_file
+}
+
+#[async_trait]
+impl PipelineJunctionCommand for TextDiffHitsCommand {
+    async fn execute(
+        &self,
+        _server: &(dyn AbstractServer + Send + Sync),
+        input: Vec<(String, PipelineValues)>,
+    ) -> Result<PipelineValues> {
+        let mut text_matches = vec![];
+        for (_name, pipe_value) in input {
+            match pipe_value {
+                PipelineValues::TextMatches(tm) => text_matches.push(tm),
+                _ => {
+                    return Err(ServerError::StickyProblem(ErrorDetails {
+                        layer: ErrorLayer::ConfigLayer,
+                        message: "text-diff-hits only accepts TextMatches".to_string(),
+                    }));
+                }
+            }
+        }
+        if text_matches.len() != 2 {
+            return Err(ServerError::StickyProblem(ErrorDetails {
+                layer: ErrorLayer::ConfigLayer,
+                message: format!(
+                    "text-diff-hits needs exactly 2 TextMatches inputs but got {}",
+                    text_matches.len()
+                ),
+            }));
+        }
+        let second = text_matches.pop().unwrap();
+        let first = text_matches.pop().unwrap();
+
+        let exit_reason = first.exit_reason.max(second.exit_reason);
+        Ok(PipelineValues::TextMatches(TextMatches {
+            by_file: diff_text_matches(first, second, &self.args.mode, &self.args.granularity),
+            exit_reason,
+        }))
+    }
+}
+
+#[test]
+fn test_diff_text_matches() {
+    use crate::abstract_server::{TextBounds, TextMatchInFile, TextSearchExitReason};
+    use ustr::ustr;
+
+    let text_matches = |hits: &[(&str, u32)]| {
+        let mut by_file: Vec<TextMatchesByFile> = vec![];
+        for (file, line_num) in hits {
+            let m = TextMatchInFile {
+                line_num: *line_num,
+                bounds: TextBounds {
+                    start: 0,
+                    end_exclusive: 1,
+                },
+                line_str: String::new(),
+                context_before: vec![],
+                context_after: vec![],
+            };
+            match by_file.iter_mut().find(|f| f.file.as_str() == *file) {
+                Some(f) => f.matches.push(m),
+                None => by_file.push(TextMatchesByFile {
+                    file: ustr(file),
+                    path_kind: ustr("Normal"),
+                    matches: vec![m],
+                }),
+            }
+        }
+        TextMatches {
+            by_file,
+            exit_reason: TextSearchExitReason::Complete,
+        }
+    };
+    let old_api = &[("a.cpp", 1), ("a.cpp", 5), ("b.cpp", 2), ("c.cpp", 3)];
+    let new_api = &[("a.cpp", 5), ("a.cpp", 7), ("c.cpp", 9)];
+    let flatten = |by_file: Vec<TextMatchesByFile>| -> Vec<(String, u32)> {
+        by_file
+            .iter()
+            .flat_map(|f| {
+                f.matches
+                    .iter()
+                    .map(move |m| (f.file.to_string(), m.line_num))
+            })
+            .collect()
+    };
+    let pairs = |hits: &[(&str, u32)]| -> Vec<(String, u32)> {
+        hits.iter().map(|(f, l)| (f.to_string(), *l)).collect()
+    };
+
+    assert_eq!(
+        flatten(diff_text_matches(
+            text_matches(old_api),
+            text_matches(new_api),
+            &TextDiffMode::OnlyFirst,
+            &TextDiffGranularity::Line,
+        )),
+        pairs(&[("a.cpp", 1), ("b.cpp", 2), ("c.cpp", 3)])
+    );
+    assert_eq!(
+        flatten(diff_text_matches(
+            text_matches(old_api),
+            text_matches(new_api),
+            &TextDiffMode::Symmetric,
+            &TextDiffGranularity::Line,
+        )),
+        pairs(&[
+            ("a.cpp", 1),
+            ("a.cpp", 7),
+            ("b.cpp", 2),
+            ("c.cpp", 3),
+            ("c.cpp", 9)
+        ])
+    );
+    assert_eq!(
+        flatten(diff_text_matches(
+            text_matches(old_api),
+            text_matches(new_api),
+            &TextDiffMode::OnlyFirst,
+            &TextDiffGranularity::File,
+        )),
+        pairs(&[("b.cpp", 2)])
+    );
+}
@@ -0,0 +1,200 @@
+use std::{cmp::Ordering, collections::BTreeSet};
+
+use async_trait::async_trait;
+use clap::Args;
+use serde::Serialize;
+use serde_json::{json, to_value};
+use tokio_stream::StreamExt;
+use ustr::Ustr;
+
+use super::{
+    cmd_module_api::is_in_module,
+    interface::{JsonValue, PipelineCommand, PipelineValues},
+    progress::ProgressTracker,
+    top_k::{RankOrd, TopK},
+};
+
+use crate::{
+    abstract_server::{AbstractServer, Result},
+    file_format::crossref_record::CrossrefRecord,
+};
+
+/// Scan the entire crossref database for the most referenced symbols, for
+/// identifying high fan-in APIs that need careful change management.
+///
+/// Produces a JsonValue of the form `{ path, kinds, externalOnly, symbols }`
+/// where `symbols` is the ranked table of `{ rank, sym, pretty, kind, uses,
+/// files, path, lno }` ordered by descending use count, which renders as a
+/// table via `render-markdown`.
+///
+/// This is expensive and requires a local index.
+#[derive(Debug, Args)]
+pub struct TopSymbols {
+    /// Only consider symbols defined in this directory (ex: "dom/base/").
+    /// Paths are matched on path segment boundaries.
+    #[clap(long, value_parser)]
+    path: Option<String>,
+
+    /// Only consider symbols of these structured kinds (ex: "class",
+    /// "method").  All kinds are considered if omitted.
+    #[clap(long, value_parser)]
+    kind: Vec<String>,
+
+    /// Only count uses from outside of `--path`, so that heavily used
+    /// internals of a directory don't crowd out its API.
+    #[clap(long, action)]
+    external_only: bool,
+
+    /// Number of symbols to list.
+    #[clap(short, long, value_parser = clap::value_parser!(u32).range(1..=10000), default_value = "100")]
+    limit: u32,
+}
+
+#[derive(Debug)]
+pub struct TopSymbolsCommand {
+    pub args: TopSymbols,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PopularSymbol {
+    rank: usize,
+    sym: String,
+    pretty: Ustr,
+    kind: Ustr,
+    /// Number of (counted) uses of the symbol.
+    uses: usize,
+    /// Number of distinct files the counted uses are in.
+    files: usize,
+    path: Ustr,
+    lno: u32,
+}
+
+impl RankOrd for PopularSymbol {
+    /// Ranking order: more uses first, then more files, then by location for
+    /// stability.
+    fn rank_cmp(&self, other: &Self) -> Ordering {
+        other
+            .uses
+            .cmp(&self.uses)
+            .then_with(|| other.files.cmp(&self.files))
+            .then_with(|| self.path.cmp(&other.path))
+            .then_with(|| self.lno.cmp(&other.lno))
+            .then_with(|| self.sym.cmp(&other.sym))
+    }
+}
+
+impl TopSymbolsCommand {
+    /// Evaluate a crossref record, returning the symbol with its use counts if
+    /// it passes the filters and has any counted uses.
+    fn evaluate(&self, sym: String, record: &CrossrefRecord) -> Option<PopularSymbol> {
+        let meta = record.meta.as_ref()?;
+        let def_path = record.defs.first()?;
+        let def_line = def_path.lines.first()?;
+
+        if let Some(dir) = &self.args.path {
+            if !is_in_module(&def_path.path, dir) {
+                return None;
+            }
+        }
+        if !self.args.kind.is_empty() && !self.args.kind.iter().any(|k| k == meta.kind.as_str()) {
+            return None;
+        }
+
+        let mut uses = 0;
+        let mut files = BTreeSet::new();
+        for path_container in &record.uses {
+            if self.args.external_only {
+                if let Some(dir) = &self.args.path {
+                    if is_in_module(&path_container.path, dir) {
+                        continue;
+                    }
+                }
+            }
+            uses += path_container.lines.len();
+            files.insert(path_container.path);
+        }
+        if uses == 0 {
+            return None;
+        }
+
+        Some(PopularSymbol {
+            rank: 0,
+            sym,
+            pretty: meta.pretty,
+            kind: meta.kind,
+            uses,
+            files: files.len(),
+            path: def_path.path,
+            lno: def_line.lineno,
+        })
+    }
+}
+
+#[async_trait]
+impl PipelineCommand for TopSymbolsCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        _input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        let mut top = TopK::new(self.args.limit as usize);
+        let mut records = server.crossref_scan().await?;
+        let mut progress = ProgressTracker::new("top-symbols", None);
+        while let Some(entry) = records.next().await {
+            let (sym, value) = entry?;
+            progress.advance(1);
+            if value.is_null() {
+                continue;
+            }
+            let record = CrossrefRecord::from_value(value)?;
+            if let Some(candidate) = self.evaluate(sym, &record) {
+                top.push(candidate);
+            }
+        }
+        progress.finish();
+
+        let mut symbols = top.into_sorted_vec();
+        for (i, symbol) in symbols.iter_mut().enumerate() {
+            symbol.rank = i + 1;
+        }
+
+        Ok(PipelineValues::JsonValue(JsonValue {
+            value: json!({
+                "path": self.args.path,
+                "kinds": self.args.kind,
+                "externalOnly": self.args.external_only,
+                "symbols": to_value(symbols)?,
+            }),
+        }))
+    }
+}
+
+#[test]
+fn test_ranked_keeps_top() {
+    let symbol = |sym: &str, uses: usize, files: usize| PopularSymbol {
+        rank: 0,
+        sym: sym.to_string(),
+        pretty: ustr::ustr(sym),
+        kind: ustr::ustr("function"),
+        uses,
+        files,
+        path: ustr::ustr("a.cpp"),
+        lno: 1,
+    };
+    let mut top = TopK::new(2);
+    for candidate in [
+        symbol("A", 5, 1),
+        symbol("B", 50, 3),
+        symbol("C", 5, 4),
+        symbol("D", 1, 1),
+    ] {
+        top.push(candidate);
+    }
+    let ranked: Vec<String> = top
+        .into_sorted_vec()
+        .into_iter()
+        .map(|symbol| symbol.sym)
+        .collect();
+    assert_eq!(ranked, vec!["B", "C"]);
+}
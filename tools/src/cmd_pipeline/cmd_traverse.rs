@@ -3,16 +3,21 @@ use std::collections::{HashSet, VecDeque};
 use async_trait::async_trait;
 use bitflags::bitflags;
 use clap::Args;
-use serde_json::{from_value, Value};
+use serde_json::{from_value, json, to_value, Value};
 use tracing::trace;
 use ustr::{ustr, Ustr};
 
 use super::{
-    interface::{OverloadInfo, OverloadKind, PipelineCommand, PipelineValues, SymbolMetaFlags},
+    interface::{
+        JsonValue, JsonValueList, OverloadInfo, OverloadKind, PipelineCommand, PipelineValues,
+        StageEstimate, SymbolMetaFlags,
+    },
+    progress::ProgressTracker,
     symbol_graph::{
         DerivedSymbolInfo, NamedSymbolGraph, SymbolBadge, SymbolGraphCollection,
-        SymbolGraphEdgeSet, SymbolGraphNodeSet,
+        SymbolGraphEdgeSet, SymbolGraphNodeId, SymbolGraphNodeSet,
     },
+    warnings::record_overload,
 };
 
 use crate::{
@@ -20,7 +25,7 @@ use crate::{
     cmd_pipeline::symbol_graph::{EdgeDetail, EdgeKind},
     file_format::{
         analysis::{
-            BindingOwnerLang, BindingSlotKind, OntologySlotInfo, OntologySlotKind,
+            BindingOwnerLang, BindingSlotKind, BindingSlotLang, OntologySlotInfo, OntologySlotKind,
             StructuredBindingSlotInfo, StructuredFieldInfo,
         },
         ontology_mapping::{label_to_badge_info, pointer_kind_to_badge_info},
@@ -33,7 +38,9 @@ use crate::{
 #[derive(Debug, Args)]
 pub struct Traverse {
     /// The edge to traverse, currently one of: "uses", "callees", "class",
-    /// "inheritance".
+    /// "inheritance", "expands-to" (from a macro to the symbols its expansions
+    /// produced), "expanded-from" (from a symbol to the macros that produced
+    /// it).
     #[clap(long, short, value_parser, default_value = "callees")]
     edge: String,
 
@@ -101,13 +108,97 @@ pub struct Traverse {
     /// of the uses.
     #[clap(long, value_parser, default_value = "24")]
     pub skip_field_member_uses_at_count: u32,
+
+    /// Instead of traversing, produce a JsonValue per input symbol of the form
+    /// `{ sym, counts: { overriddenBy, subclasses, overrides, supers } }` so
+    /// that the size of an "inheritance" traversal can be displayed without
+    /// paying for it.  `counts` is null for symbols unknown to the crossref
+    /// database.  Symbols with precomputed aggregates (see
+    /// `precompute-aggregates`) also get an `aggregates` field.
+    #[clap(long, action)]
+    pub counts_only: bool,
+
+    /// Only traverse out of symbols defined under one of these path prefixes
+    /// (ex: "dom/") so that the graph doesn't wander into unrelated modules.
+    /// Symbols defined elsewhere that we have edges to are kept as stub nodes
+    /// marked with `"external": true` in their jumprefs.  Symbols without a definition path and the starting
+    /// symbols are always traversed.
+    #[clap(long, value_parser)]
+    pub stop_at_path_boundary: Vec<String>,
+
+    /// Don't follow call, use, or override edges to symbols defined in test
+    /// files (ex: mocks overriding an interface).  This is decided as edges are
+    /// followed rather than by filtering the resulting graph so that the node
+    /// limit is spent on more interesting symbols.
+    #[clap(long, action)]
+    pub skip_test_edges: bool,
+
+    /// Like `--skip-test-edges` but for symbols defined in generated files
+    /// (ex: IPC and WebIDL binding classes).
+    #[clap(long, action)]
+    pub skip_generated_edges: bool,
 }
 
+/// The names that `per-file-info.toml` gives the test and generated path
+/// kinds, which is what crossref records as the `path_kind` of their hits.
+const TEST_PATH_KIND: &str = "Test files";
+const GENERATED_PATH_KIND: &str = "Generated code";
+
 #[derive(Debug)]
 pub struct TraverseCommand {
     pub args: Traverse,
 }
 
+impl TraverseCommand {
+    /// Is the symbol defined outside of all of the `stop_at_path_boundary`
+    /// prefixes?
+    fn is_outside_path_boundary(&self, sym_info: &DerivedSymbolInfo) -> bool {
+        let boundaries = &self.args.stop_at_path_boundary;
+        match sym_info.get_def_path() {
+            Some(path) if !boundaries.is_empty() => !boundaries
+                .iter()
+                .any(|prefix| path.starts_with(prefix.as_str())),
+            _ => false,
+        }
+    }
+
+    /// Should edges to symbols defined in files of this path kind be skipped
+    /// per `skip_test_edges` and `skip_generated_edges`?
+    fn skips_path_kind(&self, path_kind: &str) -> bool {
+        (self.args.skip_test_edges && path_kind == TEST_PATH_KIND)
+            || (self.args.skip_generated_edges && path_kind == GENERATED_PATH_KIND)
+    }
+
+    /// Should the edge to the symbol with this crossref info be skipped
+    /// because of the path kind of its definition?
+    fn skips_edge_target(&self, crossref_info: &Value) -> bool {
+        match crossref_info.pointer("/defs/0/path_kind") {
+            Some(Value::String(path_kind)) => self.skips_path_kind(path_kind),
+            _ => false,
+        }
+    }
+
+    /// Like `SymbolGraphNodeSet::ensure_symbol_if`, but rejecting the edge
+    /// target if `skips_edge_target` says to skip it.
+    async fn ensure_edge_target<'a>(
+        &self,
+        sym_node_set: &'a mut SymbolGraphNodeSet,
+        target_sym: &'a Ustr,
+        server: &'a (dyn AbstractServer + Send + Sync),
+        depth: u32,
+    ) -> Result<Option<(SymbolGraphNodeId, &'a mut DerivedSymbolInfo)>> {
+        let target = sym_node_set
+            .ensure_symbol_if(target_sym, server, depth, |info| {
+                !self.skips_edge_target(info)
+            })
+            .await?;
+        if target.is_none() {
+            trace!(sym = target_sym.as_str(), "skipping edge by path kind");
+        }
+        Ok(target)
+    }
+}
+
 bitflags! {
     #[derive(Clone, Copy)]
     pub struct Traversals: u32 {
@@ -141,6 +232,7 @@ bitflags! {
 ///
 /// We potentially traverse all of the following crossref paths:
 /// - "calls"
+/// - "meta/bindingSlots":
 /// - "meta/fields":
 /// - "meta/ontologySlots":
 /// - "meta/overrides":
@@ -170,6 +262,26 @@ impl PipelineCommand for TraverseCommand {
             }
         };
 
+        if self.args.counts_only {
+            let mut values = vec![];
+            for info in cil.symbol_crossref_infos {
+                let counts = server.symbol_relation_counts(&info.symbol).await?;
+                let mut value = json!({
+                    "sym": info.symbol,
+                    "counts": to_value(counts)?,
+                });
+                let aggregates = match server.precomputed_aggregates(&info.symbol).await {
+                    Err(ServerError::Unsupported) => None,
+                    result => result?,
+                };
+                if let Some(aggregates) = aggregates {
+                    value["aggregates"] = to_value(aggregates)?;
+                }
+                values.push(JsonValue { value });
+            }
+            return Ok(PipelineValues::JsonValueList(JsonValueList { values }));
+        }
+
         let mut sym_node_set = SymbolGraphNodeSet::new();
         let mut sym_edge_set = SymbolGraphEdgeSet::new();
         let mut graph = NamedSymbolGraph::new("only".to_string());
@@ -198,8 +310,11 @@ impl PipelineCommand for TraverseCommand {
             }
             considered.insert(info.symbol);
 
-            let (sym_node_id, _info) =
-                sym_node_set.add_symbol(DerivedSymbolInfo::new(info.symbol, info.crossref_info, 0));
+            let (sym_node_id, _info) = sym_node_set.add_symbol(DerivedSymbolInfo::new(
+                info.symbol,
+                info.crossref_info.into_value(),
+                0,
+            ));
             // Explicitly put the node in the graph so if we don't find any
             // edges, we still display the node.  This is important for things
             // like "class-diagram" where showing nothing is very confusing.
@@ -285,6 +400,11 @@ impl PipelineCommand for TraverseCommand {
             "uses" => true,
             _ => false,
         };
+        let traverse_macro_expansion = match self.args.edge.as_str() {
+            "expands-to" => Some("expands-to"),
+            "expanded-from" => Some("expanded-from"),
+            _ => None,
+        };
 
         // General operation:
         // - We pull a node to be traversed off the queue.  This ends up breadth
@@ -294,17 +414,23 @@ impl PipelineCommand for TraverseCommand {
         //   set of symbols we're traversing from which we already have cached
         //   values for and the new edges we discover, but it's not a concern.
         // - We traverse the list of edges.
+        let mut progress = ProgressTracker::new("traverse", None);
         while let Some((sym, depth, cur_traversals)) = to_traverse.pop_front() {
+            progress.advance(1);
             if sym_node_set.symbol_crossref_infos.len() as u32 >= node_limit {
                 trace!(sym = %sym, depth, "stopping because of node limit");
-                overloads_hit.push(OverloadInfo {
-                    kind: OverloadKind::NodeLimit,
-                    sym: Some(sym.to_string()),
-                    exist: to_traverse.len() as u32,
-                    included: node_limit,
-                    local_limit: 0,
-                    global_limit: node_limit,
-                });
+                record_overload(
+                    &mut overloads_hit,
+                    "traverse",
+                    OverloadInfo {
+                        kind: OverloadKind::NodeLimit,
+                        sym: Some(sym.to_string()),
+                        exist: to_traverse.len() as u32,
+                        included: node_limit,
+                        local_limit: 0,
+                        global_limit: node_limit,
+                    },
+                );
                 to_traverse.clear();
                 break;
             };
@@ -320,6 +446,16 @@ impl PipelineCommand for TraverseCommand {
             // really need that.
             let (sym_id, sym_info) = sym_node_set.ensure_symbol(&sym, server, depth).await?;
 
+            // Symbols across the path boundary stay in the graph as stubs via
+            // the edges that led to them, but we don't traverse out of them.
+            if depth > 0 && self.is_outside_path_boundary(sym_info) {
+                trace!(sym = %sym, depth, "stopping at path boundary");
+                if !self.args.retain_all_symbol_data {
+                    sym_info.reduce_memory_usage_by_dropping_non_jumpref_info();
+                }
+                continue;
+            }
+
             if let Some(stop_at_label) = &stop_at_class_label {
                 if let Some(labels_json) = sym_info.crossref_info.pointer("/meta/labels").cloned() {
                     let labels: Vec<Ustr> = from_value(labels_json).unwrap();
@@ -454,14 +590,18 @@ impl PipelineCommand for TraverseCommand {
                 let member_uses = member_uses_storage.as_array().unwrap();
 
                 if member_uses.len() as u32 >= self.args.skip_field_member_uses_at_count {
-                    overloads_hit.push(OverloadInfo {
-                        kind: OverloadKind::FieldMemberUses,
-                        sym: Some(sym.to_string()),
-                        exist: member_uses.len() as u32,
-                        included: 0,
-                        local_limit: self.args.skip_field_member_uses_at_count,
-                        global_limit: 0,
-                    });
+                    record_overload(
+                        &mut overloads_hit,
+                        "traverse",
+                        OverloadInfo {
+                            kind: OverloadKind::FieldMemberUses,
+                            sym: Some(sym.to_string()),
+                            exist: member_uses.len() as u32,
+                            included: 0,
+                            local_limit: self.args.skip_field_member_uses_at_count,
+                            global_limit: 0,
+                        },
+                    );
                 } else {
                     for target in member_uses {
                         // fmu is { sym, pretty, fields }
@@ -659,8 +799,22 @@ impl PipelineCommand for TraverseCommand {
                     let slot: StructuredBindingSlotInfo = from_value(slot_val).unwrap();
                     let (should_traverse, skip_other_edges, outbound_edge, edge_kind) =
                         match (slot.props.owner_lang, slot.props.slot_kind) {
-                            // Don't bother with IDL bindings; all the relevant traversals involve a
-                            // slotOwner at this time.
+                            // IDL fields whose C++ binding is a plain struct field (ex: WebIDL
+                            // dictionary members) are only used through the C++ field, so for
+                            // "uses" we go down to it, drawing the same edge that its slotOwner
+                            // would.
+                            (BindingOwnerLang::Idl, BindingSlotKind::Attribute)
+                                if slot.props.slot_lang == BindingSlotLang::Cpp =>
+                            {
+                                (
+                                    self.args.edge == "uses",
+                                    false,
+                                    true,
+                                    EdgeKind::Implementation,
+                                )
+                            }
+                            // Don't bother with other IDL bindings; all the relevant traversals
+                            // involve a slotOwner at this time.
                             (BindingOwnerLang::Idl, _) => (false, false, false, EdgeKind::Default),
                             // Cross-language binding class relationships are weird because
                             // the bindings inside are bidirectional, so let's ignore them.
@@ -786,9 +940,13 @@ impl PipelineCommand for TraverseCommand {
                     let target_sym_str = target.as_str().ok_or_else(bad_data)?;
                     let target_sym = ustr(target_sym_str);
 
-                    let (target_id, target_info) = sym_node_set
-                        .ensure_symbol(&target_sym, server, next_depth)
-                        .await?;
+                    let (target_id, target_info) = match self
+                        .ensure_edge_target(&mut sym_node_set, &target_sym, server, next_depth)
+                        .await?
+                    {
+                        Some(target) => target,
+                        None => continue,
+                    };
 
                     sym_edge_set.ensure_edge_in_graph(
                         sym_id.clone(),
@@ -832,9 +990,13 @@ impl PipelineCommand for TraverseCommand {
                     let target_sym_str = target["sym"].as_str().ok_or_else(bad_data)?;
                     let target_sym = ustr(target_sym_str);
 
-                    let (target_id, target_info) = sym_node_set
-                        .ensure_symbol(&target_sym, server, next_depth)
-                        .await?;
+                    let (target_id, target_info) = match self
+                        .ensure_edge_target(&mut sym_node_set, &target_sym, server, next_depth)
+                        .await?
+                    {
+                        Some(target) => target,
+                        None => continue,
+                    };
 
                     if let Some(Value::Array(labels_json)) =
                         target_info.crossref_info.pointer("/meta/labels").cloned()
@@ -894,9 +1056,13 @@ impl PipelineCommand for TraverseCommand {
                     let target_sym_str = target["sym"].as_str().ok_or_else(bad_data)?;
                     let target_sym = ustr(target_sym_str);
 
-                    let (target_id, target_info) = sym_node_set
-                        .ensure_symbol(&target_sym, server, next_depth)
-                        .await?;
+                    let (target_id, target_info) = match self
+                        .ensure_edge_target(&mut sym_node_set, &target_sym, server, next_depth)
+                        .await?
+                    {
+                        Some(target) => target,
+                        None => continue,
+                    };
 
                     if considered.insert(target_info.symbol) {
                         // As a quasi-hack, only add this edge if we didn't
@@ -948,9 +1114,13 @@ impl PipelineCommand for TraverseCommand {
                     let target_sym_str = target.as_str().ok_or_else(bad_data)?;
                     let target_sym = ustr(target_sym_str);
 
-                    let (target_id, target_info) = sym_node_set
-                        .ensure_symbol(&target_sym, server, next_depth)
-                        .await?;
+                    let (target_id, target_info) = match self
+                        .ensure_edge_target(&mut sym_node_set, &target_sym, server, next_depth)
+                        .await?
+                    {
+                        Some(target) => target,
+                        None => continue,
+                    };
 
                     if considered.insert(target_info.symbol) {
                         // Same rationale on avoiding a duplicate edge.
@@ -1012,9 +1182,13 @@ impl PipelineCommand for TraverseCommand {
                         edge_info.push(EdgeDetail::Jump(jump.clone()));
                     }
 
-                    let (target_id, target_info) = sym_node_set
-                        .ensure_symbol(&target_sym, server, next_depth)
-                        .await?;
+                    let (target_id, target_info) = match self
+                        .ensure_edge_target(&mut sym_node_set, &target_sym, server, next_depth)
+                        .await?
+                    {
+                        Some(target) => target,
+                        None => continue,
+                    };
 
                     if target_info.is_callable() {
                         sym_edge_set.ensure_edge_in_graph(
@@ -1036,6 +1210,56 @@ impl PipelineCommand for TraverseCommand {
                 }
             }
 
+            if let Some(expansion_kind) = traverse_macro_expansion {
+                let bad_data = || {
+                    ServerError::StickyProblem(ErrorDetails {
+                        layer: ErrorLayer::DataLayer,
+                        message: format!("Bad edge info in sym {sym} on {expansion_kind}"),
+                    })
+                };
+
+                let sym_info = sym_node_set.get(&sym_id);
+                let related = match sym_info.crossref_info.get(expansion_kind) {
+                    Some(Value::Array(arr)) => arr.clone(),
+                    _ => vec![],
+                };
+
+                // Like callees, these are synthetically derived by the crossref
+                // process as a flat list of { sym, pretty, jump }.  Edges always
+                // point from the macro to the symbol its expansion produced.
+                for target in related {
+                    let target_sym_str = target["sym"].as_str().ok_or_else(bad_data)?;
+                    let target_sym = ustr(target_sym_str);
+
+                    let mut edge_info = vec![];
+                    if let Some(Value::String(jump)) = target.get("jump") {
+                        edge_info.push(EdgeDetail::Jump(jump.clone()));
+                    }
+
+                    let (target_id, target_info) = sym_node_set
+                        .ensure_symbol(&target_sym, server, next_depth)
+                        .await?;
+                    let target_symbol = target_info.symbol;
+
+                    let (from_id, to_id) = if expansion_kind == "expands-to" {
+                        (sym_id.clone(), target_id)
+                    } else {
+                        (target_id, sym_id.clone())
+                    };
+                    sym_edge_set.ensure_edge_in_graph(
+                        from_id,
+                        to_id,
+                        EdgeKind::Implementation,
+                        edge_info,
+                        &mut graph,
+                    );
+                    if next_depth < max_depth && considered.insert(target_symbol) {
+                        trace!(sym = target_sym_str, "scheduling macro expansion");
+                        to_traverse.push_back((target_symbol, next_depth, all_traversals_valid));
+                    }
+                }
+            }
+
             if traverse_uses {
                 let bad_data = || {
                     ServerError::StickyProblem(ErrorDetails {
@@ -1066,14 +1290,18 @@ impl PipelineCommand for TraverseCommand {
 
                 // Do not process the uses if there are more paths than our skip limit.
                 if uses.len() as u32 >= skip_uses_at_path_count {
-                    overloads_hit.push(OverloadInfo {
-                        kind: OverloadKind::UsesPaths,
-                        sym: Some(sym.to_string()),
-                        exist: uses.len() as u32,
-                        included: 0,
-                        local_limit: skip_uses_at_path_count,
-                        global_limit: 0,
-                    });
+                    record_overload(
+                        &mut overloads_hit,
+                        "traverse",
+                        OverloadInfo {
+                            kind: OverloadKind::UsesPaths,
+                            sym: Some(sym.to_string()),
+                            exist: uses.len() as u32,
+                            included: 0,
+                            local_limit: skip_uses_at_path_count,
+                            global_limit: 0,
+                        },
+                    );
                     continue;
                 }
 
@@ -1091,6 +1319,13 @@ impl PipelineCommand for TraverseCommand {
                 for path_hits in uses {
                     let path = path_hits["path"].as_str().ok_or_else(bad_data)?;
                     let hits = path_hits["lines"].as_array().ok_or_else(bad_data)?;
+                    // The contextsyms of the uses in a file are defined in
+                    // that file, so we can skip the whole file without looking
+                    // any of them up.
+                    if self.skips_path_kind(path_hits["path_kind"].as_str().unwrap_or("")) {
+                        trace!(path, "skipping uses by path kind");
+                        continue;
+                    }
                     // For now we're just going to use the path limit for this too.
                     //
                     // The specific scenario driving this is the "abort" method
@@ -1102,28 +1337,36 @@ impl PipelineCommand for TraverseCommand {
                     // a local limit.  Then add the line count and check if the
                     // global limit has been hit.
                     if hits.len() as u32 >= skip_uses_at_path_count {
-                        overloads_hit.push(OverloadInfo {
-                            kind: OverloadKind::UsesLines,
-                            sym: Some(sym.to_string()),
-                            exist: hits.len() as u32,
-                            included: 0,
-                            local_limit: skip_uses_at_path_count,
-                            global_limit: 0,
-                        });
+                        record_overload(
+                            &mut overloads_hit,
+                            "traverse",
+                            OverloadInfo {
+                                kind: OverloadKind::UsesLines,
+                                sym: Some(sym.to_string()),
+                                exist: hits.len() as u32,
+                                included: 0,
+                                local_limit: skip_uses_at_path_count,
+                                global_limit: 0,
+                            },
+                        );
                         break;
                     }
                     line_hits += hits.len() as u32;
                     if line_hits >= skip_uses_at_path_count {
-                        overloads_hit.push(OverloadInfo {
-                            kind: OverloadKind::UsesLines,
-                            sym: Some(sym.to_string()),
-                            exist: line_hits,
-                            included: line_hits - (hits.len() as u32),
-                            local_limit: 0,
-                            // Note we're reporting this as a global limit to
-                            // differentiate from the above case.
-                            global_limit: skip_uses_at_path_count,
-                        });
+                        record_overload(
+                            &mut overloads_hit,
+                            "traverse",
+                            OverloadInfo {
+                                kind: OverloadKind::UsesLines,
+                                sym: Some(sym.to_string()),
+                                exist: line_hits,
+                                included: line_hits - (hits.len() as u32),
+                                local_limit: 0,
+                                // Note we're reporting this as a global limit to
+                                // differentiate from the above case.
+                                global_limit: skip_uses_at_path_count,
+                            },
+                        );
                         break;
                     }
                     for source in hits {
@@ -1172,6 +1415,24 @@ impl PipelineCommand for TraverseCommand {
             }
         }
 
+        progress.finish();
+
+        // ## Mark external stubs
+        //
+        // This covers both the symbols we stopped at above and those we never
+        // got to traverse because of the depth limit.
+        for sym_info in sym_node_set.symbol_crossref_infos.iter_mut() {
+            if sym_info.depth > 0 && self.is_outside_path_boundary(sym_info) {
+                sym_info.external = true;
+                // "North east arrow" to indicate the symbol leads elsewhere.
+                sym_info.badges.push(SymbolBadge {
+                    pri: 100,
+                    label: ustr("\u{2197}\u{fe0f}"),
+                    source_jump: None,
+                });
+            }
+        }
+
         // ## Paths Between
         let graph_coll = if self.args.paths_between {
             // In this case, we don't want our original node set because we
@@ -1199,17 +1460,21 @@ impl PipelineCommand for TraverseCommand {
                 &mut paths_edge_set,
             );
             if paths_node_set.symbol_crossref_infos.len() as u32 >= self.args.node_limit {
-                overloads_hit.push(OverloadInfo {
-                    kind: OverloadKind::NodeLimit,
-                    sym: None,
-                    // We don't know how many there might have been as we did a soft limit
-                    // stop while propagating, so say 0 for exist but how many
-                    // we included.
-                    exist: 0,
-                    included: paths_node_set.symbol_crossref_infos.len() as u32,
-                    local_limit: 0,
-                    global_limit: self.args.node_limit,
-                });
+                record_overload(
+                    &mut overloads_hit,
+                    "traverse",
+                    OverloadInfo {
+                        kind: OverloadKind::NodeLimit,
+                        sym: None,
+                        // We don't know how many there might have been as we did a soft limit
+                        // stop while propagating, so say 0 for exist but how many
+                        // we included.
+                        exist: 0,
+                        included: paths_node_set.symbol_crossref_infos.len() as u32,
+                        local_limit: 0,
+                        global_limit: self.args.node_limit,
+                    },
+                );
             }
 
             SymbolGraphCollection {
@@ -1231,4 +1496,91 @@ impl PipelineCommand for TraverseCommand {
 
         Ok(PipelineValues::SymbolGraphCollection(graph_coll))
     }
+
+    /// The estimate only looks at the first hop from the root symbols, which
+    /// is usually enough to tell whether the node limit will be hit.
+    async fn estimate(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        input: &PipelineValues,
+    ) -> Result<Option<StageEstimate>> {
+        let cil = match input {
+            PipelineValues::SymbolCrossrefInfoList(cil) => cil,
+            _ => return Ok(None),
+        };
+        if self.args.counts_only {
+            return Ok(None);
+        }
+
+        let mut first_hop = 0;
+        for info in &cil.symbol_crossref_infos {
+            first_hop += match self.args.edge.as_str() {
+                "uses" => info
+                    .crossref_info
+                    .uses
+                    .iter()
+                    .map(|path| path.lines.len())
+                    .sum(),
+                "callees" => info.crossref_info.callees.len(),
+                "expands-to" => info.crossref_info.expands_to.len(),
+                "expanded-from" => info.crossref_info.expanded_from.len(),
+                _ => match server.symbol_relation_counts(&info.symbol).await? {
+                    Some(counts) => counts.subclasses + counts.supers,
+                    None => 0,
+                },
+            };
+        }
+
+        let node_limit = if self.args.paths_between {
+            self.args.paths_between_node_limit
+        } else {
+            self.args.node_limit
+        } as usize;
+        let nodes = cil.symbol_crossref_infos.len() + first_hop;
+        let mut limits_hit = vec![];
+        if nodes > node_limit {
+            limits_hit.push(format!(
+                "the first hop alone reaches {} nodes, over the node limit of {}",
+                nodes, node_limit
+            ));
+        }
+        Ok(Some(StageEstimate {
+            symbols: Some(cil.symbol_crossref_infos.len() as u64),
+            files: None,
+            expected_results: Some(nodes.min(node_limit) as u64),
+            limits_hit,
+        }))
+    }
+}
+
+#[test]
+fn test_skips_edge_target() {
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Opts {
+        #[clap(flatten)]
+        traverse: Traverse,
+    }
+
+    let command = |args: &str| TraverseCommand {
+        args: Opts::try_parse_from(shell_words::split(args).unwrap())
+            .unwrap()
+            .traverse,
+    };
+    let defined_in = |path_kind: &str| json!({"defs": [{"path": "a.cpp", "path_kind": path_kind}]});
+
+    let skip_tests = command("traverse --skip-test-edges");
+    assert!(skip_tests.skips_edge_target(&defined_in("Test files")));
+    assert!(!skip_tests.skips_edge_target(&defined_in("Generated code")));
+    assert!(!skip_tests.skips_edge_target(&defined_in("Normal")));
+    assert!(!skip_tests.skips_edge_target(&json!({})));
+
+    let skip_generated = command("traverse --skip-generated-edges");
+    assert!(skip_generated.skips_edge_target(&defined_in("Generated code")));
+    assert!(!skip_generated.skips_edge_target(&defined_in("Test files")));
+
+    let skip_none = command("traverse");
+    assert!(!skip_none.skips_edge_target(&defined_in("Test files")));
+    assert!(!skip_none.skips_edge_target(&defined_in("Generated code")));
 }
@@ -0,0 +1,330 @@
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use clap::Args;
+use serde::Serialize;
+use serde_json::{json, to_value, Value};
+use tokio_stream::StreamExt;
+use tree_sitter::{Node, Parser};
+use ustr::Ustr;
+
+use super::{
+    cmd_defs_in_text_matches::{extract_def_extents, DefExtent},
+    interface::{JsonValue, PipelineCommand, PipelineValues},
+    progress::ProgressTracker,
+    warnings::add_pipeline_warning,
+};
+
+use crate::abstract_server::{AbstractServer, Result};
+
+/// Enumerate the `unsafe` blocks, functions, impls, and traits in the Rust
+/// files under a directory, with per-directory counts for auditing.
+///
+/// The analysis records don't say whether something is `unsafe`, so the
+/// source of each file is parsed with tree-sitter to find the `unsafe`
+/// constructs, and the analysis records of files with hits are then used to
+/// find the symbol of the function (or impl or trait) each one belongs to.
+///
+/// Produces a JsonValue of the form `{ path, filesScanned, totals, byDir,
+/// hits, truncated }` where `byDir` is ordered by descending total and `hits`
+/// by path and line.
+#[derive(Debug, Args)]
+pub struct UnsafeAudit {
+    /// The directory to audit (ex: "dom/webgpu/").  Defaults to the whole
+    /// tree.
+    #[clap(value_parser, default_value = "")]
+    path: String,
+
+    /// Only audit files of this path kind (ex: "Normal" to skip third-party
+    /// crates).
+    #[clap(long, value_parser)]
+    path_kind: Option<String>,
+
+    /// Maximum number of hits to list; the counts always cover every hit.  0
+    /// means no limit.
+    #[clap(long, value_parser, default_value = "1000")]
+    limit: usize,
+}
+
+#[derive(Debug)]
+pub struct UnsafeAuditCommand {
+    pub args: UnsafeAudit,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum UnsafeKind {
+    Block,
+    Fn,
+    Impl,
+    Trait,
+}
+
+/// An `unsafe` construct found in the source, with 1-based lines.
+#[derive(Debug, PartialEq)]
+struct UnsafeSite {
+    kind: UnsafeKind,
+    start_lineno: u32,
+    end_lineno: u32,
+    /// The line whose enclosing definition the site belongs to: the start of
+    /// a block or the name of a fn, impl, or trait.
+    anchor_lineno: u32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UnsafeHit {
+    path: Ustr,
+    kind: UnsafeKind,
+    lno: u32,
+    end_lno: u32,
+    /// The first line of the construct.
+    line: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sym: Option<Ustr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pretty: Option<Ustr>,
+}
+
+#[derive(Default, Serialize)]
+struct UnsafeCounts {
+    block: usize,
+    r#fn: usize,
+    r#impl: usize,
+    r#trait: usize,
+    total: usize,
+}
+
+#[derive(Serialize)]
+struct DirUnsafeCounts {
+    dir: Ustr,
+    #[serde(flatten)]
+    counts: UnsafeCounts,
+}
+
+impl UnsafeCounts {
+    fn add(&mut self, kind: UnsafeKind) {
+        match kind {
+            UnsafeKind::Block => self.block += 1,
+            UnsafeKind::Fn => self.r#fn += 1,
+            UnsafeKind::Impl => self.r#impl += 1,
+            UnsafeKind::Trait => self.r#trait += 1,
+        }
+        self.total += 1;
+    }
+}
+
+fn has_unsafe_token(node: &Node) -> bool {
+    let mut cursor = node.walk();
+    let found = node
+        .children(&mut cursor)
+        .any(|child| child.kind() == "unsafe");
+    found
+}
+
+fn row_lineno(row: usize) -> u32 {
+    row as u32 + 1
+}
+
+/// Find the `unsafe` constructs in Rust source, ordered by position.
+fn find_unsafe_sites(source: &str) -> Vec<UnsafeSite> {
+    let ts_lang: tree_sitter::Language = tree_sitter_rust::LANGUAGE.into();
+    let mut parser = Parser::new();
+    parser
+        .set_language(&ts_lang)
+        .expect("Error loading grammar");
+    let tree = match parser.parse(source.as_bytes(), None) {
+        Some(tree) => tree,
+        None => return vec![],
+    };
+
+    let mut sites = vec![];
+    let mut stack = vec![tree.root_node()];
+    while let Some(node) = stack.pop() {
+        let kind = match node.kind() {
+            "unsafe_block" => Some(UnsafeKind::Block),
+            "function_item" | "function_signature_item" => {
+                let mut cursor = node.walk();
+                let is_unsafe = node
+                    .children(&mut cursor)
+                    .any(|child| child.kind() == "function_modifiers" && has_unsafe_token(&child));
+                is_unsafe.then_some(UnsafeKind::Fn)
+            }
+            "impl_item" if has_unsafe_token(&node) => Some(UnsafeKind::Impl),
+            "trait_item" if has_unsafe_token(&node) => Some(UnsafeKind::Trait),
+            _ => None,
+        };
+        if let Some(kind) = kind {
+            let anchor = node
+                .child_by_field_name("name")
+                .or_else(|| node.child_by_field_name("type"))
+                .unwrap_or(node);
+            sites.push(UnsafeSite {
+                kind,
+                start_lineno: row_lineno(node.start_position().row),
+                end_lineno: row_lineno(node.end_position().row),
+                anchor_lineno: row_lineno(anchor.start_position().row),
+            });
+        }
+        let mut cursor = node.walk();
+        stack.extend(node.children(&mut cursor));
+    }
+    sites.sort_by_key(|site| (site.start_lineno, site.end_lineno));
+    sites
+}
+
+/// Pick the innermost of the definitions covering the line.
+fn innermost_def(defs: &[DefExtent], lineno: u32) -> Option<&DefExtent> {
+    defs.iter()
+        .filter(|def| def.start_lineno <= lineno && lineno <= def.end_lineno)
+        .min_by_key(|def| def.end_lineno - def.start_lineno)
+}
+
+#[async_trait]
+impl PipelineCommand for UnsafeAuditCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        _input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        let dir = self.args.path.trim_end_matches('/');
+        let pathre = if dir.is_empty() {
+            r"\.rs$".to_string()
+        } else {
+            format!(r"^{}/.*\.rs$", regex::escape(dir))
+        };
+        let mut files = server.search_files(&pathre, false, usize::MAX).await?;
+        if let Some(path_kind) = &self.args.path_kind {
+            files
+                .file_matches
+                .retain(|file| file.concise.path_kind == path_kind.as_str());
+        }
+        files.file_matches.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let mut totals = UnsafeCounts::default();
+        let mut by_dir: BTreeMap<Ustr, UnsafeCounts> = BTreeMap::new();
+        let mut hits = vec![];
+        let mut progress =
+            ProgressTracker::new("unsafe-audit", Some(files.file_matches.len() as u64));
+        for file in &files.file_matches {
+            progress.advance(1);
+            let source = server.fetch_raw_source(&file.path).await?;
+            let sites = find_unsafe_sites(&source);
+            if sites.is_empty() {
+                continue;
+            }
+
+            // Files that weren't compiled have no analysis data, but their
+            // hits are still worth listing.
+            let records: Vec<Value> = match server.fetch_raw_analysis(&file.path).await {
+                Ok(stream) => stream.collect().await,
+                Err(_) => vec![],
+            };
+            let mut fn_defs = extract_def_extents(records.clone(), Some("function"));
+            fn_defs.extend(extract_def_extents(records.clone(), Some("method")));
+            let all_defs = extract_def_extents(records, None);
+
+            let lines: Vec<&str> = source.lines().collect();
+            let dir_counts = by_dir.entry(file.get_containing_dir()).or_default();
+            for site in sites {
+                totals.add(site.kind);
+                dir_counts.add(site.kind);
+                if self.args.limit > 0 && hits.len() >= self.args.limit {
+                    continue;
+                }
+                let enclosing = match site.kind {
+                    UnsafeKind::Block | UnsafeKind::Fn => {
+                        innermost_def(&fn_defs, site.anchor_lineno)
+                    }
+                    // The impl or trait is itself the definition, so we want
+                    // the outermost definition starting on its name's line.
+                    UnsafeKind::Impl | UnsafeKind::Trait => all_defs
+                        .iter()
+                        .filter(|def| def.start_lineno == site.anchor_lineno)
+                        .max_by_key(|def| def.end_lineno),
+                };
+                hits.push(UnsafeHit {
+                    path: file.path,
+                    kind: site.kind,
+                    lno: site.start_lineno,
+                    end_lno: site.end_lineno,
+                    line: lines
+                        .get(site.start_lineno as usize - 1)
+                        .map(|line| line.trim().to_string())
+                        .unwrap_or_default(),
+                    sym: enclosing.and_then(|def| def.syms.first().cloned()),
+                    pretty: enclosing.map(|def| def.pretty),
+                });
+            }
+        }
+        progress.finish();
+
+        if totals.total > hits.len() {
+            add_pipeline_warning(
+                "unsafe-audit",
+                format!(
+                    "only listing {} of {} hits; the counts include all of them",
+                    hits.len(),
+                    totals.total
+                ),
+            );
+        }
+
+        let mut by_dir: Vec<DirUnsafeCounts> = by_dir
+            .into_iter()
+            .map(|(dir, counts)| DirUnsafeCounts { dir, counts })
+            .collect();
+        by_dir.sort_by(|a, b| {
+            b.counts
+                .total
+                .cmp(&a.counts.total)
+                .then_with(|| a.dir.cmp(&b.dir))
+        });
+
+        Ok(PipelineValues::JsonValue(JsonValue {
+            value: json!({
+                "path": dir,
+                "filesScanned": files.file_matches.len(),
+                "truncated": totals.total > hits.len(),
+                "totals": to_value(totals)?,
+                "byDir": to_value(by_dir)?,
+                "hits": to_value(hits)?,
+            }),
+        }))
+    }
+}
+
+#[test]
+fn test_find_unsafe_sites() {
+    let source = r#"
+unsafe fn raw() {}
+
+fn safe() {
+    let x = unsafe {
+        raw()
+    };
+}
+
+unsafe impl Send for Foo {}
+impl Foo {}
+
+pub unsafe trait Bar {
+    unsafe fn baz(&self);
+}
+"#;
+    let sites = find_unsafe_sites(source);
+    let summary: Vec<(UnsafeKind, u32, u32, u32)> = sites
+        .iter()
+        .map(|s| (s.kind, s.start_lineno, s.end_lineno, s.anchor_lineno))
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            (UnsafeKind::Fn, 2, 2, 2),
+            (UnsafeKind::Block, 5, 7, 5),
+            (UnsafeKind::Impl, 10, 10, 10),
+            (UnsafeKind::Trait, 13, 15, 13),
+            (UnsafeKind::Fn, 14, 14, 14),
+        ]
+    );
+}
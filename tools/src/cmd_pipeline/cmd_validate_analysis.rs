@@ -0,0 +1,211 @@
+use async_trait::async_trait;
+use clap::Args;
+use serde::Serialize;
+use serde_json::{from_value, to_value, Map, Value};
+use tokio_stream::StreamExt;
+use ustr::Ustr;
+
+use super::interface::{JsonValue, PipelineCommand, PipelineValues};
+use crate::{
+    abstract_server::{AbstractServer, Result},
+    file_format::{
+        analysis::AnalysisStructured,
+        structured_schema::{
+            upgrade_structured_record, validate_structured_record, SchemaIssue,
+            SchemaIssueSeverity, CURRENT_STRUCTURED_VERSION,
+        },
+    },
+};
+
+/// Validate the structured records in one or more analysis files against the
+/// current structured record schema, reporting records that are from older
+/// schema versions (and so need up-conversion) and any records that the Rust
+/// consumers will fail to ingest.  This is intended to be run against indexer
+/// output when changing the structured record format.
+#[derive(Debug, Args)]
+pub struct ValidateAnalysis {
+    /// Tree-relative analysis file paths
+    #[clap(value_parser, required = true)]
+    files: Vec<String>,
+
+    /// Only report errors, not warnings.
+    #[clap(long, value_parser)]
+    errors_only: bool,
+}
+
+#[derive(Debug)]
+pub struct ValidateAnalysisCommand {
+    pub args: ValidateAnalysis,
+}
+
+#[derive(Serialize)]
+struct RecordIssue {
+    loc: String,
+    sym: String,
+    #[serde(flatten)]
+    issue: SchemaIssue,
+}
+
+#[derive(Serialize)]
+struct FileValidation {
+    file: String,
+    structured_records: u32,
+    /// Records that were from an older schema version.
+    upgraded: u32,
+    /// Records with at least one error, which consumers will drop.
+    invalid: u32,
+    issues: Vec<RecordIssue>,
+}
+
+#[derive(Serialize)]
+struct ValidationResult {
+    schema_version: u64,
+    files: Vec<FileValidation>,
+}
+
+/// Upgrade and validate a single structured record, returning whether it was
+/// from an older schema version along with its issues.
+fn check_record(obj: &mut Map<String, Value>) -> (bool, Vec<SchemaIssue>) {
+    let orig_version = match upgrade_structured_record(obj) {
+        Ok(orig_version) => orig_version,
+        Err(issue) => return (false, vec![issue]),
+    };
+    let mut issues = validate_structured_record(obj);
+    // Only bother with serde if our own checks didn't find an error since its
+    // message will be less useful.
+    if !issues
+        .iter()
+        .any(|i| i.severity == SchemaIssueSeverity::Error)
+    {
+        if let Err(err) = from_value::<AnalysisStructured<Ustr>>(Value::Object(obj.clone())) {
+            issues.push(SchemaIssue {
+                severity: SchemaIssueSeverity::Error,
+                path: "".to_string(),
+                message: format!("deserialization failed: {}", err),
+            });
+        }
+    }
+    (orig_version < CURRENT_STRUCTURED_VERSION, issues)
+}
+
+#[async_trait]
+impl PipelineCommand for ValidateAnalysisCommand {
+    async fn execute(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        _input: PipelineValues,
+    ) -> Result<PipelineValues> {
+        let mut files = vec![];
+
+        for file in &self.args.files {
+            let mut records = server.fetch_raw_analysis(file).await?;
+            let mut validation = FileValidation {
+                file: file.clone(),
+                structured_records: 0,
+                upgraded: 0,
+                invalid: 0,
+                issues: vec![],
+            };
+
+            while let Some(mut record) = records.next().await {
+                let obj = match record.as_object_mut() {
+                    Some(obj) if obj.contains_key("structured") => obj,
+                    _ => continue,
+                };
+                validation.structured_records += 1;
+
+                let loc = obj
+                    .remove("loc")
+                    .and_then(|v| v.as_str().map(|s| s.to_string()))
+                    .unwrap_or_default();
+                let sym = obj
+                    .get("sym")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("")
+                    .to_string();
+
+                let (upgraded, mut issues) = check_record(obj);
+                if upgraded {
+                    validation.upgraded += 1;
+                }
+                if issues
+                    .iter()
+                    .any(|i| i.severity == SchemaIssueSeverity::Error)
+                {
+                    validation.invalid += 1;
+                }
+                if self.args.errors_only {
+                    issues.retain(|i| i.severity == SchemaIssueSeverity::Error);
+                }
+                for issue in issues {
+                    validation.issues.push(RecordIssue {
+                        loc: loc.clone(),
+                        sym: sym.clone(),
+                        issue,
+                    });
+                }
+            }
+
+            files.push(validation);
+        }
+
+        Ok(PipelineValues::JsonValue(JsonValue {
+            value: to_value(ValidationResult {
+                schema_version: CURRENT_STRUCTURED_VERSION,
+                files,
+            })?,
+        }))
+    }
+}
+
+#[test]
+fn test_check_record() {
+    use serde_json::json;
+
+    let record = |value: Value| match value {
+        Value::Object(obj) => obj,
+        _ => unreachable!(),
+    };
+
+    let mut valid = record(json!({
+        "structured": 1,
+        "sym": "T_outerNS::Thing",
+        "pretty": "outerNS::Thing",
+        "kind": "class",
+        "sizeBytes": 16,
+        "supers": [],
+        "subclasses": ["T_outerNS::Human"],
+    }));
+    let (upgraded, issues) = check_record(&mut valid);
+    assert!(!upgraded);
+    assert!(issues.is_empty());
+
+    // Our own checks catch the shape problems...
+    let mut malformed = record(json!({
+        "structured": 1,
+        "pretty": "outerNS::Thing",
+        "kind": "class",
+        "supers": ["T_outerNS::Base"],
+    }));
+    let (_, issues) = check_record(&mut malformed);
+    let paths: Vec<&str> = issues.iter().map(|i| i.path.as_str()).collect();
+    assert_eq!(paths, vec!["/sym", "/supers/0"]);
+    assert!(issues
+        .iter()
+        .all(|i| i.severity == SchemaIssueSeverity::Error));
+
+    // ...and serde catches the rest.
+    let mut mistyped = record(json!({
+        "structured": 1,
+        "sym": "T_outerNS::Thing",
+        "kind": "class",
+        "sizeBytes": "sixteen",
+    }));
+    let (_, issues) = check_record(&mut mistyped);
+    assert_eq!(issues.len(), 1);
+    assert!(issues[0].message.starts_with("deserialization failed"));
+
+    let mut unversioned = record(json!({ "structured": 0, "sym": "T_outerNS::Thing" }));
+    let (_, issues) = check_record(&mut unversioned);
+    assert_eq!(issues[0].path, "/structured");
+}
@@ -0,0 +1,189 @@
+//! Machine-readable descriptions of the pipeline and junction commands for the
+//! benefit of external UIs like the web query builder, which can generate forms
+//! and validate plans from these instead of parsing clap's help text.
+//!
+//! Each command's arguments are described as a JSON Schema object whose
+//! properties are keyed by the argument's long name (or its id for positional
+//! arguments, which are also listed in order under "positional").
+
+use clap::{builder::ValueParser, value_parser, Arg, ArgAction, Command, CommandFactory};
+use serde_json::{json, Map, Value};
+
+use super::parser::{JunctionOpts, ToolOpts};
+
+/// The JSON Schema type of the values of an argument.
+fn value_type(arg: &Arg) -> &'static str {
+    match arg.get_action() {
+        ArgAction::SetTrue | ArgAction::SetFalse => return "boolean",
+        ArgAction::Count => return "integer",
+        _ => {}
+    }
+    let type_id = arg.get_value_parser().type_id();
+    let is = |parser: ValueParser| parser.type_id() == type_id;
+    if is(value_parser!(u32).into())
+        || is(value_parser!(u64).into())
+        || is(value_parser!(usize).into())
+        || is(value_parser!(i32).into())
+        || is(value_parser!(i64).into())
+    {
+        "integer"
+    } else if is(value_parser!(f64).into()) {
+        "number"
+    } else if is(value_parser!(bool).into()) {
+        "boolean"
+    } else {
+        "string"
+    }
+}
+
+/// Describe a single argument as a JSON Schema property.
+fn describe_arg(arg: &Arg) -> Value {
+    let ty = value_type(arg);
+    let mut item = Map::new();
+    item.insert("type".to_string(), json!(ty));
+    let choices: Vec<String> = arg
+        .get_possible_values()
+        .iter()
+        .filter(|pv| !pv.is_hide_set())
+        .map(|pv| pv.get_name().to_string())
+        .collect();
+    if !choices.is_empty() && ty != "boolean" {
+        item.insert("enum".to_string(), json!(choices));
+    }
+
+    let defaults: Vec<Value> = arg
+        .get_default_values()
+        .iter()
+        .map(|default| {
+            let default = default.to_string_lossy();
+            match ty {
+                "integer" | "number" | "boolean" => {
+                    serde_json::from_str(&default).unwrap_or_else(|_| json!(default))
+                }
+                _ => json!(default),
+            }
+        })
+        .collect();
+
+    let multiple = matches!(arg.get_action(), ArgAction::Append);
+    let mut prop = if multiple {
+        let mut prop = Map::new();
+        prop.insert("type".to_string(), json!("array"));
+        prop.insert("items".to_string(), Value::Object(item));
+        if !defaults.is_empty() {
+            prop.insert("default".to_string(), json!(defaults));
+        }
+        prop
+    } else {
+        if let Some(default) = defaults.into_iter().next() {
+            item.insert("default".to_string(), default);
+        } else if ty == "boolean" {
+            item.insert("default".to_string(), json!(false));
+        }
+        item
+    };
+
+    if let Some(help) = arg.get_long_help().or_else(|| arg.get_help()) {
+        prop.insert("description".to_string(), json!(help.to_string()));
+    }
+    if let Some(short) = arg.get_short() {
+        prop.insert("x-short".to_string(), json!(short.to_string()));
+    }
+    Value::Object(prop)
+}
+
+/// Describe the arguments of a command as a JSON Schema object.
+fn describe_args(cmd: &Command) -> Value {
+    let mut properties = Map::new();
+    let mut required = vec![];
+    let mut positional = vec![];
+    for arg in cmd.get_arguments() {
+        let id = arg.get_id().as_str();
+        if arg.is_hide_set() || id == "help" || id == "version" {
+            continue;
+        }
+        let name = match arg.get_long() {
+            Some(long) => long.to_string(),
+            None => id.to_string(),
+        };
+        if arg.is_positional() {
+            positional.push(name.clone());
+        }
+        if arg.is_required_set() {
+            required.push(name.clone());
+        }
+        properties.insert(name, describe_arg(arg));
+    }
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+        "positional": positional,
+        "additionalProperties": false,
+    })
+}
+
+fn describe_subcommands(cmd: &Command) -> Vec<Value> {
+    cmd.get_subcommands()
+        .filter(|sub| !sub.is_hide_set())
+        .map(|sub| {
+            json!({
+                "name": sub.get_name(),
+                "about": sub
+                    .get_long_about()
+                    .or_else(|| sub.get_about())
+                    .map(|about| about.to_string()),
+                "args": describe_args(sub),
+            })
+        })
+        .collect()
+}
+
+/// Describe the global options, the pipeline commands, and the junction
+/// commands.
+pub fn describe_commands() -> Value {
+    let tool = ToolOpts::command();
+    let junction = JunctionOpts::command();
+    json!({
+        "options": describe_args(&tool),
+        "commands": describe_subcommands(&tool),
+        "junctions": describe_subcommands(&junction),
+    })
+}
+
+#[test]
+fn test_describe_commands() {
+    let described = describe_commands();
+
+    let command = |kind: &str, name: &str| -> Value {
+        described[kind]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|c| c["name"] == name)
+            .cloned()
+            .unwrap_or_else(|| panic!("{} {} not described", kind, name))
+    };
+
+    let expand = command("commands", "crossref-expand");
+    let limit = &expand["args"]["properties"]["subclass-local-limit"];
+    assert_eq!(limit["type"], "integer");
+    assert_eq!(limit["default"], 100);
+    assert_eq!(
+        expand["args"]["properties"]["specializations"]["type"],
+        "boolean"
+    );
+
+    let diff = command("junctions", "text-diff-hits");
+    let mode = &diff["args"]["properties"]["mode"];
+    assert_eq!(mode["default"], "only-first");
+    assert_eq!(
+        mode["enum"],
+        json!(["only-first", "only-second", "symmetric"])
+    );
+
+    assert_eq!(
+        described["options"]["properties"]["output-format"]["default"],
+        "concise"
+    );
+}
@@ -0,0 +1,277 @@
+//! Structured descriptions of why a pipeline couldn't be parsed, so that
+//! callers can do better than show clap's error text: the pipeline-server
+//! returns them as the body of a 400, and the web query builder can use the
+//! range to highlight the offending part of the pipeline string.
+
+use clap::error::{ContextKind, ContextValue, ErrorKind};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PipelineDiagnosticKind {
+    /// The pipeline string couldn't be split into words (ex: an unterminated
+    /// quote).
+    Syntax,
+    /// The segment's command doesn't exist.
+    UnknownCommand,
+    /// The command exists but an argument was unknown, missing, or invalid.
+    BadArg,
+    /// The command exists but isn't allowed in this context (ex: commands that
+    /// write files aren't allowed from the web).
+    NotAllowed,
+    /// Help or version output was requested, which is reported as an error
+    /// because there's no pipeline to run.
+    Usage,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PipelineDiagnostic {
+    pub kind: PipelineDiagnosticKind,
+    /// The index of the offending `|`-delimited segment, if the problem is
+    /// with a specific segment.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub segment: Option<usize>,
+    /// The command of the offending segment, if it got that far.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// The `[start, end)` byte range of the offending text in the pipeline
+    /// string: the offending word if we could find it, otherwise the whole
+    /// segment.  Absent when the pipeline wasn't provided as a string (ex:
+    /// query config segments) or the location couldn't be determined.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub range: Option<(usize, usize)>,
+    pub message: String,
+    /// Things the user may have meant, like the closest command or argument
+    /// name, or the valid values of an argument.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub suggestions: Vec<String>,
+}
+
+impl PipelineDiagnostic {
+    pub fn new(kind: PipelineDiagnosticKind, message: String) -> Self {
+        PipelineDiagnostic {
+            kind,
+            segment: None,
+            command: None,
+            range: None,
+            message,
+            suggestions: vec![],
+        }
+    }
+
+    /// Attribute the diagnostic to a segment if it isn't already.
+    pub fn in_segment(mut self, segment: usize, words: Option<&[PipelineWord]>) -> Self {
+        self.segment.get_or_insert(segment);
+        if self.range.is_none() {
+            self.range = words.and_then(segment_range);
+        }
+        self
+    }
+
+    /// Render the diagnostic for a terminal, pointing at the offending range
+    /// of the pipeline string when we know it.
+    pub fn render(&self, arg_str: &str) -> String {
+        let (start, end) = match self.range {
+            Some((start, end)) if end <= arg_str.len() => (start, end),
+            _ => return self.message.clone(),
+        };
+        let mut rendered = format!(
+            "{}\n  {}\n  {}{}",
+            self.message.trim_end(),
+            arg_str,
+            " ".repeat(arg_str[..start].chars().count()),
+            "^".repeat(arg_str[start..end].chars().count().max(1)),
+        );
+        if !self.suggestions.is_empty() {
+            rendered.push_str(&format!("\n  suggestions: {}", self.suggestions.join(", ")));
+        }
+        rendered
+    }
+}
+
+/// A shell word of a pipeline string along with the byte range it came from.
+#[derive(Debug, PartialEq)]
+pub struct PipelineWord {
+    pub value: String,
+    pub range: (usize, usize),
+}
+
+/// Split the pipeline string into shell words like `shell_words::split` does,
+/// but also track where each word came from.  Returns None if the string isn't
+/// valid, in which case `shell_words::split` will have failed too.
+pub fn split_words_with_ranges(arg_str: &str) -> Option<Vec<PipelineWord>> {
+    let mut words = vec![];
+    let mut chars = arg_str.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        // Like the shell, a `#` at the start of a word starts a comment.
+        if c == '#' {
+            while let Some((_, c)) = chars.next() {
+                if c == '\n' {
+                    break;
+                }
+            }
+            continue;
+        }
+        let mut value = String::new();
+        let mut end = start;
+        while let Some(&(i, c)) = chars.peek() {
+            if c.is_whitespace() {
+                break;
+            }
+            chars.next();
+            end = i + c.len_utf8();
+            match c {
+                '\\' => match chars.next() {
+                    Some((i, '\n')) => end = i + 1,
+                    Some((i, c)) => {
+                        value.push(c);
+                        end = i + c.len_utf8();
+                    }
+                    None => return None,
+                },
+                '\'' => loop {
+                    match chars.next() {
+                        Some((i, '\'')) => {
+                            end = i + 1;
+                            break;
+                        }
+                        Some((_, c)) => value.push(c),
+                        None => return None,
+                    }
+                },
+                '"' => loop {
+                    match chars.next() {
+                        Some((i, '"')) => {
+                            end = i + 1;
+                            break;
+                        }
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, '\n')) => {}
+                            Some((_, c @ ('$' | '`' | '"' | '\\'))) => value.push(c),
+                            Some((_, c)) => {
+                                value.push('\\');
+                                value.push(c);
+                            }
+                            None => return None,
+                        },
+                        Some((_, c)) => value.push(c),
+                        None => return None,
+                    }
+                },
+                c => value.push(c),
+            }
+        }
+        words.push(PipelineWord {
+            value,
+            range: (start, end),
+        });
+    }
+    Some(words)
+}
+
+fn segment_range(words: &[PipelineWord]) -> Option<(usize, usize)> {
+    Some((words.first()?.range.0, words.last()?.range.1))
+}
+
+fn context_strings(err: &clap::Error, kind: ContextKind) -> Vec<String> {
+    match err.get(kind) {
+        Some(ContextValue::Strings(values)) => values.clone(),
+        Some(ContextValue::None) | None => vec![],
+        Some(other) => vec![other.to_string()],
+    }
+}
+
+/// Describe a failure to parse a segment's args, which don't include the fake
+/// binary name.  The segment's words are provided when the pipeline came from
+/// a string so that we can locate the offending word.
+pub fn diagnostic_from_clap(
+    err: clap::Error,
+    args: &[String],
+    segment: usize,
+    words: Option<&[PipelineWord]>,
+) -> PipelineDiagnostic {
+    let kind = match err.kind() {
+        ErrorKind::InvalidSubcommand | ErrorKind::MissingSubcommand => {
+            PipelineDiagnosticKind::UnknownCommand
+        }
+        ErrorKind::DisplayHelp
+        | ErrorKind::DisplayHelpOnMissingArgumentOrSubcommand
+        | ErrorKind::DisplayVersion => PipelineDiagnosticKind::Usage,
+        _ => PipelineDiagnosticKind::BadArg,
+    };
+
+    let mut suggestions = vec![];
+    for context in [
+        ContextKind::SuggestedSubcommand,
+        ContextKind::SuggestedArg,
+        ContextKind::SuggestedValue,
+    ] {
+        suggestions.extend(context_strings(&err, context));
+    }
+    if suggestions.is_empty() {
+        suggestions = context_strings(&err, ContextKind::ValidValue);
+    }
+
+    // The word to point at, as it appears in `args`.
+    let offending: Option<usize> = match kind {
+        PipelineDiagnosticKind::UnknownCommand if !args.is_empty() => Some(0),
+        PipelineDiagnosticKind::BadArg => {
+            let invalid_value = context_strings(&err, ContextKind::InvalidValue);
+            let invalid_arg = context_strings(&err, ContextKind::InvalidArg);
+            args.iter()
+                .position(|arg| invalid_value.contains(arg))
+                .or_else(|| {
+                    args.iter().position(|arg| {
+                        invalid_arg.iter().any(|invalid| {
+                            arg == invalid || arg.starts_with(&format!("{}=", invalid))
+                        })
+                    })
+                })
+        }
+        _ => None,
+    };
+    let range = words.and_then(|words| match offending {
+        Some(i) => words.get(i).map(|word| word.range),
+        None => segment_range(words),
+    });
+
+    PipelineDiagnostic {
+        kind,
+        segment: Some(segment),
+        command: match kind {
+            PipelineDiagnosticKind::UnknownCommand => None,
+            _ => args.first().cloned(),
+        },
+        range,
+        message: err.to_string(),
+        suggestions,
+    }
+}
+
+#[test]
+fn test_split_words_with_ranges() {
+    let pipeline = r#"search-text "foo bar" --path='a b/*' | show\ html # trailing"#;
+    let words = split_words_with_ranges(pipeline).unwrap();
+    assert_eq!(
+        words.iter().map(|w| w.value.as_str()).collect::<Vec<_>>(),
+        shell_words::split(pipeline).unwrap()
+    );
+    assert_eq!(
+        words
+            .iter()
+            .map(|w| &pipeline[w.range.0..w.range.1])
+            .collect::<Vec<_>>(),
+        vec![
+            "search-text",
+            r#""foo bar""#,
+            "--path='a b/*'",
+            "|",
+            r"show\ html"
+        ]
+    );
+    assert_eq!(split_words_with_ranges("search-text 'oops"), None);
+}
@@ -0,0 +1,253 @@
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::PathBuf,
+    process,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::PipelineValues;
+use crate::abstract_server::{ErrorDetails, ErrorLayer, Result, ServerError};
+
+/// The number of executed pipelines we retain if `SEARCHFOX_TOOL_HISTORY_SIZE`
+/// isn't set.
+const DEFAULT_HISTORY_SIZE: usize = 100;
+
+/// A previously executed searchfox-tool pipeline.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct QueryHistoryEntry {
+    /// Monotonically increasing identifier for use with `rerun`.
+    pub id: u64,
+    pub timestamp: String,
+    /// The full pipeline string as passed to searchfox-tool, including any
+    /// `--server` and `--tree` arguments.
+    pub pipeline: String,
+    /// The number of top-level results produced, or None if the pipeline
+    /// failed.
+    pub result_count: Option<usize>,
+}
+
+/// Per-user searchfox-tool query history persisted as a JSON file so that
+/// iterative investigations can look back at and `rerun` earlier pipelines.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct QueryHistory {
+    next_id: u64,
+    pub entries: Vec<QueryHistoryEntry>,
+}
+
+/// History is opt-in since searchfox-tool is also run in bulk by the indexing
+/// scripts.  The history file is `SEARCHFOX_TOOL_HISTORY` if set, otherwise
+/// there is no history.
+pub fn history_path() -> Option<PathBuf> {
+    env::var_os("SEARCHFOX_TOOL_HISTORY")
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+}
+
+fn history_size() -> usize {
+    env::var("SEARCHFOX_TOOL_HISTORY_SIZE")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_HISTORY_SIZE)
+}
+
+fn history_problem(message: String) -> ServerError {
+    ServerError::StickyProblem(ErrorDetails {
+        layer: ErrorLayer::BadInput,
+        message,
+    })
+}
+
+impl QueryHistory {
+    /// Load the history, treating a missing file as empty history.  A file
+    /// that can't be parsed is an error rather than something we silently
+    /// replace.
+    pub fn load(path: &PathBuf) -> Result<Self> {
+        match File::open(path) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file)).map_err(|err| {
+                history_problem(format!(
+                    "history file {} is corrupt: {}",
+                    path.display(),
+                    err
+                ))
+            }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(QueryHistory::default()),
+            Err(err) => Err(history_problem(format!(
+                "unable to read history file {}: {}",
+                path.display(),
+                err
+            ))),
+        }
+    }
+
+    /// Write the history out via a temporary file unique to this process and
+    /// atomically rename it into place so that concurrent invocations can't
+    /// leave a truncated or interleaved file behind.  (Concurrent invocations
+    /// can still drop each other's entries.)
+    pub fn save(&self, path: &PathBuf) -> io::Result<()> {
+        let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(format!(
+            ".{}.{}.tmp",
+            process::id(),
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos())
+                .unwrap_or_default()
+        ));
+        let tmp_path = path.with_file_name(tmp_name);
+        let written = File::create(&tmp_path).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            serde_json::to_writer(&mut writer, self)?;
+            writer.flush()
+        });
+        match written.and_then(|_| fs::rename(&tmp_path, path)) {
+            Ok(()) => Ok(()),
+            Err(err) => {
+                let _ = fs::remove_file(&tmp_path);
+                Err(err)
+            }
+        }
+    }
+
+    pub fn record(&mut self, pipeline: &str, result_count: Option<usize>) {
+        let size = history_size();
+        if size == 0 {
+            return;
+        }
+        self.next_id += 1;
+        self.entries.push(QueryHistoryEntry {
+            id: self.next_id,
+            timestamp: chrono::Local::now().to_rfc2822(),
+            pipeline: pipeline.to_string(),
+            result_count,
+        });
+        if self.entries.len() > size {
+            let excess = self.entries.len() - size;
+            self.entries.drain(0..excess);
+        }
+    }
+
+    pub fn get(&self, id: u64) -> Result<&QueryHistoryEntry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.id == id)
+            .ok_or_else(|| history_problem(format!("rerun: no history entry {}", id)))
+    }
+}
+
+/// Replace the values of named arguments in a pipeline string for `rerun`.
+/// Each override is of the form "name=value" and replaces the value of every
+/// `--name=old` or `--name old` in the pipeline.  It's an error for an
+/// override to not match anything since that's almost certainly a typo.
+pub fn substitute_params(pipeline: &str, overrides: &[String]) -> Result<String> {
+    let mut args = shell_words::split(pipeline).map_err(|err| history_problem(err.to_string()))?;
+
+    for over in overrides {
+        let (name, value) = over.split_once('=').ok_or_else(|| {
+            history_problem(format!("rerun: --set needs name=value, got {}", over))
+        })?;
+        let flag = format!("--{}", name.trim_start_matches('-'));
+        let flag_eq = format!("{}=", flag);
+
+        let mut matched = false;
+        let mut i = 0;
+        while i < args.len() {
+            if args[i].starts_with(&flag_eq) {
+                args[i] = format!("{}{}", flag_eq, value);
+                matched = true;
+            } else if args[i] == flag && i + 1 < args.len() && !args[i + 1].starts_with('-') {
+                args[i + 1] = value.to_string();
+                matched = true;
+                i += 1;
+            }
+            i += 1;
+        }
+        if !matched {
+            return Err(history_problem(format!(
+                "rerun: {} isn't used by this pipeline",
+                flag
+            )));
+        }
+    }
+
+    // Joining the segments ourselves avoids quoting the pipes.
+    Ok(args
+        .split(|arg| arg == "|")
+        .map(shell_words::join)
+        .collect::<Vec<_>>()
+        .join(" | "))
+}
+
+/// A rough count of the top-level results in a pipeline's output for history
+/// purposes.
+pub fn count_results(values: &PipelineValues) -> usize {
+    match values {
+        PipelineValues::IdentifierList(il) => il.identifiers.len(),
+        PipelineValues::SymbolList(sl) => sl.symbols.len(),
+        PipelineValues::SymbolCrossrefInfoList(scil) => scil.symbol_crossref_infos.len(),
+        PipelineValues::SymbolGraphCollection(sgc) => sgc.node_set.symbol_crossref_infos.len(),
+        PipelineValues::JsonValue(_) => 1,
+        PipelineValues::JsonValueList(jvl) => jvl.values.len(),
+        PipelineValues::JsonRecords(jr) => jr.by_file.iter().map(|f| f.records.len()).sum(),
+        PipelineValues::FileMatches(fm) => fm.file_matches.len(),
+        PipelineValues::TextMatches(tm) => tm.by_file.iter().map(|f| f.matches.len()).sum(),
+        PipelineValues::HtmlExcerpts(he) => he.by_file.iter().map(|f| f.excerpts.len()).sum(),
+        PipelineValues::FlattenedResultsBundle(frb) => frb
+            .path_kind_results
+            .iter()
+            .map(|pk| {
+                pk.file_names.len()
+                    + pk.kind_groups
+                        .iter()
+                        .map(|kg| kg.by_file.len() + kg.collapsed_by_file.len())
+                        .sum::<usize>()
+            })
+            .sum(),
+        PipelineValues::GraphResultsBundle(grb) => grb.graphs.len(),
+        PipelineValues::TextFile(_) => 1,
+        PipelineValues::BatchGroups(bg) => bg.groups.len(),
+        PipelineValues::SymbolTreeTableList(sttl) => sttl.tables.len(),
+        PipelineValues::InterleavedResults(ir) => ir.results.len(),
+        PipelineValues::MentionMatches(mm) => mm
+            .by_pretty
+            .iter()
+            .flat_map(|pm| pm.by_file.iter().map(|f| f.matches.len()))
+            .sum(),
+        PipelineValues::Void => 0,
+    }
+}
+
+#[test]
+fn test_load_and_save() {
+    let dir = env::temp_dir().join(format!("history-test-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("history.json");
+
+    let mut history = QueryHistory::load(&path).unwrap();
+    assert!(history.entries.is_empty());
+    history.record("search-identifiers Foo", Some(3));
+    history.save(&path).unwrap();
+    assert_eq!(QueryHistory::load(&path).unwrap().entries.len(), 1);
+    // The temporary file was renamed into place.
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+    fs::write(&path, "{ not json").unwrap();
+    assert!(QueryHistory::load(&path).is_err());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_substitute_params() {
+    let pipeline = "--tree=mozilla-central search-identifiers Foo | crossref-lookup | traverse --edge=uses --max-depth 2";
+    assert_eq!(
+        substitute_params(pipeline, &["max-depth=4".to_string(), "edge=callees".to_string()])
+            .unwrap(),
+        "--tree=mozilla-central search-identifiers Foo | crossref-lookup | traverse --edge=callees --max-depth 4"
+    );
+    assert!(substitute_params(pipeline, &["node-limit=10".to_string()]).is_err());
+    assert!(substitute_params(pipeline, &["max-depth".to_string()]).is_err());
+}
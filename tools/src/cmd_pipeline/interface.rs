@@ -2,22 +2,34 @@ use async_trait::async_trait;
 use bitflags::bitflags;
 use clap::{Args, ValueEnum};
 use serde::{ser::SerializeStruct, Serialize, Serializer};
-use serde_json::{json, to_string_pretty, Value};
+use serde_json::{json, to_string_pretty, to_value, Value};
 use std::{
     cmp::Ordering,
-    collections::{BTreeMap, HashMap, HashSet},
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet, VecDeque},
     fmt::Debug,
 };
-use tracing::{trace, trace_span, Instrument};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tracing::{field, instrument, trace, trace_span, Instrument, Span};
 use ustr::{ustr, Ustr, UstrMap};
 
 pub use crate::abstract_server::{AbstractServer, Result};
 use crate::{
-    abstract_server::{FileMatches, TextMatches},
-    file_format::crossref_converter::convert_crossref_value_to_sym_info_rep,
+    abstract_server::{FileMatches, TextMatches, TextMatchesByFile},
+    file_format::{
+        config::{SymbolAnnotation, SymbolAnnotationConfig},
+        crossref_converter::convert_crossref_value_to_sym_info_rep,
+        crossref_record::CrossrefRecord,
+    },
+    query::query_plan::QueryPlan,
 };
 
-use super::symbol_graph::{SymbolGraphCollection, SymbolGraphNodeSet};
+use super::{
+    history::count_results,
+    progress::{current_progress_format, ProgressFormat, ProgressTracker},
+    size_guard::enforce_size_guard,
+    symbol_graph::{SymbolGraphCollection, SymbolGraphNodeSet},
+    warnings::{check_strict_mode, current_pipeline_warnings},
+};
 
 #[derive(Clone, Debug, PartialEq, ValueEnum)]
 pub enum RecordType {
@@ -51,6 +63,10 @@ pub struct SymbolicQueryOpts {
     pub identifier: Option<String>,
 }
 
+/// Multiple keyed sub-results which should be processed/rendered
+/// independently of each other, such as the per-directory `FileMatches` used
+/// for directory listings or the per-interpretation results of an ambiguous
+/// query produced by the `batch-render` junction.
 #[derive(Serialize)]
 pub struct BatchGroups {
     pub groups: Vec<BatchGroupItem>,
@@ -58,6 +74,8 @@ pub struct BatchGroups {
 
 #[derive(Serialize)]
 pub struct BatchGroupItem {
+    /// The key for this group; a directory path for directory listings or the
+    /// name of the named pipeline that produced the value for junctions.
     pub name: String,
     pub value: PipelineValues,
 }
@@ -177,6 +195,7 @@ pub enum SymbolTreeTableItem {
     Field(SymbolTreeTableField),
     Hole(Vec<Option<String>>),
     EndPadding(Vec<Option<String>>),
+    Note(String),
     Warning(String),
 }
 
@@ -282,16 +301,42 @@ pub enum PipelineValues {
     TextFile(TextFile),
     BatchGroups(BatchGroups),
     SymbolTreeTableList(SymbolTreeTableList),
+    InterleavedResults(InterleavedResults),
+    MentionMatches(MentionMatches),
     Void,
 }
 
+impl PipelineValues {
+    /// Clone the value if it's of a type that the sources of pipelines produce
+    /// (ex: crossref and codesearch results), returning None for the derived
+    /// types that we don't expect to be able to share between pipelines.  This
+    /// is used by `ServerPipelineGraph` to share the output of identical
+    /// pipelines.
+    pub fn try_clone(&self) -> Option<PipelineValues> {
+        Some(match self {
+            PipelineValues::IdentifierList(il) => PipelineValues::IdentifierList(il.clone()),
+            PipelineValues::SymbolList(sl) => PipelineValues::SymbolList(sl.clone()),
+            PipelineValues::SymbolCrossrefInfoList(scil) => {
+                PipelineValues::SymbolCrossrefInfoList(scil.clone())
+            }
+            PipelineValues::JsonValue(jv) => PipelineValues::JsonValue(jv.clone()),
+            PipelineValues::JsonValueList(jvl) => PipelineValues::JsonValueList(jvl.clone()),
+            PipelineValues::FileMatches(fm) => PipelineValues::FileMatches(fm.clone()),
+            PipelineValues::TextMatches(tm) => PipelineValues::TextMatches(tm.clone()),
+            PipelineValues::TextFile(tf) => PipelineValues::TextFile(tf.clone()),
+            PipelineValues::Void => PipelineValues::Void,
+            _ => return None,
+        })
+    }
+}
+
 /// A list of (searchfox) identifiers.
-#[derive(Serialize)]
+#[derive(Clone, Serialize)]
 pub struct IdentifierList {
     pub identifiers: Vec<Ustr>,
 }
 
-#[derive(Serialize)]
+#[derive(Clone, Serialize)]
 pub struct SymbolWithContext {
     pub symbol: Ustr,
     pub quality: SymbolQuality,
@@ -299,9 +344,13 @@ pub struct SymbolWithContext {
 }
 
 /// A list of (searchfox) symbols.
-#[derive(Serialize)]
+#[derive(Clone, Serialize)]
 pub struct SymbolList {
     pub symbols: Vec<SymbolWithContext>,
+    /// Any limits hit while producing the list, like a regex identifier scan
+    /// running out of time.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub overloads: Vec<OverloadInfo>,
 }
 
 /// Metadata about how we got to this symbol from the root query.  Intended to
@@ -337,6 +386,25 @@ pub enum SymbolRelation {
     /// downward edges) with a distance indicating the number of steps to get to
     /// the common ancestor.
     CousinClassOf(Ustr, u32),
+    /// This symbol was produced by an expansion of the payload macro symbol
+    /// (and was added via that symbol by following the "expands-to" edges).
+    MacroExpansionOf(Ustr),
+    /// This symbol is an explicit or partial specialization of the payload
+    /// template symbol or, conversely, the template the payload specialization
+    /// specializes (and was added via that symbol by following the
+    /// "specializations" or "specializationOf" edges).
+    SpecializationOf(Ustr),
+    /// This symbol is a member of the payload virtual symbol's configured
+    /// group (and was added when looking up the virtual symbol).
+    VirtualMemberOf(Ustr),
+    /// This symbol is a field of the payload class or of one of its
+    /// superclasses (and was added by following the "fields" and "supers"
+    /// edges).  The u32 is the field's byte offset within the payload class.
+    MemberOf(Ustr, u32),
+    /// This symbol is the other side of the payload field's IDL binding, like
+    /// the C++ field generated for a WebIDL dictionary member or vice versa
+    /// (and was added by following the "bindingSlots" or "slotOwner" edges).
+    IdlCounterpartOf(Ustr),
 }
 
 /// Metadata about how likely we think it is that the user was actually looking
@@ -362,19 +430,44 @@ pub enum SymbolQuality {
     /// identifier beyond the match point.  The latter number should always be
     /// at least 1, as 0 would make this `ExactIdentifier`.
     IdentifierPrefix(u32, u32),
+    /// Like `ExactIdentifier` (or `ExplicitIdentifier`), but the search was
+    /// case-insensitive and the identifier's case differs from what was typed,
+    /// like finding `Document` when searching for `document`.
+    CaseMismatchedIdentifier,
+    /// Like `IdentifierPrefix`, but the case of the matched prefix differs from
+    /// what was typed.
+    CaseMismatchedIdentifierPrefix(u32, u32),
+    /// We did a regex search over identifiers and the identifier matched.
+    /// There's no notion of how much was guessed here, so this ranks like an
+    /// exact identifier match.
+    IdentifierRegex,
 }
 
 impl SymbolQuality {
     /// Compute a quality rank where lower values are higher quality / closer to
-    /// what the user typed.
+    /// what the user typed.  This does not account for case mismatches, which
+    /// are only used to break ties; see `is_case_mismatched`.
     pub fn numeric_rank(&self) -> u32 {
         match self {
             SymbolQuality::ExplicitSymbol => 0,
             SymbolQuality::ExplicitIdentifier => 1,
-            SymbolQuality::ExactIdentifier => 2,
-            SymbolQuality::IdentifierPrefix(_matched, extra) => 2 + extra,
+            SymbolQuality::ExactIdentifier
+            | SymbolQuality::CaseMismatchedIdentifier
+            | SymbolQuality::IdentifierRegex => 2,
+            SymbolQuality::IdentifierPrefix(_matched, extra)
+            | SymbolQuality::CaseMismatchedIdentifierPrefix(_matched, extra) => 2 + extra,
         }
     }
+
+    /// Did the identifier this symbol was found via only match what the user
+    /// typed case-insensitively?
+    pub fn is_case_mismatched(&self) -> bool {
+        matches!(
+            self,
+            SymbolQuality::CaseMismatchedIdentifier
+                | SymbolQuality::CaseMismatchedIdentifierPrefix(_, _)
+        )
+    }
 }
 
 impl PartialOrd for SymbolQuality {
@@ -387,11 +480,13 @@ impl Ord for SymbolQuality {
     fn cmp(&self, other: &Self) -> Ordering {
         let self_rank = self.numeric_rank();
         let other_rank = other.numeric_rank();
-        self_rank.cmp(&other_rank)
+        self_rank
+            .cmp(&other_rank)
+            .then_with(|| self.is_case_mismatched().cmp(&other.is_case_mismatched()))
     }
 }
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Debug, Serialize)]
 pub enum OverloadKind {
     /// There's just too many overrides!  This would happen for
     /// nsISupports::AddRef for example.
@@ -411,7 +506,15 @@ pub enum OverloadKind {
     /// Uses limit but based on lines.
     UsesLines,
     FieldMemberUses,
+    /// There's just too many symbols produced by expansions of this macro!
+    MacroExpansions,
+    /// There's just too many specializations of this template!
+    Specializations,
+    /// There's just too many fields across this class and its superclasses!
+    Members,
     NodeLimit,
+    /// A regex scan of the identifiers hit its result or time budget.
+    IdentifierScan,
 }
 
 /// Information about overloads encountered when processing some aspect of a
@@ -451,6 +554,31 @@ pub struct OverloadInfo {
     pub global_limit: u32,
 }
 
+impl OverloadInfo {
+    /// Describe the overload for a warning.
+    pub fn describe(&self) -> String {
+        let limit = if self.local_limit > 0 {
+            format!("local {:?} limit of {}", self.kind, self.local_limit)
+        } else {
+            format!("global {:?} limit of {}", self.kind, self.global_limit)
+        };
+        let sym = match &self.sym {
+            Some(sym) => format!(" for {}", sym),
+            None => String::new(),
+        };
+        // 0 means we don't know how many results there were.
+        let exist = if self.exist > 0 {
+            format!(" of {}", self.exist)
+        } else {
+            String::new()
+        };
+        format!(
+            "hit the {}{}, including {}{} results",
+            limit, sym, self.included, exist
+        )
+    }
+}
+
 bitflags! {
     /// Experimental/hacky set of flags to enable a single pipeline to hold a
     /// heterogeneous mixture of symbols and where these flags are what makes
@@ -476,53 +604,111 @@ bitflags! {
     }
 }
 /// A symbol and its cross-reference information.
-#[derive(Serialize)]
+#[derive(Clone, Serialize)]
 pub struct SymbolCrossrefInfo {
     pub symbol: Ustr,
-    pub crossref_info: Value,
+    pub crossref_info: CrossrefRecord,
     pub relation: SymbolRelation,
     pub quality: SymbolQuality,
     /// Any overloads encountered when processing this symbol.
     pub overloads_hit: Vec<OverloadInfo>,
     #[serde(rename = "type", skip_serializing_if = "SymbolMetaFlags::is_empty")]
     pub flags: SymbolMetaFlags,
+    /// The symbol has declarations but no definitions in this tree, like a
+    /// system library function only declared in a header.
+    #[serde(rename = "declarationOnly", skip_serializing_if = "std::ops::Not::not")]
+    pub declaration_only: bool,
+    /// The tree's `symbol_annotations` entry for the symbol, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub annotation: Option<SymbolAnnotation>,
 }
 
 impl SymbolCrossrefInfo {
     /// Return the pretty identifier for this symbol from its "meta" "pretty"
     /// field, falling back to the symbol name if we don't have a pretty name.
     pub fn get_pretty(&self) -> Ustr {
-        if let Some(Value::String(s)) = self.crossref_info.pointer("/meta/pretty") {
-            ustr(s)
-        } else {
-            self.symbol
-        }
+        self.crossref_info.pretty().unwrap_or(self.symbol)
+    }
+
+    /// Does the symbol have declarations but no definitions in this tree?
+    pub fn is_declaration_only(&self) -> bool {
+        self.crossref_info.defs.is_empty() && !self.crossref_info.decls.is_empty()
+    }
+
+    /// Attach the symbol's annotation from the tree's config, if it has one.
+    pub fn annotate(&mut self, config: &SymbolAnnotationConfig) {
+        self.annotation = config
+            .annotation_for(&self.symbol, &self.get_pretty())
+            .cloned();
     }
 
     pub fn get_method_symbols(&self) -> Option<Vec<Ustr>> {
-        if let Some(Value::Array(arr)) = self.crossref_info.pointer("/meta/methods") {
-            if arr.is_empty() {
-                return None;
-            }
-            Some(
-                arr.iter()
-                    .map(|v| ustr(v["sym"].as_str().unwrap_or("")))
-                    .collect(),
-            )
-        } else {
+        let method_syms = self.crossref_info.method_syms();
+        if method_syms.is_empty() {
             None
+        } else {
+            Some(method_syms)
         }
     }
 }
 
 /// A list of `SymbolCrossrefInfo`s plus a list of any unknown symbols provided
 /// to the input.
-#[derive(Serialize)]
+#[derive(Clone, Serialize)]
 pub struct SymbolCrossrefInfoList {
     pub symbol_crossref_infos: Vec<SymbolCrossrefInfo>,
     pub unknown_symbols: Vec<String>,
 }
 
+/// Where an `InterleavedResult` came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum InterleavedSource {
+    Semantic,
+    Fulltext,
+}
+
+/// A single line result in an `InterleavedResults` list.
+#[derive(Clone, Serialize)]
+pub struct InterleavedResult {
+    pub source: InterleavedSource,
+    pub kind: PresentationKind,
+    pub path: Ustr,
+    pub path_kind: Ustr,
+    pub lno: u32,
+    pub line: String,
+    /// The symbol the hit is for; absent for fulltext results.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub symbol: Option<Ustr>,
+    /// The pretty identifier of the symbol; absent for fulltext results.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pretty: Option<Ustr>,
+    pub context: Ustr,
+    pub contextsym: Ustr,
+    /// The relevance score used by the "score-weighted" and "round-robin"
+    /// policies; higher is more relevant.
+    pub score: f64,
+}
+
+/// Semantic and fulltext line results merged into a single ordered list by the
+/// `interleave` junction, in the order they should be presented.
+#[derive(Serialize)]
+pub struct InterleavedResults {
+    pub results: Vec<InterleavedResult>,
+}
+
+/// The fulltext matches for a symbol's pretty name that are in comments or
+/// documentation files, as found by `search-mentions`.
+#[derive(Serialize)]
+pub struct PrettyMentions {
+    pub pretty: Ustr,
+    pub by_file: Vec<TextMatchesByFile>,
+}
+
+#[derive(Serialize)]
+pub struct MentionMatches {
+    pub by_pretty: Vec<PrettyMentions>,
+}
+
 /// router.py-style mozsearch compiled results that has top-level path-kind
 /// (normal/test/generated) result clusters, where each cluster has file names /
 /// paths and line hits grouped by symbol-with-kind and by file name/path
@@ -534,6 +720,71 @@ pub struct SymbolCrossrefInfoList {
 pub struct FlattenedResultsBundle {
     pub path_kind_results: Vec<FlattenedPathKindGroupResults>,
     pub content_type: String,
+    /// The lines of context around each hit included in the HTML contents by
+    /// `augment-results` (ex: via the `context:N` query term), if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context_lines: Option<ContextLines>,
+    /// "Did you mean" identifier suggestions populated by `suggest-identifiers`
+    /// when there are no results.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub suggestions: Vec<IdentifierSuggestion>,
+    /// The plan the query planner chose for the query that produced these
+    /// results, if it was planned.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query_plan: Option<QueryPlan>,
+    /// Every line hit of `path_kind_results` as a flat list ordered by path
+    /// and line, populated by `compile-results --also-flat` for consumers that
+    /// don't want to walk the grouped hierarchy.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flat: Option<Vec<FlatResultHit>>,
+    /// For each canonical path in the results, the other indexed paths of the
+    /// same file whose results were folded into it per the tree's
+    /// `path_aliases` config.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub path_alternates: BTreeMap<Ustr, Vec<Ustr>>,
+    /// The annotated symbols (ex: deprecated APIs) the results touch, one per
+    /// pretty identifier, for display as banners above the results.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub annotations: Vec<AnnotatedSymbol>,
+}
+
+/// An entry in `FlattenedResultsBundle::annotations`.
+#[derive(Clone, Serialize)]
+pub struct AnnotatedSymbol {
+    pub pretty: Ustr,
+    pub symbol: Ustr,
+    #[serde(flatten)]
+    pub annotation: SymbolAnnotation,
+}
+
+/// A single line hit in `FlattenedResultsBundle::flat`.
+#[derive(Clone, Serialize)]
+pub struct FlatResultHit {
+    pub path: Ustr,
+    pub line: u32,
+    pub kind: PresentationKind,
+    /// The symbol the hit is for; absent for fulltext hits.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub symbol: Option<Ustr>,
+    /// The pretty identifier the hit is grouped under; absent for fulltext
+    /// hits.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pretty: Option<Ustr>,
+}
+
+#[derive(Serialize)]
+pub struct ContextLines {
+    pub before: u32,
+    pub after: u32,
+}
+
+#[derive(Serialize)]
+pub struct IdentifierSuggestion {
+    pub identifier: Ustr,
+    /// Case-insensitive edit distance from what the user typed.
+    pub distance: u32,
+    /// How many symbols have this identifier, as a proxy for popularity.
+    pub symbol_count: u32,
 }
 
 impl FlattenedResultsBundle {
@@ -552,6 +803,9 @@ impl FlattenedResultsBundle {
         after: u32,
     ) {
         self.content_type = "text/html".to_string();
+        if before > 0 || after > 0 {
+            self.context_lines = Some(ContextLines { before, after });
+        }
         for path_kind_group in &mut self.path_kind_results {
             path_kind_group.ingest_html_lines(path_line_contents, before, after);
         }
@@ -561,6 +815,10 @@ impl FlattenedResultsBundle {
 #[derive(Serialize)]
 pub struct FlattenedPathKindGroupResults {
     pub path_kind: Ustr,
+    /// Whether the tree's result demotion config wants this whole group
+    /// collapsed into an expandable group.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub collapsed: bool,
     pub file_names: Vec<Ustr>,
     pub kind_groups: Vec<FlattenedKindGroupResults>,
 }
@@ -595,6 +853,11 @@ pub enum ResultFacetKind {
     SymbolByRelation,
     /// We're faceting based on the path of the definition for the symbol.
     PathByPath,
+    /// We're faceting based on the Bugzilla component of the path's file.
+    PathByComponent,
+    /// We're faceting based on the test manifest annotations of the path's
+    /// test file, like which OSes it's skipped on.
+    PathByTestStatus,
 }
 
 /// A context-sensitive facet for results.  Facets are only created when
@@ -632,6 +895,8 @@ pub enum PresentationKind {
     Uses,
     // We do give textual occurrences a kind because they are path hit-lists.
     TextualOccurrences,
+    /// Textual occurrences of a symbol's pretty name in comments and docs.
+    Mentions,
 }
 
 #[derive(Serialize)]
@@ -640,6 +905,36 @@ pub struct FlattenedKindGroupResults {
     pub pretty: Ustr,
     pub facets: Vec<ResultFacetRoot>,
     pub by_file: Vec<FlattenedResultsByFile>,
+    /// Results in files demoted enough by the tree's result demotion config
+    /// that they should be collapsed into an expandable group after `by_file`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub collapsed_by_file: Vec<FlattenedResultsByFile>,
+    /// How to present `by_file` when it's very large, so that the UI doesn't
+    /// need its own grouping heuristics.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub collapse_hint: Option<CollapseHint>,
+}
+
+/// Server-computed presentation hint for a very large `by_file` list: the first
+/// `expanded_count` files should be shown expanded and the rest collapsed into
+/// groups by top-level directory.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CollapseHint {
+    pub expanded_count: usize,
+    /// Groups of the remaining files, largest first.
+    pub dir_groups: Vec<CollapsedDirGroup>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CollapsedDirGroup {
+    /// The top-level directory, or the empty string for files at the root.
+    pub dir: String,
+    pub file_count: usize,
+    pub line_count: usize,
+    /// Indices into `by_file` of the files in this group, in order.
+    pub file_indices: Vec<usize>,
 }
 
 impl FlattenedKindGroupResults {
@@ -649,7 +944,7 @@ impl FlattenedKindGroupResults {
         before: u32,
         after: u32,
     ) {
-        for by_file in &self.by_file {
+        for by_file in self.by_file.iter().chain(&self.collapsed_by_file) {
             by_file.accumulate_path_line_sets(path_line_sets, before, after);
         }
     }
@@ -660,7 +955,7 @@ impl FlattenedKindGroupResults {
         before: u32,
         after: u32,
     ) {
-        for by_file in &mut self.by_file {
+        for by_file in self.by_file.iter_mut().chain(&mut self.collapsed_by_file) {
             by_file.ingest_html_lines(path_line_contents, before, after);
         }
     }
@@ -683,7 +978,9 @@ impl FlattenedResultsByFile {
         for span in &self.line_spans {
             let range = span.expand_range_in_isolation(before, after);
             for line in range.0..=range.1 {
-                line_set.insert(line);
+                if span.fold_containing(line).is_none() {
+                    line_set.insert(line);
+                }
             }
         }
     }
@@ -711,17 +1008,28 @@ impl FlattenedResultsByFile {
                     }
                 }
 
+                let span = &mut self.line_spans[i_span];
                 let mut lines = vec![];
-                for line in this_start..=this_end {
+                let mut last_line = this_start;
+                let mut line = this_start;
+                while line <= this_end {
+                    // Folded lines are replaced by a single marker.
+                    if let Some((_, fold_end)) = span.fold_containing(line) {
+                        let fold_end = fold_end.min(this_end);
+                        lines.push(fold_marker_html(line, fold_end));
+                        last_line = fold_end;
+                        line = fold_end + 1;
+                        continue;
+                    }
                     if let Some(content) = file_contents.get(&line) {
-                        lines.push(content.as_str());
+                        lines.push(content.clone());
+                        last_line = line;
                     }
+                    line += 1;
                 }
-                // this_end was aspirational; we may have run out of lines,
-                // so use the length.
-                self.line_spans[i_span].line_range =
-                    (this_start, this_start + (lines.len() - 1) as u32);
-                self.line_spans[i_span].contents = lines.join("\n");
+                // this_end was aspirational; we may have run out of lines.
+                span.line_range = (this_start, last_line);
+                span.contents = lines.join("\n");
 
                 highest_line = this_end;
             }
@@ -751,6 +1059,72 @@ pub struct FlattenedLineSpan {
     // of being `Option<String>` so we just maintain that for now.
     pub context: Ustr,
     pub contextsym: Ustr,
+    /// The code coverage of the span as annotated by `annotate-coverage`;
+    /// absent if the results weren't annotated or the file has no coverage
+    /// data.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coverage: Option<LineCoverage>,
+    /// Sorted, non-overlapping inclusive line ranges within `line_range` that
+    /// should initially be presented collapsed, like the body of a function
+    /// whose signature is the key line.  `ingest_html_lines` replaces each
+    /// fold with a marker from `fold_marker_html` that the UI can expand.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub folds: Vec<(u32, u32)>,
+}
+
+/// The HTML that stands in for the folded lines of a `FlattenedLineSpan` in its
+/// ingested HTML contents.  The UI expands it by fetching the lines of the
+/// range.
+pub fn fold_marker_html(start: u32, end: u32) -> String {
+    format!(
+        r#"<div class="source-line-fold" role="row" data-fold-start="{}" data-fold-end="{}"><div role="cell">&#x22EF; {} lines</div></div>"#,
+        start,
+        end,
+        end - start + 1
+    )
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum CoverageStatus {
+    Covered,
+    Uncovered,
+    /// None of the span's lines were instrumented, which is normal for lines
+    /// like declarations and comments.
+    Uninstrumented,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LineCoverage {
+    pub status: CoverageStatus,
+    /// The hit count of the line the status was derived from, absent for
+    /// interpolated and uninstrumented lines.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hits: Option<i64>,
+    /// Whether the status was interpolated from the surrounding lines because
+    /// the line itself wasn't instrumented; see `interpolate_coverage`.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub interpolated: bool,
+}
+
+impl LineCoverage {
+    /// Derive the coverage of the given line from a file's coverage data as
+    /// returned by `fetch_coverage_lines`.  Returns None for uninstrumented
+    /// lines (and lines past the end of the data) so that callers can fall back to other lines of a span.
+    pub fn for_line(coverage_lines: &[i64], lno: u32) -> Option<LineCoverage> {
+        let value = *coverage_lines.get((lno as usize).checked_sub(1)?)?;
+        let (status, hits, interpolated) = match value {
+            0 => (CoverageStatus::Uncovered, Some(0), false),
+            hits if hits > 0 => (CoverageStatus::Covered, Some(hits), false),
+            -2 => (CoverageStatus::Covered, None, true),
+            -3 => (CoverageStatus::Uncovered, None, true),
+            _ => return None,
+        };
+        Some(LineCoverage {
+            status,
+            hits,
+            interpolated,
+        })
+    }
 }
 
 impl FlattenedLineSpan {
@@ -762,6 +1136,28 @@ impl FlattenedLineSpan {
         let end = self.line_range.1 + after;
         (start, end)
     }
+
+    /// Return the fold containing the line, if any.
+    pub fn fold_containing(&self, line: u32) -> Option<(u32, u32)> {
+        self.folds
+            .iter()
+            .find(|(start, end)| *start <= line && line <= *end)
+            .cloned()
+    }
+
+    /// Replace the span's folds with a single fold of its interior, keeping
+    /// everything through `head` lines after the key line and the last `tail`
+    /// lines (ex: the closing brace) visible.  Spans too short to have an
+    /// interior are left unfolded.
+    pub fn fold_interior(&mut self, head: u32, tail: u32) {
+        let fold_start = self.key_line.max(self.line_range.0) + head + 1;
+        let fold_end = self.line_range.1.saturating_sub(tail);
+        self.folds = if fold_start <= fold_end {
+            vec![(fold_start, fold_end)]
+        } else {
+            vec![]
+        };
+    }
 }
 
 /// Rendered graphs and associated metadata.
@@ -809,7 +1205,7 @@ impl JsonRecordsByFile {
 ///
 /// It might make sense to add a type-indicating value or origin of the JSON,
 /// but for now this will only be from the query.
-#[derive(Serialize)]
+#[derive(Clone, Serialize)]
 pub struct JsonValue {
     pub value: Value,
 }
@@ -822,7 +1218,7 @@ pub struct JsonValue {
 /// This does mean that the JSON serialization of this struct will look a little
 /// awkward, but this will make it easier if we start labeling the JsonValue
 /// values with their source/etc.
-#[derive(Serialize)]
+#[derive(Clone, Serialize)]
 pub struct JsonValueList {
     pub values: Vec<JsonValue>,
 }
@@ -844,7 +1240,7 @@ pub struct HtmlExcerpts {
     pub by_file: Vec<HtmlExcerptsByFile>,
 }
 
-#[derive(Serialize)]
+#[derive(Clone, Serialize)]
 pub struct TextFile {
     pub mime_type: String,
     pub contents: String,
@@ -859,6 +1255,47 @@ pub trait PipelineCommand: Debug {
         server: &(dyn AbstractServer + Send + Sync),
         input: PipelineValues,
     ) -> Result<PipelineValues>;
+
+    /// For `--estimate`, report the work executing the command on the given
+    /// input would do without actually doing it.  Commands that can't estimate
+    /// their work return None, and get executed only if they're `is_cheap` so
+    /// that the commands after them can be estimated from their actual output.
+    async fn estimate(
+        &self,
+        _server: &(dyn AbstractServer + Send + Sync),
+        _input: &PipelineValues,
+    ) -> Result<Option<StageEstimate>> {
+        Ok(None)
+    }
+
+    /// For `--estimate`, is the command cheap enough to run to get the input
+    /// of the commands after it?  The estimate stops at the first command that
+    /// is neither cheap nor able to estimate its work.
+    fn is_cheap(&self) -> bool {
+        false
+    }
+}
+
+/// A command's estimate of the work it would do, as reported by
+/// `PipelineCommand::estimate`.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StageEstimate {
+    /// The number of symbols the command would look up, expand, or traverse
+    /// from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub symbols: Option<u64>,
+    /// The number of files whose contents the command would fetch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub files: Option<u64>,
+    /// The number of results the command is expected to produce based on the
+    /// counts that are available without running it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_results: Option<u64>,
+    /// Human-readable descriptions of the limits the command is expected to
+    /// run into.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub limits_hit: Vec<String>,
 }
 
 /// A command that takes multiple inputs and produces a single output.
@@ -877,6 +1314,14 @@ pub struct ServerPipeline {
     pub server_kind: String,
     pub server: Box<dyn AbstractServer + Send + Sync>,
     pub commands: Vec<Box<dyn PipelineCommand + Send + Sync>>,
+    /// Report estimates of the work of the commands rather than running all
+    /// of them; see `run_estimate`.
+    pub estimate: bool,
+    /// Run the pipeline in strict mode; see `PipelineWarnings::with_strict`.
+    pub strict: bool,
+    /// How the pipeline's commands report their progress; see
+    /// `ProgressTracker`.
+    pub progress: ProgressFormat,
 }
 
 /// A linear pipeline sequence that potentially runs in parallel with other
@@ -890,7 +1335,60 @@ pub struct NamedPipeline {
     pub commands: Vec<Box<dyn PipelineCommand + Send + Sync>>,
 }
 
+/// The maximum length of the argument summary we put in step spans.
+const TRACE_ARGS_SUMMARY_LIMIT: usize = 240;
+
+/// Split a command's `Debug` representation into its name and a (truncated)
+/// summary of its arguments for tracing purposes.  Commands are all structs of
+/// the form `FooCommand { args: Foo { ... } }`.
+fn describe_command<T: Debug + ?Sized>(cmd: &T) -> (String, String) {
+    let debug_str = format!("{:?}", cmd);
+    let (name, rest) = debug_str
+        .split_once(' ')
+        .unwrap_or((debug_str.as_str(), ""));
+    let name = name.strip_suffix("Command").unwrap_or(name).to_string();
+    let mut args: String = rest.chars().take(TRACE_ARGS_SUMMARY_LIMIT).collect();
+    if args.len() < rest.len() {
+        args.push('…');
+    }
+    (name, args)
+}
+
+/// Record the size of a step's output on its span, which was created with an
+/// empty `output_size` field.
+fn record_output_size(span: &Span, values: &PipelineValues) {
+    span.record("output_size", count_results(values));
+}
+
+/// Apply the server's output size guard to a step's output and record its
+/// result count and serialized size on its span, which was created with empty
+/// `output_size` and `output_bytes` fields.
+fn guard_step_output(
+    server: &(dyn AbstractServer + Send + Sync),
+    span: &Span,
+    command: &str,
+    values: PipelineValues,
+) -> Result<PipelineValues> {
+    let (values, bytes) = enforce_size_guard(server, command, values)?;
+    record_output_size(span, &values);
+    if let Some(bytes) = bytes {
+        span.record("output_bytes", bytes);
+    }
+    Ok(values)
+}
+
 impl NamedPipeline {
+    /// A key that is the same for any two pipelines that will produce the same
+    /// output because they consume the same input and run the same commands
+    /// with the same arguments.
+    ///
+    /// Only whole pipelines are compared, so pipelines that merely share a
+    /// prefix of their commands (ex: the same `search-identifiers` followed by
+    /// different filters) get different keys and are each run in full.
+    pub fn plan_key(&self) -> String {
+        format!("{:?} {:?}", self.input_name, self.commands)
+    }
+
     pub async fn run(
         self,
         server: Box<dyn AbstractServer + Send + Sync>,
@@ -898,13 +1396,23 @@ impl NamedPipeline {
         traced: bool,
     ) -> Result<PipelineValues> {
         for cmd in &self.commands {
-            let span = trace_span!("run_named_pipeline_step", cmd = ?cmd);
+            let (command, args) = describe_command(cmd);
+            let span = trace_span!(
+                "run_named_pipeline_step",
+                command = %command,
+                args = %args,
+                input_size = count_results(&cur_values),
+                output_size = field::Empty,
+                output_bytes = field::Empty,
+            );
 
             match cmd
                 .execute(server.as_ref(), cur_values)
                 .instrument(span.clone())
                 .await
-            {
+                .and_then(|next_values| {
+                    guard_step_output(server.as_ref(), &span, &command, next_values)
+                }) {
                 Ok(next_values) => {
                     cur_values = next_values;
                 }
@@ -942,13 +1450,25 @@ impl JunctionInvocation {
         input_values: Vec<(String, PipelineValues)>,
         traced: bool,
     ) -> Result<PipelineValues> {
-        let span = trace_span!("run junction step", junction = ?self.command);
+        let (command, args) = describe_command(&self.command);
+        let span = trace_span!(
+            "run junction step",
+            command = %command,
+            args = %args,
+            input_size = input_values
+                .iter()
+                .map(|(_, values)| count_results(values))
+                .sum::<usize>(),
+            output_size = field::Empty,
+            output_bytes = field::Empty,
+        );
 
         let result = match self
             .command
             .execute(server.as_ref(), input_values)
             .instrument(span.clone())
             .await
+            .and_then(|res| guard_step_output(server.as_ref(), &span, &command, res))
         {
             Ok(res) => res,
             Err(err) => {
@@ -979,19 +1499,52 @@ pub struct ParallelPipelines {
 pub struct ServerPipelineGraph {
     pub server: Box<dyn AbstractServer + Send + Sync>,
     pub pipelines: Vec<ParallelPipelines>,
+    /// The query plan the graph was built from, which gets attached to a
+    /// `FlattenedResultsBundle` result.
+    pub plan: Option<QueryPlan>,
 }
 
 impl ServerPipeline {
     pub async fn run(&self, traced: bool) -> Result<PipelineValues> {
+        let steps = self.progress.scope(self.run_steps(traced));
+        if self.strict {
+            current_pipeline_warnings()
+                .with_strict(true)
+                .scope(steps)
+                .await
+        } else {
+            steps.await
+        }
+    }
+
+    #[instrument(level = "trace", skip_all, fields(steps = self.commands.len()))]
+    async fn run_steps(&self, traced: bool) -> Result<PipelineValues> {
+        if self.estimate {
+            return self.run_estimate().await;
+        }
+
         let mut cur_values = PipelineValues::Void;
+        let mut progress = ProgressTracker::new("pipeline", Some(self.commands.len() as u64));
 
         for cmd in &self.commands {
-            let span = trace_span!("run_pipeline_step", cmd = ?cmd);
+            let (command, args) = describe_command(cmd);
+            let span = trace_span!(
+                "run_pipeline_step",
+                command = %command,
+                args = %args,
+                input_size = count_results(&cur_values),
+                output_size = field::Empty,
+                output_bytes = field::Empty,
+            );
 
             match cmd
                 .execute(self.server.as_ref(), cur_values)
                 .instrument(span.clone())
                 .await
+                .and_then(|next_values| {
+                    guard_step_output(self.server.as_ref(), &span, &command, next_values)
+                })
+                .and_then(|next_values| check_strict_mode().map(|()| next_values))
             {
                 Ok(next_values) => {
                     cur_values = next_values;
@@ -1007,85 +1560,496 @@ impl ServerPipeline {
                 let value_str = to_string_pretty(&cur_values).unwrap();
                 trace!(output_json = %value_str);
             }
+            progress.advance(1);
         }
+        progress.finish();
 
         Ok(cur_values)
     }
+
+    /// Preview the pipeline: commands that can estimate their work report the
+    /// estimate instead of running, and the commands after them are reported
+    /// as blocked since there's no input to give them.  Cheap commands run
+    /// normally, and any other command stops the preview without running.
+    /// Produces a JsonValue of the form `{ stages: [{ command, args, status,
+    /// inputSize, ... }] }` where status is one of "executed" (with
+    /// `outputSize`), "estimated" (with `estimate`), "unestimable", or
+    /// "blocked" (with `blockedBy`).
+    pub async fn run_estimate(&self) -> Result<PipelineValues> {
+        let mut cur_values = PipelineValues::Void;
+        let mut stages = vec![];
+        let mut blocked_by: Option<String> = None;
+
+        for cmd in &self.commands {
+            let (command, args) = describe_command(cmd);
+            if let Some(blocker) = &blocked_by {
+                stages.push(json!({
+                    "command": command,
+                    "args": args,
+                    "status": "blocked",
+                    "blockedBy": blocker,
+                }));
+                continue;
+            }
+
+            let input_size = count_results(&cur_values);
+            match cmd.estimate(self.server.as_ref(), &cur_values).await? {
+                Some(estimate) => {
+                    stages.push(json!({
+                        "command": command,
+                        "args": args,
+                        "status": "estimated",
+                        "inputSize": input_size,
+                        "estimate": to_value(estimate)?,
+                    }));
+                    blocked_by = Some(command);
+                }
+                None if cmd.is_cheap() => {
+                    cur_values = cmd.execute(self.server.as_ref(), cur_values).await?;
+                    stages.push(json!({
+                        "command": command,
+                        "args": args,
+                        "status": "executed",
+                        "inputSize": input_size,
+                        "outputSize": count_results(&cur_values),
+                    }));
+                }
+                None => {
+                    stages.push(json!({
+                        "command": command,
+                        "args": args,
+                        "status": "unestimable",
+                        "inputSize": input_size,
+                    }));
+                    blocked_by = Some(command);
+                }
+            }
+        }
+
+        Ok(PipelineValues::JsonValue(JsonValue {
+            value: json!({ "stages": stages }),
+        }))
+    }
+}
+
+/// A node of a `ServerPipelineGraph` flattened for dependency scheduling.
+enum GraphNode {
+    Pipeline(NamedPipeline),
+    /// A pipeline identical to the pipeline node at the given index whose
+    /// output it can share instead of running.
+    Duplicate(usize, NamedPipeline),
+    Junction(JunctionInvocation),
+}
+
+/// The scheduling-relevant details of a `GraphNode`.
+struct GraphNodeShape {
+    /// Pipelines in the Nth `ParallelPipelines` are in stage 2N and its
+    /// junctions are in stage 2N+1.  Nodes can only consume the outputs of
+    /// nodes in earlier stages.
+    stage: usize,
+    output_name: String,
+    /// The names of the values the node consumes.  Duplicates don't consume
+    /// anything.
+    input_names: Vec<String>,
+}
+
+/// Determine which node's output each input of each node will receive,
+/// preserving the semantics of running the stages one after another: inputs
+/// come from the most recent node in an earlier stage that produced a value
+/// with that name, and values are moved into their first consumer (in
+/// declaration order) so that any other consumers of the same value get None
+/// and will receive `PipelineValues::Void`.
+fn resolve_input_producers(shapes: &[GraphNodeShape]) -> Vec<Vec<Option<usize>>> {
+    let mut latest_producers: HashMap<&str, usize> = HashMap::new();
+    let mut claimed = HashSet::new();
+    let mut resolved = vec![];
+    // Producers are only published to `latest_producers` once we move on to a
+    // later stage.
+    let mut pending_start = 0;
+    for shape in shapes {
+        while shapes[pending_start].stage < shape.stage {
+            latest_producers.insert(shapes[pending_start].output_name.as_str(), pending_start);
+            pending_start += 1;
+        }
+        resolved.push(
+            shape
+                .input_names
+                .iter()
+                .map(|name| {
+                    latest_producers
+                        .get(name.as_str())
+                        .copied()
+                        .filter(|producer| claimed.insert(*producer))
+                })
+                .collect(),
+        );
+    }
+    resolved
 }
 
 impl ServerPipelineGraph {
     pub async fn run(self, traced: bool) -> Result<PipelineValues> {
-        let mut named_values: BTreeMap<String, PipelineValues> = BTreeMap::new();
+        self.run_with_partials(traced, None).await
+    }
 
-        for pipeline in self.pipelines {
-            // ## kick off all the named pipelines in parallel
-            let mut pipeline_tasks = vec![];
+    /// Like `run`, but if `partials` is provided, the JSON serialization of
+    /// every intermediate named value is sent to it as soon as it is computed.
+    /// This is intended for live query sessions that want to show the user
+    /// something (ex: file matches) before slower parts of the graph complete.
+    /// The final "result" value is only returned, not sent.
+    ///
+    /// Rather than running each `ParallelPipelines` node's pipelines and then
+    /// its junctions in lockstep, every pipeline and junction is started as
+    /// soon as the values it consumes are available, so independent parts of
+    /// the graph can overlap.  The values each node receives are the same as
+    /// they would be if the stages were run one after another; see
+    /// `resolve_input_producers`.
+    #[instrument(level = "trace", skip_all, fields(phases = self.pipelines.len()))]
+    pub async fn run_with_partials(
+        self,
+        traced: bool,
+        partials: Option<UnboundedSender<(String, Value)>>,
+    ) -> Result<PipelineValues> {
+        // ## flatten the graph
+        //
+        // Plans can end up with identical pipelines in multiple branches
+        // (ex: after term expansion), in which case we only run the first
+        // and share its output with the duplicates.  Pipelines that only share
+        // a common prefix of commands are not deduplicated; see `plan_key`.
+        let plan = self.plan;
+        let mut nodes = vec![];
+        let mut shapes = vec![];
+        for (phase, pipeline) in self.pipelines.into_iter().enumerate() {
+            let mut plan_outputs: HashMap<String, usize> = HashMap::new();
             for named_pipeline in pipeline.pipelines {
-                let output = named_pipeline.output_name.clone();
-                let input = match &named_pipeline.input_name {
-                    Some(name) => {
-                        // TODO: There could be cases like for compile-results
-                        // where we would want a second pipeline to be able to
-                        // consume the same input.
-                        match named_values.remove(name) {
-                            Some(val) => val,
-                            None => PipelineValues::Void,
-                        }
+                let idx = nodes.len();
+                let output_name = named_pipeline.output_name.clone();
+                match plan_outputs.entry(named_pipeline.plan_key()) {
+                    Entry::Occupied(primary) => {
+                        trace!(output = %output_name, shared_with = %shapes[*primary.get()].output_name, "deduplicated pipeline");
+                        shapes.push(GraphNodeShape {
+                            stage: phase * 2,
+                            output_name,
+                            input_names: vec![],
+                        });
+                        nodes.push(Some(GraphNode::Duplicate(*primary.get(), named_pipeline)));
+                    }
+                    Entry::Vacant(vacant) => {
+                        vacant.insert(idx);
+                        shapes.push(GraphNodeShape {
+                            stage: phase * 2,
+                            output_name,
+                            input_names: named_pipeline.input_name.iter().cloned().collect(),
+                        });
+                        nodes.push(Some(GraphNode::Pipeline(named_pipeline)));
                     }
+                }
+            }
+            for junction in pipeline.junctions {
+                shapes.push(GraphNodeShape {
+                    stage: phase * 2 + 1,
+                    output_name: junction.output_name.clone(),
+                    input_names: junction.input_names.clone(),
+                });
+                nodes.push(Some(GraphNode::Junction(junction)));
+            }
+        }
+
+        // ## build the dependency graph
+        let input_producers = resolve_input_producers(&shapes);
+        let mut dependents: Vec<Vec<usize>> = vec![vec![]; nodes.len()];
+        let mut unmet_deps: Vec<usize> = vec![0; nodes.len()];
+        for (idx, producers) in input_producers.iter().enumerate() {
+            for producer in producers.iter().flatten() {
+                dependents[*producer].push(idx);
+                unmet_deps[idx] += 1;
+            }
+        }
+        let mut duplicates: Vec<Vec<usize>> = vec![vec![]; nodes.len()];
+        for (idx, node) in nodes.iter().enumerate() {
+            if let Some(GraphNode::Duplicate(primary, _)) = node {
+                duplicates[*primary].push(idx);
+            }
+        }
+
+        // ## run nodes as their dependencies complete
+        //
+        // Each node's task reports its completion over the channel.  The
+        // node itself runs in a nested task so that a panic is reported as a
+        // JoinError rather than leaving us waiting forever.
+        let (done_tx, mut done_rx) = unbounded_channel();
+        let mut outputs: Vec<Option<PipelineValues>> = (0..nodes.len()).map(|_| None).collect();
+        let mut running = 0;
+        let server = self.server;
+        // Task-locals don't follow spawned tasks, so we need to propagate the
+        // warnings and progress format ourselves.
+        let warnings = current_pipeline_warnings();
+        let progress = current_progress_format();
+        let launch = |idx: usize, node: GraphNode, outputs: &mut [Option<PipelineValues>]| {
+            let mut take_input =
+                |input_idx: usize| match input_producers[idx].get(input_idx).copied().flatten() {
+                    Some(producer) => outputs[producer].take().unwrap_or(PipelineValues::Void),
                     None => PipelineValues::Void,
                 };
-                let span = trace_span!("pipeline_task", input_name=?named_pipeline.input_name, output_name=?named_pipeline.output_name).or_current();
-                pipeline_tasks.push((
-                    output,
+            let task = match node {
+                GraphNode::Pipeline(named_pipeline) | GraphNode::Duplicate(_, named_pipeline) => {
+                    // Duplicates only get here when their primary's output
+                    // couldn't be cloned, in which case they don't have an
+                    // input producer and get a Void input like any other
+                    // second consumer of the same input.
+                    let input = take_input(0);
+                    let span = trace_span!("pipeline_task", input_name=?named_pipeline.input_name, output_name=?named_pipeline.output_name).or_current();
                     tokio::spawn(
-                        named_pipeline
-                            .run(self.server.clonify(), input, traced)
-                            .instrument(span),
-                    ),
-                ));
-            }
+                        warnings.clone().scope(
+                            progress.scope(
+                                named_pipeline
+                                    .run(server.clonify(), input, traced)
+                                    .instrument(span),
+                            ),
+                        ),
+                    )
+                }
+                GraphNode::Junction(junction) => {
+                    let input_values = junction
+                        .input_names
+                        .iter()
+                        .enumerate()
+                        .map(|(input_idx, name)| (name.clone(), take_input(input_idx)))
+                        .collect();
+                    let span = trace_span!("junction_task", input_names=?junction.input_names, output_name=?junction.output_name).or_current();
+                    tokio::spawn(
+                        warnings.clone().scope(
+                            progress.scope(
+                                junction
+                                    .run(server.clonify(), input_values, traced)
+                                    .instrument(span),
+                            ),
+                        ),
+                    )
+                }
+            };
+            let done_tx = done_tx.clone();
+            tokio::spawn(async move {
+                let _ = done_tx.send((idx, task.await));
+            });
+        };
 
-            // ## join the pipelines in sequence
-            for (output, handle) in pipeline_tasks {
-                let value = handle.await??;
-                named_values.insert(output, value);
+        for (idx, node) in nodes.iter_mut().enumerate() {
+            if unmet_deps[idx] == 0 && !matches!(node, Some(GraphNode::Duplicate(..))) {
+                launch(idx, node.take().unwrap(), &mut outputs);
+                running += 1;
             }
+        }
 
-            // ## kick off junctions in parallel
-            let mut junction_tasks = vec![];
-            for junction in pipeline.junctions {
-                let output = junction.output_name.clone();
-                let mut input_values = vec![];
-                for name in &junction.input_names {
-                    input_values.push((
-                        name.clone(),
-                        match named_values.remove(name) {
-                            Some(val) => val,
-                            None => PipelineValues::Void,
-                        },
-                    ));
+        let mut completed = VecDeque::new();
+        while running > 0 {
+            let (idx, result) = match done_rx.recv().await {
+                Some(done) => done,
+                None => break,
+            };
+            running -= 1;
+            completed.push_back((idx, result??));
+
+            while let Some((idx, value)) = completed.pop_front() {
+                if let Some(tx) = &partials {
+                    if shapes[idx].output_name != "result" {
+                        // It's fine if the receiver went away.
+                        let _ = tx.send((shapes[idx].output_name.clone(), to_value(&value)?));
+                    }
                 }
 
-                let span = trace_span!("junction_task", input_names=?junction.input_names, output_name=?junction.output_name).or_current();
-                junction_tasks.push((
-                    output,
-                    tokio::spawn(
-                        junction
-                            .run(self.server.clonify(), input_values, traced)
-                            .instrument(span),
-                    ),
-                ));
-            }
+                // Share the output with any duplicates before it can be
+                // consumed.
+                for &dup in &duplicates[idx] {
+                    let node = nodes[dup].take().unwrap();
+                    match value.try_clone() {
+                        Some(shared) => completed.push_back((dup, shared)),
+                        None => {
+                            launch(dup, node, &mut outputs);
+                            running += 1;
+                        }
+                    }
+                }
 
-            for (output, handle) in junction_tasks {
-                let value = handle.await??;
-                named_values.insert(output, value);
+                outputs[idx] = Some(value);
+                for &dependent in &dependents[idx] {
+                    unmet_deps[dependent] -= 1;
+                    if unmet_deps[dependent] == 0 {
+                        let node = nodes[dependent].take().unwrap();
+                        launch(dependent, node, &mut outputs);
+                        running += 1;
+                    }
+                }
             }
         }
 
-        Ok(match named_values.remove("result") {
-            Some(val) => val,
-            None => PipelineValues::Void,
-        })
+        // The result is the last value named "result" if nothing consumed it.
+        let mut result = shapes
+            .iter()
+            .rposition(|shape| shape.output_name == "result")
+            .and_then(|idx| outputs[idx].take())
+            .unwrap_or(PipelineValues::Void);
+        if let (Some(plan), PipelineValues::FlattenedResultsBundle(frb)) = (plan, &mut result) {
+            frb.query_plan = Some(plan);
+        }
+        Ok(result)
     }
 }
+
+#[test]
+fn test_resolve_input_producers() {
+    let shape = |stage: usize, output_name: &str, input_names: &[&str]| GraphNodeShape {
+        stage,
+        output_name: output_name.to_string(),
+        input_names: input_names.iter().map(|s| s.to_string()).collect(),
+    };
+    let shapes = vec![
+        shape(0, "files", &[]),
+        shape(0, "crossref", &[]),
+        // Pipelines can't consume the outputs of pipelines in the same stage.
+        shape(0, "expanded", &["crossref"]),
+        shape(1, "compiled", &["files", "crossref"]),
+        shape(1, "graph", &["crossref", "expanded"]),
+        shape(2, "result", &["compiled"]),
+        // The first consumer already took "compiled".
+        shape(2, "other", &["compiled", "graph"]),
+    ];
+    assert_eq!(
+        resolve_input_producers(&shapes),
+        vec![
+            vec![],
+            vec![],
+            vec![None],
+            vec![Some(0), Some(1)],
+            vec![None, Some(2)],
+            vec![Some(3)],
+            vec![None, Some(4)],
+        ]
+    );
+}
+
+#[test]
+fn test_ingest_html_lines_folds() {
+    let file = ustr::ustr("a.rs");
+    let mut span = FlattenedLineSpan {
+        key_line: 2,
+        line_range: (1, 8),
+        contents: String::new(),
+        context: ustr::ustr(""),
+        contextsym: ustr::ustr(""),
+        coverage: None,
+        folds: vec![],
+    };
+    span.fold_interior(1, 1);
+    assert_eq!(span.folds, vec![(4, 7)]);
+
+    let mut by_file = FlattenedResultsByFile {
+        file,
+        line_spans: vec![span],
+    };
+    let mut path_line_sets = UstrMap::default();
+    by_file.accumulate_path_line_sets(&mut path_line_sets, 0, 0);
+    let mut lines: Vec<u32> = path_line_sets[&file].iter().cloned().collect();
+    lines.sort();
+    assert_eq!(lines, vec![1, 2, 3, 8]);
+
+    let mut path_line_contents: UstrMap<HashMap<u32, String>> = UstrMap::default();
+    path_line_contents.insert(
+        file,
+        lines.iter().map(|l| (*l, format!("L{}", l))).collect(),
+    );
+    by_file.ingest_html_lines(&path_line_contents, 0, 0);
+    let span = &by_file.line_spans[0];
+    assert_eq!(span.line_range, (1, 8));
+    assert_eq!(
+        span.contents,
+        ["L1", "L2", "L3", &fold_marker_html(4, 7), "L8"].join("\n")
+    );
+}
+
+#[test]
+fn test_plan_key() {
+    use super::{
+        builder::{fab_command_from_opts, CommandSafetyLevel},
+        parser::ToolOpts,
+    };
+    use clap::Parser;
+
+    let pipeline = |input_name: Option<&str>, output_name: &str, cmds: &[&str]| NamedPipeline {
+        input_name: input_name.map(|s| s.to_string()),
+        output_name: output_name.to_string(),
+        commands: cmds
+            .iter()
+            .map(|cmd| {
+                let args = shell_words::split(&format!("searchfox-tool {}", cmd)).unwrap();
+                fab_command_from_opts(
+                    ToolOpts::try_parse_from(args).unwrap(),
+                    CommandSafetyLevel::WebSafety,
+                )
+                .unwrap()
+            })
+            .collect(),
+    };
+
+    let lookup = pipeline(None, "a", &["search-identifiers Foo", "crossref-lookup"]);
+    // The output name doesn't matter.
+    assert_eq!(
+        lookup.plan_key(),
+        pipeline(None, "b", &["search-identifiers Foo", "crossref-lookup"]).plan_key()
+    );
+    // The input, the commands, and their arguments do.
+    assert_ne!(
+        lookup.plan_key(),
+        pipeline(
+            Some("x"),
+            "a",
+            &["search-identifiers Foo", "crossref-lookup"]
+        )
+        .plan_key()
+    );
+    assert_ne!(
+        lookup.plan_key(),
+        pipeline(None, "a", &["search-identifiers Bar", "crossref-lookup"]).plan_key()
+    );
+    // Shared prefixes aren't deduplicated.
+    assert_ne!(
+        lookup.plan_key(),
+        pipeline(None, "a", &["search-identifiers Foo"]).plan_key()
+    );
+}
+
+#[test]
+fn test_run_estimate() {
+    use super::builder::build_pipeline;
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let stages = |arg_str: &str| -> Vec<(String, String)> {
+        let (pipeline, _) = build_pipeline("searchfox-tool", arg_str).unwrap();
+        let value = match runtime.block_on(pipeline.run_estimate()).unwrap() {
+            PipelineValues::JsonValue(jv) => jv.value,
+            _ => panic!("run_estimate should produce a JsonValue"),
+        };
+        value["stages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|stage| {
+                (
+                    stage["command"].as_str().unwrap().to_string(),
+                    stage["status"].as_str().unwrap().to_string(),
+                )
+            })
+            .collect()
+    };
+    let stage = |command: &str, status: &str| (command.to_string(), status.to_string());
+
+    // A full-text search can't estimate its work and isn't cheap, so it stops
+    // the preview rather than hitting the server.
+    assert_eq!(
+        stages("--estimate --server=https://searchfox.org/ search-text foo | crossref-expand"),
+        vec![
+            stage("SearchText", "unestimable"),
+            stage("CrossrefExpand", "blocked"),
+        ]
+    );
+}
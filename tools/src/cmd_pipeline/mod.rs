@@ -1,33 +1,90 @@
 extern crate clap;
 
+pub mod admission;
+pub mod aggregates;
 pub mod builder;
+pub mod describe;
+pub mod diagnostics;
+pub mod history;
 pub mod interface;
+pub mod ndjson_output;
 pub mod parser;
+pub mod progress;
+pub mod size_guard;
 pub mod symbol_graph;
+pub mod top_k;
+pub mod trace_output;
 pub mod transforms;
+pub mod warnings;
+pub mod worker_pool;
 
+mod cmd_annotate_coverage;
+mod cmd_annotate_repos;
+mod cmd_args_of_calls;
 mod cmd_augment_results;
 mod cmd_batch_render;
+mod cmd_blame_lines;
+mod cmd_caller_rollup;
 mod cmd_cat_html;
+mod cmd_changed_symbols;
 mod cmd_compile_results;
 mod cmd_crossref_expand;
 mod cmd_crossref_lookup;
+mod cmd_dead_code;
+mod cmd_define_best;
+mod cmd_defs_in_text_matches;
+mod cmd_diff_against;
+mod cmd_diff_symbols;
+mod cmd_dir_overview;
+mod cmd_enum_usage;
+mod cmd_excerpt;
+mod cmd_expand_node;
+mod cmd_extract_fixture;
+mod cmd_fetch_file;
 mod cmd_filter_analysis;
+mod cmd_filter_crossref;
+mod cmd_flatten_text_matches;
 mod cmd_format_symbols;
 mod cmd_fuse_crossrefs;
 mod cmd_graph;
+mod cmd_graph_metrics;
+mod cmd_group_by_def_file;
+mod cmd_history_grep;
+mod cmd_index_stats;
+mod cmd_interleave;
 mod cmd_jumpref_lookup;
+mod cmd_l10n_refs;
+mod cmd_legacy_search;
 mod cmd_merge_analyses;
+mod cmd_module_api;
+mod cmd_only_new_since;
+mod cmd_outline;
+mod cmd_precompute_aggregates;
+mod cmd_preprocessor_filter;
 mod cmd_prod_filter;
 mod cmd_query;
+mod cmd_redact;
 mod cmd_render;
+mod cmd_render_markdown;
+mod cmd_render_quickfix;
+mod cmd_rerank;
 mod cmd_search;
 mod cmd_search_files;
 mod cmd_search_identifiers;
+mod cmd_search_mentions;
 mod cmd_search_text;
 mod cmd_show_html;
+mod cmd_similar_functions;
+mod cmd_slot_jump;
+mod cmd_suggest_identifiers;
+mod cmd_symbol_payloads;
+mod cmd_symbols_at_point;
+mod cmd_text_diff_hits;
 mod cmd_tokenize_source;
+mod cmd_top_symbols;
 mod cmd_traverse;
+mod cmd_unsafe_audit;
+mod cmd_validate_analysis;
 mod cmd_webtest;
 
 pub use builder::build_pipeline;
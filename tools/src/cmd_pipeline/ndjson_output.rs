@@ -0,0 +1,86 @@
+use std::io::{Result, Write};
+
+use serde_json::{json, to_writer};
+
+use super::interface::JsonRecords;
+
+/// How many records we write between sync markers.
+pub const NDJSON_SYNC_INTERVAL: usize = 1000;
+
+/// Write `JsonRecords` as newline-delimited JSON with one record per line for
+/// `--output-format=ndjson-chunked`, which is intended for tree-scale dumps
+/// that downstream consumers want to stream or split up.  Interleaved with the
+/// records are marker lines, whose keys start with "@" so they can't be
+/// confused with analysis records:
+/// - `{"@file": path, "records": N}` precedes the N records of each file.
+/// - `{"@sync": seq, "file": path, "records": total}` is written every
+///   `sync_interval` records so a consumer starting mid-stream (ex: after
+///   splitting a compressed dump at a frame boundary) knows which file the
+///   following records belong to and how many records preceded them.
+/// - `{"@end": true, "files": F, "records": R}` ends the stream so truncation
+///   can be detected.
+pub fn write_ndjson_chunked<W: Write>(
+    writer: &mut W,
+    records: &JsonRecords,
+    sync_interval: usize,
+) -> Result<()> {
+    let mut total = 0;
+    let mut seq = 0;
+    for file_records in &records.by_file {
+        to_writer(
+            &mut *writer,
+            &json!({ "@file": file_records.file, "records": file_records.records.len() }),
+        )?;
+        writer.write_all(b"\n")?;
+        for record in &file_records.records {
+            if total > 0 && total % sync_interval == 0 {
+                seq += 1;
+                to_writer(
+                    &mut *writer,
+                    &json!({ "@sync": seq, "file": file_records.file, "records": total }),
+                )?;
+                writer.write_all(b"\n")?;
+            }
+            to_writer(&mut *writer, record)?;
+            writer.write_all(b"\n")?;
+            total += 1;
+        }
+    }
+    to_writer(
+        &mut *writer,
+        &json!({ "@end": true, "files": records.by_file.len(), "records": total }),
+    )?;
+    writer.write_all(b"\n")?;
+    writer.flush()
+}
+
+#[test]
+fn test_write_ndjson_chunked() {
+    use super::interface::JsonRecordsByFile;
+
+    let records = JsonRecords {
+        by_file: vec![
+            JsonRecordsByFile {
+                file: "a.cpp".to_string(),
+                records: vec![json!({"loc": "1:0-1"}), json!({"loc": "2:0-1"})],
+            },
+            JsonRecordsByFile {
+                file: "b.cpp".to_string(),
+                records: vec![json!({"loc": "3:0-1"})],
+            },
+        ],
+    };
+    let mut out = vec![];
+    write_ndjson_chunked(&mut out, &records, 2).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        r#"{"@file":"a.cpp","records":2}
+{"loc":"1:0-1"}
+{"loc":"2:0-1"}
+{"@file":"b.cpp","records":1}
+{"@sync":1,"file":"b.cpp","records":2}
+{"loc":"3:0-1"}
+{"@end":true,"files":2,"records":3}
+"#
+    );
+}
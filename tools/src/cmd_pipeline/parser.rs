@@ -1,27 +1,74 @@
 use clap::{Parser, Subcommand, ValueEnum};
 
+use super::progress::ProgressFormat;
+
+use super::cmd_annotate_coverage::AnnotateCoverage;
+use super::cmd_annotate_repos::AnnotateRepos;
+use super::cmd_args_of_calls::ArgsOfCalls;
 use super::cmd_augment_results::AugmentResults;
-use super::cmd_batch_render::BatchRender;
+use super::cmd_batch_render::{BatchRender, BatchRenderJunction};
+use super::cmd_blame_lines::BlameLines;
+use super::cmd_caller_rollup::CallerRollup;
 use super::cmd_cat_html::CatHtml;
+use super::cmd_changed_symbols::ChangedSymbols;
 use super::cmd_compile_results::CompileResults;
 use super::cmd_crossref_expand::CrossrefExpand;
 use super::cmd_crossref_lookup::CrossrefLookup;
+use super::cmd_dead_code::DeadCode;
+use super::cmd_define_best::DefineBest;
+use super::cmd_defs_in_text_matches::DefsInTextMatches;
+use super::cmd_diff_against::DiffAgainst;
+use super::cmd_diff_symbols::DiffSymbols;
+use super::cmd_dir_overview::DirOverview;
+use super::cmd_enum_usage::EnumUsage;
+use super::cmd_excerpt::Excerpt;
+use super::cmd_expand_node::ExpandNode;
+use super::cmd_extract_fixture::ExtractFixture;
+use super::cmd_fetch_file::FetchFile;
 use super::cmd_filter_analysis::FilterAnalysis;
+use super::cmd_filter_crossref::FilterCrossref;
+use super::cmd_flatten_text_matches::FlattenTextMatches;
 use super::cmd_format_symbols::FormatSymbols;
 use super::cmd_fuse_crossrefs::FuseCrossrefs;
 use super::cmd_graph::Graph;
+use super::cmd_graph_metrics::GraphMetrics;
+use super::cmd_group_by_def_file::GroupByDefFile;
+use super::cmd_history_grep::HistoryGrep;
+use super::cmd_index_stats::IndexStats;
+use super::cmd_interleave::Interleave;
 use super::cmd_jumpref_lookup::JumprefLookup;
+use super::cmd_l10n_refs::L10nRefs;
+use super::cmd_legacy_search::LegacySearch;
 use super::cmd_merge_analyses::MergeAnalyses;
+use super::cmd_module_api::ModuleApi;
+use super::cmd_only_new_since::OnlyNewSince;
+use super::cmd_outline::Outline;
+use super::cmd_precompute_aggregates::PrecomputeAggregates;
+use super::cmd_preprocessor_filter::PreprocessorFilter;
 use super::cmd_prod_filter::ProductionFilter;
 use super::cmd_query::Query;
+use super::cmd_redact::Redact;
 use super::cmd_render::Render;
+use super::cmd_render_markdown::RenderMarkdown;
+use super::cmd_render_quickfix::RenderQuickfix;
+use super::cmd_rerank::Rerank;
 use super::cmd_search::Search;
 use super::cmd_search_files::SearchFiles;
 use super::cmd_search_identifiers::SearchIdentifiers;
+use super::cmd_search_mentions::SearchMentions;
 use super::cmd_search_text::SearchText;
 use super::cmd_show_html::ShowHtml;
+use super::cmd_similar_functions::SimilarFunctions;
+use super::cmd_slot_jump::SlotJump;
+use super::cmd_suggest_identifiers::SuggestIdentifiers;
+use super::cmd_symbol_payloads::SymbolPayloads;
+use super::cmd_symbols_at_point::SymbolsAtPoint;
+use super::cmd_text_diff_hits::TextDiffHits;
 use super::cmd_tokenize_source::TokenizeSource;
+use super::cmd_top_symbols::TopSymbols;
 use super::cmd_traverse::Traverse;
+use super::cmd_unsafe_audit::UnsafeAudit;
+use super::cmd_validate_analysis::ValidateAnalysis;
 use super::cmd_webtest::Webtest;
 
 #[derive(Clone, Debug, PartialEq, ValueEnum)]
@@ -30,58 +77,121 @@ pub enum OutputFormat {
     Pretty,
     // Un-pretty-printed JSON.
     Concise,
+    // Like concise, but `JsonRecords` get one record per line with file
+    // boundary and periodic sync markers; see `write_ndjson_chunked`.
+    NdjsonChunked,
 }
 
 #[derive(Debug, Parser)]
 pub struct ToolOpts {
-    /// URL of the server to query or the path to the root of the index tree if
-    /// using local data.
-    #[clap(
-        long,
-        value_parser,
-        default_value = "https://searchfox.org/",
-        env = "SEARCHFOX_SERVER"
-    )]
-    pub server: String,
+    /// URL of the server to query, or the path to the index's config.json (or
+    /// the index directory containing it) if using local data.  Defaults to
+    /// the local index at ~/index if there is one, and https://searchfox.org/
+    /// otherwise.
+    #[clap(long, value_parser, env = "SEARCHFOX_SERVER")]
+    pub server: Option<String>,
 
-    /// The name of the indexed tree to use.
-    #[clap(
-        long,
-        value_parser,
-        default_value = "mozilla-central",
-        env = "SEARCHFOX_TREE"
-    )]
-    pub tree: String,
+    /// The name of the indexed tree to use.  For local data this defaults to
+    /// the config's `default_tree` or the only tree if there's just one; for
+    /// remote servers it defaults to "mozilla-central".
+    #[clap(long, value_parser, env = "SEARCHFOX_TREE")]
+    pub tree: Option<String>,
 
     #[clap(long, short, value_parser, value_enum, default_value = "concise")]
     pub output_format: OutputFormat,
 
+    /// Emit machine-readable progress records on stderr for long-running
+    /// stages.  Only the first pipeline segment's value is used.
+    #[clap(long, value_parser, value_enum, default_value = "none")]
+    pub progress: ProgressFormat,
+
+    /// Write the timings of the pipeline's tracing spans to this path in the
+    /// Chrome trace event JSON format for loading in chrome://tracing or
+    /// Perfetto.  Only the first pipeline segment's value is used.
+    #[clap(long, value_parser)]
+    pub trace_output: Option<String>,
+
+    /// Instead of running the expensive commands of the pipeline, report
+    /// estimates of the work they would do (symbols to expand, files to fetch,
+    /// expected results) to preview whether the pipeline will blow past its
+    /// limits.  Only the first pipeline segment's value is used.
+    #[clap(long, action)]
+    pub estimate: bool,
+
+    /// Fail fast for CI: instead of falling back to degraded results (retries,
+    /// truncated output, partial search results, a skipped or narrowed
+    /// fulltext search), fail the pipeline with the reason so that index
+    /// regressions are noticed.  Only the first pipeline segment's value is
+    /// used.
+    #[clap(long, action)]
+    pub strict: bool,
+
     #[clap(subcommand)]
     pub cmd: Command,
 }
 
 #[derive(Debug, Subcommand)]
 pub enum Command {
+    AnnotateRepos(AnnotateRepos),
+    ArgsOfCalls(ArgsOfCalls),
     AugmentResults(AugmentResults),
     BatchRender(BatchRender),
+    BlameLines(BlameLines),
+    CallerRollup(CallerRollup),
     CatHtml(CatHtml),
+    ChangedSymbols(ChangedSymbols),
     CrossrefExpand(CrossrefExpand),
     CrossrefLookup(CrossrefLookup),
+    DeadCode(DeadCode),
+    DefineBest(DefineBest),
+    DiffAgainst(DiffAgainst),
+    DiffSymbols(DiffSymbols),
+    DirOverview(DirOverview),
+    EnumUsage(EnumUsage),
+    Excerpt(Excerpt),
+    ExpandNode(ExpandNode),
+    ExtractFixture(ExtractFixture),
+    FetchFile(FetchFile),
     FilterAnalysis(FilterAnalysis),
+    FilterCrossref(FilterCrossref),
+    FlattenTextMatches(FlattenTextMatches),
     FormatSymbols(FormatSymbols),
     Graph(Graph),
+    GraphMetrics(GraphMetrics),
+    HistoryGrep(HistoryGrep),
+    IndexStats(IndexStats),
     JumprefLookup(JumprefLookup),
+    L10nRefs(L10nRefs),
+    LegacySearch(LegacySearch),
     MergeAnalyses(MergeAnalyses),
+    ModuleApi(ModuleApi),
+    OnlyNewSince(OnlyNewSince),
+    Outline(Outline),
+    PrecomputeAggregates(PrecomputeAggregates),
+    PreprocessorFilter(PreprocessorFilter),
     ProductionFilter(ProductionFilter),
     Query(Query),
+    Redact(Redact),
     Render(Render),
+    RenderMarkdown(RenderMarkdown),
+    RenderQuickfix(RenderQuickfix),
+    Rerank(Rerank),
     Search(Search),
     SearchFiles(SearchFiles),
     SearchIdentifiers(SearchIdentifiers),
+    SearchMentions(SearchMentions),
     SearchText(SearchText),
     ShowHtml(ShowHtml),
+    SimilarFunctions(SimilarFunctions),
+    SlotJump(SlotJump),
+    SuggestIdentifiers(SuggestIdentifiers),
+    SymbolPayloads(SymbolPayloads),
+    SymbolsAtPoint(SymbolsAtPoint),
     TokenizeSource(TokenizeSource),
+    TopSymbols(TopSymbols),
     Traverse(Traverse),
+    UnsafeAudit(UnsafeAudit),
+    ValidateAnalysis(ValidateAnalysis),
     Webtest(Webtest),
 }
 
@@ -93,6 +203,12 @@ pub struct JunctionOpts {
 
 #[derive(Debug, Subcommand)]
 pub enum JunctionCommand {
+    AnnotateCoverage(AnnotateCoverage),
+    BatchRender(BatchRenderJunction),
     CompileResults(CompileResults),
+    DefsInTextMatches(DefsInTextMatches),
     FuseCrossrefs(FuseCrossrefs),
+    GroupByDefFile(GroupByDefFile),
+    Interleave(Interleave),
+    TextDiffHits(TextDiffHits),
 }
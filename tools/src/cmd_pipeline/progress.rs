@@ -0,0 +1,151 @@
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::to_string;
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ProgressFormat {
+    /// Don't emit any progress information.
+    None,
+    /// Emit newline-delimited JSON progress records on stderr.
+    Json,
+}
+
+tokio::task_local! {
+    /// Progress reporting is task-local like pipeline warnings because it's a
+    /// property of how the pipeline was invoked rather than of any specific
+    /// pipeline stage, and threading it through every `PipelineCommand` would
+    /// be silly.  `ServerPipeline::run` scopes its pipeline's format and
+    /// `ServerPipelineGraph` propagates it into the tasks it spawns.  The
+    /// pipeline-server and web-server never enable this.
+    static PROGRESS_FORMAT: ProgressFormat;
+}
+
+/// How long to wait between progress records for a single stage so that we
+/// don't spam stderr when processing lots of tiny items.
+const EMIT_INTERVAL: Duration = Duration::from_millis(500);
+
+impl ProgressFormat {
+    /// Run the future with any `ProgressTracker`s it creates using this format.
+    pub async fn scope<F: Future>(self, f: F) -> F::Output {
+        PROGRESS_FORMAT.scope(self, f).await
+    }
+}
+
+/// The progress format of the pipeline we're running in, for propagating into
+/// any tasks we spawn.  Outside of a scope progress reporting is disabled.
+pub fn current_progress_format() -> ProgressFormat {
+    PROGRESS_FORMAT
+        .try_with(|format| *format)
+        .unwrap_or(ProgressFormat::None)
+}
+
+fn progress_enabled() -> bool {
+    current_progress_format() != ProgressFormat::None
+}
+
+#[derive(Serialize)]
+struct ProgressRecord<'a> {
+    stage: &'a str,
+    /// One of "start", "progress", or "done".
+    state: &'static str,
+    processed: u64,
+    /// The total number of items we expect to process, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total: Option<u64>,
+    elapsed_ms: u64,
+    /// Naive linear estimate of the remaining time, only provided if we know
+    /// the total.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    eta_ms: Option<u64>,
+}
+
+/// Helper for long-running stages to periodically report how far along they
+/// are.  All methods are no-ops unless progress reporting has been enabled via
+/// `ProgressFormat::scope`, so it's fine to sprinkle these around liberally.
+pub struct ProgressTracker {
+    stage: String,
+    total: Option<u64>,
+    processed: u64,
+    enabled: bool,
+    started: Instant,
+    last_emitted: Instant,
+}
+
+impl ProgressTracker {
+    pub fn new(stage: &str, total: Option<u64>) -> Self {
+        let now = Instant::now();
+        let tracker = Self {
+            stage: stage.to_string(),
+            total,
+            processed: 0,
+            enabled: progress_enabled(),
+            started: now,
+            last_emitted: now,
+        };
+        tracker.emit("start");
+        tracker
+    }
+
+    /// Note that `count` more items have been processed, emitting a record if
+    /// enough time has passed since the last one.
+    pub fn advance(&mut self, count: u64) {
+        self.processed += count;
+        if self.enabled && self.last_emitted.elapsed() >= EMIT_INTERVAL {
+            self.last_emitted = Instant::now();
+            self.emit("progress");
+        }
+    }
+
+    pub fn finish(&self) {
+        self.emit("done");
+    }
+
+    fn emit(&self, state: &'static str) {
+        if !self.enabled {
+            return;
+        }
+        let elapsed = self.started.elapsed();
+        let eta_ms = match self.total {
+            Some(total) if self.processed > 0 && total >= self.processed => {
+                Some((elapsed.as_millis() as u64 * (total - self.processed)) / self.processed)
+            }
+            _ => None,
+        };
+        let record = ProgressRecord {
+            stage: &self.stage,
+            state,
+            processed: self.processed,
+            total: self.total,
+            elapsed_ms: elapsed.as_millis() as u64,
+            eta_ms,
+        };
+        if let Ok(json) = to_string(&record) {
+            eprintln!("{}", json);
+        }
+    }
+}
+
+#[test]
+fn test_progress_format_scope() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+
+    assert!(!progress_enabled());
+    let (inside, nested) = runtime.block_on(ProgressFormat::Json.scope(async {
+        let nested = ProgressFormat::None
+            .scope(async { progress_enabled() })
+            .await;
+        (ProgressTracker::new("test", None).enabled, nested)
+    }));
+    assert!(inside);
+    assert!(!nested);
+    // Other pipelines (and anything outside of a pipeline) are unaffected.
+    assert!(!ProgressTracker::new("test", None).enabled);
+    assert!(!runtime.block_on(async { progress_enabled() }));
+}
@@ -0,0 +1,159 @@
+//! Limits on the size of the values produced by each pipeline stage.  The
+//! size of a value is the length of its JSON serialization, since that's what
+//! the value would cost to send as a response.  Measuring is done with a
+//! counting writer that gives up as soon as the limit is passed, so a runaway
+//! multi-GB value only gets serialized up to the limit.
+
+use std::io::{self, Write};
+
+use serde::Serialize;
+
+use super::{
+    interface::PipelineValues,
+    warnings::{add_pipeline_warning, strict_mode_enabled},
+};
+
+use crate::{
+    abstract_server::{AbstractServer, ErrorDetails, ErrorLayer, Result, ServerError},
+    file_format::config::{OutputSizeGuardConfig, SizeGuardAction},
+};
+
+/// A `Write` sink that only counts bytes, failing once `limit` is exceeded.
+struct CountingWriter {
+    count: u64,
+    limit: u64,
+}
+
+impl Write for CountingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.count += buf.len() as u64;
+        if self.count > self.limit {
+            return Err(io::Error::new(io::ErrorKind::Other, "size limit exceeded"));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Return the size of the JSON serialization of the value if it's at most
+/// `limit` bytes, or None if it's larger.
+pub fn serialized_size_within<T: Serialize + ?Sized>(value: &T, limit: u64) -> Option<u64> {
+    let mut writer = CountingWriter { count: 0, limit };
+    // Errors other than our own limit (which can't really happen for our
+    // types) just mean we measured less than everything, which is fine for
+    // an estimate.
+    let _ = serde_json::to_writer(&mut writer, value);
+    if writer.count > limit {
+        None
+    } else {
+        Some(writer.count)
+    }
+}
+
+/// Drop trailing items until the serialization of the remaining items fits in
+/// `limit` bytes, returning the number of items dropped.
+fn truncate_to_fit<T: Serialize>(items: &mut Vec<T>, limit: u64) -> usize {
+    // Account for the brackets of the list.
+    let mut total = 2;
+    for (i, item) in items.iter().enumerate() {
+        match serialized_size_within(item, limit.saturating_sub(total)) {
+            // Plus the comma separating it from the next item.
+            Some(size) => total += size + 1,
+            None => {
+                let dropped = items.len() - i;
+                items.truncate(i);
+                return dropped;
+            }
+        }
+    }
+    0
+}
+
+/// Truncate the list-like parts of the value so that it fits in `limit`
+/// bytes, returning the number of items dropped, or None if the value isn't a
+/// type we know how to truncate.  The limit is applied to the list alone, so
+/// the rest of the value can still put it slightly over.
+fn truncate_values(values: &mut PipelineValues, limit: u64) -> Option<usize> {
+    Some(match values {
+        PipelineValues::IdentifierList(il) => truncate_to_fit(&mut il.identifiers, limit),
+        PipelineValues::SymbolList(sl) => truncate_to_fit(&mut sl.symbols, limit),
+        PipelineValues::SymbolCrossrefInfoList(scil) => {
+            truncate_to_fit(&mut scil.symbol_crossref_infos, limit)
+        }
+        PipelineValues::JsonValueList(jvl) => truncate_to_fit(&mut jvl.values, limit),
+        PipelineValues::FileMatches(fm) => truncate_to_fit(&mut fm.file_matches, limit),
+        PipelineValues::TextMatches(tm) => truncate_to_fit(&mut tm.by_file, limit),
+        _ => return None,
+    })
+}
+
+/// Check the output of a pipeline stage against the server's
+/// `OutputSizeGuardConfig`, returning the (possibly truncated) values and
+/// their measured size, which is None if the guard is disabled.  Strict mode
+/// errors instead of truncating.  Servers that
+/// don't have a config aren't guarded.
+pub fn enforce_size_guard(
+    server: &(dyn AbstractServer + Send + Sync),
+    command: &str,
+    mut values: PipelineValues,
+) -> Result<(PipelineValues, Option<u64>)> {
+    let config = match server.output_size_guard_config() {
+        Ok(config) => config,
+        Err(ServerError::Unsupported) => return Ok((values, None)),
+        Err(err) => return Err(err),
+    };
+    let OutputSizeGuardConfig {
+        max_stage_output_bytes: limit,
+        on_exceeded,
+    } = config;
+    if limit == 0 {
+        return Ok((values, None));
+    }
+
+    if let Some(size) = serialized_size_within(&values, limit) {
+        return Ok((values, Some(size)));
+    }
+
+    // Strict mode wants the error rather than partial results.
+    if on_exceeded == SizeGuardAction::Truncate && !strict_mode_enabled() {
+        if let Some(dropped) = truncate_values(&mut values, limit) {
+            add_pipeline_warning(
+                command,
+                format!(
+                    "output exceeded the maximum of {} bytes; dropped the last {} items",
+                    limit, dropped
+                ),
+            );
+            let size = serialized_size_within(&values, u64::MAX);
+            return Ok((values, size));
+        }
+    }
+
+    Err(ServerError::StickyProblem(ErrorDetails {
+        layer: ErrorLayer::ServerLayer,
+        message: format!(
+            "{} output exceeded the maximum of {} bytes; try a more specific query",
+            command, limit
+        ),
+    }))
+}
+
+#[test]
+fn test_truncate_to_fit() {
+    let values = vec!["aaaa".to_string(), "bbbb".to_string(), "cccc".to_string()];
+    // Each item is 6 bytes with its quotes.
+    assert_eq!(serialized_size_within(&values, 100), Some(22));
+    assert_eq!(serialized_size_within(&values, 21), None);
+
+    let mut truncated = values.clone();
+    assert_eq!(truncate_to_fit(&mut truncated, 16), 1);
+    assert_eq!(truncated, vec!["aaaa", "bbbb"]);
+    assert_eq!(serialized_size_within(&truncated, 16), Some(15));
+
+    let mut untouched = values.clone();
+    assert_eq!(truncate_to_fit(&mut untouched, 22), 0);
+    assert_eq!(untouched.len(), 3);
+}
@@ -6,9 +6,9 @@ use dot_structures::*;
 use graphviz_rust::printer::{DotPrinter, PrinterContext};
 use itertools::Itertools;
 use petgraph::{
-    algo::all_simple_paths,
+    algo::{all_simple_paths, tarjan_scc},
     graph::{DefaultIx, NodeIndex},
-    Directed, Graph as PetGraph,
+    Directed, Direction, Graph as PetGraph,
 };
 use serde::ser::SerializeStruct;
 use serde::{Serialize, Serializer};
@@ -105,6 +105,9 @@ pub struct DerivedSymbolInfo {
     /// instead to store the SymbolNodeId of the first target here instead.
     pub effective_subsystem: Option<Ustr>,
     pub depth: u32,
+    /// Set by traverse for symbols defined outside of its path boundary, which
+    /// are included as stubs but not traversed.
+    pub external: bool,
 }
 
 #[derive(Clone, Eq, Ord, PartialEq, PartialOrd)]
@@ -252,8 +255,21 @@ impl DerivedSymbolInfo {
             badges: vec![],
             effective_subsystem: None,
             depth,
+            external: false,
         }
     }
+
+    /// Convert the given crossref info for this symbol into its jumpref
+    /// representation, marking external stubs with `"external": true`.
+    fn to_jumpref_json(&self, crossref_info: Value) -> Value {
+        let mut jumpref = convert_crossref_value_to_sym_info_rep(crossref_info, &self.symbol, None);
+        if self.external {
+            if let Value::Object(obj) = &mut jumpref {
+                obj.insert("external".to_string(), json!(true));
+            }
+        }
+        jumpref
+    }
 }
 
 /// A collection of one or more graphs that share a common underlying set of
@@ -292,6 +308,50 @@ impl Serialize for SymbolGraphCollection {
     }
 }
 
+/// Basic structural metrics for a graph so that coupling can be quantified
+/// without exporting the graph to external tools.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SymbolGraphMetrics {
+    pub node_count: usize,
+    pub edge_count: usize,
+    pub in_degree: DegreeDistribution,
+    pub out_degree: DegreeDistribution,
+    /// The strongly connected components containing cycles as sorted lists of
+    /// symbols, largest first.
+    pub cycles: Vec<Vec<Ustr>>,
+}
+
+#[derive(Serialize)]
+pub struct DegreeDistribution {
+    pub max: usize,
+    pub mean: f64,
+    /// Maps each degree to the number of nodes with that degree.
+    pub histogram: BTreeMap<usize, usize>,
+}
+
+impl DegreeDistribution {
+    fn new(degrees: impl Iterator<Item = usize>) -> Self {
+        let mut histogram = BTreeMap::new();
+        let mut total = 0;
+        let mut count = 0;
+        for degree in degrees {
+            *histogram.entry(degree).or_insert(0) += 1;
+            total += degree;
+            count += 1;
+        }
+        DegreeDistribution {
+            max: histogram.keys().next_back().copied().unwrap_or(0),
+            mean: if count == 0 {
+                0.0
+            } else {
+                total as f64 / count as f64
+            },
+            histogram,
+        }
+    }
+}
+
 /// Escape double-quotes to safely use a string as an `esc` tagged value.
 ///
 /// Although graphviz-rust's dot-generator has a concept of `esc`, this does not
@@ -375,6 +435,59 @@ impl SymbolGraphCollection {
         graph.root.to_json(&self.node_set)
     }
 
+    /// Convert the graph with the given index to an adjacency list rep which is
+    /// an object mapping every node's symbol to a sorted array of the symbols
+    /// it has edges to.
+    pub fn graph_to_adjacency(&self, graph_idx: usize) -> Value {
+        let graph = match self.graphs.get(graph_idx) {
+            Some(g) => g,
+            None => return json!({}),
+        };
+
+        let mut adjacency: BTreeMap<Ustr, BTreeSet<Ustr>> = BTreeMap::new();
+        for node_id in graph.list_nodes() {
+            adjacency
+                .entry(self.node_set.get(&node_id).symbol)
+                .or_default();
+        }
+        for (source_id, target_id, _edge_id) in graph.list_edges() {
+            adjacency
+                .entry(self.node_set.get(&source_id).symbol)
+                .or_default()
+                .insert(self.node_set.get(&target_id).symbol);
+        }
+
+        json!(adjacency)
+    }
+
+    /// Compute basic structural metrics for the graph with the given index.
+    pub fn graph_metrics(&self, graph_idx: usize) -> Option<SymbolGraphMetrics> {
+        let graph = self.graphs.get(graph_idx)?;
+
+        let degrees = graph.list_degrees();
+        let mut cycles: Vec<Vec<Ustr>> = graph
+            .cyclic_components()
+            .iter()
+            .map(|component| {
+                let mut syms: Vec<Ustr> = component
+                    .iter()
+                    .map(|node_id| self.node_set.get(node_id).symbol)
+                    .collect();
+                syms.sort();
+                syms
+            })
+            .collect();
+        cycles.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+
+        Some(SymbolGraphMetrics {
+            node_count: degrees.len(),
+            edge_count: graph.graph.edge_count(),
+            in_degree: DegreeDistribution::new(degrees.iter().map(|(_, in_deg, _)| *in_deg)),
+            out_degree: DegreeDistribution::new(degrees.iter().map(|(_, _, out_deg)| *out_deg)),
+            cycles,
+        })
+    }
+
     /// Convert the graph with the given index to a graphviz rep.
     pub fn graph_to_graphviz<F>(&self, graph_idx: usize, node_decorate: F) -> Graph
     where
@@ -811,6 +924,36 @@ impl NamedSymbolGraph {
             .collect()
     }
 
+    /// List the (in-degree, out-degree) of every node in the graph.
+    pub fn list_degrees(&self) -> Vec<(SymbolGraphNodeId, usize, usize)> {
+        self.graph
+            .node_indices()
+            .filter_map(|ix| {
+                let id = self.node_ix_to_id.get(&(ix.index() as u32))?;
+                Some((
+                    SymbolGraphNodeId(*id),
+                    self.graph.edges_directed(ix, Direction::Incoming).count(),
+                    self.graph.edges_directed(ix, Direction::Outgoing).count(),
+                ))
+            })
+            .collect()
+    }
+
+    /// Find the strongly connected components of the graph that contain a
+    /// cycle, which means they have more than one node or a self-edge.
+    pub fn cyclic_components(&self) -> Vec<Vec<SymbolGraphNodeId>> {
+        tarjan_scc(&self.graph)
+            .into_iter()
+            .filter(|scc| scc.len() > 1 || self.graph.contains_edge(scc[0], scc[0]))
+            .map(|scc| {
+                scc.into_iter()
+                    .filter_map(|ix| self.node_ix_to_id.get(&(ix.index() as u32)))
+                    .map(|id| SymbolGraphNodeId(*id))
+                    .collect()
+            })
+            .collect()
+    }
+
     pub fn ensure_edge(
         &mut self,
         source: SymbolGraphNodeId,
@@ -2272,6 +2415,40 @@ impl SymbolGraphNodeSet {
         Ok(self.add_symbol(DerivedSymbolInfo::new(*sym, info, depth)))
     }
 
+    /// Like `ensure_symbol`, but returns None instead if `accept` returns false
+    /// for the symbol's crossref info.  Rejected symbols are not added to the
+    /// node set, so traversal can decide whether an edge is worth following
+    /// without the rejected symbol counting against its node limit.
+    pub async fn ensure_symbol_if<'a, F>(
+        &'a mut self,
+        sym: &'a Ustr,
+        server: &'a (dyn AbstractServer + Send + Sync),
+        depth: u32,
+        accept: F,
+    ) -> Result<Option<(SymbolGraphNodeId, &'a mut DerivedSymbolInfo)>>
+    where
+        F: FnOnce(&Value) -> bool,
+    {
+        if let Some(index) = self.symbol_to_index_map.get(sym) {
+            let sym_info = self
+                .symbol_crossref_infos
+                .get_mut(*index as usize)
+                .ok_or_else(make_data_invariant_err)?;
+            if !accept(&sym_info.crossref_info) {
+                return Ok(None);
+            }
+            return Ok(Some((SymbolGraphNodeId(*index), sym_info)));
+        }
+
+        let info = server.crossref_lookup(sym, false).await?;
+        if !accept(&info) {
+            return Ok(None);
+        }
+        Ok(Some(
+            self.add_symbol(DerivedSymbolInfo::new(*sym, info, depth)),
+        ))
+    }
+
     /// Destructively return a sorted Object mapping from symbol identifiers to
     /// their jumpref info.  We sort the symbols for stability for testing
     /// purposes and for human readability reasons.  The destruction is that
@@ -2289,10 +2466,7 @@ impl SymbolGraphNodeSet {
         let mut jumprefs = BTreeMap::new();
         for sym_info in self.symbol_crossref_infos.iter_mut() {
             let info = sym_info.crossref_info.take();
-            jumprefs.insert(
-                sym_info.symbol,
-                convert_crossref_value_to_sym_info_rep(info, &sym_info.symbol, None),
-            );
+            jumprefs.insert(sym_info.symbol, sym_info.to_jumpref_json(info));
         }
 
         json!(jumprefs)
@@ -2304,10 +2478,7 @@ impl SymbolGraphNodeSet {
         for sym_info in self.symbol_crossref_infos.iter() {
             // XXX This is inefficient!
             let info = sym_info.crossref_info.clone();
-            jumprefs.insert(
-                sym_info.symbol,
-                convert_crossref_value_to_sym_info_rep(info, &sym_info.symbol, None),
-            );
+            jumprefs.insert(sym_info.symbol, sym_info.to_jumpref_json(info));
         }
 
         json!(jumprefs)
@@ -2373,3 +2544,63 @@ impl SymbolGraphEdgeSet {
         graph.ensure_edge(source, target, edge_id);
     }
 }
+
+#[test]
+fn test_graph_degrees_and_cycles() {
+    let mut graph = NamedSymbolGraph::new("test".to_string());
+    let node = SymbolGraphNodeId;
+    let edge = SymbolGraphEdgeId;
+    // 0 -> 1 -> 2 -> 0 is a cycle, 3 only calls into it, 4 calls itself.
+    graph.ensure_edge(node(0), node(1), edge(0));
+    graph.ensure_edge(node(1), node(2), edge(1));
+    graph.ensure_edge(node(2), node(0), edge(2));
+    graph.ensure_edge(node(3), node(0), edge(3));
+    graph.ensure_edge(node(4), node(4), edge(4));
+
+    let degrees: HashMap<u32, (usize, usize)> = graph
+        .list_degrees()
+        .into_iter()
+        .map(|(id, in_deg, out_deg)| (id.0, (in_deg, out_deg)))
+        .collect();
+    assert_eq!(degrees[&0], (2, 1));
+    assert_eq!(degrees[&3], (0, 1));
+    assert_eq!(degrees[&4], (1, 1));
+
+    let mut cycles: Vec<Vec<u32>> = graph
+        .cyclic_components()
+        .into_iter()
+        .map(|scc| {
+            let mut ids: Vec<u32> = scc.into_iter().map(|id| id.0).collect();
+            ids.sort();
+            ids
+        })
+        .collect();
+    cycles.sort();
+    assert_eq!(cycles, vec![vec![0, 1, 2], vec![4]]);
+
+    let dist = DegreeDistribution::new(vec![2, 0, 1, 1].into_iter());
+    assert_eq!(dist.max, 2);
+    assert_eq!(dist.mean, 1.0);
+    assert_eq!(dist.histogram[&1], 2);
+}
+
+#[test]
+fn test_external_jumprefs() {
+    let mut node_set = SymbolGraphNodeSet::new();
+    let crossref_info = json!({"meta": {"pretty": "Foo"}});
+    node_set.add_symbol(DerivedSymbolInfo::new(
+        ustr("T_Foo"),
+        crossref_info.clone(),
+        0,
+    ));
+    let (_, stub) = node_set.add_symbol(DerivedSymbolInfo::new(ustr("T_Bar"), crossref_info, 1));
+    stub.external = true;
+
+    let jumprefs = node_set.symbols_meta_to_jumpref_json_nomut();
+    assert_eq!(jumprefs["T_Foo"].get("external"), None);
+    assert_eq!(jumprefs["T_Bar"]["external"], json!(true));
+    assert_eq!(
+        node_set.symbols_meta_to_jumpref_json_destructive(),
+        jumprefs
+    );
+}
@@ -0,0 +1,95 @@
+//! Bounded top-K selection for stages with a result limit, so that values that
+//! can't make the cut are discarded as they are produced instead of every
+//! value being accumulated, sorted, and then truncated.
+
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+/// A total ranking order where `Ordering::Less` means "ranks higher".  Ties
+/// should be broken (ex: by input order) so that results are deterministic.
+pub trait RankOrd {
+    fn rank_cmp(&self, other: &Self) -> Ordering;
+}
+
+/// Wrapper so that a max-heap keeps the lowest ranked value on top where it
+/// can be evicted.
+struct Ranked<T>(T);
+
+impl<T: RankOrd> PartialEq for Ranked<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T: RankOrd> Eq for Ranked<T> {}
+
+impl<T: RankOrd> PartialOrd for Ranked<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T: RankOrd> Ord for Ranked<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.rank_cmp(&other.0)
+    }
+}
+
+/// Retains the `limit` highest ranked values pushed into it, where a limit of
+/// 0 retains everything.
+pub struct TopK<T: RankOrd> {
+    limit: usize,
+    heap: BinaryHeap<Ranked<T>>,
+}
+
+impl<T: RankOrd> TopK<T> {
+    pub fn new(limit: usize) -> Self {
+        TopK {
+            limit,
+            heap: BinaryHeap::new(),
+        }
+    }
+
+    /// Add the value if it's in the top `limit` seen so far.
+    pub fn push(&mut self, value: T) {
+        if self.limit == 0 || self.heap.len() < self.limit {
+            self.heap.push(Ranked(value));
+        } else if let Some(lowest) = self.heap.peek() {
+            if value.rank_cmp(&lowest.0) == Ordering::Less {
+                self.heap.pop();
+                self.heap.push(Ranked(value));
+            }
+        }
+    }
+
+    /// The retained values, highest ranked first.
+    pub fn into_sorted_vec(self) -> Vec<T> {
+        self.heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Ranked(value)| value)
+            .collect()
+    }
+}
+
+#[test]
+fn test_top_k() {
+    struct Scored(u32, usize);
+    impl RankOrd for Scored {
+        fn rank_cmp(&self, other: &Self) -> Ordering {
+            other.0.cmp(&self.0).then_with(|| self.1.cmp(&other.1))
+        }
+    }
+    let scores = [3, 9, 1, 9, 5, 3];
+    let top = |limit: usize| {
+        let mut top = TopK::new(limit);
+        for (i, &score) in scores.iter().enumerate() {
+            top.push(Scored(score, i));
+        }
+        top.into_sorted_vec()
+            .into_iter()
+            .map(|Scored(score, i)| (score, i))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(top(3), vec![(9, 1), (9, 3), (5, 4)]);
+    assert_eq!(top(0), vec![(9, 1), (9, 3), (5, 4), (3, 0), (3, 5), (1, 2)]);
+}
@@ -0,0 +1,150 @@
+use std::{fmt::Debug, fs, sync::Mutex, time::Instant};
+
+use serde_json::{json, to_string, Map, Value};
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+struct TraceOutput {
+    path: String,
+    start: Instant,
+    events: Vec<Value>,
+}
+
+lazy_static! {
+    /// Trace output is a property of how searchfox-tool was invoked and covers
+    /// everything the process does, so it's process-global.
+    static ref TRACE_OUTPUT: Mutex<Option<TraceOutput>> = Mutex::new(None);
+}
+
+/// Start (or stop, if None) accumulating span timings to be written to the
+/// given path by `write_trace_output`.
+pub fn set_trace_output(path: Option<String>) {
+    *TRACE_OUTPUT.lock().unwrap() = path.map(|path| TraceOutput {
+        path,
+        start: Instant::now(),
+        events: vec![],
+    });
+}
+
+pub fn trace_output_enabled() -> bool {
+    TRACE_OUTPUT.lock().unwrap().is_some()
+}
+
+/// Write the accumulated span timings out in the Chrome trace event format,
+/// which can be loaded by chrome://tracing or https://ui.perfetto.dev/, and
+/// stop accumulating.  Returns the path written to, if trace output was
+/// enabled.
+pub fn write_trace_output() -> std::io::Result<Option<String>> {
+    let output = match TRACE_OUTPUT.lock().unwrap().take() {
+        Some(output) => output,
+        None => return Ok(None),
+    };
+    let trace = json!({
+        "traceEvents": output.events,
+        "displayTimeUnit": "ms",
+    });
+    fs::write(&output.path, to_string(&trace)?)?;
+    Ok(Some(output.path))
+}
+
+struct FieldVisitor<'a>(&'a mut Map<String, Value>);
+
+impl Visit for FieldVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0
+            .insert(field.name().to_string(), json!(format!("{:?}", value)));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+}
+
+/// Stored in the span's extensions from creation until it closes.
+struct SpanTiming {
+    start: Instant,
+    args: Map<String, Value>,
+}
+
+/// A tracing layer that records every span as a Chrome trace "complete" event
+/// spanning from the creation of the span until it closes, with the span's
+/// fields as the event's args.  This does nothing unless `set_trace_output`
+/// has been called, but it should also be given a filter based on
+/// `trace_output_enabled` so that trace-level spans aren't constructed for no
+/// reason.
+///
+/// Spans are placed on tracks (Chrome "threads") by the nearest enclosing
+/// "*_task" span so that the pipelines and junctions of a graph that run
+/// concurrently don't end up overlapping on the same track.
+pub struct ChromeTraceLayer;
+
+impl<S> Layer<S> for ChromeTraceLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let span = match ctx.span(id) {
+            Some(span) => span,
+            None => return,
+        };
+        let mut args = Map::new();
+        attrs.record(&mut FieldVisitor(&mut args));
+        span.extensions_mut().insert(SpanTiming {
+            start: Instant::now(),
+            args,
+        });
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(timing) = span.extensions_mut().get_mut::<SpanTiming>() {
+                values.record(&mut FieldVisitor(&mut timing.args));
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let span = match ctx.span(&id) {
+            Some(span) => span,
+            None => return,
+        };
+        let timing = match span.extensions_mut().remove::<SpanTiming>() {
+            Some(timing) => timing,
+            None => return,
+        };
+        let tid = span
+            .scope()
+            .find(|s| s.name().ends_with("_task"))
+            .map_or(0, |s| s.id().into_u64());
+
+        let mut trace_output = TRACE_OUTPUT.lock().unwrap();
+        if let Some(output) = trace_output.as_mut() {
+            output.events.push(json!({
+                "name": span.name(),
+                "cat": span.metadata().target(),
+                "ph": "X",
+                "ts": timing.start.saturating_duration_since(output.start).as_micros() as u64,
+                "dur": timing.start.elapsed().as_micros() as u64,
+                "pid": std::process::id(),
+                "tid": tid,
+                "args": timing.args,
+            }));
+        }
+    }
+}
@@ -1,4 +1,9 @@
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
 use regex::{Captures, Regex};
+use ustr::{ustr, Ustr};
+
+use crate::file_format::config::{PathAliasConfig, ResultDemotionConfig};
 
 /// Apply the searchfox path glob transformation ported from `router.py`.
 pub fn path_glob_transform(s: &str) -> String {
@@ -23,6 +28,148 @@ pub fn path_glob_transform(s: &str) -> String {
     braced.to_string()
 }
 
+/// A Bugzilla component constraint like "Core::DOM: Core & HTML" or just a
+/// product like "Core".
+#[derive(Debug)]
+pub struct BugzillaComponentFilter {
+    pub product: String,
+    pub component: Option<String>,
+}
+
+impl BugzillaComponentFilter {
+    /// Parse a "Product::Component" string; the component is optional and the
+    /// comparison is case-insensitive.
+    pub fn parse(s: &str) -> Self {
+        match s.split_once("::") {
+            Some((product, component)) => BugzillaComponentFilter {
+                product: product.trim().to_lowercase(),
+                component: Some(component.trim().to_lowercase()),
+            },
+            None => BugzillaComponentFilter {
+                product: s.trim().to_lowercase(),
+                component: None,
+            },
+        }
+    }
+
+    pub fn matches(&self, bugzilla_component: &Option<(Ustr, Ustr)>) -> bool {
+        match bugzilla_component {
+            Some((product, component)) => {
+                product.to_lowercase() == self.product
+                    && match &self.component {
+                        Some(want) => component.to_lowercase() == *want,
+                        None => true,
+                    }
+            }
+            None => false,
+        }
+    }
+}
+
+/// The compiled form of a tree's `ResultDemotionConfig`.  The default value
+/// doesn't demote anything.
+#[derive(Debug, Default)]
+pub struct PathDemotion {
+    path_kinds: BTreeMap<String, f64>,
+    path_rules: Vec<(Regex, f64)>,
+    collapse_at_or_below: Option<f64>,
+}
+
+impl PathDemotion {
+    pub fn new(config: &ResultDemotionConfig) -> Result<Self, regex::Error> {
+        let path_rules = config
+            .path_rules
+            .iter()
+            .map(|rule| Ok((Regex::new(&rule.pattern)?, rule.weight)))
+            .collect::<Result<Vec<_>, regex::Error>>()?;
+        Ok(PathDemotion {
+            path_kinds: config.path_kinds.clone(),
+            path_rules,
+            collapse_at_or_below: config.collapse_at_or_below,
+        })
+    }
+
+    pub fn path_kind_weight(&self, path_kind: &str) -> f64 {
+        self.path_kinds.get(path_kind).copied().unwrap_or(1.0)
+    }
+
+    /// The weight of the path independent of its path kind.
+    pub fn path_weight(&self, path: &str) -> f64 {
+        self.path_rules
+            .iter()
+            .find(|(re, _)| re.is_match(path))
+            .map_or(1.0, |(_, weight)| *weight)
+    }
+
+    pub fn should_collapse(&self, weight: f64) -> bool {
+        match self.collapse_at_or_below {
+            Some(threshold) => weight <= threshold,
+            None => false,
+        }
+    }
+}
+
+/// The compiled form of a tree's `PathAliasConfig`.  The default value doesn't
+/// alias anything.
+#[derive(Debug, Default)]
+pub struct PathAliases {
+    rules: Vec<(Regex, String)>,
+    /// (canonicalized absolute directory, tree path prefix) pairs, which are
+    /// only present if symlinks should be resolved.
+    symlink_roots: Vec<(PathBuf, String)>,
+}
+
+impl PathAliases {
+    pub fn new(config: &PathAliasConfig) -> Result<Self, regex::Error> {
+        let rules = config
+            .rules
+            .iter()
+            .map(|rule| Ok((Regex::new(&rule.pattern)?, rule.replacement.clone())))
+            .collect::<Result<Vec<_>, regex::Error>>()?;
+        let symlink_roots = if config.resolve_symlinks {
+            config
+                .symlink_roots
+                .iter()
+                .filter_map(|(dir, prefix)| Some((fs::canonicalize(dir).ok()?, prefix.clone())))
+                .collect()
+        } else {
+            vec![]
+        };
+        Ok(PathAliases {
+            rules,
+            symlink_roots,
+        })
+    }
+
+    /// The canonical path for the given tree path if it's an alias of another
+    /// path, or None if it's already canonical.
+    pub fn canonical_path(&self, path: &str) -> Option<Ustr> {
+        let canonical = match self.rules.iter().find(|(re, _)| re.is_match(path)) {
+            Some((re, replacement)) => re.replace(path, replacement.as_str()).to_string(),
+            None => self.resolve_symlink(path)?,
+        };
+        if canonical == path {
+            None
+        } else {
+            Some(ustr(&canonical))
+        }
+    }
+
+    /// Resolve the path on disk and map it back to a tree path, returning None
+    /// if it can't be resolved or resolves outside of the tree.
+    fn resolve_symlink(&self, path: &str) -> Option<String> {
+        let disk_path = self
+            .symlink_roots
+            .iter()
+            .find_map(|(root, prefix)| Some(root.join(path.strip_prefix(prefix.as_str())?)))?;
+        let resolved = fs::canonicalize(disk_path).ok()?;
+        self.symlink_roots.iter().find_map(|(root, prefix)| {
+            let rest = resolved.strip_prefix(root).ok()?.to_str()?;
+            Some(format!("{}{}", prefix, rest))
+        })
+    }
+}
+
 #[test]
 fn test_path_glob_transform() {
     // Test coverage for the cases we documented on the help page.
@@ -42,3 +189,60 @@ fn test_path_glob_transform() {
         "^js/src/.*\\.(cpp|h)$"
     );
 }
+
+#[test]
+fn test_bugzilla_component_filter() {
+    let dom = Some((Ustr::from("Core"), Ustr::from("DOM: Core & HTML")));
+    let js = Some((Ustr::from("Core"), Ustr::from("JavaScript Engine")));
+
+    let filter = BugzillaComponentFilter::parse("core::dom: core & html");
+    assert!(filter.matches(&dom));
+    assert!(!filter.matches(&js));
+    assert!(!filter.matches(&None));
+
+    let filter = BugzillaComponentFilter::parse("Core");
+    assert!(filter.matches(&dom));
+    assert!(filter.matches(&js));
+}
+
+#[test]
+fn test_path_demotion() {
+    let mut config = ResultDemotionConfig::default();
+    config.path_kinds.insert("Test files".to_string(), 0.5);
+    config.collapse_at_or_below = Some(0.25);
+    let demotion = PathDemotion::new(&config).unwrap();
+
+    assert_eq!(demotion.path_kind_weight("Normal"), 1.0);
+    assert_eq!(demotion.path_kind_weight("Test files"), 0.5);
+    assert_eq!(demotion.path_kind_weight(""), 1.0);
+    assert_eq!(demotion.path_weight("dom/base/Element.cpp"), 1.0);
+    assert_eq!(demotion.path_weight("third_party/rust/foo/lib.rs"), 0.25);
+    assert_eq!(
+        demotion.path_weight("obj-x86_64-pc-linux-gnu/dist/a.h"),
+        0.25
+    );
+    assert_eq!(demotion.path_weight("__GENERATED__/dist/include/a.h"), 0.5);
+    assert!(demotion.should_collapse(0.25));
+    assert!(!demotion.should_collapse(0.5));
+
+    assert!(!PathDemotion::default().should_collapse(0.0));
+}
+
+#[test]
+fn test_path_aliases() {
+    use crate::file_format::config::PathRewriteRule;
+
+    let aliases = PathAliases::new(&PathAliasConfig {
+        rules: vec![PathRewriteRule {
+            pattern: "^__GENERATED__/unified/(.*)$".to_string(),
+            replacement: "$1".to_string(),
+        }],
+        ..Default::default()
+    })
+    .unwrap();
+    assert_eq!(
+        aliases.canonical_path("__GENERATED__/unified/dom/base/Element.cpp"),
+        Some(ustr("dom/base/Element.cpp"))
+    );
+    assert_eq!(aliases.canonical_path("dom/base/Element.cpp"), None);
+}
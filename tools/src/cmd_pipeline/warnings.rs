@@ -0,0 +1,242 @@
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::trace;
+
+use super::interface::{OverloadInfo, PipelineValues};
+
+use crate::abstract_server::{Result, ServerError};
+
+/// A non-fatal problem that changed how a command behaved in a way the user
+/// wouldn't otherwise be able to tell from the results (ex: a limit getting
+/// clamped or a constraint getting ignored).
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PipelineWarning {
+    /// The pipeline command that produced the warning (ex: "search-text").
+    pub command: String,
+    pub message: String,
+}
+
+/// The warnings accumulated by a pipeline run, shared by all of the tasks
+/// running its commands, along with whether the pipeline runs in strict mode.
+#[derive(Clone, Default)]
+pub struct PipelineWarnings {
+    warnings: Arc<Mutex<Vec<PipelineWarning>>>,
+    strict: bool,
+}
+
+tokio::task_local! {
+    /// Warnings are task-local rather than threaded through every
+    /// `PipelineCommand` since very few commands have anything to say and the
+    /// pipeline-server runs many pipelines at once.  `ServerPipelineGraph`
+    /// propagates this into the tasks it spawns.  Strict mode rides along
+    /// since it's a property of the pipeline that the things which would add
+    /// warnings need to know about.
+    static WARNINGS: PipelineWarnings;
+}
+
+impl PipelineWarnings {
+    /// Run the future with any warnings it adds going to this collection.
+    pub async fn scope<F: Future>(self, f: F) -> F::Output {
+        WARNINGS.scope(self, f).await
+    }
+
+    /// The same collection, with strict mode enabled or disabled.  In strict
+    /// mode, where CI jobs want the pipeline to fail rather than quietly
+    /// produce degraded results, any warning fails the pipeline after the step
+    /// that added it (see `check_strict_mode`), and the things that would
+    /// degrade results without warning (retries, output truncation, the query
+    /// cost model, the fulltext fallback of semantic searches) are disabled.
+    pub fn with_strict(self, strict: bool) -> Self {
+        PipelineWarnings { strict, ..self }
+    }
+
+    /// The earliest warning, if there have been any.
+    pub fn first(&self) -> Option<PipelineWarning> {
+        self.warnings.lock().unwrap().first().cloned()
+    }
+
+    pub fn take(&self) -> Vec<PipelineWarning> {
+        std::mem::take(&mut *self.warnings.lock().unwrap())
+    }
+
+    fn push(&self, warning: PipelineWarning) {
+        let mut warnings = self.warnings.lock().unwrap();
+        // Identical pipelines in different branches of a graph would otherwise
+        // report the same thing repeatedly.
+        if !warnings.contains(&warning) {
+            warnings.push(warning);
+        }
+    }
+}
+
+/// Add a warning to the pipeline we're running in.  Warnings added outside of
+/// a `collect_pipeline_warnings` scope are only traced.
+pub fn add_pipeline_warning(command: &str, message: String) {
+    trace!(command, "pipeline warning: {}", message);
+    let warning = PipelineWarning {
+        command: command.to_string(),
+        message,
+    };
+    let _ = WARNINGS.try_with(|warnings| warnings.push(warning));
+}
+
+/// Record that a command truncated its results.  The `OverloadInfo` already
+/// tells the user about the truncation, but in strict mode it's also added as
+/// a warning so that it fails the pipeline like any other degraded result.
+pub fn record_overload(overloads: &mut Vec<OverloadInfo>, command: &str, overload: OverloadInfo) {
+    if strict_mode_enabled() {
+        add_pipeline_warning(command, overload.describe());
+    }
+    overloads.push(overload);
+}
+
+/// The warnings of the pipeline we're running in, for propagating into any
+/// tasks we spawn.  Outside of a scope this is a new collection that nothing
+/// will look at.
+pub fn current_pipeline_warnings() -> PipelineWarnings {
+    WARNINGS
+        .try_with(|warnings| warnings.clone())
+        .unwrap_or_default()
+}
+
+/// Run the future (ex: `ServerPipeline::run`), returning its output along with
+/// the warnings its commands added.
+pub async fn collect_pipeline_warnings<F: Future>(f: F) -> (F::Output, Vec<PipelineWarning>) {
+    let warnings = PipelineWarnings::default();
+    let output = warnings.clone().scope(f).await;
+    (output, warnings.take())
+}
+
+/// Is the pipeline we're running in in strict mode?  See
+/// `PipelineWarnings::with_strict`.
+pub fn strict_mode_enabled() -> bool {
+    WARNINGS
+        .try_with(|warnings| warnings.strict)
+        .unwrap_or(false)
+}
+
+/// In strict mode, fail with the first warning of the pipeline we're running
+/// in, if any.
+pub fn check_strict_mode() -> Result<()> {
+    if !strict_mode_enabled() {
+        return Ok(());
+    }
+    match current_pipeline_warnings().first() {
+        Some(warning) => Err(ServerError::StrictFailure(warning)),
+        None => Ok(()),
+    }
+}
+
+/// The final results of a pipeline along with its warnings, which is what we
+/// serialize for clients so that warnings are visible at the top level rather
+/// than buried in (or missing from) command-specific results.
+#[derive(Serialize)]
+pub struct PipelineOutput {
+    pub results: PipelineValues,
+    pub warnings: Vec<PipelineWarning>,
+}
+
+#[test]
+fn test_collect_pipeline_warnings() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+
+    // Warnings outside of a scope go nowhere.
+    add_pipeline_warning("test", "lost".to_string());
+
+    let ((), warnings) = runtime.block_on(collect_pipeline_warnings(async {
+        add_pipeline_warning("test", "limit clamped".to_string());
+        add_pipeline_warning("test", "limit clamped".to_string());
+        // Nested scopes (ex: in spawned tasks) share the same warnings.
+        let propagated = current_pipeline_warnings().scope(async {
+            add_pipeline_warning("other", "pathre ignored".to_string());
+        });
+        propagated.await;
+    }));
+    assert_eq!(
+        warnings,
+        vec![
+            PipelineWarning {
+                command: "test".to_string(),
+                message: "limit clamped".to_string(),
+            },
+            PipelineWarning {
+                command: "other".to_string(),
+                message: "pathre ignored".to_string(),
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_strict_mode_scope() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+
+    assert!(!strict_mode_enabled());
+    let (strict, warnings) = runtime.block_on(collect_pipeline_warnings(async {
+        assert!(!strict_mode_enabled());
+        // Only the pipeline that asked for strict mode gets it, but its
+        // warnings still go to the same collection.
+        current_pipeline_warnings()
+            .with_strict(true)
+            .scope(async {
+                add_pipeline_warning("test", "limit clamped".to_string());
+                (strict_mode_enabled(), check_strict_mode().is_err())
+            })
+            .await
+    }));
+    assert_eq!(strict, (true, true));
+    assert_eq!(warnings.len(), 1);
+    assert!(!strict_mode_enabled());
+}
+
+#[test]
+fn test_record_overload() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let overload = || OverloadInfo {
+        kind: super::interface::OverloadKind::NodeLimit,
+        sym: Some("foo".to_string()),
+        exist: 0,
+        included: 16,
+        local_limit: 0,
+        global_limit: 16,
+    };
+
+    let (failed, warnings) = runtime.block_on(collect_pipeline_warnings(async {
+        let mut overloads = vec![];
+        record_overload(&mut overloads, "traverse", overload());
+        assert_eq!(overloads.len(), 1);
+        check_strict_mode().is_err()
+    }));
+    assert!(!failed);
+    assert!(warnings.is_empty());
+
+    let (failed, warnings) = runtime.block_on(collect_pipeline_warnings(async {
+        current_pipeline_warnings()
+            .with_strict(true)
+            .scope(async {
+                let mut overloads = vec![];
+                record_overload(&mut overloads, "traverse", overload());
+                check_strict_mode().is_err()
+            })
+            .await
+    }));
+    assert!(failed);
+    assert_eq!(
+        warnings,
+        vec![PipelineWarning {
+            command: "traverse".to_string(),
+            message: "hit the global NodeLimit limit of 16 for foo, including 16 results"
+                .to_string(),
+        }]
+    );
+}
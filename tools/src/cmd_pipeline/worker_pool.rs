@@ -0,0 +1,586 @@
+//! A pool of pre-spawned pipeline worker processes that the pipeline-server
+//! can hand queries to over Unix domain sockets instead of running them
+//! in-process.  Each worker has already opened (and warmed) its trees before it
+//! starts listening, and runs one job at a time, so the web tier's latency is
+//! bounded by the number of workers rather than degrading for every request as
+//! load increases.
+//!
+//! The protocol is newline-delimited JSON: the pool writes a `WorkerJob` line
+//! and the worker answers with a `WorkerReply` line.  Workers classify their
+//! jobs for admission control themselves since that runs part of the job, so a
+//! worker whose job is expensive first answers with a `NeedsAdmission` reply
+//! and waits for a `WorkerGrant` line before it answers with the actual reply.
+//! The pool only ever gives a worker one job at a time, and applies
+//! backpressure by rejecting jobs once `max_queued` jobs are already waiting
+//! for a worker, which the pipeline-server surfaces as a 503.
+
+use std::{
+    env,
+    fs::DirBuilder,
+    os::unix::fs::DirBuilderExt,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    net::{
+        unix::{OwnedReadHalf, OwnedWriteHalf},
+        UnixListener, UnixStream,
+    },
+    process::Command,
+    sync::{Semaphore, SemaphorePermit},
+    time::{sleep, Duration},
+};
+use uuid::Uuid;
+
+use super::{
+    admission::{AdmissionControl, PlanCost},
+    diagnostics::PipelineDiagnostic,
+    warnings::PipelineWarning,
+};
+use crate::abstract_server::{ErrorDetails, ErrorLayer, Result, ServerError};
+
+/// How long to wait before respawning a worker process that exited.
+const WORKER_RESPAWN_DELAY: Duration = Duration::from_secs(1);
+
+/// How often to check whether the workers are listening at startup.
+const WORKER_READY_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// What a `WorkerJob`'s query is.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WorkerJobKind {
+    /// A query for the query planner.
+    #[default]
+    Query,
+    /// The query string of a legacy router.py "search" request, whose output
+    /// is the response's exact JSON as a string.
+    LegacySearch,
+}
+
+/// A query to run against a tree.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct WorkerJob {
+    pub tree: String,
+    pub query: String,
+    #[serde(default)]
+    pub kind: WorkerJobKind,
+}
+
+/// The serialized results of a successful `WorkerJob`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct WorkerOutput {
+    pub results: Value,
+    pub warnings: Vec<PipelineWarning>,
+    /// The SYM_INFO for the results, if their type has one, since the results
+    /// can no longer be inspected once serialized.
+    pub sym_info: Option<Value>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum WorkerReply {
+    Done(WorkerOutput),
+    /// The tree doesn't exist.
+    NoSuchTree,
+    Error {
+        error: WorkerError,
+    },
+    /// The query produced a pipeline that couldn't be parsed.
+    BadPipeline {
+        diagnostic: PipelineDiagnostic,
+    },
+    /// The job is expensive, and the worker is waiting for a `WorkerGrant`
+    /// before running it.  This is followed by another reply.
+    NeedsAdmission,
+    /// The job was expensive and not admitted, so it wasn't run.
+    NotAdmitted,
+}
+
+/// The pool's answer to a `NeedsAdmission` reply.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct WorkerGrant {
+    pub admitted: bool,
+}
+
+/// A `ServerError` other than `BadPipeline` in a form that can be sent back
+/// from a worker, so the pipeline-server can respond as if it had run the job
+/// itself.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum WorkerError {
+    Sticky { layer: ErrorLayer, message: String },
+    Transient { layer: ErrorLayer, message: String },
+    Unsupported,
+    Strict { warning: PipelineWarning },
+}
+
+impl WorkerReply {
+    /// The reply for a job that failed with the given error.
+    pub fn from_error(err: ServerError) -> Self {
+        let error = match err {
+            ServerError::BadPipeline(diagnostic) => {
+                return WorkerReply::BadPipeline { diagnostic };
+            }
+            ServerError::StickyProblem(ErrorDetails { layer, message }) => {
+                WorkerError::Sticky { layer, message }
+            }
+            ServerError::TransientProblem(ErrorDetails { layer, message }) => {
+                WorkerError::Transient { layer, message }
+            }
+            ServerError::Unsupported => WorkerError::Unsupported,
+            ServerError::StrictFailure(warning) => WorkerError::Strict { warning },
+        };
+        WorkerReply::Error { error }
+    }
+}
+
+impl From<WorkerError> for ServerError {
+    fn from(error: WorkerError) -> Self {
+        match error {
+            WorkerError::Sticky { layer, message } => {
+                ServerError::StickyProblem(ErrorDetails { layer, message })
+            }
+            WorkerError::Transient { layer, message } => {
+                ServerError::TransientProblem(ErrorDetails { layer, message })
+            }
+            WorkerError::Unsupported => ServerError::Unsupported,
+            WorkerError::Strict { warning } => ServerError::StrictFailure(warning),
+        }
+    }
+}
+
+/// Asks the pool for admission to run an expensive job.
+#[async_trait]
+pub trait WorkerAdmission: Send {
+    /// Wait for admission, returning false if the job shouldn't be run.
+    async fn admit(&mut self) -> Result<bool>;
+}
+
+/// Admits every job, for running jobs outside of the pool (ex: warming).
+pub struct AdmitAll;
+
+#[async_trait]
+impl WorkerAdmission for AdmitAll {
+    async fn admit(&mut self) -> Result<bool> {
+        Ok(true)
+    }
+}
+
+/// Runs the jobs a worker receives.
+#[async_trait]
+pub trait WorkerJobHandler: Send + Sync {
+    /// Run the job, asking for admission before doing anything expensive.
+    async fn run_job(&self, job: WorkerJob, admission: &mut dyn WorkerAdmission) -> WorkerReply;
+}
+
+fn worker_problem(message: String) -> ServerError {
+    ServerError::TransientProblem(ErrorDetails {
+        layer: ErrorLayer::ServerLayer,
+        message,
+    })
+}
+
+/// Create a directory for the worker sockets that only the current user can
+/// access, since anything that can connect to a worker can run queries without
+/// going through admission control.  This is `pipeline-workers` in
+/// `$XDG_RUNTIME_DIR` if there is one and otherwise a new directory in the
+/// temporary directory.
+pub fn create_private_socket_dir() -> std::io::Result<PathBuf> {
+    let socket_dir = match env::var_os("XDG_RUNTIME_DIR") {
+        Some(runtime_dir) => PathBuf::from(runtime_dir).join("pipeline-workers"),
+        None => env::temp_dir().join(format!("pipeline-workers-{}", Uuid::new_v4())),
+    };
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&socket_dir)?;
+    Ok(socket_dir)
+}
+
+/// The socket path of the given worker.
+pub fn worker_socket_path(socket_dir: &Path, worker: usize) -> PathBuf {
+    socket_dir.join(format!("pipeline-worker-{}.sock", worker))
+}
+
+/// Listen on the socket and run jobs until the process is killed.  Callers
+/// should do any warming before calling this since the pool treats a listening
+/// socket as a ready worker.
+pub async fn serve_worker(socket_path: &Path, handler: &dyn WorkerJobHandler) -> Result<()> {
+    // A previous incarnation of the worker may have left its socket behind.
+    let _ = std::fs::remove_file(socket_path);
+    let listener = UnixListener::bind(socket_path).map_err(|err| {
+        worker_problem(format!(
+            "unable to listen on {}: {}",
+            socket_path.display(),
+            err
+        ))
+    })?;
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                warn!("worker failed to accept a connection: {}", err);
+                continue;
+            }
+        };
+        // We deliberately handle a single connection at a time; anything else
+        // waits in the listen backlog.
+        if let Err(err) = serve_connection(stream, handler).await {
+            warn!("worker connection failed: {}", err);
+        }
+    }
+}
+
+async fn write_line<T: Serialize>(write: &mut OwnedWriteHalf, value: &T) -> std::io::Result<()> {
+    let mut bytes = serde_json::to_vec(value)?;
+    bytes.push(b'\n');
+    write.write_all(&bytes).await
+}
+
+/// Asks for admission over the job's connection.
+struct ConnectionAdmission<'a> {
+    lines: &'a mut Lines<BufReader<OwnedReadHalf>>,
+    write: &'a mut OwnedWriteHalf,
+}
+
+#[async_trait]
+impl<'a> WorkerAdmission for ConnectionAdmission<'a> {
+    async fn admit(&mut self) -> Result<bool> {
+        let exchange = async {
+            write_line(&mut *self.write, &WorkerReply::NeedsAdmission).await?;
+            let admitted = match self.lines.next_line().await? {
+                Some(line) => serde_json::from_str::<WorkerGrant>(&line)?.admitted,
+                // The pool hung up, so nobody wants the results anymore.
+                None => false,
+            };
+            Ok::<bool, std::io::Error>(admitted)
+        };
+        exchange
+            .await
+            .map_err(|err| worker_problem(format!("admission failed: {}", err)))
+    }
+}
+
+async fn serve_connection(
+    stream: UnixStream,
+    handler: &dyn WorkerJobHandler,
+) -> std::io::Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    while let Some(line) = lines.next_line().await? {
+        let reply = match serde_json::from_str::<WorkerJob>(&line) {
+            Ok(job) => {
+                let mut admission = ConnectionAdmission {
+                    lines: &mut lines,
+                    write: &mut write,
+                };
+                handler.run_job(job, &mut admission).await
+            }
+            Err(err) => WorkerReply::Error {
+                error: WorkerError::Sticky {
+                    layer: ErrorLayer::BadInput,
+                    message: format!("malformed job: {}", err),
+                },
+            },
+        };
+        write_line(&mut write, &reply).await?;
+    }
+    Ok(())
+}
+
+/// Spawn `count` worker processes by running `program` with `args` followed by
+/// the socket path of the worker, respawning any that exit.  The processes are
+/// killed if the pipeline-server exits (cleanly).
+pub fn spawn_worker_processes(
+    program: PathBuf,
+    args: Vec<String>,
+    socket_dir: &Path,
+    count: usize,
+) {
+    for worker in 0..count {
+        let program = program.clone();
+        let args = args.clone();
+        let socket_path = worker_socket_path(socket_dir, worker);
+        // Make sure we can't mistake a stale socket for a ready worker.
+        let _ = std::fs::remove_file(&socket_path);
+        tokio::spawn(async move {
+            loop {
+                let spawned = Command::new(&program)
+                    .args(&args)
+                    .arg(&socket_path)
+                    .kill_on_drop(true)
+                    .spawn();
+                match spawned {
+                    Ok(mut child) => match child.wait().await {
+                        Ok(status) => warn!("pipeline worker {} exited: {}", worker, status),
+                        Err(err) => warn!("pipeline worker {} wait failed: {}", worker, err),
+                    },
+                    Err(err) => warn!("unable to spawn pipeline worker {}: {}", worker, err),
+                }
+                let _ = std::fs::remove_file(&socket_path);
+                sleep(WORKER_RESPAWN_DELAY).await;
+            }
+        });
+    }
+}
+
+/// Dispatches jobs to the workers listening on the sockets in a directory.
+pub struct WorkerPool {
+    sockets: Vec<PathBuf>,
+    /// The indices of the workers not currently running a job.
+    idle: Mutex<Vec<usize>>,
+    /// One permit per idle worker.
+    permits: Semaphore,
+    /// Jobs that have been submitted but not finished, including running jobs.
+    outstanding: AtomicUsize,
+    max_queued: usize,
+}
+
+/// A submitted job's claim on the pool, which returns the worker to the pool
+/// even if the submitting future is dropped.
+struct Checkout<'a> {
+    pool: &'a WorkerPool,
+    worker: Option<usize>,
+    _permit: Option<SemaphorePermit<'a>>,
+}
+
+impl<'a> Drop for Checkout<'a> {
+    fn drop(&mut self) {
+        // If we were dropped mid-job, the worker will finish the job before it
+        // accepts its next connection, which is fine since that connection
+        // just waits a little longer.
+        if let Some(worker) = self.worker {
+            self.pool.idle.lock().unwrap().push(worker);
+        }
+        self.pool.outstanding.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl WorkerPool {
+    pub fn new(socket_dir: &Path, count: usize, max_queued: usize) -> Self {
+        WorkerPool {
+            sockets: (0..count)
+                .map(|worker| worker_socket_path(socket_dir, worker))
+                .collect(),
+            // Reversed so that jobs start out on the first worker.
+            idle: Mutex::new((0..count).rev().collect()),
+            permits: Semaphore::new(count),
+            outstanding: AtomicUsize::new(0),
+            max_queued,
+        }
+    }
+
+    /// Wait until every worker is listening.
+    pub async fn wait_until_ready(&self) {
+        for socket in &self.sockets {
+            while UnixStream::connect(socket).await.is_err() {
+                sleep(WORKER_READY_POLL_INTERVAL).await;
+            }
+        }
+    }
+
+    /// Run the job on the next idle worker, returning None without running it
+    /// if too many jobs are already waiting for a worker.  If the worker finds
+    /// the job expensive, it's admitted through `admission` and the admission
+    /// is held until the job finishes.  Jobs that aren't admitted get a
+    /// `NotAdmitted` reply.
+    pub async fn submit(
+        &self,
+        job: &WorkerJob,
+        admission: &AdmissionControl,
+    ) -> Result<Option<WorkerReply>> {
+        let outstanding = self.outstanding.fetch_add(1, Ordering::SeqCst);
+        let mut checkout = Checkout {
+            pool: self,
+            worker: None,
+            _permit: None,
+        };
+        if outstanding >= self.sockets.len() + self.max_queued {
+            return Ok(None);
+        }
+
+        // The semaphore is never closed.
+        checkout._permit = Some(self.permits.acquire().await.unwrap());
+        let worker = match self.idle.lock().unwrap().pop() {
+            Some(worker) => worker,
+            None => {
+                return Err(ServerError::StickyProblem(ErrorDetails {
+                    layer: ErrorLayer::RuntimeInvariantViolation,
+                    message: "worker permit without an idle worker".to_string(),
+                }))
+            }
+        };
+        checkout.worker = Some(worker);
+
+        let reply = self
+            .exchange(worker, job, admission)
+            .await
+            .map_err(|err| worker_problem(format!("pipeline worker {} failed: {}", worker, err)))?;
+        match reply {
+            Some(reply) => Ok(Some(reply)),
+            None => Err(worker_problem(format!(
+                "pipeline worker {} hung up without replying",
+                worker
+            ))),
+        }
+    }
+
+    /// Send the job to the worker and return its reply, handling any
+    /// admission request along the way.  Returns None if the worker hung up.
+    async fn exchange(
+        &self,
+        worker: usize,
+        job: &WorkerJob,
+        admission: &AdmissionControl,
+    ) -> std::io::Result<Option<WorkerReply>> {
+        let stream = UnixStream::connect(&self.sockets[worker]).await?;
+        let (read, mut write) = stream.into_split();
+        let mut lines = BufReader::new(read).lines();
+        write_line(&mut write, job).await?;
+        // Held until the job finishes.
+        let mut admitted = None;
+        loop {
+            let reply = match lines.next_line().await? {
+                Some(line) => serde_json::from_str::<WorkerReply>(&line)?,
+                None => return Ok(None),
+            };
+            if !matches!(reply, WorkerReply::NeedsAdmission) {
+                return Ok(Some(reply));
+            }
+            admitted = admission.admit(PlanCost::Expensive).await;
+            let grant = WorkerGrant {
+                admitted: admitted.is_some(),
+            };
+            write_line(&mut write, &grant).await?;
+        }
+    }
+}
+
+#[test]
+fn test_worker_pool() {
+    use tokio::sync::Notify;
+
+    struct EchoHandler {
+        release: Notify,
+    }
+
+    #[async_trait]
+    impl WorkerJobHandler for EchoHandler {
+        async fn run_job(
+            &self,
+            job: WorkerJob,
+            admission: &mut dyn WorkerAdmission,
+        ) -> WorkerReply {
+            if job.query == "block" {
+                self.release.notified().await;
+            }
+            if job.query == "expensive" && !admission.admit().await.unwrap() {
+                return WorkerReply::NotAdmitted;
+            }
+            WorkerReply::Done(WorkerOutput {
+                results: Value::String(job.query),
+                warnings: vec![],
+                sym_info: None,
+            })
+        }
+    }
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let socket_dir = std::env::temp_dir().join(format!("worker-pool-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&socket_dir).unwrap();
+        let handler: &'static EchoHandler = Box::leak(Box::new(EchoHandler {
+            release: Notify::new(),
+        }));
+        let socket_path = worker_socket_path(&socket_dir, 0);
+        tokio::spawn(async move { serve_worker(&socket_path, handler).await });
+
+        let pool = WorkerPool::new(&socket_dir, 1, 1);
+        pool.wait_until_ready().await;
+        let admission = AdmissionControl::new(1, 0);
+        let job = |query: &str| WorkerJob {
+            tree: "tests".to_string(),
+            query: query.to_string(),
+            kind: WorkerJobKind::Query,
+        };
+        match pool.submit(&job("foo"), &admission).await.unwrap() {
+            Some(WorkerReply::Done(output)) => assert_eq!(output.results, "foo"),
+            other => panic!("unexpected reply {:?}", other),
+        }
+
+        // With the worker busy and one job queued, the next job is rejected.
+        let (running, queued, rejected) = tokio::join!(
+            pool.submit(&job("block"), &admission),
+            pool.submit(&job("queued"), &admission),
+            async {
+                let rejected = pool.submit(&job("rejected"), &admission).await;
+                handler.release.notify_one();
+                rejected
+            }
+        );
+        assert!(matches!(running, Ok(Some(WorkerReply::Done(_)))));
+        assert!(matches!(queued, Ok(Some(WorkerReply::Done(_)))));
+        assert!(matches!(rejected, Ok(None)));
+
+        // Expensive jobs are only run once they're admitted.
+        let running = admission.admit(PlanCost::Expensive).await;
+        assert!(matches!(
+            pool.submit(&job("expensive"), &admission).await,
+            Ok(Some(WorkerReply::NotAdmitted))
+        ));
+        drop(running);
+        assert!(matches!(
+            pool.submit(&job("expensive"), &admission).await,
+            Ok(Some(WorkerReply::Done(_)))
+        ));
+
+        std::fs::remove_dir_all(&socket_dir).unwrap();
+    });
+}
+
+#[test]
+fn test_worker_reply_from_error() {
+    let round_trip = |err: ServerError| -> ServerError {
+        let reply = WorkerReply::from_error(err);
+        match serde_json::from_str(&serde_json::to_string(&reply).unwrap()).unwrap() {
+            WorkerReply::Error { error } => error.into(),
+            other => panic!("unexpected reply {:?}", other),
+        }
+    };
+
+    match round_trip(ServerError::TransientProblem(ErrorDetails {
+        layer: ErrorLayer::CodesearchLayer,
+        message: "timed out".to_string(),
+    })) {
+        ServerError::TransientProblem(ErrorDetails {
+            layer: ErrorLayer::CodesearchLayer,
+            message,
+        }) => assert_eq!(message, "timed out"),
+        other => panic!("unexpected error {:?}", other),
+    }
+    assert!(matches!(
+        round_trip(ServerError::StickyProblem(ErrorDetails {
+            layer: ErrorLayer::DataLayer,
+            message: "not indexed".to_string(),
+        })),
+        ServerError::StickyProblem(ErrorDetails {
+            layer: ErrorLayer::DataLayer,
+            ..
+        })
+    ));
+    assert!(matches!(
+        round_trip(ServerError::Unsupported),
+        ServerError::Unsupported
+    ));
+}
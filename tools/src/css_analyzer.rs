@@ -57,6 +57,7 @@ fn to_source(
             type_sym: None,
             arg_ranges: vec![],
             expansion_info: None,
+            origin: None,
         },
         loc,
     }
@@ -81,6 +82,7 @@ fn to_target(
                 end_lineno: 0,
             },
             arg_ranges: vec![],
+            origin: None,
         },
         loc,
     }
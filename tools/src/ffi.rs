@@ -0,0 +1,178 @@
+//! A small C ABI for running pipelines in-process so that infrastructure not
+//! written in Rust (ex: the web front-end) can embed query execution rather
+//! than spawning `searchfox-tool` for every query.  The crate is built as a
+//! `cdylib` for this purpose and `searchfox_ffi.h` declares the functions.
+//!
+//! The interface is deliberately JSON-in, JSON-out so that it can stay stable
+//! as the pipeline commands and their result types evolve:
+//! - The plan is `{ "pipeline": "..." }` where the pipeline is exactly what
+//!   would be passed to `searchfox-tool`, including any `--server` and
+//!   `--tree` arguments on the first command.  Only the commands allowed for
+//!   the web are available, and the arguments that configure the
+//!   searchfox-tool process (ex: `--progress`, `--trace-output`) are ignored,
+//!   but `--strict` applies to the query, failing it with a "strict" error.
+//! - The result is `{ "result": ..., "warnings": [...] }` on success, where
+//!   `result` is the serialized `PipelineValues`, or `{ "error": { "kind",
+//!   "layer", "message" } }` on failure.
+
+use std::{
+    ffi::{CStr, CString},
+    os::raw::c_char,
+    panic::{catch_unwind, AssertUnwindSafe},
+    ptr,
+};
+
+use serde::Deserialize;
+use serde_json::{json, to_value, Value};
+use tokio::runtime::Runtime;
+
+use crate::{
+    abstract_server::{ErrorDetails, ErrorLayer, Result, ServerError},
+    cmd_pipeline::{
+        builder::{build_pipeline_with_safety, CommandSafetyLevel},
+        warnings::collect_pipeline_warnings,
+    },
+};
+
+#[derive(Deserialize)]
+struct QueryPlan {
+    pipeline: String,
+}
+
+lazy_static! {
+    /// All queries share a single runtime which is created on first use; the
+    /// embedding process calls us synchronously from whatever threads it has.
+    static ref RUNTIME: Runtime = Runtime::new().expect("Unable to create tokio runtime");
+}
+
+fn error_to_json(err: ServerError) -> Value {
+    let (kind, details) = match err {
+        ServerError::StickyProblem(details) => ("sticky", Some(details)),
+        ServerError::TransientProblem(details) => ("transient", Some(details)),
+        ServerError::Unsupported => ("unsupported", None),
+        ServerError::BadPipeline(diagnostic) => {
+            return json!({
+                "error": {
+                    "kind": "bad-pipeline",
+                    "diagnostic": diagnostic,
+                }
+            });
+        }
+        ServerError::StrictFailure(warning) => {
+            return json!({
+                "error": {
+                    "kind": "strict",
+                    "warning": warning,
+                }
+            });
+        }
+    };
+    match details {
+        Some(ErrorDetails { layer, message }) => json!({
+            "error": {
+                "kind": kind,
+                "layer": format!("{:?}", layer),
+                "message": message,
+            }
+        }),
+        None => json!({ "error": { "kind": kind } }),
+    }
+}
+
+fn run_query(json_plan: &str) -> Result<Value> {
+    let plan: QueryPlan = serde_json::from_str(json_plan).map_err(|err| {
+        ServerError::StickyProblem(ErrorDetails {
+            layer: ErrorLayer::BadInput,
+            message: format!("Bad query plan: {}", err),
+        })
+    })?;
+    // We're running inside someone else's process on behalf of its users, so
+    // only the commands the web can use are allowed.
+    let (pipeline, _output_format) = build_pipeline_with_safety(
+        "sf_run_query",
+        &plan.pipeline,
+        CommandSafetyLevel::WebSafety,
+    )?;
+    let (results, warnings) = RUNTIME.block_on(collect_pipeline_warnings(pipeline.run(false)));
+    Ok(json!({
+        "result": to_value(results?)?,
+        "warnings": warnings,
+    }))
+}
+
+/// Run the pipeline described by the NUL-terminated UTF-8 JSON `json_plan`,
+/// returning a NUL-terminated UTF-8 JSON result that must be released with
+/// `sf_free_result`.  Errors (including panics) are reported in the result
+/// rather than by returning null; null is only returned if the result itself
+/// couldn't be allocated as a C string.
+///
+/// # Safety
+///
+/// `json_plan` must be a valid pointer to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn sf_run_query(json_plan: *const c_char) -> *mut c_char {
+    let value = if json_plan.is_null() {
+        error_to_json(ServerError::StickyProblem(ErrorDetails {
+            layer: ErrorLayer::BadInput,
+            message: "Null query plan".to_string(),
+        }))
+    } else {
+        let plan = CStr::from_ptr(json_plan).to_string_lossy();
+        // Unwinding across the FFI boundary is undefined behavior, so we have
+        // to turn panics into errors here.
+        match catch_unwind(AssertUnwindSafe(|| run_query(&plan))) {
+            Ok(Ok(value)) => value,
+            Ok(Err(err)) => error_to_json(err),
+            Err(_) => error_to_json(ServerError::StickyProblem(ErrorDetails {
+                layer: ErrorLayer::ServerLayer,
+                message: "Panic while running query".to_string(),
+            })),
+        }
+    };
+
+    match CString::new(value.to_string()) {
+        Ok(result) => result.into_raw(),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Release a result returned by `sf_run_query`.  Passing null is a no-op.
+///
+/// # Safety
+///
+/// `result` must be null or a pointer returned by `sf_run_query` which has
+/// not already been freed.
+#[no_mangle]
+pub unsafe extern "C" fn sf_free_result(result: *mut c_char) {
+    if !result.is_null() {
+        drop(CString::from_raw(result));
+    }
+}
+
+#[test]
+fn test_run_query_bad_plan() {
+    let plan = CString::new("{\"pipe\": \"\"}").unwrap();
+    unsafe {
+        let result = sf_run_query(plan.as_ptr());
+        let value: Value = serde_json::from_str(CStr::from_ptr(result).to_str().unwrap()).unwrap();
+        sf_free_result(result);
+        assert_eq!(value["error"]["kind"], "sticky");
+        assert_eq!(value["error"]["layer"], "BadInput");
+    }
+}
+
+#[test]
+fn test_run_query_web_safety() {
+    // Commands that write files aren't available to embedders.
+    let plan = CString::new(
+        json!({ "pipeline": "--server=https://searchfox.org/ index-stats" }).to_string(),
+    )
+    .unwrap();
+    unsafe {
+        let result = sf_run_query(plan.as_ptr());
+        let value: Value = serde_json::from_str(CStr::from_ptr(result).to_str().unwrap()).unwrap();
+        sf_free_result(result);
+        assert_eq!(value["error"]["kind"], "bad-pipeline");
+        assert_eq!(value["error"]["diagnostic"]["kind"], "not-allowed");
+    }
+}
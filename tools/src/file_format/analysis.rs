@@ -30,6 +30,7 @@ use ustr::{ustr, Ustr, UstrMap};
 type Ustr = String;
 
 use super::ontology_pointer_kind::OntologyPointerKind;
+use super::structured_schema::upgrade_structured_record;
 
 #[derive(Copy, Clone, Default, Eq, PartialEq, PartialOrd, Ord, Debug)]
 pub struct Location {
@@ -79,6 +80,27 @@ impl SourceRange {
         // we allow both 0 and u32::MAX as sentinel values.
         self.start_lineno == 0 || self.start_lineno == u32::MAX
     }
+
+    /// The single-line range covered by a record's `loc`.
+    pub fn from_location(loc: &Location) -> SourceRange {
+        SourceRange {
+            start_lineno: loc.lineno,
+            start_col: loc.col_start,
+            end_lineno: loc.lineno,
+            end_col: loc.col_end,
+        }
+    }
+
+    /// Do the ranges share at least one position?  The ends are treated as
+    /// exclusive like the `col_end` of a record's `loc`, so ranges that only
+    /// touch don't overlap and a single position needs a one column range.
+    /// Empty ranges overlap nothing.
+    pub fn overlaps(&self, other: &SourceRange) -> bool {
+        !self.is_empty()
+            && !other.is_empty()
+            && (self.start_lineno, self.start_col) < (other.end_lineno, other.end_col)
+            && (other.start_lineno, other.start_col) < (self.end_lineno, self.end_col)
+    }
 }
 
 impl SourceRange {
@@ -209,16 +231,20 @@ where
     pub peek_range: LineRange,
     #[serde(rename = "argRanges", default, skip_serializing_if = "Vec::is_empty")]
     pub arg_ranges: Vec<SourceRange>,
+    /// The indexer which produced this record, which the indexers don't
+    /// currently emit; see `docs/analysis.md`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<StrT>,
 }
 
 /// See TargetTag for more info
-#[derive(Debug, Eq, PartialEq, Serialize_repr, Deserialize_repr)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize_repr, Deserialize_repr)]
 #[repr(u8)]
 pub enum StructuredTag {
     Structured = 1,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct StructuredSuperInfo<StrT = Ustr>
 where
     StrT: Clone + Debug + Default + Deref<Target = str> + FromStr + Hash + Ord + PartialEq,
@@ -231,7 +257,7 @@ where
     pub props: Vec<StrT>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct StructuredArgInfo<StrT = Ustr>
 where
     StrT: Clone + Debug + Default + Deref<Target = str> + FromStr + Hash + Ord + PartialEq,
@@ -243,7 +269,7 @@ where
     pub type_sym: StrT,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct StructuredMethodInfo<StrT = Ustr>
 where
     StrT: Clone + Debug + Default + Deref<Target = str> + FromStr + Hash + Ord + PartialEq,
@@ -266,12 +292,28 @@ pub struct StructuredBitPositionInfo {
     pub width: u32,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct StructuredOverrideInfo<StrT = Ustr> {
     #[serde(default)]
     pub sym: StrT,
 }
 
+/// The template an explicit (or partial) specialization specializes.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StructuredSpecializationInfo<StrT = Ustr> {
+    #[serde(default)]
+    pub sym: StrT,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StructuredEnumeratorInfo<StrT = Ustr> {
+    #[serde(default)]
+    pub pretty: StrT,
+    #[serde(default)]
+    pub sym: StrT,
+    pub value: Option<i64>,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct StructuredFieldInfo<StrT = Ustr>
 where
@@ -304,6 +346,9 @@ where
     pub bit_positions: Option<StructuredBitPositionInfo>,
     #[serde(rename = "sizeBytes")]
     pub size_bytes: Option<u32>,
+    /// For fields of enum type, the enum's underlying integer type.
+    #[serde(rename = "underlyingType", skip_serializing_if = "Option::is_none")]
+    pub underlying_type: Option<StrT>,
     #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
     pub labels: BTreeSet<StrT>,
     #[serde(default, rename = "pointerInfo", skip_serializing_if = "Vec::is_empty")]
@@ -412,7 +457,7 @@ pub struct BindingSlotProps {
     #[serde(rename = "ownerLang")]
     pub owner_lang: BindingOwnerLang,
 }
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct StructuredBindingSlotInfo<StrT = Ustr>
 where
     StrT: Clone + Debug + Default + Deref<Target = str> + FromStr + Hash + Ord + PartialEq,
@@ -489,7 +534,7 @@ pub enum OntologySlotKind {
 /// extensive that a normal faceting UI would be of dubious utility because the
 /// user should probably just keep typing if they are interested in a specific
 /// preference.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct OntologySlotInfo<StrT = Ustr>
 where
     StrT: Clone + Debug + Default + Deref<Target = str> + FromStr + Hash + Ord + PartialEq,
@@ -509,7 +554,7 @@ where
 /// Structured records are merged by choosing one platform rep to be the canonical variant and
 /// embedding the other variants observed under a `variants` attribute.  See `analysis.md` and
 /// `merge-analyses.rs` for more details.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct AnalysisStructured<StrT = Ustr>
 where
     StrT: Clone + Debug + Default + Deref<Target = str> + FromStr + Hash + Ord + PartialEq,
@@ -545,6 +590,15 @@ where
     pub size_bytes: Option<u32>,
     #[serde(rename = "ownVFPtrBytes")]
     pub own_vf_ptr_bytes: Option<u32>,
+    #[serde(rename = "alignBytes", skip_serializing_if = "Option::is_none")]
+    pub align_bytes: Option<u32>,
+    /// For packed records, the most their fields can be aligned to, which is 1
+    /// for `__attribute__((packed))`.
+    #[serde(rename = "maxFieldAlignBytes", skip_serializing_if = "Option::is_none")]
+    pub max_field_align_bytes: Option<u32>,
+    /// For enums, the underlying integer type.
+    #[serde(rename = "underlyingType", skip_serializing_if = "Option::is_none")]
+    pub underlying_type: Option<StrT>,
 
     #[serde(rename = "bindingSlots", default)]
     pub binding_slots: Vec<StructuredBindingSlotInfo<StrT>>,
@@ -559,8 +613,16 @@ where
     // this really is not sufficient.
     #[serde(default)]
     pub fields: Vec<StructuredFieldInfo<StrT>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub enumerators: Vec<StructuredEnumeratorInfo<StrT>>,
     #[serde(default)]
     pub overrides: Vec<StructuredOverrideInfo<StrT>>,
+    #[serde(
+        rename = "specializationOf",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub specialization_of: Vec<StructuredSpecializationInfo<StrT>>,
     #[serde(default)]
     pub props: Vec<StrT>,
     #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
@@ -580,6 +642,12 @@ where
         skip_serializing_if = "Vec::is_empty"
     )]
     pub overridden_by_syms: Vec<StrT>,
+    #[serde(
+        rename = "specializations",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub specialization_syms: Vec<StrT>,
 
     #[serde(default)]
     pub variants: Vec<AnalysisStructured<StrT>>,
@@ -754,6 +822,9 @@ where
     pub arg_ranges: Vec<SourceRange>,
     #[serde(flatten, skip_serializing_if = "Option::is_none")]
     pub expansion_info: Option<ExpansionInfo>,
+    /// The indexer which produced this record, as for `AnalysisTarget`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<StrT>,
 }
 
 impl<StrT> AnalysisSource<StrT>
@@ -800,6 +871,11 @@ where
         if let Some(type_sym) = other.type_sym {
             self.type_sym.get_or_insert(type_sym);
         }
+        // Different indexers shouldn't be producing records for the same
+        // (loc, pretty), so just keep the first origin we saw.
+        if let Some(origin) = other.origin {
+            self.origin.get_or_insert(origin);
+        }
 
         use ExpansionInfo::*;
         match (&mut self.expansion_info, &mut other.expansion_info) {
@@ -934,7 +1010,7 @@ impl<'de> Deserialize<'de> for LineRange {
     }
 }
 
-fn parse_source_range(range: &str) -> SourceRange {
+pub fn parse_source_range(range: &str) -> SourceRange {
     let v: Vec<&str> = range.split(&['-', ':'][..]).collect();
     if v.len() != 4 {
         return SourceRange::default();
@@ -951,6 +1027,22 @@ fn parse_source_range(range: &str) -> SourceRange {
     }
 }
 
+/// Does the raw analysis record's `loc` or, for source records that have one,
+/// its `nestingRange` overlap the given range?
+pub fn analysis_record_overlaps(record: &Value, range: &SourceRange) -> bool {
+    let loc = match record.get("loc").and_then(Value::as_str) {
+        Some(loc) => parse_location(loc),
+        None => return false,
+    };
+    if SourceRange::from_location(&loc).overlaps(range) {
+        return true;
+    }
+    match record.get("nestingRange").and_then(Value::as_str) {
+        Some(nesting_range) => parse_source_range(nesting_range).overlaps(range),
+        None => false,
+    }
+}
+
 impl Serialize for SourceRange {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -1092,15 +1184,39 @@ pub fn read_target(obj: Value, _loc: &Location, _i_size: usize) -> Option<Analys
     from_value(obj).ok()
 }
 
+/// Read a structured record, upgrading it to the current schema version first.
+/// Records that can't be upgraded or deserialized are warned about rather than
+/// silently dropped because that usually means the indexers have evolved the
+/// format without the Rust consumers being updated.
 pub fn read_structured(
-    obj: Value,
+    mut obj: Value,
     _loc: &Location,
     _i_size: usize,
 ) -> Option<AnalysisStructured<Ustr>> {
+    let map = obj.as_object_mut()?;
     // XXX this shouldn't be necessary thanks to our tag, so this should be removable
-    obj.get("structured")?;
+    map.get("structured")?;
+
+    if let Err(_issue) = upgrade_structured_record(map) {
+        #[cfg(not(target_arch = "wasm32"))]
+        warn!(
+            "Unable to upgrade structured record at line [{}]: {}",
+            _loc.lineno, _issue.message
+        );
+        return None;
+    }
 
-    from_value(obj).ok()
+    match from_value(obj) {
+        Ok(structured) => Some(structured),
+        Err(_err) => {
+            #[cfg(not(target_arch = "wasm32"))]
+            warn!(
+                "Unable to deserialize structured record at line [{}]: {}",
+                _loc.lineno, _err
+            );
+            None
+        }
+    }
 }
 
 pub fn read_source(obj: Value, _loc: &Location, _i_size: usize) -> Option<AnalysisSource<Ustr>> {
@@ -1173,11 +1289,33 @@ pub struct SearchResult {
         skip_serializing_if = "LineRange::is_empty"
     )]
     pub peek_range: LineRange,
+    /// The `origin` of the target record this hit was derived from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<Ustr>,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct PathSearchResult {
     pub path: Ustr,
     pub path_kind: Ustr,
     pub lines: Vec<SearchResult>,
 }
+
+#[test]
+fn test_source_range_overlaps() {
+    let range = |start_lineno, start_col, end_lineno, end_col| SourceRange {
+        start_lineno,
+        start_col,
+        end_lineno,
+        end_col,
+    };
+    let token = range(5, 4, 5, 7);
+    assert!(token.overlaps(&range(5, 4, 5, 5)));
+    assert!(token.overlaps(&range(5, 6, 5, 7)));
+    assert!(token.overlaps(&range(1, 0, 9, 0)));
+    // Adjacent ranges don't overlap.
+    assert!(!token.overlaps(&range(5, 7, 5, 8)));
+    assert!(!token.overlaps(&range(5, 0, 5, 4)));
+    assert!(!range(5, 7, 5, 8).overlaps(&token));
+    assert!(!token.overlaps(&SourceRange::default()));
+}
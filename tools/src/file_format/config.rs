@@ -8,6 +8,8 @@ use serde::{Deserialize, Serialize};
 
 use git2::{Oid, Repository};
 
+use crate::url_encode_path::url_encode_path;
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum TreeCaching {
@@ -89,10 +91,103 @@ pub struct TreeConfigPaths {
     /// Manually allocated port number to host the livegrep server on, starting
     /// from 8081 why not.
     pub codesearch_port: u32,
+    /// If the tree's fulltext index is sharded across multiple codesearch
+    /// backends, the backends to fan text searches out to, in which case
+    /// `codesearch_port` is ignored for searching.  Shards are expected to
+    /// index disjoint sets of files.
+    #[serde(default)]
+    pub codesearch_shards: Vec<CodesearchShardConfig>,
     /// Definitions of SCIP-based indexes to ingest.  Currently it's expected
     /// that the build script will handle downloading or generating the indexes.
     #[serde(default)]
     pub scip_subtrees: BTreeMap<String, ScipSubtreeConfig>,
+    /// Sub-repositories (ex: git submodules or separately checked out repos)
+    /// aggregated into this tree, keyed by the tree-relative path of the
+    /// directory the repo is checked out at (no trailing `/`).  Files under
+    /// these prefixes are considered to belong to the sub-repo for purposes of
+    /// annotating results and generating upstream permalinks.
+    #[serde(default)]
+    pub sub_repos: BTreeMap<String, SubRepoConfig>,
+    /// How search results should be ordered and collapsed based on where they
+    /// live.  If omitted, path kinds are ordered as in the default
+    /// `per-file-info.toml` and third-party and objdir files are pushed to the
+    /// bottom of their groups, but nothing is collapsed.
+    #[serde(default)]
+    pub result_demotion: ResultDemotionConfig,
+    /// How the server retries operations that fail in ways that might go away
+    /// on their own, like livegrep restarting.
+    #[serde(default)]
+    pub retry_policy: RetryPolicyConfig,
+    /// How the `redact` command anonymizes results.  By default authors are
+    /// hashed and only the tree's absolute directories are rewritten.
+    #[serde(default)]
+    pub redaction: RedactionConfig,
+    /// External processes the `rerank` command can use to re-order results,
+    /// keyed by the name passed to its `--ranker`.
+    #[serde(default)]
+    pub external_rankers: BTreeMap<String, ExternalRankerConfig>,
+    /// Named groups of symbols for concepts that span many symbols (ex: the
+    /// cycle collection macros) which can be queried as a single virtual
+    /// symbol, keyed by the group name.
+    #[serde(default)]
+    pub virtual_symbols: BTreeMap<String, VirtualSymbolConfig>,
+    /// Bounds on the serialized size of each pipeline stage's output so that
+    /// runaway query plans can't produce multi-GB responses.  When omitted,
+    /// pipeline-server applies its `--max-stage-output-bytes` and other tools
+    /// don't limit the output.
+    #[serde(default)]
+    pub output_size_guard: Option<OutputSizeGuardConfig>,
+    /// How files reachable via multiple indexed paths (ex: headers symlinked
+    /// into an objdir include directory) get folded together in search
+    /// results.  Nothing is folded by default.
+    #[serde(default)]
+    pub path_aliases: PathAliasConfig,
+    /// Annotations (ex: deprecation notices) surfaced alongside every search
+    /// that touches the symbols they match.
+    #[serde(default)]
+    pub symbol_annotations: SymbolAnnotationConfig,
+}
+
+impl TreeConfigPaths {
+    /// Find the sub-repo the given tree-relative path belongs to, returning its
+    /// path prefix and config.  When sub-repos are nested, the most specific
+    /// (longest) prefix wins.
+    pub fn sub_repo_for_path(&self, path: &str) -> Option<(&str, &SubRepoConfig)> {
+        self.sub_repos
+            .iter()
+            .filter(|(prefix, _)| {
+                path.strip_prefix(prefix.as_str())
+                    .map_or(false, |rest| rest.is_empty() || rest.starts_with('/'))
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(prefix, config)| (prefix.as_str(), config))
+    }
+
+    /// The gRPC endpoints of the codesearch backends to issue text searches
+    /// against, which is just the local `codesearch_port` unless the tree has
+    /// `codesearch_shards`.
+    pub fn codesearch_endpoints(&self) -> Vec<String> {
+        if self.codesearch_shards.is_empty() {
+            return vec![format!("http://localhost:{}", self.codesearch_port)];
+        }
+        self.codesearch_shards
+            .iter()
+            .map(|shard| format!("http://{}:{}", shard.host, shard.port))
+            .collect()
+    }
+}
+
+fn default_codesearch_host() -> String {
+    "localhost".to_string()
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CodesearchShardConfig {
+    /// The host the shard's codesearch server runs on.
+    #[serde(default = "default_codesearch_host")]
+    pub host: String,
+    /// The port the shard's codesearch server is hosted on.
+    pub port: u32,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -111,6 +206,356 @@ pub struct ScipSubtreeConfig {
     pub subtree_root: String,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SubRepoConfig {
+    /// Human-readable name for the repo used when annotating results.
+    pub name: String,
+    /// Absolute path to the sub-repo's `.git` directory (or a checkout of it)
+    /// which is used to determine its revision if `revision` isn't specified.
+    pub git_path: Option<String>,
+    /// The revision of the sub-repo that's checked out in the tree, ex: from a
+    /// submodule gitlink or a vendoring manifest.
+    pub revision: Option<String>,
+    /// If the sub-repo is hosted on github, its URL, no trailing `/`.
+    pub github_repo: Option<String>,
+    /// If the sub-repo is a mercurial repo, the URL of the hg server, no
+    /// trailing `/`.
+    pub hg_root: Option<String>,
+}
+
+impl SubRepoConfig {
+    /// Determine the sub-repo's revision, preferring the explicitly configured
+    /// revision and falling back to the HEAD of its git repo.
+    pub fn resolve_revision(&self) -> Option<String> {
+        if let Some(revision) = &self.revision {
+            return Some(revision.clone());
+        }
+        let repo = Repository::open(self.git_path.as_ref()?).ok()?;
+        let head_oid = repo.refname_to_id("HEAD").ok()?;
+        Some(head_oid.to_string())
+    }
+
+    /// Build a link to the given repo-relative path at the given revision on
+    /// the sub-repo's upstream server, if we know what that is.  Line anchors
+    /// can be added via `line_anchor_pattern`.
+    pub fn permalink(&self, revision: &str, repo_path: &str) -> Option<String> {
+        let encoded_path = url_encode_path(repo_path);
+        if let Some(github_repo) = &self.github_repo {
+            return Some(format!(
+                "{}/blob/{}/{}",
+                github_repo, revision, encoded_path
+            ));
+        }
+        self.hg_root
+            .as_ref()
+            .map(|hg_root| format!("{}/file/{}/{}", hg_root, revision, encoded_path))
+    }
+
+    /// The pattern to append to `permalink` links to target a line, where `{}`
+    /// is replaced by the line number.
+    pub fn line_anchor_pattern(&self) -> &'static str {
+        if self.github_repo.is_some() {
+            "#L{}"
+        } else {
+            "#l{}"
+        }
+    }
+}
+
+/// Retry policy for transient failures (`ServerError::TransientProblem`) of
+/// codesearch requests and index file reads.  Delays start at
+/// `initial_backoff_ms` and double after each failed attempt up to
+/// `max_backoff_ms`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicyConfig {
+    /// Total number of attempts including the first; 1 disables retries.
+    pub attempts: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+}
+
+impl Default for RetryPolicyConfig {
+    fn default() -> Self {
+        RetryPolicyConfig {
+            attempts: 3,
+            initial_backoff_ms: 50,
+            max_backoff_ms: 1000,
+        }
+    }
+}
+
+/// What to do when a pipeline stage's output exceeds
+/// `OutputSizeGuardConfig::max_stage_output_bytes`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SizeGuardAction {
+    /// Fail the pipeline with an error.
+    Abort,
+    /// Drop trailing items of list-like outputs (ex: symbols, files, JSON
+    /// values) until the output fits, reporting a pipeline warning.  Outputs
+    /// that can't be truncated are aborted instead.
+    Truncate,
+}
+
+/// Limit on the size of the output of each pipeline stage, as measured by the
+/// size of its JSON serialization.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutputSizeGuardConfig {
+    /// The maximum number of bytes; 0 disables the guard.
+    pub max_stage_output_bytes: u64,
+    pub on_exceeded: SizeGuardAction,
+}
+
+impl Default for OutputSizeGuardConfig {
+    fn default() -> Self {
+        OutputSizeGuardConfig {
+            max_stage_output_bytes: 0,
+            on_exceeded: SizeGuardAction::Abort,
+        }
+    }
+}
+
+fn default_ranker_timeout_ms() -> u64 {
+    5000
+}
+
+/// An external scorer for the `rerank` command, which lets ranking experiments
+/// (ex: ML-based) happen outside of searchfox.  The process is run once per
+/// `rerank` and is sent a single JSON object of the form `{ kind, items }` on
+/// stdin and must write `{ scores }` with one number per item to stdout, where
+/// higher scores are ranked first.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExternalRankerConfig {
+    /// The program to run followed by its arguments.
+    pub command: Vec<String>,
+    /// How long to wait for the ranker's scores before failing.
+    #[serde(default = "default_ranker_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+/// Virtual symbols are named `VIRTUAL_{name}` after their config key.
+pub const VIRTUAL_SYMBOL_PREFIX: &str = "VIRTUAL_";
+
+/// A named group of symbols that can be queried as one virtual symbol, either
+/// via its `VIRTUAL_`-prefixed symbol or by searching for its name as an
+/// identifier.  Its members are the union of the explicitly listed symbols and
+/// the symbols of all identifiers matching any of the patterns.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct VirtualSymbolConfig {
+    /// Human-readable explanation of what the group represents.
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Exact (raw) symbols that are members of the group.
+    #[serde(default)]
+    pub symbols: Vec<String>,
+    /// Regexps matched against identifiers, like `search-identifiers --symre`,
+    /// whose symbols are members of the group.
+    #[serde(default)]
+    pub identifier_patterns: Vec<String>,
+}
+
+/// How the `redact` command treats author names.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthorRedaction {
+    Keep,
+    /// Replace the author with an empty string.
+    Strip,
+    /// Replace the author with a salted hash so that results by the same
+    /// author can still be correlated.
+    Hash,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PathRewriteRule {
+    /// Regular expression matched against every string value.
+    pub pattern: String,
+    /// The replacement, which may reference capture groups like "$1".
+    pub replacement: String,
+}
+
+/// How the `redact` command anonymizes results before they are shared outside
+/// of the organization.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RedactionConfig {
+    pub authors: AuthorRedaction,
+    /// Object keys whose string values are author names or emails.
+    pub author_fields: Vec<String>,
+    /// Salt mixed into author hashes so they can't be reversed by hashing a
+    /// list of known names.  If empty, a random salt is generated for each
+    /// run, so hashes are only consistent within a single result.
+    pub hash_salt: String,
+    /// Rewrites applied in order to all string values, in addition to the
+    /// automatic rewriting of the tree's absolute directories.
+    pub path_rewrites: Vec<PathRewriteRule>,
+}
+
+impl Default for RedactionConfig {
+    fn default() -> Self {
+        RedactionConfig {
+            authors: AuthorRedaction::Hash,
+            author_fields: vec!["author".to_string(), "email".to_string()],
+            hash_salt: String::new(),
+            path_rewrites: vec![],
+        }
+    }
+}
+
+/// Rules for recognizing indexed paths that are aliases of other indexed paths
+/// so that `compile-results` can report each file once under its canonical
+/// path with the aliases listed as alternates.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PathAliasConfig {
+    /// Rewrites checked in order against tree-relative paths; the first rule
+    /// whose pattern matches produces the canonical path.
+    pub rules: Vec<PathRewriteRule>,
+    /// Also treat paths that are symlinks (or inside symlinked directories)
+    /// as aliases of the tree paths they resolve to.  This requires a local
+    /// index.
+    pub resolve_symlinks: bool,
+    /// The (absolute directory, tree path prefix) pairs used to map between
+    /// tree paths and the files symlinks resolve to.  This is filled in by the
+    /// server when `resolve_symlinks` is set.
+    #[serde(skip)]
+    pub symlink_roots: Vec<(String, String)>,
+}
+
+/// The status a `SymbolAnnotation` attaches to a symbol.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SymbolStatus {
+    /// Still works but new code should use something else.
+    Deprecated,
+    /// Scheduled for (or partially through) removal.
+    Obsolete,
+    /// Nothing should be using it anymore but it hasn't been removed yet.
+    Dormant,
+}
+
+impl SymbolStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SymbolStatus::Deprecated => "deprecated",
+            SymbolStatus::Obsolete => "obsolete",
+            SymbolStatus::Dormant => "dormant",
+        }
+    }
+}
+
+/// What search results say about an annotated symbol.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SymbolAnnotation {
+    pub status: SymbolStatus,
+    /// Free-form migration guidance.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// The pretty identifier of what to use instead.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replacement: Option<String>,
+    /// The bug (number or URL) tracking the symbol's removal.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub removal_bug: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SymbolAnnotationRule {
+    /// Exact (raw) symbols the annotation applies to.
+    #[serde(default)]
+    pub symbols: Vec<String>,
+    /// Pretty identifiers the annotation applies to, along with everything
+    /// nested under them, so annotating a class covers its members.
+    #[serde(default)]
+    pub pretty: Vec<String>,
+    #[serde(flatten)]
+    pub annotation: SymbolAnnotation,
+}
+
+/// Annotations attached to symbols by `crossref-lookup` and `crossref-expand`
+/// and collected by `compile-results` so that results touching, for example,
+/// a deprecated API carry a banner pointing at its replacement.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SymbolAnnotationConfig {
+    /// Rules checked in order; the first rule matching a symbol wins.
+    pub rules: Vec<SymbolAnnotationRule>,
+}
+
+impl SymbolAnnotationConfig {
+    /// Find the annotation for the given symbol and its pretty identifier.
+    pub fn annotation_for(&self, sym: &str, pretty: &str) -> Option<&SymbolAnnotation> {
+        let covers = |annotated: &String| {
+            pretty
+                .strip_prefix(annotated.as_str())
+                .map_or(false, |rest| rest.is_empty() || rest.starts_with("::"))
+        };
+        self.rules
+            .iter()
+            .find(|rule| rule.symbols.iter().any(|s| s == sym) || rule.pretty.iter().any(covers))
+            .map(|rule| &rule.annotation)
+    }
+}
+
+/// Weights used to demote search results, where a weight of 1 is a normal
+/// result and lower weights are displayed later.  The weight of a file is the
+/// weight of its path kind multiplied by the weight of the first matching path
+/// rule.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ResultDemotionConfig {
+    /// Weights keyed by path kind name as configured in `per-file-info.toml`
+    /// (ex: "Generated code").  Path kinds that aren't listed have a weight of
+    /// 1.
+    #[serde(default)]
+    pub path_kinds: BTreeMap<String, f64>,
+    /// Rules checked in order against tree-relative paths.
+    #[serde(default)]
+    pub path_rules: Vec<PathDemotionRule>,
+    /// Path kind groups and files with a weight at or below this are collapsed
+    /// into expandable groups instead of being displayed inline.  Nothing is
+    /// collapsed if omitted.
+    #[serde(default)]
+    pub collapse_at_or_below: Option<f64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PathDemotionRule {
+    /// Regular expression matched against the tree-relative path.
+    pub pattern: String,
+    pub weight: f64,
+}
+
+impl Default for ResultDemotionConfig {
+    fn default() -> Self {
+        let rule = |pattern: &str, weight| PathDemotionRule {
+            pattern: pattern.to_string(),
+            weight,
+        };
+        // The path kind weights match the `sort_order` of the default path
+        // kinds.
+        let path_kinds = [
+            ("Normal", 1.0),
+            ("Third-party code", 0.9),
+            ("Test files", 0.8),
+            ("Generated code", 0.7),
+        ];
+        ResultDemotionConfig {
+            path_kinds: path_kinds
+                .iter()
+                .map(|(name, weight)| (name.to_string(), *weight))
+                .collect(),
+            path_rules: vec![
+                rule("^third_party/", 0.25),
+                rule("^obj-[^/]*/", 0.25),
+                rule("^__GENERATED__/", 0.5),
+            ],
+            collapse_at_or_below: None,
+        }
+    }
+}
+
 pub struct GitData {
     pub repo: Repository,
     pub blame_repo: Option<Repository>,
@@ -489,3 +934,70 @@ impl GitData {
         self.blame_ignore.should_ignore(rev)
     }
 }
+
+#[test]
+fn test_sub_repo_for_path() {
+    let paths: TreeConfigPaths = serde_json::from_value(serde_json::json!({
+        "priority": 1,
+        "on_error": "continue",
+        "cache": "nothing",
+        "index_path": "/index",
+        "files_path": "/index/git",
+        "objdir_path": "/index/objdir",
+        "codesearch_path": "/index/livegrep.idx",
+        "codesearch_port": 8081,
+        "sub_repos": {
+            "third_party/wasm": { "name": "wasm", "revision": "abc123",
+                                  "github_repo": "https://github.com/example/wasm" },
+            "third_party/wasm/spec": { "name": "spec", "revision": "def456" },
+        },
+    }))
+    .unwrap();
+
+    assert!(paths
+        .sub_repo_for_path("third_party/wasmtime/lib.rs")
+        .is_none());
+    assert!(paths.sub_repo_for_path("dom/base/nsINode.cpp").is_none());
+    let (prefix, sub_repo) = paths
+        .sub_repo_for_path("third_party/wasm/src/a b.rs")
+        .unwrap();
+    assert_eq!(prefix, "third_party/wasm");
+    assert_eq!(
+        sub_repo.permalink("abc123", "src/a b.rs").unwrap(),
+        "https://github.com/example/wasm/blob/abc123/src/a%20b.rs"
+    );
+    let (prefix, sub_repo) = paths
+        .sub_repo_for_path("third_party/wasm/spec/test.wast")
+        .unwrap();
+    assert_eq!(prefix, "third_party/wasm/spec");
+    assert!(sub_repo.permalink("def456", "test.wast").is_none());
+}
+
+#[test]
+fn test_symbol_annotation_for() {
+    let config: SymbolAnnotationConfig = serde_json::from_value(serde_json::json!({
+        "rules": [
+            { "symbols": ["_ZN3Foo3OldEv"], "status": "obsolete", "removal_bug": "1234" },
+            { "pretty": ["Foo"], "status": "deprecated", "replacement": "Bar" },
+        ],
+    }))
+    .unwrap();
+
+    let status = |sym: &str, pretty: &str| config.annotation_for(sym, pretty).map(|a| a.status);
+    assert_eq!(
+        status("_ZN3Foo3OldEv", "Foo::Old"),
+        Some(SymbolStatus::Obsolete)
+    );
+    assert_eq!(
+        status("_ZN3Foo3NewEv", "Foo::New"),
+        Some(SymbolStatus::Deprecated)
+    );
+    assert_eq!(status("T_Foo", "Foo"), Some(SymbolStatus::Deprecated));
+    assert_eq!(status("T_FooBar", "FooBar"), None);
+    assert_eq!(
+        config
+            .annotation_for("T_Foo", "Foo")
+            .and_then(|a| a.replacement.as_deref()),
+        Some("Bar")
+    );
+}
@@ -1,16 +1,21 @@
 extern crate memmap;
 
 use self::memmap::Mmap;
+use std::convert::TryInto;
 use std::fs::File;
 use std::str;
 use std::sync::Arc;
 use std::{cmp::Ordering, collections::HashMap};
 
+use serde::{
+    de::{IgnoredAny, SeqAccess, Visitor},
+    Deserialize, Deserializer,
+};
 use serde_json::{from_slice, Value};
 
 use crate::{
     abstract_server::Result,
-    abstract_server::{ErrorDetails, ErrorLayer, ServerError},
+    abstract_server::{ErrorDetails, ErrorLayer, ServerError, SymbolRelationCounts},
 };
 
 use super::config::Config;
@@ -19,6 +24,10 @@ use super::config::Config;
 pub struct CrossrefLookupMap {
     inline_mm: Arc<Mmap>,
     extra_mm: Arc<Mmap>,
+    /// The `-index` file of little-endian u64 offsets of each identifier line
+    /// in `inline_mm`, if it exists.  Indexes generated before the index file
+    /// existed fall back to bisecting `inline_mm` directly.
+    index_mm: Option<Arc<Mmap>>,
 }
 
 const SPACE: u8 = b' ';
@@ -26,14 +35,67 @@ const NEWLINE: u8 = b'\n';
 const ID_START: u8 = b'!';
 const INLINE_STORED: u8 = b':';
 const EXTERNALLY_STORED: u8 = b'@';
+const INDEX_ENTRY_SIZE: usize = 8;
 
 fn make_crossref_data_error(sym: &str) -> ServerError {
     ServerError::StickyProblem(ErrorDetails {
-        layer: ErrorLayer::DataLayer,
+        layer: ErrorLayer::CrossrefIoLayer,
         message: format!("bad crossref data for symbol: {}", sym),
     })
 }
 
+/// Deserializes a JSON array as just its length, skipping over the items
+/// without allocating anything for them.
+#[derive(Default)]
+struct ArrayLength(usize);
+
+impl<'de> Deserialize<'de> for ArrayLength {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        struct LengthVisitor;
+
+        impl<'de> Visitor<'de> for LengthVisitor {
+            type Value = ArrayLength;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("an array")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(
+                self,
+                mut seq: A,
+            ) -> std::result::Result<ArrayLength, A::Error> {
+                let mut len = 0;
+                while seq.next_element::<IgnoredAny>()?.is_some() {
+                    len += 1;
+                }
+                Ok(ArrayLength(len))
+            }
+        }
+
+        deserializer.deserialize_seq(LengthVisitor)
+    }
+}
+
+/// The subset of `AnalysisStructured` that `lookup_relation_counts` looks at;
+/// all other fields are skipped.
+#[derive(Deserialize)]
+struct RelationCountsMeta {
+    #[serde(rename = "overriddenBy", default)]
+    overridden_by: ArrayLength,
+    #[serde(default)]
+    subclasses: ArrayLength,
+    #[serde(default)]
+    overrides: ArrayLength,
+    #[serde(default)]
+    supers: ArrayLength,
+}
+
+#[derive(Deserialize)]
+struct RelationCountsRecord {
+    #[serde(default)]
+    meta: Option<RelationCountsMeta>,
+}
+
 // This implementation is a port of `crossrefs.py` (which was adapted from
 // `identifiers.py`) and informed by `identifiers.rs` (which presumably was
 // adapted from `identifiers.py` as well).
@@ -53,9 +115,15 @@ impl CrossrefLookupMap {
                 Err(_) => return None,
             }
         };
+        let index_mm = File::open(format!("{}-index", inline_path))
+            .ok()
+            .and_then(|index_file| unsafe { Mmap::map(&index_file).ok() })
+            .filter(|mmap| mmap.len() % INDEX_ENTRY_SIZE == 0)
+            .map(Arc::new);
         Some(CrossrefLookupMap {
             inline_mm,
             extra_mm,
+            index_mm,
         })
     }
 
@@ -119,10 +187,61 @@ impl CrossrefLookupMap {
         (&bytes[start + 1..end], start, end)
     }
 
+    // Look for an exact symbol match `sym`, returning the payload line which may
+    // be either inline JSON or external offsets to be retrieved from another
+    // map, or an empty slice for a miss.
+    fn bisect_for_payload(&self, search_sym: &[u8]) -> &[u8] {
+        if let Some(index_mm) = &self.index_mm {
+            if let Some(payload) = self.bisect_index_for_payload(index_mm, search_sym) {
+                return payload;
+            }
+        }
+        self.bisect_inline_for_payload(search_sym)
+    }
+
+    // Bisect the offset index so that each probe only has to look at a single
+    // identifier line and a match seeks directly to its payload line.  Returns
+    // None if the index is inconsistent with the inline map, in which case the
+    // caller should fall back to `bisect_inline_for_payload`.
+    fn bisect_index_for_payload(&self, index_mm: &Mmap, search_sym: &[u8]) -> Option<&[u8]> {
+        let bytes: &[u8] = self.inline_mm.as_ref();
+        let size = bytes.len();
+        let index: &[u8] = index_mm.as_ref();
+        let find_newline = |start: usize| {
+            bytes[start..]
+                .iter()
+                .position(|b| *b == NEWLINE)
+                .map_or(size, |offset| start + offset)
+        };
+
+        let mut lo = 0;
+        let mut hi = index.len() / INDEX_ENTRY_SIZE;
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            let entry = &index[mid * INDEX_ENTRY_SIZE..(mid + 1) * INDEX_ENTRY_SIZE];
+            let id_start = u64::from_le_bytes(entry.try_into().ok()?) as usize;
+            if id_start >= size || bytes[id_start] != ID_START {
+                return None;
+            }
+            let id_end = find_newline(id_start);
+
+            match bytes[id_start + 1..id_end].cmp(search_sym) {
+                Ordering::Equal => {
+                    let payload_start = (id_end + 1).min(size);
+                    return Some(&bytes[payload_start..find_newline(payload_start)]);
+                }
+                Ordering::Less => lo = mid + 1,
+                Ordering::Greater => hi = mid,
+            }
+        }
+
+        Some(&[])
+    }
+
     // Bisect the mmap to look for an exact symbol match `sym`, and returning the
     // payload line which may be either inline JSON or external offsets to be
     // retrieved from another map.
-    fn bisect_for_payload(&self, search_sym: &[u8]) -> &[u8] {
+    fn bisect_inline_for_payload(&self, search_sym: &[u8]) -> &[u8] {
         // We are always looking at a byte-range window within the mmap that is
         // a slice with bounds [0, mmap_end).
         let mut first = 0;
@@ -199,10 +318,62 @@ impl CrossrefLookupMap {
 
     pub fn lookup(&self, sym: &str) -> Result<Value> {
         let payload = self.bisect_for_payload(sym.as_bytes());
+        self.decode_payload(sym, payload)
+    }
+
+    /// Look up just the sizes of the symbol's relationship lists without
+    /// parsing (or allocating) the rest of the crossref record, returning None
+    /// if the symbol isn't known.
+    pub fn lookup_relation_counts(&self, sym: &str) -> Result<Option<SymbolRelationCounts>> {
+        let payload = self.bisect_for_payload(sym.as_bytes());
+        let json = match self.payload_json(sym, payload)? {
+            Some(json) => json,
+            None => return Ok(None),
+        };
+        let record: RelationCountsRecord = from_slice(json)?;
+        Ok(Some(match record.meta {
+            Some(meta) => SymbolRelationCounts {
+                overridden_by: meta.overridden_by.0,
+                subclasses: meta.subclasses.0,
+                overrides: meta.overrides.0,
+                supers: meta.supers.0,
+            },
+            None => SymbolRelationCounts::default(),
+        }))
+    }
+
+    /// Iterate over every (symbol, crossref value) pair in the map in symbol
+    /// order.  The iterator holds its own references to the underlying maps so
+    /// it can outlive `self`.
+    pub fn iter(&self) -> CrossrefLookupIter {
+        CrossrefLookupIter {
+            map: self.clone(),
+            pos: 0,
+        }
+    }
+
+    // Decode a payload line as returned by `bisect_for_payload`.
+    fn decode_payload(&self, sym: &str, payload: &[u8]) -> Result<Value> {
+        let marker_char = payload.first().copied();
+        match self.payload_json(sym, payload)? {
+            // Inline payloads that fail to parse have historically been treated
+            // as a miss rather than an error.
+            Some(json) if marker_char == Some(INLINE_STORED) => {
+                Ok(from_slice(json).unwrap_or(Value::Null))
+            }
+            Some(json) => Ok(from_slice(json)?),
+            // Finding nothing (a miss!) is not an error and so is an in-band
+            // null.
+            None => Ok(Value::Null),
+        }
+    }
+
+    // Locate the JSON bytes for a payload line as returned by
+    // `bisect_for_payload`, returning None for a miss.
+    fn payload_json<'a>(&'a self, sym: &str, payload: &'a [u8]) -> Result<Option<&'a [u8]>> {
         let payload_len = payload.len();
-        // Finding nothing (a miss!) is not an error and so is an in-band null.
         if payload_len == 0 {
-            return Ok(Value::Null);
+            return Ok(None);
         }
         // Let's also rule out results that are too short and therefore must be
         // an error.
@@ -213,7 +384,7 @@ impl CrossrefLookupMap {
         let marker_char = payload[0];
 
         if marker_char == INLINE_STORED {
-            return from_slice(&payload[1..]).or(Ok(Value::Null));
+            return Ok(Some(&payload[1..]));
         } else if marker_char != EXTERNALLY_STORED {
             // Fail if we're seeing something other than an external ref.
             return Err(make_crossref_data_error(sym));
@@ -234,8 +405,110 @@ impl CrossrefLookupMap {
         };
 
         let extra_bytes: &[u8] = self.extra_mm.as_ref();
-        Ok(from_slice(
+        Ok(Some(
             &extra_bytes[brace_offset..brace_offset + length_with_newline - 1],
-        )?)
+        ))
+    }
+}
+
+pub struct CrossrefLookupIter {
+    map: CrossrefLookupMap,
+    /// Offset of the next identifier line in the inline map.
+    pos: usize,
+}
+
+impl Iterator for CrossrefLookupIter {
+    type Item = Result<(String, Value)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bytes: &[u8] = self.map.inline_mm.as_ref();
+        let size = bytes.len();
+        if self.pos >= size {
+            return None;
+        }
+        if bytes[self.pos] != ID_START {
+            // We've lost sync with the file; there's no sane way to continue.
+            self.pos = size;
+            return Some(Err(make_crossref_data_error("<iteration>")));
+        }
+
+        let find_newline = |start: usize| {
+            bytes[start..]
+                .iter()
+                .position(|b| *b == NEWLINE)
+                .map_or(size, |offset| start + offset)
+        };
+        let id_end = find_newline(self.pos);
+        let payload_start = (id_end + 1).min(size);
+        let payload_end = find_newline(payload_start);
+
+        let sym = String::from_utf8_lossy(&bytes[self.pos + 1..id_end]).into_owned();
+        let payload = &bytes[payload_start..payload_end];
+        self.pos = payload_end + 1;
+
+        Some(
+            self.map
+                .decode_payload(&sym, payload)
+                .map(|value| (sym, value)),
+        )
+    }
+}
+
+#[test]
+fn test_relation_counts_record() {
+    let record: RelationCountsRecord = from_slice(
+        br#"{"uses": [{"path": "a.cpp", "lines": []}], "meta": {"kind": "method", "overriddenBy": [{"sym": "a"}, {"sym": "b"}], "overrides": [{"sym": "c"}]}}"#,
+    )
+    .unwrap();
+    let meta = record.meta.unwrap();
+    assert_eq!(meta.overridden_by.0, 2);
+    assert_eq!(meta.subclasses.0, 0);
+    assert_eq!(meta.overrides.0, 1);
+    assert_eq!(meta.supers.0, 0);
+
+    let record: RelationCountsRecord = from_slice(br#"{"defs": []}"#).unwrap();
+    assert!(record.meta.is_none());
+}
+
+#[test]
+fn test_index_lookup() {
+    let dir = std::env::temp_dir().join(format!("crossref-index-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let inline_path = dir.join("crossref");
+    let extra_path = dir.join("crossref-extra");
+
+    let extra = "!b\n:{\"uses\":[]}\n";
+    let inline = "!a\n:{\"defs\":[]}\n!b\n@4 c\n!c\n:{}\n";
+    let mut index = vec![];
+    let mut offset = 0;
+    for line in inline.split_inclusive('\n') {
+        if line.starts_with('!') {
+            index.extend_from_slice(&(offset as u64).to_le_bytes());
+        }
+        offset += line.len();
     }
+    std::fs::write(&inline_path, inline).unwrap();
+    std::fs::write(&extra_path, extra).unwrap();
+
+    // Without an index we bisect the inline map directly.
+    let unindexed =
+        CrossrefLookupMap::new(inline_path.to_str().unwrap(), extra_path.to_str().unwrap())
+            .unwrap();
+    assert!(unindexed.index_mm.is_none());
+
+    std::fs::write(dir.join("crossref-index"), &index).unwrap();
+    let indexed =
+        CrossrefLookupMap::new(inline_path.to_str().unwrap(), extra_path.to_str().unwrap())
+            .unwrap();
+    assert!(indexed.index_mm.is_some());
+
+    for map in [&unindexed, &indexed] {
+        assert_eq!(map.lookup("a").unwrap(), serde_json::json!({"defs": []}));
+        assert_eq!(map.lookup("b").unwrap(), serde_json::json!({"uses": []}));
+        assert_eq!(map.lookup("c").unwrap(), serde_json::json!({}));
+        assert_eq!(map.lookup("bb").unwrap(), Value::Null);
+        assert_eq!(map.lookup("0").unwrap(), Value::Null);
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
 }
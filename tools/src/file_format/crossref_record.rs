@@ -0,0 +1,315 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{from_value, to_value, Map, Value};
+use ustr::Ustr;
+
+use super::analysis::{
+    AnalysisStructured, BindingOwnerLang, BindingSlotKind, BindingSlotLang, BindingSlotProps,
+    PathSearchResult,
+};
+
+/// A reference to another symbol from a crossref record, as used by "callees",
+/// "expands-to", and "expanded-from".  The `jump` is of the form
+/// "path#line1,line2" and identifies where the relationship occurs.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CrossrefSymbolRef {
+    pub sym: Ustr,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pretty: Option<Ustr>,
+    /// Only present for "callees", where it's the structured kind of the callee.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<Ustr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jump: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CrossrefFieldMemberUseField {
+    pub pretty: Ustr,
+    pub ptr: Value,
+}
+
+/// A "field-member-uses" entry identifying a class whose fields use the
+/// symbol.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CrossrefFieldMemberUse {
+    pub sym: Ustr,
+    #[serde(default)]
+    pub pretty: Ustr,
+    #[serde(default)]
+    pub fields: Vec<CrossrefFieldMemberUseField>,
+}
+
+/// The per-symbol record that crossref.rs writes into the crossref database and
+/// which `crossref_lookup` returns.  The hit kinds are lists of per-path hits,
+/// "meta" is the symbol's structured analysis record (if it had one), and the
+/// remaining fields are symbol relationships derived by crossref.
+///
+/// Any keys we don't know about end up in `extra` so that records round-trip
+/// even when crossref.rs has grown new keys that haven't been typed here yet.
+/// The field order here matches the order crossref.rs writes keys in so that
+/// serialized output stays stable.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CrossrefRecord {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub uses: Vec<PathSearchResult>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub defs: Vec<PathSearchResult>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub assignments: Vec<PathSearchResult>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub decls: Vec<PathSearchResult>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub forwards: Vec<PathSearchResult>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub idl: Vec<PathSearchResult>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<PathSearchResult>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub callees: Vec<CrossrefSymbolRef>,
+    #[serde(rename = "expands-to", default, skip_serializing_if = "Vec::is_empty")]
+    pub expands_to: Vec<CrossrefSymbolRef>,
+    #[serde(
+        rename = "expanded-from",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub expanded_from: Vec<CrossrefSymbolRef>,
+    #[serde(
+        rename = "field-member-uses",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub field_member_uses: Vec<CrossrefFieldMemberUse>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<AnalysisStructured>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl CrossrefRecord {
+    /// Convert the raw JSON returned by `crossref_lookup` into a record.  Null
+    /// (unknown symbol) becomes an empty record.  A "meta" that can't be
+    /// deserialized is preserved in `extra` rather than failing the lookup
+    /// because it's still useful to have the hits.
+    pub fn from_value(val: Value) -> serde_json::Result<Self> {
+        let mut obj = match val {
+            Value::Null => return Ok(CrossrefRecord::default()),
+            Value::Object(obj) => obj,
+            other => return from_value(other),
+        };
+        let raw_meta = obj.remove("meta");
+        let mut record: CrossrefRecord = from_value(Value::Object(obj))?;
+        if let Some(raw_meta) = raw_meta {
+            match from_value(raw_meta.clone()) {
+                Ok(meta) => record.meta = Some(meta),
+                Err(_) => {
+                    record.extra.insert("meta".to_string(), raw_meta);
+                }
+            }
+        }
+        Ok(record)
+    }
+
+    pub fn into_value(self) -> Value {
+        to_value(self).unwrap_or(Value::Null)
+    }
+
+    /// The path hit-lists for each hit kind, keyed by the kind's name in the
+    /// crossref JSON.
+    pub fn hit_kinds(&self) -> [(&'static str, &Vec<PathSearchResult>); 7] {
+        [
+            ("uses", &self.uses),
+            ("defs", &self.defs),
+            ("assignments", &self.assignments),
+            ("decls", &self.decls),
+            ("forwards", &self.forwards),
+            ("idl", &self.idl),
+            ("aliases", &self.aliases),
+        ]
+    }
+
+    pub fn hit_kinds_mut(&mut self) -> [&mut Vec<PathSearchResult>; 7] {
+        [
+            &mut self.uses,
+            &mut self.defs,
+            &mut self.assignments,
+            &mut self.decls,
+            &mut self.forwards,
+            &mut self.idl,
+            &mut self.aliases,
+        ]
+    }
+
+    pub fn pretty(&self) -> Option<Ustr> {
+        match &self.meta {
+            Some(meta) if !meta.pretty.is_empty() => Some(meta.pretty),
+            _ => None,
+        }
+    }
+
+    pub fn method_syms(&self) -> Vec<Ustr> {
+        match &self.meta {
+            Some(meta) => meta.methods.iter().map(|m| m.sym).collect(),
+            None => vec![],
+        }
+    }
+
+    pub fn overridden_by_syms(&self) -> Vec<Ustr> {
+        match &self.meta {
+            Some(meta) => meta.overridden_by_syms.clone(),
+            None => vec![],
+        }
+    }
+
+    pub fn override_syms(&self) -> Vec<Ustr> {
+        match &self.meta {
+            Some(meta) => meta.overrides.iter().map(|o| o.sym).collect(),
+            None => vec![],
+        }
+    }
+
+    pub fn subclass_syms(&self) -> Vec<Ustr> {
+        match &self.meta {
+            Some(meta) => meta.subclass_syms.clone(),
+            None => vec![],
+        }
+    }
+
+    pub fn specialization_syms(&self) -> Vec<Ustr> {
+        match &self.meta {
+            Some(meta) => meta.specialization_syms.clone(),
+            None => vec![],
+        }
+    }
+
+    pub fn specialization_of_syms(&self) -> Vec<Ustr> {
+        match &self.meta {
+            Some(meta) => meta.specialization_of.iter().map(|s| s.sym).collect(),
+            None => vec![],
+        }
+    }
+
+    pub fn super_syms(&self) -> Vec<Ustr> {
+        match &self.meta {
+            Some(meta) => meta.supers.iter().map(|s| s.sym).collect(),
+            None => vec![],
+        }
+    }
+
+    /// The fields on the other side of an IDL field binding.  For an IDL field
+    /// whose C++ binding is a plain struct field (ex: a WebIDL dictionary
+    /// member) this is the C++ field, and for that C++ field it's the IDL
+    /// field.
+    pub fn idl_field_counterpart_syms(&self) -> Vec<Ustr> {
+        let meta = match &self.meta {
+            Some(meta) if meta.kind.as_str() == "field" => meta,
+            _ => return vec![],
+        };
+        let is_field_binding = |props: &BindingSlotProps| {
+            props.owner_lang == BindingOwnerLang::Idl
+                && props.slot_kind == BindingSlotKind::Attribute
+                && props.slot_lang == BindingSlotLang::Cpp
+        };
+        meta.binding_slots
+            .iter()
+            .chain(meta.slot_owner.iter())
+            .filter(|slot| is_field_binding(&slot.props))
+            .map(|slot| slot.sym)
+            .collect()
+    }
+}
+
+#[test]
+fn test_crossref_record_round_trip() {
+    use serde_json::json;
+
+    let raw = json!({
+        "uses": [{
+            "path": "dom/Foo.cpp",
+            "path_kind": "Normal",
+            "lines": [{
+                "lno": 3,
+                "bounds": [4, 7],
+                "line": "Foo();",
+                "context": "Bar",
+                "contextsym": "_ZN3BarEv",
+            }],
+        }],
+        "callees": [{ "sym": "_ZN3BazEv", "kind": "method", "jump": "dom/Foo.cpp#5" }],
+        "meta": { "structured": 1, "pretty": "Foo", "sym": "_ZN3FooEv", "kind": "method" },
+        "some-future-kind": [1, 2],
+    });
+    let record = CrossrefRecord::from_value(raw.clone()).unwrap();
+    assert_eq!(record.uses[0].lines[0].contextsym.as_str(), "_ZN3BarEv");
+    assert_eq!(record.callees[0].sym.as_str(), "_ZN3BazEv");
+    assert_eq!(record.pretty().unwrap().as_str(), "Foo");
+    assert_eq!(record.extra["some-future-kind"], json!([1, 2]));
+
+    let round_tripped = record.into_value();
+    assert_eq!(round_tripped["uses"], raw["uses"]);
+    assert_eq!(round_tripped["some-future-kind"], raw["some-future-kind"]);
+
+    let broken_meta = json!({ "meta": { "pretty": "no structured tag" } });
+    let record = CrossrefRecord::from_value(broken_meta.clone()).unwrap();
+    assert!(record.meta.is_none());
+    assert_eq!(record.into_value(), broken_meta);
+
+    assert!(CrossrefRecord::from_value(Value::Null)
+        .unwrap()
+        .uses
+        .is_empty());
+}
+
+#[test]
+fn test_idl_field_counterpart_syms() {
+    use serde_json::json;
+
+    let idl_field = json!({
+        "meta": {
+            "structured": 1,
+            "pretty": "TestDictionary::member1",
+            "sym": "WEBIDL_TestDictionary_member1",
+            "kind": "field",
+            "implKind": "idl",
+            "bindingSlots": [
+                {
+                    "slotKind": "attribute",
+                    "slotLang": "cpp",
+                    "ownerLang": "idl",
+                    "sym": "F_<T_mozilla::dom::TestDictionary>_mMember1",
+                },
+                {
+                    "slotKind": "attribute",
+                    "slotLang": "js",
+                    "ownerLang": "idl",
+                    "sym": "#member1",
+                },
+            ],
+        },
+    });
+    let record = CrossrefRecord::from_value(idl_field).unwrap();
+    assert_eq!(
+        record.idl_field_counterpart_syms(),
+        vec![Ustr::from("F_<T_mozilla::dom::TestDictionary>_mMember1")]
+    );
+
+    let cpp_field = json!({
+        "meta": {
+            "structured": 1,
+            "pretty": "mozilla::dom::TestDictionary::mMember1",
+            "sym": "F_<T_mozilla::dom::TestDictionary>_mMember1",
+            "kind": "field",
+            "slotOwner": {
+                "slotKind": "attribute",
+                "slotLang": "cpp",
+                "ownerLang": "idl",
+                "sym": "WEBIDL_TestDictionary_member1",
+            },
+        },
+    });
+    let record = CrossrefRecord::from_value(cpp_field).unwrap();
+    assert_eq!(
+        record.idl_field_counterpart_syms(),
+        vec![Ustr::from("WEBIDL_TestDictionary_member1")]
+    );
+}
@@ -1,16 +1,21 @@
 extern crate memmap;
 
 use self::memmap::Mmap;
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::BufRead;
 
 use std::str;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
+
+use regex::Regex;
 use ustr::{ustr, Ustr};
 
 use serde::{Deserialize, Serialize};
 use serde_json::to_string;
+use unicode_normalization::{is_nfc_quick, IsNormalized, UnicodeNormalization};
 
 use super::config::Config;
 
@@ -18,9 +23,64 @@ fn uppercase(s: &[u8]) -> Vec<u8> {
     s.iter().map(u8::to_ascii_uppercase).collect()
 }
 
+/// Count the symbols of each unqualified identifier in the contents of an
+/// identifiers file, keeping the ones with at least
+/// `IDENTIFIER_FREQUENCY_FLOOR`.  The file is sorted case-insensitively, so all
+/// of the lines for an identifier are adjacent.
+fn build_frequencies(bytes: &[u8]) -> HashMap<Vec<u8>, usize> {
+    let mut frequencies = HashMap::new();
+    let mut run_id: &[u8] = &[];
+    let mut run_len = 0;
+    for line in bytes.split(|&b| b == b'\n') {
+        let id = match line.iter().rposition(|&b| b == b' ') {
+            Some(i) => &line[..i],
+            None => continue,
+        };
+        if id.contains(&b':') || id.contains(&b'.') {
+            continue;
+        }
+        if id.eq_ignore_ascii_case(run_id) {
+            run_len += 1;
+            continue;
+        }
+        if run_len >= IDENTIFIER_FREQUENCY_FLOOR {
+            frequencies.insert(uppercase(run_id), run_len);
+        }
+        run_id = id;
+        run_len = 1;
+    }
+    if run_len >= IDENTIFIER_FREQUENCY_FLOOR {
+        frequencies.insert(uppercase(run_id), run_len);
+    }
+    frequencies
+}
+
+/// Normalize an identifier to Unicode NFC so that identifiers which look the
+/// same but are encoded differently (ex: a precomposed "é" versus "e" followed
+/// by a combining acute accent) are treated the same.  The `identifiers` file
+/// is written normalized, so needles need to be normalized before lookup.
+/// This is cheap for the common case of ASCII identifiers, which are always
+/// already normalized.
+pub fn normalize_identifier(s: &str) -> Cow<str> {
+    match is_nfc_quick(s.chars()) {
+        IsNormalized::Yes => Cow::Borrowed(s),
+        _ => Cow::Owned(s.nfc().collect()),
+    }
+}
+
+/// Identifiers with fewer symbols than this aren't tracked by the frequency
+/// table built by `IdentMap::frequency`, which keeps the table small.  Any
+/// stop word threshold needs to be at least this.
+pub const IDENTIFIER_FREQUENCY_FLOOR: usize = 32;
+
 #[derive(Clone, Debug)]
 pub struct IdentMap {
     mmap: Arc<Mmap>,
+    /// Symbol counts of the unqualified identifiers with at least
+    /// `IDENTIFIER_FREQUENCY_FLOOR` symbols, keyed by the uppercased
+    /// identifier.  Built by `build_frequency_table` since it requires a full
+    /// scan.
+    frequencies: Arc<OnceLock<HashMap<Vec<u8>, usize>>>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -67,6 +127,7 @@ impl IdentMap {
             match Mmap::map(&file) {
                 Ok(mmap) => Some(IdentMap {
                     mmap: Arc::new(mmap),
+                    frequencies: Arc::new(OnceLock::new()),
                 }),
                 Err(e) => {
                     warn!("Failed to mmap {}: {:?}", filename, e);
@@ -113,7 +174,10 @@ impl IdentMap {
     fn bisect(&self, needle: &[u8], upper_bound: bool) -> usize {
         let mut needle = uppercase(needle);
         if upper_bound {
-            needle.push(b'~');
+            // 0xFF never appears in UTF-8, so this sorts after every line that
+            // starts with the needle, including identifiers continuing with a
+            // non-ASCII character, whose lead byte sorts after all of ASCII.
+            needle.push(0xFF);
         }
 
         let mut first = 0;
@@ -144,6 +208,8 @@ impl IdentMap {
         max_results: usize,
     ) -> Vec<IdentResult> {
         let bytes = self.mmap.as_ref();
+        let needle = normalize_identifier(needle);
+        let needle = needle.as_ref();
 
         let start = self.bisect(needle.as_bytes(), false);
         let end = self.bisect(needle.as_bytes(), true);
@@ -161,7 +227,13 @@ impl IdentMap {
             // We only need to worry about suffix-related cases if the needle is
             // shorter than the identifier.
             if needle.len() < id.len() {
-                let suffix = &id[needle.len()..];
+                // Case folding is ASCII-only, so the needle's length should
+                // be a char boundary in the identifier, but an identifier
+                // that wasn't normalized could violate that.
+                let suffix = match id.get(needle.len()..) {
+                    Some(suffix) => suffix,
+                    None => continue,
+                };
                 if exact_match || suffix.contains(':') || suffix.contains('.') {
                     continue;
                 }
@@ -192,6 +264,169 @@ impl IdentMap {
         result
     }
 
+    /// Find the identifiers sharing a case-insensitive prefix of at least
+    /// `min_prefix_chars` characters with `needle`, returning each identifier
+    /// along with all of its symbols.  This is equivalent to doing a prefix
+    /// `lookup` for every prefix length from `min_prefix_chars` up to the
+    /// length of the needle, but makes a single pass over the identifiers.
+    ///
+    /// Identifiers are bucketed by how much of the needle they share and at
+    /// most `limit_per_prefix` identifiers are returned per bucket so that the
+    /// many identifiers sharing only a short prefix can't crowd out the ones
+    /// sharing a longer prefix.  Once a bucket is full we bisect past the rest
+    /// of its identifiers rather than reading them.  The returned bool is true
+    /// if any identifiers were skipped because of the limit.
+    pub fn identifiers_starting_with(
+        &self,
+        needle: &str,
+        min_prefix_chars: usize,
+        limit_per_prefix: usize,
+    ) -> (Vec<(Ustr, Vec<Ustr>)>, bool) {
+        let bytes = self.mmap.as_ref();
+        let needle = normalize_identifier(needle);
+        let needle = needle.as_ref();
+        let needle_upper = uppercase(needle.as_bytes());
+        let min_len = match needle.char_indices().nth(min_prefix_chars) {
+            Some((i, _)) => i,
+            None => needle.len(),
+        };
+
+        let mut results: Vec<(Ustr, Vec<Ustr>)> = vec![];
+        let mut result_index: HashMap<Ustr, usize> = HashMap::new();
+        // Number of identifiers returned, keyed by shared prefix length.
+        let mut bucket_counts: HashMap<usize, usize> = HashMap::new();
+        let mut truncated = false;
+
+        let mut pos = self.bisect(&needle.as_bytes()[..min_len], false);
+        let end = self.bisect(&needle.as_bytes()[..min_len], true);
+        while pos < end {
+            let line_end = bytes[pos..end]
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(end, |i| pos + i);
+            let line = &bytes[pos..line_end];
+            let next = line_end + 1;
+            let (id, symbol) = match str::from_utf8(line).ok().and_then(|l| l.rsplit_once(' ')) {
+                Some((id, symbol)) => (id, symbol),
+                None => {
+                    pos = next;
+                    continue;
+                }
+            };
+
+            let mut shared = id
+                .bytes()
+                .zip(needle_upper.iter())
+                .take_while(|(a, b)| a.to_ascii_uppercase() == **b)
+                .count();
+            while !id.is_char_boundary(shared) {
+                shared -= 1;
+            }
+            // Same hierarchy traversal rule as `lookup`, relative to the
+            // longest prefix this identifier would have been found by.
+            let suffix = &id[shared..];
+            if suffix.contains(':') || suffix.contains('.') {
+                pos = next;
+                continue;
+            }
+
+            let id = ustr(id);
+            if let Some(&i) = result_index.get(&id) {
+                results[i].1.push(ustr(symbol));
+                pos = next;
+                continue;
+            }
+
+            let count = bucket_counts.entry(shared).or_default();
+            if *count >= limit_per_prefix {
+                truncated = true;
+                // Identifiers sorting before the needle share increasingly
+                // more of it, so skip ahead to the first one sharing more.
+                // Identifiers sorting after it share increasingly less, so
+                // skip past everything sharing this much.
+                let skip_to = if uppercase(id.as_bytes()) < needle_upper {
+                    self.bisect(&needle.as_bytes()[..shared + 1], false)
+                } else {
+                    self.bisect(&needle.as_bytes()[..shared], true)
+                };
+                pos = skip_to.max(next);
+                continue;
+            }
+            *count += 1;
+
+            result_index.insert(id, results.len());
+            results.push((id, vec![ustr(symbol)]));
+            pos = next;
+        }
+
+        (results, truncated)
+    }
+
+    /// Scan every identifier in the map for ones matching the regex, which is
+    /// necessarily a linear scan since the regex may not have a literal prefix
+    /// we could bisect on.  The scan stops once `max_results` matches have
+    /// been found or the `deadline` passes, in which case the returned bool is
+    /// true to indicate the results are incomplete.
+    pub fn scan_regex(
+        &self,
+        re: &Regex,
+        types_only: bool,
+        max_results: usize,
+        deadline: Instant,
+    ) -> (Vec<IdentResult>, bool) {
+        let mut result = vec![];
+
+        for (i, line) in self.mmap.as_ref().split(|&b| b == b'\n').enumerate() {
+            // Checking the clock isn't free, so only do it periodically.
+            if i % 4096 == 0 && Instant::now() >= deadline {
+                return (result, true);
+            }
+            let line = match str::from_utf8(line) {
+                Ok(line) => line,
+                Err(_) => continue,
+            };
+            let (id, symbol) = match line.rsplit_once(' ') {
+                Some((id, symbol)) => (id, symbol),
+                None => continue,
+            };
+            if (types_only && !symbol.starts_with("T_")) || !re.is_match(id) {
+                continue;
+            }
+            if result.len() == max_results {
+                return (result, true);
+            }
+            result.push(IdentResult {
+                id: ustr(id),
+                symbol: ustr(symbol),
+            });
+        }
+
+        (result, false)
+    }
+
+    /// Build the frequency table used by `frequency` if it hasn't been built
+    /// yet.  This scans the entire map, so async callers should run it via
+    /// `spawn_blocking`.
+    pub fn build_frequency_table(&self) {
+        self.frequencies
+            .get_or_init(|| build_frequencies(self.mmap.as_ref()));
+    }
+
+    /// Return how many symbols have the given unqualified identifier,
+    /// case-insensitively, or 0 if it has fewer than
+    /// `IDENTIFIER_FREQUENCY_FLOOR`.  Returns None if `build_frequency_table`
+    /// hasn't finished building the frequency table yet.
+    pub fn frequency(&self, id: &str) -> Option<usize> {
+        let id = normalize_identifier(id);
+        let frequencies = self.frequencies.get()?;
+        Some(
+            frequencies
+                .get(&uppercase(id.as_bytes()))
+                .cloned()
+                .unwrap_or(0),
+        )
+    }
+
     pub fn lookup_json(
         &self,
         needle: &str,
@@ -203,3 +438,76 @@ impl IdentMap {
         to_string(&results).unwrap()
     }
 }
+
+#[test]
+fn test_identifiers_starting_with() {
+    let path = std::env::temp_dir().join(format!("identifiers-test-{}", std::process::id()));
+    // The file is sorted case-insensitively.
+    let lines = [
+        "Abc A_abc",
+        "Foo F_Foo",
+        "Foo F_Foo2",
+        "FooBar F_FooBar",
+        "FOOBAZ F_FOOBAZ",
+        "ZZTop Z_Top",
+    ];
+    std::fs::write(&path, lines.join("\n") + "\n").unwrap();
+    let map = IdentMap::new(path.to_str().unwrap()).unwrap();
+    let ids = |results: (Vec<(Ustr, Vec<Ustr>)>, bool)| -> (Vec<(String, usize)>, bool) {
+        (
+            results
+                .0
+                .into_iter()
+                .map(|(id, syms)| (id.to_string(), syms.len()))
+                .collect(),
+            results.1,
+        )
+    };
+
+    // The first and last identifiers in the file.
+    assert_eq!(
+        ids(map.identifiers_starting_with("Abcdef", 2, 10)),
+        (vec![("Abc".to_string(), 1)], false)
+    );
+    assert_eq!(
+        ids(map.identifiers_starting_with("ZZTop", 2, 10)),
+        (vec![("ZZTop".to_string(), 1)], false)
+    );
+
+    // Prefixes are matched case-insensitively.
+    assert_eq!(
+        ids(map.identifiers_starting_with("foobar", 3, 10)),
+        (
+            vec![
+                ("Foo".to_string(), 2),
+                ("FooBar".to_string(), 1),
+                ("FOOBAZ".to_string(), 1)
+            ],
+            false
+        )
+    );
+    // Only one identifier per shared prefix length.
+    assert_eq!(
+        ids(map.identifiers_starting_with("foobar", 3, 1)),
+        (
+            vec![
+                ("Foo".to_string(), 2),
+                ("FooBar".to_string(), 1),
+                ("FOOBAZ".to_string(), 1)
+            ],
+            false
+        )
+    );
+    assert_eq!(
+        ids(map.identifiers_starting_with("FooQux", 3, 1)),
+        (vec![("Foo".to_string(), 2)], true)
+    );
+
+    // A prefix nothing starts with.
+    assert_eq!(
+        ids(map.identifiers_starting_with("Nope", 2, 10)),
+        (vec![], false)
+    );
+
+    std::fs::remove_file(&path).unwrap();
+}
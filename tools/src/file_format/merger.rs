@@ -15,6 +15,7 @@ use super::analysis::AnalysisUnion;
 use super::analysis::{
     read_analyses, AnalysisSource, AnalysisStructured, ExpansionInfo, Location, WithLocation,
 };
+use super::structured_schema::upgrade_structured_record;
 
 #[derive(Debug)]
 pub struct HashedStructured {
@@ -39,7 +40,20 @@ pub fn merge_files<W: std::io::Write>(filenames: &[String], platforms: &[String]
 
     let src_data = read_analyses(
         filenames,
-        &mut |obj: Value, loc: &Location, i_file: usize| {
+        &mut |mut obj: Value, loc: &Location, i_file: usize| {
+            // Structured records need to be upgraded to the current schema
+            // before we can deserialize them.
+            if let Some(map) = obj.as_object_mut() {
+                if map.contains_key("structured") {
+                    if let Err(issue) = upgrade_structured_record(map) {
+                        warn!(
+                            "Unable to upgrade structured record at line [{}]: {}",
+                            loc.lineno, issue.message
+                        );
+                        return None;
+                    }
+                }
+            }
             if let Ok(unified) = from_value::<AnalysisUnion<Ustr>>(obj) {
                 match unified {
                     AnalysisUnion::Source(mut src) => {
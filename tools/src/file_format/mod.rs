@@ -1,5 +1,6 @@
 pub mod analysis;
 pub mod ontology_pointer_kind;
+pub mod structured_schema;
 
 #[cfg(not(target_arch = "wasm32"))]
 pub mod history;
@@ -15,6 +16,8 @@ pub mod crossref_converter;
 #[cfg(not(target_arch = "wasm32"))]
 pub mod crossref_lookup;
 #[cfg(not(target_arch = "wasm32"))]
+pub mod crossref_record;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod globbing_file_list;
 #[cfg(not(target_arch = "wasm32"))]
 pub mod identifiers;
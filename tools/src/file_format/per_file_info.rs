@@ -123,6 +123,87 @@ pub struct TestInfo {
     pub wpt_expectation_info: Option<WPTExpectationInfo>,
 }
 
+/// The test manifest annotations for a test file, as ingested into the concise
+/// per-file info's `info.test` from `test-info-all-tests.json`.  Each condition
+/// is a single line of the manifest's clause, any of which being true means
+/// the annotation applies.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TestAnnotations {
+    pub skip_if: Vec<String>,
+    pub fail_if: Vec<String>,
+}
+
+impl TestAnnotations {
+    /// Extract the annotations from a file's concise `info`, returning None
+    /// for files the test manifests don't know about.
+    pub fn from_concise_info(info: &Value) -> Option<Self> {
+        let test = info.get("test")?.as_object()?;
+        let conditions = |key: &str| -> Vec<String> {
+            match test.get(key).and_then(|v| v.as_str()) {
+                Some(clause) => clause
+                    .lines()
+                    .map(|line| line.trim())
+                    .filter(|line| !line.is_empty())
+                    .map(|line| line.to_string())
+                    .collect(),
+                None => vec![],
+            }
+        };
+        Some(TestAnnotations {
+            skip_if: conditions("skip_if"),
+            fail_if: conditions("fail_if"),
+        })
+    }
+
+    /// The operating systems the conditions compare `os` against for equality,
+    /// like "win" for `os == "win" && debug`.  We don't evaluate the
+    /// conditions, so this means the test is affected on at least some
+    /// configurations of each OS.
+    fn mentioned_oses(conditions: &[String]) -> Vec<String> {
+        let mut oses = vec![];
+        for condition in conditions {
+            let normalized: String = condition
+                .chars()
+                .filter(|c| !c.is_whitespace())
+                .map(|c| if c == '\'' { '"' } else { c })
+                .collect();
+            let mut rest = normalized.as_str();
+            while let Some(offset) = rest.find("os==\"") {
+                let preceding = rest[..offset].chars().next_back();
+                rest = &rest[offset + 5..];
+                if preceding.map_or(false, |c| c.is_alphanumeric() || c == '_') {
+                    continue;
+                }
+                if let Some(end) = rest.find('"') {
+                    let os = rest[..end].to_string();
+                    if !oses.contains(&os) {
+                        oses.push(os);
+                    }
+                }
+            }
+        }
+        oses.sort();
+        oses
+    }
+
+    /// The operating systems the test is skipped on in at least some
+    /// configurations.
+    pub fn skipped_oses(&self) -> Vec<String> {
+        Self::mentioned_oses(&self.skip_if)
+    }
+
+    /// The operating systems the test is expected to fail on in at least some
+    /// configurations.
+    pub fn failing_oses(&self) -> Vec<String> {
+        Self::mentioned_oses(&self.fail_if)
+    }
+
+    /// Is the test disabled (skipped) on the given OS, ex: "win"?
+    pub fn disabled_on(&self, os: &str) -> bool {
+        self.skipped_oses().iter().any(|skipped| skipped == os)
+    }
+}
+
 /// Per-file info derived from the concise and detailed info for a given file.
 /// Everything in here is optional data, but this structure will be available
 /// for every file to simplify control-flow.
@@ -154,3 +235,24 @@ pub fn read_detailed_file_info(path: &str, index_path: &str) -> Option<DetailedP
     let mut reader = BufReader::new(&json_file);
     from_reader(&mut reader).ok()
 }
+
+#[test]
+fn test_test_annotations() {
+    let info = serde_json::json!({
+        "test": {
+            "skip_if": "(os == \"win\" && processor == \"aarch64\") || (os == 'mac')\n\nverify\nmacos == \"linux\"",
+            "failure_count": 3,
+        }
+    });
+    let annotations = TestAnnotations::from_concise_info(&info).unwrap();
+    assert_eq!(annotations.skip_if.len(), 3);
+    assert!(annotations.fail_if.is_empty());
+    assert_eq!(annotations.skipped_oses(), vec!["mac", "win"]);
+    assert!(annotations.disabled_on("win"));
+    assert!(!annotations.disabled_on("linux"));
+
+    assert_eq!(
+        TestAnnotations::from_concise_info(&serde_json::json!({})),
+        None
+    );
+}
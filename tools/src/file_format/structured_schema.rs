@@ -0,0 +1,318 @@
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// The version of the "structured" analysis record format that the Rust
+/// consumers understand natively.  The value of a structured record's
+/// `"structured"` attribute is its schema version, so a record's version is
+/// always available without any additional attributes.
+///
+/// When changing the format in a way that existing consumers can't handle:
+/// - Bump this version.
+/// - Add an up-converter to `UPCONVERTERS` that transforms records from the
+///   previous version into the new version.
+/// - Update `StructuredTag` in `analysis.rs` so the new version deserializes.
+/// - Update `docs/analysis.md`.
+pub const CURRENT_STRUCTURED_VERSION: u64 = 1;
+
+/// An up-converter transforms a record of version `from_version` into a record
+/// of version `from_version + 1` in place.  It does not need to update the
+/// `"structured"` version attribute itself.
+struct UpConverter {
+    from_version: u64,
+    convert: fn(&mut Map<String, Value>),
+}
+
+/// Up-converters ordered by `from_version`.  There have been no incompatible
+/// format changes since the format was first versioned, so this is currently
+/// empty, but `normalize_current_version` deals with older variations in the
+/// version 1 format.
+const UPCONVERTERS: &[UpConverter] = &[];
+
+/// The `kind` values we know about; see `docs/analysis.md`.  Unknown kinds are
+/// only a warning because consumers generally pass `kind` through as a string.
+const KNOWN_KINDS: &[&str] = &[
+    "file",
+    "enum",
+    "enumConstant",
+    "class",
+    "struct",
+    "union",
+    "method",
+    "function",
+    "field",
+    "ipc",
+    "namespace",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SchemaIssueSeverity {
+    /// Something unexpected that consumers can still handle.
+    Warning,
+    /// Something that will cause the record to be dropped by consumers.
+    Error,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SchemaIssue {
+    pub severity: SchemaIssueSeverity,
+    /// A JSON-pointer-ish path to the problematic attribute, or empty for the
+    /// record as a whole.
+    pub path: String,
+    pub message: String,
+}
+
+impl SchemaIssue {
+    fn error(path: &str, message: String) -> Self {
+        SchemaIssue {
+            severity: SchemaIssueSeverity::Error,
+            path: path.to_string(),
+            message,
+        }
+    }
+
+    fn warning(path: &str, message: String) -> Self {
+        SchemaIssue {
+            severity: SchemaIssueSeverity::Warning,
+            path: path.to_string(),
+            message,
+        }
+    }
+}
+
+/// Extract the schema version of a structured record, if it is one.
+pub fn structured_record_version(obj: &Map<String, Value>) -> Option<u64> {
+    obj.get("structured").and_then(|v| v.as_u64())
+}
+
+/// Upgrade a structured record in place to `CURRENT_STRUCTURED_VERSION`,
+/// returning the version the record was originally at.  Records from a version
+/// newer than we understand are an error because the most likely explanation
+/// is that the indexers were updated without updating the Rust consumers, and
+/// we want that to be loud rather than having records silently dropped.
+pub fn upgrade_structured_record(obj: &mut Map<String, Value>) -> Result<u64, SchemaIssue> {
+    let orig_version = match structured_record_version(obj) {
+        Some(v) if v >= 1 => v,
+        _ => {
+            return Err(SchemaIssue::error(
+                "/structured",
+                format!("bad structured version: {:?}", obj.get("structured")),
+            ));
+        }
+    };
+    if orig_version > CURRENT_STRUCTURED_VERSION {
+        return Err(SchemaIssue::error(
+            "/structured",
+            format!(
+                "structured version {} is newer than supported version {}",
+                orig_version, CURRENT_STRUCTURED_VERSION
+            ),
+        ));
+    }
+
+    let mut version = orig_version;
+    for upconverter in UPCONVERTERS {
+        if upconverter.from_version == version {
+            (upconverter.convert)(obj);
+            version += 1;
+        }
+    }
+    obj.insert("structured".to_string(), Value::from(version));
+    normalize_current_version(obj);
+
+    Ok(orig_version)
+}
+
+/// Normalize variations of the current version's format that were emitted by
+/// older indexers (and older cross-referencing) but which the serde types in
+/// `analysis.rs` would reject or misinterpret:
+/// - `subclasses` and `overriddenBy` used to be (and are still documented as)
+///   arrays of `{ pretty, sym }` objects but are now arrays of symbols.
+/// - An empty `implKind` string was emitted rather than omitting it; the
+///   absence of the attribute means "impl".
+/// - `null` was emitted for empty arrays.
+/// This is also applied to any `variants`.
+fn normalize_current_version(obj: &mut Map<String, Value>) {
+    for key in ["subclasses", "overriddenBy"] {
+        if let Some(Value::Array(arr)) = obj.get_mut(key) {
+            for item in arr.iter_mut() {
+                if let Some(sym) = item.get("sym").cloned() {
+                    *item = sym;
+                }
+            }
+        }
+    }
+
+    if let Some(Value::String(s)) = obj.get("implKind") {
+        if s.is_empty() {
+            obj.remove("implKind");
+        }
+    }
+
+    for key in [
+        "bindingSlots",
+        "ontologySlots",
+        "supers",
+        "methods",
+        "fields",
+        "overrides",
+        "props",
+        "labels",
+        "subclasses",
+        "overriddenBy",
+        "variants",
+    ] {
+        if let Some(Value::Null) = obj.get(key) {
+            obj.remove(key);
+        }
+    }
+
+    if let Some(Value::Array(variants)) = obj.get_mut("variants") {
+        for variant in variants.iter_mut() {
+            if let Value::Object(vobj) = variant {
+                normalize_current_version(vobj);
+            }
+        }
+    }
+}
+
+fn check_string(obj: &Map<String, Value>, key: &str, path: &str, issues: &mut Vec<SchemaIssue>) {
+    match obj.get(key) {
+        None | Some(Value::String(_)) => {}
+        Some(other) => issues.push(SchemaIssue::error(
+            &format!("{}/{}", path, key),
+            format!("expected a string, got {}", other),
+        )),
+    }
+}
+
+/// Check an array of objects which are expected to each have a string `sym`.
+fn check_sym_object_array(
+    obj: &Map<String, Value>,
+    key: &str,
+    path: &str,
+    issues: &mut Vec<SchemaIssue>,
+) {
+    let arr = match obj.get(key) {
+        None => return,
+        Some(Value::Array(arr)) => arr,
+        Some(other) => {
+            issues.push(SchemaIssue::error(
+                &format!("{}/{}", path, key),
+                format!("expected an array, got {}", other),
+            ));
+            return;
+        }
+    };
+    for (i, item) in arr.iter().enumerate() {
+        let item_path = format!("{}/{}/{}", path, key, i);
+        match item {
+            Value::Object(item_obj) => match item_obj.get("sym") {
+                Some(Value::String(_)) => {}
+                _ => issues.push(SchemaIssue::warning(
+                    &item_path,
+                    "missing string sym".to_string(),
+                )),
+            },
+            other => issues.push(SchemaIssue::error(
+                &item_path,
+                format!("expected an object, got {}", other),
+            )),
+        }
+    }
+}
+
+fn validate_record_shape(obj: &Map<String, Value>, path: &str, issues: &mut Vec<SchemaIssue>) {
+    match obj.get("sym") {
+        Some(Value::String(s)) if !s.is_empty() => {}
+        _ => issues.push(SchemaIssue::error(
+            &format!("{}/sym", path),
+            "missing or empty sym".to_string(),
+        )),
+    }
+    for key in [
+        "pretty",
+        "kind",
+        "parentsym",
+        "implKind",
+        "type_pretty",
+        "idlsym",
+    ] {
+        check_string(obj, key, path, issues);
+    }
+    if let Some(Value::String(kind)) = obj.get("kind") {
+        if !KNOWN_KINDS.contains(&kind.as_str()) {
+            issues.push(SchemaIssue::warning(
+                &format!("{}/kind", path),
+                format!("unknown kind: {}", kind),
+            ));
+        }
+    }
+    for key in ["supers", "methods", "fields", "overrides", "bindingSlots"] {
+        check_sym_object_array(obj, key, path, issues);
+    }
+    if let Some(Value::Array(variants)) = obj.get("variants") {
+        for (i, variant) in variants.iter().enumerate() {
+            let variant_path = format!("{}/variants/{}", path, i);
+            match variant {
+                Value::Object(vobj) => validate_record_shape(vobj, &variant_path, issues),
+                other => issues.push(SchemaIssue::error(
+                    &variant_path,
+                    format!("expected an object, got {}", other),
+                )),
+            }
+        }
+    }
+}
+
+/// Validate a structured record that has already been upgraded via
+/// `upgrade_structured_record`, returning any issues found.  The checks here
+/// are intended to produce more actionable messages than serde would; callers
+/// that actually deserialize the record should also report deserialization
+/// failures.
+pub fn validate_structured_record(obj: &Map<String, Value>) -> Vec<SchemaIssue> {
+    let mut issues = vec![];
+    validate_record_shape(obj, "", &mut issues);
+    issues
+}
+
+#[test]
+fn test_upgrade_structured_record() {
+    use serde_json::json;
+
+    let mut rec = match json!({
+        "structured": 1,
+        "sym": "T_Foo",
+        "pretty": "Foo",
+        "kind": "class",
+        "implKind": "",
+        "props": null,
+        "subclasses": [{ "pretty": "Bar", "sym": "T_Bar" }, "T_Baz"],
+    }) {
+        Value::Object(obj) => obj,
+        _ => unreachable!(),
+    };
+    assert_eq!(upgrade_structured_record(&mut rec).ok(), Some(1));
+    assert_eq!(rec.get("implKind"), None);
+    assert_eq!(rec.get("props"), None);
+    assert_eq!(rec["subclasses"], json!(["T_Bar", "T_Baz"]));
+    assert!(validate_structured_record(&rec).is_empty());
+
+    let enum_constant = match json!({
+        "structured": 1,
+        "sym": "E_<T_Foo>_Bar",
+        "pretty": "Foo::Bar",
+        "kind": "enumConstant",
+        "parentsym": "T_Foo",
+    }) {
+        Value::Object(obj) => obj,
+        _ => unreachable!(),
+    };
+    assert!(validate_structured_record(&enum_constant).is_empty());
+
+    let mut future = match json!({ "structured": CURRENT_STRUCTURED_VERSION + 1, "sym": "T_Foo" }) {
+        Value::Object(obj) => obj,
+        _ => unreachable!(),
+    };
+    assert!(upgrade_structured_record(&mut future).is_err());
+}
@@ -53,6 +53,8 @@ pub mod blame;
 #[cfg(not(target_arch = "wasm32"))]
 pub mod describe;
 #[cfg(not(target_arch = "wasm32"))]
+pub mod ffi;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod file_utils;
 #[cfg(not(target_arch = "wasm32"))]
 pub mod format;
@@ -7,6 +7,7 @@ use query_parser::{parse, TermValue};
 use serde::{Deserialize, Serialize};
 use toml::value::Table;
 
+use super::query_plan::{QueryCostModel, QueryPlan};
 use crate::{
     abstract_server::{ErrorDetails, ErrorLayer, Result, ServerError},
     cmd_pipeline::transforms::path_glob_transform,
@@ -24,6 +25,11 @@ use crate::{
        used.  This is intended for short-hands like "C" for "context" where we
        want our UI to act like "context" had been used when "C" is observed so
        that we can explain to the user what is going on without being cryptic.
+     - Term escapes: If the value starts with `@` (`TERM_ESCAPE_PREFIX`) and
+       the term has an "escaped" term, we re-process the value without the
+       prefix as that term instead of processing the term itself.  This lets
+       `symbol:@SYM` look up a raw symbol without any of the expansion that
+       `symbol:` would otherwise do.
      - Term expansions: We re-process the term as one or more other terms,
        potentially transforming the value associated with the term.  Allowing
        expansion to multiple terms lets us have a single query run against
@@ -38,9 +44,15 @@ use crate::{
        (searching from the most recently added command).  Arguments are then
        contributed to the command.  This allows terms to add additional
        constraints or settings to a single pipeline command.
-     - Maybe in the future: The ability to set some kind of global variable so
-       that a single term can influence multiple pipeline commands that may or
-       may not exist (and without bringing them into existence)?
+     - Pipeline command constraints placed in a specific group.  These are
+       like command invocations except they are only applied after all terms
+       have been processed, and only to groups that some other term brought
+       into existence.  This lets a term like "path" constrain the semantic
+       and fulltext searches that other terms requested without itself
+       causing those searches to happen.  Because constraints are applied
+       last, constraining commands that don't already exist will be appended
+       after the commands contributed by the terms, regardless of the order
+       the terms appeared in the query.
   3. After the terms have produced the starting groups, we consult the "group"
      and "junction" nodes that have not yet been processed.  For each group /
      junction, we look up its config settings and:
@@ -58,17 +70,60 @@ pub struct QueryConfig {
     pub term: BTreeMap<String, TermConfig>,
     pub group: BTreeMap<String, GroupConfig>,
     pub junction: BTreeMap<String, JunctionConfig>,
+    /// Cost model thresholds used by `plan_query_with_config`.
+    #[serde(default)]
+    pub planner: QueryCostModel,
 }
 
+const QUERY_CORE_TOML: &str = include_str!("query_core.toml");
+
+impl QueryConfig {
+    /// Build a config from `query_core.toml` with the given per-tree TOML
+    /// overrides layered on top.  Each "term", "group", and "junction" entry in
+    /// the overrides replaces the core entry of the same name in its entirety,
+    /// which allows a tree to tweak limits or add terms without having to
+    /// repeat the rest of the core config.
+    pub fn from_core_with_overrides(overrides: &str) -> Result<QueryConfig> {
+        let bad_config = |err: toml::de::Error| {
+            ServerError::StickyProblem(ErrorDetails {
+                layer: ErrorLayer::ConfigLayer,
+                message: format!("bad query config: {}", err),
+            })
+        };
+        let mut merged: Table = toml::from_str(QUERY_CORE_TOML).map_err(bad_config)?;
+        let overrides: Table = toml::from_str(overrides).map_err(bad_config)?;
+        for (section, entries) in overrides {
+            match (merged.get_mut(&section), entries) {
+                (Some(toml::Value::Table(merged_entries)), toml::Value::Table(entries)) => {
+                    merged_entries.extend(entries);
+                }
+                (_, entries) => {
+                    merged.insert(section, entries);
+                }
+            }
+        }
+        toml::Value::Table(merged).try_into().map_err(bad_config)
+    }
+}
+
+/// Prefix on a term's value which requests that the term's "escaped" term be
+/// used instead, if it has one.
+pub const TERM_ESCAPE_PREFIX: char = '@';
+
 #[derive(Deserialize)]
 pub struct TermConfig {
     pub alias: Option<String>,
+    /// The term to process values starting with `TERM_ESCAPE_PREFIX` as, with
+    /// the prefix removed.
+    pub escaped: Option<String>,
     #[serde(default)]
     pub conflicts: Vec<String>,
     #[serde(default)]
     pub expand: Vec<TermExpansion>,
     #[serde(default)]
     pub group: BTreeMap<String, Vec<PipelineUse>>,
+    #[serde(default)]
+    pub constrain: BTreeMap<String, Vec<PipelineUse>>,
 }
 
 #[derive(Deserialize)]
@@ -106,7 +161,7 @@ pub struct JunctionConfig {
 }
 
 lazy_static! {
-    static ref QUERY_CORE: QueryConfig = toml::from_str(include_str!("query_core.toml")).unwrap();
+    pub(crate) static ref QUERY_CORE: QueryConfig = toml::from_str(QUERY_CORE_TOML).unwrap();
 }
 
 #[derive(Default, Serialize)]
@@ -120,6 +175,18 @@ pub struct QueryPipelineGroupBuilder {
     pub groups: BTreeMap<String, PipelineGroup>,
     pub junctions: BTreeMap<String, JunctionNode>,
     pub phases: Vec<PipelinePhase>,
+    /// Constraints from `TermConfig::constrain` which get applied by
+    /// `apply_constraints` once all terms have been ingested, as
+    /// (group name, command, args).
+    #[serde(skip)]
+    pending_constraints: Vec<(String, String, PipelineArgs)>,
+    /// The terms that appeared in the query itself (prior to alias and
+    /// expansion processing), with bare values recorded as "default".
+    #[serde(skip)]
+    pub root_terms: Vec<String>,
+    /// The plan chosen by `plan_query_with_config`, if the query was planned.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub plan: Option<QueryPlan>,
 }
 
 fn apply_transforms(user_val: String, transforms: &[String]) -> String {
@@ -165,13 +232,26 @@ impl QueryPipelineGroupBuilder {
         group.ensure_pipeline_step(command, args);
     }
 
-    pub fn ingest_term(&mut self, root_term: &str, value: &str) -> Result<()> {
+    pub fn ingest_term(
+        &mut self,
+        config: &QueryConfig,
+        root_term: &str,
+        value: &str,
+    ) -> Result<()> {
         let mut terms_to_process: VecDeque<(String, String)> = VecDeque::new();
         terms_to_process.push_back((root_term.to_string(), value.to_string()));
 
         let mut terms_processed = vec![];
         while let Some((term_str, term_value)) = terms_to_process.pop_front() {
-            if let Some(term) = QUERY_CORE.term.get(&term_str) {
+            if let Some(term) = config.term.get(&term_str) {
+                if let (Some(escaped), Some(raw_value)) =
+                    (&term.escaped, term_value.strip_prefix(TERM_ESCAPE_PREFIX))
+                {
+                    terms_to_process.push_back((escaped.clone(), raw_value.to_string()));
+                    terms_processed.push(term_str);
+                    continue;
+                }
+
                 if let Some(alias) = &term.alias {
                     terms_to_process.push_back((alias.clone(), term_value.clone()));
                 }
@@ -202,6 +282,17 @@ impl QueryPipelineGroupBuilder {
                         );
                     }
                 }
+
+                for (group_name, pipeline_uses) in term.constrain.iter() {
+                    for pipe_use in pipeline_uses.iter() {
+                        let flattened_args = flatten_args(&term_value, 0, &pipe_use.args);
+                        self.pending_constraints.push((
+                            group_name.clone(),
+                            pipe_use.command.clone(),
+                            flattened_args,
+                        ));
+                    }
+                }
             }
 
             terms_processed.push(term_str);
@@ -209,6 +300,17 @@ impl QueryPipelineGroupBuilder {
 
         Ok(())
     }
+
+    /// Apply the constraints accumulated by `ingest_term` to the groups that
+    /// exist now that all terms have been ingested, discarding constraints on
+    /// groups that don't exist.
+    pub fn apply_constraints(&mut self) {
+        for (group_name, command, args) in std::mem::take(&mut self.pending_constraints) {
+            if let Some(group) = self.groups.get_mut(&group_name) {
+                group.ensure_pipeline_step(command, args);
+            }
+        }
+    }
 }
 
 #[derive(Default, Serialize)]
@@ -301,6 +403,25 @@ pub struct PipelineSegment {
 }
 
 pub fn chew_query(full_arg_str: &str) -> Result<QueryPipelineGroupBuilder> {
+    chew_query_with_config(full_arg_str, &QUERY_CORE)
+}
+
+/// Like `chew_query` but using the given config instead of the built-in
+/// `query_core.toml` config, such as a per-tree config from
+/// `TreeQueryConfigs`.
+pub fn chew_query_with_config(
+    full_arg_str: &str,
+    config: &QueryConfig,
+) -> Result<QueryPipelineGroupBuilder> {
+    let builder = ingest_query(full_arg_str, config)?;
+    build_query_graph(builder, config)
+}
+
+/// Perform steps 1 and 2 of `chew_query_with_config`, producing a builder with
+/// the groups requested by the terms but no graph.  The query planner uses
+/// this to inspect (and potentially prune) the groups before the graph gets
+/// built by `build_query_graph`.
+pub fn ingest_query(full_arg_str: &str, config: &QueryConfig) -> Result<QueryPipelineGroupBuilder> {
     let mut builder = QueryPipelineGroupBuilder::default();
     // ## 1: Parse the Query
     let q = parse(full_arg_str);
@@ -309,15 +430,26 @@ pub fn chew_query(full_arg_str: &str) -> Result<QueryPipelineGroupBuilder> {
     for term in q.terms {
         match term.value {
             TermValue::Simple(value) => {
-                if let Some(key) = term.key {
-                    builder.ingest_term(&key, &value)?;
-                } else {
-                    builder.ingest_term("default", &value)?;
-                }
+                let key = match term.key {
+                    Some(key) => key.to_string(),
+                    None => "default".to_string(),
+                };
+                builder.ingest_term(config, &key, &value)?;
+                builder.root_terms.push(key);
             }
         }
     }
+    builder.apply_constraints();
+
+    Ok(builder)
+}
 
+/// Perform steps 3 and 4 of `chew_query_with_config` on a builder produced by
+/// `ingest_query`.
+pub fn build_query_graph(
+    mut builder: QueryPipelineGroupBuilder,
+    config: &QueryConfig,
+) -> Result<QueryPipelineGroupBuilder> {
     // ## 3: Process group rules to build the graph suggested by the terms above
     let mut unprocessed_groups: VecDeque<String> = builder.groups.keys().cloned().collect();
     let mut unprocessed_junctions: VecDeque<String> = VecDeque::new();
@@ -345,7 +477,7 @@ pub fn chew_query(full_arg_str: &str) -> Result<QueryPipelineGroupBuilder> {
         // easier if we process them first.
         if let Some(group_name) = unprocessed_groups.pop_front() {
             if let (Some(group_config), Some(group)) = (
-                QUERY_CORE.group.get(&group_name),
+                config.group.get(&group_name),
                 builder.groups.get_mut(&group_name),
             ) {
                 group.output = Some(group_config.output.clone());
@@ -358,7 +490,7 @@ pub fn chew_query(full_arg_str: &str) -> Result<QueryPipelineGroupBuilder> {
             }
         } else if let Some(junction_name) = unprocessed_junctions.pop_front() {
             if let (Some(junction_config), Some(junction)) = (
-                QUERY_CORE.junction.get(&junction_name),
+                config.junction.get(&junction_name),
                 builder.junctions.get_mut(&junction_name),
             ) {
                 junction.output = Some(junction_config.output.clone());
@@ -372,7 +504,7 @@ pub fn chew_query(full_arg_str: &str) -> Result<QueryPipelineGroupBuilder> {
         // Make the requested thing.
         if let Some(group_name) = next_group {
             if let (Some(group_config), group) = (
-                QUERY_CORE.group.get(&group_name),
+                config.group.get(&group_name),
                 builder
                     .groups
                     .entry(group_name.clone())
@@ -402,7 +534,7 @@ pub fn chew_query(full_arg_str: &str) -> Result<QueryPipelineGroupBuilder> {
             }
         } else if let Some(junction_name) = next_junction {
             if let (Some(junction_config), junction, Some(input)) = (
-                QUERY_CORE.junction.get(&junction_name),
+                config.junction.get(&junction_name),
                 builder
                     .junctions
                     .entry(junction_name.clone())
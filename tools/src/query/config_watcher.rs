@@ -0,0 +1,168 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use tokio::task::JoinHandle;
+
+use super::chew_query::QueryConfig;
+
+/// The name of the optional per-tree file in the tree's config repo directory
+/// whose contents are layered over `query_core.toml`; see
+/// `QueryConfig::from_core_with_overrides`.
+pub const TREE_QUERY_CONFIG_FILENAME: &str = "query.toml";
+
+struct TreeQueryConfig {
+    path: PathBuf,
+    /// The modification time and size of the file when we last (tried to)
+    /// load it, or None if it didn't exist.  The size helps catch changes made
+    /// within the granularity of the file system's modification times.
+    last_modified: Mutex<Option<(SystemTime, u64)>>,
+    current: RwLock<Arc<QueryConfig>>,
+}
+
+/// The per-tree `QueryConfig`s for a long-running server.  Each tree may have a
+/// `query.toml` in its config repo directory, and `reload_changed` (which
+/// `spawn_watcher` calls periodically) picks up any changes to these files so
+/// that config tweaks don't require a server restart.
+///
+/// Only the `QueryConfig` (terms, groups, junctions and the planner's cost
+/// model) is hot-reloaded this way.  The rest of the tree's configuration,
+/// such as its path classification in `per-file-info.toml` and the settings in
+/// `config.json`, is loaded once when the server starts, so changing it still
+/// requires a restart.
+///
+/// Configs are swapped atomically; queries that already retrieved a config via
+/// `get` keep using it until they complete.  If a changed file fails to parse,
+/// we log a warning and keep serving the last good config.
+pub struct TreeQueryConfigs {
+    trees: BTreeMap<String, TreeQueryConfig>,
+}
+
+fn modified_stamp(path: &PathBuf) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Load the config for the given path, which is the core config if the file
+/// does not exist.
+fn load_config(path: &PathBuf) -> Result<QueryConfig, String> {
+    let overrides = match fs::read_to_string(path) {
+        Ok(overrides) => overrides,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err.to_string()),
+    };
+    QueryConfig::from_core_with_overrides(&overrides).map_err(|err| format!("{:?}", err))
+}
+
+impl TreeQueryConfigs {
+    pub fn new<'a>(config_repo_path: &str, tree_names: impl Iterator<Item = &'a String>) -> Self {
+        let mut trees = BTreeMap::new();
+        for tree_name in tree_names {
+            let path = PathBuf::from(format!(
+                "{}/{}/{}",
+                config_repo_path, tree_name, TREE_QUERY_CONFIG_FILENAME
+            ));
+            let config = match load_config(&path) {
+                Ok(config) => config,
+                Err(err) => {
+                    warn!(tree = %tree_name, err = %err, "using core query config");
+                    // The core config is compiled in and known to be valid.
+                    QueryConfig::from_core_with_overrides("").unwrap()
+                }
+            };
+            trees.insert(
+                tree_name.clone(),
+                TreeQueryConfig {
+                    last_modified: Mutex::new(modified_stamp(&path)),
+                    path,
+                    current: RwLock::new(Arc::new(config)),
+                },
+            );
+        }
+        TreeQueryConfigs { trees }
+    }
+
+    /// Get the current config for the tree, if it's a tree we know about.
+    pub fn get(&self, tree_name: &str) -> Option<Arc<QueryConfig>> {
+        self.trees
+            .get(tree_name)
+            .map(|tree| tree.current.read().unwrap().clone())
+    }
+
+    /// Reload the configs of any trees whose config files changed (including
+    /// being created or removed) since we last looked, returning the names of
+    /// the trees whose configs were swapped.
+    pub fn reload_changed(&self) -> Vec<String> {
+        let mut reloaded = vec![];
+        for (tree_name, tree) in &self.trees {
+            let modified = modified_stamp(&tree.path);
+            {
+                let mut last_modified = tree.last_modified.lock().unwrap();
+                if *last_modified == modified {
+                    continue;
+                }
+                // We update this even if the load fails so that we don't keep
+                // retrying (and warning about) the same broken file.
+                *last_modified = modified;
+            }
+            match load_config(&tree.path) {
+                Ok(config) => {
+                    *tree.current.write().unwrap() = Arc::new(config);
+                    info!(tree = %tree_name, "reloaded query config");
+                    reloaded.push(tree_name.clone());
+                }
+                Err(err) => {
+                    warn!(tree = %tree_name, err = %err, "keeping previous query config");
+                }
+            }
+        }
+        reloaded
+    }
+
+    /// Spawn a task that checks for config changes every `interval`.
+    pub fn spawn_watcher(self: Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                self.reload_changed();
+            }
+        })
+    }
+}
+
+#[test]
+fn test_reload_changed() {
+    let root = std::env::temp_dir().join(format!("query-config-test-{}", std::process::id()));
+    fs::create_dir_all(root.join("tree")).unwrap();
+    let toml_path = root.join("tree").join(TREE_QUERY_CONFIG_FILENAME);
+    let _ = fs::remove_file(&toml_path);
+
+    let tree_names = vec!["tree".to_string()];
+    let configs = TreeQueryConfigs::new(root.to_str().unwrap(), tree_names.iter());
+    let core_config = configs.get("tree").unwrap();
+    assert!(!core_config.term.contains_key("test-only-term"));
+    assert!(configs.reload_changed().is_empty());
+
+    fs::write(&toml_path, "[term.test-only-term]\nalias = \"text\"\n").unwrap();
+    assert_eq!(configs.reload_changed(), vec!["tree".to_string()]);
+    let config = configs.get("tree").unwrap();
+    assert!(config.term.contains_key("test-only-term"));
+    // The core terms are still present.
+    assert_eq!(config.term.len(), core_config.term.len() + 1);
+
+    // A broken file keeps the last good config.
+    fs::write(&toml_path, "[term.test-only-term\n").unwrap();
+    assert!(configs.reload_changed().is_empty());
+    assert!(configs
+        .get("tree")
+        .unwrap()
+        .term
+        .contains_key("test-only-term"));
+
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,1053 @@
+//! A compatibility layer for the legacy `router.py` "search" endpoint.  It
+//! accepts the same query-string parameters (`q`, `path`, `case`, `regexp`)
+//! and produces the same JSON, byte for byte, so that the pipeline-server can
+//! replace router.py and so the two can be differentially tested against each
+//! other (see the `legacy-search` command).
+//!
+//! This is a port of router.py's `get_json_search_results` and its helpers
+//! that deliberately preserves their quirks, which are called out in comments
+//! starting with "Quirk".  The known divergences are:
+//! - Regexps are evaluated by the `regex` crate rather than Python's `re` and
+//!   grep's EREs, which only matters for syntax the crate doesn't support.
+//! - Identifier lookup uses `IdentMap`, which normalizes the needle.
+//! - Only the JSON response is produced; the HTML wrapping router.py does for
+//!   requests that don't accept JSON is not.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    process::Stdio,
+};
+
+use regex::{Regex, RegexBuilder};
+use serde::Serialize;
+use serde_json::{json, ser::Formatter, Map, Value};
+use tokio::{io::AsyncWriteExt, process::Command};
+use ustr::Ustr;
+
+use crate::{
+    abstract_server::{AbstractServer, Result, TextSearchExitReason},
+    cmd_pipeline::{interface::StageEstimate, transforms::path_glob_transform},
+};
+
+// router.py's "brief and poorly but excitingly named scaling factor".
+const EXTREME_FACTOR: usize = 4;
+/// The maximum number of line results (plus paths; see `sort_compiled`).
+const MAX_COUNT: usize = 1000 * EXTREME_FACTOR;
+/// The number of path hits per kind after which the qualified results of a
+/// default search stop being compiled.
+const MAX_WORK: usize = 1000 * EXTREME_FACTOR;
+const FILE_RESPONSE_LIMIT: usize = 1000 * EXTREME_FACTOR;
+const FILE_PRE_FILTER_RESPONSE_LIMIT: usize = FILE_RESPONSE_LIMIT * 8;
+/// The `-max_matches` router.py's codesearch daemon is started with.
+const CODESEARCH_MAX_MATCHES: usize = 4000;
+/// Quirk: router.py breaks out of the identifier loop when the index is
+/// greater than 500, so it processes 501 identifiers.
+const IDENTIFIER_LIMIT: usize = 501;
+
+const PATH_PRECEDENCES: [&str; 4] = ["normal", "thirdparty", "test", "generated"];
+const KEY_PRECEDENCES: [&str; 13] = [
+    "Files",
+    "IDL",
+    "Definitions",
+    "Declarations",
+    "Bindings",
+    "Aliases",
+    "Overrides",
+    "Overridden By",
+    "Superclasses",
+    "Subclasses",
+    "Assignments",
+    "Uses",
+    "Textual Occurrences",
+];
+/// How crossref keys map to the legacy result kinds, where None means the
+/// key is dropped.
+const KEY_REMAPPING: [(&str, Option<&str>); 8] = [
+    ("uses", Some("Uses")),
+    ("defs", Some("Definitions")),
+    ("assignments", Some("Assignments")),
+    ("decls", Some("Declarations")),
+    ("idl", Some("IDL")),
+    ("aliases", Some("Aliases")),
+    ("callees", None),
+    ("field-member-uses", None),
+];
+
+/// The query-string parameters router.py's "search" endpoint understood.
+#[derive(Debug, Default, PartialEq)]
+pub struct LegacySearchParams {
+    pub q: Option<String>,
+    pub path: Option<String>,
+    pub case: Option<String>,
+    pub regexp: Option<String>,
+}
+
+impl LegacySearchParams {
+    /// Parse a URL query string the way router.py's use of
+    /// `urllib.parse.parse_qs` did: blank values are dropped and the first
+    /// value of a repeated parameter wins.
+    pub fn from_query_string(query: &str) -> Self {
+        let mut params = LegacySearchParams::default();
+        for (key, value) in url::form_urlencoded::parse(query.as_bytes()) {
+            if value.is_empty() {
+                continue;
+            }
+            let slot = match key.as_ref() {
+                "q" => &mut params.q,
+                "path" => &mut params.path,
+                "case" => &mut params.case,
+                "regexp" => &mut params.regexp,
+                _ => continue,
+            };
+            slot.get_or_insert_with(|| value.into_owned());
+        }
+        params
+    }
+}
+
+/// The result of router.py's `parse_search`.
+#[derive(Debug, Default, PartialEq)]
+pub struct ParsedSearch {
+    pub pathre: Option<String>,
+    pub context_lines: Option<u32>,
+    pub symbol: Option<String>,
+    pub re: Option<String>,
+    pub id: Option<String>,
+    pub default: Option<String>,
+}
+
+impl ParsedSearch {
+    /// router.py's `is_trivial_search`: searches where every term is shorter
+    /// than 3 characters return no results.
+    pub fn is_trivial(&self) -> bool {
+        if self.symbol.is_some() {
+            return false;
+        }
+        [&self.pathre, &self.re, &self.id, &self.default]
+            .iter()
+            .filter_map(|term| term.as_deref())
+            .all(|term| term.chars().count() < 3)
+    }
+}
+
+/// router.py's `escape_regex`, which unlike `re.escape` leaves non-ASCII
+/// characters alone.
+pub fn escape_regex(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if "(){}[].*?|^$\\+-".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// router.py's `parse_search`.
+pub fn parse_search(search: &str) -> ParsedSearch {
+    let pieces: Vec<&str> = search.split(' ').collect();
+    let mut parsed = ParsedSearch::default();
+    for (i, piece) in pieces.iter().enumerate() {
+        let rest = || pieces[i..].join(" ");
+        if let Some(path) = piece.strip_prefix("path:") {
+            parsed.pathre = Some(path_glob_transform(path));
+        } else if let Some(pathre) = piece.strip_prefix("pathre:") {
+            parsed.pathre = Some(pathre.to_string());
+        } else if let Some(context) = piece.strip_prefix("context:") {
+            if let Ok(context) = context.parse::<i64>() {
+                parsed.context_lines = Some(context.clamp(0, 10) as u32);
+            }
+        } else if piece.starts_with("symbol:") {
+            // Quirk: the symbol consumes the rest of the string but parsing
+            // continues, so "symbol:S path:P" searches for the symbol "S
+            // path:P" filtered to P.
+            parsed.symbol = Some(rest()["symbol:".len()..].trim().to_string());
+        } else if piece.starts_with("re:") {
+            parsed.re = Some(rest()["re:".len()..].to_string());
+            break;
+        } else if piece.starts_with("text:") {
+            parsed.re = Some(escape_regex(&rest()["text:".len()..]));
+            break;
+        } else if let Some(id) = piece.strip_prefix("id:") {
+            parsed.id = Some(id.to_string());
+        } else {
+            parsed.default = Some(escape_regex(&rest()));
+            break;
+        }
+    }
+    parsed
+}
+
+/// router.py's `SearchResults.categorize_path`.
+fn categorize_path(path: &str) -> &'static str {
+    let is_test = |p: &str| {
+        if p.contains("/unit/") || p.contains("/androidTest/") || p.starts_with("LayoutTests/") {
+            return true;
+        }
+        if !p.contains("test") {
+            return false;
+        }
+        [
+            "/test/",
+            "/tests/",
+            "/mochitest/",
+            "testing/",
+            "/jsapi-tests/",
+            "/reftests/",
+            "/reftest/",
+            "/crashtests/",
+            "/crashtest/",
+            "/googletest/",
+            "/gtest/",
+            "/gtests/",
+            "/imptests/",
+        ]
+        .iter()
+        .any(|pattern| p.contains(pattern))
+    };
+
+    if path.contains("__GENERATED__") {
+        "generated"
+    } else if path.starts_with("third_party/") {
+        "thirdparty"
+    } else if is_test(path) {
+        "test"
+    } else {
+        "normal"
+    }
+}
+
+/// The line hits of a path under a "qkind" along with the length to narrow
+/// their bounds to, if any, which is router.py's `line_modifier`.
+type CompiledPath = (Vec<Value>, Option<usize>);
+
+/// The "qkind"s of a path kind in the order they were first seen, which is
+/// what router.py's `OrderedDict` gave it.
+#[derive(Default)]
+struct CompiledPathKind {
+    qkinds: Vec<(String, BTreeMap<String, CompiledPath>)>,
+    index: HashMap<String, usize>,
+}
+
+impl CompiledPathKind {
+    fn qkind_paths(&mut self, qkind: String) -> &mut BTreeMap<String, CompiledPath> {
+        let next = self.qkinds.len();
+        let i = *self.index.entry(qkind.clone()).or_insert(next);
+        if i == next {
+            self.qkinds.push((qkind, BTreeMap::new()));
+        }
+        &mut self.qkinds[i].1
+    }
+}
+
+/// router.py's `SearchResults`.  Results are maps from legacy result kinds
+/// (ex: "Definitions") to lists of `{ path, lines }` path hits.
+#[derive(Default)]
+struct LegacyResults {
+    results: Vec<Map<String, Value>>,
+    /// (qualified identifier, results, bounds narrowing length)
+    qualified_results: Vec<(String, Map<String, Value>, Option<usize>)>,
+    count_limit_hit: bool,
+    work_limit_hit: bool,
+    pathre: Option<Regex>,
+    compiled: HashMap<&'static str, CompiledPathKind>,
+}
+
+impl LegacyResults {
+    fn set_path_filter(&mut self, path: Option<&str>) {
+        self.pathre = match path {
+            None | Some("") | Some(".*") => None,
+            Some(path) => {
+                let build =
+                    |pattern: &str| RegexBuilder::new(pattern).case_insensitive(true).build();
+                // Invalid patterns are treated as literal strings.
+                build(path).or_else(|_| build(&regex::escape(path))).ok()
+            }
+        };
+    }
+
+    fn add_results(&mut self, results: Map<String, Value>) {
+        self.results.push(results);
+    }
+
+    fn add_qualified_results(
+        &mut self,
+        qual: String,
+        results: Map<String, Value>,
+        narrow_to: Option<usize>,
+    ) {
+        self.qualified_results.push((qual, results, narrow_to));
+    }
+
+    fn compile_result(
+        &mut self,
+        kind: &str,
+        qual: Option<&str>,
+        path_hits: &Value,
+        narrow_to: Option<usize>,
+    ) {
+        let qkind = match qual {
+            Some(qual) if !qual.is_empty() => format!("{} ({})", kind, qual),
+            _ => kind.to_string(),
+        };
+        let path = path_hits["path"].as_str().unwrap_or_default();
+        let pathkind = categorize_path(path);
+        if let Some(pathre) = &self.pathre {
+            if !pathre.is_match(path) {
+                return;
+            }
+        }
+
+        let (lines, _) = self
+            .compiled
+            .entry(pathkind)
+            .or_default()
+            .qkind_paths(qkind)
+            .entry(path.to_string())
+            .or_insert_with(|| (vec![], narrow_to));
+        if let Some(path_lines) = path_hits["lines"].as_array() {
+            lines.extend(path_lines.iter().cloned());
+        }
+    }
+
+    fn sort_compiled(&mut self) -> Map<String, Value> {
+        let mut count = 0;
+        let mut seen_lines: HashSet<(String, Option<i64>)> = HashSet::new();
+        let mut result = Map::new();
+        'pathkinds: for pathkind in PATH_PRECEDENCES {
+            let compiled = match self.compiled.remove(pathkind) {
+                Some(compiled) => compiled,
+                None => continue,
+            };
+            for (qkind, paths) in compiled.qkinds {
+                for (path, (mut lines, narrow_to)) in paths {
+                    lines.sort_by_key(|line| line["lno"].as_i64());
+                    let mut lines_out = vec![];
+                    let mut stopped = false;
+                    for mut line in lines {
+                        if !seen_lines.insert((path.clone(), line["lno"].as_i64())) {
+                            continue;
+                        }
+                        if let Some(narrow_to) = narrow_to {
+                            narrow_bounds(&mut line, narrow_to);
+                        }
+                        lines_out.push(line);
+                        count += 1;
+                        if count == MAX_COUNT {
+                            self.count_limit_hit = true;
+                            stopped = true;
+                            break;
+                        }
+                    }
+                    // Quirk: this is a Python `for`/`else` that was meant to
+                    // count paths without lines as 1 result, but it actually
+                    // counts every path whose lines didn't hit the limit.
+                    if !stopped {
+                        count += 1;
+                        if count == MAX_COUNT {
+                            self.count_limit_hit = true;
+                        }
+                    }
+
+                    if !lines_out.is_empty() || qkind == "Files" {
+                        if let Value::Object(qkinds) = result.entry(pathkind).or_insert(json!({})) {
+                            if let Value::Array(path_hits) =
+                                qkinds.entry(qkind.clone()).or_insert(json!([]))
+                            {
+                                path_hits.push(json!({ "path": path, "lines": lines_out }));
+                            }
+                        }
+                    }
+                    if count == MAX_COUNT {
+                        break 'pathkinds;
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    fn get(&mut self, work_limit: bool) -> Map<String, Value> {
+        // Sorting is stable, like Python's.
+        let mut qualified_results = std::mem::take(&mut self.qualified_results);
+        qualified_results.sort_by(|a, b| a.0.cmp(&b.0));
+        let results = std::mem::take(&mut self.results);
+        for kind in KEY_PRECEDENCES {
+            let mut work = 0;
+            for (qual, qual_results, narrow_to) in &qualified_results {
+                if work > MAX_WORK && work_limit {
+                    self.work_limit_hit = true;
+                    break;
+                }
+                for path_hits in qual_results
+                    .get(kind)
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                {
+                    self.compile_result(kind, Some(qual), path_hits, *narrow_to);
+                    work += 1;
+                }
+            }
+            for plain_results in &results {
+                for path_hits in plain_results
+                    .get(kind)
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                {
+                    self.compile_result(kind, None, path_hits, None);
+                    work += 1;
+                }
+            }
+        }
+        self.sort_compiled()
+    }
+}
+
+/// router.py's identifier search `line_modifier`, which narrows the bounds of
+/// a hit to the length of the last component of the (prefix) needle.
+fn narrow_bounds(line: &mut Value, narrow_to: usize) {
+    if let Some(start) = line
+        .get("bounds")
+        .and_then(|bounds| bounds.get(0))
+        .and_then(Value::as_i64)
+    {
+        line["bounds"] = json!([start, start + narrow_to as i64]);
+    }
+}
+
+/// router.py's `crossrefs.lookup_merging`: look up the comma-separated
+/// symbols, concatenating their results by key.  The metas are gathered into
+/// a list for `expand_keys`.
+async fn lookup_merging(
+    server: &(dyn AbstractServer + Send + Sync),
+    symbols: &str,
+) -> Result<Map<String, Value>> {
+    let mut results = Map::new();
+    for symbol in symbols.split(',') {
+        let record = match server.crossref_lookup(symbol, false).await? {
+            Value::Object(record) => record,
+            _ => continue,
+        };
+        for (key, value) in record {
+            if key == "callees" {
+                continue;
+            }
+            let values = match (key.as_str(), value) {
+                ("meta", meta) => vec![meta],
+                (_, Value::Array(values)) => values,
+                _ => continue,
+            };
+            if let Value::Array(merged) = results.entry(key).or_insert_with(|| json!([])) {
+                merged.extend(values);
+            }
+        }
+    }
+    Ok(results)
+}
+
+/// router.py's `merge_defs_from_symbols_as`: add the definitions of the
+/// symbols to the results under `as_key`, each labeled with an "upsearch" for
+/// the symbol.
+async fn merge_defs_from_symbols_as(
+    server: &(dyn AbstractServer + Send + Sync),
+    target: &mut Map<String, Value>,
+    symbol_names: Vec<String>,
+    as_key: &str,
+) -> Result<()> {
+    if symbol_names.len() >= 100 {
+        return Ok(());
+    }
+
+    let mut aggr_defs = vec![];
+    for symbol_name in symbol_names {
+        let mut info = server.crossref_lookup(&symbol_name, false).await?;
+        let defs = match info.get_mut("defs").map(Value::take) {
+            Some(Value::Array(defs)) => defs,
+            _ => continue,
+        };
+        for mut path_hit in defs {
+            if let Some(Value::Object(line)) = path_hit.get_mut("lines").and_then(|l| l.get_mut(0))
+            {
+                line.insert(
+                    "upsearch".to_string(),
+                    json!(format!("symbol:{}", symbol_name)),
+                );
+            }
+            aggr_defs.push(path_hit);
+        }
+    }
+
+    if !aggr_defs.is_empty() {
+        if let Value::Array(existing) = target.entry(as_key).or_insert_with(|| json!([])) {
+            existing.extend(aggr_defs);
+        }
+    }
+    Ok(())
+}
+
+/// router.py's `expand_keys` with `traverse_relations` set: rename the
+/// crossref keys to the legacy result kinds and add the definitions of the
+/// symbols related by the metas.
+async fn expand_keys(
+    server: &(dyn AbstractServer + Send + Sync),
+    mut keyed: Map<String, Value>,
+) -> Result<Map<String, Value>> {
+    for (new_name, old_name) in KEY_REMAPPING {
+        if let Some(value) = keyed.remove(new_name) {
+            if let Some(old_name) = old_name {
+                keyed.insert(old_name.to_string(), value);
+            }
+        }
+    }
+
+    let metas = match keyed.remove("meta") {
+        Some(Value::Array(metas)) => metas,
+        _ => return Ok(keyed),
+    };
+    let strs = |meta: &Value, key: &str| -> Vec<String> {
+        meta[key]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect()
+    };
+    let syms = |meta: &Value, key: &str, owner_is_idl: Option<bool>| -> Vec<String> {
+        meta[key]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|entry| match owner_is_idl {
+                Some(is_idl) => (entry["ownerLang"] == "idl") == is_idl,
+                None => true,
+            })
+            .filter_map(|entry| entry["sym"].as_str())
+            .map(str::to_string)
+            .collect()
+    };
+    for meta in &metas {
+        if meta.get("overrides").is_some() {
+            let names = syms(meta, "overrides", None);
+            merge_defs_from_symbols_as(server, &mut keyed, names, "Overrides").await?;
+        }
+        if meta.get("overriddenBy").is_some() {
+            let names = strs(meta, "overriddenBy");
+            merge_defs_from_symbols_as(server, &mut keyed, names, "Overridden By").await?;
+        }
+        if meta.get("supers").is_some() {
+            let names = syms(meta, "supers", None);
+            merge_defs_from_symbols_as(server, &mut keyed, names, "Superclasses").await?;
+        }
+        if meta.get("subclasses").is_some() {
+            let names = strs(meta, "subclasses");
+            merge_defs_from_symbols_as(server, &mut keyed, names, "Subclasses").await?;
+        }
+        if let Some(owner) = meta.get("slotOwner") {
+            let names = owner["sym"]
+                .as_str()
+                .map(str::to_string)
+                .into_iter()
+                .collect();
+            let as_key = if owner["ownerLang"] == "idl" {
+                "IDL"
+            } else {
+                "Definitions"
+            };
+            merge_defs_from_symbols_as(server, &mut keyed, names, as_key).await?;
+        }
+        if meta.get("bindingSlots").is_some() {
+            let names = syms(meta, "bindingSlots", Some(true));
+            merge_defs_from_symbols_as(server, &mut keyed, names, "Definitions").await?;
+            let names = syms(meta, "bindingSlots", Some(false));
+            merge_defs_from_symbols_as(server, &mut keyed, names, "Bindings").await?;
+        }
+    }
+    Ok(keyed)
+}
+
+/// router.py labels identifier results with the `c++filt --no-params`
+/// demangling of the symbol when there is one.  c++filt leaves symbols that
+/// aren't mangled C++ names alone, so we only pass it those, and all of them
+/// go through a single c++filt process, one per line.  The returned map only
+/// has entries for the symbols that were demangled.
+async fn demangle_all(syms: &[Ustr]) -> HashMap<Ustr, String> {
+    let mangled: Vec<Ustr> = syms
+        .iter()
+        .filter(|sym| sym.starts_with("_Z"))
+        .cloned()
+        .collect();
+    if mangled.is_empty() {
+        return HashMap::new();
+    }
+
+    let run = async {
+        let mut child = Command::new("c++filt")
+            .arg("--no-params")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let mut stdin = child.stdin.take().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::BrokenPipe, "c++filt has no stdin")
+        })?;
+        let mut input = String::new();
+        for sym in &mangled {
+            input.push_str(sym);
+            input.push('\n');
+        }
+        // Write while reading so that c++filt can't block on a full stdout
+        // pipe while we block on a full stdin pipe.  Dropping stdin closes it.
+        let write = async move { stdin.write_all(input.as_bytes()).await };
+        let (written, output) = tokio::join!(write, child.wait_with_output());
+        written?;
+        output
+    };
+    let output = match run.await {
+        Ok(output) if output.status.success() => output,
+        _ => return HashMap::new(),
+    };
+    let demangled = match String::from_utf8(output.stdout) {
+        Ok(demangled) => demangled,
+        Err(_) => return HashMap::new(),
+    };
+    let lines: Vec<&str> = demangled.lines().collect();
+    if lines.len() != mangled.len() {
+        return HashMap::new();
+    }
+    mangled
+        .into_iter()
+        .zip(lines)
+        .map(|(sym, line)| (sym, line.trim().to_string()))
+        .collect()
+}
+
+/// Undo backslash escapes like router.py's `re.sub(r'\\(.)', r'\1', needle)`.
+fn unescape_needle(needle: &str) -> String {
+    let mut unescaped = String::with_capacity(needle.len());
+    let mut chars = needle.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some(&next)) if next != '\n' => {
+                unescaped.push(next);
+                chars.next();
+            }
+            _ => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+/// router.py's `identifier_search`.
+async fn identifier_search(
+    server: &(dyn AbstractServer + Send + Sync),
+    search: &mut LegacyResults,
+    needle: &str,
+    complete: bool,
+    fold_case: bool,
+) -> Result<()> {
+    lazy_static! {
+        static ref RE_COMPONENT_SEP: Regex = Regex::new(r"\.|::").unwrap();
+    }
+    // Quirk: this undoes `escape_regex` but also applies to "id:" needles,
+    // which were never escaped.
+    let needle = unescape_needle(needle);
+    let last_len = RE_COMPONENT_SEP
+        .split(&needle)
+        .last()
+        .map_or(0, |last| last.chars().count());
+    if !complete && last_len < 3 {
+        return Ok(());
+    }
+
+    let ids = server
+        .search_identifiers(&needle, complete, fold_case, IDENTIFIER_LIMIT)
+        .await?;
+    let syms: Vec<Ustr> = ids.iter().map(|(sym, _)| *sym).collect();
+    let demangled = demangle_all(&syms).await;
+    for (sym, qualified) in ids {
+        let qual = match demangled.get(&sym) {
+            Some(demangled) if demangled != sym.as_str() => demangled.clone(),
+            _ => qualified.to_string(),
+        };
+        let results = expand_keys(server, lookup_merging(server, &sym).await?).await?;
+        search.add_qualified_results(qual, results, Some(last_len));
+    }
+    Ok(())
+}
+
+/// router.py's `codesearch.search`, returning the path hits and whether the
+/// search timed out or hit its match limit.
+async fn codesearch(
+    server: &(dyn AbstractServer + Send + Sync),
+    pattern: &str,
+    fold_case: bool,
+    path: Option<&str>,
+    context_lines: u32,
+) -> (Vec<Value>, bool, bool) {
+    let path = match path {
+        None | Some(".*") => "",
+        Some(path) => path,
+    };
+    match server
+        .search_text(
+            pattern,
+            fold_case,
+            path,
+            CODESEARCH_MAX_MATCHES,
+            context_lines,
+        )
+        .await
+    {
+        Ok(matches) => {
+            let hits = matches
+                .by_file
+                .into_iter()
+                .map(|file_matches| {
+                    let lines: Vec<Value> = file_matches
+                        .matches
+                        .into_iter()
+                        .map(|m| {
+                            let mut line = json!({
+                                "lno": m.line_num,
+                                "bounds": [m.bounds.start, m.bounds.end_exclusive],
+                                "line": m.line_str,
+                            });
+                            if !m.context_before.is_empty() {
+                                line["context_before"] = json!(m.context_before);
+                            }
+                            if !m.context_after.is_empty() {
+                                line["context_after"] = json!(m.context_after);
+                            }
+                            line
+                        })
+                        .collect();
+                    json!({ "path": file_matches.file, "lines": lines })
+                })
+                .collect();
+            (
+                hits,
+                matches.exit_reason == TextSearchExitReason::Timeout,
+                matches.exit_reason == TextSearchExitReason::MatchLimit,
+            )
+        }
+        // Quirk: router.py swallows all codesearch errors (ex: invalid
+        // regexps) and reports no results.
+        Err(_) => (vec![], false, false),
+    }
+}
+
+/// router.py's `search_files`, which greps the file lists case-insensitively,
+/// returning the path hits and whether there were too many.
+async fn search_files(
+    server: &(dyn AbstractServer + Send + Sync),
+    path: &str,
+) -> (Vec<Value>, bool) {
+    match server
+        .search_files(
+            &format!("(?i){}", path),
+            false,
+            FILE_PRE_FILTER_RESPONSE_LIMIT,
+        )
+        .await
+    {
+        Ok(file_matches) => {
+            let limit_hit = file_matches.file_matches.len() > FILE_RESPONSE_LIMIT;
+            let hits = file_matches
+                .file_matches
+                .into_iter()
+                .map(|file_match| json!({ "path": file_match.path, "lines": [] }))
+                .collect();
+            (hits, limit_hit)
+        }
+        // grep failing (ex: an invalid pattern) means no results.
+        Err(_) => (vec![], false),
+    }
+}
+
+fn keyed(kind: &str, path_hits: Vec<Value>) -> Map<String, Value> {
+    let mut results = Map::new();
+    results.insert(kind.to_string(), Value::Array(path_hits));
+    results
+}
+
+/// Parse the search of the request the way `get_json_search_results` did,
+/// applying its `path` and `regexp` parameters.
+fn parse_params(params: &LegacySearchParams) -> ParsedSearch {
+    let search_string = params.q.clone().unwrap_or_default();
+    let mut parsed = parse_search(&search_string);
+    if let Some(path_filter) = &params.path {
+        parsed.pathre = Some(path_glob_transform(path_filter));
+    }
+    if params.regexp.as_deref() == Some("true") {
+        // Quirk: the whole search string is the regexp, "path:" and all.
+        parsed.default = None;
+        parsed.re = Some(search_string);
+    }
+    if parsed.default.as_deref() == Some("") {
+        parsed.default = None;
+    }
+    parsed
+}
+
+/// Estimate the work of the search for admission control without running it.
+/// Text searches are estimated at codesearch's match limit since there's no
+/// way to count their matches up front, which makes them expensive, and
+/// identifier searches at the number of identifiers they may expand.
+pub fn legacy_search_estimate(params: &LegacySearchParams) -> StageEstimate {
+    let parsed = parse_params(params);
+    if parsed.is_trivial() {
+        return StageEstimate::default();
+    }
+    if let Some(symbols) = &parsed.symbol {
+        StageEstimate {
+            symbols: Some(symbols.split(',').count() as u64),
+            ..StageEstimate::default()
+        }
+    } else if parsed.re.is_some() || parsed.default.is_some() {
+        StageEstimate {
+            expected_results: Some(CODESEARCH_MAX_MATCHES as u64),
+            ..StageEstimate::default()
+        }
+    } else if parsed.id.is_some() {
+        StageEstimate {
+            symbols: Some(IDENTIFIER_LIMIT as u64),
+            ..StageEstimate::default()
+        }
+    } else {
+        StageEstimate::default()
+    }
+}
+
+/// Run the search the way router.py's `get_json_search_results` did,
+/// returning its exact JSON.
+pub async fn legacy_search_json(
+    server: &(dyn AbstractServer + Send + Sync),
+    params: &LegacySearchParams,
+) -> Result<String> {
+    let search_string = params.q.clone().unwrap_or_default();
+    let fold_case = params.case.as_deref() != Some("true");
+    let path_filter = params.path.clone().unwrap_or_default();
+
+    let parsed = parse_params(params);
+    if parsed.is_trivial() {
+        return Ok(python_json_dumps(&json!({})));
+    }
+    let context_lines = parsed.context_lines.unwrap_or(0);
+
+    let mut title = if search_string.is_empty() {
+        format!("Files {}", path_filter)
+    } else {
+        search_string.clone()
+    };
+    let mut search = LegacyResults::default();
+    let mut work_limit = false;
+    let mut hit_timeout = false;
+    let mut hit_limits: Vec<&str> = vec![];
+
+    if let Some(symbols) = &parsed.symbol {
+        search.set_path_filter(parsed.pathre.as_deref());
+        title = format!("Symbol {}", symbols);
+        let results = expand_keys(server, lookup_merging(server, symbols).await?).await?;
+        search.add_results(results);
+    } else if let Some(re) = &parsed.re {
+        let (hits, timed_out, limit_hit) = codesearch(
+            server,
+            re,
+            fold_case,
+            parsed.pathre.as_deref(),
+            context_lines,
+        )
+        .await;
+        search.add_results(keyed("Textual Occurrences", hits));
+        hit_timeout |= timed_out;
+        if limit_hit {
+            hit_limits.push("fulltext search hit limit");
+        }
+    } else if let Some(id) = &parsed.id {
+        search.set_path_filter(parsed.pathre.as_deref());
+        identifier_search(server, &mut search, id, true, fold_case).await?;
+    } else if let Some(default) = &parsed.default {
+        work_limit = true;
+        let (hits, timed_out, limit_hit) = codesearch(
+            server,
+            default,
+            fold_case,
+            parsed.pathre.as_deref(),
+            context_lines,
+        )
+        .await;
+        search.add_results(keyed("Textual Occurrences", hits));
+        hit_timeout |= timed_out;
+        if limit_hit {
+            hit_limits.push("fulltext search hit limit");
+        }
+        if parsed.pathre.is_none() {
+            let (file_hits, file_limit_hit) = search_files(server, default).await;
+            if file_limit_hit {
+                hit_limits.push("file pre-filter limit");
+            }
+            search.add_results(keyed("Files", file_hits));
+            identifier_search(server, &mut search, default, false, fold_case).await?;
+        }
+    } else if let Some(pathre) = &parsed.pathre {
+        let (file_hits, file_limit_hit) = search_files(server, pathre).await;
+        if file_limit_hit {
+            hit_limits.push("file");
+        }
+        search.add_results(keyed("Files", file_hits));
+    }
+
+    let mut results = search.get(work_limit);
+    if search.count_limit_hit {
+        hit_limits.push("result count limit");
+    }
+    if search.work_limit_hit {
+        hit_limits.push("work limit");
+    }
+    results.insert("*title*".to_string(), json!(title));
+    results.insert("*timedout*".to_string(), json!(hit_timeout));
+    results.insert("*limits*".to_string(), json!(hit_limits));
+    Ok(python_json_dumps(&Value::Object(results)))
+}
+
+/// A serde_json formatter producing what Python's `json.dumps` does with its
+/// default arguments: ", " and ": " separators, and `ensure_ascii` escaping of
+/// everything outside of printable ASCII.
+struct PythonFormatter;
+
+impl Formatter for PythonFormatter {
+    fn begin_array_value<W: ?Sized + std::io::Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> std::io::Result<()> {
+        if first {
+            Ok(())
+        } else {
+            writer.write_all(b", ")
+        }
+    }
+
+    fn begin_object_key<W: ?Sized + std::io::Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> std::io::Result<()> {
+        if first {
+            Ok(())
+        } else {
+            writer.write_all(b", ")
+        }
+    }
+
+    fn begin_object_value<W: ?Sized + std::io::Write>(
+        &mut self,
+        writer: &mut W,
+    ) -> std::io::Result<()> {
+        writer.write_all(b": ")
+    }
+
+    fn write_string_fragment<W: ?Sized + std::io::Write>(
+        &mut self,
+        writer: &mut W,
+        fragment: &str,
+    ) -> std::io::Result<()> {
+        // serde_json has already escaped quotes, backslashes, and control
+        // characters the same way Python does, leaving DEL and non-ASCII.
+        for c in fragment.chars() {
+            if c.is_ascii() && c != '\x7f' {
+                writer.write_all(&[c as u8])?;
+            } else {
+                for unit in c.encode_utf16(&mut [0; 2]) {
+                    write!(writer, "\\u{:04x}", unit)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Serialize the value exactly like Python's `json.dumps` would, given that
+/// the value has no floats.
+pub fn python_json_dumps<T: Serialize>(value: &T) -> String {
+    let mut out = vec![];
+    let mut serializer = serde_json::Serializer::with_formatter(&mut out, PythonFormatter);
+    // Serializing to a Vec can't fail for our values and our formatter only
+    // emits ASCII.
+    value.serialize(&mut serializer).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn test_parse_search() {
+    let parsed = parse_search("path:dom/*.cpp context:20 foo.bar(");
+    assert_eq!(parsed.pathre.as_deref(), Some(r"dom/[^/]*\.cpp"));
+    assert_eq!(parsed.context_lines, Some(10));
+    assert_eq!(parsed.default.as_deref(), Some(r"foo\.bar\("));
+
+    // The symbol swallows the rest of the string but parsing continues.
+    let parsed = parse_search("symbol:_ZN3Foo path:dom/");
+    assert_eq!(parsed.symbol.as_deref(), Some("_ZN3Foo path:dom/"));
+    assert_eq!(parsed.pathre.as_deref(), Some("dom/"));
+
+    assert!(parse_search("id:ab").is_trivial());
+    assert!(!parse_search("id:abc").is_trivial());
+    assert_eq!(
+        LegacySearchParams::from_query_string("q=foo+bar&path=&q=baz&case=true"),
+        LegacySearchParams {
+            q: Some("foo bar".to_string()),
+            path: None,
+            case: Some("true".to_string()),
+            regexp: None,
+        }
+    );
+}
+
+#[test]
+fn test_python_json_dumps() {
+    assert_eq!(python_json_dumps(&json!({})), "{}");
+    assert_eq!(
+        python_json_dumps(&json!({ "a": [1, true, null], "b": "caf\u{e9} \u{1f600}\u{7f}\n\"" })),
+        r#"{"a": [1, true, null], "b": "caf\u00e9 \ud83d\ude00\u007f\n\""}"#
+    );
+}
+
+#[test]
+fn test_sort_compiled_counts() {
+    let mut search = LegacyResults::default();
+    search.add_results(keyed(
+        "Files",
+        vec![json!({ "path": "b/test/x.js", "lines": [] })],
+    ));
+    search.add_results(keyed(
+        "Textual Occurrences",
+        vec![
+            json!({ "path": "a.cpp", "lines": [{ "lno": 2 }, { "lno": 1 }, { "lno": 2 }] }),
+            json!({ "path": "b/test/x.js", "lines": [] }),
+        ],
+    ));
+    let results = search.get(false);
+    assert_eq!(
+        Value::Object(results),
+        json!({
+            "normal": { "Textual Occurrences": [{ "path": "a.cpp", "lines": [{ "lno": 1 }, { "lno": 2 }] }] },
+            "test": { "Files": [{ "path": "b/test/x.js", "lines": [] }] },
+        })
+    );
+    assert!(!search.count_limit_hit);
+}
+
+#[test]
+fn test_legacy_search_estimate() {
+    let estimate =
+        |query: &str| legacy_search_estimate(&LegacySearchParams::from_query_string(query));
+    assert_eq!(estimate("q=ab").expected_results, None);
+    assert_eq!(
+        estimate("q=foo+context:3").expected_results,
+        Some(CODESEARCH_MAX_MATCHES as u64)
+    );
+    assert_eq!(estimate("q=id:foo").symbols, Some(IDENTIFIER_LIMIT as u64));
+    assert_eq!(estimate("q=symbol:a,b").symbols, Some(2));
+}
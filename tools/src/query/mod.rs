@@ -1 +1,4 @@
 pub mod chew_query;
+pub mod config_watcher;
+pub mod legacy_router;
+pub mod query_plan;
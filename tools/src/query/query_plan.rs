@@ -0,0 +1,413 @@
+use serde::{Deserialize, Serialize};
+
+use super::chew_query::{
+    build_query_graph, ingest_query, QueryConfig, QueryPipelineGroupBuilder, QUERY_CORE,
+};
+use crate::{
+    abstract_server::{AbstractServer, Result, ServerError},
+    cmd_pipeline::warnings::{add_pipeline_warning, strict_mode_enabled},
+    file_format::crossref_record::CrossrefRecord,
+};
+
+/// The groups created by the "idprefix"/"id" and "text"/"re" terms whose
+/// relative costs the planner weighs.
+const SEMANTIC_GROUP: &str = "semantic-search";
+const FULLTEXT_GROUP: &str = "text-search";
+
+/// Terms that explicitly ask for a fulltext search, which we always honor.
+const FULLTEXT_TERMS: &[&str] = &["text", "re"];
+
+/// Thresholds for the query planner's cost model, configurable via the
+/// "planner" section of the query config.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct QueryCostModel {
+    /// Set to true to let the planner skip or narrow fulltext searches.  When
+    /// false, semantic and fulltext searches always run in parallel.
+    pub enabled: bool,
+    /// If the identifier is a prefix of more identifiers than this, it's too
+    /// ambiguous for semantic results to stand in for fulltext results.
+    pub max_identifier_matches: usize,
+    /// How many exactly matching symbols to look up the hit counts of.
+    pub max_probed_symbols: usize,
+    /// The minimum number of semantic hits across the probed symbols for us to
+    /// consider the semantic results sufficient on their own.
+    pub min_symbol_hits: u64,
+    /// The most regular expression features the fulltext search can use and
+    /// still be skipped.  A plain literal has a complexity of 0.
+    pub max_regex_complexity: u32,
+    /// Identifiers shared by at least this many symbols (ex: "get", "init")
+    /// are stop words whose fulltext search is restricted to whole words
+    /// unless it has a path constraint.  Values below the identifier frequency
+    /// table's floor of 32 act like the floor.  0 disables stop words.
+    pub stop_word_min_symbols: usize,
+    /// Stop words shorter than this don't get a fulltext search at all since
+    /// even whole word matches would be overwhelming.
+    pub stop_word_min_length: usize,
+}
+
+impl Default for QueryCostModel {
+    fn default() -> Self {
+        QueryCostModel {
+            enabled: false,
+            max_identifier_matches: 16,
+            max_probed_symbols: 4,
+            min_symbol_hits: 1,
+            max_regex_complexity: 0,
+            stop_word_min_symbols: 256,
+            stop_word_min_length: 4,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum QueryStrategy {
+    /// Run the semantic and fulltext searches in parallel.
+    Parallel,
+    /// The semantic lookup was run first and found the identifier, so the
+    /// fulltext search was skipped.
+    SemanticOnly,
+    /// Like `Parallel`, but the identifier is a stop word so the fulltext
+    /// search only matches it as a whole word.
+    ParallelWholeWord,
+    /// The identifier is a stop word too short for even a whole word fulltext
+    /// search to be useful, so the fulltext search was skipped.
+    FulltextRefused,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryCostEstimate {
+    /// How many identifiers the needle is a prefix of, up to
+    /// `max_identifier_matches + 1`.
+    pub identifier_matches: usize,
+    /// How many symbols exactly match the needle, up to `max_probed_symbols`.
+    pub exact_symbols: usize,
+    /// The total number of crossref hits for the exactly matching symbols.
+    pub symbol_hits: u64,
+    /// The number of regular expression features used by the fulltext search.
+    pub regex_complexity: u32,
+    /// How many symbols share the identifier if it's common enough to be in
+    /// the identifier frequency table, otherwise 0.  Only looked up for
+    /// unconstrained fulltext searches of literals.
+    pub identifier_frequency: usize,
+    /// The length of the identifier in characters.
+    pub needle_length: usize,
+}
+
+/// The plan chosen for a query along with a human-readable explanation of why
+/// it was chosen.
+#[derive(Clone, Debug, Serialize)]
+pub struct QueryPlan {
+    pub strategy: QueryStrategy,
+    pub estimate: QueryCostEstimate,
+    pub explanation: String,
+}
+
+/// Count the regular expression features used by an re2 pattern, where escaped
+/// punctuation is considered literal.  Patterns produced by the "text" term via
+/// `regexp_escape` have a complexity of 0.
+pub fn regex_complexity(re: &str) -> u32 {
+    let mut complexity = 0;
+    let mut chars = re.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                // Character classes like "\d" and assertions like "\b" are
+                // features, escaped punctuation is not.
+                if chars
+                    .next()
+                    .map_or(false, |escaped| escaped.is_alphanumeric())
+                {
+                    complexity += 1;
+                }
+            }
+            '.' | '*' | '+' | '?' | '(' | ')' | '[' | ']' | '{' | '}' | '|' | '^' | '$' => {
+                complexity += 1;
+            }
+            _ => {}
+        }
+    }
+    complexity
+}
+
+impl QueryCostModel {
+    fn is_stop_word(&self, estimate: &QueryCostEstimate) -> bool {
+        self.stop_word_min_symbols > 0
+            && estimate.identifier_frequency >= self.stop_word_min_symbols
+    }
+
+    /// Pick a strategy for the estimate, explaining the choice.
+    pub fn choose(&self, estimate: &QueryCostEstimate) -> (QueryStrategy, String) {
+        let (strategy, explanation) = self.choose_ignoring_stop_words(estimate);
+        if strategy != QueryStrategy::Parallel || !self.is_stop_word(estimate) {
+            return (strategy, explanation);
+        }
+        if estimate.needle_length < self.stop_word_min_length {
+            (
+                QueryStrategy::FulltextRefused,
+                format!(
+                    "the identifier is shared by {} symbols and is too short for a useful fulltext search; use \"text:\" with a longer string or add a \"path:\" constraint to search the text",
+                    estimate.identifier_frequency
+                ),
+            )
+        } else {
+            (
+                QueryStrategy::ParallelWholeWord,
+                format!(
+                    "{}, but the identifier is shared by {} symbols so the fulltext search only matches it as a whole word",
+                    explanation, estimate.identifier_frequency
+                ),
+            )
+        }
+    }
+
+    fn choose_ignoring_stop_words(&self, estimate: &QueryCostEstimate) -> (QueryStrategy, String) {
+        if estimate.regex_complexity > self.max_regex_complexity {
+            return (
+                QueryStrategy::Parallel,
+                format!(
+                    "the fulltext pattern uses {} regular expression features that semantic lookup can't answer",
+                    estimate.regex_complexity
+                ),
+            );
+        }
+        if estimate.exact_symbols == 0 {
+            return (
+                QueryStrategy::Parallel,
+                "no symbols exactly match the identifier".to_string(),
+            );
+        }
+        if estimate.identifier_matches > self.max_identifier_matches {
+            return (
+                QueryStrategy::Parallel,
+                format!(
+                    "the identifier is a prefix of more than {} identifiers",
+                    self.max_identifier_matches
+                ),
+            );
+        }
+        if estimate.symbol_hits < self.min_symbol_hits {
+            return (
+                QueryStrategy::Parallel,
+                format!(
+                    "the matching symbols only have {} semantic hits",
+                    estimate.symbol_hits
+                ),
+            );
+        }
+        (
+            QueryStrategy::SemanticOnly,
+            format!(
+                "{} symbols exactly match the identifier with {} semantic hits, so a literal fulltext search would mostly repeat them",
+                estimate.exact_symbols, estimate.symbol_hits
+            ),
+        )
+    }
+
+    async fn estimate(
+        &self,
+        server: &(dyn AbstractServer + Send + Sync),
+        needle: &str,
+        re: &str,
+        check_stop_words: bool,
+    ) -> Result<QueryCostEstimate> {
+        let mut estimate = QueryCostEstimate {
+            regex_complexity: regex_complexity(re),
+            needle_length: needle.chars().count(),
+            ..QueryCostEstimate::default()
+        };
+        // There's no point looking anything up if the pattern already decides
+        // things.
+        if estimate.regex_complexity > self.max_regex_complexity {
+            return Ok(estimate);
+        }
+
+        if check_stop_words && self.stop_word_min_symbols > 0 && estimate.regex_complexity == 0 {
+            estimate.identifier_frequency = match server.identifier_frequency(needle).await {
+                Ok(frequency) => frequency,
+                Err(ServerError::Unsupported) => 0,
+                Err(err) => return Err(err),
+            };
+        }
+
+        estimate.identifier_matches = server
+            .search_identifiers(needle, false, false, self.max_identifier_matches + 1)
+            .await?
+            .len();
+        let exact = server
+            .search_identifiers(needle, true, false, self.max_probed_symbols)
+            .await?;
+        estimate.exact_symbols = exact.len();
+        for (symbol, _id) in exact {
+            let info = server.crossref_lookup(&symbol, false).await?;
+            let crossref = CrossrefRecord::from_value(info)?;
+            for (_kind, path_hits) in crossref.hit_kinds() {
+                estimate.symbol_hits += path_hits
+                    .iter()
+                    .map(|path_hit| path_hit.lines.len() as u64)
+                    .sum::<u64>();
+            }
+        }
+        Ok(estimate)
+    }
+}
+
+/// Like `chew_query_with_config`, but if the query would run both a semantic
+/// search and a fulltext search, consult the cost model (which performs a
+/// cheap semantic lookup) to decide whether the fulltext search can be skipped
+/// or the two should run in parallel.  The chosen plan is recorded in the
+/// builder's `plan`.  If the estimate can't be made, both searches are run in
+/// parallel as if the planner were disabled.  Strict mode doesn't consult the
+/// cost model and never runs the fulltext search as a fallback for a semantic
+/// search, only when the query explicitly asks for it.
+pub async fn plan_query_with_config(
+    server: &(dyn AbstractServer + Send + Sync),
+    full_arg_str: &str,
+    config: &QueryConfig,
+) -> Result<QueryPipelineGroupBuilder> {
+    let mut builder = ingest_query(full_arg_str, config)?;
+    let model = &config.planner;
+
+    let needle = builder.groups.get(SEMANTIC_GROUP).and_then(|group| {
+        group
+            .segments
+            .iter()
+            .find(|seg| seg.command == "search-identifiers")
+            .and_then(|seg| seg.args.positional_args.first().cloned())
+    });
+    let search_text = builder.groups.get(FULLTEXT_GROUP).and_then(|group| {
+        group
+            .segments
+            .iter()
+            .find(|seg| seg.command == "search-text")
+    });
+    let re = search_text.and_then(|seg| seg.args.named_args.get("re").map(|(re, _pri)| re.clone()));
+    // Path constraints keep fulltext searches of stop words manageable.
+    let path_constrained = search_text.map_or(false, |seg| {
+        seg.args.named_args.contains_key("path") || seg.args.named_args.contains_key("pathre")
+    });
+    let explicit_fulltext = builder
+        .root_terms
+        .iter()
+        .any(|term| FULLTEXT_TERMS.contains(&term.as_str()));
+
+    if strict_mode_enabled() {
+        if let (false, Some(_), Some(_)) = (explicit_fulltext, &needle, &re) {
+            builder.groups.remove(FULLTEXT_GROUP);
+            builder.plan = Some(QueryPlan {
+                strategy: QueryStrategy::SemanticOnly,
+                estimate: QueryCostEstimate::default(),
+                explanation: "strict mode skips the fulltext fallback".to_string(),
+            });
+        }
+        return build_query_graph(builder, config);
+    }
+
+    if let (true, false, Some(needle), Some(re)) = (model.enabled, explicit_fulltext, needle, re) {
+        let (strategy, estimate, explanation) = match model
+            .estimate(server, &needle, &re, !path_constrained)
+            .await
+        {
+            Ok(estimate) => {
+                let (strategy, explanation) = model.choose(&estimate);
+                (strategy, estimate, explanation)
+            }
+            Err(err) => (
+                QueryStrategy::Parallel,
+                QueryCostEstimate::default(),
+                format!("estimating the cost of the query failed: {:?}", err),
+            ),
+        };
+        match strategy {
+            QueryStrategy::Parallel => {}
+            QueryStrategy::SemanticOnly => {
+                builder.groups.remove(FULLTEXT_GROUP);
+            }
+            QueryStrategy::ParallelWholeWord => {
+                if let Some(group) = builder.groups.get_mut(FULLTEXT_GROUP) {
+                    for seg in &mut group.segments {
+                        if seg.command == "search-text" {
+                            seg.args.bool_args.insert("word".to_string());
+                        }
+                    }
+                }
+            }
+            QueryStrategy::FulltextRefused => {
+                builder.groups.remove(FULLTEXT_GROUP);
+                add_pipeline_warning(
+                    "query",
+                    format!(
+                        "skipped the fulltext search for {:?}: {}",
+                        needle, explanation
+                    ),
+                );
+            }
+        }
+        builder.plan = Some(QueryPlan {
+            strategy,
+            estimate,
+            explanation,
+        });
+    }
+
+    build_query_graph(builder, config)
+}
+
+/// Like `chew_query` but planned via `plan_query_with_config`.
+pub async fn plan_query(
+    server: &(dyn AbstractServer + Send + Sync),
+    full_arg_str: &str,
+) -> Result<QueryPipelineGroupBuilder> {
+    plan_query_with_config(server, full_arg_str, &QUERY_CORE).await
+}
+
+#[test]
+fn test_query_cost_model() {
+    assert_eq!(regex_complexity(&regex::escape("nsIFoo::Bar()")), 0);
+    assert_eq!(regex_complexity(r"foo\d+.*"), 4);
+
+    let model = QueryCostModel::default();
+    let estimate = QueryCostEstimate {
+        identifier_matches: 3,
+        exact_symbols: 1,
+        symbol_hits: 42,
+        regex_complexity: 0,
+        ..QueryCostEstimate::default()
+    };
+    assert_eq!(model.choose(&estimate).0, QueryStrategy::SemanticOnly);
+
+    let ambiguous = QueryCostEstimate {
+        identifier_matches: 17,
+        ..estimate.clone()
+    };
+    assert_eq!(model.choose(&ambiguous).0, QueryStrategy::Parallel);
+
+    let unknown = QueryCostEstimate {
+        exact_symbols: 0,
+        symbol_hits: 0,
+        ..estimate.clone()
+    };
+    assert_eq!(model.choose(&unknown).0, QueryStrategy::Parallel);
+
+    let common = QueryCostEstimate {
+        identifier_matches: 500,
+        identifier_frequency: 3000,
+        needle_length: 4,
+        ..estimate.clone()
+    };
+    assert_eq!(model.choose(&common).0, QueryStrategy::ParallelWholeWord);
+    let short = QueryCostEstimate {
+        needle_length: 3,
+        ..common.clone()
+    };
+    assert_eq!(model.choose(&short).0, QueryStrategy::FulltextRefused);
+    // Stop words only matter if we'd otherwise run the fulltext search.
+    let covered = QueryCostEstimate {
+        identifier_frequency: 3000,
+        needle_length: 3,
+        ..estimate
+    };
+    assert_eq!(model.choose(&covered).0, QueryStrategy::SemanticOnly);
+}
@@ -141,6 +141,9 @@ async fn test_check_glob() -> Result<(), std::io::Error> {
                         Ok(PipelineValues::SymbolTreeTableList(sttl)) => {
                             insta::assert_json_snapshot!(&to_value(sttl).unwrap());
                         }
+                        Ok(PipelineValues::InterleavedResults(ir)) => {
+                            insta::assert_json_snapshot!(&to_value(ir).unwrap());
+                        }
                         Err(ServerError::Unsupported) => {
                             // We're intentionally skipping doing anything here.
                             // Our assumption is that this error will only be